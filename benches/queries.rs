@@ -0,0 +1,113 @@
+//! Benchmarks of representative workloads, on the synthetic graphs of
+//! `salsa::bench_support`. Run with
+//! `cargo bench --features bench-support`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use salsa::bench_support::{Graph, GraphDatabase, GraphDatabaseImpl, NodeValueQuery};
+use salsa::Database;
+#[cfg(not(feature = "single-threaded"))]
+use salsa::ParallelDatabase;
+
+/// Reads the root of `graph`, then benchmarks reading it again after
+/// `change` modified the database, which creates a new revision.
+fn bench_change(
+    c: &mut Criterion,
+    name: &str,
+    graph: &Graph,
+    change: impl Fn(&mut GraphDatabaseImpl, u64),
+) {
+    let mut db = graph.database();
+    db.node_value(0);
+    let mut weight = 1;
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            weight += 1;
+            change(&mut db, weight);
+            black_box(db.node_value(0))
+        })
+    });
+}
+
+/// Deep dependency chains: reading an up-to-date value, validating
+/// the whole chain after an unrelated input changed, and re-executing
+/// it after its leaf changed.
+fn chain(c: &mut Criterion) {
+    let graph = Graph::chain(1_000);
+    let db = graph.database();
+    db.node_value(0);
+    c.bench_function("chain/read_up_to_date", |b| {
+        b.iter(|| black_box(db.node_value(0)))
+    });
+
+    let unrelated = graph.node_count();
+    bench_change(c, "chain/revalidate", &graph, |db, weight| {
+        db.set_weight(unrelated, weight)
+    });
+    let leaf = graph.node_count() - 1;
+    bench_change(c, "chain/recompute", &graph, |db, weight| {
+        db.set_weight(leaf, weight)
+    });
+}
+
+/// Wide fan-out: a root reading many leaves, which is validated (or
+/// re-executed, when one of them changed) by checking each of them.
+fn fan_out(c: &mut Criterion) {
+    let graph = Graph::fan_out(10_000);
+    let unrelated = graph.node_count();
+    bench_change(c, "fan_out/revalidate", &graph, |db, weight| {
+        db.set_weight(unrelated, weight)
+    });
+    bench_change(c, "fan_out/recompute", &graph, |db, weight| {
+        db.set_weight(1, weight)
+    });
+
+    let graph = Graph::layered(10, 30);
+    let unrelated = graph.node_count();
+    bench_change(c, "layered/revalidate", &graph, |db, weight| {
+        db.set_weight(unrelated, weight)
+    });
+}
+
+/// LRU churn: reading more values than the LRU capacity of the query,
+/// so that values are evicted and re-executed all the time.
+fn lru_churn(c: &mut Criterion) {
+    let graph = Graph::fan_out(1_000);
+    let mut db = graph.database();
+    db.query_mut(NodeValueQuery).set_lru_capacity(100);
+    c.bench_function("lru_churn", |b| {
+        b.iter(|| {
+            for node in graph.leaves() {
+                black_box(db.node_value(node));
+            }
+        })
+    });
+}
+
+/// Parallel readers: after each change, several snapshots read the
+/// root at once, blocking on the thread that re-executes it.
+#[cfg(not(feature = "single-threaded"))]
+fn parallel_readers(c: &mut Criterion) {
+    let graph = Graph::layered(10, 30);
+    let leaf = graph.leaves().next().unwrap();
+    let mut db = graph.database();
+    db.node_value(0);
+    let mut weight = 1;
+    c.bench_function("parallel_readers", |b| {
+        b.iter(|| {
+            weight += 1;
+            db.set_weight(leaf, weight);
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    let snapshot = db.snapshot();
+                    scope.spawn(move || black_box(snapshot.node_value(0)));
+                }
+            });
+        })
+    });
+}
+
+#[cfg(not(feature = "single-threaded"))]
+criterion_group!(benches, chain, fan_out, lru_churn, parallel_readers);
+#[cfg(feature = "single-threaded")]
+criterion_group!(benches, chain, fan_out, lru_churn);
+criterion_main!(benches);
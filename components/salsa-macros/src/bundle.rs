@@ -0,0 +1,77 @@
+use crate::database_storage::QueryGroup;
+use proc_macro::TokenStream;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Ident, Token, Visibility};
+
+pub(crate) fn query_group_bundle(input: TokenStream) -> TokenStream {
+    let QueryGroupBundle {
+        attrs,
+        visibility,
+        name,
+        query_groups,
+    } = syn::parse_macro_input!(input as QueryGroupBundle);
+
+    let query_groups: Vec<_> = query_groups
+        .iter()
+        .map(QueryGroup::absolute_tokens)
+        .collect();
+
+    // Public bundles are exported, so that they can be used from other
+    // crates, as `other_crate::bundle!`.
+    let export = match visibility {
+        Visibility::Public(_) => quote! { #[macro_export] },
+        _ => quote! {},
+    };
+
+    // The `database` attribute invokes the bundle with the entries
+    // that come before and after it, its path, and the database struct;
+    // see `database_storage::database`.
+    let output = quote! {
+        #(#attrs)*
+        #export
+        macro_rules! #name {
+            ([$($before:tt)*] [$($after:tt)*] [$($bundle:tt)*] $($database:tt)*) => {
+                #[salsa::database($($before)* #(#query_groups,)* $($after)*)]
+                $($database)*
+            };
+        }
+    };
+
+    if std::env::var("SALSA_DUMP").is_ok() {
+        println!("~~~ query_group_bundle");
+        println!("{}", output);
+        println!("~~~ query_group_bundle");
+    }
+
+    output.into()
+}
+
+struct QueryGroupBundle {
+    attrs: Vec<Attribute>,
+    visibility: Visibility,
+    name: Ident,
+    query_groups: Punctuated<QueryGroup, Token![,]>,
+}
+
+impl Parse for QueryGroupBundle {
+    /// ```ignore
+    ///         pub my_bundle = [crate::MyGroup, other_crate::OtherGroup];
+    /// ```
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let visibility = input.parse()?;
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let content;
+        syn::bracketed!(content in input);
+        let query_groups = content.parse_terminated(QueryGroup::parse)?;
+        input.parse::<Option<Token![;]>>()?;
+        Ok(QueryGroupBundle {
+            attrs,
+            visibility,
+            name,
+            query_groups,
+        })
+    }
+}
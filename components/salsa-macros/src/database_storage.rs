@@ -1,8 +1,10 @@
-use heck::SnakeCase;
+use heck::{CamelCase, SnakeCase};
 use proc_macro::TokenStream;
+use proc_macro2::TokenTree;
+use quote::ToTokens;
 use syn::parse::{Parse, ParseStream};
 use syn::punctuated::Punctuated;
-use syn::{Ident, ItemStruct, Path, Token};
+use syn::{Ident, ItemStruct, Path, PathArguments, Token};
 
 type PunctuatedQueryGroups = Punctuated<QueryGroup, Token![,]>;
 
@@ -10,10 +12,42 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = syn::parse_macro_input!(args as QueryGroupList);
     let input = syn::parse_macro_input!(input as ItemStruct);
 
-    let query_groups = &args.query_groups;
+    // Bundles of query groups (see `query_group_bundle!`), and query
+    // groups that extend others (see `#[salsa::extends]`), are
+    // expanded one at a time: we invoke the first one on the other
+    // entries, its own path and the struct, and it expands to this
+    // attribute again, with its query groups in its place.
+    if let Some(index) = args.query_groups.iter().position(|group| group.bundle) {
+        let bundle = &args.query_groups[index].group_path;
+        let before = args.query_groups.iter().take(index);
+        let after = args.query_groups.iter().skip(index + 1);
+        return quote! {
+            #bundle! { [#(#before,)*] [#(#after,)*] [#bundle] #input }
+        }
+        .into();
+    }
+
+    // A query group may be listed several times, say, when two listed
+    // groups extend it; only keep the first entry.
+    let mut query_groups: Vec<&QueryGroup> = Vec::new();
+    for query_group in &args.query_groups {
+        if query_groups
+            .iter()
+            .all(|other| other.name() != query_group.name())
+        {
+            query_groups.push(query_group);
+        }
+    }
+
     let database_name = &input.ident;
     let visibility = &input.vis;
 
+    // The database may have generic parameters, such as the lifetime of
+    // the environment that it borrows; the types that we generate for
+    // it take the same parameters.
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let database_type = quote! { #database_name #ty_generics };
+
     let mut output = proc_macro2::TokenStream::new();
     output.extend(quote! { #input });
 
@@ -27,18 +61,18 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
 
     let query_group_storage_names: Vec<_> = query_groups
         .iter()
-        .map(|QueryGroup { group_path }| {
+        .map(|QueryGroup { group_path, .. }| {
             quote! {
-                <#group_path as salsa::plumbing::QueryGroup<#database_name>>::GroupStorage
+                <#group_path as salsa::plumbing::QueryGroup<#database_type>>::GroupStorage
             }
         })
         .collect();
 
     let query_group_key_names: Vec<_> = query_groups
         .iter()
-        .map(|QueryGroup { group_path }| {
+        .map(|QueryGroup { group_path, .. }| {
             quote! {
-                <#group_path as salsa::plumbing::QueryGroup<#database_name>>::GroupKey
+                <#group_path as salsa::plumbing::QueryGroup<#database_type>>::GroupKey
             }
         })
         .collect();
@@ -62,17 +96,26 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
             #group_name_snake: #group_storage,
         });
         has_group_impls.extend(quote! {
-            impl salsa::plumbing::HasQueryGroup<#group_path> for #database_name {
+            impl #impl_generics salsa::plumbing::HasQueryGroup<#group_path> for #database_type #where_clause {
                 fn group_storage(db: &Self) -> &#group_storage {
                     let runtime = salsa::Database::salsa_runtime(db);
                     &runtime.storage().#group_name_snake
                 }
 
-                fn database_key(group_key: #group_key) -> __SalsaDatabaseKey {
+                fn database_key(group_key: #group_key) -> __SalsaDatabaseKey #ty_generics {
                     __SalsaDatabaseKey {
                         kind: __SalsaDatabaseKeyKind::#group_name(group_key),
                     }
                 }
+
+                fn for_each_query(
+                    db: &Self,
+                    op: &mut dyn FnMut(&dyn salsa::plumbing::QueryStorageMassOps<Self>),
+                ) {
+                    let storage: &#group_storage =
+                        <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(db);
+                    storage.for_each_query(db, op);
+                }
             }
         });
     }
@@ -81,7 +124,7 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
     output.extend(quote! {
         #[derive(Default)]
         #[doc(hidden)]
-        #visibility struct __SalsaDatabaseStorage {
+        #visibility struct __SalsaDatabaseStorage #impl_generics #where_clause {
             #storage_fields
         }
     });
@@ -90,15 +133,15 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
     output.extend(quote! {
         #[derive(Clone, Debug, PartialEq, Eq, Hash)]
         #[doc(hidden)]
-        #visibility struct __SalsaDatabaseKey {
-            kind: __SalsaDatabaseKeyKind
+        #visibility struct __SalsaDatabaseKey #impl_generics #where_clause {
+            kind: __SalsaDatabaseKeyKind #ty_generics
         }
     });
 
     // For each query `fn foo() for FooType` create
     //
     // ```
-    // foo(<FooType as salsa::Query<#database_name>>::Key),
+    // foo(<FooType as salsa::Query<#database_type>>::Key),
     // ```
     let mut variants = proc_macro2::TokenStream::new();
     for (query_group, group_key) in query_groups.iter().zip(&query_group_key_names) {
@@ -107,33 +150,48 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
             #group_name(#group_key),
         ));
     }
+    let mut display_arms = proc_macro2::TokenStream::new();
+    for query_group in query_groups.iter() {
+        let group_name = query_group.name();
+        display_arms.extend(quote!(
+            __SalsaDatabaseKeyKind::#group_name(group_key) => std::fmt::Display::fmt(group_key, fmt),
+        ));
+    }
     output.extend(quote! {
         #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-        enum __SalsaDatabaseKeyKind {
+        enum __SalsaDatabaseKeyKind #impl_generics #where_clause {
             #variants
         }
+
+        impl #impl_generics std::fmt::Display for __SalsaDatabaseKey #ty_generics #where_clause {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match &self.kind {
+                    #display_arms
+                }
+            }
+        }
     });
 
     // Create a tuple (D1, D2, ...) where Di is the data for a given query group.
     let mut database_data = vec![];
-    for QueryGroup { group_path } in query_groups {
+    for QueryGroup { group_path, .. } in &query_groups {
         database_data.push(quote! {
-            <#group_path as salsa::plumbing::QueryGroup<#database_name>>::GroupData
+            <#group_path as salsa::plumbing::QueryGroup<#database_type>>::GroupData
         });
     }
 
     //
     output.extend(quote! {
-        impl salsa::plumbing::DatabaseStorageTypes for #database_name {
-            type DatabaseKey = __SalsaDatabaseKey;
-            type DatabaseStorage = __SalsaDatabaseStorage;
+        impl #impl_generics salsa::plumbing::DatabaseStorageTypes for #database_type #where_clause {
+            type DatabaseKey = __SalsaDatabaseKey #ty_generics;
+            type DatabaseStorage = __SalsaDatabaseStorage #ty_generics;
             type DatabaseData = (#(#database_data),*);
         }
     });
 
     //
     let mut for_each_ops = proc_macro2::TokenStream::new();
-    for (QueryGroup { group_path }, group_storage) in
+    for (QueryGroup { group_path, .. }, group_storage) in
         query_groups.iter().zip(&query_group_storage_names)
     {
         for_each_ops.extend(quote! {
@@ -142,19 +200,120 @@ pub(crate) fn database(args: TokenStream, input: TokenStream) -> TokenStream {
             storage.for_each_query(self, &mut op);
         });
     }
+    let mut for_each_stats_ops = proc_macro2::TokenStream::new();
+    for (QueryGroup { group_path, .. }, group_storage) in
+        query_groups.iter().zip(&query_group_storage_names)
+    {
+        for_each_stats_ops.extend(quote! {
+            let storage: &#group_storage =
+                <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
+            storage.for_each_query_stats(&mut op);
+        });
+    }
+    let mut for_each_persistent_ops = proc_macro2::TokenStream::new();
+    for (QueryGroup { group_path, .. }, group_storage) in
+        query_groups.iter().zip(&query_group_storage_names)
+    {
+        for_each_persistent_ops.extend(quote! {
+            let storage: &#group_storage =
+                <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
+            storage.for_each_persistent_query(&mut op);
+        });
+    }
+    let mut fetch_by_key_arms = proc_macro2::TokenStream::new();
+    for (query_group, group_storage) in query_groups.iter().zip(&query_group_storage_names) {
+        let group_path = &query_group.group_path;
+        let group_name = query_group.name();
+        fetch_by_key_arms.extend(quote! {
+            __SalsaDatabaseKeyKind::#group_name(ref group_key) => {
+                let storage: &#group_storage =
+                    <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
+                storage.fetch_by_key(self, group_key);
+            }
+        });
+    }
+    let mut invoke_by_name_ops = proc_macro2::TokenStream::new();
+    for (QueryGroup { group_path, .. }, group_storage) in
+        query_groups.iter().zip(&query_group_storage_names)
+    {
+        invoke_by_name_ops.extend(quote! {
+            let storage: &#group_storage =
+                <Self as salsa::plumbing::HasQueryGroup<#group_path>>::group_storage(self);
+            if let Some(result) = storage.invoke_by_name(self, name, args) {
+                return Some(result);
+            }
+        });
+    }
     output.extend(quote! {
-        impl salsa::plumbing::DatabaseOps for #database_name {
+        impl #impl_generics salsa::plumbing::DatabaseOps for #database_type #where_clause {
             fn for_each_query(
                 &self,
                 mut op: impl FnMut(&dyn salsa::plumbing::QueryStorageMassOps<Self>),
             ) {
                 #for_each_ops
             }
+
+            fn for_each_query_stats(
+                &self,
+                mut op: impl FnMut(&'static str, &dyn salsa::QueryStorageStats),
+            ) {
+                #for_each_stats_ops
+            }
+
+            fn fetch_by_key(&self, database_key: &__SalsaDatabaseKey #ty_generics) {
+                match database_key.kind {
+                    #fetch_by_key_arms
+                }
+            }
+
+            salsa::__if_persist! {
+                fn for_each_persistent_query(
+                    &self,
+                    mut op: impl FnMut(&dyn salsa::plumbing::PersistQueryStorageOps<Self>),
+                ) {
+                    #for_each_persistent_ops
+                }
+            }
+
+            salsa::__if_dynamic! {
+                fn invoke_by_name(
+                    &self,
+                    name: &str,
+                    args: &salsa::plumbing::serde_json::Value,
+                ) -> Option<Result<salsa::plumbing::serde_json::Value, salsa::QueryByNameError>> {
+                    #invoke_by_name_ops
+                    None
+                }
+            }
         }
     });
 
+    // Keys that are `Send` and `'static` are thrown in `QueryPanicked`
+    // payloads. Autoref specialization cannot tell if the keys of a
+    // generic database are, so those only get a message.
+    let query_panicked_key = if input.generics.params.is_empty() {
+        quote! {
+            use salsa::plumbing::{QueryPanickedViaMessage as _, QueryPanickedViaPayload as _};
+            (&&salsa::plumbing::QueryPanickedKey::<Self>(std::marker::PhantomData))
+        }
+    } else {
+        quote! {
+            use salsa::plumbing::QueryPanickedViaMessage as _;
+            salsa::plumbing::QueryPanickedKey::<Self>(std::marker::PhantomData)
+        }
+    };
+
     output.extend(quote! {
-        impl salsa::plumbing::DatabaseKey<#database_name> for __SalsaDatabaseKey {
+        impl #impl_generics salsa::plumbing::DatabaseKey<#database_type> for __SalsaDatabaseKey #ty_generics #where_clause {
+            fn throw_query_panicked(&self) -> ! {
+                #query_panicked_key.throw(self)
+            }
+
+            fn query_panicked_message(
+                payload: &(dyn std::any::Any + Send),
+            ) -> Option<String> {
+                #query_panicked_key.message(payload)
+            }
         }
     });
 
@@ -182,19 +341,57 @@ impl Parse for QueryGroupList {
 }
 
 #[derive(Clone, Debug)]
-struct QueryGroup {
+pub(crate) struct QueryGroup {
     group_path: Path,
+
+    /// True for a bundle of query groups, written `path::to::bundle!`.
+    bundle: bool,
 }
 
 impl QueryGroup {
-    /// The name of the query group trait.
+    /// Makes the path of this entry of a bundle absolute, by replacing
+    /// a leading `crate` with `$crate`, as the bundle is expanded in
+    /// the crate of the database.
+    pub(crate) fn absolute_tokens(&self) -> proc_macro2::TokenStream {
+        let mut segments = self.group_path.segments.iter();
+        let path = match segments.next() {
+            Some(first) if self.group_path.leading_colon.is_none() && first.ident == "crate" => {
+                quote! { $crate #(::#segments)* }
+            }
+            _ => {
+                let path = &self.group_path;
+                quote! { #path }
+            }
+        };
+        if self.bundle {
+            quote! { #path! }
+        } else {
+            path
+        }
+    }
+
+    /// The name of the query group trait. For a generic query group,
+    /// such as `MyGroup<ast::Item>`, the names of the type arguments are
+    /// appended (`MyGroupAstItem`), so that each instance of the group
+    /// gets its own storage.
     fn name(&self) -> Ident {
-        self.group_path
-            .segments
-            .last()
-            .unwrap()
-            .ident
-            .clone()
+        let segment = self.group_path.segments.last().unwrap();
+        let mut name = segment.ident.to_string();
+        if let PathArguments::AngleBracketed(arguments) = &segment.arguments {
+            fn append_idents(tokens: proc_macro2::TokenStream, name: &mut String) {
+                for token in tokens {
+                    match token {
+                        TokenTree::Ident(ident) => {
+                            name.push_str(&ident.to_string().to_camel_case())
+                        }
+                        TokenTree::Group(group) => append_idents(group.stream(), name),
+                        _ => {}
+                    }
+                }
+            }
+            append_idents(arguments.args.to_token_stream(), &mut name);
+        }
+        Ident::new(&name, segment.ident.span())
     }
 }
 
@@ -204,7 +401,17 @@ impl Parse for QueryGroup {
     /// ```
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let group_path: Path = input.parse()?;
-        Ok(QueryGroup { group_path })
+        let bundle = input.parse::<Option<Token![!]>>()?.is_some();
+        Ok(QueryGroup { group_path, bundle })
+    }
+}
+
+impl quote::ToTokens for QueryGroup {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.group_path.to_tokens(tokens);
+        if self.bundle {
+            tokens.extend(quote! { ! });
+        }
     }
 }
 
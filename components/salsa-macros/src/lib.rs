@@ -9,6 +9,7 @@ extern crate quote;
 
 use proc_macro::TokenStream;
 
+mod bundle;
 mod database_storage;
 mod parenthesized;
 mod query_group;
@@ -27,6 +28,12 @@ mod query_group;
 /// (e.g., `MyQuery`, below) that represents the query, and optionally
 /// other details, such as its storage.
 ///
+/// The keys of a query must implement `Clone`, `Eq` and `Hash`, and its
+/// value `Clone`. They need not implement `Debug`: where salsa
+/// formats keys and values that don't (in events, cycle errors or
+/// `DebugQueryTable::debug_dump`, for example), it writes
+/// `<opaque TypeName>` instead.
+///
 /// # Examples
 ///
 /// The simplest example is something like this:
@@ -44,6 +51,35 @@ mod query_group;
 /// }
 /// ```
 ///
+/// Query group traits can have type parameters, say, to parameterize
+/// the queries by the type of an AST or by a target architecture:
+///
+/// ```ignore
+/// #[salsa::query_group(LayoutStorage)]
+/// trait Layout<A: Arch>: salsa::Database {
+///     fn struct_size(&self, name: String) -> usize;
+/// }
+///
+/// fn struct_size<A: Arch>(db: &impl Layout<A>, name: String) -> usize {
+///     ...
+/// }
+/// ```
+///
+/// The group struct and the query types (`LayoutStorage<A>`,
+/// `StructSizeQuery<A>`) then have the same type parameters, and a
+/// database lists each instance of the group it needs, as in
+/// `#[salsa::database(LayoutStorage<X86>, LayoutStorage<Arm64>)]`;
+/// each instance has its own storage. The type arguments need not
+/// implement `Clone`, `Eq` and so on themselves: only the keys and
+/// values of the queries do. Generic query groups can only have type
+/// parameters, and cannot extend other groups (see `#[salsa::extends]`).
+///
+/// A query group that extends `salsa::DynDatabase` (directly or through
+/// other such groups) rather than `salsa::Database` can be used as a
+/// trait object: its query functions can then take `db: &dyn MyGroup`,
+/// and are compiled once rather than for each database type. See
+/// `salsa::DynDatabase` for the part of the database these can use.
+///
 /// Here is a list of legal `salsa::XXX` attributes:
 ///
 /// - Query group attributes: apply to the trait itself
@@ -54,11 +90,26 @@ mod query_group;
 ///     a super trait, with a difference that users of the query group don't
 ///     get access to `OtherGroup` automatcally, which would be the case with
 ///     a super trait.
+///   - `#[salsa::extends(path::to::ParentStorage)]` -- for a group whose
+///     trait has the query group trait `Parent` as a super trait (as in
+///     `trait Child: Parent`), makes the group reuse the storage of
+///     `Parent`: a database then lists the group as
+///     `#[salsa::database(path::to::ChildStorage!)]`, and gets the
+///     storage of `Parent` (and of the groups `Parent` extends) along
+///     with it. Like the entries of `query_group_bundle!`, the path of
+///     the parent must be absolute, or start with `crate`; if the parent
+///     extends other groups itself, write it with a `!`, too. A group
+///     that several listed groups extend is only stored once.
 /// - Storage attributes: control how the query data is stored and set. These
 ///   are described in detail in the section below.
 ///   - `#[salsa::input]`
 ///   - `#[salsa::memoized]`
 ///   - `#[salsa::dependencies]`
+///   - `#[salsa::weak]`
+///   - `#[salsa::firewall]`
+///   - `#[salsa::dedup]`
+///   - `#[salsa::fingerprint]` or `#[salsa::fingerprint(path::to::fn)]`
+///   - `#[salsa::tracked]`
 /// - Query execution:
 ///   - `#[salsa::invoke(path::to::my_fn)]` -- for a non-input, this
 ///     indicates the function to call when a query must be
@@ -68,6 +119,133 @@ mod query_group;
 ///     dummy struct created fo the query. Default is the name of the
 ///     query, in camel case, plus the word "Query" (e.g.,
 ///     `MyQueryQuery` and `OtherQueryQuery` in the examples above).
+///   - `#[salsa::shallow_revalidation]` -- for a derived query, makes
+///     validating its memoized value in a new revision shallow: rather
+///     than validating the queries it depends on first (recursively),
+///     the query is re-executed unless they were all validated in the
+///     current revision already. If the new value is equal to the old
+///     one, it is still backdated. This pays off for queries that are
+///     cheap to execute but depend on large graphs of queries.
+///   - `#[salsa::keep_previous]` -- for a memoized query, keeps the
+///     value that each memoized value replaced, until the value changes
+///     again, so that `QueryTable::changed_entries_since` can report
+///     both the old and the new value of the keys that changed.
+///   - `#[salsa::catch_panics]` -- for a derived query declared as
+///     `fn my_query(&self, input: u32) -> Result<T, salsa::QueryPanic>`
+///     whose function returns `T`: if the function panics, the panic
+///     is caught and the query returns `Err(QueryPanic)` instead. The
+///     error is memoized for the current revision, so that further
+///     reads (including those of threads that were blocked on the
+///     query) return it rather than panicking again; the query is
+///     executed again in the next revision. Cancellation and the
+///     other panics that salsa uses internally are not caught.
+///   - `#[salsa::fallible]` -- for a memoized query declared as
+///     `fn my_query(&self, input: u32) -> Result<T, E>`: the query
+///     returns `Result<T, salsa::Failed<E>>`, while its function still
+///     returns `Result<T, E>`. `Failed` shares the error rather than
+///     cloning it, so `E` need not implement `Clone`; errors are
+///     compared by value for backdating, so `E` must implement `Eq`.
+///     The options `#[salsa::fallible(no_backdate, no_memoize)]` change
+///     how errors are stored: with `no_backdate`, a recomputed error is
+///     never equal to the previous one (and `E` need not implement
+///     `Eq`), so that the dependents of the query are re-executed; with
+///     `no_memoize`, errors are not memoized, only the inputs they were
+///     computed from, and reading one executes the query again.
+///     `QueryTable::failed` tells whether a key fails without cloning
+///     the value.
+///   - `#[salsa::specifiable]` -- for a derived query, generates a
+///     `specify_my_query` method, with which another query can specify
+///     the value of a key as a side product of its own execution (see
+///     `QueryTable::specify`).
+///   - `#[salsa::multi_version]` -- for an input or derived query,
+///     keeps the values that snapshots pinned with
+///     `ParallelDatabase::snapshot_at_current_revision` may still read
+///     after the database moved on to a newer revision. Such snapshots
+///     can only read multi-version queries once that happened.
+/// - Values:
+///   - `#[salsa::arc]` -- wraps the value of the query in an `Arc`:
+///     for `fn my_query(&self, input: u32) -> T`, the accessor returns
+///     `Arc<T>`, while the query function still returns `T` (and,
+///     for inputs, `set_my_query` still takes `T`). This saves
+///     writing `Arc` in the signatures of queries whose values are
+///     expensive to clone. Not allowed on interned or transparent
+///     queries.
+///   - `#[salsa::send_only]` -- for a query whose value is `Send` but
+///     not `Sync` (say, because it contains a `Cell`), stores the value
+///     in a `salsa::SendOnly`, which is only accessed under a lock and
+///     never lends out references, so that the database can still be
+///     shared with other threads. The accessor returns a clone of the
+///     value (and the query function and setters deal in the value as
+///     well). Not allowed on interned, tracked or transparent queries,
+///     nor together with `arc`, `catch_panics` or `fallible`.
+///   - `#[salsa::fields(text: String, version: u32)]` -- for an
+///     input whose value is a struct, generates a memoized query for
+///     each listed field (giving its name and type), named after the
+///     input and the field (`my_query_text`, `my_query_version`),
+///     which returns a clone of that field. Queries that read a field
+///     through such a query only depend on that field: when the input
+///     changes, they are re-executed only if the field changed, too.
+///     The field types must implement `Eq`.
+/// - Caching:
+///   - `#[salsa::lru_cost(path::to::cost_fn)]` -- for a memoized
+///     query, makes its LRU cache cost-based: `cost_fn(&value) ->
+///     usize` estimates the cost (e.g., the size in bytes) of each
+///     value, and the capacity given to `set_lru_capacity` becomes a
+///     budget for the total cost of the values kept in memory.
+///   - `#[salsa::value_store(path::to::store_fn)]` -- for a memoized
+///     query, keeps its values in a `salsa::ValueStore` (say, on disk)
+///     rather than in memory: `store_fn(db) -> &dyn ValueStore<K, V>`
+///     gives the store, to which each new value is saved, and from
+///     which it is loaded whenever it is read. Only the revision
+///     stamps and dependencies of the values stay in memory.
+///   - `#[salsa::max_age(duration)]` -- for a derived query, makes
+///     its memoized values expire once `duration` (a constant
+///     expression of type `std::time::Duration`) has passed since they
+///     were computed. An expired value is recomputed the next time it
+///     is read, even within a revision; this is meant for queries that
+///     read untracked state that changes over time (say, the current
+///     git `HEAD`). Queries that already read the old value within
+///     the current revision keep using it until the next revision.
+///     Values of such queries always have low durability.
+///   - `#[salsa::heap_size(path::to::size_fn)]` -- gives a function
+///     `size_fn(&value) -> usize` that estimates the heap memory (in
+///     bytes) owned by a value, which `QueryTable::memory_usage` and
+///     `Database::salsa_memory_usage` add to their estimates. Cannot
+///     be set on interned queries.
+///   - `#[salsa::eq_with(path::to::eq_fn)]` -- for a memoized query
+///     whose value does not implement `Eq` (because it holds an `f64`
+///     or a trait object, say): `eq_fn(&old, &new) -> bool` decides
+///     whether a recomputed value equals the old one, so that the memo
+///     can be backdated.
+///   - `#[salsa::no_backdate]` -- for a memoized query whose value
+///     does not implement `Eq`: the value is still memoized, but a
+///     recomputed value is always considered changed, so the queries
+///     that depend on it are re-executed too.
+///   - `#[salsa::hasher(std::collections::hash_map::RandomState)]` --
+///     for an input or derived query, hashes its keys with the given
+///     `BuildHasher` (which must implement `Default`) rather than with
+///     `FxHasher`; say, to resist collisions when the keys come from
+///     untrusted sources.
+///   - `#[salsa::ordered]` -- for an input or derived query whose key
+///     implements `Ord`, iterates over its storage in the order of the
+///     keys, so that `QueryTable::entries` and `debug_dump` list the
+///     entries in a deterministic order.
+/// - Persistence:
+///   - `#[salsa::persist]` -- includes the query's results when the
+///     database is saved with `Database::serialize_memos` (requires
+///     the `persist` feature of salsa, and that the keys and values
+///     of the query implement `Serialize` and `Deserialize`).
+/// - Reflection:
+///   - `#[salsa::dynamic]` -- allows invoking the query by name with
+///     `Database::query_by_name` (requires the `dynamic` feature of
+///     salsa, and that the keys of the query implement `Deserialize`
+///     and its value `Serialize`).
+///   - `#[salsa::display_key(path)]` -- formats the keys of the query
+///     with the function `path`, of type `fn(&Key, &mut
+///     fmt::Formatter<'_>) -> fmt::Result`, where the database-key is
+///     displayed (in cycle errors and `Event`s, for example). By
+///     default, the query's name is followed by the `Debug` output of
+///     its keys.
 ///
 /// # Storage attributes
 ///
@@ -85,6 +263,23 @@ mod query_group;
 /// value has changed, and so we will potentially re-execute derived
 /// queries that read (transitively) from this input.
 ///
+/// ## Tracked queries
+///
+/// `#[salsa::tracked]` gives a query that creates **tracked entities**,
+/// such as the items found while lowering a file: for `fn item(&self,
+/// key: ItemKey) -> ItemId`, where `ItemId` implements `InternKey`,
+/// invoking `item(key)` from within a query (the creator) returns an
+/// id for the key, and `lookup_item(id)` returns the key. The id stays
+/// the same as long as the creator creates the same key each time it
+/// is executed; different creators get different ids for the same
+/// key. Once the creator is executed without creating the key, the
+/// entity is freed by the next sweep, and ids are never reused.
+/// Tracked queries cannot be invoked outside of a query.
+///
+/// Entities can carry fields as queries keyed by their ids: make
+/// those queries `#[salsa::specifiable]`, and specify them in the
+/// creator.
+///
 /// ## Derived queries
 ///
 /// Derived queries are specified by a function.
@@ -99,6 +294,34 @@ mod query_group;
 ///   be recomputed every time it is needed. We do track the inputs, however,
 ///   so if they have not changed, then things that rely on this query
 ///   may be known not to have changed.
+/// - `#[salsa::weak]` -- for queries returning an `Arc<T>`: the memo
+///   only holds a weak reference to the value, so that its memory is
+///   freed as soon as nobody else holds on to it. If the value is
+///   needed after that, it is recomputed. This requires that `T`
+///   implements `Eq`.
+/// - `#[salsa::firewall]` -- like `memoized`, but also keeps a 128-bit
+///   hash of the value. When the inputs of the query change, the
+///   queries that depend on it re-execute it and compare the hashes,
+///   and are only invalidated if the value changed. This is meant for
+///   cheap "projections" of large inputs (say, the names of the items
+///   in a file), and requires that the value implements `Hash`.
+/// - `#[salsa::dedup]` -- for queries returning an `Arc<T>`: like
+///   `memoized`, but equal values are shared. When the query computes
+///   a value that is equal to one that another key (of this or another
+///   `dedup` query returning `Arc<T>`) already holds, it returns and
+///   memoizes that value instead, so that only one copy is kept in
+///   memory. This is meant for queries where many keys produce the
+///   same large values (say, empty lists of diagnostics), and requires
+///   that `T` implements `Eq` and `Hash`. The statistics of the query
+///   count how many values were shared (see
+///   `QueryStatistics::deduplications`).
+/// - `#[salsa::fingerprint]` -- like `memoized`, but rather than
+///   comparing the old and new values with `Eq`, compares a 128-bit
+///   hash of them, which is kept next to the value. This requires that
+///   the value implements `Hash` (and not `Eq`), and is meant for large
+///   values that are expensive to compare. A custom fingerprint
+///   function `fn(&Value) -> u128` can be given instead, as in
+///   `#[salsa::fingerprint(path::to::fn)]`.
 ///
 /// ## Attribute combinations
 ///
@@ -146,6 +369,31 @@ pub fn query_group(args: TokenStream, input: TokenStream) -> TokenStream {
 /// attribute, the struct needs to have a `runtime` field (of type
 /// [`salsa::Runtime`]) and to implement the `salsa::Database` trait.
 ///
+/// Bundles of query groups (see `query_group_bundle!`) and query groups
+/// that extend others (see `#[salsa::extends]`) can be listed too,
+/// followed by a `!`, as in
+/// `#[salsa::database(MyQueryGroup1, plugin::plugin_groups!)]`.
+///
+/// The database need not be `'static`: it can borrow from its
+/// environment (say, a memory-mapped file) for as long as it lives, by
+/// taking a lifetime parameter, which its runtime mentions as well:
+///
+/// ```rust,ignore
+/// #[salsa::database(MyQueryGroup1)]
+/// struct MyDatabase<'env> {
+///     runtime: salsa::Runtime<MyDatabase<'env>>,
+///     source: &'env [u8],
+/// }
+/// ```
+///
+/// The queries can then read the environment through a method of the
+/// database (which a supertrait of the query group declares). This
+/// need not be tracked, as the environment cannot change while it is
+/// borrowed. Snapshots can be sent to threads spawned with
+/// `std::thread::scope`; `Database::prefetch`, `QueryTable::stream` and
+/// `salsa::replay` spawn threads (or downcast the database) of their
+/// own, so they still require a `'static` database.
+///
 /// See [the `hello_world` example][hw] for more details.
 ///
 /// [`salsa::Runtime`]: struct.Runtime.html
@@ -154,3 +402,33 @@ pub fn query_group(args: TokenStream, input: TokenStream) -> TokenStream {
 pub fn database(args: TokenStream, input: TokenStream) -> TokenStream {
     database_storage::database(args, input)
 }
+
+/// Defines a bundle of query groups, which a database can include as a
+/// whole. This lets a crate (say, a plugin) contribute several query
+/// groups to a database defined in another crate, without that crate
+/// naming each of them:
+///
+/// ```rust,ignore
+/// // In the plugin crate:
+/// salsa::query_group_bundle! {
+///     /// The query groups of the plugin.
+///     pub plugin_groups = [crate::parse::ParseStorage, crate::check::CheckStorage];
+/// }
+///
+/// // In the host crate:
+/// #[salsa::database(HostStorage, plugin::plugin_groups!)]
+/// struct MyDatabase {
+///     runtime: salsa::Runtime<MyDatabase>,
+/// }
+/// ```
+///
+/// The bundle is a `macro_rules!` macro, which the `database`
+/// attribute invokes to get the query groups; it is exported (with
+/// `#[macro_export]`) if it is declared `pub`. Paths starting with
+/// `crate` refer to the crate defining the bundle; other paths are
+/// resolved where the database is defined. A bundle can include other
+/// bundles, written `path::to::bundle!` as well.
+#[proc_macro]
+pub fn query_group_bundle(input: TokenStream) -> TokenStream {
+    bundle::query_group_bundle(input)
+}
@@ -1,5 +1,6 @@
 use std::convert::TryFrom;
 
+use crate::database_storage::QueryGroup;
 use crate::parenthesized::Parenthesized;
 use heck::CamelCase;
 use proc_macro::TokenStream;
@@ -20,18 +21,58 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
 
     let (trait_attrs, salsa_attrs) = filter_attrs(input.attrs);
     let mut requires: Punctuated<Path, Token![+]> = Punctuated::new();
+    let mut extends: Vec<QueryGroup> = Vec::new();
     for SalsaAttr { name, tts } in salsa_attrs {
         match name.as_str() {
             "requires" => {
                 requires.push(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
             }
+            "extends" => {
+                extends.push(parse_macro_input!(tts as Parenthesized<QueryGroup>).0);
+            }
             _ => panic!("unknown salsa attribute `{}`", name),
         }
     }
 
     let trait_vis = input.vis;
     let trait_name = input.ident;
-    let _generics = input.generics.clone();
+
+    // The type parameters of a generic query group (say,
+    // `trait MyDatabase<T: Ast>`), which the group struct, the query
+    // types and the storage get too.
+    let mut generics = input.generics.clone();
+    for param in generics.params.iter_mut() {
+        match param {
+            syn::GenericParam::Type(param) => {
+                param.eq_token = None;
+                param.default = None;
+            }
+            _ => panic!("query groups can only have type parameters"),
+        }
+    }
+    let is_generic = !generics.params.is_empty();
+    if is_generic && !extends.is_empty() {
+        panic!("generic query groups cannot be #[salsa::extends]");
+    }
+    let gen_params = &generics.params;
+    let gen_args: Vec<_> = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let ty_args = if is_generic {
+        quote! { <#(#gen_args),*> }
+    } else {
+        quote! {}
+    };
+    let gen_where = match &generics.where_clause {
+        Some(where_clause) => {
+            let predicates = where_clause.predicates.iter();
+            quote! { #(#predicates,)* }
+        }
+        None => quote! {},
+    };
+    let trait_ref = quote! { #trait_name #ty_args };
+    let group_struct_ty = quote! { #group_struct #ty_args };
 
     // Decompose the trait into the corresponding queries.
     let mut queries = vec![];
@@ -45,6 +86,26 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     Span::call_site(),
                 );
                 let mut num_storages = 0;
+                let mut persist = false;
+                let mut dynamic = false;
+                let mut lru_cost = None;
+                let mut fingerprint = None;
+                let mut value_store = None;
+                let mut arc = false;
+                let mut send_only = false;
+                let mut fields = None;
+                let mut max_age = None;
+                let mut shallow_revalidation = false;
+                let mut keep_previous = false;
+                let mut catch_panics = false;
+                let mut fallible = None;
+                let mut multi_version = false;
+                let mut specifiable = false;
+                let mut heap_size = None;
+                let mut display_key = None;
+                let mut value_eq = None;
+                let mut key_hasher = None;
+                let mut ordered = false;
 
                 // Extract attributes.
                 let (attrs, salsa_attrs) = filter_attrs(method.attrs);
@@ -58,6 +119,26 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                             storage = QueryStorage::Dependencies;
                             num_storages += 1;
                         }
+                        "weak" => {
+                            storage = QueryStorage::Weak;
+                            num_storages += 1;
+                        }
+                        "firewall" => {
+                            storage = QueryStorage::Firewall;
+                            num_storages += 1;
+                        }
+                        "dedup" => {
+                            storage = QueryStorage::Dedup;
+                            num_storages += 1;
+                        }
+                        "fingerprint" => {
+                            storage = QueryStorage::Fingerprint;
+                            num_storages += 1;
+                            if !tts.is_empty() {
+                                fingerprint =
+                                    Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                            }
+                        }
                         "input" => {
                             storage = QueryStorage::Input;
                             num_storages += 1;
@@ -66,6 +147,10 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                             storage = QueryStorage::Interned;
                             num_storages += 1;
                         }
+                        "tracked" => {
+                            storage = QueryStorage::Tracked;
+                            num_storages += 1;
+                        }
                         "invoke" => {
                             invoke = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
                         }
@@ -76,6 +161,95 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                             storage = QueryStorage::Transparent;
                             num_storages += 1;
                         }
+                        "persist" => {
+                            persist = true;
+                        }
+                        "dynamic" => {
+                            dynamic = true;
+                        }
+                        "lru_cost" => {
+                            lru_cost = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "value_store" => {
+                            value_store =
+                                Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "arc" => {
+                            arc = true;
+                        }
+                        "send_only" => {
+                            send_only = true;
+                        }
+                        "heap_size" => {
+                            heap_size = Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "display_key" => {
+                            display_key =
+                                Some(parse_macro_input!(tts as Parenthesized<syn::Path>).0);
+                        }
+                        "eq_with" => {
+                            if value_eq.is_some() {
+                                panic!("multiple #[salsa::eq_with] or #[salsa::no_backdate]");
+                            }
+                            let eq = parse_macro_input!(tts as Parenthesized<syn::Path>).0;
+                            value_eq = Some(ValueEq::With(eq));
+                        }
+                        "no_backdate" => {
+                            if value_eq.is_some() {
+                                panic!("multiple #[salsa::eq_with] or #[salsa::no_backdate]");
+                            }
+                            value_eq = Some(ValueEq::Never);
+                        }
+                        "hasher" => {
+                            key_hasher =
+                                Some(parse_macro_input!(tts as Parenthesized<syn::Type>).0);
+                        }
+                        "ordered" => {
+                            ordered = true;
+                        }
+                        "max_age" => {
+                            max_age = Some(parse_macro_input!(tts as Parenthesized<syn::Expr>).0);
+                        }
+                        "shallow_revalidation" => {
+                            shallow_revalidation = true;
+                        }
+                        "keep_previous" => {
+                            keep_previous = true;
+                        }
+                        "catch_panics" => {
+                            catch_panics = true;
+                        }
+                        "fallible" => {
+                            let options = if tts.is_empty() {
+                                Punctuated::new()
+                            } else {
+                                let Parenthesized(FallibleOptions(options)) =
+                                    parse_macro_input!(tts as Parenthesized<FallibleOptions>);
+                                options
+                            };
+                            let mut no_backdate = false;
+                            let mut no_memoize = false;
+                            for option in options {
+                                match option.to_string().as_str() {
+                                    "no_backdate" => no_backdate = true,
+                                    "no_memoize" => no_memoize = true,
+                                    _ => panic!("unknown #[salsa::fallible] option `{}`", option),
+                                }
+                            }
+                            fallible = Some(Fallible {
+                                no_backdate,
+                                no_memoize,
+                            });
+                        }
+                        "multi_version" => {
+                            multi_version = true;
+                        }
+                        "specifiable" => {
+                            specifiable = true;
+                        }
+                        "fields" => {
+                            fields = Some(parse_macro_input!(tts as Parenthesized<FieldList>).0);
+                        }
                         _ => panic!("unknown salsa attribute `{}`", name),
                     }
                 }
@@ -87,6 +261,125 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 if invoke.is_some() && storage == QueryStorage::Input {
                     panic!("#[salsa::invoke] cannot be set on #[salsa::input] queries");
                 }
+                if persist && storage == QueryStorage::Transparent {
+                    panic!("#[salsa::persist] cannot be set on #[salsa::transparent] queries");
+                }
+                if persist && storage == QueryStorage::Tracked {
+                    panic!("#[salsa::persist] cannot be set on #[salsa::tracked] queries");
+                }
+                if dynamic && storage == QueryStorage::Transparent {
+                    panic!("#[salsa::dynamic] cannot be set on #[salsa::transparent] queries");
+                }
+                if lru_cost.is_some()
+                    && storage != QueryStorage::Memoized
+                    && storage != QueryStorage::Fingerprint
+                {
+                    panic!("#[salsa::lru_cost] can only be set on memoized queries");
+                }
+                if value_store.is_some()
+                    && storage != QueryStorage::Memoized
+                    && storage != QueryStorage::Fingerprint
+                {
+                    panic!("#[salsa::value_store] can only be set on memoized queries");
+                }
+                if arc
+                    && (storage == QueryStorage::Interned
+                        || storage == QueryStorage::Tracked
+                        || storage == QueryStorage::Transparent)
+                {
+                    panic!(
+                        "#[salsa::arc] cannot be set on interned, tracked or transparent queries"
+                    );
+                }
+                if send_only
+                    && (storage == QueryStorage::Interned
+                        || storage == QueryStorage::Tracked
+                        || storage == QueryStorage::Transparent)
+                {
+                    panic!(
+                        "#[salsa::send_only] cannot be set on interned, tracked or transparent \
+                         queries"
+                    );
+                }
+                if send_only && (arc || catch_panics || fallible.is_some()) {
+                    panic!(
+                        "#[salsa::send_only] queries cannot be #[salsa::arc], \
+                         #[salsa::catch_panics] or #[salsa::fallible]"
+                    );
+                }
+                if heap_size.is_some()
+                    && (storage == QueryStorage::Interned
+                        || storage == QueryStorage::Tracked
+                        || storage == QueryStorage::Transparent)
+                {
+                    panic!(
+                        "#[salsa::heap_size] cannot be set on interned, tracked or transparent \
+                         queries"
+                    );
+                }
+                if max_age.is_some() && !storage.needs_query_function() {
+                    panic!("#[salsa::max_age] can only be set on derived queries");
+                }
+                if shallow_revalidation && !storage.needs_query_function() {
+                    panic!("#[salsa::shallow_revalidation] can only be set on derived queries");
+                }
+                if keep_previous
+                    && storage != QueryStorage::Memoized
+                    && storage != QueryStorage::Fingerprint
+                {
+                    panic!("#[salsa::keep_previous] can only be set on memoized queries");
+                }
+                if catch_panics && !storage.needs_query_function() {
+                    panic!("#[salsa::catch_panics] can only be set on derived queries");
+                }
+                if catch_panics && arc {
+                    panic!("#[salsa::catch_panics] queries cannot be #[salsa::arc]");
+                }
+                if fallible.is_some() && storage != QueryStorage::Memoized {
+                    panic!("#[salsa::fallible] can only be set on memoized queries");
+                }
+                if fallible.is_some() && (arc || catch_panics) {
+                    panic!(
+                        "#[salsa::fallible] queries cannot be #[salsa::arc] or \
+                         #[salsa::catch_panics]"
+                    );
+                }
+                if multi_version
+                    && storage != QueryStorage::Input
+                    && !storage.needs_query_function()
+                {
+                    panic!("#[salsa::multi_version] can only be set on input and derived queries");
+                }
+                if (key_hasher.is_some() || ordered)
+                    && storage != QueryStorage::Input
+                    && !storage.needs_query_function()
+                {
+                    panic!(
+                        "#[salsa::hasher] and #[salsa::ordered] can only be set on input and \
+                         derived queries"
+                    );
+                }
+                if specifiable && !storage.needs_query_function() {
+                    panic!("#[salsa::specifiable] can only be set on derived queries");
+                }
+                if specifiable && multi_version {
+                    panic!("#[salsa::specifiable] queries cannot be #[salsa::multi_version]");
+                }
+                if fields.is_some() && storage != QueryStorage::Input {
+                    panic!("#[salsa::fields] can only be set on input queries");
+                }
+                if value_eq.is_some() && storage != QueryStorage::Memoized {
+                    panic!(
+                        "#[salsa::eq_with] and #[salsa::no_backdate] can only be set on memoized \
+                         queries"
+                    );
+                }
+                if value_eq.is_some() && fallible.is_some() {
+                    panic!(
+                        "#[salsa::fallible] queries cannot be #[salsa::eq_with] or \
+                         #[salsa::no_backdate]"
+                    );
+                }
 
                 // Extract keys.
                 let mut iter = method.sig.inputs.iter();
@@ -116,7 +409,38 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     ),
                 };
 
-                // For `#[salsa::interned]` keys, we create a "lookup key" automatically.
+                // For `#[salsa::arc]` queries, the value is stored as
+                // `Arc<T>`, while the query function returns (and
+                // the setters take) `T`. The same goes for
+                // `#[salsa::send_only]` queries and `SendOnly<T>`, except
+                // that their accessors return `T` as well.
+                let (value, unwrapped_value) = if arc {
+                    (parse_quote!(std::sync::Arc<#value>), Some(value))
+                } else if send_only {
+                    (parse_quote!(salsa::SendOnly<#value>), Some(value))
+                } else {
+                    (value, None)
+                };
+
+                // For `#[salsa::fallible]` queries, the value is stored as
+                // `Result<T, salsa::Failed<E>>`, while the query function
+                // returns `Result<T, E>`.
+                let value = match &fallible {
+                    Some(_) => {
+                        let (ok, err) = match result_types(&value) {
+                            Some(types) => types,
+                            None => panic!(
+                                "#[salsa::fallible] query `{}` must return a `Result<T, E>`",
+                                method.sig.ident
+                            ),
+                        };
+                        parse_quote!(Result<#ok, salsa::Failed<#err>>)
+                    }
+                    None => value,
+                };
+
+                // For `#[salsa::interned]` and `#[salsa::tracked]` keys, we
+                // create a "lookup key" automatically.
                 //
                 // For a query like:
                 //
@@ -125,7 +449,16 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 // we would create
                 //
                 //     fn lookup_foo(&self, x: u32) -> (Key1, Key2)
-                let lookup_query = if let QueryStorage::Interned = storage {
+                let lookup_storage = match storage {
+                    QueryStorage::Interned => Some(QueryStorage::InternedLookup {
+                        intern_query_type: query_type.clone(),
+                    }),
+                    QueryStorage::Tracked => Some(QueryStorage::TrackedLookup {
+                        tracked_query_type: query_type.clone(),
+                    }),
+                    _ => None,
+                };
+                let lookup_query = if let Some(lookup_storage) = lookup_storage {
                     let lookup_query_type = Ident::new(
                         &format!(
                             "{}LookupQuery",
@@ -144,17 +477,98 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                         query_type: lookup_query_type,
                         fn_name: lookup_fn_name,
                         attrs: vec![], // FIXME -- some automatically generated docs on this method?
-                        storage: QueryStorage::InternedLookup {
-                            intern_query_type: query_type.clone(),
-                        },
+                        storage: lookup_storage,
                         keys: lookup_keys,
                         value: lookup_value,
+                        unwrapped_value: None,
+                        send_only: false,
+                        field_of: None,
                         invoke: None,
+                        persist: false,
+                        max_age: None,
+                        shallow_revalidation: false,
+                        keep_previous: false,
+                        catch_panics: false,
+                        fallible: None,
+                        multi_version: false,
+                        specifiable: false,
+                        heap_size: None,
+                        display_key: None,
+                        value_eq: None,
+                        key_hasher: None,
+                        ordered: false,
+                        dynamic: false,
+                        lru_cost: None,
+                        fingerprint: None,
+                        value_store: None,
                     })
                 } else {
                     None
                 };
 
+                // For `#[salsa::fields]` inputs, we create a derived
+                // query for each field. For an input like:
+                //
+                //     #[salsa::fields(text: String)]
+                //     fn file(&self, x: Key1) -> File
+                //
+                // we would create
+                //
+                //     fn file_text(&self, x: Key1) -> String
+                //
+                // which reads the input and returns `.text` of it. When
+                // the input changes but the field does not, the value of
+                // the field query is backdated, so queries that only read
+                // the field are not invalidated.
+                let input_fn = &method.sig.ident;
+                let field_queries: Vec<Query> = fields
+                    .into_iter()
+                    .flat_map(|fields| fields.0)
+                    .map(|FieldDecl { name, ty }| {
+                        let fn_name =
+                            Ident::new(&format!("{}_{}", input_fn, name), input_fn.span());
+                        let query_type = Ident::new(
+                            &format!("{}Query", fn_name.to_string().to_camel_case()),
+                            Span::call_site(),
+                        );
+                        let doc = format!(
+                            "The `{field}` field of `{input}`; reading it only depends \
+                             on that field of the input.",
+                            field = name,
+                            input = input_fn,
+                        );
+                        Query {
+                            query_type,
+                            fn_name,
+                            attrs: vec![parse_quote!(#[doc = #doc])],
+                            storage: QueryStorage::Memoized,
+                            keys: keys.clone(),
+                            value: ty,
+                            unwrapped_value: None,
+                            send_only: false,
+                            field_of: Some((input_fn.clone(), name)),
+                            invoke: None,
+                            persist,
+                            max_age: None,
+                            shallow_revalidation: false,
+                            keep_previous: false,
+                            catch_panics: false,
+                            fallible: None,
+                            multi_version,
+                            specifiable: false,
+                            heap_size: None,
+                            display_key: None,
+                            value_eq: None,
+                            key_hasher: key_hasher.clone(),
+                            ordered,
+                            dynamic: false,
+                            lru_cost: None,
+                            fingerprint: None,
+                            value_store: None,
+                        }
+                    })
+                    .collect();
+
                 queries.push(Query {
                     query_type,
                     fn_name: method.sig.ident,
@@ -162,10 +576,31 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                     storage,
                     keys,
                     value,
+                    unwrapped_value,
+                    send_only,
+                    field_of: None,
                     invoke,
+                    persist,
+                    max_age,
+                    shallow_revalidation,
+                    keep_previous,
+                    catch_panics,
+                    fallible,
+                    multi_version,
+                    specifiable,
+                    heap_size,
+                    display_key,
+                    value_eq,
+                    key_hasher,
+                    ordered,
+                    dynamic,
+                    lru_cost,
+                    fingerprint,
+                    value_store,
                 });
 
                 queries.extend(lookup_query);
+                queries.extend(field_queries);
             }
             _ => (),
         }
@@ -181,9 +616,14 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         Span::call_site(),
     );
 
+    let group_key_ty = quote! { #group_key #ty_args };
+
     let mut query_fn_declarations = proc_macro2::TokenStream::new();
     let mut query_fn_definitions = proc_macro2::TokenStream::new();
     let mut query_descriptor_variants = proc_macro2::TokenStream::new();
+    let mut key_variants = vec![];
+    let mut display_key_arms = proc_macro2::TokenStream::new();
+    let mut debug_key_arms = proc_macro2::TokenStream::new();
     let mut group_data_elements = vec![];
     let mut storage_fields = proc_macro2::TokenStream::new();
     let mut storage_defaults = proc_macro2::TokenStream::new();
@@ -194,12 +634,24 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         let keys = &query.keys;
         let value = &query.value;
         let fn_name = &query.fn_name;
-        let qt = &query.query_type;
+        let query_type = &query.query_type;
+        let qt = &quote! { #query_type #ty_args };
         let attrs = &query.attrs;
 
+        // The value returned by the accessors, and how to get it from
+        // the stored value (or from an `Option` of it).
+        let (get_value, unwrap_value, unwrap_maybe) = match &query.unwrapped_value {
+            Some(unwrapped_value) if query.send_only => (
+                unwrapped_value,
+                quote! { .into_inner() },
+                quote! { .map(salsa::SendOnly::into_inner) },
+            ),
+            _ => (value, quote! {}, quote! {}),
+        };
+
         query_fn_declarations.extend(quote! {
             #(#attrs)*
-            fn #fn_name(&self, #(#key_names: #keys),*) -> #value;
+            fn #fn_name(&self, #(#key_names: #keys),*) -> #get_value;
         });
 
         // Special case: transparent queries don't create actual storage,
@@ -215,16 +667,52 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         }
 
         query_fn_definitions.extend(quote! {
-            fn #fn_name(&self, #(#key_names: #keys),*) -> #value {
-                <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(self).get((#(#key_names),*))
+            fn #fn_name(&self, #(#key_names: #keys),*) -> #get_value {
+                <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(self).get((#(#key_names),*))#unwrap_value
             }
         });
 
+        // The value passed to the setters, and how to store it.
+        let (set_value, wrap_value) = match &query.unwrapped_value {
+            Some(unwrapped_value) if query.send_only => {
+                (unwrapped_value, quote! { salsa::SendOnly::new(value__) })
+            }
+            Some(unwrapped_value) => (unwrapped_value, quote! { std::sync::Arc::new(value__) }),
+            None => (value, quote! { value__ }),
+        };
+
+        // For `#[salsa::specifiable]` queries, we need `specify_foo`
+        if query.specifiable {
+            let specify_fn_name = Ident::new(&format!("specify_{}", fn_name), fn_name.span());
+            let specify_fn_docs = format!(
+                "
+                Specify the value of `{fn_name}`, from within the query
+                that is currently executing.
+
+                See `QueryTable::specify` for details.
+            ",
+                fn_name = fn_name
+            );
+
+            query_fn_declarations.extend(quote! {
+                # [doc = #specify_fn_docs]
+                fn #specify_fn_name(&self, #(#key_names: #keys,)* value__: #set_value);
+            });
+
+            query_fn_definitions.extend(quote! {
+                fn #specify_fn_name(&self, #(#key_names: #keys,)* value__: #set_value) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(self).specify((#(#key_names),*), #wrap_value)
+                }
+            });
+        }
+
         // For input queries, we need `set_foo` etc
         if let QueryStorage::Input = query.storage {
             let set_fn_name = Ident::new(&format!("set_{}", fn_name), fn_name.span());
             let set_with_durability_fn_name =
                 Ident::new(&format!("set_{}_with_durability", fn_name), fn_name.span());
+            let remove_fn_name = Ident::new(&format!("remove_{}", fn_name), fn_name.span());
+            let maybe_fn_name = Ident::new(&format!("maybe_{}", fn_name), fn_name.span());
 
             let set_fn_docs = format!(
                 "
@@ -253,22 +741,56 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 fn_name = fn_name
             );
 
+            let remove_fn_docs = format!(
+                "
+                Remove the value of the `{fn_name}` input, so that
+                `{fn_name}` panics and `maybe_{fn_name}` returns `None`.
+
+                *Note:* Removing values will trigger cancellation
+                of any ongoing queries; this method blocks until
+                those queries have been cancelled.
+            ",
+                fn_name = fn_name
+            );
+
+            let maybe_fn_docs = format!(
+                "
+                Like `{fn_name}`, but returns `None` if no value is set
+                (or it was removed) instead of panicking.
+            ",
+                fn_name = fn_name
+            );
+
             query_fn_declarations.extend(quote! {
                 # [doc = #set_fn_docs]
-                fn #set_fn_name(&mut self, #(#key_names: #keys,)* value__: #value);
+                fn #set_fn_name(&mut self, #(#key_names: #keys,)* value__: #set_value);
 
 
                 # [doc = #set_constant_fn_docs]
-                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #value, durability__: salsa::Durability);
+                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #set_value, durability__: salsa::Durability);
+
+                # [doc = #remove_fn_docs]
+                fn #remove_fn_name(&mut self, #(#key_names: #keys),*);
+
+                # [doc = #maybe_fn_docs]
+                fn #maybe_fn_name(&self, #(#key_names: #keys),*) -> Option<#get_value>;
             });
 
             query_fn_definitions.extend(quote! {
-                fn #set_fn_name(&mut self, #(#key_names: #keys,)* value__: #value) {
-                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set((#(#key_names),*), value__)
+                fn #set_fn_name(&mut self, #(#key_names: #keys,)* value__: #set_value) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set((#(#key_names),*), #wrap_value)
+                }
+
+                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #set_value, durability__: salsa::Durability) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set_with_durability((#(#key_names),*), #wrap_value, durability__)
+                }
+
+                fn #remove_fn_name(&mut self, #(#key_names: #keys),*) {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).remove((#(#key_names),*))
                 }
 
-                fn #set_with_durability_fn_name(&mut self, #(#key_names: #keys,)* value__: #value, durability__: salsa::Durability) {
-                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table_mut(self).set_with_durability((#(#key_names),*), value__, durability__)
+                fn #maybe_fn_name(&self, #(#key_names: #keys),*) -> Option<#get_value> {
+                    <Self as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(self).get_maybe((#(#key_names),*))#unwrap_maybe
                 }
             });
         }
@@ -277,6 +799,43 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         query_descriptor_variants.extend(quote! {
             #fn_name((#(#keys),*)),
         });
+        key_variants.push((fn_name, quote! { (#(#keys),*) }));
+
+        // An arm of its `Display` impl: the `#[salsa::display_key]`
+        // function, or else `fn_name(key0, key1)` with the keys
+        // debug-formatted.
+        let query_name = fn_name.to_string();
+        let debug_keys = debug_keys(key_names);
+        display_key_arms.extend(match &query.display_key {
+            Some(display_key) => quote! {
+                #group_key::#fn_name(key) => #display_key(key, fmt),
+            },
+            None => {
+                let open = format!("{}(", query_name);
+                quote! {
+                    #group_key::#fn_name((#(#key_names),*)) => {
+                        fmt.write_str(#open)?;
+                        #debug_keys
+                        fmt.write_str(")")
+                    }
+                }
+            }
+        });
+
+        // An arm of its `Debug` impl, which formats the keys like
+        // `#[derive(Debug)]` would, except for those without `Debug`.
+        let (open, close) = if key_names.len() == 1 {
+            (format!("{}(", query_name), ")")
+        } else {
+            (format!("{}((", query_name), "))")
+        };
+        debug_key_arms.extend(quote! {
+            #group_key::#fn_name((#(#key_names),*)) => {
+                fmt.write_str(#open)?;
+                #debug_keys
+                fmt.write_str(#close)
+            }
+        });
 
         // Entry for the query group data tuple
         group_data_elements.push(quote! {
@@ -295,31 +854,78 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
     // Emit the trait itself.
     let mut output = {
         let bounds = &input.supertraits;
+        let where_clause = &input.generics.where_clause;
+        let params = &input.generics.params;
         quote! {
             #(#trait_attrs)*
-            #trait_vis trait #trait_name : #bounds {
+            #trait_vis trait #trait_name<#params> : #bounds #where_clause {
                 #query_fn_declarations
             }
         }
     };
 
+    // A generic group struct, query type or group key does not hold
+    // values of its type parameters, but has to use them.
+    let phantom = quote! { std::marker::PhantomData<fn() -> (#(#gen_args,)*)> };
+
     // Emit the query group struct and impl of `QueryGroup`.
+    let group_struct_body = if is_generic {
+        quote! { { phantom: #phantom } }
+    } else {
+        quote! { { } }
+    };
     output.extend(quote! {
         /// Representative struct for the query group.
-        #trait_vis struct #group_struct { }
+        #trait_vis struct #group_struct<#gen_params> #group_struct_body
 
-        impl<DB__> salsa::plumbing::QueryGroup<DB__> for #group_struct
+        impl<DB__, #gen_params> salsa::plumbing::QueryGroup<DB__> for #group_struct_ty
         where
-            DB__: #trait_name + #requires,
-            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            DB__: #trait_ref + #requires,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+            #gen_where
             DB__: salsa::Database,
         {
-            type GroupStorage = #group_storage<DB__>;
-            type GroupKey = #group_key;
+            type GroupStorage = #group_storage<DB__, #(#gen_args),*>;
+            type GroupKey = #group_key_ty;
             type GroupData = (#(#group_data_elements),*);
         }
     });
 
+    // A query group that extends others is also a bundle (see
+    // `bundle::query_group_bundle`) of itself and the groups it extends,
+    // so that listing it in a database, as `path::to::MyGroup!`, adds
+    // the storage of these groups too. The bundle is imported next to
+    // the group struct, under the same name, so that both have the same
+    // path.
+    if !extends.is_empty() {
+        let parents = extends.iter().map(QueryGroup::absolute_tokens);
+        let export = match trait_vis {
+            syn::Visibility::Public(_) => quote! { #[macro_export] },
+            _ => quote! {},
+        };
+        let bundle = Ident::new(
+            &format!("__salsa_extends_{}", group_struct),
+            group_struct.span(),
+        );
+        let doc = format!(
+            "Lists `{}` and the query groups it extends in a `#[salsa::database]`.",
+            group_struct,
+        );
+        output.extend(quote! {
+            #[doc(hidden)]
+            #export
+            macro_rules! #bundle {
+                ([$($before:tt)*] [$($after:tt)*] [$($group:tt)*] $($database:tt)*) => {
+                    #[salsa::database($($before)* $($group)*, #(#parents,)* $($after)*)]
+                    $($database)*
+                };
+            }
+
+            #[doc = #doc]
+            #trait_vis use #bundle as #group_struct;
+        });
+    }
+
     // Emit an impl of the trait
     output.extend({
         let mut bounds = input.supertraits.clone();
@@ -332,10 +938,11 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             }));
         }
         quote! {
-            impl<T> #trait_name for T
+            impl<DB__, #gen_params> #trait_ref for DB__
             where
-                T: #bounds,
-                T: salsa::plumbing::HasQueryGroup<#group_struct>
+                DB__: #bounds,
+                DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+                #gen_where
             {
                 #query_fn_definitions
             }
@@ -346,41 +953,128 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
     for query in &queries {
         let fn_name = &query.fn_name;
         let qt = &query.query_type;
+        let qt_ty = quote! { #qt #ty_args };
 
         let db = quote! {DB};
 
         let storage = match &query.storage {
+            QueryStorage::Memoized if query.value_eq.is_some() => {
+                quote!(salsa::plumbing::EqWithStorage<#db, Self>)
+            }
             QueryStorage::Memoized => quote!(salsa::plumbing::MemoizedStorage<#db, Self>),
             QueryStorage::Dependencies => quote!(salsa::plumbing::DependencyStorage<#db, Self>),
+            QueryStorage::Weak => quote!(salsa::plumbing::WeakStorage<#db, Self>),
+            QueryStorage::Firewall => quote!(salsa::plumbing::FirewallStorage<#db, Self>),
+            QueryStorage::Dedup => quote!(salsa::plumbing::DedupStorage<#db, Self>),
+            QueryStorage::Fingerprint => quote!(salsa::plumbing::FingerprintStorage<#db, Self>),
             QueryStorage::Input => quote!(salsa::plumbing::InputStorage<#db, Self>),
             QueryStorage::Interned => quote!(salsa::plumbing::InternedStorage<#db, Self>),
             QueryStorage::InternedLookup { intern_query_type } => {
-                quote!(salsa::plumbing::LookupInternedStorage<#db, Self, #intern_query_type>)
+                quote!(salsa::plumbing::LookupInternedStorage<#db, Self, #intern_query_type #ty_args>)
+            }
+            QueryStorage::Tracked => quote!(salsa::plumbing::TrackedStorage<#db, Self>),
+            QueryStorage::TrackedLookup { tracked_query_type } => {
+                quote!(salsa::plumbing::LookupTrackedStorage<#db, Self, #tracked_query_type #ty_args>)
             }
             QueryStorage::Transparent => continue,
         };
         let keys = &query.keys;
         let value = &query.value;
+        let query_name = fn_name.to_string();
+        let key_names: &Vec<_> = &(0..query.keys.len())
+            .map(|i| Ident::new(&format!("key{}", i), Span::call_site()))
+            .collect();
+        let debug_key = if key_names.len() == 1 {
+            let debug_keys = debug_keys(key_names);
+            quote! {
+                let #(#key_names)* = key;
+                #debug_keys
+            }
+        } else {
+            let debug_keys = debug_keys(key_names);
+            quote! {
+                let (#(#key_names),*) = key;
+                fmt.write_str("(")?;
+                #debug_keys
+                fmt.write_str(")")?;
+            }
+        };
+        let heap_size = match &query.heap_size {
+            Some(heap_size) => quote! {
+                const HEAP_SIZE: Option<fn(&Self::Value) -> usize> = Some(#heap_size);
+            },
+            None => quote! {},
+        };
+        let multi_version = if query.multi_version {
+            quote! {
+                const MULTI_VERSION: bool = true;
+            }
+        } else {
+            quote! {}
+        };
+        let key_hasher = match &query.key_hasher {
+            Some(key_hasher) => quote! {
+                const KEY_HASHER: Option<fn() -> salsa::plumbing::KeyHasher> =
+                    Some(salsa::plumbing::KeyHasher::of::<#key_hasher>);
+            },
+            None => quote! {},
+        };
+        let key_order = if query.ordered {
+            quote! {
+                const KEY_ORDER: Option<salsa::plumbing::KeyOrderFn<Self::Key>> =
+                    Some(Ord::cmp);
+            }
+        } else {
+            quote! {}
+        };
 
         // Emit the query struct and implement the Query trait on it.
-        output.extend(quote! {
-            #[derive(Default, Debug)]
-            #trait_vis struct #qt;
+        output.extend(if is_generic {
+            let qt_name = qt.to_string();
+            quote! {
+                #trait_vis struct #qt<#gen_params>(#phantom);
+
+                impl<#gen_params> Default for #qt_ty {
+                    fn default() -> Self {
+                        #qt(std::marker::PhantomData)
+                    }
+                }
 
+                impl<#gen_params> std::fmt::Debug for #qt_ty {
+                    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        fmt.write_str(#qt_name)
+                    }
+                }
+            }
+        } else {
+            quote! {
+                #[derive(Default, Debug)]
+                #trait_vis struct #qt;
+            }
+        });
+        output.extend(quote! {
             // Unsafe proof obligation: that our key/value are a part
             // of the `GroupData`.
-            unsafe impl<#db> salsa::Query<#db> for #qt
+            unsafe impl<#db, #gen_params> salsa::Query<#db> for #qt_ty
             where
-                DB: #trait_name + #requires,
-                DB: salsa::plumbing::HasQueryGroup<#group_struct>,
+                DB: #trait_ref + #requires,
+                DB: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+                #gen_where
                 DB: salsa::Database,
             {
                 type Key = (#(#keys),*);
                 type Value = #value;
                 type Storage = #storage;
-                type Group = #group_struct;
-                type GroupStorage = #group_storage<#db>;
-                type GroupKey = #group_key;
+                type Group = #group_struct_ty;
+                type GroupStorage = #group_storage<#db, #(#gen_args),*>;
+                type GroupKey = #group_key_ty;
+
+                const QUERY_NAME: &'static str = #query_name;
+
+                #heap_size
+                #multi_version
+                #key_hasher
+                #key_order
 
                 fn query_storage(group_storage: &Self::GroupStorage) -> &Self::Storage {
                     &group_storage.#fn_name
@@ -389,6 +1083,17 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 fn group_key(key: Self::Key) -> Self::GroupKey {
                     #group_key::#fn_name(key)
                 }
+
+                fn debug_key(key: &Self::Key, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    use salsa::plumbing::{DebugViaDebug as _, DebugViaOpaque as _};
+                    #debug_key
+                    Ok(())
+                }
+
+                fn debug_value(value: &Self::Value, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    use salsa::plumbing::{DebugViaDebug as _, DebugViaOpaque as _};
+                    (&&salsa::plumbing::DebugOrOpaque(value)).fmt_debug(fmt)
+                }
             }
         });
 
@@ -404,28 +1109,216 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
                 quote! { (#(#key_names),*) }
             };
             let invoke = query.invoke_tt();
+            let execute = match (&query.field_of, &query.unwrapped_value) {
+                (Some((input, field)), _) => quote! {
+                    <DB as #trait_ref>::#input(db, #(#key_names),*).#field.clone()
+                },
+                (None, Some(_)) if query.send_only => {
+                    quote! { salsa::SendOnly::new(#invoke(db, #(#key_names),*)) }
+                }
+                (None, Some(_)) => quote! { std::sync::Arc::new(#invoke(db, #(#key_names),*)) },
+                (None, None) if query.catch_panics => quote! { Ok(#invoke(db, #(#key_names),*)) },
+                (None, None) if query.fallible.is_some() => {
+                    let wrap_error = if query.fallible.as_ref().unwrap().no_backdate {
+                        quote! { salsa::Failed::new }
+                    } else {
+                        quote! { salsa::Failed::comparable }
+                    };
+                    quote! { #invoke(db, #(#key_names),*).map_err(#wrap_error) }
+                }
+                (None, None) => quote! { #invoke(db, #(#key_names),*) },
+            };
+            let lru_cost = match &query.lru_cost {
+                Some(lru_cost) => quote! {
+                    const LRU_COST: Option<fn(&Self::Value) -> usize> = Some(#lru_cost);
+                },
+                None => quote! {},
+            };
+            let fingerprint = match (&query.storage, &query.fingerprint) {
+                (QueryStorage::Fingerprint, Some(fingerprint)) => quote! {
+                    const FINGERPRINT: Option<fn(&Self::Value) -> u128> = Some(#fingerprint);
+                },
+                (QueryStorage::Fingerprint, None) => quote! {
+                    const FINGERPRINT: Option<fn(&Self::Value) -> u128> =
+                        Some(salsa::plumbing::hash_fingerprint);
+                },
+                _ => quote! {},
+            };
+            let value_store = match &query.value_store {
+                Some(value_store) => quote! {
+                    const VALUE_STORE: Option<
+                        salsa::plumbing::ValueStoreFn<DB, Self::Key, Self::Value>,
+                    > = Some(#value_store);
+                },
+                None => quote! {},
+            };
+            let max_age = match &query.max_age {
+                Some(max_age) => quote! {
+                    const MAX_AGE: Option<std::time::Duration> = Some(#max_age);
+                },
+                None => quote! {},
+            };
+            let shallow_revalidation = if query.shallow_revalidation {
+                quote! {
+                    const SHALLOW_REVALIDATION: bool = true;
+                }
+            } else {
+                quote! {}
+            };
+            let keep_previous = if query.keep_previous {
+                quote! {
+                    const KEEP_PREVIOUS: bool = true;
+                }
+            } else {
+                quote! {}
+            };
+            let catch_panics = if query.catch_panics {
+                quote! {
+                    const CATCH_PANICS: Option<fn(salsa::QueryPanic) -> Self::Value> = Some(Err);
+                }
+            } else {
+                quote! {}
+            };
+            let forget_value = match &query.fallible {
+                Some(Fallible {
+                    no_memoize: true, ..
+                }) => quote! {
+                    const FORGET_VALUE: Option<fn(&Self::Value) -> bool> = Some(Result::is_err);
+                },
+                _ => quote! {},
+            };
+            let value_eq = match &query.value_eq {
+                Some(ValueEq::With(eq)) => quote! {
+                    const VALUE_EQ: Option<salsa::plumbing::ValueEqFn<Self::Value>> = Some(#eq);
+                },
+                _ => quote! {},
+            };
             output.extend(quote_spanned! {span=>
-                impl<DB> salsa::plumbing::QueryFunction<DB> for #qt
+                impl<DB, #gen_params> salsa::plumbing::QueryFunction<DB> for #qt_ty
                 where
-                    DB: #trait_name + #requires,
-                    DB: salsa::plumbing::HasQueryGroup<#group_struct>,
+                    DB: #trait_ref + #requires,
+                    DB: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+                    #gen_where
                     DB: salsa::Database,
                 {
                     fn execute(db: &DB, #key_pattern: <Self as salsa::Query<DB>>::Key)
                         -> <Self as salsa::Query<DB>>::Value {
-                        #invoke(db, #(#key_names),*)
+                        #execute
                     }
+
+                    #lru_cost
+                    #fingerprint
+                    #value_store
+                    #max_age
+                    #shallow_revalidation
+                    #keep_previous
+                    #catch_panics
+                    #forget_value
+                    #value_eq
                 }
             });
         }
     }
 
-    // Emit query group descriptor
+    // Emit query group descriptor. The descriptor of a generic group
+    // has a variant that uses its type parameters, and cannot be
+    // constructed. Its impls are written out, as deriving them would
+    // require the type parameters to implement the traits too.
+    if is_generic {
+        let fn_names: Vec<_> = key_variants.iter().map(|(fn_name, _)| fn_name).collect();
+        let key_types: Vec<_> = key_variants.iter().map(|(_, keys)| keys).collect();
+        let never = quote! {
+            #group_key::__Phantom(never, _) => match *never {},
+        };
+        debug_key_arms.extend(never.clone());
+        display_key_arms.extend(never.clone());
+        output.extend(quote! {
+            #[allow(non_camel_case_types)]
+            #trait_vis enum #group_key<#gen_params> {
+                #query_descriptor_variants
+                #[doc(hidden)]
+                __Phantom(std::convert::Infallible, #phantom),
+            }
+
+            impl<#gen_params> Clone for #group_key_ty
+            where
+                #(#key_types: Clone,)*
+                #gen_where
+            {
+                fn clone(&self) -> Self {
+                    match self {
+                        #(#group_key::#fn_names(key) => #group_key::#fn_names(key.clone()),)*
+                        #never
+                    }
+                }
+            }
+
+            impl<#gen_params> PartialEq for #group_key_ty
+            where
+                #(#key_types: Eq,)*
+                #gen_where
+            {
+                fn eq(&self, other: &Self) -> bool {
+                    match (self, other) {
+                        #((#group_key::#fn_names(key), #group_key::#fn_names(other)) => key == other,)*
+                        _ => false,
+                    }
+                }
+            }
+
+            impl<#gen_params> Eq for #group_key_ty
+            where
+                #(#key_types: Eq,)*
+                #gen_where
+            {
+            }
+
+            impl<#gen_params> std::hash::Hash for #group_key_ty
+            where
+                #(#key_types: std::hash::Hash,)*
+                #gen_where
+            {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                    std::mem::discriminant(self).hash(state);
+                    match self {
+                        #(#group_key::#fn_names(key) => key.hash(state),)*
+                        #never
+                    }
+                }
+            }
+        });
+    } else {
+        output.extend(quote! {
+            #[derive(Clone, PartialEq, Eq, Hash)]
+            #[allow(non_camel_case_types)]
+            #trait_vis enum #group_key {
+                #query_descriptor_variants
+            }
+        });
+    }
     output.extend(quote! {
-        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-        #[allow(non_camel_case_types)]
-        #trait_vis enum #group_key {
-            #query_descriptor_variants
+        impl<#gen_params> std::fmt::Debug for #group_key_ty
+        where
+            #gen_where
+        {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                use salsa::plumbing::{DebugViaDebug as _, DebugViaOpaque as _};
+                match self {
+                    #debug_key_arms
+                }
+            }
+        }
+
+        impl<#gen_params> std::fmt::Display for #group_key_ty
+        where
+            #gen_where
+        {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                use salsa::plumbing::{DebugViaDebug as _, DebugViaOpaque as _};
+                match self {
+                    #display_key_arms
+                }
+            }
         }
     });
 
@@ -439,23 +1332,102 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
         });
     }
 
+    let mut for_each_stats_ops = proc_macro2::TokenStream::new();
+    for Query { fn_name, .. } in queries.iter().filter(|q| {
+        q.storage.needs_query_function()
+            || q.storage == QueryStorage::Input
+            || q.storage == QueryStorage::Interned
+    }) {
+        let query_name = fn_name.to_string();
+        for_each_stats_ops.extend(quote! {
+            op(#query_name, &self.#fn_name);
+        });
+    }
+
+    let mut for_each_persistent_ops = proc_macro2::TokenStream::new();
+    for Query { fn_name, .. } in queries.iter().filter(|q| q.persist) {
+        for_each_persistent_ops.extend(quote! {
+            op(&self.#fn_name);
+        });
+    }
+
+    let mut fetch_by_key_arms = proc_macro2::TokenStream::new();
+    for query in queries
+        .iter()
+        .filter(|q| q.storage != QueryStorage::Transparent)
+    {
+        let fn_name = &query.fn_name;
+        let qt = &query.query_type;
+        let qt = quote! { #qt #ty_args };
+        // Reading inputs (and interned values) through the table records
+        // the read without panicking if there is no value.
+        let fetch = match query.storage {
+            QueryStorage::Input => quote! { get_maybe(key.clone()) },
+            QueryStorage::Interned => quote! { lookup_existing(key.clone()) },
+            // Tracked entities can only be created by their creator.
+            QueryStorage::Tracked => quote! { peek(key.clone()) },
+            _ => quote! { try_get(key.clone()) },
+        };
+        fetch_by_key_arms.extend(quote! {
+            #group_key::#fn_name(ref key) => {
+                let _ = <DB__ as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(db)
+                    .#fetch;
+            }
+        });
+    }
+    if is_generic {
+        fetch_by_key_arms.extend(quote! {
+            #group_key::__Phantom(ref never, _) => match *never {},
+        });
+    }
+
+    let mut invoke_by_name_arms = proc_macro2::TokenStream::new();
+    for query in queries.iter().filter(|q| q.dynamic) {
+        let qt = &query.query_type;
+        let qt = quote! { #qt #ty_args };
+        let keys = &query.keys;
+        let key_count = keys.len();
+        let key_names: Vec<_> = (0..keys.len())
+            .map(|i| Ident::new(&format!("key{}", i), Span::call_site()))
+            .collect();
+        let name = query.fn_name.to_string();
+        let check_args = if key_count == 0 {
+            quote! { salsa::plumbing::dynamic_args(#name, args, 0)?; }
+        } else {
+            quote! { let mut args = salsa::plumbing::dynamic_args(#name, args, #key_count)?; }
+        };
+        invoke_by_name_arms.extend(quote! {
+            #name => Some((|| {
+                #check_args
+                #(
+                    let #key_names: #keys = salsa::plumbing::dynamic_arg(#name, &mut args)?;
+                )*
+                let value = <DB__ as salsa::plumbing::GetQueryTable<#qt>>::get_query_table(db)
+                    .get((#(#key_names),*));
+                salsa::plumbing::dynamic_value(#name, &value)
+            })()),
+        });
+    }
+
     // Emit query group storage struct
     // It would derive Default, but then all database structs would have to implement Default
     // as the derived version includes an unused `+ Default` constraint.
     output.extend(quote! {
-        #trait_vis struct #group_storage<DB__>
+        #trait_vis struct #group_storage<DB__, #gen_params>
         where
-            DB__: #trait_name + #requires,
-            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            DB__: #trait_ref + #requires,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+            #gen_where
             DB__: salsa::Database,
         {
             #storage_fields
         }
 
-        impl<DB__> Default for #group_storage<DB__>
+        impl<DB__, #gen_params> Default for #group_storage<DB__, #(#gen_args),*>
         where
-            DB__: #trait_name + #requires,
-            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            DB__: #trait_ref + #requires,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+            #gen_where
             DB__: salsa::Database,
         {
             #[inline]
@@ -466,10 +1438,11 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             }
         }
 
-        impl<DB__> #group_storage<DB__>
+        impl<DB__, #gen_params> #group_storage<DB__, #(#gen_args),*>
         where
-            DB__: #trait_name + #requires,
-            DB__: salsa::plumbing::HasQueryGroup<#group_struct>,
+            DB__: #trait_ref + #requires,
+            DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+        #gen_where
         {
             #trait_vis fn for_each_query(
                 &self,
@@ -478,6 +1451,57 @@ pub(crate) fn query_group(args: TokenStream, input: TokenStream) -> TokenStream
             ) {
                 #for_each_ops
             }
+
+            #[allow(unused_variables)]
+            #trait_vis fn for_each_query_stats(
+                &self,
+                op: &mut dyn FnMut(&'static str, &dyn salsa::QueryStorageStats),
+            ) {
+                #for_each_stats_ops
+            }
+
+            #trait_vis fn fetch_by_key(&self, db: &DB__, key: &#group_key_ty) {
+                match *key {
+                    #fetch_by_key_arms
+                }
+            }
+        }
+
+        salsa::__if_persist! {
+            impl<DB__, #gen_params> #group_storage<DB__, #(#gen_args),*>
+            where
+                DB__: #trait_ref + #requires,
+                DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+            #gen_where
+            {
+                #trait_vis fn for_each_persistent_query(
+                    &self,
+                    op: &mut dyn FnMut(&dyn salsa::plumbing::PersistQueryStorageOps<DB__>),
+                ) {
+                    #for_each_persistent_ops
+                }
+            }
+        }
+
+        salsa::__if_dynamic! {
+            impl<DB__, #gen_params> #group_storage<DB__, #(#gen_args),*>
+            where
+                DB__: #trait_ref + #requires,
+                DB__: salsa::plumbing::HasQueryGroup<#group_struct_ty>,
+            #gen_where
+            {
+                #trait_vis fn invoke_by_name(
+                    &self,
+                    db: &DB__,
+                    name: &str,
+                    args: &salsa::plumbing::serde_json::Value,
+                ) -> Option<Result<salsa::plumbing::serde_json::Value, salsa::QueryByNameError>> {
+                    match name {
+                        #invoke_by_name_arms
+                        _ => None,
+                    }
+                }
+            }
         }
     });
 
@@ -540,7 +1564,122 @@ struct Query {
     storage: QueryStorage,
     keys: Vec<syn::Type>,
     value: syn::Type,
+    /// For `#[salsa::arc]` and `#[salsa::send_only]` queries, the value
+    /// type as written, which `value` wraps in an `Arc` or `SendOnly`.
+    unwrapped_value: Option<syn::Type>,
+    /// Whether `value` wraps `unwrapped_value` in a `SendOnly`, which
+    /// the accessors unwrap again.
+    send_only: bool,
+    /// For the queries generated by `#[salsa::fields]`, the input
+    /// query and the field of its value that the query returns.
+    field_of: Option<(Ident, Ident)>,
     invoke: Option<syn::Path>,
+    persist: bool,
+    max_age: Option<syn::Expr>,
+    shallow_revalidation: bool,
+    keep_previous: bool,
+    catch_panics: bool,
+    fallible: Option<Fallible>,
+    multi_version: bool,
+    specifiable: bool,
+    heap_size: Option<syn::Path>,
+    display_key: Option<syn::Path>,
+    value_eq: Option<ValueEq>,
+    key_hasher: Option<syn::Type>,
+    ordered: bool,
+    dynamic: bool,
+    lru_cost: Option<syn::Path>,
+    fingerprint: Option<syn::Path>,
+    value_store: Option<syn::Path>,
+}
+
+/// How the values of a memoized query are compared when backdating,
+/// if not with `Eq`.
+#[derive(Debug)]
+enum ValueEq {
+    /// `#[salsa::eq_with(path)]`
+    With(syn::Path),
+    /// `#[salsa::no_backdate]`
+    Never,
+}
+
+/// The options of `#[salsa::fallible(...)]`.
+#[derive(Debug)]
+struct Fallible {
+    no_backdate: bool,
+    no_memoize: bool,
+}
+
+/// The options listed in `#[salsa::fallible(option, ...)]`.
+struct FallibleOptions(Punctuated<Ident, Token![,]>);
+
+impl syn::parse::Parse for FallibleOptions {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        Punctuated::parse_terminated(input).map(FallibleOptions)
+    }
+}
+
+/// If `ty` is `Result<T, E>`, returns `T` and `E`.
+fn result_types(ty: &Type) -> Option<(Type, Type)> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let args = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => &args.args,
+        _ => return None,
+    };
+    let mut types = args.iter().map(|arg| match arg {
+        syn::GenericArgument::Type(ty) => Some(ty.clone()),
+        _ => None,
+    });
+    match (types.next(), types.next(), types.next()) {
+        (Some(Some(ok)), Some(Some(err)), None) => Some((ok, err)),
+        _ => None,
+    }
+}
+
+/// Statements that debug-format the keys bound to `key_names`,
+/// separated by commas, with `salsa::plumbing::DebugOrOpaque` (whose
+/// traits must be in scope).
+fn debug_keys(key_names: &[Ident]) -> proc_macro2::TokenStream {
+    let mut output = proc_macro2::TokenStream::new();
+    for (i, key_name) in key_names.iter().enumerate() {
+        if i > 0 {
+            output.extend(quote! { fmt.write_str(", ")?; });
+        }
+        output.extend(quote! {
+            (&&salsa::plumbing::DebugOrOpaque(#key_name)).fmt_debug(fmt)?;
+        });
+    }
+    output
+}
+
+/// The fields listed in `#[salsa::fields(name: Type, ...)]`.
+struct FieldList(Punctuated<FieldDecl, Token![,]>);
+
+struct FieldDecl {
+    name: Ident,
+    ty: Type,
+}
+
+impl syn::parse::Parse for FieldList {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        Punctuated::parse_terminated(input).map(FieldList)
+    }
+}
+
+impl syn::parse::Parse for FieldDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        Ok(FieldDecl { name, ty })
+    }
 }
 
 impl Query {
@@ -556,9 +1695,15 @@ impl Query {
 enum QueryStorage {
     Memoized,
     Dependencies,
+    Weak,
+    Firewall,
+    Dedup,
+    Fingerprint,
     Input,
     Interned,
     InternedLookup { intern_query_type: Ident },
+    Tracked,
+    TrackedLookup { tracked_query_type: Ident },
     Transparent,
 }
 
@@ -568,8 +1713,15 @@ impl QueryStorage {
             QueryStorage::Input
             | QueryStorage::Interned
             | QueryStorage::InternedLookup { .. }
+            | QueryStorage::Tracked
+            | QueryStorage::TrackedLookup { .. }
             | QueryStorage::Transparent => false,
-            QueryStorage::Memoized | QueryStorage::Dependencies => true,
+            QueryStorage::Memoized
+            | QueryStorage::Dependencies
+            | QueryStorage::Weak
+            | QueryStorage::Firewall
+            | QueryStorage::Dedup
+            | QueryStorage::Fingerprint => true,
         }
     }
 }
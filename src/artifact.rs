@@ -0,0 +1,314 @@
+//! Exporting the results of queries as standalone artifacts, which a
+//! later process can import for as long as the inputs they were
+//! computed from still have the same values (as a distributed build
+//! cache would). See `QueryTable::export_results`.
+//!
+//! An artifact records the serialized key and value along with a
+//! manifest of the inputs that the value transitively depends on:
+//! their query names, serialized keys and a hash of their serialized
+//! values. Only inputs marked `#[salsa::persist]` can be hashed, so a
+//! value that depends on anything else cannot be exported.
+
+use crate::dependency::Dependency;
+use crate::durability::Durability;
+use crate::persist::{self, PersistedSlotId};
+use crate::plumbing::{DerivedQueryStorageOps, GetQueryTable};
+use crate::revision::Revision;
+use crate::{Database, Query, QueryTable};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Bumped whenever the layout of a bundle changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Query results exported by `QueryTable::export_results`, to be
+/// imported by `QueryTable::import_results` in another process.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ArtifactBundle {
+    artifacts: Vec<Artifact>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Artifact {
+    query_name: String,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    /// Sorted by query name and key.
+    inputs: Vec<InputDigest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct InputDigest {
+    query_name: String,
+    key: Vec<u8>,
+    hash: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedBundle {
+    version: u32,
+    bundle: ArtifactBundle,
+}
+
+impl ArtifactBundle {
+    /// Returns the number of results in the bundle.
+    pub fn len(&self) -> usize {
+        self.artifacts.len()
+    }
+
+    /// Returns true if the bundle contains no results.
+    pub fn is_empty(&self) -> bool {
+        self.artifacts.is_empty()
+    }
+
+    /// Adds the results of `other` (typically exported from another
+    /// query) to this bundle.
+    pub fn extend(&mut self, other: ArtifactBundle) {
+        self.artifacts.extend(other.artifacts);
+    }
+
+    /// Writes the bundle to `writer`.
+    pub fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let persisted = PersistedBundle {
+            version: FORMAT_VERSION,
+            bundle: self.clone(),
+        };
+        writer.write_all(&persist::serialize(&persisted)?)
+    }
+
+    /// Reads a bundle written by `write`.
+    pub fn read(reader: &mut impl io::Read) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let persisted: PersistedBundle = persist::deserialize(&bytes)?;
+        if persisted.version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported bundle version {}", persisted.version),
+            ));
+        }
+        Ok(persisted.bundle)
+    }
+}
+
+/// The slot of an input along with a hash of its value; see
+/// `PersistQueryStorageOps::hash_input`.
+pub struct HashedInput<DB: Database> {
+    pub(crate) slot: Dependency<DB>,
+    pub(crate) hash: u128,
+    pub(crate) durability: Durability,
+    pub(crate) changed_at: Revision,
+}
+
+/// The slots that memoized values transitively depend on and that do
+/// not track any inputs of their own: inputs, mostly. See
+/// `DerivedQueryStorageOps::transitive_inputs`.
+pub struct TransitiveInputs<DB: Database> {
+    visited: FxHashSet<Dependency<DB>>,
+    leaves: Vec<Dependency<DB>>,
+}
+
+impl<DB: Database> Default for TransitiveInputs<DB> {
+    fn default() -> Self {
+        TransitiveInputs {
+            visited: Default::default(),
+            leaves: Default::default(),
+        }
+    }
+}
+
+impl<DB: Database> TransitiveInputs<DB> {
+    /// Adds the leaves that `root` depends on; returns false if the
+    /// inputs of `root` itself are not tracked.
+    pub(crate) fn collect(&mut self, root: Dependency<DB>) -> bool {
+        let mut stack = match root.tracked_inputs() {
+            Some(inputs) => vec![inputs],
+            None => return false,
+        };
+        while let Some(inputs) = stack.pop() {
+            for input in inputs.iter() {
+                if self.visited.insert(input.clone()) {
+                    match input.tracked_inputs() {
+                        Some(inputs) => stack.push(inputs),
+                        None => self.leaves.push(input.clone()),
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Hashes the serialized form of `value`.
+pub(crate) fn hash_value<T: Serialize>(value: &T) -> io::Result<u128> {
+    Ok(crate::plumbing::hash_fingerprint(&persist::serialize(
+        value,
+    )?))
+}
+
+pub(crate) fn export_results<DB, Q>(
+    table: &QueryTable<'_, DB, Q>,
+    keys: impl IntoIterator<Item = Q::Key>,
+) -> io::Result<ArtifactBundle>
+where
+    DB: GetQueryTable<Q>,
+    Q: Query<DB>,
+    Q::Key: Serialize,
+    Q::Value: Serialize,
+    Q::Storage: DerivedQueryStorageOps<DB, Q>,
+{
+    let db = table.db;
+    let mut results = Vec::new();
+    for key in keys {
+        let value = table.get(key.clone());
+        let mut inputs = TransitiveInputs::default();
+        if !table.storage.transitive_inputs(&key, &mut inputs) {
+            return Err(invalid_input(format!(
+                "the inputs of {:?} are not tracked",
+                table.database_key(key.clone())
+            )));
+        }
+        results.push((key, value, inputs.leaves));
+    }
+
+    let (query_names, slots) = persist::save_all_slots(db)?;
+    let mut wanted: FxHashMap<u32, FxHashSet<&[u8]>> = FxHashMap::default();
+    for (key, _, leaves) in &results {
+        for leaf in leaves {
+            match slots.get(leaf) {
+                Some(id) => {
+                    wanted.entry(id.table).or_default().insert(&id.key);
+                }
+                None => return Err(not_hashable(table, key, leaf)),
+            }
+        }
+    }
+
+    let mut hashes: FxHashMap<PersistedSlotId, u128> = FxHashMap::default();
+    let mut index = 0;
+    let mut result = Ok(());
+    db.for_each_persistent_query(|storage| {
+        for key in wanted.get(&index).into_iter().flatten() {
+            if result.is_ok() {
+                match storage.hash_input(key) {
+                    Ok(Some(input)) => {
+                        let id = PersistedSlotId {
+                            table: index,
+                            key: key.to_vec(),
+                        };
+                        hashes.insert(id, input.hash);
+                    }
+                    Ok(None) => {}
+                    Err(error) => result = Err(error),
+                }
+            }
+        }
+        index += 1;
+    });
+    result?;
+
+    let mut artifacts = Vec::new();
+    for (key, value, leaves) in results {
+        let mut inputs = Vec::new();
+        for leaf in &leaves {
+            let id = slots.get(leaf).unwrap();
+            match hashes.get(id) {
+                Some(&hash) => inputs.push(InputDigest {
+                    query_name: query_names[id.table as usize].to_string(),
+                    key: id.key.clone(),
+                    hash,
+                }),
+                None => return Err(not_hashable(table, &key, leaf)),
+            }
+        }
+        inputs.sort();
+        artifacts.push(Artifact {
+            query_name: Q::QUERY_NAME.to_string(),
+            key: persist::serialize(&key)?,
+            value: persist::serialize(&value)?,
+            inputs,
+        });
+    }
+    Ok(ArtifactBundle { artifacts })
+}
+
+pub(crate) fn import_results<DB, Q>(
+    table: &QueryTable<'_, DB, Q>,
+    bundle: &ArtifactBundle,
+) -> io::Result<usize>
+where
+    DB: GetQueryTable<Q>,
+    Q: Query<DB>,
+    Q::Key: DeserializeOwned,
+    Q::Value: DeserializeOwned,
+    Q::Storage: DerivedQueryStorageOps<DB, Q>,
+{
+    let artifacts: Vec<&Artifact> = bundle
+        .artifacts
+        .iter()
+        .filter(|artifact| artifact.query_name == Q::QUERY_NAME)
+        .collect();
+
+    // Hash the current values of all the inputs mentioned by the
+    // artifacts (that still exist).
+    let mut current: FxHashMap<(&str, &[u8]), HashedInput<DB>> = FxHashMap::default();
+    let mut result = Ok(());
+    table.db.for_each_persistent_query(|storage| {
+        let query_name = storage.query_name();
+        for artifact in &artifacts {
+            for input in &artifact.inputs {
+                let id = (&input.query_name[..], &input.key[..]);
+                if result.is_err() || input.query_name != query_name || current.contains_key(&id) {
+                    continue;
+                }
+                match storage.hash_input(&input.key) {
+                    Ok(Some(hashed)) => {
+                        current.insert(id, hashed);
+                    }
+                    Ok(None) => {}
+                    Err(error) => result = Err(error),
+                }
+            }
+        }
+    });
+    result?;
+
+    let mut imported = 0;
+    'artifacts: for artifact in artifacts {
+        let mut inputs = Vec::with_capacity(artifact.inputs.len());
+        for input in &artifact.inputs {
+            match current.get(&(&input.query_name[..], &input.key[..])) {
+                Some(hashed) if hashed.hash == input.hash => inputs.push(hashed),
+                _ => continue 'artifacts,
+            }
+        }
+        let key: Q::Key = persist::deserialize(&artifact.key)?;
+        let value: Q::Value = persist::deserialize(&artifact.value)?;
+        if table.storage.import_result(table.db, &key, value, &inputs) {
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+fn not_hashable<DB, Q>(
+    table: &QueryTable<'_, DB, Q>,
+    key: &Q::Key,
+    leaf: &Dependency<DB>,
+) -> io::Error
+where
+    DB: GetQueryTable<Q>,
+    Q: Query<DB>,
+{
+    invalid_input(format!(
+        "{:?} depends on {:?}, which is not a persisted input",
+        table.database_key(key.clone()),
+        leaf
+    ))
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
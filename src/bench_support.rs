@@ -0,0 +1,142 @@
+//! Synthetic query graphs, to benchmark salsa itself. Requires the
+//! `bench-support` feature of salsa; the benchmarks in `benches/` are
+//! built on it.
+//!
+//! A graph is made of nodes, numbered from 0, each of which reads some
+//! others (its edges). The derived query `GraphDatabase::node_value`
+//! of a node adds its weight (an input) to the values of the nodes it
+//! reads, so that the shape of the graph is the shape of the
+//! dependencies between queries. Build a `Graph` (say, a long chain or
+//! a wide fan-out), load it into a `GraphDatabaseImpl`, read the value
+//! of its root, then change the weight of some node and read it again
+//! to measure how long revalidation (or re-execution) takes.
+
+use crate::Database;
+use std::sync::Arc;
+
+/// The inputs and the derived query of a synthetic graph.
+#[salsa::query_group(GraphStorage)]
+pub trait GraphDatabase: Database {
+    /// The nodes that `node` reads.
+    #[salsa::input]
+    fn edges(&self, node: u32) -> Arc<Vec<u32>>;
+
+    /// The weight of `node`, which its value includes.
+    #[salsa::input]
+    fn weight(&self, node: u32) -> u64;
+
+    /// The weight of `node` plus the values of the nodes it reads
+    /// (wrapping around on overflow).
+    fn node_value(&self, node: u32) -> u64;
+}
+
+fn node_value(db: &impl GraphDatabase, node: u32) -> u64 {
+    db.edges(node).iter().fold(db.weight(node), |value, &edge| {
+        value.wrapping_add(db.node_value(edge))
+    })
+}
+
+/// A database with just the graph, which supports snapshots (unless
+/// salsa is built with the `single-threaded` feature).
+#[salsa::database(GraphStorage)]
+#[derive(Default)]
+pub struct GraphDatabaseImpl {
+    runtime: crate::Runtime<GraphDatabaseImpl>,
+}
+
+impl Database for GraphDatabaseImpl {
+    fn salsa_runtime(&self) -> &crate::Runtime<GraphDatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[cfg(not(feature = "single-threaded"))]
+impl crate::ParallelDatabase for GraphDatabaseImpl {
+    fn snapshot(&self) -> crate::Snapshot<Self> {
+        crate::Snapshot::new(GraphDatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+/// The shape of a synthetic graph: the edges of each node. Node 0 is
+/// the root, and nodes without edges are leaves.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    edges: Vec<Vec<u32>>,
+}
+
+impl Graph {
+    /// A chain of `len` nodes, each of which reads the next one: the
+    /// deepest dependencies there are.
+    pub fn chain(len: u32) -> Self {
+        Graph {
+            edges: (0..len)
+                .map(|node| {
+                    if node + 1 < len {
+                        vec![node + 1]
+                    } else {
+                        vec![]
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// A root that reads `width` leaves: the widest dependencies there
+    /// are.
+    pub fn fan_out(width: u32) -> Self {
+        let mut edges = vec![(1..=width).collect()];
+        edges.extend((0..width).map(|_| vec![]));
+        Graph { edges }
+    }
+
+    /// A root that reads the `width` nodes of the first of `layers`
+    /// layers, each node of which reads all the nodes of the next
+    /// layer; the nodes of the last layer are leaves. Every node is
+    /// read by several others, which shows how well values that were
+    /// already validated are reused.
+    pub fn layered(layers: u32, width: u32) -> Self {
+        let layer = |index: u32| (1 + index * width..1 + (index + 1) * width).collect::<Vec<_>>();
+        let mut edges = vec![layer(0)];
+        for index in 0..layers {
+            let next = if index + 1 < layers {
+                layer(index + 1)
+            } else {
+                vec![]
+            };
+            edges.extend((0..width).map(|_| next.clone()));
+        }
+        Graph { edges }
+    }
+
+    /// The number of nodes, which are numbered from 0.
+    pub fn node_count(&self) -> u32 {
+        self.edges.len() as u32
+    }
+
+    /// The nodes that `node` reads.
+    pub fn edges(&self, node: u32) -> &[u32] {
+        &self.edges[node as usize]
+    }
+
+    /// The nodes that read no other nodes.
+    pub fn leaves(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.node_count()).filter(move |&node| self.edges(node).is_empty())
+    }
+
+    /// Sets the edges of all the nodes in `db`, and their weights to 1.
+    pub fn load(&self, db: &mut impl GraphDatabase) {
+        for (node, edges) in self.edges.iter().enumerate() {
+            db.set_edges(node as u32, Arc::new(edges.clone()));
+            db.set_weight(node as u32, 1);
+        }
+    }
+
+    /// A new database with this graph loaded.
+    pub fn database(&self) -> GraphDatabaseImpl {
+        let mut db = GraphDatabaseImpl::default();
+        self.load(&mut db);
+        db
+    }
+}
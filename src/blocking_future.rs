@@ -0,0 +1,135 @@
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use smallvec::SmallVec;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// The receiving half of a one-shot channel, used to wait for a value
+/// that another thread is computing. The value can either be waited
+/// for by blocking the current thread (`wait`) or asynchronously (by
+/// awaiting the `BlockingFuture` itself). Yields `None` if the
+/// `Promise` was dropped without being fulfilled (i.e., the other
+/// thread panicked).
+///
+/// A `BlockingFuture` can be cloned, so that any number of threads
+/// wait on the same channel, and each of them receives a clone of the
+/// value.
+pub(crate) struct BlockingFuture<T> {
+    slot: Arc<Slot<T>>,
+}
+
+/// The sending half of a one-shot channel; see `BlockingFuture`.
+pub(crate) struct Promise<T> {
+    fulfilled: bool,
+    slot: Arc<Slot<T>>,
+}
+
+struct Slot<T> {
+    state: Mutex<State<T>>,
+    cvar: Condvar,
+}
+
+enum State<T> {
+    /// Not fulfilled yet; holds the wakers of the futures that are
+    /// being awaited.
+    Empty(SmallVec<[Waker; 1]>),
+    Full(T),
+    Dead,
+}
+
+impl<T> BlockingFuture<T> {
+    pub(crate) fn new() -> (BlockingFuture<T>, Promise<T>) {
+        let slot = Arc::new(Slot {
+            state: Mutex::new(State::Empty(SmallVec::new())),
+            cvar: Condvar::new(),
+        });
+        let future = BlockingFuture { slot: slot.clone() };
+        let promise = Promise {
+            fulfilled: false,
+            slot,
+        };
+        (future, promise)
+    }
+}
+
+impl<T: Clone> BlockingFuture<T> {
+    /// Blocks the current thread until the value is available. While
+    /// waiting, invokes `check` every `interval` (if any), without
+    /// holding any lock; it can unwind to give up waiting.
+    pub(crate) fn wait(self, interval: Option<Duration>, mut check: impl FnMut()) -> Option<T> {
+        let mut state = self.slot.state.lock();
+        while let State::Empty(_) = &*state {
+            match interval {
+                Some(interval) => {
+                    if self.slot.cvar.wait_for(&mut state, interval).timed_out() {
+                        MutexGuard::unlocked(&mut state, &mut check);
+                    }
+                }
+                None => self.slot.cvar.wait(&mut state),
+            }
+        }
+        state.get()
+    }
+}
+
+impl<T> Clone for BlockingFuture<T> {
+    fn clone(&self) -> Self {
+        BlockingFuture {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Future for BlockingFuture<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.slot.state.lock();
+        match &mut *state {
+            State::Empty(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            _ => Poll::Ready(state.get()),
+        }
+    }
+}
+
+impl<T> Promise<T> {
+    pub(crate) fn fulfil(mut self, value: T) {
+        self.fulfilled = true;
+        self.transition(State::Full(value));
+    }
+
+    fn transition(&mut self, new_state: State<T>) {
+        let old_state = std::mem::replace(&mut *self.slot.state.lock(), new_state);
+        self.slot.cvar.notify_all();
+        if let State::Empty(wakers) = old_state {
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        if !self.fulfilled {
+            self.transition(State::Dead);
+        }
+    }
+}
+
+impl<T: Clone> State<T> {
+    fn get(&self) -> Option<T> {
+        match self {
+            State::Full(value) => Some(value.clone()),
+            State::Dead => None,
+            State::Empty(_) => panic!("value taken too early"),
+        }
+    }
+}
@@ -3,9 +3,12 @@
 
 use crate::durability::Durability;
 use crate::plumbing;
+use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
+use crate::revision::Revision;
 use crate::Query;
 use crate::QueryTable;
+use std::io;
 use std::iter::FromIterator;
 
 /// Additional methods on queries that can be used to "peek into"
@@ -28,6 +31,51 @@ pub trait DebugQueryTable {
     fn entries<C>(&self) -> C
     where
         C: FromIterator<TableEntry<Self::Key, Self::Value>>;
+
+    /// Writes the state of every slot in the query table to `out`, as
+    /// a JSON object of the form:
+    ///
+    /// ```text
+    /// {"query":"length","slots":[{"key":"\"a\"","state":"Memoized",
+    ///   "changed_at":1,"verified_at":2,"durability":0,
+    ///   "untracked":false,"inputs":["..."]}]}
+    /// ```
+    ///
+    /// Keys (and the keys of the inputs) are written with their
+    /// `Debug` representation, and the slots are sorted by key, so that
+    /// the output does not depend on the order in which the table was
+    /// filled. The `state` is one of `NotComputed`, `InProgress` and
+    /// `Memoized`; the stamps (revisions and durability level) are
+    /// `null` unless a value is memoized.
+    /// `inputs` lists the queries that the value was computed from
+    /// (for derived queries only), and `untracked` is true if the
+    /// value also read untracked inputs.
+    fn debug_dump(&self, out: &mut impl io::Write) -> io::Result<()>;
+}
+
+/// Writes the state of every query in the group `G` to `out`, as a
+/// JSON array with one object per query in the format of
+/// [`DebugQueryTable::debug_dump`].
+///
+/// [`DebugQueryTable::debug_dump`]: trait.DebugQueryTable.html#tymethod.debug_dump
+pub fn dump_group<G, DB>(db: &DB, out: &mut impl io::Write) -> io::Result<()>
+where
+    G: plumbing::QueryGroup<DB>,
+    DB: plumbing::HasQueryGroup<G>,
+{
+    let mut result = write!(out, "[");
+    let mut first = true;
+    <DB as plumbing::HasQueryGroup<G>>::for_each_query(db, &mut |query| {
+        if result.is_ok() && !first {
+            result = write!(out, ",");
+        }
+        if result.is_ok() {
+            result = query.debug_dump(db, &mut *out);
+        }
+        first = false;
+    });
+    result?;
+    write!(out, "]")
 }
 
 /// An entry from a query table, for debugging and inspecting the table state.
@@ -37,6 +85,15 @@ pub struct TableEntry<K, V> {
     pub key: K,
     /// value of the query, if it is stored
     pub value: Option<V>,
+    /// revision in which the value last changed, if it is stored
+    pub changed_at: Option<Revision>,
+    /// revision in which the value was last verified to be up to date;
+    /// only tracked for derived queries
+    pub verified_at: Option<Revision>,
+    /// durability of the value, if it is stored
+    pub durability: Option<Durability>,
+    /// true if the value was computed by reading untracked inputs
+    pub has_untracked_input: bool,
     _for_future_use: (),
 }
 
@@ -45,9 +102,111 @@ impl<K, V> TableEntry<K, V> {
         TableEntry {
             key,
             value,
+            changed_at: None,
+            verified_at: None,
+            durability: None,
+            has_untracked_input: false,
             _for_future_use: (),
         }
     }
+
+    pub(crate) fn with_stamp(mut self, changed_at: Revision, durability: Durability) -> Self {
+        self.changed_at = Some(changed_at);
+        self.durability = Some(durability);
+        self
+    }
+}
+
+/// The state of a slot, as written by `DebugQueryTable::debug_dump`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SlotState {
+    NotComputed,
+    InProgress,
+    Memoized,
+}
+
+/// A slot of a query table, as written by `DebugQueryTable::debug_dump`.
+pub(crate) struct SlotDump {
+    pub(crate) key: String,
+    pub(crate) state: SlotState,
+    pub(crate) changed_at: Option<Revision>,
+    pub(crate) verified_at: Option<Revision>,
+    pub(crate) durability: Option<Durability>,
+    pub(crate) untracked: bool,
+    pub(crate) inputs: Vec<String>,
+}
+
+impl SlotDump {
+    pub(crate) fn new(key: &impl std::fmt::Debug, state: SlotState) -> Self {
+        SlotDump {
+            key: format!("{:?}", key),
+            state,
+            changed_at: None,
+            verified_at: None,
+            durability: None,
+            untracked: false,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_stamp(mut self, changed_at: Revision, durability: Durability) -> Self {
+        self.changed_at = Some(changed_at);
+        self.durability = Some(durability);
+        self
+    }
+}
+
+/// Writes the slots of the query `query_name` as a JSON object; see
+/// `DebugQueryTable::debug_dump`.
+pub(crate) fn write_query_dump(
+    out: &mut dyn io::Write,
+    query_name: &str,
+    mut slots: Vec<SlotDump>,
+) -> io::Result<()> {
+    fn revision(revision: Option<Revision>) -> String {
+        revision.map_or("null".to_string(), |r| r.as_u64().to_string())
+    }
+
+    slots.sort_by(|a, b| a.key.cmp(&b.key));
+    write!(out, "{{\"query\":{},\"slots\":[", json_string(query_name))?;
+    for (i, slot) in slots.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        let inputs: Vec<String> = slot.inputs.iter().map(|k| json_string(k)).collect();
+        write!(
+            out,
+            "{{\"key\":{},\"state\":\"{:?}\",\"changed_at\":{},\"verified_at\":{},\
+             \"durability\":{},\"untracked\":{},\"inputs\":[{}]}}",
+            json_string(&slot.key),
+            slot.state,
+            revision(slot.changed_at),
+            revision(slot.verified_at),
+            slot.durability
+                .map_or("null".to_string(), |d| d.level().to_string()),
+            slot.untracked,
+            inputs.join(","),
+        )?;
+    }
+    write!(out, "]}}")
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
 }
 
 impl<DB, Q> DebugQueryTable for QueryTable<'_, DB, Q>
@@ -68,4 +227,8 @@ where
     {
         self.storage.entries(self.db)
     }
+
+    fn debug_dump(&self, out: &mut impl io::Write) -> io::Result<()> {
+        self.storage.debug_dump(self.db, out)
+    }
 }
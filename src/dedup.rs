@@ -0,0 +1,58 @@
+use crate::runtime::AnyValue;
+use crate::{MaybeSend, MaybeSync};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::any::TypeId;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Tables that are not pruned before they hold this many values.
+const MIN_PRUNE_LEN: usize = 64;
+
+/// The values of the `DedupStorage` queries of a database, with one
+/// table per value type, so that equal values share a single `Arc`
+/// even if they were produced by different keys or queries.
+#[derive(Default)]
+pub(crate) struct ValueTables {
+    tables: FxHashMap<TypeId, Box<AnyValue>>,
+}
+
+struct ValueTable<T> {
+    values: FxHashSet<Arc<T>>,
+
+    /// Once the table holds this many values, the values that are no
+    /// longer used outside of the table are removed.
+    prune_at: usize,
+}
+
+impl ValueTables {
+    /// Replaces `value` by the equal value in the table, if there is
+    /// one, and returns true. Otherwise adds `value` to the table.
+    pub(crate) fn dedup<T>(&mut self, value: &mut Arc<T>) -> bool
+    where
+        T: Eq + Hash + MaybeSend + MaybeSync + 'static,
+    {
+        let table = self
+            .tables
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| {
+                Box::new(ValueTable::<T> {
+                    values: FxHashSet::default(),
+                    prune_at: MIN_PRUNE_LEN,
+                })
+            })
+            .downcast_mut::<ValueTable<T>>()
+            .unwrap();
+
+        if let Some(shared) = table.values.get(&**value) {
+            *value = shared.clone();
+            return true;
+        }
+
+        if table.values.len() >= table.prune_at {
+            table.values.retain(|value| Arc::strong_count(value) > 1);
+            table.prune_at = MIN_PRUNE_LEN.max(2 * table.values.len());
+        }
+        table.values.insert(value.clone());
+        false
+    }
+}
@@ -1,5 +1,6 @@
 use crate::revision::Revision;
-use crate::Database;
+use crate::runtime::FxIndexSet;
+use crate::{Database, InvalidationReason};
 use std::fmt::Debug;
 use std::hash::Hasher;
 use std::ptr;
@@ -13,6 +14,44 @@ pub(crate) unsafe trait DatabaseSlot<DB: Database>: Debug {
     /// Returns true if the value of this query may have changed since
     /// the given revision.
     fn maybe_changed_since(&self, db: &DB, revision: Revision) -> bool;
+
+    /// Like `maybe_changed_since`, but returns `None` rather than
+    /// validating (or re-executing) the value, if that would be
+    /// needed to tell.
+    fn maybe_changed_since_shallow(&self, db: &DB, revision: Revision) -> Option<bool> {
+        Some(self.maybe_changed_since(db, revision))
+    }
+
+    /// Returns the database key that identifies this slot, or `None`
+    /// if it does not belong to a query (as for the slots of
+    /// `InvalidationToken`s).
+    fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey>;
+
+    /// Returns why a query that depends on this slot has to be
+    /// re-executed, given that this slot may have changed.
+    fn changed_reason(&self, db: &DB) -> InvalidationReason<DB::DatabaseKey> {
+        InvalidationReason::InputChanged(
+            self.database_key(db)
+                .expect("slot without a database key must override `changed_reason`"),
+        )
+    }
+
+    /// Returns the inputs of the memo in this slot, provided that the
+    /// value is reported as changed whenever one of them changes (that
+    /// is, the value is never backdated). A query that depends on this
+    /// slot need not depend on these inputs as well; see
+    /// `Runtime::set_dependency_pruning`.
+    fn dominated_inputs(&self) -> Option<Arc<FxIndexSet<Dependency<DB>>>> {
+        None
+    }
+
+    /// Returns the inputs of the memo in this slot, or `None` if there
+    /// is no memo or its inputs are not tracked (as for the slots of
+    /// inputs, which have no inputs of their own).
+    #[cfg(feature = "persist")]
+    fn tracked_inputs(&self) -> Option<Arc<FxIndexSet<Dependency<DB>>>> {
+        None
+    }
 }
 
 pub(crate) struct Dependency<DB: Database> {
@@ -24,7 +63,9 @@ impl<DB: Database> Dependency<DB> {
     pub(crate) fn new(slot: Arc<dyn DatabaseSlot<DB> + '_>) -> Self {
         // Unsafety note: It is safe to 'pretend' the trait object is
         // Send+Sync+'static because the phantom-data will reflect the
-        // reality.
+        // reality. In particular, a database that borrows from its
+        // environment is not `'static`, but then neither is
+        // `Dependency<DB>`, which names it.
         let slot: Arc<dyn DatabaseSlot<DB> + Send + Sync> = unsafe { std::mem::transmute(slot) };
         Self {
             slot,
@@ -35,6 +76,50 @@ impl<DB: Database> Dependency<DB> {
     pub(crate) fn maybe_changed_since(&self, db: &DB, revision: Revision) -> bool {
         self.slot.maybe_changed_since(db, revision)
     }
+
+    pub(crate) fn maybe_changed_since_shallow(&self, db: &DB, revision: Revision) -> Option<bool> {
+        self.slot.maybe_changed_since_shallow(db, revision)
+    }
+
+    pub(crate) fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey> {
+        self.slot.database_key(db)
+    }
+
+    pub(crate) fn changed_reason(&self, db: &DB) -> InvalidationReason<DB::DatabaseKey> {
+        self.slot.changed_reason(db)
+    }
+
+    #[cfg(feature = "persist")]
+    pub(crate) fn tracked_inputs(&self) -> Option<Arc<FxIndexSet<Dependency<DB>>>> {
+        self.slot.tracked_inputs()
+    }
+}
+
+/// Removes the dependencies that are dominated by another one (see
+/// `DatabaseSlot::dominated_inputs`); returns how many were removed.
+pub(crate) fn prune_dominated<DB: Database>(
+    dependencies: &mut FxIndexSet<Dependency<DB>>,
+) -> usize {
+    let dominated: Vec<_> = dependencies
+        .iter()
+        .filter_map(|dependency| dependency.slot.dominated_inputs())
+        .collect();
+    if dominated.is_empty() {
+        return 0;
+    }
+
+    let len = dependencies.len();
+    dependencies.retain(|dependency| !dominated.iter().any(|inputs| inputs.contains(dependency)));
+    len - dependencies.len()
+}
+
+impl<DB: Database> Clone for Dependency<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+            phantom: std::marker::PhantomData,
+        }
+    }
 }
 
 impl<DB: Database> std::hash::Hash for Dependency<DB> {
@@ -1,32 +1,87 @@
-use crate::debug::TableEntry;
+#[cfg(feature = "persist")]
+use crate::artifact::{HashedInput, TransitiveInputs};
+use crate::debug::{self, TableEntry};
+use crate::dependency::DatabaseSlot;
+#[cfg(feature = "persist")]
+use crate::dependency::Dependency;
 use crate::durability::Durability;
+use crate::key_map::KeyHasher;
+use crate::logging::debug;
 use crate::lru::Lru;
-use crate::plumbing::CycleDetected;
+use crate::opaque::{debug_key, debug_value};
+use crate::peek_future::PeekFuture;
+#[cfg(feature = "persist")]
+use crate::persist::{
+    self, LoadedSlots, PersistQueryStorageOps, RevisionMap, RevisionSet, SavedSlots,
+};
+use crate::plumbing;
+use crate::plumbing::DerivedQueryStorageOps;
 use crate::plumbing::HasQueryGroup;
 use crate::plumbing::LruQueryStorageOps;
+use crate::plumbing::PinQueryStorageOps;
 use crate::plumbing::QueryFunction;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
+use crate::revision::Revision;
+use crate::revision_log::InputChange;
 use crate::runtime::StampedValue;
-use crate::{Database, SweepStrategy};
-use parking_lot::RwLock;
-use rustc_hash::FxHashMap;
+use crate::{
+    ChangedEntry, CycleError, Database, MaybeSend, MaybeSync, MemoryReport, QueryStorageStats,
+    RuntimeId, SweepPolicy, SweepStrategy, ValueGuard,
+};
+#[cfg(feature = "persist")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 mod slot;
+pub(crate) mod slot_core;
+mod slot_map;
 use slot::Slot;
+use slot_map::SlotMap;
 
 /// Memoized queries store the result plus a list of the other queries
 /// that they invoked. This means we can avoid recomputing them when
 /// none of those inputs have changed.
 pub type MemoizedStorage<DB, Q> = DerivedStorage<DB, Q, AlwaysMemoizeValue>;
 
+/// Like `MemoizedStorage`, but for values that do not implement `Eq`:
+/// they are compared with `QueryFunction::VALUE_EQ` when backdating,
+/// or never considered equal if it is not set.
+pub type EqWithStorage<DB, Q> = DerivedStorage<DB, Q, EqWithMemoizeValue>;
+
+/// "Dedup" queries are memoized like regular ones, but return an
+/// `Arc` and hash-cons their values: a newly computed value that is
+/// equal to a value already held by another key (of any dedup query
+/// with the same value type) is replaced by that value, so that equal
+/// values share their memory.
+pub type DedupStorage<DB, Q> = DerivedStorage<DB, Q, DedupMemoizeValue>;
+
 /// "Dependency" queries just track their dependencies and not the
 /// actual value (which they produce on demand). This lessens the
 /// storage requirements.
 pub type DependencyStorage<DB, Q> = DerivedStorage<DB, Q, NeverMemoizeValue>;
 
+/// "Weak" queries return an `Arc` and only keep a weak reference to it
+/// in their memo, so that the value is freed once nothing else holds
+/// it (and recomputed if it is needed again).
+pub type WeakStorage<DB, Q> = DerivedStorage<DB, Q, WeakMemoizeValue>;
+
+/// "Fingerprint" queries are memoized like regular ones, but they
+/// keep a fingerprint of their value (see `QueryFunction::FINGERPRINT`)
+/// and compare fingerprints rather than values when backdating.
+pub type FingerprintStorage<DB, Q> = DerivedStorage<DB, Q, FingerprintMemoizeValue>;
+
+/// "Firewall" queries are memoized like regular ones, but they keep a
+/// 128-bit hash of their value (see `hash_fingerprint`) and compare
+/// hashes rather than values when backdating. When their inputs
+/// change, they are re-executed to find out if the value actually
+/// changed, so that the change only propagates to the queries that
+/// depend on them if it did.
+pub type FirewallStorage<DB, Q> = DerivedStorage<DB, Q, FirewallMemoizeValue>;
+
 /// Handles storage where the value is 'derived' by executing a
 /// function (in contrast to "inputs").
 pub struct DerivedStorage<DB, Q, MP>
@@ -36,7 +91,7 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     lru_list: Lru<Slot<DB, Q, MP>>,
-    slot_map: RwLock<FxHashMap<Q::Key, Arc<Slot<DB, Q, MP>>>>,
+    slot_map: SlotMap<Q::Key, Arc<Slot<DB, Q, MP>>>,
     policy: PhantomData<MP>,
 }
 
@@ -55,9 +110,41 @@ where
     Q: QueryFunction<DB>,
     DB: Database,
 {
+    /// The form in which values are kept in the memo. This must be
+    /// `Send`, `Sync` and `'static` whenever `Q::Value` is.
+    type Memoized;
+
     fn should_memoize_value(key: &Q::Key) -> bool;
 
-    fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool;
+    /// True if `old_value` (in its memoized form) is known to be equal
+    /// to `new_value`, so that the memo can be backdated.
+    fn memoized_value_eq(old_value: &Self::Memoized, new_value: &Q::Value) -> bool;
+
+    /// False if `memoized_value_eq` never considers values equal, in
+    /// which case they are not compared when checking determinism.
+    fn compares_values() -> bool {
+        true
+    }
+
+    /// Converts a value into the form in which it is memoized.
+    fn memoize(value: &Q::Value) -> Self::Memoized;
+
+    /// Recovers the value from its memoized form, if it is still
+    /// available.
+    fn recall(memoized: &Self::Memoized) -> Option<Q::Value>;
+
+    /// Borrows the value from its memoized form, if it is kept there
+    /// as is; used by `QueryTable::get_ref`.
+    fn recall_ref(_memoized: &Self::Memoized) -> Option<&Q::Value> {
+        None
+    }
+
+    /// Replaces a newly computed value by an equal value that is
+    /// already shared, if the policy hash-conses values; returns true
+    /// if it did.
+    fn dedup(_db: &DB, _value: &mut Q::Value) -> bool {
+        false
+    }
 }
 
 pub enum AlwaysMemoizeValue {}
@@ -67,6 +154,8 @@ where
     Q::Value: Eq,
     DB: Database,
 {
+    type Memoized = Q::Value;
+
     fn should_memoize_value(_key: &Q::Key) -> bool {
         true
     }
@@ -74,6 +163,102 @@ where
     fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool {
         old_value == new_value
     }
+
+    fn memoize(value: &Q::Value) -> Q::Value {
+        value.clone()
+    }
+
+    fn recall(memoized: &Q::Value) -> Option<Q::Value> {
+        Some(memoized.clone())
+    }
+
+    fn recall_ref(memoized: &Q::Value) -> Option<&Q::Value> {
+        Some(memoized)
+    }
+}
+
+pub enum EqWithMemoizeValue {}
+impl<DB, Q> MemoizationPolicy<DB, Q> for EqWithMemoizeValue
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+{
+    type Memoized = Q::Value;
+
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn memoized_value_eq(old_value: &Q::Value, new_value: &Q::Value) -> bool {
+        Q::VALUE_EQ.is_some_and(|eq| eq(old_value, new_value))
+    }
+
+    fn compares_values() -> bool {
+        Q::VALUE_EQ.is_some()
+    }
+
+    fn memoize(value: &Q::Value) -> Q::Value {
+        value.clone()
+    }
+
+    fn recall(memoized: &Q::Value) -> Option<Q::Value> {
+        Some(memoized.clone())
+    }
+
+    fn recall_ref(memoized: &Q::Value) -> Option<&Q::Value> {
+        Some(memoized)
+    }
+}
+
+pub enum FingerprintMemoizeValue {}
+impl<DB, Q> MemoizationPolicy<DB, Q> for FingerprintMemoizeValue
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+{
+    type Memoized = (Q::Value, u128);
+
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn memoized_value_eq(old_value: &(Q::Value, u128), new_value: &Q::Value) -> bool {
+        old_value.1 == fingerprint::<DB, Q>(new_value)
+    }
+
+    fn memoize(value: &Q::Value) -> (Q::Value, u128) {
+        (value.clone(), fingerprint::<DB, Q>(value))
+    }
+
+    fn recall(memoized: &(Q::Value, u128)) -> Option<Q::Value> {
+        Some(memoized.0.clone())
+    }
+
+    fn recall_ref(memoized: &(Q::Value, u128)) -> Option<&Q::Value> {
+        Some(&memoized.0)
+    }
+}
+
+fn fingerprint<DB, Q>(value: &Q::Value) -> u128
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+{
+    match Q::FINGERPRINT {
+        Some(fingerprint) => fingerprint(value),
+        None => panic!("{:?} has no fingerprint function", Q::default()),
+    }
+}
+
+/// The default fingerprint function of `#[salsa::fingerprint]`
+/// queries: a 128-bit hash of the value.
+pub fn hash_fingerprint<T: Hash>(value: &T) -> u128 {
+    let mut low = DefaultHasher::new();
+    value.hash(&mut low);
+    let mut high = DefaultHasher::new();
+    u64::MAX.hash(&mut high);
+    value.hash(&mut high);
+    (u128::from(high.finish()) << 64) | u128::from(low.finish())
 }
 
 pub enum NeverMemoizeValue {}
@@ -82,6 +267,8 @@ where
     Q: QueryFunction<DB>,
     DB: Database,
 {
+    type Memoized = Q::Value;
+
     fn should_memoize_value(_key: &Q::Key) -> bool {
         false
     }
@@ -89,6 +276,121 @@ where
     fn memoized_value_eq(_old_value: &Q::Value, _new_value: &Q::Value) -> bool {
         panic!("cannot reach since we never memoize")
     }
+
+    fn memoize(_value: &Q::Value) -> Q::Value {
+        panic!("cannot reach since we never memoize")
+    }
+
+    fn recall(memoized: &Q::Value) -> Option<Q::Value> {
+        Some(memoized.clone())
+    }
+}
+
+pub enum WeakMemoizeValue {}
+impl<DB, Q, T> MemoizationPolicy<DB, Q> for WeakMemoizeValue
+where
+    Q: QueryFunction<DB, Value = Arc<T>>,
+    T: Eq,
+    DB: Database,
+{
+    type Memoized = Weak<T>;
+
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn memoized_value_eq(old_value: &Weak<T>, new_value: &Arc<T>) -> bool {
+        match old_value.upgrade() {
+            Some(old_value) => old_value == *new_value,
+            None => false,
+        }
+    }
+
+    fn memoize(value: &Arc<T>) -> Weak<T> {
+        Arc::downgrade(value)
+    }
+
+    fn recall(memoized: &Weak<T>) -> Option<Arc<T>> {
+        memoized.upgrade()
+    }
+}
+
+pub enum DedupMemoizeValue {}
+impl<DB, Q, T> MemoizationPolicy<DB, Q> for DedupMemoizeValue
+where
+    Q: QueryFunction<DB, Value = Arc<T>>,
+    T: Eq + Hash + MaybeSend + MaybeSync + 'static,
+    DB: Database,
+{
+    type Memoized = Arc<T>;
+
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn memoized_value_eq(old_value: &Arc<T>, new_value: &Arc<T>) -> bool {
+        Arc::ptr_eq(old_value, new_value) || old_value == new_value
+    }
+
+    fn memoize(value: &Arc<T>) -> Arc<T> {
+        value.clone()
+    }
+
+    fn recall(memoized: &Arc<T>) -> Option<Arc<T>> {
+        Some(memoized.clone())
+    }
+
+    fn recall_ref(memoized: &Arc<T>) -> Option<&Arc<T>> {
+        Some(memoized)
+    }
+
+    fn dedup(db: &DB, value: &mut Arc<T>) -> bool {
+        db.salsa_runtime().dedup_value(value)
+    }
+}
+
+pub enum FirewallMemoizeValue {}
+impl<DB, Q> MemoizationPolicy<DB, Q> for FirewallMemoizeValue
+where
+    Q: QueryFunction<DB>,
+    Q::Value: Hash,
+    DB: Database,
+{
+    type Memoized = (Q::Value, u128);
+
+    fn should_memoize_value(_key: &Q::Key) -> bool {
+        true
+    }
+
+    fn memoized_value_eq(old_value: &(Q::Value, u128), new_value: &Q::Value) -> bool {
+        old_value.1 == hash_fingerprint(new_value)
+    }
+
+    fn memoize(value: &Q::Value) -> (Q::Value, u128) {
+        (value.clone(), hash_fingerprint(value))
+    }
+
+    fn recall(memoized: &(Q::Value, u128)) -> Option<Q::Value> {
+        Some(memoized.0.clone())
+    }
+
+    fn recall_ref(memoized: &(Q::Value, u128)) -> Option<&Q::Value> {
+        Some(&memoized.0)
+    }
+}
+
+impl<DB, Q, MP> Drop for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn drop(&mut self) {
+        // A memo keeps the slots of its inputs alive, so the slots of
+        // a long chain of queries would otherwise be dropped
+        // recursively, which can overflow the stack.
+        self.slot_map.for_each(|_, slot| slot.discard_inputs());
+    }
 }
 
 impl<DB, Q, MP> Default for DerivedStorage<DB, Q, MP>
@@ -99,7 +401,7 @@ where
 {
     fn default() -> Self {
         DerivedStorage {
-            slot_map: RwLock::new(FxHashMap::default()),
+            slot_map: SlotMap::new(KeyHasher::for_query::<DB, Q>(), Q::KEY_ORDER),
             lru_list: Default::default(),
             policy: PhantomData,
         }
@@ -113,15 +415,50 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     fn slot(&self, key: &Q::Key) -> Arc<Slot<DB, Q, MP>> {
-        if let Some(v) = self.slot_map.read().get(key) {
-            return v.clone();
+        self.slot_map
+            .get_or_insert_with(key, || Arc::new(Slot::new(key.clone())))
+    }
+
+    /// Common tail of `try_fetch` and `try_fetch_ref`: records the
+    /// use of `slot` in the LRU lists and reports the read to the
+    /// runtime.
+    fn record_read(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        slot: Arc<Slot<DB, Q, MP>>,
+        value: StampedValue<Q::Value>,
+    ) -> Q::Value {
+        let cost = Q::LRU_COST.map_or(1, |cost| cost(&value.value));
+        self.record_use(db, key, slot, cost, value.durability, value.changed_at);
+        value.value
+    }
+
+    /// Records the use of `slot`, whose value has the given LRU cost,
+    /// in the LRU lists and reports the read to the runtime.
+    fn record_use(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        slot: Arc<Slot<DB, Q, MP>>,
+        cost: usize,
+        durability: Durability,
+        changed_at: Revision,
+    ) {
+        if Q::LRU_COST.is_some() {
+            for evicted in self.lru_list.record_use_with_cost(&slot, cost) {
+                evicted.evict(db);
+            }
+        } else if let Some(evicted) = self.lru_list.record_use(&slot) {
+            evicted.evict(db);
         }
 
-        let mut write = self.slot_map.write();
-        write
-            .entry(key.clone())
-            .or_insert_with(|| Arc::new(Slot::new(key.clone())))
-            .clone()
+        if MP::should_memoize_value(key) {
+            db.salsa_runtime().record_global_lru_use(db, &slot, cost);
+        }
+
+        db.salsa_runtime()
+            .report_query_read(slot, durability, changed_at);
     }
 }
 
@@ -131,37 +468,108 @@ where
     DB: Database + HasQueryGroup<Q::Group>,
     MP: MemoizationPolicy<DB, Q>,
 {
-    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleDetected> {
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        db.salsa_runtime().end_transaction_batch();
+        let slot = self.slot(key);
+        let value = slot.read(db)?;
+        Ok(self.record_read(db, key, slot, value))
+    }
+
+    fn try_fetch_at(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        revision: Revision,
+    ) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        if !Q::MULTI_VERSION {
+            plumbing::not_multi_version::<DB, Q>(key, revision);
+        }
         let slot = self.slot(key);
         let StampedValue {
             value,
             durability,
             changed_at,
-        } = slot.read(db)?;
-
-        if let Some(evicted) = self.lru_list.record_use(&slot) {
-            evicted.evict();
-        }
-
+        } = slot.read_at(db, revision)?;
         db.salsa_runtime()
             .report_query_read(slot, durability, changed_at);
-
         Ok(value)
     }
 
+    fn try_fetch_ref<'a>(
+        &'a self,
+        db: &'a DB,
+        key: &Q::Key,
+    ) -> Result<ValueGuard<'a, Q::Value>, CycleError<DB::DatabaseKey>> {
+        db.salsa_runtime().end_transaction_batch();
+        let slot = self.slot(key);
+
+        if let Some(value) = slot.memoized_ref(db) {
+            // Recording the use may evict memos, which must not happen
+            // while we hold a lock on one of them; so we release the
+            // lock first and lock the memo again afterwards.
+            let cost = Q::LRU_COST.map_or(1, |cost| cost(&value.value));
+            let (durability, changed_at) = (value.durability, value.changed_at);
+            std::mem::drop(value);
+            self.record_use(db, key, slot.clone(), cost, durability, changed_at);
+
+            let borrow = db
+                .salsa_runtime()
+                .borrow_slot(Arc::as_ptr(&slot) as *const ());
+            // Safety: the closure only uses `slot` to lock it.
+            let guard = unsafe {
+                ValueGuard::borrowed(slot.clone(), Some(borrow), |slot| {
+                    slot.memoized_ref(db).map(|value| value.value)
+                })
+            };
+            if let Some(guard) = guard {
+                return Ok(guard);
+            }
+        }
+
+        // The value is not memoized (or was evicted in the meantime).
+        let value = slot.read(db)?;
+        Ok(ValueGuard::owned(self.record_read(db, key, slot, value)))
+    }
+
     fn durability(&self, db: &DB, key: &Q::Key) -> Durability {
         self.slot(key).durability(db)
     }
 
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        match self.slot_map.get(key) {
+            Some(slot) => slot.maybe_changed_since(db, revision),
+            None => true,
+        }
+    }
+
+    fn peek(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        self.slot_map.get(key).and_then(|slot| slot.peek(db))
+    }
+
+    fn peek_or_wait(&self, db: &DB, key: &Q::Key) -> PeekFuture<Q::Value> {
+        match self.slot_map.get(key) {
+            Some(slot) => slot.peek_or_wait(db),
+            None => PeekFuture::ready(None),
+        }
+    }
+
+    fn sweep_keys(&self, db: &DB, keys: &mut dyn Iterator<Item = Q::Key>, strategy: SweepStrategy) {
+        let revision_now = db.salsa_runtime().current_revision();
+        for key in keys {
+            if let Some(slot) = self.slot_map.get(&key) {
+                slot.sweep(db, revision_now, strategy, None);
+            }
+        }
+    }
+
     fn entries<C>(&self, _db: &DB) -> C
     where
         C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
     {
-        let slot_map = self.slot_map.read();
-        slot_map
-            .values()
-            .filter_map(|slot| slot.as_table_entry())
-            .collect()
+        let mut entries = Vec::new();
+        self.slot_map
+            .for_each(|_, slot| entries.extend(slot.as_table_entry()));
+        entries.into_iter().collect()
     }
 }
 
@@ -171,12 +579,87 @@ where
     DB: Database + HasQueryGroup<Q::Group>,
     MP: MemoizationPolicy<DB, Q>,
 {
-    fn sweep(&self, db: &DB, strategy: SweepStrategy) {
-        let map_read = self.slot_map.read();
+    fn sweep(&self, db: &DB, strategy: SweepStrategy, policy: Option<&dyn SweepPolicy>) {
+        let revision_now = db.salsa_runtime().current_revision();
+        self.slot_map
+            .for_each(|_, slot| slot.sweep(db, revision_now, strategy, policy));
+    }
+
+    fn sweep_some(
+        &self,
+        db: &DB,
+        strategy: SweepStrategy,
+        start: usize,
+        limit: usize,
+    ) -> (usize, Option<usize>) {
+        // Slots are never removed from the map, and are visited in
+        // insertion order, so positions are stable.
+        let mut slots = Vec::new();
+        let mut position = 0;
+        self.slot_map.for_each(|_, slot| {
+            if position >= start && slots.len() < limit {
+                slots.push(slot.clone());
+            }
+            position += 1;
+        });
         let revision_now = db.salsa_runtime().current_revision();
-        for slot in map_read.values() {
-            slot.sweep(revision_now, strategy);
+        for slot in &slots {
+            slot.sweep(db, revision_now, strategy, None);
         }
+        let swept = slots.len();
+        if swept < limit {
+            (swept, None)
+        } else {
+            (swept, Some(start + swept))
+        }
+    }
+
+    fn debug_dump(&self, db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let mut slots = Vec::new();
+        self.slot_map
+            .for_each(|_, slot| slots.push(slot.debug_dump(db)));
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        let entry_size = std::mem::size_of::<(Q::Key, Arc<Slot<DB, Q, MP>>)>();
+        let mut report = MemoryReport::default();
+        self.slot_map.for_each(|_, slot| {
+            report += slot.memory_usage();
+            report.bytes += entry_size;
+        });
+        (Q::QUERY_NAME, report)
+    }
+
+    fn find_dangling_in_progress(&self, db: &DB, dangling: &mut Vec<(String, RuntimeId)>) {
+        self.slot_map.for_each(|_, slot| {
+            dangling.extend(slot.dangling_in_progress(db));
+        });
+    }
+}
+
+impl<DB, Q, MP> QueryStorageStats for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn slot_count(&self) -> usize {
+        self.slot_map.len()
+    }
+
+    fn in_progress_count(&self) -> usize {
+        let mut count = 0;
+        self.slot_map
+            .for_each(|_, slot| count += slot.stats().0 as usize);
+        count
+    }
+
+    fn memoized_count(&self) -> usize {
+        let mut count = 0;
+        self.slot_map
+            .for_each(|_, slot| count += slot.stats().1 as usize);
+        count
     }
 }
 
@@ -187,6 +670,179 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     fn set_lru_capacity(&self, new_capacity: usize) {
-        self.lru_list.set_lru_capacity(new_capacity);
+        match Q::LRU_COST {
+            Some(_) => self.lru_list.set_lru_cost_budget(new_capacity),
+            None => self.lru_list.set_lru_capacity(new_capacity),
+        }
+    }
+}
+
+impl<DB, Q, MP> PinQueryStorageOps<DB, Q> for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn set_pinned(&self, key: &Q::Key, pinned: bool) {
+        self.slot(key).set_pinned(pinned);
+    }
+}
+
+impl<DB, Q, MP> DerivedQueryStorageOps<DB, Q> for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn set_memo(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        value: Q::Value,
+        durability: Durability,
+    ) {
+        debug!(
+            "{:?}({:?}) memo = {:?} ({:?})",
+            Q::default(),
+            debug_key::<DB, Q>(key),
+            debug_value::<DB, Q>(&value),
+            durability
+        );
+
+        // Like setting an input, this needs a new revision, so that the
+        // queries that read the old value (if any) are revalidated.
+        db.salsa_runtime().with_incremented_revision(|guard| {
+            let slot = self.slot(key);
+            if let Some(old_durability) = slot.set_memo(db, value, durability, guard.new_revision())
+            {
+                guard.mark_durability_as_changed(old_durability);
+            }
+            guard.record_change(|| InputChange::MemoSet {
+                database_key: database_key.clone(),
+                durability,
+            });
+        });
+    }
+
+    fn invalidate(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey) {
+        let slot = match self.slot_map.get(key) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        debug!(
+            "{:?}({:?}) invalidated",
+            Q::default(),
+            debug_key::<DB, Q>(key)
+        );
+
+        // The queries that read the value must be revalidated (even if
+        // they only depend on durable inputs otherwise), so this needs
+        // a new revision.
+        db.salsa_runtime().with_incremented_revision(|guard| {
+            if let Some(durability) = slot.invalidate() {
+                guard.mark_durability_as_changed(durability);
+                guard.record_change(|| InputChange::Invalidated {
+                    database_key: database_key.clone(),
+                    durability,
+                });
+            }
+        });
+    }
+
+    fn specify(&self, db: &DB, key: &Q::Key, value: Q::Value) {
+        self.slot(key).specify(db, value);
+    }
+
+    fn changed_entries_since(
+        &self,
+        db: &DB,
+        revision: Revision,
+    ) -> Vec<ChangedEntry<Q::Key, Q::Value>> {
+        let mut entries = Vec::new();
+        self.slot_map.for_each(|_, slot| {
+            entries.extend(slot.changed_entry_since(db, revision));
+        });
+        entries
+    }
+
+    #[cfg(feature = "persist")]
+    fn transitive_inputs(&self, key: &Q::Key, inputs: &mut TransitiveInputs<DB>) -> bool {
+        inputs.collect(Dependency::new(self.slot(key)))
+    }
+
+    #[cfg(feature = "persist")]
+    fn import_result(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        value: Q::Value,
+        inputs: &[&HashedInput<DB>],
+    ) -> bool {
+        self.slot(key).import_memo(db, value, inputs)
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<DB, Q, MP> PersistQueryStorageOps<DB> for DerivedStorage<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: Serialize + DeserializeOwned,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn query_name(&self) -> &'static str {
+        Q::QUERY_NAME
+    }
+
+    fn save_slots(&self, table: u32, slots: &mut SavedSlots<DB>) -> std::io::Result<()> {
+        for (key, slot) in self.slot_map.to_vec() {
+            slots.insert(Dependency::new(slot), table, persist::serialize(&key)?);
+        }
+        Ok(())
+    }
+
+    fn save_table(
+        &self,
+        slots: &SavedSlots<DB>,
+        revisions: &mut RevisionSet,
+    ) -> std::io::Result<Vec<u8>> {
+        let mut memos = Vec::new();
+        self.slot_map.for_each(|key, slot| {
+            if let Some(memo) = slot.persisted_memo(slots, revisions) {
+                memos.push((key.clone(), memo));
+            }
+        });
+        persist::serialize(&memos)
+    }
+
+    fn load_slots(
+        &self,
+        table: u32,
+        data: &[u8],
+        _revisions: &RevisionMap,
+        slots: &mut LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        let memos: Vec<(Q::Key, persist::PersistedMemo<Q::Value>)> = persist::deserialize(data)?;
+        for (key, _) in memos {
+            let slot = self.slot(&key);
+            slots.insert(table, persist::serialize(&key)?, Dependency::new(slot));
+        }
+        Ok(())
+    }
+
+    fn load_table(
+        &self,
+        data: &[u8],
+        revisions: &RevisionMap,
+        slots: &LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        let memos: Vec<(Q::Key, persist::PersistedMemo<Q::Value>)> = persist::deserialize(data)?;
+        for (key, memo) in memos {
+            self.slot(&key).restore_memo(memo, revisions, slots)?;
+        }
+        Ok(())
     }
 }
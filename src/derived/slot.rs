@@ -1,11 +1,18 @@
-use crate::debug::TableEntry;
-use crate::dependency::DatabaseSlot;
-use crate::dependency::Dependency;
+#[cfg(feature = "persist")]
+use crate::artifact::HashedInput;
+use crate::debug::{SlotDump, SlotState, TableEntry};
+use crate::dependency::{self, DatabaseSlot, Dependency};
+use crate::derived::slot_core::{self, MemoInputs, MemoRevisions, Registered, Waiting};
 use crate::derived::MemoizationPolicy;
 use crate::durability::Durability;
+use crate::logging::{debug, info};
+use crate::lru::GlobalLruNode;
 use crate::lru::LruIndex;
 use crate::lru::LruNode;
-use crate::plumbing::CycleDetected;
+use crate::opaque::{debug_key, debug_value};
+use crate::peek_future::PeekFuture;
+#[cfg(feature = "persist")]
+use crate::persist::{self, LoadedSlots, PersistedMemo, RevisionMap, RevisionSet, SavedSlots};
 use crate::plumbing::GetQueryTable;
 use crate::plumbing::HasQueryGroup;
 use crate::plumbing::QueryFunction;
@@ -14,15 +21,21 @@ use crate::runtime::FxIndexSet;
 use crate::runtime::Runtime;
 use crate::runtime::RuntimeId;
 use crate::runtime::StampedValue;
-use crate::{Database, DiscardIf, DiscardWhat, Event, EventKind, SweepStrategy};
-use log::{debug, info};
+use crate::time::Instant;
+use crate::{
+    ChangedEntry, CycleError, Database, Discard, DiscardIf, DiscardWhat, Event, EventKind,
+    InvalidationReason, MemoryReport, Query, QueryPanic, SchedulePoint, SweepInfo, SweepPolicy,
+    SweepStrategy,
+};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::epoch::{self, Atomic, Owned, Shared};
 use parking_lot::Mutex;
-use parking_lot::RwLock;
-use smallvec::SmallVec;
+use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
 use std::marker::PhantomData;
-use std::ops::Deref;
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
+use std::ops::{Deref, DerefMut};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
 
 pub(super) struct Slot<DB, Q, MP>
 where
@@ -31,16 +44,62 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     key: Q::Key,
-    state: RwLock<QueryState<DB, Q>>,
+    state: RwLock<QueryState<DB, Q, MP>>,
     policy: PhantomData<MP>,
     lru_index: LruIndex,
+    global_lru_index: LruIndex,
+
+    /// If true, the value is exempt from LRU eviction and sweeping;
+    /// see `QueryTable::pin`.
+    pinned: AtomicBool,
+
+    /// The memoized value with its revision stamps, published so that
+    /// reads of a value that was verified in the current revision need
+    /// not lock `state` (see `read_published`). Null if the memo has
+    /// no value in memory. Otherwise, the published value is the one
+    /// in the memo, though the memo may have been verified in a later
+    /// revision than the published stamps say.
+    published: Atomic<Published<MP::Memoized>>,
+
+    /// When the value was last read (or computed); used by
+    /// `SweepStrategy::sweep_idle_for`.
+    last_accessed: AtomicCell<Instant>,
+
+    /// The value that another query specified for this key, if any;
+    /// see `QueryTable::specify`.
+    specified: Mutex<Option<Box<Specified<DB, Q>>>>,
+
+    /// For `#[salsa::multi_version]` queries, values that snapshots
+    /// pinned at older revisions may still read, along with the last
+    /// revision in which each was verified; see `read_at`.
+    old_values: Mutex<Vec<(StampedValue<Q::Value>, Revision)>>,
+}
+
+/// A value specified by another query; see `Slot::specify`.
+struct Specified<DB, Q>
+where
+    Q: QueryFunction<DB>,
+    DB: Database,
+{
+    value: Q::Value,
+
+    /// The query that specified the value. Executing this key means
+    /// fetching that query, which specifies the value again if needed.
+    by: DB::DatabaseKey,
+
+    /// Last revision in which the value was specified.
+    specified_at: Revision,
+
+    /// Last revision in which the specified value changed.
+    changed_at: Revision,
 }
 
 /// Defines the "current state" of query's memoized results.
-enum QueryState<DB, Q>
+enum QueryState<DB, Q, MP>
 where
     Q: QueryFunction<DB>,
     DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
 {
     NotComputed,
 
@@ -49,57 +108,61 @@ where
     /// indeeds a cycle.
     InProgress {
         id: RuntimeId,
-        waiting: Mutex<SmallVec<[Sender<StampedValue<Q::Value>>; 2]>>,
+        waiting: Waiting<Q::Value>,
     },
 
     /// We have computed the query already, and here is the result.
-    Memoized(Memo<DB, Q>),
+    Memoized(Memo<DB, Q, MP>),
 }
 
-struct Memo<DB, Q>
+struct Memo<DB, Q, MP>
 where
     Q: QueryFunction<DB>,
     DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
 {
-    /// The result of the query, if we decide to memoize it.
-    value: Option<Q::Value>,
+    /// The result of the query, if we decide to memoize it (in the
+    /// form chosen by the memoization policy). Shared with
+    /// `Slot::published`.
+    value: Option<Arc<MP::Memoized>>,
+
+    /// True if the value was saved to the query's `ValueStore` rather
+    /// than kept in `value`.
+    spilled: bool,
+
+    /// The revisions of the memo and its inputs; the memo derefs to
+    /// these.
+    revisions: MemoRevisions<DB>,
+
+    /// With `#[salsa::keep_previous]`, the value that `value` replaced
+    /// and the revision in which that one had changed.
+    previous: Option<(Q::Value, Revision)>,
+}
 
-    /// Last revision when this memo was verified (if there are
-    /// untracked inputs, this will also be when the memo was
-    /// created).
+/// A memoized value as published in `Slot::published`: the value is
+/// up to date in revision `verified_at`. The value itself is owned by
+/// the memo, so that it is dropped as soon as the memo lets go of it,
+/// rather than once the `Published` is garbage collected.
+struct Published<M> {
+    memoized: Weak<M>,
     verified_at: Revision,
-
-    /// Last revision when the memoized value was observed to change.
     changed_at: Revision,
-
-    /// Minimum durability of the inputs to this query.
     durability: Durability,
-
-    /// The inputs that went into our query, if we are tracking them.
-    inputs: MemoInputs<DB>,
-}
-
-/// An insertion-order-preserving set of queries. Used to track the
-/// inputs accessed during query execution.
-pub(super) enum MemoInputs<DB: Database> {
-    /// Non-empty set of inputs, fully known
-    Tracked {
-        inputs: Arc<FxIndexSet<Dependency<DB>>>,
-    },
-
-    /// Empty set of inputs, fully known.
-    NoInputs,
-
-    /// Unknown quantity of inputs
-    Untracked,
+    executed_at: Instant,
 }
 
 /// Return value of `probe` helper.
-enum ProbeState<V, G> {
-    UpToDate(Result<V, CycleDetected>),
+enum ProbeState<'me, DB: Database, V, G> {
+    UpToDate(Result<StampedValue<V>, CycleError<DB::DatabaseKey>>),
+    Pending(Registered<'me, DB, V>),
     StaleOrAbsent(G),
 }
 
+/// Return value of `claim` helper; `StaleOrAbsent` carries the old
+/// memo, if any.
+type ClaimState<'me, DB, Q, MP> =
+    ProbeState<'me, DB, <Q as Query<DB>>::Value, Option<Memo<DB, Q, MP>>>;
+
 impl<DB, Q, MP> Slot<DB, Q, MP>
 where
     Q: QueryFunction<DB>,
@@ -111,35 +174,184 @@ where
             key,
             state: RwLock::new(QueryState::NotComputed),
             lru_index: LruIndex::default(),
+            global_lru_index: LruIndex::default(),
+            pinned: AtomicBool::new(false),
             policy: PhantomData,
+            published: Atomic::null(),
+            last_accessed: AtomicCell::new(Instant::now()),
+            specified: Mutex::new(None),
+            old_values: Default::default(),
         }
     }
 
-    pub(super) fn database_key(&self, db: &DB) -> DB::DatabaseKey {
+    fn database_key(&self, db: &DB) -> DB::DatabaseKey {
         <DB as GetQueryTable<Q>>::database_key(db, self.key.clone())
     }
 
-    pub(super) fn read(&self, db: &DB) -> Result<StampedValue<Q::Value>, CycleDetected> {
+    /// Returns the value as of `revision`, which the snapshot `db` is
+    /// pinned at, once the database has moved on. That is the memoized
+    /// value if it was verified to be valid at `revision`, or else one
+    /// of the old values kept for pinned snapshots; failing both, the
+    /// query is executed with its inputs read as of `revision` as well,
+    /// and the result is kept for other snapshots pinned there.
+    pub(super) fn read_at(
+        &self,
+        db: &DB,
+        revision: Revision,
+    ) -> Result<StampedValue<Q::Value>, CycleError<DB::DatabaseKey>> {
+        if let QueryState::Memoized(memo) = &*self.state.read() {
+            if !memo.invalidated && memo.changed_at <= revision && revision <= memo.verified_at {
+                if let Some(value) = memo.value(db, &self.key) {
+                    return Ok(memo.stamp(value));
+                }
+            }
+        }
+
+        let old_value = self
+            .old_values
+            .lock()
+            .iter()
+            .find(|(value, verified_at)| value.changed_at <= revision && revision <= *verified_at)
+            .map(|(value, _)| value.clone());
+        if let Some(value) = old_value {
+            return Ok(value);
+        }
+
+        let runtime = db.salsa_runtime();
+        let database_key = self.database_key(db);
+        if runtime.active_query_stack().contains(&database_key) {
+            return Err(runtime.cycle_error(&database_key));
+        }
+        let result = runtime.execute_query_implementation(db, &database_key, || {
+            info!("{:?}: executing query at {:?}", self, revision);
+            grow_stack(|| self.execute_query_function(db))
+        });
+        let value = StampedValue {
+            value: result.value,
+            durability: result.durability,
+            changed_at: result.changed_at,
+        };
+        self.keep_old_value(db, value.clone(), revision);
+        Ok(value)
+    }
+
+    /// Keeps `value`, which was valid from its `changed_at` through
+    /// `verified_at`, if a pinned snapshot may still read it; see
+    /// `read_at`. Old values that no pinned snapshot can read anymore
+    /// are dropped.
+    fn keep_old_value(&self, db: &DB, value: StampedValue<Q::Value>, verified_at: Revision) {
+        let runtime = db.salsa_runtime();
+        let mut old_values = self.old_values.lock();
+        old_values.retain(|(value, verified_at)| {
+            runtime.is_pinned_within(value.changed_at, verified_at.next())
+        });
+        if runtime.is_pinned_within(value.changed_at, verified_at.next()) {
+            old_values.push((value, verified_at));
+        }
+    }
+
+    pub(super) fn read(
+        &self,
+        db: &DB,
+    ) -> Result<StampedValue<Q::Value>, CycleError<DB::DatabaseKey>> {
         let runtime = db.salsa_runtime();
 
         // NB: We don't need to worry about people modifying the
-        // revision out from under our feet. Either `db` is a frozen
-        // database, in which case there is a lock, or the mutator
-        // thread is the current thread, and it will be prevented from
-        // doing any `set` invocations while the query function runs.
+        // revision out from under our feet. Either `db` is a snapshot
+        // (see `ParallelDatabase::snapshot`), in which case there is a
+        // lock, or the mutator thread is the current thread, and it
+        // will be prevented from doing any `set` invocations while the
+        // query function runs.
         let revision_now = runtime.current_revision();
 
         info!("{:?}: invoked at {:?}", self, revision_now,);
 
-        // First, do a check with a read-lock.
-        match self.probe(db, self.state.read(), runtime, revision_now) {
+        // First, check the published value, without locking.
+        if let Some(value) = self.read_published(db, revision_now) {
+            return Ok(value);
+        }
+
+        // Then, do a check with a read-lock.
+        match self.probe(db, self.read_state(runtime), runtime, revision_now) {
             ProbeState::UpToDate(v) => return v,
+            ProbeState::Pending(registered) => {
+                if let Some(value) = self.wait(db, registered) {
+                    return Ok(value);
+                }
+            }
             ProbeState::StaleOrAbsent(_guard) => (),
         }
 
         self.read_upgrade(db, revision_now)
     }
 
+    /// Returns the published value, if it is up to date in
+    /// `revision_now`. This is the common case of reading a value
+    /// that was already read (or computed) in the current revision,
+    /// and it takes no locks.
+    fn read_published(&self, db: &DB, revision_now: Revision) -> Option<StampedValue<Q::Value>> {
+        // Values that are checked for determinism need their memo.
+        if db.salsa_runtime().is_checking_determinism() {
+            return None;
+        }
+
+        let guard = &epoch::pin();
+        // Safe because published values are only destroyed once no
+        // thread is pinned anymore (see `publish`).
+        let published = unsafe { self.published.load(Ordering::Acquire, guard).as_ref()? };
+        if published.verified_at != revision_now {
+            return None;
+        }
+        if let Some(max_age) = Q::MAX_AGE {
+            if published.executed_at.elapsed() >= max_age {
+                return None;
+            }
+        }
+
+        let value = MP::recall(&*published.memoized.upgrade()?)?;
+        self.last_accessed.store(Instant::now());
+        info!(
+            "{:?}: returning published value changed at {:?}",
+            self, published.changed_at
+        );
+        Some(StampedValue {
+            durability: published.durability,
+            changed_at: published.changed_at,
+            value,
+        })
+    }
+
+    /// Publishes the value of the memo in `state` (if any) for
+    /// `read_published`, replacing the value published before. Must be
+    /// invoked whenever the memo is replaced, loses its value or is
+    /// verified in a new revision, while `state` is still locked.
+    fn publish(&self, state: &QueryState<DB, Q, MP>) {
+        let published = match state {
+            QueryState::Memoized(memo) => memo.value.as_ref().map(|memoized| Published {
+                memoized: Arc::downgrade(memoized),
+                verified_at: memo.verified_at,
+                changed_at: memo.changed_at,
+                durability: memo.durability,
+                executed_at: memo.executed_at,
+            }),
+            _ => None,
+        };
+
+        let guard = &epoch::pin();
+        let old = match published {
+            Some(published) => self
+                .published
+                .swap(Owned::new(published), Ordering::AcqRel, guard),
+            None => self.published.swap(Shared::null(), Ordering::AcqRel, guard),
+        };
+        if !old.is_null() {
+            // Safe because `old` is no longer reachable, and is only
+            // destroyed once the threads that may still be reading it
+            // have unpinned.
+            unsafe { guard.defer_destroy(old) };
+        }
+    }
+
     /// Second phase of a read operation: acquires an upgradable-read
     /// and -- if needed -- validates whether inputs have changed,
     /// recomputes value, etc. This is invoked after our initial probe
@@ -148,10 +360,63 @@ where
         &self,
         db: &DB,
         revision_now: Revision,
-    ) -> Result<StampedValue<Q::Value>, CycleDetected> {
-        let runtime = db.salsa_runtime();
-
+    ) -> Result<StampedValue<Q::Value>, CycleError<DB::DatabaseKey>> {
         debug!("{:?}: read_upgrade(revision_now={:?})", self, revision_now,);
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!(
+            "salsa_query",
+            query = Q::QUERY_NAME,
+            key = ?debug_key::<DB, Q>(&self.key),
+        )
+        .entered();
+
+        match self.claim(db, revision_now) {
+            ProbeState::UpToDate(v) => v,
+            ProbeState::Pending(registered) => match self.wait(db, registered) {
+                Some(value) => Ok(value),
+                None => self.read_upgrade(db, revision_now),
+            },
+            ProbeState::StaleOrAbsent(old_memo) => self.execute(db, revision_now, old_memo),
+        }
+    }
+
+    /// Helper for `maybe_changed_since`: reads the value (validating
+    /// or re-executing it as needed) and checks when it changed.
+    fn read_changed_since(&self, db: &DB, revision_now: Revision, revision: Revision) -> bool {
+        match self.read_upgrade(db, revision_now) {
+            Ok(v) => {
+                debug!(
+                    "maybe_changed_since({:?}: {:?} since (recomputed) value changed at {:?}",
+                    self,
+                    v.changed_at > revision,
+                    v.changed_at,
+                );
+                v.changed_at > revision
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Acquires a write lock and -- unless the value turns out to be
+    /// up-to-date or in progress on another thread after all --
+    /// installs an `InProgress` marker, so that the current thread is
+    /// responsible for validating or computing the value. In that
+    /// case, returns the old memo (if any) as `StaleOrAbsent`.
+    fn claim<'db>(&self, db: &'db DB, revision_now: Revision) -> ClaimState<'db, DB, Q, MP> {
+        let runtime = db.salsa_runtime();
+        runtime.schedule(SchedulePoint::Claim);
+
+        // If a new revision is pending, anything we compute here
+        // would be thrown away anyway, so don't bother: unwind and
+        // let the pending write proceed.
+        runtime.unwind_if_cancelled();
+
+        // A `ValueGuard` of this runtime holds a read lock on the memo,
+        // so we would wait for ourselves; like a cycle, this can only
+        // be resolved by the caller.
+        if self.is_borrowed(runtime) {
+            return ProbeState::UpToDate(Err(runtime.cycle_error(&self.database_key(db))));
+        }
 
         // Check with an upgradable read to see if there is a value
         // already. (This permits other readers but prevents anyone
@@ -160,17 +425,30 @@ where
         // FIXME(Amanieu/parking_lot#101) -- we are using a write-lock
         // and not an upgradable read here because upgradable reads
         // can sometimes encounter deadlocks.
-        let old_memo = match self.probe(db, self.state.write(), runtime, revision_now) {
-            ProbeState::UpToDate(v) => return v,
-            ProbeState::StaleOrAbsent(mut state) => {
+        match self.probe(db, self.state.write(), runtime, revision_now) {
+            ProbeState::UpToDate(v) => ProbeState::UpToDate(v),
+            ProbeState::Pending(registered) => ProbeState::Pending(registered),
+            ProbeState::StaleOrAbsent(mut state) => ProbeState::StaleOrAbsent(
                 match std::mem::replace(&mut *state, QueryState::in_progress(runtime.id())) {
                     QueryState::Memoized(old_memo) => Some(old_memo),
                     QueryState::InProgress { .. } => unreachable!(),
                     QueryState::NotComputed => None,
-                }
-            }
-        };
+                },
+            ),
+        }
+    }
 
+    /// Last phase of a read operation, invoked once `claim` has
+    /// installed our `InProgress` marker: validates the old memo (if
+    /// any) or else executes the query, and stores the result.
+    fn execute(
+        &self,
+        db: &DB,
+        revision_now: Revision,
+        old_memo: Option<Memo<DB, Q, MP>>,
+    ) -> Result<StampedValue<Q::Value>, CycleError<DB::DatabaseKey>> {
+        let runtime = db.salsa_runtime();
+        runtime.schedule(SchedulePoint::Execute);
         let database_key = self.database_key(db);
         let mut panic_guard = PanicGuard::new(&database_key, self, old_memo, runtime);
 
@@ -178,29 +456,57 @@ where
         // has been a new revision since the last time we checked. So,
         // first things first, let's walk over each of our previous
         // inputs and check whether they are out of date.
-        if let Some(memo) = &mut panic_guard.memo {
-            if let Some(value) = memo.validate_memoized_value(db, revision_now) {
-                info!("{:?}: validated old memoized value", self,);
-
-                db.salsa_event(|| Event {
-                    runtime_id: runtime.id(),
-                    kind: EventKind::DidValidateMemoizedValue {
-                        database_key: database_key.clone(),
-                    },
-                });
+        //
+        // Validating the inputs may re-execute the query that
+        // specified our value (if any), so check for a newly specified
+        // value afterwards.
+        let invalidation_reason = match &mut panic_guard.memo {
+            Some(memo) => {
+                let verified_at = memo.verified_at;
+                match memo
+                    .validate_memoized_value(db, &self.key, revision_now)
+                    .and_then(|value| match self.specified_since(verified_at) {
+                        Some(by) => Err(InvalidationReason::Specified(by)),
+                        None => Ok(value),
+                    }) {
+                    Ok(value) => {
+                        info!("{:?}: validated old memoized value", self,);
+                        #[cfg(feature = "trace")]
+                        tracing::debug!("validated memoized value");
+                        runtime.record_statistics::<Q>(
+                            || database_key.clone(),
+                            |statistics| statistics.validations += 1,
+                        );
 
-                panic_guard.proceed(&value);
+                        db.salsa_runtime().report_event(db, || Event {
+                            runtime_id: runtime.id(),
+                            kind: EventKind::DidValidateMemoizedValue {
+                                database_key: database_key.clone(),
+                            },
+                        });
+
+                        self.last_accessed.store(Instant::now());
+                        self.check_determinism(db, memo, &value.value);
+                        panic_guard.proceed(&value);
 
-                return Ok(value);
+                        return Ok(value);
+                    }
+                    Err(reason) => reason,
+                }
             }
-        }
+            None => InvalidationReason::NotComputed,
+        };
+        #[cfg(feature = "trace")]
+        tracing::debug!(reason = ?invalidation_reason, "executing query");
+        runtime.record_invalidation(&database_key, invalidation_reason);
 
         // Query was not previously executed, or value is potentially
         // stale, or value is absent. Let's execute!
+        let start = runtime.statistics_start();
         let mut result = runtime.execute_query_implementation(db, &database_key, || {
             info!("{:?}: executing query", self);
 
-            Q::execute(db, self.key.clone())
+            grow_stack(|| self.execute_query_function(db))
         });
 
         // We assume that query is side-effect free -- that is, does
@@ -212,40 +518,108 @@ where
             "revision altered during query execution",
         );
 
+        // A value that expires depends on the wall clock, which acts
+        // like a volatile input: it may have changed since the last
+        // execution even if none of the tracked inputs did.
+        if Q::MAX_AGE.is_some() {
+            result.changed_at = revision_now;
+            result.durability = Durability::LOW;
+        }
+
+        let deduplicated = MP::dedup(db, &mut result.value);
+
         // If the new value is equal to the old one, then it didn't
         // really change, even if some of its inputs have. So we can
         // "backdate" its `changed_at` revision to be the same as the
         // old value.
+        let mut backdated = false;
+        let mut previous = None;
         if let Some(old_memo) = &panic_guard.memo {
-            if let Some(old_value) = &old_memo.value {
+            let spilled_value = old_memo
+                .spilled_value(db, &self.key)
+                .map(|value| MP::memoize(&value));
+            if let Some(old_value) = old_memo.value.as_deref().or(spilled_value.as_ref()) {
                 // Careful: if the value became less durable than it
                 // used to be, that is a "breaking change" that our
                 // consumers must be aware of. Becoming *more* durable
                 // is not. See the test `constant_to_non_constant`.
                 if result.durability >= old_memo.durability
-                    && MP::memoized_value_eq(&old_value, &result.value)
+                    && MP::memoized_value_eq(old_value, &result.value)
                 {
                     debug!(
                         "read_upgrade({:?}): value is equal, back-dating to {:?}",
                         self, old_memo.changed_at,
                     );
 
-                    assert!(old_memo.changed_at <= result.changed_at);
+                    #[cfg(feature = "trace")]
+                    tracing::debug!(changed_at = ?old_memo.changed_at, "backdated value");
+
+                    // A memo set with `set_memo` has no inputs to
+                    // bound when it changed.
+                    assert!(
+                        old_memo.changed_at <= result.changed_at || old_memo.has_untracked_input()
+                    );
                     result.changed_at = old_memo.changed_at;
+                    backdated = true;
+                }
+            }
+
+            // Conversely, if the memo was set with `set_memo` or
+            // invalidated with `QueryTableMut::invalidate`, its inputs
+            // may not have changed since it did; queries that read the
+            // old value still have to see that the new one differs.
+            if !backdated && old_memo.changed_at >= result.changed_at {
+                result.changed_at = revision_now;
+            }
+
+            if Q::KEEP_PREVIOUS {
+                previous = if backdated {
+                    old_memo.previous.clone()
+                } else {
+                    old_memo
+                        .value(db, &self.key)
+                        .map(|value| (value, old_memo.changed_at))
+                };
+            }
+
+            // A backdated memo stays valid for the revisions in which
+            // the old one was.
+            if Q::MULTI_VERSION && !backdated {
+                if let Some(value) = old_memo.value(db, &self.key) {
+                    self.keep_old_value(db, old_memo.stamp(value), old_memo.verified_at);
                 }
             }
         }
 
+        if let Some(start) = start {
+            let elapsed = start.elapsed();
+            runtime.record_statistics::<Q>(
+                || database_key.clone(),
+                |statistics| {
+                    statistics.executions += 1;
+                    statistics.backdates += backdated as u64;
+                    statistics.deduplications += deduplicated as u64;
+                    statistics.execution_time += elapsed;
+                },
+            );
+        }
+
         let new_value = StampedValue {
             value: result.value,
             durability: result.durability,
             changed_at: result.changed_at,
         };
 
-        let value = if self.should_memoize_value(&self.key) {
-            Some(new_value.value.clone())
-        } else {
+        let mut spilled = false;
+        let forget = Q::FORGET_VALUE.is_some_and(|forget| forget(&new_value.value));
+        let value = if !self.should_memoize_value(&self.key) || forget {
+            None
+        } else if let Some(value_store) = Q::VALUE_STORE {
+            value_store(db).store(&self.key, &new_value.value);
+            spilled = true;
             None
+        } else {
+            Some(MP::memoize(&new_value.value))
         };
 
         debug!(
@@ -257,7 +631,17 @@ where
         let inputs = match result.dependencies {
             None => MemoInputs::Untracked,
 
-            Some(dependencies) => {
+            Some(mut dependencies) => {
+                if runtime.prunes_dependencies() {
+                    let pruned = dependency::prune_dominated(&mut dependencies);
+                    if pruned > 0 {
+                        runtime.record_statistics::<Q>(
+                            || database_key.clone(),
+                            |statistics| statistics.pruned_dependencies += pruned as u64,
+                        );
+                    }
+                }
+
                 if dependencies.is_empty() {
                     MemoInputs::NoInputs
                 } else {
@@ -269,12 +653,19 @@ where
         };
         debug!("read_upgrade({:?}): inputs={:?}", self, inputs);
 
+        self.last_accessed.store(Instant::now());
         panic_guard.memo = Some(Memo {
-            value,
-            changed_at: result.changed_at,
-            verified_at: revision_now,
-            inputs,
-            durability: result.durability,
+            value: value.map(Arc::new),
+            spilled,
+            revisions: MemoRevisions {
+                changed_at: result.changed_at,
+                verified_at: revision_now,
+                inputs,
+                durability: result.durability,
+                executed_at: Instant::now(),
+                invalidated: false,
+            },
+            previous,
         });
 
         panic_guard.proceed(&new_value);
@@ -282,6 +673,130 @@ where
         Ok(new_value)
     }
 
+    /// Computes the value: executes the query function or, if another
+    /// query specified the value, fetches that query (which specifies
+    /// the value anew if it is re-executed) and returns its value.
+    fn execute_query_function(&self, db: &DB) -> Q::Value {
+        let by = match &*self.specified.lock() {
+            Some(specified) => specified.by.clone(),
+            None => {
+                return match Q::CATCH_PANICS {
+                    Some(poisoned) => self.execute_catching_panics(db, poisoned),
+                    None => Q::execute(db, self.key.clone()),
+                }
+            }
+        };
+        db.fetch_by_key(&by);
+        match &*self.specified.lock() {
+            Some(specified) => specified.value.clone(),
+            None => unreachable!("specified value was removed"),
+        }
+    }
+
+    /// Executes the query function of a `#[salsa::catch_panics]` query:
+    /// if it panics, returns the value that `poisoned` makes of the
+    /// panic instead, unless salsa itself unwound (to cancel the query,
+    /// say).
+    fn execute_catching_panics(&self, db: &DB, poisoned: fn(QueryPanic) -> Q::Value) -> Q::Value {
+        let result =
+            std::panic::catch_unwind(AssertUnwindSafe(|| Q::execute(db, self.key.clone())));
+        let payload = match result {
+            Ok(value) => return value,
+            Err(payload) => payload,
+        };
+        let runtime = db.salsa_runtime();
+        if runtime.is_yielding() || runtime.is_current_revision_canceled() {
+            std::panic::resume_unwind(payload);
+        }
+        match QueryPanic::from_payload::<DB>(&*payload) {
+            Some(panic) => {
+                // The query may not panic when executed again, even
+                // if its inputs did not change.
+                runtime.report_untracked_read();
+                poisoned(panic)
+            }
+            None => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// If the value was specified by another query, and the specified
+    /// value changed since `revision`, returns that query.
+    fn specified_since(&self, revision: Revision) -> Option<DB::DatabaseKey> {
+        match &*self.specified.lock() {
+            Some(specified) if specified.changed_at > revision => Some(specified.by.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records `value` as the value of this key, specified by the query
+    /// that is currently executing; see `QueryTable::specify`.
+    pub(super) fn specify(&self, db: &DB, value: Q::Value) {
+        let runtime = db.salsa_runtime();
+        let by = match runtime.active_query() {
+            Some(by) => by,
+            None => panic!("{:?} specified outside of a query", self),
+        };
+        let revision_now = runtime.current_revision();
+        let read_in_this_revision = match &*self.state.read() {
+            QueryState::Memoized(memo) => memo.verified_at == revision_now,
+            _ => false,
+        };
+
+        let mut specified = self.specified.lock();
+        let changed_at = match specified.as_deref() {
+            Some(old) if old.by != by && old.specified_at == revision_now => panic!(
+                "{:?} specified by both {:?} and {:?} in the same revision",
+                self, old.by, by,
+            ),
+            Some(old)
+                if self.should_memoize_value(&self.key)
+                    && MP::memoized_value_eq(&MP::memoize(&old.value), &value) =>
+            {
+                old.changed_at
+            }
+            _ => revision_now,
+        };
+        if changed_at == revision_now && read_in_this_revision {
+            panic!(
+                "{:?} specified by {:?} after it was read in the same revision",
+                self, by,
+            );
+        }
+        debug!("{:?}: specified by {:?}", self, by);
+        *specified = Some(Box::new(Specified {
+            value,
+            by,
+            specified_at: revision_now,
+            changed_at,
+        }));
+    }
+
+    /// While checking determinism (see `salsa::testing`), re-executes
+    /// the query the first time in a revision that the memoized
+    /// `value` is reused, and panics if that produces a different
+    /// value. Values with untracked inputs are not checked.
+    fn check_determinism(&self, db: &DB, memo: &Memo<DB, Q, MP>, value: &Q::Value) {
+        let runtime = db.salsa_runtime();
+        if memo.has_untracked_input()
+            || !MP::compares_values()
+            || !runtime.should_check_determinism(|| self.database_key(db))
+        {
+            return;
+        }
+
+        let database_key = self.database_key(db);
+        let new_value =
+            runtime.execute_query_again(&database_key, || self.execute_query_function(db));
+        if !MP::memoized_value_eq(&MP::memoize(value), &new_value) {
+            panic!(
+                "{:?} is not deterministic: re-executing it produced {:?} instead of {:?}",
+                database_key,
+                debug_value::<DB, Q>(&new_value),
+                debug_value::<DB, Q>(value),
+            );
+        }
+    }
+
     /// Helper for `read`:
     ///
     /// Invoked with the guard `map` of some lock on `self.map` (read
@@ -291,26 +806,27 @@ where
     ///
     /// - `ProbeState::UpToDate(r)` if the table has an up-to-date
     ///   value (or we blocked on another thread that produced such a value).
-    /// - `ProbeState::CycleDetected` if this thread is (directly or
-    ///   indirectly) already computing this value.
-    /// - `ProbeState::BlockedOnOtherThread` if some other thread
-    ///   (which does not depend on us) was already computing this
-    ///   value; caller should re-acquire the lock and try again.
+    /// - `ProbeState::UpToDate(Err(e))` if this thread is (directly or
+    ///   indirectly) already computing this value; `e` lists the
+    ///   queries in the cycle.
+    /// - `ProbeState::Pending(r)` if some other thread (which does
+    ///   not depend on us) was already computing this value; the
+    ///   caller should wait for it with `r`.
     /// - `ProbeState::StaleOrAbsent` if either (a) there is no memo
     ///    for this key, (b) the memo has no value; or (c) the memo
     ///    has not been verified at the current revision.
     ///
     /// Note that in all cases **except** for `StaleOrAbsent`, the lock on
     /// `map` will have been released.
-    fn probe<StateGuard>(
+    fn probe<'me, StateGuard>(
         &self,
         db: &DB,
         state: StateGuard,
-        runtime: &Runtime<DB>,
+        runtime: &'me Runtime<DB>,
         revision_now: Revision,
-    ) -> ProbeState<StampedValue<Q::Value>, StateGuard>
+    ) -> ProbeState<'me, DB, Q::Value, StateGuard>
     where
-        StateGuard: Deref<Target = QueryState<DB, Q>>,
+        StateGuard: Deref<Target = QueryState<DB, Q, MP>>,
     {
         match &*state {
             QueryState::NotComputed => { /* fall through */ }
@@ -318,12 +834,15 @@ where
             QueryState::InProgress { id, waiting } => {
                 let other_id = *id;
                 return match self.register_with_in_progress_thread(db, runtime, other_id, waiting) {
-                    Ok(rx) => {
+                    Ok(registered) => {
                         // Release our lock on `self.map`, so other thread
                         // can complete.
                         std::mem::drop(state);
 
-                        db.salsa_event(|| Event {
+                        #[cfg(feature = "trace")]
+                        tracing::debug!(other_runtime = ?other_id, "blocking on other runtime");
+
+                        db.salsa_runtime().report_event(db, || Event {
                             runtime_id: db.salsa_runtime().id(),
                             kind: EventKind::WillBlockOn {
                                 other_runtime_id: other_id,
@@ -331,11 +850,10 @@ where
                             },
                         });
 
-                        let value = rx.recv().unwrap_or_else(|_| db.on_propagated_panic());
-                        ProbeState::UpToDate(Ok(value))
+                        ProbeState::Pending(registered)
                     }
 
-                    Err(CycleDetected) => ProbeState::UpToDate(Err(CycleDetected)),
+                    Err(err) => ProbeState::UpToDate(Err(err)),
                 };
             }
 
@@ -345,13 +863,11 @@ where
                     self, memo.verified_at, memo.changed_at,
                 );
 
-                if let Some(value) = &memo.value {
-                    if memo.verified_at == revision_now {
-                        let value = StampedValue {
-                            durability: memo.durability,
-                            changed_at: memo.changed_at,
-                            value: value.clone(),
-                        };
+                if memo.verified_at == revision_now && !memo.is_expired() {
+                    if let Some(value) = memo.value(db, &self.key) {
+                        self.last_accessed.store(Instant::now());
+                        self.check_determinism(db, memo, &value);
+                        let value = memo.stamp(value);
 
                         info!(
                             "{:?}: returning memoized value changed at {:?}",
@@ -367,6 +883,86 @@ where
         ProbeState::StaleOrAbsent(state)
     }
 
+    /// Locks the memoized value, if it was verified in the current
+    /// revision and is kept in the memo as is (see
+    /// `MemoizationPolicy::recall_ref`), without recording a read.
+    pub(super) fn memoized_ref(
+        &self,
+        db: &DB,
+    ) -> Option<StampedValue<MappedRwLockReadGuard<'_, Q::Value>>> {
+        let runtime = db.salsa_runtime();
+        let revision_now = runtime.current_revision();
+        let state = self.read_state(runtime);
+        let (durability, changed_at) = match &*state {
+            QueryState::Memoized(memo)
+                if memo.verified_at == revision_now && !memo.is_expired() =>
+            {
+                let value = MP::recall_ref(memo.value.as_deref()?)?;
+                self.last_accessed.store(Instant::now());
+                self.check_determinism(db, memo, value);
+                (memo.durability, memo.changed_at)
+            }
+            _ => return None,
+        };
+        let value = RwLockReadGuard::try_map(state, |state| match state {
+            QueryState::Memoized(memo) => memo.value.as_deref().and_then(MP::recall_ref),
+            _ => None,
+        })
+        .ok()?;
+        Some(StampedValue {
+            value,
+            durability,
+            changed_at,
+        })
+    }
+
+    /// Returns the memoized value if it was verified in the current
+    /// revision, without executing the query or recording a read.
+    pub(super) fn peek(&self, db: &DB) -> Option<Q::Value> {
+        let revision_now = db.salsa_runtime().current_revision();
+        match &*self.state.read() {
+            QueryState::Memoized(memo)
+                if memo.verified_at == revision_now && !memo.is_expired() =>
+            {
+                memo.value(db, &self.key)
+            }
+            _ => None,
+        }
+    }
+
+    /// Like `peek`, but if another runtime is computing the value,
+    /// returns a future of the value it computes; see
+    /// `QueryTable::peek_or_wait`. Registers no dependency-graph edge:
+    /// the caller executes no query, so it cannot be part of a cycle.
+    pub(super) fn peek_or_wait(&self, db: &DB) -> PeekFuture<Q::Value> {
+        let runtime = db.salsa_runtime();
+        let revision_now = runtime.current_revision();
+        match &*self.state.read() {
+            QueryState::Memoized(memo)
+                if memo.verified_at == revision_now && !memo.is_expired() =>
+            {
+                PeekFuture::ready(memo.value(db, &self.key))
+            }
+            // With the `single-threaded` feature, the other runtime is
+            // further up the stack of this thread, so the future could
+            // only complete once the caller returns.
+            QueryState::InProgress { id, waiting }
+                if *id != runtime.id() && !cfg!(feature = "single-threaded") =>
+            {
+                PeekFuture::waiting(slot_core::wait_channel(waiting, runtime.priority()))
+            }
+            _ => PeekFuture::ready(None),
+        }
+    }
+
+    /// Forgets the inputs of the memoized value (if any), releasing
+    /// the slots they refer to; used when the storage is dropped.
+    pub(super) fn discard_inputs(&self) {
+        if let QueryState::Memoized(memo) = &mut *self.state.write() {
+            memo.inputs = MemoInputs::Untracked;
+        }
+    }
+
     pub(super) fn durability(&self, db: &DB) -> Durability {
         match &*self.state.read() {
             QueryState::NotComputed => Durability::LOW,
@@ -386,35 +982,418 @@ where
             QueryState::NotComputed => None,
             QueryState::InProgress { .. } => Some(TableEntry::new(self.key.clone(), None)),
             QueryState::Memoized(memo) => {
-                Some(TableEntry::new(self.key.clone(), memo.value.clone()))
+                let mut entry =
+                    TableEntry::new(self.key.clone(), memo.value.as_deref().and_then(MP::recall))
+                        .with_stamp(memo.changed_at, memo.durability);
+                entry.verified_at = Some(memo.verified_at);
+                entry.has_untracked_input = memo.has_untracked_input();
+                Some(entry)
             }
         }
     }
 
-    pub(super) fn evict(&self) {
+    /// Returns the entry of this slot if its memoized value changed
+    /// since `revision`; see `QueryTable::changed_entries_since`.
+    pub(super) fn changed_entry_since(
+        &self,
+        db: &DB,
+        revision: Revision,
+    ) -> Option<ChangedEntry<Q::Key, Q::Value>> {
+        match &*self.state.read() {
+            QueryState::Memoized(memo) if memo.changed_at > revision => {
+                let old_value = match &memo.previous {
+                    Some((value, changed_at)) if *changed_at <= revision => Some(value.clone()),
+                    _ => None,
+                };
+                Some(ChangedEntry {
+                    key: self.key.clone(),
+                    old_value,
+                    new_value: memo.value(db, &self.key)?,
+                    changed_at: memo.changed_at,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// If this slot is in progress in a runtime that was dropped, and
+    /// thus will never be released, returns its (displayed)
+    /// database-key and the id of the runtime.
+    pub(super) fn dangling_in_progress(&self, db: &DB) -> Option<(String, RuntimeId)> {
+        let runtime_id = match &*self.state.read() {
+            QueryState::InProgress { id, .. } => *id,
+            _ => return None,
+        };
+        if db.salsa_runtime().is_runtime_live(runtime_id) {
+            return None;
+        }
+        Some((self.database_key(db).to_string(), runtime_id))
+    }
+
+    pub(super) fn debug_dump(&self, db: &DB) -> SlotDump {
+        match &*self.state.read() {
+            QueryState::NotComputed => {
+                SlotDump::new(&debug_key::<DB, Q>(&self.key), SlotState::NotComputed)
+            }
+            QueryState::InProgress { .. } => {
+                SlotDump::new(&debug_key::<DB, Q>(&self.key), SlotState::InProgress)
+            }
+            QueryState::Memoized(memo) => {
+                let mut dump = SlotDump::new(&debug_key::<DB, Q>(&self.key), SlotState::Memoized)
+                    .with_stamp(memo.changed_at, memo.durability);
+                dump.verified_at = Some(memo.verified_at);
+                match &memo.inputs {
+                    MemoInputs::Tracked { inputs } => {
+                        dump.inputs = inputs
+                            .iter()
+                            .map(|input| match input.database_key(db) {
+                                Some(database_key) => format!("{:?}", database_key),
+                                None => format!("{:?}", input),
+                            })
+                            .collect();
+                    }
+                    MemoInputs::NoInputs => {}
+                    MemoInputs::Untracked => dump.untracked = true,
+                }
+                dump
+            }
+        }
+    }
+
+    /// Returns whether this slot is in progress, and whether it has a
+    /// memoized value; see `QueryStorageStats`.
+    pub(super) fn stats(&self) -> (bool, bool) {
+        match &*self.state.read() {
+            QueryState::NotComputed => (false, false),
+            QueryState::InProgress { .. } => (true, false),
+            QueryState::Memoized(memo) => (false, memo.value.is_some() || memo.spilled),
+        }
+    }
+
+    /// Estimates the memory used by this slot; see
+    /// `QueryTable::memory_usage`.
+    pub(super) fn memory_usage(&self) -> MemoryReport {
+        let mut report = MemoryReport::slot(std::mem::size_of::<Self>());
+        if let QueryState::Memoized(memo) = &*self.state.read() {
+            if let Some(value) = memo.value.as_deref().and_then(MP::recall_ref) {
+                report.add_value(value, Q::HEAP_SIZE);
+            }
+            if let MemoInputs::Tracked { inputs } = &memo.inputs {
+                report.bytes += inputs.len() * std::mem::size_of::<Dependency<DB>>();
+            }
+        }
+        report
+    }
+
+    /// Installs `value` as the memoized value, with untracked inputs,
+    /// as if it had been computed in `revision`; see
+    /// `QueryTableMut::set_memo`. Returns the durability of the memo
+    /// it replaces, if any.
+    pub(super) fn set_memo(
+        &self,
+        db: &DB,
+        value: Q::Value,
+        durability: Durability,
+        revision: Revision,
+    ) -> Option<Durability> {
+        let mut spilled = false;
+        let value = if !self.should_memoize_value(&self.key) {
+            None
+        } else if let Some(value_store) = Q::VALUE_STORE {
+            value_store(db).store(&self.key, &value);
+            spilled = true;
+            None
+        } else {
+            Some(Arc::new(MP::memoize(&value)))
+        };
+
+        let mut state = self.state.write();
+        let (old_durability, previous) = match &*state {
+            QueryState::Memoized(memo) => {
+                let previous = if Q::KEEP_PREVIOUS {
+                    memo.value(db, &self.key)
+                        .map(|value| (value, memo.changed_at))
+                } else {
+                    None
+                };
+                (Some(memo.durability), previous)
+            }
+            QueryState::NotComputed => (None, None),
+            QueryState::InProgress { .. } => {
+                panic!("set_memo invoked while {:?} is being computed", self)
+            }
+        };
+        *state = QueryState::Memoized(Memo {
+            value,
+            spilled,
+            revisions: MemoRevisions {
+                changed_at: revision,
+                verified_at: revision,
+                inputs: MemoInputs::Untracked,
+                durability,
+                executed_at: Instant::now(),
+                invalidated: false,
+            },
+            previous,
+        });
+        self.last_accessed.store(Instant::now());
+        self.publish(&state);
+        old_durability
+    }
+
+    /// Marks the memoized value (if any) as invalidated;
+    /// see `QueryTableMut::invalidate`. Returns its durability.
+    pub(super) fn invalidate(&self) -> Option<Durability> {
         let mut state = self.state.write();
-        if let QueryState::Memoized(memo) = &mut *state {
-            // Similar to GC, evicting a value with an untracked input could
-            // lead to inconsistencies. Note that we can't check
-            // `has_untracked_input` when we add the value to the cache,
-            // because inputs can become untracked in the next revision.
-            if memo.has_untracked_input() {
-                return;
+        let durability = match &mut *state {
+            QueryState::Memoized(memo) => {
+                memo.invalidated = true;
+                memo.durability
+            }
+            QueryState::NotComputed => return None,
+            QueryState::InProgress { .. } => {
+                panic!("invalidate invoked while {:?} is being computed", self)
+            }
+        };
+        self.publish(&state);
+        Some(durability)
+    }
+
+    pub(super) fn set_pinned(&self, pinned: bool) {
+        self.pinned.store(pinned, Ordering::SeqCst);
+    }
+
+    pub(super) fn evict(&self, db: &DB) {
+        if self.pinned.load(Ordering::SeqCst) {
+            return;
+        }
+
+        {
+            // Skip the memo if it is locked, say, by a `ValueGuard`:
+            // waiting could deadlock, and the value will be evicted
+            // once it is the least recently used again.
+            let mut state = match self.state.try_write() {
+                Some(state) => state,
+                None => return,
+            };
+            match &mut *state {
+                // Similar to GC, evicting a value with an untracked input could
+                // lead to inconsistencies. Note that we can't check
+                // `has_untracked_input` when we add the value to the cache,
+                // because inputs can become untracked in the next revision.
+                QueryState::Memoized(memo)
+                    if memo.value.is_some() && !memo.has_untracked_input() =>
+                {
+                    memo.value = None;
+                    memo.previous = None;
+                }
+                _ => return,
+            }
+            self.publish(&state);
+        }
+
+        db.salsa_runtime().report_event(db, || Event {
+            runtime_id: db.salsa_runtime().id(),
+            kind: EventKind::DidEvictValue {
+                database_key: self.database_key(db),
+            },
+        });
+    }
+
+    /// Converts the memo (if any) into the form in which it is saved
+    /// to disk. Memos with untracked inputs are not saved (those
+    /// inputs may well be different in the process that loads them),
+    /// and neither are memos that depend on slots that are not saved.
+    #[cfg(feature = "persist")]
+    pub(super) fn persisted_memo(
+        &self,
+        slots: &SavedSlots<DB>,
+        revisions: &mut RevisionSet,
+    ) -> Option<PersistedMemo<Q::Value>> {
+        let state = self.state.read();
+        let memo = match &*state {
+            QueryState::Memoized(memo) => memo,
+            QueryState::NotComputed | QueryState::InProgress { .. } => return None,
+        };
+        if memo.invalidated {
+            return None;
+        }
+
+        let inputs = match &memo.inputs {
+            MemoInputs::Tracked { inputs } => Some(
+                inputs
+                    .iter()
+                    .map(|input| slots.get(input).cloned())
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+            MemoInputs::NoInputs => None,
+            MemoInputs::Untracked => return None,
+        };
+
+        Some(PersistedMemo {
+            value: memo.value.as_deref().and_then(MP::recall),
+            verified_at: revisions.record(memo.verified_at),
+            changed_at: revisions.record(memo.changed_at),
+            durability: memo.durability.index() as u8,
+            inputs,
+        })
+    }
+
+    /// Installs a memo that was loaded from disk, unless this slot has
+    /// been computed in the meantime. Memos whose inputs could not be
+    /// loaded are dropped.
+    #[cfg(feature = "persist")]
+    pub(super) fn restore_memo(
+        &self,
+        memo: PersistedMemo<Q::Value>,
+        revisions: &RevisionMap,
+        slots: &LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        let inputs = match memo.inputs {
+            Some(ids) => {
+                let mut inputs = FxIndexSet::default();
+                for id in &ids {
+                    match slots.get(id) {
+                        Some(input) => {
+                            inputs.insert(input.clone());
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                MemoInputs::Tracked {
+                    inputs: Arc::new(inputs),
+                }
             }
-            memo.value = None;
+            None => MemoInputs::NoInputs,
+        };
+
+        let memo = Memo {
+            value: memo
+                .value
+                .as_ref()
+                .map(|value| Arc::new(MP::memoize(value))),
+            spilled: false,
+            revisions: MemoRevisions {
+                verified_at: revisions.get(memo.verified_at)?,
+                changed_at: revisions.get(memo.changed_at)?,
+                durability: persist::durability_from_u8(memo.durability)?,
+                inputs,
+                executed_at: Instant::now(),
+                invalidated: false,
+            },
+            previous: None,
+        };
+
+        let mut state = self.state.write();
+        if let QueryState::NotComputed = *state {
+            *state = QueryState::Memoized(memo);
+            self.last_accessed.store(Instant::now());
+            self.publish(&state);
         }
+        Ok(())
     }
 
-    pub(super) fn sweep(&self, revision_now: Revision, strategy: SweepStrategy) {
+    /// Installs `value` as the memo, computed from `inputs` (whose
+    /// values are the same as when it was exported), unless there is a
+    /// memo already; see `QueryTable::import_results`. Returns whether
+    /// it was installed.
+    #[cfg(feature = "persist")]
+    pub(super) fn import_memo(
+        &self,
+        db: &DB,
+        value: Q::Value,
+        inputs: &[&HashedInput<DB>],
+    ) -> bool {
+        let memo = Memo {
+            value: if self.should_memoize_value(&self.key) {
+                Some(Arc::new(MP::memoize(&value)))
+            } else {
+                None
+            },
+            spilled: false,
+            revisions: MemoRevisions {
+                verified_at: db.salsa_runtime().current_revision(),
+                // The value only depends on the inputs, so it is the same as
+                // it was when the last of them changed.
+                changed_at: inputs
+                    .iter()
+                    .map(|input| input.changed_at)
+                    .max()
+                    .unwrap_or_else(Revision::start),
+                durability: inputs
+                    .iter()
+                    .map(|input| input.durability)
+                    .min()
+                    .unwrap_or(Durability::HIGH),
+                inputs: if inputs.is_empty() {
+                    MemoInputs::NoInputs
+                } else {
+                    MemoInputs::Tracked {
+                        inputs: Arc::new(inputs.iter().map(|input| input.slot.clone()).collect()),
+                    }
+                },
+                executed_at: Instant::now(),
+                invalidated: false,
+            },
+            previous: None,
+        };
+
         let mut state = self.state.write();
-        match &mut *state {
-            QueryState::NotComputed => (),
+        match *state {
+            QueryState::NotComputed => {
+                *state = QueryState::Memoized(memo);
+                self.last_accessed.store(Instant::now());
+                self.publish(&state);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn sweep(
+        &self,
+        db: &DB,
+        revision_now: Revision,
+        strategy: SweepStrategy,
+        policy: Option<&dyn SweepPolicy>,
+    ) {
+        if self.pinned.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let discarded = self.sweep_state(db, revision_now, strategy, policy);
+        if let Some(discarded) = discarded {
+            db.salsa_runtime().report_event(db, || Event {
+                runtime_id: db.salsa_runtime().id(),
+                kind: EventKind::DidSweep {
+                    database_key: self.database_key(db),
+                    discarded,
+                },
+            });
+        }
+    }
+
+    /// Applies `strategy` (and `policy`) to the memo, returning what
+    /// was discarded (if anything).
+    fn sweep_state(
+        &self,
+        db: &DB,
+        revision_now: Revision,
+        strategy: SweepStrategy,
+        policy: Option<&dyn SweepPolicy>,
+    ) -> Option<DiscardWhat> {
+        // Skip the memo if it is locked, say, by a `ValueGuard`:
+        // waiting could deadlock.
+        let mut state = self.state.try_write()?;
+        let discarded = match &mut *state {
+            QueryState::NotComputed => None,
 
             // Leave stuff that is currently being computed -- the
             // other thread doing that work has unique access to
             // this slot and we should not interfere.
             QueryState::InProgress { .. } => {
                 debug!("sweep({:?}): in-progress", self);
+                None
             }
 
             // Otherwise, drop only value or the whole memo accoring to the
@@ -443,62 +1422,129 @@ where
                 // revision, since we are holding the write lock
                 // when we read `revision_now`.
                 assert!(memo.verified_at <= revision_now);
-                match strategy.discard_if {
+
+                let discard = match policy {
+                    Some(policy) => {
+                        let info = SweepInfo {
+                            query_name: Q::QUERY_NAME,
+                            changed_at: memo.changed_at,
+                            verified_at: memo.verified_at,
+                            durability: memo.durability,
+                            idle_for: self.last_accessed.load().elapsed(),
+                            has_untracked_input,
+                        };
+                        policy.should_discard(&debug_key::<DB, Q>(&self.key), &info)
+                    }
+                    None => Discard::FollowStrategy,
+                };
+                let discard_if = match discard {
+                    Discard::Keep => return None,
+                    Discard::FollowStrategy => {
+                        // Keep values that were read recently, if asked to.
+                        if let Some(min_idle) = strategy.min_idle {
+                            if self.last_accessed.load().elapsed() < min_idle {
+                                return None;
+                            }
+                        }
+                        strategy.discard_if
+                    }
+                    Discard::Anyway => DiscardIf::Always,
+                };
+
+                match discard_if {
                     DiscardIf::Never => unreachable!(),
 
                     // If we are only discarding outdated things,
                     // and this is not outdated, keep it.
-                    DiscardIf::Outdated if memo.verified_at == revision_now => (),
+                    DiscardIf::Outdated if memo.verified_at == revision_now => None,
 
                     // As explained on the `has_untracked_input` variable
                     // definition, if this is a volatile entry, we
                     // can't discard it unless it is outdated.
                     DiscardIf::Always
-                        if has_untracked_input && memo.verified_at == revision_now => {}
+                        if has_untracked_input && memo.verified_at == revision_now =>
+                    {
+                        None
+                    }
 
                     // Otherwise, we can discard -- discard whatever the user requested.
                     DiscardIf::Outdated | DiscardIf::Always => match strategy.discard_what {
+                        DiscardWhat::Nothing if discard == Discard::Anyway => None,
                         DiscardWhat::Nothing => unreachable!(),
+                        DiscardWhat::Values if memo.spilled => {
+                            memo.discard_spilled_value(db, &self.key);
+                            memo.previous = None;
+                            Some(DiscardWhat::Values)
+                        }
                         DiscardWhat::Values => {
-                            memo.value = None;
+                            memo.previous = None;
+                            memo.value.take().map(|_| DiscardWhat::Values)
                         }
                         DiscardWhat::Everything => {
+                            memo.discard_spilled_value(db, &self.key);
                             *state = QueryState::NotComputed;
+                            Some(DiscardWhat::Everything)
                         }
                     },
                 }
             }
+        };
+        if discarded.is_some() {
+            self.publish(&state);
         }
+        discarded
     }
 
-    /// Helper:
-    ///
-    /// When we encounter an `InProgress` indicator, we need to either
-    /// report a cycle or else register ourselves to be notified when
-    /// that work completes. This helper does that; it returns a port
-    /// where you can wait for the final value that wound up being
-    /// computed (but first drop the lock on the map).
-    fn register_with_in_progress_thread(
+    /// Helper: reports a cycle if `other_id` is our own runtime, or
+    /// else registers us to be notified once it computed the value;
+    /// see `slot_core::register_with_in_progress_thread`.
+    fn register_with_in_progress_thread<'me>(
         &self,
         db: &DB,
-        runtime: &Runtime<DB>,
+        runtime: &'me Runtime<DB>,
         other_id: RuntimeId,
-        waiting: &Mutex<SmallVec<[Sender<StampedValue<Q::Value>>; 2]>>,
-    ) -> Result<Receiver<StampedValue<Q::Value>>, CycleDetected> {
-        if other_id == runtime.id() {
-            return Err(CycleDetected);
-        } else {
-            if !runtime.try_block_on(&self.database_key(db), other_id) {
-                return Err(CycleDetected);
-            }
+        waiting: &Waiting<Q::Value>,
+    ) -> Result<Registered<'me, DB, Q::Value>, CycleError<DB::DatabaseKey>> {
+        let database_key = self.database_key(db);
+        let registered =
+            slot_core::register_with_in_progress_thread(runtime, &database_key, other_id, waiting)?;
+        runtime.record_statistics::<Q>(|| database_key, |statistics| statistics.blocks += 1);
+        Ok(registered)
+    }
 
-            let (tx, rx) = mpsc::channel();
+    /// Blocks until the thread we registered with (see
+    /// `register_with_in_progress_thread`) produces its value. Returns
+    /// `None` if that thread yielded the slot instead, in which case
+    /// the caller has to read the slot again.
+    fn wait(
+        &self,
+        db: &DB,
+        registered: Registered<'_, DB, Q::Value>,
+    ) -> Option<StampedValue<Q::Value>> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("salsa_blocked", query = Q::QUERY_NAME).entered();
+        let _unblock = slot_core::Blocked::new(db.salsa_runtime());
+        slot_core::wait(db, registered, &|| self.database_key(db), &|| {
+            self.dangling_in_progress(db)
+        })
+    }
 
-            // The reader of this will have to acquire map
-            // lock, we don't need any particular ordering.
-            waiting.lock().push(tx);
+    /// True if a `ValueGuard` of `runtime` borrows our value (see
+    /// `Runtime::is_slot_borrowed`), so that locking the memo for
+    /// writing on this thread would deadlock.
+    fn is_borrowed(&self, runtime: &Runtime<DB>) -> bool {
+        runtime.is_slot_borrowed(self as *const Self as *const ())
+    }
 
-            Ok(rx)
+    /// Read-locks the memo. If a `ValueGuard` of `runtime` holds a
+    /// read lock on it already, the lock is taken recursively: a fair
+    /// read lock would wait behind a writer queued on another thread,
+    /// which waits for the guard in turn.
+    fn read_state(&self, runtime: &Runtime<DB>) -> RwLockReadGuard<'_, QueryState<DB, Q, MP>> {
+        if self.is_borrowed(runtime) {
+            self.state.read_recursive()
+        } else {
+            self.state.read()
         }
     }
 
@@ -507,10 +1553,25 @@ where
     }
 }
 
-impl<DB, Q> QueryState<DB, Q>
+/// Runs the query function `f`. With the `grow-stack` feature, this
+/// first switches to a new stack segment if the current one is nearly
+/// exhausted, so that deeply nested queries do not overflow the stack.
+#[cfg(feature = "grow-stack")]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    // Allocate 1 MiB at a time, whenever less than 128 KiB are left.
+    stacker::maybe_grow(128 * 1024, 1024 * 1024, f)
+}
+
+#[cfg(not(feature = "grow-stack"))]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+impl<DB, Q, MP> QueryState<DB, Q, MP>
 where
     Q: QueryFunction<DB>,
     DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
 {
     fn in_progress(id: RuntimeId) -> Self {
         QueryState::InProgress {
@@ -528,7 +1589,7 @@ where
 {
     database_key: &'me DB::DatabaseKey,
     slot: &'me Slot<DB, Q, MP>,
-    memo: Option<Memo<DB, Q>>,
+    memo: Option<Memo<DB, Q, MP>>,
     runtime: &'me Runtime<DB>,
 }
 
@@ -541,7 +1602,7 @@ where
     fn new(
         database_key: &'me DB::DatabaseKey,
         slot: &'me Slot<DB, Q, MP>,
-        memo: Option<Memo<DB, Q>>,
+        memo: Option<Memo<DB, Q, MP>>,
         runtime: &'me Runtime<DB>,
     ) -> Self {
         Self {
@@ -576,29 +1637,18 @@ where
             // access to our slot, so we can just remove the key.
             None => std::mem::replace(&mut *write, QueryState::NotComputed),
         };
+        self.slot.publish(&write);
 
         match old_value {
             QueryState::InProgress { id, waiting } => {
                 assert_eq!(id, self.runtime.id());
 
-                self.runtime
+                let unblocked = self
+                    .runtime
                     .unblock_queries_blocked_on_self(&self.database_key);
-
-                match new_value {
-                    // If anybody has installed themselves in our "waiting"
-                    // list, notify them that the value is available.
-                    Some(new_value) => {
-                        for tx in waiting.into_inner() {
-                            tx.send(new_value.clone()).unwrap()
-                        }
-                    }
-
-                    // We have no value to send when we are panicking.
-                    // Therefore, we need to drop the sending half of the
-                    // channel so that our panic propagates to those waiting
-                    // on the receiving half.
-                    None => std::mem::drop(waiting),
-                }
+                std::mem::drop(write);
+                self.runtime.schedule(SchedulePoint::Release { unblocked });
+                slot_core::wake_waiting(self.runtime, waiting, new_value);
             }
             _ => panic!(
                 "\
@@ -628,104 +1678,94 @@ where
     }
 }
 
-impl<DB, Q> Memo<DB, Q>
+impl<DB, Q, MP> Memo<DB, Q, MP>
 where
     Q: QueryFunction<DB>,
     DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
 {
-    /// True if this memo is known not to have changed based on its durability.
-    fn check_durability(&self, db: &DB) -> bool {
-        let last_changed = db.salsa_runtime().last_changed_revision(self.durability);
-        debug!(
-            "check_durability(last_changed={:?} <= verified_at={:?}) = {:?}",
-            last_changed,
-            self.verified_at,
-            last_changed <= self.verified_at,
-        );
-        last_changed <= self.verified_at
+    /// True if the value is older than `QueryFunction::MAX_AGE`.
+    fn is_expired(&self) -> bool {
+        self.is_older_than(Q::MAX_AGE)
     }
 
-    fn validate_memoized_value(
-        &mut self,
-        db: &DB,
-        revision_now: Revision,
-    ) -> Option<StampedValue<Q::Value>> {
-        // If we don't have a memoized value, nothing to validate.
-        if self.value.is_none() {
-            return None;
+    /// Recovers the memoized value, loading it from the value store if
+    /// it was spilled there.
+    fn value(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        match &self.value {
+            Some(value) => MP::recall(value),
+            None => self.spilled_value(db, key),
         }
+    }
 
-        assert!(self.verified_at != revision_now);
-        let verified_at = self.verified_at;
-
-        debug!(
-            "validate_memoized_value({:?}): verified_at={:#?}",
-            Q::default(),
-            self.inputs,
-        );
-
-        if self.check_durability(db) {
-            return Some(self.mark_value_as_verified(revision_now));
+    /// Loads the value from the value store, if it was spilled there.
+    fn spilled_value(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        match Q::VALUE_STORE {
+            Some(value_store) if self.spilled => value_store(db).load(key),
+            _ => None,
         }
+    }
 
-        match &self.inputs {
-            // We can't validate values that had untracked inputs; just have to
-            // re-execute.
-            MemoInputs::Untracked { .. } => {
-                return None;
+    /// Removes the value from the value store, if it was spilled there.
+    fn discard_spilled_value(&mut self, db: &DB, key: &Q::Key) {
+        if let Some(value_store) = Q::VALUE_STORE {
+            if self.spilled {
+                value_store(db).remove(key);
+                self.spilled = false;
             }
+        }
+    }
 
-            MemoInputs::NoInputs => {}
-
-            // Check whether any of our inputs changed since the
-            // **last point where we were verified** (not since we
-            // last changed). This is important: if we have
-            // memoized values, then an input may have changed in
-            // revision R2, but we found that *our* value was the
-            // same regardless, so our change date is still
-            // R1. But our *verification* date will be R2, and we
-            // are only interested in finding out whether the
-            // input changed *again*.
-            MemoInputs::Tracked { inputs } => {
-                let changed_input = inputs
-                    .iter()
-                    .filter(|input| input.maybe_changed_since(db, verified_at))
-                    .next();
-
-                if let Some(input) = changed_input {
-                    debug!(
-                        "{:?}::validate_memoized_value: `{:?}` may have changed",
-                        Q::default(),
-                        input
-                    );
-
-                    return None;
-                }
-            }
+    fn validate_memoized_value(
+        &mut self,
+        db: &DB,
+        key: &Q::Key,
+        revision_now: Revision,
+    ) -> Result<StampedValue<Q::Value>, InvalidationReason<DB::DatabaseKey>> {
+        // If we don't have a memoized value (or it is no longer
+        // available), nothing to validate.
+        let value = match self.value(db, key) {
+            Some(value) => value,
+            None => return Err(InvalidationReason::NoValue),
         };
 
-        Some(self.mark_value_as_verified(revision_now))
+        // An expired value has to be recomputed, even if it was already
+        // verified in this revision.
+        if self.is_expired() {
+            return Err(InvalidationReason::Expired);
+        }
+        if self.invalidated {
+            return Err(InvalidationReason::Invalidated);
+        }
+
+        debug!("validate_memoized_value({:?})", Q::default());
+        self.revisions
+            .verify_inputs(db, revision_now, Q::SHALLOW_REVALIDATION)?;
+        Ok(self.stamp(value))
     }
+}
 
-    fn mark_value_as_verified(&mut self, revision_now: Revision) -> StampedValue<Q::Value> {
-        let value = match &self.value {
-            Some(v) => v.clone(),
-            None => panic!("invoked `verify_value` without a value!"),
-        };
-        self.verified_at = revision_now;
+impl<DB, Q, MP> Deref for Memo<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    type Target = MemoRevisions<DB>;
 
-        StampedValue {
-            durability: self.durability,
-            changed_at: self.changed_at,
-            value,
-        }
+    fn deref(&self) -> &MemoRevisions<DB> {
+        &self.revisions
     }
+}
 
-    fn has_untracked_input(&self) -> bool {
-        match self.inputs {
-            MemoInputs::Untracked => true,
-            _ => false,
-        }
+impl<DB, Q, MP> DerefMut for Memo<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn deref_mut(&mut self) -> &mut MemoRevisions<DB> {
+        &mut self.revisions
     }
 }
 
@@ -736,18 +1776,29 @@ where
     MP: MemoizationPolicy<DB, Q>,
 {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(fmt, "{:?}({:?})", Q::default(), self.key)
+        write!(
+            fmt,
+            "{:?}({:?})",
+            Q::default(),
+            debug_key::<DB, Q>(&self.key)
+        )
     }
 }
 
-impl<DB: Database> std::fmt::Debug for MemoInputs<DB> {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MemoInputs::Tracked { inputs } => {
-                fmt.debug_struct("Tracked").field("inputs", inputs).finish()
+impl<DB, Q, MP> Drop for Slot<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn drop(&mut self) {
+        // Safe because we have unique access to the slot, so nobody
+        // else can be reading the published value.
+        unsafe {
+            let published = self.published.load(Ordering::Relaxed, epoch::unprotected());
+            if !published.is_null() {
+                std::mem::drop(published.into_owned());
             }
-            MemoInputs::NoInputs => fmt.debug_struct("NoInputs").finish(),
-            MemoInputs::Untracked => fmt.debug_struct("Untracked").finish(),
         }
     }
 }
@@ -763,6 +1814,21 @@ where
     }
 }
 
+impl<DB, Q, MP> GlobalLruNode<DB> for Slot<DB, Q, MP>
+where
+    Q: QueryFunction<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+    MP: MemoizationPolicy<DB, Q>,
+{
+    fn global_lru_index(&self) -> &LruIndex {
+        &self.global_lru_index
+    }
+
+    fn evict(&self, db: &DB) {
+        Slot::evict(self, db)
+    }
+}
+
 // The unsafe obligation here is for us to assert that `Slot<DB, Q,
 // MP>` is `Send + Sync + 'static`, assuming `Q::Key` and `Q::Value`
 // are. We assert this with the `check_send_sync` and `check_static`
@@ -773,6 +1839,51 @@ where
     DB: Database + HasQueryGroup<Q::Group>,
     MP: MemoizationPolicy<DB, Q>,
 {
+    fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey> {
+        Some(Slot::database_key(self, db))
+    }
+
+    fn dominated_inputs(&self) -> Option<Arc<FxIndexSet<Dependency<DB>>>> {
+        // Values that are not memoized cannot be backdated.
+        if self.should_memoize_value(&self.key) {
+            return None;
+        }
+        match &*self.state.read() {
+            QueryState::Memoized(memo) => match &memo.inputs {
+                MemoInputs::Tracked { inputs } => Some(inputs.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "persist")]
+    fn tracked_inputs(&self) -> Option<Arc<FxIndexSet<Dependency<DB>>>> {
+        match &*self.state.read() {
+            QueryState::Memoized(memo) => match &memo.inputs {
+                MemoInputs::Tracked { inputs } => Some(inputs.clone()),
+                MemoInputs::NoInputs => Some(Default::default()),
+                MemoInputs::Untracked => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn maybe_changed_since_shallow(&self, db: &DB, revision: Revision) -> Option<bool> {
+        match &*self.state.read() {
+            QueryState::NotComputed => Some(true),
+            QueryState::Memoized(memo) if !memo.is_expired() && !memo.invalidated => {
+                let revision_now = db.salsa_runtime().current_revision();
+                if memo.verified_at == revision_now || memo.check_durability(db) {
+                    Some(memo.changed_at > revision)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
     fn maybe_changed_since(&self, db: &DB, revision: Revision) -> bool {
         let runtime = db.salsa_runtime();
         let revision_now = runtime.current_revision();
@@ -784,7 +1895,7 @@ where
 
         // Acquire read lock to start. In some of the arms below, we
         // drop this explicitly.
-        let state = self.state.read();
+        let state = self.read_state(runtime);
 
         // Look for a memoized value.
         let memo = match &*state {
@@ -806,23 +1917,40 @@ where
                     self, other_id,
                 );
                 match self.register_with_in_progress_thread(db, runtime, other_id, waiting) {
-                    Ok(rx) => {
+                    Ok(registered) => {
                         // Release our lock on `self.map`, so other thread
                         // can complete.
                         std::mem::drop(state);
 
-                        let value = rx.recv().unwrap_or_else(|_| db.on_propagated_panic());
-                        return value.changed_at > revision;
+                        return match self.wait(db, registered) {
+                            Some(value) => value.changed_at > revision,
+                            None => self.maybe_changed_since(db, revision),
+                        };
                     }
 
                     // Consider a cycle to have changed.
-                    Err(CycleDetected) => return true,
+                    Err(_) => return true,
                 }
             }
 
             QueryState::Memoized(memo) => memo,
         };
 
+        if memo.is_expired() {
+            debug!("maybe_changed_since({:?}: recomputing expired value", self);
+            std::mem::drop(state);
+            return self.read_changed_since(db, revision_now, revision);
+        }
+
+        if memo.invalidated {
+            debug!(
+                "maybe_changed_since({:?}: recomputing invalidated value",
+                self
+            );
+            std::mem::drop(state);
+            return self.read_changed_since(db, revision_now, revision);
+        }
+
         if memo.verified_at == revision_now {
             debug!(
                 "maybe_changed_since({:?}: {:?} since up-to-date memo that changed at {:?}",
@@ -833,6 +1961,14 @@ where
             return memo.changed_at > revision;
         }
 
+        // A specified value may have changed even if the inputs of the
+        // memo did not (see `execute`).
+        if self.specified.lock().is_some() {
+            debug!("maybe_changed_since({:?}: checking specified value", self);
+            std::mem::drop(state);
+            return self.read_changed_since(db, revision_now, revision);
+        }
+
         let maybe_changed;
 
         // If we only depended on constants, and no constant has been
@@ -868,22 +2004,15 @@ where
                     // note that we skip the "pure read" part as we
                     // already know the result.
                     assert!(inputs.len() > 0);
-                    if memo.value.is_some() {
+                    if memo.value.as_deref().and_then(MP::recall).is_some() {
                         std::mem::drop(state);
-                        return match self.read_upgrade(db, revision_now) {
-                            Ok(v) => {
-                                debug!(
-                                    "maybe_changed_since({:?}: {:?} since (recomputed) value changed at {:?}",
-                                    self,
-                                    v.changed_at > revision,
-                                    v.changed_at,
-                                );
-                                v.changed_at > revision
-                            }
-                            Err(CycleDetected) => true,
-                        };
+                        return self.read_changed_since(db, revision_now, revision);
                     }
 
+                    // If the value is gone (see `WeakStorage`), or if
+                    // it is kept in a value store, we check the inputs
+                    // first and only re-execute if they changed.
+                    let can_backdate = memo.value.is_some() || memo.spilled;
                     let inputs = inputs.clone();
 
                     // We have a **tracked set of inputs**
@@ -892,12 +2021,20 @@ where
                     std::mem::drop(state);
 
                     // Iterate the inputs and see if any have maybe changed.
-                    maybe_changed = inputs
-                        .iter()
-                        .filter(|input| input.maybe_changed_since(db, revision))
-                        .inspect(|input| debug!("{:?}: input `{:?}` may have changed", self, input))
-                        .next()
-                        .is_some();
+                    let changed_input = slot_core::find_changed_input(
+                        db,
+                        &inputs,
+                        revision,
+                        Q::SHALLOW_REVALIDATION,
+                    );
+                    if let Some(input) = changed_input {
+                        debug!("{:?}: input `{:?}` may have changed", self, input);
+                    }
+                    maybe_changed = changed_input.is_some();
+
+                    if maybe_changed && can_backdate {
+                        return self.read_changed_since(db, revision_now, revision);
+                    }
                 }
             }
         }
@@ -936,7 +2073,12 @@ where
                         // `verified_at` to reflect the current
                         // revision.
                         memo.verified_at = revision_now;
+                        runtime.record_statistics::<Q>(
+                            || self.database_key(db),
+                            |statistics| statistics.validations += 1,
+                        );
                     }
+                    self.publish(&state);
                 }
 
                 QueryState::InProgress { .. } => {
@@ -960,7 +2102,10 @@ where
 
 /// Check that `Slot<DB, Q, MP>: Send + Sync` as long as
 /// `DB::DatabaseData: Send + Sync`, which in turn implies that
-/// `Q::Key: Send + Sync`, `Q::Value: Send + Sync`.
+/// `Q::Key: Send + Sync`, `Q::Value: Send + Sync` (and therefore
+/// `MP::Memoized: Send + Sync`, as `MemoizationPolicy` requires) and
+/// `DB::DatabaseKey: Send + Sync` (as the database keys are made of
+/// the query keys).
 #[allow(dead_code)]
 fn check_send_sync<DB, Q, MP>()
 where
@@ -968,8 +2113,10 @@ where
     DB: Database + HasQueryGroup<Q::Group>,
     MP: MemoizationPolicy<DB, Q>,
     DB::DatabaseData: Send + Sync,
+    DB::DatabaseKey: Send + Sync,
     Q::Key: Send + Sync,
     Q::Value: Send + Sync,
+    MP::Memoized: Send + Sync,
 {
     fn is_send_sync<T: Send + Sync>() {}
     is_send_sync::<Slot<DB, Q, MP>>();
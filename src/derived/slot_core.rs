@@ -0,0 +1,378 @@
+//! The parts of a derived query slot that do not depend on the query:
+//! the revisions of a memo and the validation of its inputs, and the
+//! threads blocked on a slot that is in progress. `Slot<DB, Q, MP>` is
+//! instantiated for every query, so these are kept out of it, and only
+//! instantiated once per database (or per value type, for the
+//! waiters), with `Slot` wrapping them with what is specific to `Q`.
+
+use crate::blocking_future::{BlockingFuture, Promise};
+use crate::dependency::Dependency;
+use crate::durability::Durability;
+use crate::logging::debug;
+use crate::plumbing::DatabaseKey;
+use crate::revalidate;
+use crate::revision::Revision;
+use crate::runtime::BlockedOn;
+use crate::runtime::FxIndexSet;
+use crate::runtime::Priority;
+use crate::runtime::Runtime;
+use crate::runtime::RuntimeId;
+use crate::runtime::StampedValue;
+use crate::time::Instant;
+use crate::{
+    CycleError, DanglingInProgress, Database, InvalidationReason, PropagatedPanicPolicy,
+    SchedulePoint,
+};
+use parking_lot::Mutex;
+use smallvec::SmallVec;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An insertion-order-preserving set of queries. Used to track the
+/// inputs accessed during query execution.
+pub(super) enum MemoInputs<DB: Database> {
+    /// Non-empty set of inputs, fully known
+    Tracked {
+        inputs: Arc<FxIndexSet<Dependency<DB>>>,
+    },
+
+    /// Empty set of inputs, fully known.
+    NoInputs,
+
+    /// Unknown quantity of inputs
+    Untracked,
+}
+
+/// The revisions of a memo, and the inputs it was computed from.
+pub(super) struct MemoRevisions<DB: Database> {
+    /// Last revision when this memo was verified (if there are
+    /// untracked inputs, this will also be when the memo was
+    /// created).
+    pub(super) verified_at: Revision,
+
+    /// Last revision when the memoized value was observed to change.
+    pub(super) changed_at: Revision,
+
+    /// Minimum durability of the inputs to this query.
+    pub(super) durability: Durability,
+
+    /// The inputs that went into our query, if we are tracking them.
+    pub(super) inputs: MemoInputs<DB>,
+
+    /// When the value was computed (or loaded); used to expire it
+    /// after `QueryFunction::MAX_AGE`.
+    pub(super) executed_at: Instant,
+
+    /// Set by `QueryTableMut::invalidate`: the value has to be
+    /// recomputed (but can still be backdated).
+    pub(super) invalidated: bool,
+}
+
+impl<DB: Database> MemoRevisions<DB> {
+    /// True if the value was computed at least `max_age` ago.
+    pub(super) fn is_older_than(&self, max_age: Option<Duration>) -> bool {
+        match max_age {
+            Some(max_age) => self.executed_at.elapsed() >= max_age,
+            None => false,
+        }
+    }
+
+    /// True if this memo is known not to have changed based on its durability.
+    pub(super) fn check_durability(&self, db: &DB) -> bool {
+        let last_changed = db.salsa_runtime().last_changed_revision(self.durability);
+        debug!(
+            "check_durability(last_changed={:?} <= verified_at={:?}) = {:?}",
+            last_changed,
+            self.verified_at,
+            last_changed <= self.verified_at,
+        );
+        last_changed <= self.verified_at
+    }
+
+    pub(super) fn has_untracked_input(&self) -> bool {
+        matches!(self.inputs, MemoInputs::Untracked)
+    }
+
+    /// Checks that none of the inputs changed since the memo was last
+    /// verified, and if so, marks it as verified in `revision_now`.
+    /// With `shallow` (see `#[salsa::shallow_revalidation]`), the
+    /// inputs are not validated, but have to be validated already.
+    pub(super) fn verify_inputs(
+        &mut self,
+        db: &DB,
+        revision_now: Revision,
+        shallow: bool,
+    ) -> Result<(), InvalidationReason<DB::DatabaseKey>> {
+        assert!(self.verified_at != revision_now);
+        let verified_at = self.verified_at;
+
+        debug!("verify_inputs: verified_at={:#?}", self.inputs);
+
+        if self.check_durability(db) {
+            self.verified_at = revision_now;
+            return Ok(());
+        }
+
+        match &self.inputs {
+            // We can't validate values that had untracked inputs; just have to
+            // re-execute.
+            MemoInputs::Untracked => {
+                return Err(InvalidationReason::UntrackedRead);
+            }
+
+            MemoInputs::NoInputs => {}
+
+            // Check whether any of our inputs changed since the
+            // **last point where we were verified** (not since we
+            // last changed). This is important: if we have
+            // memoized values, then an input may have changed in
+            // revision R2, but we found that *our* value was the
+            // same regardless, so our change date is still
+            // R1. But our *verification* date will be R2, and we
+            // are only interested in finding out whether the
+            // input changed *again*.
+            //
+            // With `#[salsa::shallow_revalidation]`, we do not validate
+            // the inputs ourselves, but re-execute unless they were all
+            // validated already.
+            MemoInputs::Tracked { inputs } if shallow => {
+                for input in inputs.iter() {
+                    match input.maybe_changed_since_shallow(db, verified_at) {
+                        Some(false) => {}
+                        Some(true) => return Err(input.changed_reason(db)),
+                        None => {
+                            // Only the slots of derived queries are
+                            // ever unverified.
+                            return Err(InvalidationReason::InputUnverified(
+                                input.database_key(db).unwrap(),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            MemoInputs::Tracked { inputs } => {
+                if let Some(input) = revalidate::find_changed_input(db, inputs, verified_at) {
+                    debug!("verify_inputs: `{:?}` may have changed", input);
+                    return Err(input.changed_reason(db));
+                }
+            }
+        };
+
+        self.verified_at = revision_now;
+        Ok(())
+    }
+
+    /// Stamps `value` with the revisions of the memo.
+    pub(super) fn stamp<V>(&self, value: V) -> StampedValue<V> {
+        StampedValue {
+            durability: self.durability,
+            changed_at: self.changed_at,
+            value,
+        }
+    }
+}
+
+/// Returns an input that may have changed since `revision`, if any;
+/// with `shallow`, an input that is not known to be unchanged.
+pub(super) fn find_changed_input<'i, DB: Database>(
+    db: &DB,
+    inputs: &'i FxIndexSet<Dependency<DB>>,
+    revision: Revision,
+    shallow: bool,
+) -> Option<&'i Dependency<DB>> {
+    if shallow {
+        inputs
+            .iter()
+            .find(|input| input.maybe_changed_since_shallow(db, revision) != Some(false))
+    } else {
+        revalidate::find_changed_input(db, inputs, revision)
+    }
+}
+
+/// What the threads blocked on an `InProgress` slot receive once the
+/// thread computing it releases the slot (unless it panicked).
+#[derive(Clone)]
+pub(crate) enum WaitResult<V> {
+    /// The value was computed.
+    Completed(V),
+
+    /// The computing runtime had background priority and yielded the
+    /// slot to a foreground runtime; the slot has to be read again.
+    Yielded,
+}
+
+/// The threads blocked on an `InProgress` slot: there is one channel
+/// per priority, shared by all the threads of that priority, so that
+/// blocking allocates nothing unless the thread is the first of its
+/// priority to block on the slot.
+pub(super) type Waiting<V> = Mutex<SmallVec<[(Priority, WaitChannel<V>); 2]>>;
+
+type WaitChannel<V> = (
+    Promise<WaitResult<StampedValue<V>>>,
+    BlockingFuture<WaitResult<StampedValue<V>>>,
+);
+
+/// When we encounter an `InProgress` indicator, we need to either
+/// report a cycle or else register ourselves to be notified when
+/// that work completes. This helper does that; it returns a future
+/// where you can wait for the final value that wound up being
+/// computed (but first drop the lock on the slot).
+///
+/// With the `single-threaded` feature, the other runtime can only be
+/// on the current thread too, further up the stack (a handle on the
+/// database used from within a query), and would never complete if
+/// we blocked; so that is reported as a cycle as well.
+pub(super) fn register_with_in_progress_thread<'me, DB: Database, V>(
+    runtime: &'me Runtime<DB>,
+    database_key: &DB::DatabaseKey,
+    other_id: RuntimeId,
+    waiting: &Waiting<V>,
+) -> Result<Registered<'me, DB, V>, CycleError<DB::DatabaseKey>> {
+    if other_id == runtime.id() || cfg!(feature = "single-threaded") {
+        return Err(runtime.cycle_error(database_key));
+    }
+    runtime.give_up_revalidation_if_worker();
+    let blocked_on = runtime.try_block_on(database_key, other_id)?;
+    let future = wait_channel(waiting, runtime.priority());
+    Ok(Registered { future, blocked_on })
+}
+
+/// Returns the channel where the threads of the given priority wait
+/// for the value of an `InProgress` slot, creating it if needed.
+pub(super) fn wait_channel<V>(
+    waiting: &Waiting<V>,
+    priority: Priority,
+) -> BlockingFuture<WaitResult<StampedValue<V>>> {
+    // The reader of this will have to acquire map
+    // lock, we don't need any particular ordering.
+    let mut waiting = waiting.lock();
+    match waiting.iter().find(|(p, _)| *p == priority) {
+        Some((_, (_, future))) => future.clone(),
+        None => {
+            let (future, promise) = BlockingFuture::new();
+            waiting.push((priority, (promise, future.clone())));
+            future
+        }
+    }
+}
+
+/// A runtime registered with the thread that computes a slot: the
+/// future where the value arrives, and the `BlockedOn` guard of the
+/// runtime, which stops blocking once it is dropped (even if the
+/// runtime unwinds before it waits).
+pub(super) struct Registered<'me, DB: Database, V> {
+    future: BlockingFuture<WaitResult<StampedValue<V>>>,
+    blocked_on: BlockedOn<'me, DB>,
+}
+
+/// Reaches `SchedulePoint::Block` when created, and
+/// `SchedulePoint::Unblock` when dropped, even if the thread unwinds
+/// while it is blocked.
+pub(super) struct Blocked<'me, DB: Database> {
+    runtime: &'me Runtime<DB>,
+}
+
+impl<'me, DB: Database> Blocked<'me, DB> {
+    pub(super) fn new(runtime: &'me Runtime<DB>) -> Self {
+        runtime.schedule(SchedulePoint::Block);
+        Blocked { runtime }
+    }
+}
+
+impl<DB: Database> Drop for Blocked<'_, DB> {
+    fn drop(&mut self) {
+        self.runtime.schedule(SchedulePoint::Unblock);
+    }
+}
+
+/// Blocks until the thread we registered with (see
+/// `register_with_in_progress_thread`) produces its value. Returns
+/// `None` if that thread yielded the slot instead, in which case the
+/// caller has to read the slot again. `dangling` checks whether that
+/// thread died without releasing the slot.
+pub(super) fn wait<DB: Database, V: Clone>(
+    db: &DB,
+    registered: Registered<'_, DB, V>,
+    database_key: &dyn Fn() -> DB::DatabaseKey,
+    dangling: &dyn Fn() -> Option<(String, RuntimeId)>,
+) -> Option<StampedValue<V>> {
+    let runtime = db.salsa_runtime();
+    let Registered { future, blocked_on } = registered;
+    let result = future.wait(runtime.liveness_check_interval(), || {
+        if let Some(dangling) = dangling() {
+            DanglingInProgress::new(vec![dangling]).throw();
+        }
+    });
+    std::mem::drop(blocked_on);
+    match result {
+        Some(WaitResult::Completed(value)) => Some(value),
+        Some(WaitResult::Yielded) => None,
+        None => propagate_panic(db, runtime, &database_key()),
+    }
+}
+
+/// Wakes the threads that blocked on a slot, once the runtime that
+/// was computing it released it: they receive `new_value`, or else
+/// retry if the runtime yielded, or else panic along with it.
+pub(super) fn wake_waiting<DB: Database, V: Clone>(
+    runtime: &Runtime<DB>,
+    waiting: Waiting<V>,
+    new_value: Option<&StampedValue<V>>,
+) {
+    // Foreground waiters are woken first.
+    let mut waiting = waiting.into_inner();
+    waiting.sort_by_key(|(priority, _)| *priority);
+
+    match new_value {
+        // If anybody has installed themselves in our "waiting"
+        // list, notify them that the value is available.
+        Some(new_value) => {
+            for (_, (promise, _)) in waiting {
+                promise.fulfil(WaitResult::Completed(new_value.clone()));
+            }
+        }
+
+        // If we are unwinding to yield to a foreground
+        // runtime, the waiters retry (and one of them
+        // computes the value).
+        None if runtime.is_yielding() => {
+            for (_, (promise, _)) in waiting {
+                promise.fulfil(WaitResult::Yielded);
+            }
+        }
+
+        // We have no value to send when we are panicking.
+        // Therefore, we need to drop the promises so that our
+        // panic propagates to those waiting on the futures.
+        None => std::mem::drop(waiting),
+    }
+}
+
+/// Invoked when the thread we were blocked on panicked before it
+/// could send us a value. If that happened because the revision was
+/// cancelled, we are cancelled too; otherwise, what happens depends
+/// on the policy of the database.
+pub(super) fn propagate_panic<DB: Database>(
+    db: &DB,
+    runtime: &Runtime<DB>,
+    database_key: &DB::DatabaseKey,
+) -> ! {
+    runtime.unwind_if_cancelled();
+    match db.propagated_panic_policy() {
+        PropagatedPanicPolicy::Diverge => db.on_propagated_panic(),
+        PropagatedPanicPolicy::Error => database_key.throw_query_panicked(),
+    }
+}
+
+impl<DB: Database> std::fmt::Debug for MemoInputs<DB> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoInputs::Tracked { inputs } => {
+                fmt.debug_struct("Tracked").field("inputs", inputs).finish()
+            }
+            MemoInputs::NoInputs => fmt.debug_struct("NoInputs").finish(),
+            MemoInputs::Untracked => fmt.debug_struct("Untracked").finish(),
+        }
+    }
+}
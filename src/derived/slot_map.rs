@@ -0,0 +1,147 @@
+use crate::key_map::{KeyHasher, KeyMap, KeyOrderFn};
+use indexmap::IndexMap;
+use parking_lot::RwLock;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of shards of a `SlotMap`; a power of two.
+const SHARDS: usize = 16;
+
+/// Map from the keys of a derived query to their slots.
+///
+/// The map is split into shards, each behind its own lock, with keys
+/// assigned to shards by their hash; threads that look up or insert
+/// different keys thus rarely contend for the same lock. Each entry
+/// records when it was inserted, so that the map is iterated in
+/// insertion order rather than in an order that depends on the hashes
+/// of the keys.
+///
+/// Queries without arguments have `()` as their key type; more
+/// generally, all the values of a zero-sized key type are equal. For
+/// such queries, there is at most one slot, which is stored inline
+/// rather than in a hash map.
+pub(super) struct SlotMap<K, V> {
+    single: RwLock<Option<(K, V)>>,
+    shards: Box<[Shard<K, V>]>,
+    hasher: KeyHasher,
+
+    /// The insertion index of the next entry.
+    next_index: AtomicUsize,
+
+    /// If set, `for_each` visits the entries in this order.
+    order: Option<KeyOrderFn<K>>,
+}
+
+/// A shard of a `SlotMap`, storing the insertion index of each entry
+/// along with its value.
+type Shard<K, V> = RwLock<KeyMap<K, (usize, V)>>;
+
+impl<K, V> SlotMap<K, V> {
+    pub(super) fn new(hasher: KeyHasher, order: Option<KeyOrderFn<K>>) -> Self {
+        SlotMap {
+            single: RwLock::new(None),
+            shards: (0..SHARDS)
+                .map(|_| RwLock::new(IndexMap::with_hasher(hasher.clone())))
+                .collect(),
+            hasher,
+            next_index: AtomicUsize::new(0),
+            order,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> SlotMap<K, V> {
+    fn is_single() -> bool {
+        std::mem::size_of::<K>() == 0
+    }
+
+    fn shard(&self, key: &K) -> &Shard<K, V> {
+        // The low bits of the hash pick the bucket within the shard's
+        // map, and the high bits are used by the map as tags, so pick
+        // the shard with bits in between.
+        &self.shards[(self.hasher.hash_one(key) >> 32) as usize % SHARDS]
+    }
+
+    pub(super) fn get(&self, key: &K) -> Option<V> {
+        if Self::is_single() {
+            self.single.read().as_ref().map(|(_, value)| value.clone())
+        } else {
+            self.shard(key)
+                .read()
+                .get(key)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    pub(super) fn get_or_insert_with(&self, key: &K, value: impl FnOnce() -> V) -> V
+    where
+        K: Clone,
+    {
+        if let Some(value) = self.get(key) {
+            return value;
+        }
+
+        if Self::is_single() {
+            let mut single = self.single.write();
+            single
+                .get_or_insert_with(|| (key.clone(), value()))
+                .1
+                .clone()
+        } else {
+            let mut shard = self.shard(key).write();
+            shard
+                .entry(key.clone())
+                .or_insert_with(|| (self.next_index.fetch_add(1, Ordering::Relaxed), value()))
+                .1
+                .clone()
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        if Self::is_single() {
+            self.single.read().iter().count()
+        } else {
+            self.shards.iter().map(|shard| shard.read().len()).sum()
+        }
+    }
+
+    /// Invokes `op` on each entry, without holding any lock: in the
+    /// order of the map if it has one, and in insertion order
+    /// otherwise.
+    pub(super) fn for_each(&self, mut op: impl FnMut(&K, &V))
+    where
+        K: Clone,
+    {
+        let mut entries = self.to_vec();
+        if let Some(order) = self.order {
+            entries.sort_by(|(a, _), (b, _)| order(a, b));
+        }
+        for (key, value) in &entries {
+            op(key, value);
+        }
+    }
+
+    /// Returns a copy of all entries, in insertion order.
+    pub(super) fn to_vec(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+    {
+        if let Some((key, value)) = &*self.single.read() {
+            return vec![(key.clone(), value.clone())];
+        }
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            entries.extend(
+                shard
+                    .read()
+                    .iter()
+                    .map(|(key, (index, value))| (*index, key.clone(), value.clone())),
+            );
+        }
+        entries.sort_by_key(|(index, _, _)| *index);
+        entries
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect()
+    }
+}
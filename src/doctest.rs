@@ -119,3 +119,81 @@ fn test_key_not_sync_db_not_send() {}
 /// }
 /// ```
 fn test_key_not_sync_db_not_sync() {}
+
+/// Test that a database that borrows from its environment cannot
+/// outlive it.
+///
+/// ```compile_fail,E0597
+/// #[salsa::query_group(ScopedStorage)]
+/// trait ScopedDatabase: salsa::Database {
+///     fn length(&self) -> usize;
+/// }
+///
+/// fn length(_db: &impl ScopedDatabase) -> usize {
+///     0
+/// }
+///
+/// #[salsa::database(ScopedStorage)]
+/// struct DatabaseImpl<'env> {
+///     runtime: salsa::Runtime<DatabaseImpl<'env>>,
+///     source: &'env str,
+/// }
+///
+/// impl<'env> salsa::Database for DatabaseImpl<'env> {
+///     fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl<'env>> {
+///         &self.runtime
+///     }
+/// }
+///
+/// fn outlive_environment() -> usize {
+///     let db = {
+///         let source = String::from("text");
+///         DatabaseImpl {
+///             runtime: Default::default(),
+///             source: &source,
+///         }
+///     };
+///     db.length()
+/// }
+/// ```
+fn test_db_does_not_outlive_environment() {}
+
+/// Test that an input cannot be set while a `ValueGuard` returned by
+/// `get_ref` still borrows a value from the database.
+///
+/// ```compile_fail,E0502
+/// use salsa::Database;
+///
+/// #[salsa::query_group(GuardStorage)]
+/// trait GuardDatabase: salsa::Database {
+///     #[salsa::input]
+///     fn input(&self) -> String;
+///
+///     fn length(&self) -> usize;
+/// }
+///
+/// fn length(db: &impl GuardDatabase) -> usize {
+///     db.input().len()
+/// }
+///
+/// #[salsa::database(GuardStorage)]
+/// #[derive(Default)]
+/// struct DatabaseImpl {
+///     runtime: salsa::Runtime<DatabaseImpl>,
+/// }
+///
+/// impl salsa::Database for DatabaseImpl {
+///     fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+///         &self.runtime
+///     }
+/// }
+///
+/// fn set_while_borrowed() {
+///     let mut db = DatabaseImpl::default();
+///     db.set_input(String::from("text"));
+///     let length = db.query(LengthQuery).get_ref(());
+///     db.set_input(String::new());
+///     assert_eq!(*length, 4);
+/// }
+/// ```
+fn test_no_set_while_value_borrowed() {}
@@ -0,0 +1,149 @@
+//! A query group for the documents that an editor has open, as a
+//! language server sees them, and helpers to apply the edits that the
+//! editor sends. Requires the `documents` feature of salsa.
+//!
+//! Include `salsa::documents::DocumentStorage` in your
+//! `#[salsa::database]` and forward the notifications of the editor:
+//! `didOpen` to `open_document`, `didChange` to `change_document` and
+//! `didClose` to `close_document`. Queries then read the documents
+//! through `DocumentDatabase`. Each of these functions creates a
+//! single new revision; to apply several notifications at once, wrap
+//! them in `Database::transaction`.
+
+use crate::Database;
+use std::sync::Arc;
+
+/// A position in a document: a zero-based line, and a zero-based
+/// offset within that line in UTF-16 code units, as in the Language
+/// Server Protocol.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// The line.
+    pub line: u32,
+
+    /// The offset within the line, in UTF-16 code units.
+    pub character: u32,
+}
+
+/// A range in a document, from `start` (inclusive) to `end`
+/// (exclusive).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Range {
+    /// The start of the range.
+    pub start: Position,
+
+    /// The end of the range.
+    pub end: Position,
+}
+
+/// A change to the text of a document, like the Language Server
+/// Protocol's `TextDocumentContentChangeEvent`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ContentChange {
+    /// The range that is replaced by `text`, or `None` if `text` is the
+    /// new text of the whole document.
+    pub range: Option<Range>,
+
+    /// The new text of the range.
+    pub text: String,
+}
+
+/// The documents that are (or were) open in the editor, by URI. Only
+/// documents that were opened (see `open_document`) can be read.
+#[salsa::query_group(DocumentStorage)]
+pub trait DocumentDatabase: Database {
+    /// The text of the document; for a closed document, the text it
+    /// had when it was closed.
+    #[salsa::input]
+    fn document_text(&self, uri: String) -> Arc<String>;
+
+    /// The version of the document, as given by the editor.
+    #[salsa::input]
+    fn document_version(&self, uri: String) -> i32;
+
+    /// Whether the document is currently open.
+    #[salsa::input]
+    fn document_is_open(&self, uri: String) -> bool;
+}
+
+/// Opens the document `uri`, with the given version and text.
+pub fn open_document(db: &mut impl DocumentDatabase, uri: String, version: i32, text: String) {
+    db.transaction(|db| {
+        db.set_document_text(uri.clone(), Arc::new(text));
+        db.set_document_version(uri.clone(), version);
+        db.set_document_is_open(uri, true);
+    });
+}
+
+/// Applies `changes` to the open document `uri`, one after the other,
+/// and sets its version to `version`.
+///
+/// # Panics
+///
+/// If the document is not open.
+pub fn change_document(
+    db: &mut impl DocumentDatabase,
+    uri: String,
+    version: i32,
+    changes: &[ContentChange],
+) {
+    if db.maybe_document_is_open(uri.clone()) != Some(true) {
+        panic!("document `{}` is not open", uri);
+    }
+    let mut text = String::clone(&db.document_text(uri.clone()));
+    for change in changes {
+        apply_change(&mut text, change);
+    }
+
+    db.transaction(|db| {
+        db.set_document_text(uri.clone(), Arc::new(text));
+        db.set_document_version(uri, version);
+    });
+}
+
+/// Closes the document `uri`. Its text is kept, so queries that read it
+/// are only re-executed if they also check whether it is open.
+pub fn close_document(db: &mut impl DocumentDatabase, uri: String) {
+    db.set_document_is_open(uri, false);
+}
+
+/// Applies `change` to `text`. Positions beyond the end of a line
+/// refer to the end of that line, and positions beyond the last line
+/// to the end of the text, as the Language Server Protocol specifies.
+pub fn apply_change(text: &mut String, change: &ContentChange) {
+    match change.range {
+        Some(range) => {
+            let start = offset_of(text, range.start);
+            let end = offset_of(text, range.end).max(start);
+            text.replace_range(start..end, &change.text);
+        }
+        None => {
+            text.clear();
+            text.push_str(&change.text);
+        }
+    }
+}
+
+/// Returns the byte offset of `position` in `text`.
+pub fn offset_of(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(index) => line_start += index + 1,
+            None => return text.len(),
+        }
+    }
+    let line_end = match text[line_start..].find('\n') {
+        Some(index) => line_start + index,
+        None => text.len(),
+    };
+
+    let mut character = 0;
+    for (index, c) in text[line_start..line_end].char_indices() {
+        if character >= position.character {
+            return line_start + index;
+        }
+        character += c.len_utf16() as u32;
+    }
+    line_end
+}
@@ -15,6 +15,12 @@
 /// frequently editing. Medium or high durabilities are used for
 /// configuration, the source from library crates, or other things
 /// that are unlikely to be edited.
+///
+/// When three levels are not enough, `Durability::user` gives access
+/// to all `Durability::LEVELS` levels, ordered from least to most
+/// durable: `LOW` is `user(0)`, `MEDIUM` is `user(1)` and `HIGH` is
+/// the topmost level. This lets you model tiers like "workspace file
+/// < dependency source < sysroot < compiler version".
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Durability(u8);
 
@@ -33,17 +39,47 @@ impl Durability {
     /// common usage.
     ///
     /// Example: the standard library or something from crates.io
-    pub const HIGH: Durability = Durability(2);
+    pub const HIGH: Durability = Durability(Self::LEVELS as u8 - 1);
+
+    /// Number of available durability levels.
+    pub const LEVELS: usize = 16;
+
+    /// The durability at the given level, where `0` is the least
+    /// durable level and `LEVELS - 1` the most durable one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is not less than `Durability::LEVELS`.
+    pub const fn user(level: u8) -> Durability {
+        assert!(
+            (level as usize) < Self::LEVELS,
+            "durability level out of range"
+        );
+        Durability(level)
+    }
+
+    /// The level of this durability; the inverse of `Durability::user`.
+    pub fn level(self) -> u8 {
+        self.0
+    }
 
     /// The maximum possible durability; equivalent to HIGH but
-    /// "conceptually" distinct (i.e., if we add more durability
-    /// levels, this could change).
+    /// "conceptually" distinct.
     pub(crate) const MAX: Durability = Self::HIGH;
 
     /// Number of durability levels.
-    pub(crate) const LEN: usize = 3;
+    pub(crate) const LEN: usize = Self::LEVELS;
 
     pub(crate) fn index(self) -> usize {
         self.0 as usize
     }
+
+    #[cfg(feature = "persist")]
+    pub(crate) fn from_index(index: usize) -> Option<Durability> {
+        if index < Self::LEN {
+            Some(Durability(index as u8))
+        } else {
+            None
+        }
+    }
 }
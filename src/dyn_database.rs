@@ -0,0 +1,71 @@
+use crate::Database;
+use crate::Durability;
+use crate::Revision;
+
+/// The part of `Database` that can be used through a trait object.
+/// `Database` itself cannot be (`salsa_runtime` returns a
+/// `Runtime<Self>`, and some of its methods are generic), and neither
+/// can query groups that extend it; query groups that extend
+/// `DynDatabase` instead can, so that their query functions (and
+/// helpers) can take a `&dyn MyGroup` rather than a `&impl MyGroup`:
+///
+/// ```ignore
+/// #[salsa::query_group(ParserStorage)]
+/// trait Parser: salsa::DynDatabase {
+///     #[salsa::input]
+///     fn source_text(&self, name: String) -> String;
+///
+///     fn line_count(&self, name: String) -> usize;
+/// }
+///
+/// fn line_count(db: &dyn Parser, name: String) -> usize {
+///     db.source_text(name).lines().count()
+/// }
+/// ```
+///
+/// Such query functions are compiled once, rather than once per
+/// database type, and reads through the trait object are tracked like
+/// any other. Every `Database` implements `DynDatabase`.
+pub trait DynDatabase {
+    /// The current revision; see `Runtime::current_revision`.
+    fn current_revision(&self) -> Revision;
+
+    /// Reports that the query depends on some state unknown to salsa;
+    /// see `Runtime::report_untracked_read`.
+    fn report_untracked_read(&self);
+
+    /// Reports that the query depends on some state unknown to salsa,
+    /// which only changes along with the inputs of durability
+    /// `durability`; see `Runtime::report_synthetic_read`.
+    fn report_synthetic_read(&self, durability: Durability);
+
+    /// True if a new revision is pending, and this one canceled; see
+    /// `Runtime::is_current_revision_canceled`.
+    fn is_current_revision_canceled(&self) -> bool;
+
+    /// Unwinds if the current revision was canceled; see
+    /// `Runtime::unwind_if_cancelled`.
+    fn unwind_if_cancelled(&self);
+}
+
+impl<DB: Database> DynDatabase for DB {
+    fn current_revision(&self) -> Revision {
+        self.salsa_runtime().current_revision()
+    }
+
+    fn report_untracked_read(&self) {
+        self.salsa_runtime().report_untracked_read()
+    }
+
+    fn report_synthetic_read(&self, durability: Durability) {
+        self.salsa_runtime().report_synthetic_read(durability)
+    }
+
+    fn is_current_revision_canceled(&self) -> bool {
+        self.salsa_runtime().is_current_revision_canceled()
+    }
+
+    fn unwind_if_cancelled(&self) {
+        self.salsa_runtime().unwind_if_cancelled()
+    }
+}
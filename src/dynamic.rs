@@ -0,0 +1,197 @@
+//! Invoking queries by name, with keys and values converted from and
+//! to JSON. This is meant for debuggers, REPLs and similar tools that
+//! do not know the query types at compile time. See
+//! `Database::query_by_name` for the user-facing entry point.
+//!
+//! Only queries marked with `#[salsa::dynamic]` can be invoked. For
+//! each such query, the `query_group` macro generates a shim that
+//! uses the helpers below to convert the arguments and the result.
+//!
+//! Queries can also be registered at runtime, without declaring them
+//! in a query group; see `DynamicQueryDatabase`.
+
+use crate::Database;
+use parking_lot::RwLock;
+use rustc_hash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+/// The error returned by `Database::query_by_name`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryByNameError {
+    /// No query marked `#[salsa::dynamic]` has the given name.
+    UnknownQuery(String),
+
+    /// The arguments could not be converted to the keys of the query.
+    InvalidArguments {
+        /// Name of the query.
+        query: &'static str,
+
+        /// Describes what went wrong.
+        message: String,
+    },
+
+    /// The value of the query could not be converted to JSON.
+    InvalidValue {
+        /// Name of the query.
+        query: &'static str,
+
+        /// Describes what went wrong.
+        message: String,
+    },
+}
+
+impl fmt::Display for QueryByNameError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryByNameError::UnknownQuery(name) => write!(fmt, "unknown query `{}`", name),
+            QueryByNameError::InvalidArguments { query, message } => {
+                write!(fmt, "invalid arguments for query `{}`: {}", query, message)
+            }
+            QueryByNameError::InvalidValue { query, message } => {
+                write!(
+                    fmt,
+                    "cannot convert value of query `{}`: {}",
+                    query, message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryByNameError {}
+
+/// Checks that `args` is an array of `count` arguments and returns
+/// them.
+pub fn dynamic_args(
+    query: &'static str,
+    args: &Value,
+    count: usize,
+) -> Result<std::vec::IntoIter<Value>, QueryByNameError> {
+    match args {
+        Value::Array(args) if args.len() == count => Ok(args.clone().into_iter()),
+        _ => Err(QueryByNameError::InvalidArguments {
+            query,
+            message: format!(
+                "expected an array of {} argument(s), found `{}`",
+                count, args
+            ),
+        }),
+    }
+}
+
+/// Converts the next argument to a key of the query.
+pub fn dynamic_arg<K: DeserializeOwned>(
+    query: &'static str,
+    args: &mut std::vec::IntoIter<Value>,
+) -> Result<K, QueryByNameError> {
+    let arg = args.next().expect("number of arguments already checked");
+    serde_json::from_value(arg).map_err(|error| QueryByNameError::InvalidArguments {
+        query,
+        message: error.to_string(),
+    })
+}
+
+/// Converts the value of the query to JSON.
+pub fn dynamic_value<V: Serialize>(
+    query: &'static str,
+    value: &V,
+) -> Result<Value, QueryByNameError> {
+    serde_json::to_value(value).map_err(|error| QueryByNameError::InvalidValue {
+        query,
+        message: error.to_string(),
+    })
+}
+
+/// The function of a dynamic query, given the database (to read other
+/// queries through) and the key of the query; see
+/// `Database::register_dynamic_query`.
+#[cfg(not(feature = "single-threaded"))]
+pub type DynamicQueryFn = Arc<dyn Fn(&dyn DynamicContext, &Value) -> Value + Send + Sync>;
+
+/// The function of a dynamic query, given the database (to read other
+/// queries through) and the key of the query; see
+/// `Database::register_dynamic_query`.
+#[cfg(feature = "single-threaded")]
+pub type DynamicQueryFn = Arc<dyn Fn(&dyn DynamicContext, &Value) -> Value>;
+
+/// The queries registered with
+/// `Database::register_dynamic_query`, by name. The
+/// runtime keeps these, so that they are shared with snapshots.
+#[derive(Default)]
+pub(crate) struct DynamicQueries {
+    functions: RwLock<FxHashMap<String, DynamicQueryFn>>,
+}
+
+impl DynamicQueries {
+    pub(crate) fn get(&self, name: &str) -> Option<DynamicQueryFn> {
+        self.functions.read().get(name).cloned()
+    }
+
+    pub(crate) fn insert(&self, name: String, function: DynamicQueryFn) {
+        self.functions.write().insert(name, function);
+    }
+}
+
+/// The database as seen by the functions of dynamic queries, which do
+/// not know its type. Reads through it are tracked as dependencies,
+/// like any other.
+pub trait DynamicContext {
+    /// Invokes the query named `name` on `key`, which is either a
+    /// query registered with `register_dynamic_query` (whose key can
+    /// be any JSON value) or one marked `#[salsa::dynamic]` (whose key
+    /// is an array of arguments, as for `Database::query_by_name`).
+    fn invoke(&self, name: &str, key: Value) -> Result<Value, QueryByNameError>;
+}
+
+/// A query group whose queries are registered at runtime rather than
+/// declared, so that (e.g.) analyses loaded from plugins take part in
+/// incremental computation like any other query: include
+/// `salsa::DynamicQueryStorage` in your `#[salsa::database]`, register
+/// queries with `Database::register_dynamic_query`, and invoke them with
+/// `DynamicContext::invoke`.
+///
+/// The keys and values of dynamic queries are JSON values. As for
+/// other queries, the function of a dynamic query must be
+/// deterministic: it should only read the database passed to it, and
+/// queries that depend on it are only re-executed if its value
+/// changed.
+#[salsa::query_group(DynamicQueryStorage)]
+pub trait DynamicQueryDatabase: Database {
+    /// How many times a query named `name` was registered; queries
+    /// read this to be re-executed when it is registered anew.
+    #[salsa::input]
+    fn dynamic_query_generation(&self, name: String) -> u64;
+
+    /// Executes the dynamic query `name` on `key`, a JSON value
+    /// serialized to a string (so that it can be hashed).
+    #[salsa::invoke(execute_dynamic_query)]
+    fn dynamic_query(&self, name: String, key: String) -> Value;
+}
+
+fn execute_dynamic_query(db: &impl DynamicQueryDatabase, name: String, key: String) -> Value {
+    if db.maybe_dynamic_query_generation(name.clone()).is_none() {
+        panic!("no dynamic query named `{}` is registered", name);
+    }
+    let function = db
+        .salsa_runtime()
+        .dynamic_queries()
+        .get(&name)
+        .expect("registered dynamic queries are never removed");
+    let key: Value = serde_json::from_str(&key).expect("invalid key of dynamic query");
+    function(db, &key)
+}
+
+impl<DB: DynamicQueryDatabase> DynamicContext for DB {
+    fn invoke(&self, name: &str, key: Value) -> Result<Value, QueryByNameError> {
+        // Reading the generation also makes us depend on the query
+        // being registered, if it is not yet.
+        match self.maybe_dynamic_query_generation(name.to_string()) {
+            Some(_) => Ok(self.dynamic_query(name.to_string(), key.to_string())),
+            None => self.query_by_name(name, key),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The error of a `#[salsa::fallible]` query, which dereferences to the
+/// error that the query function returned.
+///
+/// The error is shared rather than cloned, so it need not implement
+/// `Clone`. How two errors compare decides whether a new error
+/// backdates the value: by default, errors are compared by value
+/// (which requires `E: Eq`), while the errors of
+/// `#[salsa::fallible(no_backdate)]` queries are never equal, so that
+/// their dependents are re-executed whenever the error is recomputed.
+pub struct Failed<E> {
+    error: Arc<E>,
+    eq: Option<fn(&E, &E) -> bool>,
+}
+
+impl<E> Failed<E> {
+    /// Wraps `error`, which is only ever equal to itself (and its
+    /// clones).
+    pub fn new(error: E) -> Self {
+        Failed {
+            error: Arc::new(error),
+            eq: None,
+        }
+    }
+
+    /// Wraps `error`, which is equal to the errors that compare equal
+    /// to it and were also created with `comparable`.
+    pub fn comparable(error: E) -> Self
+    where
+        E: Eq,
+    {
+        Failed {
+            error: Arc::new(error),
+            eq: Some(E::eq),
+        }
+    }
+
+    /// Returns the error.
+    pub fn error(&self) -> &E {
+        &self.error
+    }
+}
+
+impl<E> Clone for Failed<E> {
+    fn clone(&self) -> Self {
+        Failed {
+            error: self.error.clone(),
+            eq: self.eq,
+        }
+    }
+}
+
+impl<E> Deref for Failed<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.error
+    }
+}
+
+impl<E> PartialEq for Failed<E> {
+    fn eq(&self, other: &Self) -> bool {
+        if Arc::ptr_eq(&self.error, &other.error) {
+            return true;
+        }
+        match (self.eq, other.eq) {
+            (Some(eq), Some(_)) => eq(&self.error, &other.error),
+            _ => false,
+        }
+    }
+}
+
+impl<E> Eq for Failed<E> {}
+
+impl<E: fmt::Debug> fmt::Debug for Failed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.error, fmt)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Failed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.error, fmt)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Failed<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
@@ -0,0 +1,182 @@
+//! A query group with the contents of the file system as inputs, and a
+//! watcher that keeps them up to date. Requires the `fs` feature of
+//! salsa.
+//!
+//! Include `salsa::fs::FileSystemStorage` in your `#[salsa::database]`
+//! and load the directories you are interested in, either once with
+//! `load_path` or with a `FileWatcher`, which also reloads whatever
+//! changes on disk afterwards. Queries then read files through
+//! `FileSystemDatabase`, and are re-executed when those files change.
+
+use crate::{Database, Durability};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+/// The contents of the file system. Only paths that were loaded (see
+/// `load_path` and `FileWatcher::watch`) can be read.
+#[salsa::query_group(FileSystemStorage)]
+pub trait FileSystemDatabase: Database {
+    /// The text of the file at `path`, or `None` if it is not a file
+    /// or could not be read as UTF-8.
+    #[salsa::input]
+    fn file_text(&self, path: PathBuf) -> Option<Arc<String>>;
+
+    /// Whether a file or directory exists at `path`.
+    #[salsa::input]
+    fn file_exists(&self, path: PathBuf) -> bool;
+
+    /// The paths of the entries of the directory at `path`, sorted; empty
+    /// if it is not a directory.
+    #[salsa::input]
+    fn dir_entries(&self, path: PathBuf) -> Arc<Vec<PathBuf>>;
+}
+
+/// Sets the inputs for `path` (and, if it is a directory, for all the
+/// paths within it) from the file system, with the given durability.
+/// Inputs whose value did not change are left alone, so reloading a
+/// path that did not change does not create a new revision.
+///
+/// If `path` was a directory and no longer exists, the paths that were
+/// loaded within it are marked as missing as well.
+pub fn load_path(db: &mut impl FileSystemDatabase, path: &Path, durability: Durability) {
+    let old_entries = db.maybe_dir_entries(path.to_path_buf());
+    let entries = load_entry(db, path, durability);
+    for entry in entries.iter() {
+        load_path(db, entry, durability);
+    }
+    if let Some(old_entries) = old_entries {
+        for entry in old_entries.iter().filter(|entry| !entries.contains(entry)) {
+            load_path(db, entry, durability);
+        }
+    }
+}
+
+/// Sets the inputs for `path` alone; returns its directory entries.
+fn load_entry(
+    db: &mut impl FileSystemDatabase,
+    path: &Path,
+    durability: Durability,
+) -> Arc<Vec<PathBuf>> {
+    let exists = path.exists();
+    let text = if path.is_file() {
+        std::fs::read_to_string(path).ok().map(Arc::new)
+    } else {
+        None
+    };
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(path) {
+        Ok(read_dir) => read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    let entries = Arc::new(entries);
+
+    let path = path.to_path_buf();
+    if db.maybe_file_text(path.clone()) != Some(text.clone()) {
+        db.set_file_text_with_durability(path.clone(), text, durability);
+    }
+    if db.maybe_file_exists(path.clone()) != Some(exists) {
+        db.set_file_exists_with_durability(path.clone(), exists, durability);
+    }
+    if db.maybe_dir_entries(path.clone()) != Some(entries.clone()) {
+        db.set_dir_entries_with_durability(path, entries.clone(), durability);
+    }
+    entries
+}
+
+/// Watches directories for changes (using the `notify` crate) and
+/// applies them to the inputs of a `FileSystemDatabase`.
+///
+/// Each watched directory has a durability, which its files are set
+/// with: typically `Durability::LOW` for the files that the user
+/// edits, and `Durability::HIGH` for libraries or toolchain files that
+/// rarely change. Changes are only applied when `apply_changes` is
+/// invoked, as that needs mutable access to the database; call it
+/// from the application's event loop, or whenever you are about to
+/// run queries.
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    roots: Vec<(PathBuf, Durability)>,
+}
+
+impl FileWatcher {
+    /// Creates a watcher that does not watch anything yet.
+    pub fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        Ok(FileWatcher {
+            watcher: notify::recommended_watcher(sender)?,
+            events,
+            roots: Vec::new(),
+        })
+    }
+
+    /// Loads `root` (see `load_path`) and watches it for changes from
+    /// then on. Paths within `root` get the given durability, unless
+    /// they are also within a more deeply nested root.
+    pub fn watch(
+        &mut self,
+        db: &mut impl FileSystemDatabase,
+        root: impl Into<PathBuf>,
+        durability: Durability,
+    ) -> notify::Result<()> {
+        let root = root.into();
+        self.watcher.watch(&root, RecursiveMode::Recursive)?;
+        load_path(db, &root, durability);
+        self.roots.push((root, durability));
+        Ok(())
+    }
+
+    /// Applies the changes that happened since the last call (or since
+    /// the roots were loaded) to the inputs of `db`, reloading the
+    /// changed paths and the directories containing them. Returns the
+    /// number of events that were processed.
+    ///
+    /// If the watcher reports an error (for example, because events
+    /// were lost), all roots are reloaded.
+    pub fn apply_changes(&self, db: &mut impl FileSystemDatabase) -> usize {
+        let mut count = 0;
+        while let Ok(event) = self.events.try_recv() {
+            count += 1;
+            let event = match event {
+                Ok(event) => event,
+                Err(_) => {
+                    for (root, durability) in &self.roots {
+                        load_path(db, root, *durability);
+                    }
+                    continue;
+                }
+            };
+            if let EventKind::Access(_) = event.kind {
+                continue;
+            }
+            for path in &event.paths {
+                let durability = match self.durability_of(path) {
+                    Some(durability) => durability,
+                    None => continue,
+                };
+                load_path(db, path, durability);
+                if let Some(parent) = path.parent() {
+                    if let Some(durability) = self.durability_of(parent) {
+                        load_entry(db, parent, durability);
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Returns the durability of the innermost root containing `path`,
+    /// if any.
+    fn durability_of(&self, path: &Path) -> Option<Durability> {
+        self.roots
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|&(_, durability)| durability)
+    }
+}
@@ -1,21 +1,44 @@
-use crate::debug::TableEntry;
+#[cfg(feature = "persist")]
+use crate::artifact::{self, HashedInput};
+use crate::debug::{self, SlotDump, SlotState, TableEntry};
 use crate::dependency::DatabaseSlot;
+#[cfg(feature = "persist")]
+use crate::dependency::Dependency;
 use crate::durability::Durability;
-use crate::plumbing::CycleDetected;
+use crate::key_map::{self, KeyMap};
+use crate::logging::debug;
+use crate::opaque::{debug_key, debug_value};
+#[cfg(feature = "persist")]
+use crate::persist::{
+    self, LoadedSlots, PersistQueryStorageOps, RevisionMap, RevisionSet, SavedSlots,
+};
+use crate::plumbing;
+use crate::plumbing::GetQueryTable;
+use crate::plumbing::HasQueryGroup;
 use crate::plumbing::InputQueryStorageOps;
+use crate::plumbing::JournalWriteId;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
 use crate::revision::Revision;
+use crate::revision_log::InputChange;
+use crate::runtime::DatabaseWriteLockGuard;
 use crate::runtime::StampedValue;
+use crate::CycleError;
 use crate::Database;
 use crate::Event;
 use crate::EventKind;
+use crate::MemoryReport;
 use crate::Query;
+use crate::QueryStorageStats;
+use crate::SweepPolicy;
 use crate::SweepStrategy;
-use log::debug;
-use parking_lot::RwLock;
+use crate::ValueGuard;
+use indexmap::map::Entry;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use rustc_hash::FxHashMap;
-use std::collections::hash_map::Entry;
+#[cfg(feature = "persist")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 /// Input queries store the result plus a list of the other queries
@@ -26,16 +49,68 @@ where
     Q: Query<DB>,
     DB: Database,
 {
-    slots: RwLock<FxHashMap<Q::Key, Arc<Slot<DB, Q>>>>,
+    slots: RwLock<KeyMap<Q::Key, Arc<Slot<DB, Q>>>>,
+
+    /// The writes recorded in the journal of the runtime; see
+    /// `Runtime::set_journal_capacity`.
+    journal: Mutex<FxHashMap<JournalWriteId, JournaledWrite<DB, Q>>>,
+
+    /// The writes made while recording, with their sequence numbers
+    /// and revisions; see `salsa::replay`.
+    recorded: Mutex<Vec<RecordedInputWrite<DB, Q>>>,
+}
+
+/// A write to an input, with the value (and durability) it replaced
+/// and the one it set; `None` if there was no value.
+struct JournaledWrite<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    key: Q::Key,
+    database_key: DB::DatabaseKey,
+    old: JournaledValue<Q::Value>,
+    new: JournaledValue<Q::Value>,
 }
 
+type JournaledValue<V> = Option<(V, Durability)>;
+
+/// A write to an input that was recorded, to be replayed on another
+/// database; see `salsa::replay`.
+struct InputWrite<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    key: Q::Key,
+    value: JournaledValue<Q::Value>,
+    phantom: PhantomData<fn() -> DB>,
+}
+
+type RecordedInputWrite<DB, Q> = (u64, Revision, InputWrite<DB, Q>);
+
 struct Slot<DB, Q>
 where
     Q: Query<DB>,
     DB: Database,
 {
     key: Q::Key,
-    stamped_value: RwLock<StampedValue<Q::Value>>,
+
+    /// The value of the input, or `None` if it has been removed (or
+    /// was read with `get_maybe` before ever being set).
+    stamped_value: RwLock<StampedValue<Option<Q::Value>>>,
+
+    /// For `#[salsa::multi_version]` queries, the values that were
+    /// replaced while a snapshot pinned at a revision in which they
+    /// were current was alive. Always locked after `stamped_value`.
+    old_values: Mutex<Vec<OldValue<Q::Value>>>,
+}
+
+/// A value of an input, along with the revision in which it was
+/// replaced; see `Slot::old_values`.
+struct OldValue<V> {
+    stamped_value: StampedValue<Option<V>>,
+    replaced_at: Revision,
 }
 
 impl<DB, Q> std::panic::RefUnwindSafe for InputStorage<DB, Q>
@@ -54,7 +129,9 @@ where
 {
     fn default() -> Self {
         InputStorage {
-            slots: Default::default(),
+            slots: RwLock::new(key_map::new_key_map::<DB, Q, _>()),
+            journal: Default::default(),
+            recorded: Default::default(),
         }
     }
 }
@@ -67,17 +144,121 @@ where
     fn slot(&self, key: &Q::Key) -> Option<Arc<Slot<DB, Q>>> {
         self.slots.read().get(key).cloned()
     }
+
+    /// Returns the slots, in the order of their keys if the query has
+    /// one (see `Query::KEY_ORDER`).
+    fn ordered_slots(&self) -> Vec<Arc<Slot<DB, Q>>> {
+        let mut slots: Vec<_> = self.slots.read().values().cloned().collect();
+        key_map::sort_by_key::<DB, Q, _>(&mut slots, |slot| &slot.key);
+        slots
+    }
+
+    /// Records a write of `key` in the journal and in the recording in
+    /// progress, if any; `values` gives the old and new values.
+    fn record_write(
+        &self,
+        guard: &DatabaseWriteLockGuard<'_, DB>,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        values: impl FnOnce() -> (JournaledValue<Q::Value>, JournaledValue<Q::Value>),
+    ) {
+        let journal_write = guard.journal_write();
+        let seq = guard.record_write();
+        if journal_write.is_none() && seq.is_none() {
+            return;
+        }
+        let (old, new) = values();
+        if let Some(seq) = seq {
+            let write = InputWrite {
+                key: key.clone(),
+                value: new.clone(),
+                phantom: PhantomData,
+            };
+            self.recorded
+                .lock()
+                .push((seq, guard.new_revision(), write));
+        }
+        if let Some(write) = journal_write {
+            self.journal.lock().insert(
+                write,
+                JournaledWrite {
+                    key: key.clone(),
+                    database_key: database_key.clone(),
+                    old,
+                    new,
+                },
+            );
+        }
+    }
 }
 
-impl<DB, Q> QueryStorageOps<DB, Q> for InputStorage<DB, Q>
+impl<DB, Q> Slot<DB, Q>
 where
     Q: Query<DB>,
     DB: Database,
 {
-    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleDetected> {
-        let slot = self.slot(key).unwrap_or_else(|| {
-            panic!("no value set for {:?}({:?})", Q::default(), key)
+    /// Invoked with the write lock on `stamped_value` held, before
+    /// replacing `old` in revision `replaced_at`: keeps the old value
+    /// if a pinned snapshot may still read it. Old values that no
+    /// pinned snapshot can read anymore are dropped.
+    fn keep_old_value(&self, db: &DB, old: &StampedValue<Option<Q::Value>>, replaced_at: Revision) {
+        if !Q::MULTI_VERSION {
+            return;
+        }
+        let runtime = db.salsa_runtime();
+        let mut old_values = self.old_values.lock();
+        old_values.retain(|old_value| {
+            runtime.is_pinned_within(old_value.stamped_value.changed_at, old_value.replaced_at)
         });
+        if runtime.is_pinned_within(old.changed_at, replaced_at) {
+            old_values.push(OldValue {
+                stamped_value: old.clone(),
+                replaced_at,
+            });
+        }
+    }
+
+    /// Returns the value as of `revision`, which a snapshot is pinned
+    /// at; the value is absent if the key was not set then.
+    fn stamped_value_at(&self, revision: Revision) -> StampedValue<Option<Q::Value>> {
+        let stamped_value = self.stamped_value.read();
+        if stamped_value.changed_at <= revision {
+            return stamped_value.clone();
+        }
+        self.old_values
+            .lock()
+            .iter()
+            .find(|old_value| {
+                old_value.stamped_value.changed_at <= revision && revision < old_value.replaced_at
+            })
+            .map(|old_value| old_value.stamped_value.clone())
+            .unwrap_or(StampedValue {
+                value: None,
+                durability: Durability::LOW,
+                changed_at: Revision::start(),
+            })
+    }
+}
+
+fn no_value<DB, Q>(key: &Q::Key) -> !
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    panic!(
+        "no value set for {:?}({:?})",
+        Q::default(),
+        debug_key::<DB, Q>(key)
+    )
+}
+
+impl<DB, Q> QueryStorageOps<DB, Q> for InputStorage<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        let slot = self.slot(key).unwrap_or_else(|| no_value::<DB, Q>(key));
 
         let StampedValue {
             value,
@@ -85,30 +266,127 @@ where
             changed_at,
         } = slot.stamped_value.read().clone();
 
+        let value = value.unwrap_or_else(|| {
+            panic!(
+                "value for {:?}({:?}) was removed",
+                Q::default(),
+                debug_key::<DB, Q>(key)
+            )
+        });
+
         db.salsa_runtime()
             .report_query_read(slot, durability, changed_at);
 
         Ok(value)
     }
 
+    fn try_fetch_at(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        revision: Revision,
+    ) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        if !Q::MULTI_VERSION {
+            plumbing::not_multi_version::<DB, Q>(key, revision);
+        }
+        let slot = self.slot(key).unwrap_or_else(|| no_value::<DB, Q>(key));
+
+        let StampedValue {
+            value,
+            durability,
+            changed_at,
+        } = slot.stamped_value_at(revision);
+
+        let value = value.unwrap_or_else(|| no_value::<DB, Q>(key));
+
+        db.salsa_runtime()
+            .report_query_read(slot, durability, changed_at);
+
+        Ok(value)
+    }
+
+    fn try_fetch_ref<'a>(
+        &'a self,
+        db: &'a DB,
+        key: &Q::Key,
+    ) -> Result<ValueGuard<'a, Q::Value>, CycleError<DB::DatabaseKey>> {
+        let slot = self.slot(key).unwrap_or_else(|| no_value::<DB, Q>(key));
+
+        let (durability, changed_at) = {
+            let stamped_value = slot.stamped_value.read();
+            if stamped_value.value.is_none() {
+                panic!(
+                    "value for {:?}({:?}) was removed",
+                    Q::default(),
+                    debug_key::<DB, Q>(key)
+                );
+            }
+            (stamped_value.durability, stamped_value.changed_at)
+        };
+
+        db.salsa_runtime()
+            .report_query_read(slot.clone(), durability, changed_at);
+
+        // Safety: the closure only uses `slot` to lock it. Setting the
+        // value requires `&mut DB`, so the guard cannot observe a
+        // different value than the one whose read we just reported.
+        let guard = unsafe {
+            ValueGuard::borrowed(slot, None, |slot| {
+                RwLockReadGuard::try_map(slot.stamped_value.read(), |stamped_value| {
+                    stamped_value.value.as_ref()
+                })
+                .ok()
+            })
+        };
+        Ok(guard.unwrap())
+    }
+
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        match self.slot(key) {
+            Some(slot) => slot.maybe_changed_since(db, revision),
+            None => true,
+        }
+    }
+
+    fn peek(&self, _db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        let slot = self.slot(key)?;
+        let stamped_value = slot.stamped_value.read();
+        stamped_value.value.clone()
+    }
+
     fn durability(&self, _db: &DB, key: &Q::Key) -> Durability {
         match self.slot(key) {
-            Some(slot) => slot.stamped_value.read().durability,
-            None => panic!("no value set for {:?}({:?})", Q::default(), key),
+            Some(slot) => {
+                let stamped_value = slot.stamped_value.read();
+                if stamped_value.value.is_none() {
+                    no_value::<DB, Q>(key);
+                }
+                stamped_value.durability
+            }
+            None => no_value::<DB, Q>(key),
         }
     }
 
+    fn sweep_keys(
+        &self,
+        _db: &DB,
+        _keys: &mut dyn Iterator<Item = Q::Key>,
+        _strategy: SweepStrategy,
+    ) {
+    }
+
     fn entries<C>(&self, _db: &DB) -> C
     where
         C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
     {
-        let slots = self.slots.read();
-        slots
-            .values()
-            .map(|slot| {
-                TableEntry::new(
-                    slot.key.clone(),
-                    Some(slot.stamped_value.read().value.clone()),
+        self.ordered_slots()
+            .iter()
+            .filter_map(|slot| {
+                let stamped_value = slot.stamped_value.read();
+                let value = stamped_value.value.clone()?;
+                Some(
+                    TableEntry::new(slot.key.clone(), Some(value))
+                        .with_stamp(stamped_value.changed_at, stamped_value.durability),
                 )
             })
             .collect()
@@ -116,18 +394,243 @@ where
 }
 
 impl<DB, Q> QueryStorageMassOps<DB> for InputStorage<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn sweep(&self, _db: &DB, _strategy: SweepStrategy, _policy: Option<&dyn SweepPolicy>) {}
+
+    fn sweep_some(
+        &self,
+        _db: &DB,
+        _strategy: SweepStrategy,
+        _start: usize,
+        _limit: usize,
+    ) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    fn debug_dump(&self, _db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let slots = self
+            .ordered_slots()
+            .iter()
+            .map(|slot| {
+                let stamped_value = slot.stamped_value.read();
+                match stamped_value.value {
+                    Some(_) => SlotDump::new(&debug_key::<DB, Q>(&slot.key), SlotState::Memoized)
+                        .with_stamp(stamped_value.changed_at, stamped_value.durability),
+                    None => SlotDump::new(&debug_key::<DB, Q>(&slot.key), SlotState::NotComputed),
+                }
+            })
+            .collect();
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        let slot_size =
+            std::mem::size_of::<(Q::Key, Arc<Slot<DB, Q>>)>() + std::mem::size_of::<Slot<DB, Q>>();
+        let report = self
+            .slots
+            .read()
+            .values()
+            .map(|slot| {
+                let mut report = MemoryReport::slot(slot_size);
+                if let Some(value) = &slot.stamped_value.read().value {
+                    report.add_value(value, Q::HEAP_SIZE);
+                }
+                report
+            })
+            .sum();
+        (Q::QUERY_NAME, report)
+    }
+
+    fn replay_journaled(&self, db: &DB, write: JournalWriteId, undo: bool) {
+        let (key, database_key, value) = match self.journal.lock().get(&write) {
+            Some(write) => (
+                write.key.clone(),
+                write.database_key.clone(),
+                if undo { &write.old } else { &write.new }.clone(),
+            ),
+            None => return,
+        };
+        match value {
+            Some((value, durability)) => self.set(db, &key, &database_key, value, durability),
+            None => self.remove(db, &key, &database_key),
+        }
+    }
+
+    fn forget_journaled(&self, writes: &[JournalWriteId]) {
+        let mut journal = self.journal.lock();
+        if !journal.is_empty() {
+            for write in writes {
+                journal.remove(write);
+            }
+        }
+    }
+
+    fn dump_journaled(
+        &self,
+        revision: Revision,
+        write: JournalWriteId,
+        out: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        match self.journal.lock().get(&write) {
+            Some(write) => writeln!(
+                out,
+                "{:?} {:?}: {} -> {}",
+                revision,
+                write.database_key,
+                describe_journaled::<DB, Q>(&write.old),
+                describe_journaled::<DB, Q>(&write.new)
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn take_recorded_writes(&self, writes: &mut Vec<plumbing::RecordedWrite<DB>>)
+    where
+        DB: 'static,
+    {
+        for (seq, revision, write) in self.recorded.lock().drain(..) {
+            writes.push(plumbing::RecordedWrite::new(seq, revision, Box::new(write)));
+        }
+    }
+}
+
+impl<DB, Q> QueryStorageStats for InputStorage<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn slot_count(&self) -> usize {
+        self.slots.read().len()
+    }
+
+    fn in_progress_count(&self) -> usize {
+        0
+    }
+
+    fn memoized_count(&self) -> usize {
+        self.slots
+            .read()
+            .values()
+            .filter(|slot| slot.stamped_value.read().value.is_some())
+            .count()
+    }
+}
+
+impl<DB, Q> plumbing::ReplayWrite<DB> for InputWrite<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group> + 'static,
+{
+    fn replay(&self, db: &mut DB) {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let storage: &dyn std::any::Any = Q::query_storage(group_storage);
+        let storage: &InputStorage<DB, Q> = storage
+            .downcast_ref()
+            .expect("input query without input storage");
+        let database_key = <DB as GetQueryTable<Q>>::database_key(db, self.key.clone());
+        match &self.value {
+            Some((value, durability)) => {
+                storage.set(db, &self.key, &database_key, value.clone(), *durability)
+            }
+            None => storage.remove(db, &self.key, &database_key),
+        }
+        db.salsa_runtime().discard_forgotten_writes(db);
+    }
+}
+
+impl<DB, Q> std::fmt::Debug for InputWrite<DB, Q>
 where
     Q: Query<DB>,
     DB: Database,
 {
-    fn sweep(&self, _db: &DB, _strategy: SweepStrategy) {}
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{:?}({:?}) = {}",
+            Q::default(),
+            debug_key::<DB, Q>(&self.key),
+            describe_journaled::<DB, Q>(&self.value)
+        )
+    }
 }
 
 impl<DB, Q> InputQueryStorageOps<DB, Q> for InputStorage<DB, Q>
 where
     Q: Query<DB>,
-    DB: Database,
+    DB: Database + HasQueryGroup<Q::Group>,
 {
+    fn try_fetch_maybe(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        // If the key was never set, we still need a slot to record as
+        // a dependency, so that the reading query is invalidated when
+        // a value is set later. The key has been absent since the
+        // very first revision.
+        let slot = match self.slot(key) {
+            Some(slot) => slot,
+            None => self
+                .slots
+                .write()
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    Arc::new(Slot {
+                        key: key.clone(),
+                        stamped_value: RwLock::new(StampedValue {
+                            value: None,
+                            durability: Durability::LOW,
+                            changed_at: Revision::start(),
+                        }),
+                        old_values: Default::default(),
+                    })
+                })
+                .clone(),
+        };
+
+        let StampedValue {
+            value,
+            durability,
+            changed_at,
+        } = slot.stamped_value.read().clone();
+
+        db.salsa_runtime()
+            .report_query_read(slot, durability, changed_at);
+
+        value
+    }
+
+    fn try_fetch_maybe_at(&self, db: &DB, key: &Q::Key, revision: Revision) -> Option<Q::Value> {
+        if !Q::MULTI_VERSION {
+            plumbing::not_multi_version::<DB, Q>(key, revision);
+        }
+        let slot = self.slot(key)?;
+
+        let StampedValue {
+            value,
+            durability,
+            changed_at,
+        } = slot.stamped_value_at(revision);
+
+        db.salsa_runtime()
+            .report_query_read(slot, durability, changed_at);
+
+        value
+    }
+
+    fn is_set_to(&self, key: &Q::Key, value: &Q::Value, durability: Durability) -> bool
+    where
+        Q::Value: Eq,
+    {
+        match self.slot(key) {
+            Some(slot) => {
+                let stamped_value = slot.stamped_value.read();
+                stamped_value.durability == durability
+                    && stamped_value.value.as_ref() == Some(value)
+            }
+            None => false,
+        }
+    }
+
     fn set(
         &self,
         db: &DB,
@@ -136,11 +639,11 @@ where
         value: Q::Value,
         durability: Durability,
     ) {
-        log::debug!(
+        debug!(
             "{:?}({:?}) = {:?} ({:?})",
             Q::default(),
-            key,
-            value,
+            debug_key::<DB, Q>(key),
+            debug_value::<DB, Q>(&value),
             durability
         );
 
@@ -162,7 +665,7 @@ where
         db.salsa_runtime().with_incremented_revision(|guard| {
             let mut slots = self.slots.write();
 
-            db.salsa_event(|| Event {
+            db.salsa_runtime().report_event(db, || Event {
                 runtime_id: db.salsa_runtime().id(),
                 kind: EventKind::WillChangeInputValue {
                     database_key: database_key.clone(),
@@ -174,15 +677,29 @@ where
             // (Otherwise, someone else might write a *newer* revision
             // into the same cell while we block on the lock.)
             let stamped_value = StampedValue {
-                value,
+                value: Some(value),
                 durability,
                 changed_at: guard.new_revision(),
             };
 
+            guard.record_change(|| InputChange::Set {
+                database_key: database_key.clone(),
+                durability,
+            });
+            self.record_write(guard, key, database_key, || {
+                let old = slots
+                    .get(key)
+                    .and_then(|slot| journaled(&slot.stamped_value.read()));
+                (old, journaled(&stamped_value))
+            });
+
             match slots.entry(key.clone()) {
                 Entry::Occupied(entry) => {
                     let mut slot_stamped_value = entry.get().stamped_value.write();
                     guard.mark_durability_as_changed(slot_stamped_value.durability);
+                    entry
+                        .get()
+                        .keep_old_value(db, &slot_stamped_value, guard.new_revision());
                     *slot_stamped_value = stamped_value;
                 }
 
@@ -190,11 +707,200 @@ where
                     entry.insert(Arc::new(Slot {
                         key: key.clone(),
                         stamped_value: RwLock::new(stamped_value),
+                        old_values: Default::default(),
                     }));
                 }
             }
         });
     }
+
+    fn set_durability(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        durability: Durability,
+    ) {
+        debug!(
+            "{:?}({:?}) durability = {:?}",
+            Q::default(),
+            debug_key::<DB, Q>(key),
+            durability
+        );
+
+        // We need the global revision lock, so that no queries are
+        // reading the slot as we modify it. When raising the
+        // durability, we keep `changed_at` as it is: the value has not
+        // changed, and queries that read it recorded the lower
+        // durability, which remains a safe approximation. Lowering it
+        // is different: queries that read the value with its old
+        // durability must no longer assume that it cannot change, so
+        // in that case we treat the value as changed.
+        db.salsa_runtime().with_incremented_revision(|guard| {
+            let slots = self.slots.read();
+            let slot = slots.get(key).unwrap_or_else(|| no_value::<DB, Q>(key));
+            let mut stamped_value = slot.stamped_value.write();
+            if stamped_value.value.is_none() {
+                no_value::<DB, Q>(key);
+            }
+            self.record_write(guard, key, database_key, || {
+                let new = stamped_value.value.clone().map(|value| (value, durability));
+                (journaled(&stamped_value), new)
+            });
+            if durability < stamped_value.durability {
+                guard.mark_durability_as_changed(stamped_value.durability);
+                slot.keep_old_value(db, &stamped_value, guard.new_revision());
+                stamped_value.changed_at = guard.new_revision();
+            }
+            stamped_value.durability = durability;
+            guard.record_change(|| InputChange::DurabilityChanged {
+                database_key: database_key.clone(),
+                durability,
+            });
+        });
+    }
+
+    fn remove(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey) {
+        debug!("{:?}({:?}) removed", Q::default(), debug_key::<DB, Q>(key));
+
+        // We keep the slot around, but without a value: queries that
+        // read the old value hold on to it and need to see that it
+        // changed, and setting the key again later reuses it.
+        db.salsa_runtime().with_incremented_revision(|guard| {
+            let slots = self.slots.read();
+            let slot = match slots.get(key) {
+                Some(slot) => slot,
+                None => return,
+            };
+            let mut stamped_value = slot.stamped_value.write();
+            if stamped_value.value.is_none() {
+                return;
+            }
+
+            db.salsa_runtime().report_event(db, || Event {
+                runtime_id: db.salsa_runtime().id(),
+                kind: EventKind::WillChangeInputValue {
+                    database_key: database_key.clone(),
+                },
+            });
+
+            guard.mark_durability_as_changed(stamped_value.durability);
+            slot.keep_old_value(db, &stamped_value, guard.new_revision());
+            self.record_write(guard, key, database_key, || {
+                (journaled(&stamped_value), None)
+            });
+            guard.record_change(|| InputChange::Removed {
+                database_key: database_key.clone(),
+                durability: stamped_value.durability,
+            });
+            *stamped_value = StampedValue {
+                value: None,
+                durability: Durability::LOW,
+                changed_at: guard.new_revision(),
+            };
+        });
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<DB, Q> PersistQueryStorageOps<DB> for InputStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: Serialize + DeserializeOwned,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn query_name(&self) -> &'static str {
+        Q::QUERY_NAME
+    }
+
+    fn save_slots(&self, table: u32, slots: &mut SavedSlots<DB>) -> std::io::Result<()> {
+        for (key, slot) in self.slots.read().iter() {
+            slots.insert(
+                Dependency::new(slot.clone()),
+                table,
+                persist::serialize(key)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn save_table(
+        &self,
+        _slots: &SavedSlots<DB>,
+        revisions: &mut RevisionSet,
+    ) -> std::io::Result<Vec<u8>> {
+        let values: Vec<_> = self
+            .slots
+            .read()
+            .iter()
+            .map(|(key, slot)| {
+                let stamped_value = slot.stamped_value.read();
+                (
+                    key.clone(),
+                    stamped_value.value.clone(),
+                    stamped_value.durability.index() as u8,
+                    revisions.record(stamped_value.changed_at),
+                )
+            })
+            .collect();
+        persist::serialize(&values)
+    }
+
+    fn load_slots(
+        &self,
+        table: u32,
+        data: &[u8],
+        revisions: &RevisionMap,
+        slots: &mut LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        let values: Vec<(Q::Key, Option<Q::Value>, u8, u64)> = persist::deserialize(data)?;
+        let mut map = self.slots.write();
+        for (key, value, durability, changed_at) in values {
+            let stamped_value = StampedValue {
+                value,
+                durability: persist::durability_from_u8(durability)?,
+                changed_at: revisions.get(changed_at)?,
+            };
+            let key_bytes = persist::serialize(&key)?;
+            let slot = map
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    Arc::new(Slot {
+                        key,
+                        stamped_value: RwLock::new(stamped_value),
+                        old_values: Default::default(),
+                    })
+                })
+                .clone();
+            slots.insert(table, key_bytes, Dependency::new(slot));
+        }
+        Ok(())
+    }
+
+    fn load_table(
+        &self,
+        _data: &[u8],
+        _revisions: &RevisionMap,
+        _slots: &LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn hash_input(&self, key: &[u8]) -> std::io::Result<Option<HashedInput<DB>>> {
+        let key: Q::Key = persist::deserialize(key)?;
+        let slot = match self.slots.read().get(&key) {
+            Some(slot) => slot.clone(),
+            None => return Ok(None),
+        };
+        let stamped_value = slot.stamped_value.read();
+        Ok(Some(HashedInput {
+            hash: artifact::hash_value(&stamped_value.value)?,
+            durability: stamped_value.durability,
+            changed_at: stamped_value.changed_at,
+            slot: Dependency::new(slot.clone()),
+        }))
+    }
 }
 
 // Unsafe proof obligation: `Slot<DB, Q>` is Send + Sync if the query
@@ -204,8 +910,12 @@ where
 unsafe impl<DB, Q> DatabaseSlot<DB> for Slot<DB, Q>
 where
     Q: Query<DB>,
-    DB: Database,
+    DB: Database + HasQueryGroup<Q::Group>,
 {
+    fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey> {
+        Some(<DB as GetQueryTable<Q>>::database_key(db, self.key.clone()))
+    }
+
     fn maybe_changed_since(&self, _db: &DB, revision: Revision) -> bool {
         debug!(
             "maybe_changed_since(slot={:?}, revision={:?})",
@@ -253,12 +963,37 @@ where
     is_static::<Slot<DB, Q>>();
 }
 
+/// Returns the value and durability of an input, as journaled.
+fn journaled<V: Clone>(stamped_value: &StampedValue<Option<V>>) -> JournaledValue<V> {
+    let durability = stamped_value.durability;
+    stamped_value.value.clone().map(|value| (value, durability))
+}
+
+/// Describes a journaled value for `Database::dump_journal`.
+fn describe_journaled<DB, Q>(value: &JournaledValue<Q::Value>) -> String
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    match value {
+        Some((value, durability)) => {
+            format!("{:?} ({:?})", debug_value::<DB, Q>(value), durability)
+        }
+        None => "-".to_string(),
+    }
+}
+
 impl<DB, Q> std::fmt::Debug for Slot<DB, Q>
 where
     Q: Query<DB>,
     DB: Database,
 {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(fmt, "{:?}({:?})", Q::default(), self.key)
+        write!(
+            fmt,
+            "{:?}({:?})",
+            Q::default(),
+            debug_key::<DB, Q>(&self.key)
+        )
     }
 }
@@ -127,3 +127,27 @@ impl fmt::Display for InternId {
         self.as_usize().fmt(f)
     }
 }
+
+/// Intern ids are serialized as their `u32` value, so that they can be
+/// saved alongside the interned values (see `Database::serialize_memos`).
+#[cfg(feature = "persist")]
+impl serde::Serialize for InternId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.as_u32())
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<'de> serde::Deserialize<'de> for InternId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <u32 as serde::Deserialize>::deserialize(deserializer)?;
+        if value < InternId::MAX {
+            Ok(InternId::from(value))
+        } else {
+            Err(serde::de::Error::custom(format!(
+                "intern id {} out of range",
+                value
+            )))
+        }
+    }
+}
@@ -1,21 +1,33 @@
-use crate::debug::TableEntry;
+use crate::debug::{self, SlotDump, SlotState, TableEntry};
 use crate::dependency::DatabaseSlot;
+#[cfg(feature = "persist")]
+use crate::dependency::Dependency;
 use crate::durability::Durability;
 use crate::intern_id::InternId;
-use crate::plumbing::CycleDetected;
+use crate::key_map::KeyMap;
+use crate::opaque::debug_key;
+#[cfg(feature = "persist")]
+use crate::persist::{
+    self, LoadedSlots, PersistQueryStorageOps, RevisionMap, RevisionSet, SavedSlots,
+};
+use crate::plumbing::GetQueryTable;
 use crate::plumbing::HasQueryGroup;
+use crate::plumbing::InternedQueryStorageOps;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
 use crate::revision::Revision;
 use crate::Query;
-use crate::{Database, DiscardIf, SweepStrategy};
+use crate::QueryStorageStats;
+use crate::{CycleError, Database, DiscardIf, MemoryReport, SweepPolicy, SweepStrategy};
 use crossbeam::atomic::AtomicCell;
+use indexmap::map::Entry;
 use parking_lot::RwLock;
-use rustc_hash::FxHashMap;
-use std::collections::hash_map::Entry;
+#[cfg(feature = "persist")]
+use serde::{de::DeserializeOwned, Serialize};
 use std::convert::From;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 const INTERN_DURABILITY: Durability = Durability::HIGH;
@@ -28,7 +40,7 @@ where
     Q::Value: InternKey,
     DB: Database,
 {
-    tables: RwLock<InternTables<Q::Key>>,
+    tables: RwLock<InternTables<Q::Key, Q>>,
 }
 
 /// Storage for the looking up interned things.
@@ -50,14 +62,14 @@ where
     phantom: std::marker::PhantomData<(Q::Key, IQ)>,
 }
 
-struct InternTables<K> {
+struct InternTables<K, Q> {
     /// Map from the key to the corresponding intern-index.
-    map: FxHashMap<K, InternId>,
+    map: KeyMap<K, InternId>,
 
     /// For each valid intern-index, stores the interned value. When
     /// an interned value is GC'd, the entry is set to
     /// `InternValue::Free` with the next free item.
-    values: Vec<InternValue<K>>,
+    values: Vec<InternValue<K, Q>>,
 
     /// Index of the first free intern-index, if any.
     first_free: Option<InternId>,
@@ -84,16 +96,15 @@ impl InternKey for InternId {
     }
 }
 
-enum InternValue<K> {
+enum InternValue<K, Q> {
     /// The value has not been gc'd.
-    Present { slot: Arc<Slot<K>> },
+    Present { slot: Arc<Slot<K, Q>> },
 
     /// Free-list -- the index is the next
     Free { next: Option<InternId> },
 }
 
-#[derive(Debug)]
-struct Slot<K> {
+struct Slot<K, Q> {
     /// Index of this slot in the list of interned values;
     /// set to None if gc'd.
     index: InternId,
@@ -117,6 +128,20 @@ struct Slot<K> {
     /// `accessed_at` field to `Some(revision_now)` before releasing
     /// the read-lock on our interning tables.
     accessed_at: AtomicCell<Option<Revision>>,
+
+    /// The interning query, which determines the database key of
+    /// this slot.
+    query: PhantomData<fn() -> Q>,
+}
+
+impl<K, Q> Debug for Slot<K, Q> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Slot")
+            .field("index", &self.index)
+            .field("interned_at", &self.interned_at)
+            .field("accessed_at", &self.accessed_at)
+            .finish()
+    }
 }
 
 impl<DB, Q> std::panic::RefUnwindSafe for InternedStorage<DB, Q>
@@ -165,21 +190,32 @@ where
     }
 }
 
-impl<K: Debug + Hash + Eq> InternTables<K> {
+impl<K: Hash + Eq, Q> InternTables<K, Q> {
     /// Returns the slot for the given key.
     ///
     /// The slot will have its "accessed at" field updated to its current revision,
     /// ensuring that it cannot be GC'd until the current queries complete.
-    fn slot_for_key(&self, key: &K, revision_now: Revision) -> Option<Arc<Slot<K>>> {
+    fn slot_for_key(&self, key: &K, revision_now: Revision) -> Option<Arc<Slot<K, Q>>> {
         let index = self.map.get(key)?;
         Some(self.slot_for_index(*index, revision_now))
     }
 
+    /// Returns the revision in which the value at the given index was
+    /// interned, without marking it as accessed.
+    fn interned_at(&self, index: InternId) -> Revision {
+        match &self.values[index.as_usize()] {
+            InternValue::Present { slot } => slot.interned_at,
+            InternValue::Free { .. } => {
+                panic!("index {:?} is free but should not be", index);
+            }
+        }
+    }
+
     /// Returns the slot at the given index.
     ///
     /// The slot will have its "accessed at" field updated to its current revision,
     /// ensuring that it cannot be GC'd until the current queries complete.
-    fn slot_for_index(&self, index: InternId, revision_now: Revision) -> Arc<Slot<K>> {
+    fn slot_for_index(&self, index: InternId, revision_now: Revision) -> Arc<Slot<K, Q>> {
         match &self.values[index.as_usize()] {
             InternValue::Present { slot } => {
                 // Subtle: we must update the "accessed at" to the
@@ -200,7 +236,7 @@ impl<K: Debug + Hash + Eq> InternTables<K> {
     }
 }
 
-impl<K> Default for InternTables<K>
+impl<K, Q> Default for InternTables<K, Q>
 where
     K: Eq + Hash,
 {
@@ -225,7 +261,7 @@ where
     /// In either case, the `accessed_at` field of the slot is updated
     /// to the current revision, ensuring that the slot cannot be GC'd
     /// while the current queries execute.
-    fn intern_index(&self, db: &DB, key: &Q::Key) -> Arc<Slot<Q::Key>> {
+    fn intern_index(&self, db: &DB, key: &Q::Key) -> Arc<Slot<Q::Key, Q>> {
         if let Some(i) = self.intern_check(db, key) {
             return i;
         }
@@ -246,13 +282,16 @@ where
                 let index = *entry.get();
                 match &tables.values[index.as_usize()] {
                     InternValue::Present { slot } => {
-                        debug_assert_eq!(owned_key2, slot.value);
+                        debug_assert!(owned_key2 == slot.value);
                         debug_assert_eq!(slot.accessed_at.load(), Some(revision_now));
                         return slot.clone();
                     }
 
                     InternValue::Free { .. } => {
-                        panic!("key {:?} should be present but is not", key,);
+                        panic!(
+                            "key {:?} should be present but is not",
+                            debug_key::<DB, Q>(key),
+                        );
                     }
                 }
             }
@@ -264,6 +303,7 @@ where
                 value: owned_key2,
                 interned_at: revision_now,
                 accessed_at: AtomicCell::new(Some(revision_now)),
+                query: PhantomData,
             })
         };
 
@@ -286,7 +326,8 @@ where
                     InternValue::Present { slot } => {
                         panic!(
                             "index {:?} was supposed to be free but contains {:?}",
-                            i, slot.value
+                            i,
+                            debug_key::<DB, Q>(&slot.value)
                         );
                     }
                 };
@@ -301,15 +342,24 @@ where
         slot
     }
 
-    fn intern_check(&self, db: &DB, key: &Q::Key) -> Option<Arc<Slot<Q::Key>>> {
+    fn intern_check(&self, db: &DB, key: &Q::Key) -> Option<Arc<Slot<Q::Key, Q>>> {
         let revision_now = db.salsa_runtime().current_revision();
         let slot = self.tables.read().slot_for_key(key, revision_now)?;
         Some(slot)
     }
 
+    /// Given an index, clones its value (if it is still interned),
+    /// without marking it as accessed.
+    fn peek_value(&self, index: InternId) -> Option<Q::Key> {
+        match self.tables.read().values.get(index.as_usize())? {
+            InternValue::Present { slot } => Some(slot.value.clone()),
+            InternValue::Free { .. } => None,
+        }
+    }
+
     /// Given an index, lookup and clone its value, updating the
     /// `accessed_at` time if necessary.
-    fn lookup_value(&self, db: &DB, index: InternId) -> Arc<Slot<Q::Key>> {
+    fn lookup_value(&self, db: &DB, index: InternId) -> Arc<Slot<Q::Key, Q>> {
         let revision_now = db.salsa_runtime().current_revision();
         self.tables.read().slot_for_index(index, revision_now)
     }
@@ -319,9 +369,9 @@ impl<DB, Q> QueryStorageOps<DB, Q> for InternedStorage<DB, Q>
 where
     Q: Query<DB>,
     Q::Value: InternKey,
-    DB: Database,
+    DB: Database + HasQueryGroup<Q::Group>,
 {
-    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleDetected> {
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
         let slot = self.intern_index(db, key);
         let changed_at = slot.interned_at;
         let index = slot.index;
@@ -334,6 +384,50 @@ where
         INTERN_DURABILITY
     }
 
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        match self.intern_check(db, key) {
+            Some(slot) => slot.maybe_changed_since(db, revision),
+            None => true,
+        }
+    }
+
+    fn peek(&self, _db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        let tables = self.tables.read();
+        let index = tables.map.get(key)?;
+        Some(<Q::Value>::from_intern_id(*index))
+    }
+
+    fn sweep_keys(&self, db: &DB, keys: &mut dyn Iterator<Item = Q::Key>, strategy: SweepStrategy) {
+        if strategy.discard_if == DiscardIf::Never {
+            return;
+        }
+
+        // See `sweep` for why only keys that were not accessed in the
+        // current revision are discarded.
+        let mut tables = self.tables.write();
+        let last_changed = db.salsa_runtime().last_changed_revision(INTERN_DURABILITY);
+        let revision_now = db.salsa_runtime().current_revision();
+        let InternTables {
+            map,
+            values,
+            first_free,
+        } = &mut *tables;
+        for key in keys {
+            if let Some(&intern_index) = map.get(&key) {
+                if try_free(
+                    values,
+                    first_free,
+                    &debug_key::<DB, Q>(&key),
+                    intern_index,
+                    last_changed,
+                    revision_now,
+                ) {
+                    map.remove(&key);
+                }
+            }
+        }
+    }
+
     fn entries<C>(&self, _db: &DB) -> C
     where
         C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
@@ -344,18 +438,87 @@ where
             .iter()
             .map(|(key, index)| {
                 TableEntry::new(key.clone(), Some(<Q::Value>::from_intern_id(*index)))
+                    .with_stamp(tables.interned_at(*index), INTERN_DURABILITY)
             })
             .collect()
     }
 }
 
+impl<DB, Q> InternedQueryStorageOps<DB, Q> for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn lookup_existing(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        match self.intern_check(db, key) {
+            Some(slot) => {
+                let changed_at = slot.interned_at;
+                let index = slot.index;
+                db.salsa_runtime()
+                    .report_query_read(slot, INTERN_DURABILITY, changed_at);
+                Some(<Q::Value>::from_intern_id(index))
+            }
+            None => {
+                // There is nothing to depend on until the key gets
+                // interned, which does not create a new revision.
+                db.salsa_runtime().report_untracked_read();
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "persist")]
+    fn export_interned(&self, out: &mut dyn std::io::Write) -> std::io::Result<()>
+    where
+        Q::Key: Serialize,
+    {
+        let tables = self.tables.read();
+        let values: Vec<(u32, &Q::Key)> = tables
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                InternValue::Present { slot } => Some((slot.index.as_u32(), &slot.value)),
+                InternValue::Free { .. } => None,
+            })
+            .collect();
+        persist::write_intern_table(out, Q::QUERY_NAME, &values)
+    }
+
+    #[cfg(feature = "persist")]
+    fn import_interned(&self, db: &DB, reader: &mut dyn std::io::Read) -> std::io::Result<()>
+    where
+        Q::Key: DeserializeOwned,
+    {
+        let values: Vec<(u32, Q::Key)> = persist::read_intern_table(reader, Q::QUERY_NAME)?;
+        let revision_now = db.salsa_runtime().current_revision();
+
+        let mut tables = self.tables.write();
+        check_empty::<Q::Key, Q>(&tables)?;
+        let slots = values
+            .into_iter()
+            .map(|(index, key)| {
+                Ok(Arc::new(Slot {
+                    index: intern_id_from_u32(index)?,
+                    value: key,
+                    interned_at: revision_now,
+                    accessed_at: AtomicCell::new(Some(revision_now)),
+                    query: PhantomData,
+                }))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        tables.restore(slots);
+        Ok(())
+    }
+}
+
 impl<DB, Q> QueryStorageMassOps<DB> for InternedStorage<DB, Q>
 where
     Q: Query<DB>,
     Q::Value: InternKey,
     DB: Database,
 {
-    fn sweep(&self, db: &DB, strategy: SweepStrategy) {
+    fn sweep(&self, db: &DB, strategy: SweepStrategy, _policy: Option<&dyn SweepPolicy>) {
         let mut tables = self.tables.write();
         let last_changed = db.salsa_runtime().last_changed_revision(INTERN_DURABILITY);
         let revision_now = db.salsa_runtime().current_revision();
@@ -380,28 +543,252 @@ where
                 // revision don't have this problem. Anything
                 // dependent on them would regard itself as dirty if
                 // they are removed and also be forced to re-execute.
-                DiscardIf::Always | DiscardIf::Outdated => match &values[intern_index.as_usize()] {
-                    InternValue::Present { slot, .. } => {
-                        if slot.try_collect(last_changed, revision_now) {
-                            values[intern_index.as_usize()] =
-                                InternValue::Free { next: *first_free };
-                            *first_free = Some(*intern_index);
-                            false
-                        } else {
-                            true
-                        }
-                    }
-
-                    InternValue::Free { .. } => {
-                        panic!(
-                            "key {:?} maps to index {:?} which is free",
-                            key, intern_index
-                        );
-                    }
-                },
+                DiscardIf::Always | DiscardIf::Outdated => !try_free(
+                    values,
+                    first_free,
+                    &debug_key::<DB, Q>(key),
+                    *intern_index,
+                    last_changed,
+                    revision_now,
+                ),
             }
         });
     }
+
+    fn sweep_some(
+        &self,
+        db: &DB,
+        strategy: SweepStrategy,
+        start: usize,
+        limit: usize,
+    ) -> (usize, Option<usize>) {
+        let mut tables = self.tables.write();
+        let keys: Vec<Q::Key> = tables.map.keys().skip(start).take(limit).cloned().collect();
+        let swept = keys.len();
+        let next = Some(start + swept).filter(|_| swept == limit);
+        if strategy.discard_if == DiscardIf::Never {
+            return (swept, next);
+        }
+
+        // See `sweep` for why only keys that were not accessed in the
+        // current revision are discarded.
+        let last_changed = db.salsa_runtime().last_changed_revision(INTERN_DURABILITY);
+        let revision_now = db.salsa_runtime().current_revision();
+        let InternTables {
+            map,
+            values,
+            first_free,
+        } = &mut *tables;
+        let mut freed = 0;
+        for key in keys {
+            let intern_index = map[&key];
+            if try_free(
+                values,
+                first_free,
+                &debug_key::<DB, Q>(&key),
+                intern_index,
+                last_changed,
+                revision_now,
+            ) {
+                map.remove(&key);
+                freed += 1;
+            }
+        }
+
+        // Removing keys does not reorder the remaining ones, but the
+        // ones after them move up.
+        (swept, next.map(|next| next - freed))
+    }
+
+    fn debug_dump(&self, _db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let tables = self.tables.read();
+        let slots = tables
+            .map
+            .iter()
+            .map(|(key, index)| {
+                SlotDump::new(&debug_key::<DB, Q>(key), SlotState::Memoized)
+                    .with_stamp(tables.interned_at(*index), INTERN_DURABILITY)
+            })
+            .collect();
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        let tables = self.tables.read();
+        let mut report = MemoryReport {
+            slots: tables.map.len(),
+            values: tables.map.len(),
+            bytes: tables.values.len() * std::mem::size_of::<InternValue<Q::Key, Q>>(),
+        };
+        report.bytes += tables.map.len()
+            * (std::mem::size_of::<(Q::Key, InternId)>() + std::mem::size_of::<Slot<Q::Key, Q>>());
+        (Q::QUERY_NAME, report)
+    }
+}
+
+impl<DB, Q> QueryStorageStats for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn slot_count(&self) -> usize {
+        self.tables.read().map.len()
+    }
+
+    fn in_progress_count(&self) -> usize {
+        0
+    }
+
+    fn memoized_count(&self) -> usize {
+        self.slot_count()
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<DB, Q> PersistQueryStorageOps<DB> for InternedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Key: Serialize + DeserializeOwned,
+    Q::Value: InternKey,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn query_name(&self) -> &'static str {
+        Q::QUERY_NAME
+    }
+
+    fn save_slots(&self, table: u32, slots: &mut SavedSlots<DB>) -> std::io::Result<()> {
+        for value in &self.tables.read().values {
+            if let InternValue::Present { slot } = value {
+                slots.insert(
+                    Dependency::new(slot.clone()),
+                    table,
+                    persist::serialize(&slot.value)?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn save_table(
+        &self,
+        _slots: &SavedSlots<DB>,
+        revisions: &mut RevisionSet,
+    ) -> std::io::Result<Vec<u8>> {
+        let tables = self.tables.read();
+        let values: Vec<_> = tables
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                InternValue::Present { slot } => Some((
+                    slot.index.as_u32(),
+                    slot.value.clone(),
+                    revisions.record(slot.interned_at),
+                    revisions.record(slot.accessed_at.load()?),
+                )),
+                InternValue::Free { .. } => None,
+            })
+            .collect();
+        persist::serialize(&values)
+    }
+
+    /// Interned values are restored with their original intern-index,
+    /// so that intern keys stored in other tables remain valid. This
+    /// requires that nothing was interned before loading.
+    fn load_slots(
+        &self,
+        table: u32,
+        data: &[u8],
+        revisions: &RevisionMap,
+        slots: &mut LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        let values: Vec<(u32, Q::Key, u64, u64)> = persist::deserialize(data)?;
+
+        let mut tables = self.tables.write();
+        check_empty::<Q::Key, Q>(&tables)?;
+
+        let mut new_slots = Vec::with_capacity(values.len());
+        for (index, key, interned_at, accessed_at) in values {
+            let slot = Arc::new(Slot {
+                index: intern_id_from_u32(index)?,
+                value: key,
+                interned_at: revisions.get(interned_at)?,
+                accessed_at: AtomicCell::new(Some(revisions.get(accessed_at)?)),
+                query: PhantomData,
+            });
+            slots.insert(
+                table,
+                persist::serialize(&slot.value)?,
+                Dependency::new(slot.clone()),
+            );
+            new_slots.push(slot);
+        }
+        tables.restore(new_slots);
+        Ok(())
+    }
+
+    fn load_table(
+        &self,
+        _data: &[u8],
+        _revisions: &RevisionMap,
+        _slots: &LoadedSlots<DB>,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<K: Hash + Eq + Clone, Q> InternTables<K, Q> {
+    /// Replaces the (empty) tables with `slots`, which keep their
+    /// intern-index; the indices in between are free.
+    fn restore(&mut self, slots: Vec<Arc<Slot<K, Q>>>) {
+        let len = slots
+            .iter()
+            .map(|slot| slot.index.as_usize() + 1)
+            .max()
+            .unwrap_or(0);
+        let mut values: Vec<InternValue<K, Q>> =
+            (0..len).map(|_| InternValue::Free { next: None }).collect();
+        for slot in slots {
+            let index = slot.index;
+            self.map.insert(slot.value.clone(), index);
+            values[index.as_usize()] = InternValue::Present { slot };
+        }
+
+        // Chain the unused indices into the free list, lowest first.
+        let mut first_free = None;
+        for (index, value) in values.iter_mut().enumerate().rev() {
+            if let InternValue::Free { next } = value {
+                *next = first_free;
+                first_free = Some(InternId::from(index));
+            }
+        }
+        self.values = values;
+        self.first_free = first_free;
+    }
+}
+
+#[cfg(feature = "persist")]
+fn check_empty<K, Q: Debug + Default>(tables: &InternTables<K, Q>) -> std::io::Result<()> {
+    if tables.map.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{:?} already contains interned values", Q::default()),
+        ))
+    }
+}
+
+#[cfg(feature = "persist")]
+fn intern_id_from_u32(index: u32) -> std::io::Result<InternId> {
+    if index >= InternId::MAX {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid intern index {}", index),
+        ));
+    }
+    Ok(InternId::from(index))
 }
 
 impl<DB, Q, IQ> QueryStorageOps<DB, Q> for LookupInternedStorage<DB, Q, IQ>
@@ -420,7 +807,7 @@ where
     >,
     DB: Database + HasQueryGroup<Q::Group>,
 {
-    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleDetected> {
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
         let index = key.as_intern_id();
         let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
         let interned_storage = IQ::query_storage(group_storage);
@@ -436,6 +823,28 @@ where
         INTERN_DURABILITY
     }
 
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let interned_storage = IQ::query_storage(group_storage);
+        interned_storage
+            .lookup_value(db, key.as_intern_id())
+            .maybe_changed_since(db, revision)
+    }
+
+    fn peek(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let interned_storage = IQ::query_storage(group_storage);
+        interned_storage.peek_value(key.as_intern_id())
+    }
+
+    fn sweep_keys(
+        &self,
+        _db: &DB,
+        _keys: &mut dyn Iterator<Item = Q::Key>,
+        _strategy: SweepStrategy,
+    ) {
+    }
+
     fn entries<C>(&self, db: &DB) -> C
     where
         C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
@@ -448,6 +857,7 @@ where
             .iter()
             .map(|(key, index)| {
                 TableEntry::new(<Q::Key>::from_intern_id(*index), Some(key.clone()))
+                    .with_stamp(tables.interned_at(*index), INTERN_DURABILITY)
             })
             .collect()
     }
@@ -462,16 +872,82 @@ where
         DB,
         Key = Q::Value,
         Value = Q::Key,
+        Storage = InternedStorage<DB, IQ>,
         Group = Q::Group,
         GroupStorage = Q::GroupStorage,
         GroupKey = Q::GroupKey,
     >,
-    DB: Database,
+    DB: Database + HasQueryGroup<Q::Group>,
 {
-    fn sweep(&self, _db: &DB, _strategy: SweepStrategy) {}
+    fn sweep(&self, _db: &DB, _strategy: SweepStrategy, _policy: Option<&dyn SweepPolicy>) {}
+
+    fn sweep_some(
+        &self,
+        _db: &DB,
+        _strategy: SweepStrategy,
+        _start: usize,
+        _limit: usize,
+    ) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    fn debug_dump(&self, db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let interned_storage = IQ::query_storage(group_storage);
+        let tables = interned_storage.tables.read();
+        let slots = tables
+            .map
+            .values()
+            .map(|index| {
+                SlotDump::new(
+                    &debug_key::<DB, Q>(&<Q::Key>::from_intern_id(*index)),
+                    SlotState::Memoized,
+                )
+                .with_stamp(tables.interned_at(*index), INTERN_DURABILITY)
+            })
+            .collect();
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        // The interned values are kept (and counted) by the interning
+        // query.
+        (Q::QUERY_NAME, MemoryReport::default())
+    }
 }
 
-impl<K> Slot<K> {
+/// Frees the value that `key` is interned as (at `intern_index`),
+/// unless it was accessed in the current revision; returns true if it
+/// was freed. See `InternedStorage::sweep`.
+fn try_free<K, Q>(
+    values: &mut [InternValue<K, Q>],
+    first_free: &mut Option<InternId>,
+    key: &dyn Debug,
+    intern_index: InternId,
+    last_changed: Revision,
+    revision_now: Revision,
+) -> bool {
+    match &values[intern_index.as_usize()] {
+        InternValue::Present { slot, .. } => {
+            if slot.try_collect(last_changed, revision_now) {
+                values[intern_index.as_usize()] = InternValue::Free { next: *first_free };
+                *first_free = Some(intern_index);
+                true
+            } else {
+                false
+            }
+        }
+
+        InternValue::Free { .. } => {
+            panic!(
+                "key {:?} maps to index {:?} which is free",
+                key, intern_index
+            );
+        }
+    }
+}
+
+impl<K, Q> Slot<K, Q> {
     /// Updates the `accessed_at` time to be `revision_now` (if
     /// necessary).  Returns true if the update was successful, or
     /// false if the slot has been GC'd in the interim.
@@ -524,15 +1000,22 @@ impl<K> Slot<K> {
     }
 }
 
-// Unsafe proof obligation: `Slot<K>` is Send + Sync if the query
+// Unsafe proof obligation: `Slot<K, Q>` is Send + Sync if the query
 // key/value is Send + Sync (also, that we introduce no
 // references). These are tested by the `check_send_sync` and
 // `check_static` helpers below.
-unsafe impl<DB, K> DatabaseSlot<DB> for Slot<K>
+unsafe impl<DB, Q> DatabaseSlot<DB> for Slot<Q::Key, Q>
 where
-    DB: Database,
-    K: Debug,
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
 {
+    fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey> {
+        Some(<DB as GetQueryTable<Q>>::database_key(
+            db,
+            self.value.clone(),
+        ))
+    }
+
     fn maybe_changed_since(&self, db: &DB, revision: Revision) -> bool {
         let revision_now = db.salsa_runtime().current_revision();
         if !self.try_update_accessed_at(revision_now) {
@@ -549,22 +1032,23 @@ where
 /// `DB::DatabaseData: Send + Sync`, which in turn implies that
 /// `Q::Key: Send + Sync`, `Q::Value: Send + Sync`.
 #[allow(dead_code)]
-fn check_send_sync<K>()
+fn check_send_sync<K, Q>()
 where
     K: Send + Sync,
 {
     fn is_send_sync<T: Send + Sync>() {}
-    is_send_sync::<Slot<K>>();
+    is_send_sync::<Slot<K, Q>>();
 }
 
 /// Check that `Slot<DB, Q, MP>: 'static` as long as
 /// `DB::DatabaseData: 'static`, which in turn implies that
 /// `Q::Key: 'static`, `Q::Value: 'static`.
 #[allow(dead_code)]
-fn check_static<K>()
+fn check_static<K, Q>()
 where
     K: 'static,
+    Q: 'static,
 {
     fn is_static<T: 'static>() {}
-    is_static::<Slot<K>>();
+    is_static::<Slot<K, Q>>();
 }
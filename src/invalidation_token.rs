@@ -0,0 +1,105 @@
+use crate::dependency::DatabaseSlot;
+use crate::revision::{AtomicRevision, Revision};
+use crate::{Database, InvalidationReason};
+use parking_lot::RwLock;
+use rustc_hash::FxHashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A named handle for some state that salsa does not track, such as
+/// "anything on the file system". Queries that consult that state
+/// report it with `Runtime::report_read_of`; once it changes, the
+/// application calls `Database::invalidate_token`, and all those
+/// queries are re-executed in the new revision.
+///
+/// This is a middle ground between inputs, which track each value
+/// separately, and `Runtime::report_untracked_read`, which makes a
+/// query re-execute in every new revision. Two tokens with the same
+/// name are the same token.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct InvalidationToken {
+    name: Arc<str>,
+}
+
+impl InvalidationToken {
+    /// Creates the token with the given name.
+    pub fn new(name: impl Into<Arc<str>>) -> Self {
+        InvalidationToken { name: name.into() }
+    }
+
+    /// Returns the name of the token.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for InvalidationToken {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "InvalidationToken({:?})", self.name)
+    }
+}
+
+/// The slot of a token, which queries that read the token depend on.
+pub(crate) struct TokenSlot {
+    token: InvalidationToken,
+
+    /// The last revision in which the token was invalidated.
+    changed_at: AtomicRevision,
+}
+
+impl TokenSlot {
+    pub(crate) fn changed_at(&self) -> Revision {
+        self.changed_at.load()
+    }
+}
+
+impl fmt::Debug for TokenSlot {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.token.fmt(fmt)
+    }
+}
+
+// Unsafe proof obligation: `TokenSlot` is `Send + Sync + 'static`.
+unsafe impl<DB: Database> DatabaseSlot<DB> for TokenSlot {
+    fn maybe_changed_since(&self, _db: &DB, revision: Revision) -> bool {
+        self.changed_at() > revision
+    }
+
+    fn database_key(&self, _db: &DB) -> Option<DB::DatabaseKey> {
+        None
+    }
+
+    fn changed_reason(&self, _db: &DB) -> InvalidationReason<DB::DatabaseKey> {
+        InvalidationReason::TokenInvalidated(self.token.clone())
+    }
+}
+
+/// The slots of all the tokens that were read or invalidated so far.
+#[derive(Default)]
+pub(crate) struct TokenSlots {
+    slots: RwLock<FxHashMap<InvalidationToken, Arc<TokenSlot>>>,
+}
+
+impl TokenSlots {
+    pub(crate) fn get(&self, token: &InvalidationToken) -> Arc<TokenSlot> {
+        if let Some(slot) = self.slots.read().get(token) {
+            return slot.clone();
+        }
+
+        self.slots
+            .write()
+            .entry(token.clone())
+            .or_insert_with(|| {
+                Arc::new(TokenSlot {
+                    token: token.clone(),
+                    changed_at: AtomicRevision::start(),
+                })
+            })
+            .clone()
+    }
+
+    /// Records that `token` was invalidated in `revision`.
+    pub(crate) fn invalidate(&self, token: &InvalidationToken, revision: Revision) {
+        self.get(token).changed_at.store(revision);
+    }
+}
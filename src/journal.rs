@@ -0,0 +1,127 @@
+use crate::revision::Revision;
+use std::collections::VecDeque;
+
+/// Identifies a write to an input that was recorded in the journal
+/// (see `Runtime::set_journal_capacity`). The runtime only keeps the
+/// ids, grouped into steps; the table of the input keeps the key and
+/// the old and new values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JournalWriteId(u64);
+
+/// The writes made in one revision, in the order in which they were
+/// made; undoing the step reverts all of them.
+struct JournalStep {
+    revision: Revision,
+    writes: Vec<JournalWriteId>,
+}
+
+/// The steps that can be undone (the last `capacity` of them) and
+/// those that were undone and can be redone.
+#[derive(Default)]
+pub(crate) struct Journal {
+    capacity: usize,
+    next_id: u64,
+    undo: VecDeque<JournalStep>,
+    redo: Vec<JournalStep>,
+
+    /// Writes that were dropped from the journal, whose values the
+    /// tables have yet to discard.
+    forgotten: Vec<JournalWriteId>,
+
+    /// True while a step is being undone or redone: its writes are
+    /// not recorded again.
+    replaying: bool,
+}
+
+impl Journal {
+    /// Sets the number of steps to keep, discarding the oldest ones
+    /// if there are too many. A capacity of 0 discards all steps,
+    /// including those that could be redone.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if capacity == 0 {
+            let redo = std::mem::take(&mut self.redo);
+            self.forget(redo);
+        }
+        self.truncate();
+    }
+
+    /// Records a write made in `revision`, unless the journal is
+    /// disabled or the write undoes (or redoes) a step. Recording a
+    /// write discards the steps that could be redone.
+    pub(crate) fn record(&mut self, revision: Revision) -> Option<JournalWriteId> {
+        if self.capacity == 0 || self.replaying {
+            return None;
+        }
+        let redo = std::mem::take(&mut self.redo);
+        self.forget(redo);
+
+        let write = JournalWriteId(self.next_id);
+        self.next_id += 1;
+        match self.undo.back_mut() {
+            Some(step) if step.revision == revision => step.writes.push(write),
+            _ => {
+                self.undo.push_back(JournalStep {
+                    revision,
+                    writes: vec![write],
+                });
+                self.truncate();
+            }
+        }
+        Some(write)
+    }
+
+    /// Moves the last step that can be undone to the steps that can be
+    /// redone, and returns its writes, latest first; until
+    /// `end_replay`, writes are not recorded.
+    pub(crate) fn begin_undo(&mut self) -> Option<Vec<JournalWriteId>> {
+        let step = self.undo.pop_back()?;
+        let mut writes = step.writes.clone();
+        writes.reverse();
+        self.redo.push(step);
+        self.replaying = true;
+        Some(writes)
+    }
+
+    /// Like `begin_undo`, but for the last step that was undone; its
+    /// writes are returned in the order in which they were made.
+    pub(crate) fn begin_redo(&mut self) -> Option<Vec<JournalWriteId>> {
+        let step = self.redo.pop()?;
+        let writes = step.writes.clone();
+        self.undo.push_back(step);
+        self.replaying = true;
+        Some(writes)
+    }
+
+    pub(crate) fn end_replay(&mut self) {
+        self.replaying = false;
+    }
+
+    /// Returns the writes that were dropped from the journal since the
+    /// last call.
+    pub(crate) fn take_forgotten(&mut self) -> Vec<JournalWriteId> {
+        std::mem::take(&mut self.forgotten)
+    }
+
+    /// Returns the steps that can be undone, oldest first, with the
+    /// revision that each was made in.
+    pub(crate) fn steps(&self) -> Vec<(Revision, Vec<JournalWriteId>)> {
+        self.undo
+            .iter()
+            .map(|step| (step.revision, step.writes.clone()))
+            .collect()
+    }
+
+    fn truncate(&mut self) {
+        while self.undo.len() > self.capacity {
+            let step = self.undo.pop_front().unwrap();
+            self.forgotten.extend(step.writes);
+        }
+    }
+
+    fn forget(&mut self, steps: Vec<JournalStep>) {
+        for step in steps {
+            self.forgotten.extend(step.writes);
+        }
+    }
+}
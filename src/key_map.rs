@@ -0,0 +1,162 @@
+use crate::{Database, Query};
+use indexmap::IndexMap;
+use rustc_hash::FxHasher;
+#[cfg(feature = "test-support")]
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+#[cfg(feature = "test-support")]
+thread_local! {
+    /// Mixed into the keys hashed with `FxHasher` by the maps created
+    /// on this thread; see `with_key_hash_seed`.
+    static KEY_HASH_SEED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Runs `op`, perturbing the hashes of the keys of the maps created
+/// meanwhile (on this thread) with `seed`. As storage iterates in
+/// insertion order, this must not change anything observable; see
+/// `testing::assert_deterministic`.
+#[cfg(feature = "test-support")]
+pub(crate) fn with_key_hash_seed<R>(seed: u64, op: impl FnOnce() -> R) -> R {
+    struct Restore(u64);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            KEY_HASH_SEED.with(|cell| cell.set(self.0));
+        }
+    }
+    let _restore = Restore(KEY_HASH_SEED.with(|cell| cell.replace(seed)));
+    op()
+}
+
+/// Hashes the keys of a query: with `FxHasher` by default, or with the
+/// hasher given by the `#[salsa::hasher]` attribute (say,
+/// `std::collections::hash_map::RandomState`, when the keys come from
+/// untrusted sources).
+#[derive(Clone)]
+pub struct KeyHasher {
+    custom: Option<Arc<dyn DynBuildHasher>>,
+    #[cfg(feature = "test-support")]
+    seed: u64,
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        KeyHasher {
+            custom: None,
+            #[cfg(feature = "test-support")]
+            seed: KEY_HASH_SEED.with(Cell::get),
+        }
+    }
+}
+
+impl KeyHasher {
+    /// Hashes keys with a default `S`.
+    pub fn of<S>() -> Self
+    where
+        S: BuildHasher + Default + Send + Sync + 'static,
+        S::Hasher: 'static,
+    {
+        KeyHasher {
+            custom: Some(Arc::new(S::default())),
+            #[cfg(feature = "test-support")]
+            seed: 0,
+        }
+    }
+
+    /// The hasher of the keys of `Q`; see `Query::KEY_HASHER`.
+    pub(crate) fn for_query<DB: Database, Q: Query<DB>>() -> Self {
+        match Q::KEY_HASHER {
+            Some(key_hasher) => key_hasher(),
+            None => KeyHasher::default(),
+        }
+    }
+}
+
+impl BuildHasher for KeyHasher {
+    type Hasher = KeyHasherState;
+
+    fn build_hasher(&self) -> KeyHasherState {
+        match &self.custom {
+            Some(custom) => KeyHasherState(HasherState::Custom(custom.build_dyn_hasher())),
+            None => {
+                let hasher = FxHasher::default();
+                #[cfg(feature = "test-support")]
+                let hasher = {
+                    let mut hasher = hasher;
+                    if self.seed != 0 {
+                        hasher.write_u64(self.seed);
+                    }
+                    hasher
+                };
+                KeyHasherState(HasherState::Fx(hasher))
+            }
+        }
+    }
+}
+
+/// The `Hasher` of a `KeyHasher`.
+pub struct KeyHasherState(HasherState);
+
+enum HasherState {
+    Fx(FxHasher),
+    Custom(Box<dyn Hasher>),
+}
+
+impl Hasher for KeyHasherState {
+    fn finish(&self) -> u64 {
+        match &self.0 {
+            HasherState::Fx(hasher) => hasher.finish(),
+            HasherState::Custom(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match &mut self.0 {
+            HasherState::Fx(hasher) => hasher.write(bytes),
+            HasherState::Custom(hasher) => hasher.write(bytes),
+        }
+    }
+}
+
+/// An object-safe `BuildHasher`.
+trait DynBuildHasher: Send + Sync {
+    fn build_dyn_hasher(&self) -> Box<dyn Hasher>;
+}
+
+impl<S> DynBuildHasher for S
+where
+    S: BuildHasher + Send + Sync,
+    S::Hasher: 'static,
+{
+    fn build_dyn_hasher(&self) -> Box<dyn Hasher> {
+        Box::new(self.build_hasher())
+    }
+}
+
+/// Orders the keys of a query; see `Query::KEY_ORDER`.
+pub type KeyOrderFn<K> = fn(&K, &K) -> Ordering;
+
+/// Map from the keys of a query to their slots. It iterates in the
+/// order in which the keys were inserted (removing a key moves the
+/// last one in its place), so that, given the same operations, storage
+/// is visited in the same order whatever the hashes of the keys are.
+pub(crate) type KeyMap<K, V> = IndexMap<K, V, KeyHasher>;
+
+/// Creates an empty map for the keys of `Q`.
+pub(crate) fn new_key_map<DB: Database, Q: Query<DB>, V>() -> KeyMap<Q::Key, V> {
+    IndexMap::with_hasher(KeyHasher::for_query::<DB, Q>())
+}
+
+/// Sorts `items` by their keys (given by `key`), if `Q` orders its
+/// keys.
+pub(crate) fn sort_by_key<DB, Q, T>(items: &mut [T], key: impl Fn(&T) -> &Q::Key)
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    if let Some(order) = Q::KEY_ORDER {
+        items.sort_by(|a, b| order(key(a), key(b)));
+    }
+}
@@ -6,38 +6,124 @@
 //! values derived from those inputs; as you set the inputs, you can
 //! re-execute the derived queries and it will try to re-use results
 //! from previous invocations as appropriate.
+//!
+//! With the `trace` feature enabled, salsa reports what it does to
+//! the [`tracing`](https://docs.rs/tracing) crate, at the `DEBUG`
+//! level: whenever a derived query has to be validated or executed,
+//! that happens inside a `salsa_query` span whose `query` and `key`
+//! fields identify the query; within it, events record whether the
+//! memoized value was validated or, if not, why the query is executed,
+//! and whether its new value was backdated. Time spent blocked on another thread is recorded as a
+//! `salsa_blocked` span.
 
+#[cfg(feature = "persist")]
+mod artifact;
+mod blocking_future;
+mod dedup;
 mod dependency;
 mod derived;
 mod doctest;
 mod durability;
+mod dyn_database;
+#[cfg(feature = "dynamic")]
+mod dynamic;
+mod fallible;
 mod input;
 mod intern_id;
 mod interned;
+mod invalidation_token;
+mod journal;
+mod key_map;
+mod logging;
 mod lru;
+mod maybe_send;
+mod memory_usage;
+mod opaque;
+mod peek_future;
+#[cfg(feature = "persist")]
+mod persist;
+mod prefetch;
+mod revalidate;
 mod revision;
+mod revision_log;
 mod runtime;
+mod send_only;
+mod statistics;
+mod storage_stats;
+mod stream;
+mod time;
+mod tracked;
+mod value_guard;
+mod value_store;
 
+#[cfg(feature = "bench-support")]
+#[allow(missing_docs)] // for the items generated by `query_group`
+pub mod bench_support;
 pub mod debug;
+#[cfg(feature = "documents")]
+#[allow(missing_docs)] // for the items generated by `query_group`
+pub mod documents;
+#[cfg(feature = "fs")]
+#[allow(missing_docs)] // for the items generated by `query_group`
+pub mod fs;
 /// Items in this module are public for implementation reasons,
 /// and are exempt from the SemVer guarantees.
 #[doc(hidden)]
 pub mod plumbing;
+pub mod replay;
+pub mod testing;
 
-use crate::plumbing::CycleDetected;
+use crate::plumbing::DatabaseKey as _;
+use crate::plumbing::DerivedQueryStorageOps;
 use crate::plumbing::InputQueryStorageOps;
+use crate::plumbing::InternedQueryStorageOps;
 use crate::plumbing::LruQueryStorageOps;
+use crate::plumbing::PinQueryStorageOps;
 use crate::plumbing::QueryStorageMassOps;
 use crate::plumbing::QueryStorageOps;
+use crate::time::Instant;
 use derive_new::new;
+use std::any::Any;
 use std::fmt::{self, Debug};
 use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
 
+#[cfg(feature = "persist")]
+pub use crate::artifact::ArtifactBundle;
 pub use crate::durability::Durability;
+pub use crate::dyn_database::DynDatabase;
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::{
+    DynamicContext, DynamicQueryDatabase, DynamicQueryFn, DynamicQueryStorage, QueryByNameError,
+};
+pub use crate::fallible::Failed;
 pub use crate::intern_id::InternId;
 pub use crate::interned::InternKey;
+pub use crate::invalidation_token::InvalidationToken;
+pub use crate::maybe_send::{MaybeSend, MaybeSync};
+pub use crate::memory_usage::MemoryReport;
+pub use crate::peek_future::PeekFuture;
+#[cfg(feature = "persist")]
+pub use crate::persist::{FileMemoCache, MemoCache};
+pub use crate::prefetch::Prefetch;
+pub use crate::revision::Revision;
+pub use crate::revision_log::{InputChange, RevisionLogEntry};
+pub use crate::runtime::EventListener;
+pub use crate::runtime::Priority;
 pub use crate::runtime::Runtime;
 pub use crate::runtime::RuntimeId;
+pub use crate::runtime::ScheduleHook;
+pub use crate::runtime::SchedulePoint;
+pub use crate::runtime::SubscriptionId;
+pub use crate::send_only::SendOnly;
+pub use crate::statistics::QueryStatistics;
+pub use crate::statistics::StatisticsMode;
+pub use crate::statistics::WriteLockStatistics;
+pub use crate::storage_stats::QueryStorageStats;
+pub use crate::stream::Stream;
+pub use crate::value_guard::ValueGuard;
+pub use crate::value_store::ValueStore;
 
 /// The base trait which your "query context" must implement. Gives
 /// access to the salsa runtime, which you must embed into your query
@@ -57,6 +143,164 @@ pub trait Database: plumbing::DatabaseStorageTypes + plumbing::DatabaseOps {
         self.salsa_runtime().sweep_all(self, strategy);
     }
 
+    /// Enables automatic sweeping: from then on, each call to
+    /// `auto_sweep_tick` sweeps the database with the configured
+    /// strategy if it is worth it. Replaces any previous
+    /// configuration.
+    fn enable_auto_sweep(&self, config: SweepConfig) {
+        self.salsa_runtime().enable_auto_sweep(Some(config));
+    }
+
+    /// Disables automatic sweeping; `auto_sweep_tick` then does
+    /// nothing.
+    fn disable_auto_sweep(&self) {
+        self.salsa_runtime().enable_auto_sweep(None);
+    }
+
+    /// Drives automatic sweeping (see `enable_auto_sweep`); meant to
+    /// be called regularly, for example from an idle callback or a
+    /// timer of the application's event loop. Sweeps all query tables
+    /// (as `sweep_all` does) if
+    ///
+    /// - at least the configured interval has passed since the last
+    ///   sweep (or since sweeping was enabled);
+    /// - a new revision was created since then; and
+    /// - the database is idle: this is not a snapshot, no query is
+    ///   executing, and no snapshot is alive.
+    ///
+    /// Returns true if it swept.
+    fn auto_sweep_tick(&self) -> bool {
+        self.salsa_runtime().auto_sweep_tick(self)
+    }
+
+    /// Sweeps incrementally: each call only sweeps the slots that
+    /// `budget` allows, picking up where the previous call left off,
+    /// so that memory can be reclaimed during idle time without long
+    /// pauses. Returns true when this call completed a pass over all
+    /// the query tables; the next call then starts a new pass.
+    ///
+    /// The position reached is shared by the database and all its
+    /// snapshots, regardless of the strategy used. Tables that grow
+    /// between calls may have some of their slots swept twice, or not
+    /// at all, in a pass.
+    fn sweep_incremental(&self, strategy: SweepStrategy, budget: SweepBudget) -> bool {
+        self.salsa_runtime()
+            .sweep_incremental(self, strategy, budget)
+    }
+
+    /// Like `sweep_all`, but asks `policy` about each memo of a
+    /// derived query before the strategy is applied to it, letting it
+    /// keep memos that the strategy would discard or discard memos
+    /// that it would keep (see `SweepPolicy`).
+    fn sweep_all_with_policy(&self, strategy: SweepStrategy, policy: &dyn SweepPolicy) {
+        self.salsa_runtime()
+            .sweep_all_with_policy(self, strategy, policy);
+    }
+
+    /// Returns the execution statistics of all derived queries that
+    /// executed or were validated while statistics were enabled (see
+    /// [`Runtime::set_statistics_mode`]), the ones with the largest
+    /// execution time first.
+    ///
+    /// [`Runtime::set_statistics_mode`]: struct.Runtime.html#method.set_statistics_mode
+    fn salsa_statistics(&self) -> Vec<(&'static str, QueryStatistics)> {
+        self.salsa_runtime().statistics()
+    }
+
+    /// Estimates the memory used by each query table of the database,
+    /// the tables using the most memory first. See
+    /// [`QueryTable::memory_usage`]; the reports can be summed up to
+    /// get an estimate for the whole database.
+    ///
+    /// [`QueryTable::memory_usage`]: struct.QueryTable.html#method.memory_usage
+    fn salsa_memory_usage(&self) -> Vec<(&'static str, MemoryReport)> {
+        self.salsa_runtime().memory_usage(self)
+    }
+
+    /// Invokes `op` with the name and the slot counts of each derived,
+    /// input and interned query table of the database, in the order in
+    /// which the query groups and their queries are declared; see
+    /// [`QueryStorageStats`].
+    ///
+    /// [`QueryStorageStats`]: trait.QueryStorageStats.html
+    fn for_each_query_table(&self, op: impl FnMut(&'static str, &dyn QueryStorageStats)) {
+        self.for_each_query_stats(op)
+    }
+
+    /// Limits the memoized values of all derived queries together: once
+    /// their total cost exceeds `budget`, the least recently used
+    /// values are evicted, whichever table they belong to. The cost of
+    /// a value is given by the query's `#[salsa::lru_cost]` function,
+    /// or is 1 for queries without one (so that, if no query has a cost
+    /// function, `budget` is simply a number of values). This works in
+    /// addition to the capacities of the individual tables (see
+    /// `QueryTableMut::set_lru_capacity`).
+    ///
+    /// If `budget` is zero, the global limit is disabled; this is the
+    /// default.
+    fn set_global_lru_budget(&self, budget: usize) {
+        self.salsa_runtime().set_global_lru_budget(budget);
+    }
+
+    /// Returns why the given query last had to be (re-)executed
+    /// instead of reusing its memoized value. Reasons are only
+    /// recorded while invalidation tracing is enabled (see
+    /// [`Runtime::set_invalidation_tracing`]); returns `None` if
+    /// nothing was recorded for the query.
+    ///
+    /// [`Runtime::set_invalidation_tracing`]: struct.Runtime.html#method.set_invalidation_tracing
+    fn last_invalidation_reason(
+        &self,
+        database_key: &Self::DatabaseKey,
+    ) -> Option<InvalidationReason<Self::DatabaseKey>> {
+        self.salsa_runtime().last_invalidation_reason(database_key)
+    }
+
+    /// Invalidates `token`: creates a new revision, in which all the
+    /// queries that read the token (see `Runtime::report_read_of`) are
+    /// re-executed when they are next demanded.
+    fn invalidate_token(&mut self, token: &InvalidationToken) {
+        self.salsa_runtime().invalidate_token(token);
+    }
+
+    /// Watches the value of `query` for `key`: from now on, whenever
+    /// `poll_watches` finds that the value changed, `callback` is
+    /// invoked with the new value. See `QueryTable::watch`.
+    fn watch<Q>(
+        &self,
+        query: Q,
+        key: Q::Key,
+        callback: impl FnMut(&Q::Value) + MaybeSend + 'static,
+    ) -> SubscriptionId
+    where
+        Q: Query<Self>,
+        Q::Key: MaybeSend + 'static,
+        Self: plumbing::GetQueryTable<Q>,
+    {
+        self.query(query).watch(key, callback)
+    }
+
+    /// Specifies the value of `query` for `key`, from within the query
+    /// that computes it; see `QueryTable::specify`.
+    fn specify<Q>(&self, query: Q, key: Q::Key, value: Q::Value)
+    where
+        Q: Query<Self>,
+        Q::Storage: plumbing::DerivedQueryStorageOps<Self, Q>,
+        Self: plumbing::GetQueryTable<Q>,
+    {
+        self.query(query).specify(key, value)
+    }
+
+    /// Brings the queries watched with `watch` up to date, and invokes
+    /// the callbacks of those whose value changed since they were last
+    /// polled. Meant to be called after applying changes to the
+    /// inputs, or regularly from an idle callback of the application's
+    /// event loop; watches are only polled once per revision. Returns
+    /// the number of callbacks that were invoked.
+    fn poll_watches(&self) -> usize {
+        self.salsa_runtime().poll_watches(self)
+    }
+
     /// Get access to extra methods pertaining to a given query. For
     /// example, you can use this to run the GC (`sweep`) across a
     /// single input. You can also use it to invoke a query, though
@@ -90,13 +334,19 @@ pub trait Database: plumbing::DatabaseStorageTypes + plumbing::DatabaseOps {
     /// deadlock.
     ///
     /// Before blocking, the thread that is attempting to `set` will
-    /// also set a cancellation flag. In the threads operating on
-    /// snapshots, you can use the [`is_current_revision_canceled`]
-    /// method to check for this flag and bring those operations to a
-    /// close, thus allowing the `set` to succeed. Ignoring this flag
-    /// may lead to "starvation", meaning that the thread attempting
-    /// to `set` has to wait a long, long time. =)
+    /// also set a cancellation flag. Threads operating on snapshots
+    /// observe this flag automatically: the next time they would have
+    /// to execute (or revalidate) a derived query, they unwind with a
+    /// [`Cancelled`] payload instead. Long-running queries can also
+    /// check for the flag themselves, using
+    /// [`unwind_if_cancelled`] or [`is_current_revision_canceled`],
+    /// and bring those operations to a close, thus allowing the `set`
+    /// to succeed. Ignoring this flag may lead to "starvation",
+    /// meaning that the thread attempting to `set` has to wait a
+    /// long, long time. =)
     ///
+    /// [`Cancelled`]: struct.Cancelled.html
+    /// [`unwind_if_cancelled`]: struct.Runtime.html#method.unwind_if_cancelled
     /// [`is_current_revision_canceled`]: struct.Runtime.html#method.is_current_revision_canceled
     #[allow(unused_variables)]
     fn query_mut<Q>(&mut self, query: Q) -> QueryTableMut<'_, Self, Q>
@@ -107,23 +357,262 @@ pub trait Database: plumbing::DatabaseStorageTypes + plumbing::DatabaseOps {
         <Self as plumbing::GetQueryTable<Q>>::get_query_table_mut(self)
     }
 
+    /// Applies all the input writes performed by `op` in a single new
+    /// revision, rather than creating one revision (and triggering
+    /// one round of cancellation) per write. This matters for large
+    /// updates, e.g. applying an edit that touches many files:
+    ///
+    /// ```ignore
+    /// db.transaction(|db| {
+    ///     db.set_file_text(a, text_a);
+    ///     db.set_file_text(b, text_b);
+    /// });
+    /// ```
+    ///
+    /// Reading derived queries (or creating a snapshot) within `op`
+    /// is allowed, but it observes only the writes performed so far;
+    /// the writes that follow then go into another new revision.
+    /// Transactions can be nested, in which case the outermost one
+    /// determines the batch.
+    fn transaction<R>(&mut self, op: impl FnOnce(&mut Self) -> R) -> R
+    where
+        Self: Sized,
+    {
+        let nested = self.salsa_runtime().begin_transaction();
+        let result = op(self);
+        self.salsa_runtime().end_transaction(nested);
+        result
+    }
+
+    /// Reverts the writes to inputs made in the most recent revision
+    /// that is still in the journal (see
+    /// `Runtime::set_journal_capacity`), restoring the old values (and
+    /// durabilities) of the inputs, or removing the ones that had no
+    /// value. The writes are reverted in a single new revision, like a
+    /// transaction. Returns false if there is nothing to undo.
+    ///
+    /// Steps that were undone can be redone with `redo`, until an
+    /// input is written otherwise.
+    fn undo(&mut self) -> bool {
+        self.salsa_runtime().replay_journal(self, true)
+    }
+
+    /// Reapplies the writes of the step most recently reverted by
+    /// `undo`, in a single new revision. Returns false if there is
+    /// nothing to redo.
+    fn redo(&mut self) -> bool {
+        self.salsa_runtime().replay_journal(self, false)
+    }
+
+    /// Writes the steps in the journal (see
+    /// `Runtime::set_journal_capacity`) to `out`, oldest first, with
+    /// one line per write: the revision, the database-key of the input,
+    /// and its old and new values and durabilities (`-` if there was
+    /// no value), using their `Debug` representation. Attached to a bug
+    /// report, this lets you reconstruct the inputs that led to the
+    /// problem.
+    fn dump_journal(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.salsa_runtime().dump_journal(self, out)
+    }
+
+    /// Writes the memoized results of all queries marked with
+    /// `#[salsa::persist]` to `writer`, so that they can be loaded
+    /// into a new database using [`deserialize_memos`]. Memoized
+    /// values that read untracked inputs are not saved, and neither are
+    /// values that depend on queries that are not persisted.
+    ///
+    /// Requires the `persist` feature; the keys and values of the
+    /// persisted queries must implement `Serialize` and `Deserialize`.
+    ///
+    /// [`deserialize_memos`]: trait.Database.html#method.deserialize_memos
+    #[cfg(feature = "persist")]
+    fn serialize_memos(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        persist::serialize_memos(self, writer)
+    }
+
+    /// Loads memoized results written by [`serialize_memos`]. This
+    /// must be done before any input is set (or value interned), and
+    /// afterwards the database continues from the revision in which
+    /// the memos were saved. Loaded values are revalidated as usual:
+    /// when you subsequently set the inputs to their current values,
+    /// only the queries that depend on inputs that actually changed
+    /// will have to produce a different result.
+    ///
+    /// Tables of queries that are not (or no longer) persisted are
+    /// ignored.
+    ///
+    /// [`serialize_memos`]: trait.Database.html#method.serialize_memos
+    #[cfg(feature = "persist")]
+    fn deserialize_memos(&mut self, reader: &mut impl std::io::Read) -> std::io::Result<()> {
+        persist::deserialize_memos(&*self, reader)
+    }
+
+    /// Writes the memoized results of the persisted queries (see
+    /// [`serialize_memos`]) to a cache shared with other processes,
+    /// replacing the memos published there before.
+    ///
+    /// [`serialize_memos`]: trait.Database.html#method.serialize_memos
+    #[cfg(feature = "persist")]
+    fn publish_memos(&self, cache: &impl MemoCache) -> std::io::Result<()> {
+        let mut memos = vec![];
+        self.serialize_memos(&mut memos)?;
+        cache.publish(&memos)
+    }
+
+    /// Loads the memos most recently published to `cache` by another
+    /// process (see [`publish_memos`]), so that this process reuses
+    /// their results. As with [`deserialize_memos`], this must be done
+    /// before any input is set; the revisions of the memos are mapped
+    /// onto the history of this database. Returns false if nothing was
+    /// published yet.
+    ///
+    /// [`publish_memos`]: trait.Database.html#method.publish_memos
+    /// [`deserialize_memos`]: trait.Database.html#method.deserialize_memos
+    #[cfg(feature = "persist")]
+    fn attach_memo_cache(&mut self, cache: &impl MemoCache) -> std::io::Result<bool> {
+        match cache.fetch()? {
+            Some(memos) => {
+                self.deserialize_memos(&mut &memos[..])?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Exports the values of `query` for `keys` as an artifact bundle,
+    /// which another process can import with [`import_results`]; see
+    /// `QueryTable::export_results`.
+    ///
+    /// [`import_results`]: trait.Database.html#method.import_results
+    #[cfg(feature = "persist")]
+    fn export_results<Q>(
+        &self,
+        query: Q,
+        keys: impl IntoIterator<Item = Q::Key>,
+    ) -> std::io::Result<ArtifactBundle>
+    where
+        Q: Query<Self>,
+        Q::Key: serde::Serialize,
+        Q::Value: serde::Serialize,
+        Q::Storage: plumbing::DerivedQueryStorageOps<Self, Q>,
+        Self: plumbing::GetQueryTable<Q>,
+    {
+        self.query(query).export_results(keys)
+    }
+
+    /// Imports the values of `query` from a bundle written by
+    /// [`export_results`], for as long as the inputs they were computed
+    /// from still have the same values; see `QueryTable::import_results`.
+    ///
+    /// [`export_results`]: trait.Database.html#method.export_results
+    #[cfg(feature = "persist")]
+    fn import_results<Q>(&self, query: Q, bundle: &ArtifactBundle) -> std::io::Result<usize>
+    where
+        Q: Query<Self>,
+        Q::Key: serde::de::DeserializeOwned,
+        Q::Value: serde::de::DeserializeOwned,
+        Q::Storage: plumbing::DerivedQueryStorageOps<Self, Q>,
+        Self: plumbing::GetQueryTable<Q>,
+    {
+        self.query(query).import_results(bundle)
+    }
+
+    /// Invokes the query named `name` (as declared in its query
+    /// group) on the given arguments, which must be a JSON array with
+    /// one element per key of the query, and returns its value as
+    /// JSON. For example, `db.query_by_name("type_of", json!([42]))`
+    /// is equivalent to `db.type_of(42)`. This is meant for tools like
+    /// debuggers that do not know the query types at compile time.
+    ///
+    /// Only queries marked `#[salsa::dynamic]` can be invoked this
+    /// way; requires the `dynamic` feature of salsa. If several query
+    /// groups have a query of the same name, the one in the group
+    /// listed first in `#[salsa::database]` is invoked.
+    #[cfg(feature = "dynamic")]
+    fn query_by_name(
+        &self,
+        name: &str,
+        args: serde_json::Value,
+    ) -> Result<serde_json::Value, QueryByNameError> {
+        self.invoke_by_name(name, &args)
+            .unwrap_or_else(|| Err(QueryByNameError::UnknownQuery(name.to_string())))
+    }
+
+    /// Registers `function` as the function of the dynamic query
+    /// `name`, replacing the previous one (if any); see
+    /// `DynamicQueryDatabase`. This creates a new revision, in which
+    /// the queries that invoked `name` (or tried to, before it was
+    /// registered) are re-executed. Requires the `dynamic` feature of
+    /// salsa.
+    #[cfg(feature = "dynamic")]
+    fn register_dynamic_query(
+        &mut self,
+        name: &str,
+        function: impl Fn(&dyn DynamicContext, &serde_json::Value) -> serde_json::Value
+            + Send
+            + Sync
+            + 'static,
+    ) where
+        Self: DynamicQueryDatabase,
+    {
+        self.salsa_runtime()
+            .dynamic_queries()
+            .insert(name.to_string(), Arc::new(function));
+        let generation = self.maybe_dynamic_query_generation(name.to_string());
+        self.set_dynamic_query_generation(name.to_string(), generation.unwrap_or(0) + 1);
+    }
+
+    /// Panics if a derived query is marked as in progress by a runtime
+    /// (a snapshot, typically) that was dropped, which happens if a
+    /// thread died without unwinding through salsa while executing a
+    /// query; the message names the queries. Any thread reading such a
+    /// query would otherwise block until the next liveness check (see
+    /// `Runtime::set_liveness_check_interval`). Meant for debugging.
+    fn assert_no_dangling_in_progress(&self) {
+        if let Some(dangling) = self.salsa_runtime().find_dangling_in_progress(self) {
+            panic!("{}", dangling);
+        }
+    }
+
     /// This function is invoked at key points in the salsa
     /// runtime. It permits the database to be customized and to
-    /// inject logging or other custom behavior.
+    /// inject logging or other custom behavior. To observe events
+    /// from several places, see also `Runtime::subscribe_events`.
+    ///
+    /// With the `strip-logging` feature of salsa, this is never
+    /// invoked, and salsa does not log anything either: the code that
+    /// builds the events and the log messages is compiled out.
     fn salsa_event(&self, event_fn: impl Fn() -> Event<Self>) {
         #![allow(unused_variables)]
     }
 
     /// This function is invoked when a dependent query is being computed by the
-    /// other thread, and that thread panics.
+    /// other thread, and that thread panics, if `propagated_panic_policy`
+    /// is `PropagatedPanicPolicy::Diverge`.
     fn on_propagated_panic(&self) -> ! {
         panic!("concurrent salsa query panicked")
     }
+
+    /// Decides what a read does when the query it is blocked on panics
+    /// in another thread: by default, it invokes `on_propagated_panic`.
+    /// Returning `PropagatedPanicPolicy::Error` instead makes the read
+    /// unwind with a [`QueryPanicked`] payload carrying the key of that
+    /// query, which [`QueryPanicked::catch`] turns into an error where
+    /// you invoke queries from outside of salsa. This way, a server can
+    /// fail the requests that waited on the query, and keep serving
+    /// others.
+    ///
+    /// [`QueryPanicked`]: struct.QueryPanicked.html
+    /// [`QueryPanicked::catch`]: struct.QueryPanicked.html#method.catch
+    fn propagated_panic_policy(&self) -> PropagatedPanicPolicy {
+        PropagatedPanicPolicy::Diverge
+    }
 }
 
 /// The `Event` struct identifies various notable things that can
 /// occur during salsa execution. Instances of this struct are given
-/// to `salsa_event`.
+/// to `salsa_event` and to listeners registered with
+/// `Runtime::subscribe_events`.
 pub struct Event<DB: Database> {
     /// The id of the snapshot that triggered the event.  Usually
     /// 1-to-1 with a thread, as well.
@@ -142,6 +631,14 @@ impl<DB: Database> fmt::Debug for Event<DB> {
     }
 }
 
+/// Describes the event for end users, with the `Display` output of the
+/// database-key (see `#[salsa::display_key]`).
+impl<DB: Database> fmt::Display for Event<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:?}: {}", self.runtime_id, self.kind)
+    }
+}
+
 /// An enum identifying the various kinds of events that can occur.
 pub enum EventKind<DB: Database> {
     /// Occurs when we found that all inputs to a memoized value are
@@ -185,6 +682,25 @@ pub enum EventKind<DB: Database> {
         /// The database-key for the affected value. Implements `Debug`.
         database_key: DB::DatabaseKey,
     },
+
+    /// Indicates that the memoized value of this query was evicted
+    /// because an LRU list (of the query's table, or the one of the
+    /// whole database) was full. Its dependencies are kept.
+    DidEvictValue {
+        /// The database-key for the affected value. Implements `Debug`.
+        database_key: DB::DatabaseKey,
+    },
+
+    /// Indicates that a sweep (see `SweepStrategy`) discarded the
+    /// memoized value of this query, or its whole memo.
+    DidSweep {
+        /// The database-key for the affected value. Implements `Debug`.
+        database_key: DB::DatabaseKey,
+
+        /// What was discarded; either `DiscardWhat::Values` or
+        /// `DiscardWhat::Everything`.
+        discarded: DiscardWhat,
+    },
 }
 
 impl<DB: Database> fmt::Debug for EventKind<DB> {
@@ -210,6 +726,51 @@ impl<DB: Database> fmt::Debug for EventKind<DB> {
                 .debug_struct("WillExecute")
                 .field("database_key", database_key)
                 .finish(),
+            EventKind::DidEvictValue { database_key } => fmt
+                .debug_struct("DidEvictValue")
+                .field("database_key", database_key)
+                .finish(),
+            EventKind::DidSweep {
+                database_key,
+                discarded,
+            } => fmt
+                .debug_struct("DidSweep")
+                .field("database_key", database_key)
+                .field("discarded", discarded)
+                .finish(),
+        }
+    }
+}
+
+impl<DB: Database> fmt::Display for EventKind<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::DidValidateMemoizedValue { database_key } => {
+                write!(fmt, "reusing {}", database_key)
+            }
+            EventKind::WillBlockOn {
+                other_runtime_id,
+                database_key,
+            } => write!(
+                fmt,
+                "waiting for {:?} to compute {}",
+                other_runtime_id, database_key
+            ),
+            EventKind::WillChangeInputValue { database_key } => {
+                write!(fmt, "changing {}", database_key)
+            }
+            EventKind::WillExecute { database_key } => write!(fmt, "computing {}", database_key),
+            EventKind::DidEvictValue { database_key } => {
+                write!(fmt, "evicted the value of {}", database_key)
+            }
+            EventKind::DidSweep {
+                database_key,
+                discarded: DiscardWhat::Values,
+            } => write!(fmt, "swept the value of {}", database_key),
+            EventKind::DidSweep {
+                database_key,
+                discarded: _,
+            } => write!(fmt, "swept {}", database_key),
         }
     }
 }
@@ -227,10 +788,19 @@ impl Default for DiscardIf {
     }
 }
 
+/// What a sweep discards from the memos it collects; see
+/// `SweepStrategy` and `EventKind::DidSweep`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-enum DiscardWhat {
+pub enum DiscardWhat {
+    /// Nothing is discarded.
     Nothing,
+
+    /// Only the memoized values are discarded; the dependencies are
+    /// kept, so that queries depending on the memo can still be
+    /// validated.
     Values,
+
+    /// The whole memo is discarded.
     Everything,
 }
 
@@ -240,6 +810,79 @@ impl Default for DiscardWhat {
     }
 }
 
+/// Decides which memos a sweep discards, with more precision than a
+/// `SweepStrategy` offers; see `Database::sweep_all_with_policy` and
+/// `QueryTable::sweep_with_policy`. For example, an IDE can keep the
+/// results computed for the files that are open, and discard the rest
+/// no matter how recently they were used.
+///
+/// The policy is consulted for the memos of derived queries only.
+/// Memos that are being computed, or that belong to pinned keys, are
+/// never discarded, and neither are memos that read untracked inputs
+/// and were verified in the current revision.
+pub trait SweepPolicy {
+    /// Decides what to do with the memo of the query key `key`
+    /// (which is only available through its `Debug` representation),
+    /// described by `info`.
+    fn should_discard(&self, key: &dyn Debug, info: &SweepInfo) -> Discard;
+}
+
+/// What a `SweepPolicy` decides to do with a memo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discard {
+    /// Keep the memo, whatever the sweep strategy says.
+    Keep,
+
+    /// Apply the sweep strategy to the memo, as a sweep without a
+    /// policy would.
+    FollowStrategy,
+
+    /// Discard what the sweep strategy discards (see
+    /// `SweepStrategy::discard_values` and `discard_everything`), even
+    /// if the strategy would keep the memo because it was verified in
+    /// the current revision or read recently.
+    Anyway,
+}
+
+/// The state of a memo, as given to `SweepPolicy::should_discard`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SweepInfo {
+    /// The name of the query.
+    pub query_name: &'static str,
+    /// Revision in which the value last changed.
+    pub changed_at: Revision,
+    /// Revision in which the value was last verified to be up to date.
+    pub verified_at: Revision,
+    /// Durability of the value.
+    pub durability: Durability,
+    /// Time since the value was last read (or computed).
+    pub idle_for: Duration,
+    /// True if the value was computed by reading untracked inputs.
+    pub has_untracked_input: bool,
+}
+
+/// Configures automatic sweeping; see `Database::enable_auto_sweep`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepConfig {
+    /// The minimum time between two sweeps.
+    pub interval: Duration,
+
+    /// The strategy to sweep with.
+    pub strategy: SweepStrategy,
+}
+
+/// How much work a call to `Database::sweep_incremental` may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepBudget {
+    /// Sweep at most this many slots.
+    Slots(usize),
+
+    /// Sweep slots (in small batches) until this much time has
+    /// passed.
+    Time(Duration),
+}
+
 /// The sweep strategy controls what data we will keep/discard when we
 /// do a GC-sweep. The default (`SweepStrategy::default`) is a no-op,
 /// use `SweepStrategy::discard_outdated` constructor or `discard_*`
@@ -248,6 +891,7 @@ impl Default for DiscardWhat {
 pub struct SweepStrategy {
     discard_if: DiscardIf,
     discard_what: DiscardWhat,
+    min_idle: Option<Duration>,
 }
 
 impl SweepStrategy {
@@ -261,6 +905,18 @@ impl SweepStrategy {
             .sweep_outdated()
     }
 
+    /// Convenience function that discards the values of derived
+    /// queries that have not been read for at least `duration`,
+    /// whichever revision they were last verified in.
+    ///
+    /// Equivalent to `SweepStrategy::default().discard_values().sweep_all_revisions().sweep_idle_for(duration)`.
+    pub fn discard_older_than(duration: Duration) -> SweepStrategy {
+        SweepStrategy::default()
+            .discard_values()
+            .sweep_all_revisions()
+            .sweep_idle_for(duration)
+    }
+
     /// Collects query values.
     ///
     /// Query dependencies are left in the database, which allows to quickly
@@ -299,6 +955,16 @@ impl SweepStrategy {
             ..self
         }
     }
+
+    /// Of the keys that would otherwise be processed, only process
+    /// those of derived queries whose value has not been read for at
+    /// least `duration`. Interned keys are not affected.
+    pub fn sweep_idle_for(self, duration: Duration) -> SweepStrategy {
+        SweepStrategy {
+            min_idle: Some(self.min_idle.map_or(duration, |d| d.max(duration))),
+            ..self
+        }
+    }
 }
 
 /// Indicates a database that also supports parallel query
@@ -309,9 +975,10 @@ pub trait ParallelDatabase: Database + Send {
     /// Creates a second handle to the database that holds the
     /// database fixed at a particular revision. So long as this
     /// "frozen" handle exists, any attempt to [`set`] an input will
-    /// block.
+    /// block (see also [`Runtime::live_snapshots`]).
     ///
-    /// [`set`]: struct.QueryTable.html#method.set
+    /// [`set`]: struct.QueryTableMut.html#method.set
+    /// [`Runtime::live_snapshots`]: struct.Runtime.html#method.live_snapshots
     ///
     /// This is the method you are meant to use most of the time in a
     /// parallel setting where modifications may arise asynchronously
@@ -367,6 +1034,69 @@ pub trait ParallelDatabase: Database + Send {
     /// }
     /// ```
     fn snapshot(&self) -> Snapshot<Self>;
+
+    /// Like `snapshot`, but the snapshot does not hold the database
+    /// fixed: it keeps observing the current revision as of its
+    /// creation, while `set` on the database proceeds without waiting
+    /// for it (and without canceling its queries). This suits
+    /// long-lived readers, like an export or a diagnostics pass, that
+    /// should neither block edits nor be canceled by them.
+    ///
+    /// Once the database has moved on, only queries declared with
+    /// `#[salsa::multi_version]` can still be read from the snapshot:
+    /// their values are kept for as long as a snapshot may read them.
+    /// Reading any other query panics then, so do mark all the queries
+    /// that the reader (transitively) reads. Only `get`, `get_ref` and
+    /// `get_maybe` observe the pinned revision; other methods, like
+    /// `peek` and `peek_or_wait`, observe the database as it is now.
+    ///
+    /// Snapshots of this snapshot are pinned at the same revision.
+    fn snapshot_at_current_revision(&self) -> Snapshot<Self> {
+        self.salsa_runtime().pin_snapshot(|| self.snapshot())
+    }
+
+    /// Fetches the given queries on background threads, each using a
+    /// snapshot of the database, so that they are already up to date
+    /// when they are next read. This is typically called right after
+    /// setting inputs, with the "root" queries that the next request
+    /// is likely to need; use `QueryTable::database_key` to name them.
+    /// Only derived queries are fetched; other keys are ignored.
+    ///
+    /// The workers run with `Priority::Background`: a foreground read
+    /// of a query they are computing takes the query over. They stop
+    /// as soon as a new revision is pending (like other snapshots,
+    /// they block `set` until then), so setting an input cancels the
+    /// prefetch. Panics raised by the prefetched
+    /// queries are discarded. Like `snapshot`, this may not be called
+    /// from inside a query.
+    fn prefetch(&self, database_keys: impl IntoIterator<Item = Self::DatabaseKey>) -> Prefetch
+    where
+        Self: 'static,
+        Self::DatabaseKey: Send,
+    {
+        prefetch::spawn(self, database_keys.into_iter().collect())
+    }
+
+    /// Enables (or, given `None`, disables) checking the inputs of a
+    /// memoized value in parallel, when the value is validated in a
+    /// new revision and has at least `min_inputs` inputs. The inputs
+    /// are then checked by worker threads, each using a snapshot of
+    /// the database, and the check stops as soon as one of them finds
+    /// an input that changed. This pays off for memos with hundreds of
+    /// inputs, as spawning the workers costs more than checking a few
+    /// inputs. The setting is shared with all snapshots of the
+    /// database.
+    ///
+    /// The workers do not block on queries that other threads are
+    /// computing; the thread that validates the memo checks those
+    /// inputs itself once the workers are done.
+    fn set_parallel_revalidation(&self, min_inputs: Option<usize>)
+    where
+        Self::DatabaseData: Send + Sync,
+    {
+        self.salsa_runtime()
+            .set_parallel_revalidation(min_inputs.map(revalidate::ParallelRevalidation::new));
+    }
 }
 
 /// Simple wrapper struct that takes ownership of a database `DB` and
@@ -394,6 +1124,29 @@ where
     }
 }
 
+/// Cloning a snapshot creates a new snapshot of the same database,
+/// with its own runtime id, so that it can be handed to another
+/// thread. This makes snapshots usable with thread pools that clone
+/// their per-worker state. For example, with rayon:
+///
+/// ```rust,ignore
+/// let results: Vec<_> = keys
+///     .par_iter()
+///     .map_with(db.snapshot(), |db, &key| db.my_query(key))
+///     .collect();
+/// ```
+///
+/// The workers then block on (and detect cycles with) one another
+/// like any other snapshots would.
+impl<DB> Clone for Snapshot<DB>
+where
+    DB: ParallelDatabase,
+{
+    fn clone(&self) -> Self {
+        self.db.snapshot()
+    }
+}
+
 impl<DB> std::ops::Deref for Snapshot<DB>
 where
     DB: ParallelDatabase,
@@ -405,94 +1158,1028 @@ where
     }
 }
 
-/// Trait implements by all of the "special types" associated with
-/// each of your queries.
+/// A panic payload indicating that execution of a salsa query was
+/// cancelled because a new revision is pending. Salsa throws this
+/// (via `std::panic::resume_unwind`) from [`unwind_if_cancelled`]
+/// and whenever a snapshot would otherwise execute a query whose
+/// result is already known to be stale.
 ///
-/// Unsafe trait obligation: Asserts that the Key/Value associated
-/// types for this trait are a part of the `Group::GroupData` type.
-/// In particular, `Group::GroupData: Send + Sync` must imply that
-/// `Key: Send + Sync` and `Value: Send + Sync`. This is relied upon
-/// by the dependency tracking logic.
-pub unsafe trait Query<DB: Database>: Debug + Default + Sized + 'static {
-    /// Type that you you give as a parameter -- for queries with zero
-    /// or more than one input, this will be a tuple.
-    type Key: Clone + Debug + Hash + Eq;
+/// The payload is meant to be caught at the boundary where you invoke
+/// queries from outside of salsa, typically with [`Cancelled::catch`].
+/// Catching it *inside* of a query is not advisable, as that query
+/// would then memoize a result derived from a cancelled computation.
+///
+/// [`unwind_if_cancelled`]: struct.Runtime.html#method.unwind_if_cancelled
+/// [`Cancelled::catch`]: struct.Cancelled.html#method.catch
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cancelled;
 
-    /// What value does the query return?
-    type Value: Clone + Debug;
+impl Cancelled {
+    pub(crate) fn throw() -> ! {
+        // We use resume and not panic here to avoid running the panic
+        // hook (that is, to avoid collecting and printing backtrace).
+        std::panic::resume_unwind(Box::new(Cancelled))
+    }
 
-    /// Internal struct storing the values for the query.
-    type Storage: plumbing::QueryStorageOps<DB, Self>;
+    /// Runs `f`, and catches any salsa cancellation. Panics with
+    /// other payloads are propagated unchanged.
+    pub fn catch<F, T>(f: F) -> Result<T, Cancelled>
+    where
+        F: FnOnce() -> T + std::panic::UnwindSafe,
+    {
+        match std::panic::catch_unwind(f) {
+            Ok(t) => Ok(t),
+            Err(payload) => match payload.downcast::<Cancelled>() {
+                Ok(cancelled) => Err(*cancelled),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
+}
 
-    /// Associate query group struct.
-    type Group: plumbing::QueryGroup<
-        DB,
-        GroupStorage = Self::GroupStorage,
-        GroupKey = Self::GroupKey,
-    >;
+impl fmt::Display for Cancelled {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("cancelled because of a pending write")
+    }
+}
 
-    /// Generated struct that contains storage for all queries in a group.
-    type GroupStorage;
+/// A panic payload indicating that a query ran past the time limit set
+/// with [`Runtime::set_query_timeout`]. Like [`Cancelled`], it is
+/// meant to be caught where you invoke queries from outside of salsa,
+/// typically with [`QueryTimedOut::catch`].
+///
+/// [`Runtime::set_query_timeout`]: struct.Runtime.html#method.set_query_timeout
+/// [`Cancelled`]: struct.Cancelled.html
+/// [`QueryTimedOut::catch`]: struct.QueryTimedOut.html#method.catch
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QueryTimedOut;
 
-    /// Type that identifies a particular query within the group + its key.
-    type GroupKey;
+impl QueryTimedOut {
+    pub(crate) fn throw() -> ! {
+        std::panic::resume_unwind(Box::new(QueryTimedOut))
+    }
 
-    /// Extact storage for this query from the storage for its group.
-    fn query_storage(group_storage: &Self::GroupStorage) -> &Self::Storage;
+    /// Runs `f`, and catches any salsa query timeout. Panics with
+    /// other payloads are propagated unchanged.
+    pub fn catch<F, T>(f: F) -> Result<T, QueryTimedOut>
+    where
+        F: FnOnce() -> T + std::panic::UnwindSafe,
+    {
+        match std::panic::catch_unwind(f) {
+            Ok(t) => Ok(t),
+            Err(payload) => match payload.downcast::<QueryTimedOut>() {
+                Ok(timed_out) => Err(*timed_out),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
+}
 
-    /// Create group key for this query.
-    fn group_key(key: Self::Key) -> Self::GroupKey;
+impl fmt::Display for QueryTimedOut {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("query timed out")
+    }
 }
 
-/// Return value from [the `query` method] on `Database`.
-/// Gives access to various less common operations on queries.
+/// A panic payload indicating that executing a query would have
+/// nested queries deeper than the limit set with
+/// [`Runtime::set_recursion_limit`]. Catch it with
+/// [`RecursionLimitExceeded::catch`] to find out which queries were
+/// involved.
 ///
-/// [the `query_mut` method]: trait.Database#method.query
-#[derive(new)]
-pub struct QueryTable<'me, DB, Q>
-where
-    DB: plumbing::GetQueryTable<Q>,
-    Q: Query<DB> + 'me,
-{
-    db: &'me DB,
-    storage: &'me Q::Storage,
+/// [`Runtime::set_recursion_limit`]: struct.Runtime.html#method.set_recursion_limit
+/// [`RecursionLimitExceeded::catch`]: struct.RecursionLimitExceeded.html#method.catch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecursionLimitExceeded {
+    limit: usize,
+    queries: Vec<String>,
 }
 
-impl<DB, Q> QueryTable<'_, DB, Q>
-where
-    DB: plumbing::GetQueryTable<Q>,
-    Q: Query<DB>,
-{
-    /// Execute the query on a given input. Usually it's easier to
-    /// invoke the trait method directly. Note that for variadic
-    /// queries (those with no inputs, or those with more than one
-    /// input) the key will be a tuple.
-    pub fn get(&self, key: Q::Key) -> Q::Value {
-        self.storage
-            .try_fetch(self.db, &key)
-            .unwrap_or_else(|CycleDetected| {
-                let database_key = self.database_key(&key);
-                self.db
-                    .salsa_runtime()
-                    .report_unexpected_cycle(database_key)
-            })
+impl RecursionLimitExceeded {
+    /// How many innermost queries are named in the error.
+    pub(crate) const REPORTED_QUERIES: usize = 10;
+
+    pub(crate) fn throw(limit: usize, queries: Vec<String>) -> ! {
+        std::panic::resume_unwind(Box::new(RecursionLimitExceeded { limit, queries }))
     }
 
-    /// Remove all values for this query that have not been used in
-    /// the most recent revision.
-    pub fn sweep(&self, strategy: SweepStrategy)
+    /// Runs `f`, and catches the error if the recursion limit is
+    /// exceeded. Panics with other payloads are propagated unchanged.
+    pub fn catch<F, T>(f: F) -> Result<T, RecursionLimitExceeded>
     where
-        Q::Storage: plumbing::QueryStorageMassOps<DB>,
+        F: FnOnce() -> T + std::panic::UnwindSafe,
     {
-        self.storage.sweep(self.db, strategy);
+        match std::panic::catch_unwind(f) {
+            Ok(t) => Ok(t),
+            Err(payload) => match payload.downcast::<RecursionLimitExceeded>() {
+                Ok(exceeded) => Err(*exceeded),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
     }
 
-    fn database_key(&self, key: &Q::Key) -> DB::DatabaseKey {
-        <DB as plumbing::GetQueryTable<Q>>::database_key(&self.db, key.clone())
+    /// The recursion limit that was exceeded.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The (displayed) database-keys of the innermost queries,
+    /// outermost first; the last one is the query that could not be
+    /// executed.
+    pub fn queries(&self) -> &[String] {
+        &self.queries
     }
 }
 
-/// Return value from [the `query_mut` method] on `Database`.
+impl fmt::Display for RecursionLimitExceeded {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            fmt,
+            "query recursion limit of {} exceeded; innermost queries:",
+            self.limit
+        )?;
+        for query in &self.queries {
+            writeln!(fmt, "- {}", query)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RecursionLimitExceeded {}
+
+/// The error returned by a query marked `#[salsa::catch_panics]` if its
+/// function panicked. It is memoized like any other value, for the
+/// current revision.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QueryPanic {
+    message: String,
+}
+
+impl QueryPanic {
+    /// Makes an error of the payload of a panic, unless it is one that
+    /// salsa uses to unwind, which must not be caught.
+    pub(crate) fn from_payload<DB: Database>(payload: &(dyn Any + Send)) -> Option<Self> {
+        if payload.is::<Cancelled>()
+            || payload.is::<QueryTimedOut>()
+            || payload.is::<RecursionLimitExceeded>()
+            || payload.is::<DanglingInProgress>()
+        {
+            return None;
+        }
+        let message = if let Some(message) = payload.downcast_ref::<&'static str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else if let Some(message) = DB::DatabaseKey::query_panicked_message(payload) {
+            message
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+        Some(QueryPanic { message })
+    }
+
+    /// The message of the panic, if it had one.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for QueryPanic {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "query panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for QueryPanic {}
+
+/// What a read does when the query it is blocked on panics in another
+/// thread; see `Database::propagated_panic_policy`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PropagatedPanicPolicy {
+    /// Invoke `Database::on_propagated_panic`.
+    #[default]
+    Diverge,
+
+    /// Unwind with a [`QueryPanicked`] payload.
+    ///
+    /// [`QueryPanicked`]: struct.QueryPanicked.html
+    Error,
+}
+
+/// A panic payload indicating that a query that the current thread was
+/// blocked on panicked in another thread, if the database chose this
+/// with [`Database::propagated_panic_policy`]. Like [`Cancelled`], it
+/// is meant to be caught where you invoke queries from outside of
+/// salsa, with [`QueryPanicked::catch`]. It carries the `DatabaseKey`
+/// of that query.
+///
+/// [`Database::propagated_panic_policy`]: trait.Database.html#method.propagated_panic_policy
+/// [`Cancelled`]: struct.Cancelled.html
+/// [`QueryPanicked::catch`]: struct.QueryPanicked.html#method.catch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryPanicked<K> {
+    database_key: K,
+}
+
+impl<K: Send + 'static> QueryPanicked<K> {
+    pub(crate) fn throw(database_key: K) -> ! {
+        std::panic::resume_unwind(Box::new(QueryPanicked { database_key }))
+    }
+
+    /// Runs `f`, and catches the error if a query that it was blocked
+    /// on panicked. Panics with other payloads are propagated
+    /// unchanged. `K` is the `DatabaseKey` of the database, that is,
+    /// `<DB as salsa::plumbing::DatabaseStorageTypes>::DatabaseKey`.
+    pub fn catch<F, T>(f: F) -> Result<T, QueryPanicked<K>>
+    where
+        F: FnOnce() -> T + std::panic::UnwindSafe,
+    {
+        match std::panic::catch_unwind(f) {
+            Ok(t) => Ok(t),
+            Err(payload) => match payload.downcast::<QueryPanicked<K>>() {
+                Ok(panicked) => Err(*panicked),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
+}
+
+impl<K> QueryPanicked<K> {
+    /// The database-key of the query that panicked.
+    pub fn database_key(&self) -> &K {
+        &self.database_key
+    }
+}
+
+impl<K: fmt::Display> fmt::Display for QueryPanicked<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "concurrent salsa query {} panicked", self.database_key)
+    }
+}
+
+impl<K: fmt::Debug + fmt::Display> std::error::Error for QueryPanicked<K> {}
+
+/// A panic payload indicating that queries are stuck in progress in a
+/// runtime that no longer exists, so that their values will never be
+/// produced. A thread blocked on such a query unwinds with this payload
+/// (see [`Runtime::set_liveness_check_interval`]), and
+/// [`Database::assert_no_dangling_in_progress`] panics with its
+/// `Display` output.
+///
+/// [`Runtime::set_liveness_check_interval`]: struct.Runtime.html#method.set_liveness_check_interval
+/// [`Database::assert_no_dangling_in_progress`]: trait.Database.html#method.assert_no_dangling_in_progress
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DanglingInProgress {
+    queries: Vec<(String, RuntimeId)>,
+}
+
+impl DanglingInProgress {
+    pub(crate) fn new(queries: Vec<(String, RuntimeId)>) -> Self {
+        DanglingInProgress { queries }
+    }
+
+    pub(crate) fn throw(self) -> ! {
+        std::panic::resume_unwind(Box::new(self))
+    }
+
+    /// Runs `f`, and catches the error if it blocks on a dangling
+    /// query. Panics with other payloads are propagated unchanged.
+    pub fn catch<F, T>(f: F) -> Result<T, DanglingInProgress>
+    where
+        F: FnOnce() -> T + std::panic::UnwindSafe,
+    {
+        match std::panic::catch_unwind(f) {
+            Ok(t) => Ok(t),
+            Err(payload) => match payload.downcast::<DanglingInProgress>() {
+                Ok(dangling) => Err(*dangling),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        }
+    }
+
+    /// The (displayed) database-keys of the dangling queries,
+    /// each with the id of the runtime that was executing it.
+    pub fn queries(&self) -> &[(String, RuntimeId)] {
+        &self.queries
+    }
+}
+
+impl fmt::Display for DanglingInProgress {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(fmt, "queries in progress in runtimes that no longer exist:")?;
+        for (query, runtime_id) in &self.queries {
+            writeln!(fmt, "- {} (in {:?})", query, runtime_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DanglingInProgress {}
+
+impl std::error::Error for Cancelled {}
+
+/// The error returned when a query could not be resolved because it
+/// (transitively) depends on itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError<K> {
+    cycle: Vec<K>,
+    runtimes: Vec<CycleRuntime<K>>,
+}
+
+impl<K> CycleError<K> {
+    pub(crate) fn new(cycle: Vec<K>) -> Self {
+        CycleError {
+            cycle,
+            runtimes: vec![],
+        }
+    }
+
+    pub(crate) fn with_runtimes(self, runtimes: Vec<CycleRuntime<K>>) -> Self {
+        CycleError { runtimes, ..self }
+    }
+
+    /// The database-keys of all queries that participate in the
+    /// cycle, in the order in which they invoked one another. The
+    /// last query in the list is the one that tried to read the first.
+    pub fn cycle(&self) -> &[K] {
+        &self.cycle
+    }
+
+    /// If the cycle spans several runtimes (that is, threads which
+    /// would otherwise have deadlocked waiting on one another), what
+    /// each of them was doing, starting with the runtime that
+    /// detected the cycle. Empty if the cycle lies within a single
+    /// runtime.
+    pub fn runtimes(&self) -> &[CycleRuntime<K>] {
+        &self.runtimes
+    }
+}
+
+/// One of the runtimes involved in a cycle that spans several of
+/// them; see `CycleError::runtimes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleRuntime<K> {
+    /// The id of the runtime.
+    pub runtime_id: RuntimeId,
+
+    /// The queries that the runtime was executing, outermost first.
+    pub query_stack: Vec<K>,
+
+    /// The query that the runtime is blocked on, which the next
+    /// runtime in the list is executing.
+    pub blocked_on: K,
+}
+
+impl<K> fmt::Display for CycleError<K>
+where
+    K: fmt::Display,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(fmt, "Internal error, cycle detected:")?;
+        for database_key in &self.cycle {
+            writeln!(fmt, "- {}", database_key)?;
+        }
+        if !self.runtimes.is_empty() {
+            writeln!(fmt, "Runtimes involved:")?;
+            for runtime in &self.runtimes {
+                writeln!(
+                    fmt,
+                    "- {:?}, blocked on {}, executing:",
+                    runtime.runtime_id, runtime.blocked_on
+                )?;
+                for database_key in &runtime.query_stack {
+                    writeln!(fmt, "  - {}", database_key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Explains why the memoized value of a derived query could not be
+/// reused, so that the query had to be executed. See
+/// [`Database::last_invalidation_reason`].
+///
+/// [`Database::last_invalidation_reason`]: trait.Database.html#method.last_invalidation_reason
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidationReason<K> {
+    /// The query had not been executed before (or its memo was
+    /// discarded by a sweep).
+    NotComputed,
+
+    /// The memo did not hold a value, either because the query only
+    /// tracks its dependencies or because the value was evicted.
+    NoValue,
+
+    /// The query read untracked state, so it can never be reused in
+    /// a later revision.
+    UntrackedRead,
+
+    /// The given input may have changed since the memoized value was
+    /// last verified. This is the first such input the query read.
+    InputChanged(K),
+
+    /// The memoized value was older than the maximum age of the query
+    /// (see `#[salsa::max_age]`).
+    Expired,
+
+    /// The memoized value was invalidated with
+    /// `QueryTableMut::invalidate`.
+    Invalidated,
+
+    /// The given input was not verified in the current revision yet,
+    /// and the query does not validate its inputs itself (see
+    /// `#[salsa::shallow_revalidation]`).
+    InputUnverified(K),
+
+    /// The query read the given token (see `Runtime::report_read_of`),
+    /// which was invalidated since the memoized value was last
+    /// verified.
+    TokenInvalidated(InvalidationToken),
+
+    /// The given query specified a new value for the key (see
+    /// `QueryTable::specify`).
+    Specified(K),
+}
+
+/// Trait implements by all of the "special types" associated with
+/// each of your queries.
+///
+/// Unsafe trait obligation: Asserts that the Key/Value associated
+/// types for this trait are a part of the `Group::GroupData` type.
+/// In particular, `Group::GroupData: Send + Sync` must imply that
+/// `Key: Send + Sync` and `Value: Send + Sync`. This is relied upon
+/// by the dependency tracking logic.
+pub unsafe trait Query<DB: Database>: Debug + Default + Sized + 'static {
+    /// Type that you you give as a parameter -- for queries with zero
+    /// or more than one input, this will be a tuple.
+    type Key: Clone + Hash + Eq;
+
+    /// What value does the query return?
+    type Value: Clone;
+
+    /// Internal struct storing the values for the query.
+    type Storage: plumbing::QueryStorageOps<DB, Self>;
+
+    /// The name of the query (i.e., of the method that invokes it).
+    const QUERY_NAME: &'static str;
+
+    /// Estimates the heap memory owned by a value; set with the
+    /// `#[salsa::heap_size]` attribute. Used by
+    /// `QueryTable::memory_usage`.
+    const HEAP_SIZE: Option<fn(&Self::Value) -> usize> = None;
+
+    /// If true, the values that snapshots pinned at older revisions
+    /// may still read are kept when they are replaced; set with the
+    /// `#[salsa::multi_version]` attribute. See
+    /// `ParallelDatabase::snapshot_at_current_revision`.
+    const MULTI_VERSION: bool = false;
+
+    /// Creates the hasher of the keys of this query, if they are not
+    /// to be hashed with `FxHasher`; set with the `#[salsa::hasher]`
+    /// attribute. Only used by input and derived queries.
+    const KEY_HASHER: Option<fn() -> plumbing::KeyHasher> = None;
+
+    /// Orders the keys of this query when iterating over its storage
+    /// (as `QueryTable::entries` and `DebugQueryTable::debug_dump` do);
+    /// set with the `#[salsa::ordered]` attribute. Only used by input
+    /// and derived queries.
+    const KEY_ORDER: Option<plumbing::KeyOrderFn<Self::Key>> = None;
+
+    /// Associate query group struct.
+    type Group: plumbing::QueryGroup<
+        DB,
+        GroupStorage = Self::GroupStorage,
+        GroupKey = Self::GroupKey,
+    >;
+
+    /// Generated struct that contains storage for all queries in a group.
+    type GroupStorage;
+
+    /// Type that identifies a particular query within the group + its key.
+    type GroupKey;
+
+    /// Extact storage for this query from the storage for its group.
+    fn query_storage(group_storage: &Self::GroupStorage) -> &Self::Storage;
+
+    /// Create group key for this query.
+    fn group_key(key: Self::Key) -> Self::GroupKey;
+
+    /// Debug-formats a key of this query, or writes its type name if
+    /// the key does not implement `Debug`.
+    fn debug_key(key: &Self::Key, fmt: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    /// Debug-formats a value of this query, or writes its type name if
+    /// the value does not implement `Debug`.
+    fn debug_value(value: &Self::Value, fmt: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// Return value from [the `query` method] on `Database`.
+/// Gives access to various less common operations on queries.
+///
+/// [the `query_mut` method]: trait.Database#method.query
+#[derive(new)]
+pub struct QueryTable<'me, DB, Q>
+where
+    DB: plumbing::GetQueryTable<Q>,
+    Q: Query<DB> + 'me,
+{
+    db: &'me DB,
+    storage: &'me Q::Storage,
+}
+
+impl<'me, DB, Q> QueryTable<'me, DB, Q>
+where
+    DB: plumbing::GetQueryTable<Q>,
+    Q: Query<DB>,
+{
+    /// Execute the query on a given input. Usually it's easier to
+    /// invoke the trait method directly. Note that for variadic
+    /// queries (those with no inputs, or those with more than one
+    /// input) the key will be a tuple.
+    pub fn get(&self, key: Q::Key) -> Q::Value {
+        self.try_get(key)
+            .unwrap_or_else(|err| self.db.salsa_runtime().report_unexpected_cycle(err))
+    }
+
+    /// Like `get`, but returns an error if computing the value would
+    /// require this query to (transitively) depend on itself. The
+    /// error lists every query that participates in the cycle, so the
+    /// caller can report it or recover from it rather than panicking.
+    pub fn try_get(&self, key: Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        self.record_read(&key, || {
+            let runtime = self.db.salsa_runtime();
+            match runtime.pinned_revision() {
+                Some(revision) => runtime
+                    .read_pinned(revision, || self.storage.try_fetch(self.db, &key))
+                    .unwrap_or_else(|| self.storage.try_fetch_at(self.db, &key, revision)),
+                None => self.storage.try_fetch(self.db, &key),
+            }
+        })
+    }
+
+    /// Performs `read` of `key`; if it is a top-level read made while
+    /// recording (see `salsa::replay`), records it with the time it
+    /// took.
+    fn record_read<R>(&self, key: &Q::Key, read: impl FnOnce() -> R) -> R {
+        let runtime = self.db.salsa_runtime();
+        if !runtime.is_recording_top_level_reads() {
+            return read();
+        }
+        let start = Instant::now();
+        let result = read();
+        runtime.record_top_level_read(self.database_key(key.clone()), start.elapsed());
+        result
+    }
+
+    /// Like `get`, but if the value is already memoized, returns a
+    /// guard that borrows it instead of a clone; this helps for large
+    /// values that are only inspected briefly. The read is recorded
+    /// as usual.
+    ///
+    /// The guard holds a read lock on the memo, so drop it before
+    /// invoking other queries: while it is alive, the memo is not
+    /// evicted (say, to make room in the LRU list of this query) or
+    /// swept, and recomputing the value blocks on other threads. On
+    /// the current thread, that would deadlock, and is reported as a
+    /// cycle instead; see `ValueGuard`.
+    pub fn get_ref(&self, key: Q::Key) -> ValueGuard<'me, Q::Value> {
+        self.try_get_ref(key)
+            .unwrap_or_else(|err| self.db.salsa_runtime().report_unexpected_cycle(err))
+    }
+
+    /// Like `get_ref`, but returns an error in the event of a cycle;
+    /// see `try_get`.
+    pub fn try_get_ref(
+        &self,
+        key: Q::Key,
+    ) -> Result<ValueGuard<'me, Q::Value>, CycleError<DB::DatabaseKey>> {
+        if self.db.salsa_runtime().pinned_revision().is_some() {
+            // The memo may be replaced while the guard is alive.
+            return self.try_get(key).map(ValueGuard::owned);
+        }
+        self.record_read(&key, || self.storage.try_fetch_ref(self.db, &key))
+    }
+
+    /// For a query whose value is a `Result` (such as a
+    /// `#[salsa::fallible]` query), returns true if the value of `key`
+    /// is an error. Like `get_ref`, this does not clone a memoized value.
+    pub fn failed<T, E>(&self, key: Q::Key) -> bool
+    where
+        Q: Query<DB, Value = Result<T, E>>,
+    {
+        self.get_ref(key).is_err()
+    }
+
+    /// Returns the value for `key` if it is already memoized and
+    /// known to be up to date in the current revision; otherwise
+    /// returns `None`. Unlike `get`, this never executes (or
+    /// revalidates) the query, and it does not record a dependency
+    /// when called from inside another query.
+    pub fn peek(&self, key: Q::Key) -> Option<Q::Value> {
+        self.storage.peek(self.db, &key)
+    }
+
+    /// Like `peek`, but if another thread is computing the value of
+    /// `key`, returns a future that resolves to that value once the
+    /// thread completes, rather than to `None`. This lets an async task
+    /// wait for values that are being computed, say, by a blocking
+    /// thread pool (e.g., tokio's `spawn_blocking`), without blocking
+    /// the executor thread itself.
+    ///
+    /// Like `peek`, this never executes the query: the future also
+    /// resolves to `None` if the other thread panics or is canceled, or
+    /// yields the value to a foreground runtime. The future does not
+    /// borrow the database, so it is `Send` (if the value is) and can
+    /// be dropped at any point.
+    ///
+    /// Panics if called from inside a query.
+    pub fn peek_or_wait(&self, key: Q::Key) -> PeekFuture<Q::Value> {
+        assert!(
+            self.db.salsa_runtime().active_query().is_none(),
+            "invoked `peek_or_wait` from inside a query"
+        );
+        self.storage.peek_or_wait(self.db, &key)
+    }
+
+    /// Returns the id that `key` is interned as, if it was interned
+    /// already; unlike `get`, this never interns `key`. Can only be
+    /// used with `#[salsa::interned]` queries.
+    ///
+    /// When called from inside another query, if `key` is not
+    /// interned, that query is treated as having read an untracked
+    /// input (see `Runtime::report_untracked_read`): its result will
+    /// not be reused in a later revision, in which `key` may be
+    /// interned.
+    pub fn lookup_existing(&self, key: Q::Key) -> Option<Q::Value>
+    where
+        Q::Storage: plumbing::InternedQueryStorageOps<DB, Q>,
+    {
+        self.storage.lookup_existing(self.db, &key)
+    }
+
+    /// Writes the values interned by this query, along with their
+    /// ids, to `out`. Importing them into another database with
+    /// [`import_interned`] assigns the same ids there, so that ids
+    /// stored on disk (say, as keys of a cache) stay meaningful in
+    /// later sessions, which would otherwise assign ids in whatever
+    /// order the values happen to get interned. Can only be used
+    /// with `#[salsa::interned]` queries, and requires the `persist`
+    /// feature.
+    ///
+    /// [`import_interned`]: struct.QueryTable.html#method.import_interned
+    #[cfg(feature = "persist")]
+    pub fn export_interned(&self, out: &mut impl std::io::Write) -> std::io::Result<()>
+    where
+        Q::Key: serde::Serialize,
+        Q::Storage: plumbing::InternedQueryStorageOps<DB, Q>,
+    {
+        self.storage.export_interned(out)
+    }
+
+    /// Interns the values written by [`export_interned`], with the
+    /// same ids as in the database that exported them. This must be
+    /// done before the query interns anything else; the values count
+    /// as interned in the current revision.
+    ///
+    /// [`export_interned`]: struct.QueryTable.html#method.export_interned
+    #[cfg(feature = "persist")]
+    pub fn import_interned(&self, reader: &mut impl std::io::Read) -> std::io::Result<()>
+    where
+        Q::Key: serde::de::DeserializeOwned,
+        Q::Storage: plumbing::InternedQueryStorageOps<DB, Q>,
+    {
+        self.storage.import_interned(self.db, reader)
+    }
+
+    /// Computes the values for `keys` (if they are not up to date
+    /// already) and exports them, along with a manifest of the inputs
+    /// they transitively depend on and hashes of their values, as a
+    /// standalone bundle: a later process can load it with
+    /// [`import_results`] and reuse the values as long as those inputs
+    /// still have the same values, as with a distributed build cache.
+    ///
+    /// The keys and values are serialized with `bincode`. Every input
+    /// that the values depend on must be marked `#[salsa::persist]`, as
+    /// only those can be hashed; values that depend on anything else
+    /// (interned or tracked values, invalidation tokens, untracked
+    /// reads, ...) cannot be exported, and make this return an error.
+    /// The same goes for values whose dependencies were evicted by an
+    /// LRU list or swept. Hashes are only comparable between builds made
+    /// with the same compiler.
+    ///
+    /// [`import_results`]: struct.QueryTable.html#method.import_results
+    #[cfg(feature = "persist")]
+    pub fn export_results(
+        &self,
+        keys: impl IntoIterator<Item = Q::Key>,
+    ) -> std::io::Result<ArtifactBundle>
+    where
+        Q::Key: serde::Serialize,
+        Q::Value: serde::Serialize,
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        artifact::export_results(self, keys)
+    }
+
+    /// Imports the values of this query from a bundle written by
+    /// [`export_results`] (values of other queries are ignored). A value
+    /// is only imported if all the inputs recorded for it exist and
+    /// their values hash the same as when it was exported, and if the key
+    /// has no memo yet. It is then memoized as if it had been computed
+    /// from those inputs in the current revision: it is trusted for as
+    /// long as the inputs do not change, and re-executed as usual once
+    /// they do. Returns the number of values imported.
+    ///
+    /// [`export_results`]: struct.QueryTable.html#method.export_results
+    #[cfg(feature = "persist")]
+    pub fn import_results(&self, bundle: &ArtifactBundle) -> std::io::Result<usize>
+    where
+        Q::Key: serde::de::DeserializeOwned,
+        Q::Value: serde::de::DeserializeOwned,
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        artifact::import_results(self, bundle)
+    }
+
+    /// Computes the value for `key` on a new thread (using a
+    /// snapshot of the database), and returns a stream of its items.
+    /// Items that the query function emits with
+    /// `Runtime::emit_partial` are delivered as soon as they are
+    /// emitted; the remaining items once the value is complete. The
+    /// value itself is memoized as usual, so streaming a value that
+    /// is already up to date just iterates over it.
+    ///
+    /// Like `snapshot`, this may not be called from inside a query,
+    /// and blocks new revisions until the value is complete.
+    pub fn stream<T>(&self, key: Q::Key) -> Stream<T>
+    where
+        DB: ParallelDatabase + 'static,
+        DB::DatabaseKey: Send,
+        Q::Key: Send,
+        Q::Value: IntoIterator<Item = T>,
+        T: Clone + Send + 'static,
+    {
+        let snapshot = self.db.snapshot();
+        let database_key = <DB as plumbing::GetQueryTable<Q>>::database_key(self.db, key.clone());
+        let shared = Arc::new(stream::StreamShared::new());
+        let id = self
+            .db
+            .salsa_runtime()
+            .register_stream(database_key.clone(), shared.clone());
+        let stream = Stream::new(shared.clone());
+        std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                snapshot.query(Q::default()).get(key)
+            }));
+            snapshot
+                .salsa_runtime()
+                .unregister_stream(&database_key, id);
+            if let Ok(value) = result {
+                let emitted = shared.pushed();
+                for item in value.into_iter().skip(emitted) {
+                    shared.push(item);
+                }
+            }
+            shared.close();
+        });
+        stream
+    }
+
+    /// Returns false if the value of the query for `key` is known not
+    /// to have changed since `revision` (as obtained from
+    /// `Runtime::current_revision`), and true if it may have. Unlike
+    /// `get`, this does not clone the value; it may still have to
+    /// re-execute the query (or the queries it depends on) to find
+    /// out. Keys whose value was never computed are considered
+    /// changed.
+    ///
+    /// The check is not recorded as a dependency of the active query,
+    /// if any, so it is meant to be used outside of queries.
+    pub fn maybe_changed_since(&self, key: Q::Key, revision: Revision) -> bool {
+        self.storage.maybe_changed_since(self.db, &key, revision)
+    }
+
+    /// Reads the value of an "input query", returning `None` if no
+    /// value has been set for `key` (or if it has been removed). The
+    /// read is recorded like any other, so a query that observed the
+    /// key as absent is re-executed once a value is set.
+    pub fn get_maybe(&self, key: Q::Key) -> Option<Q::Value>
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.record_read(&key, || {
+            let runtime = self.db.salsa_runtime();
+            match runtime.pinned_revision() {
+                Some(revision) => runtime
+                    .read_pinned(revision, || self.storage.try_fetch_maybe(self.db, &key))
+                    .unwrap_or_else(|| self.storage.try_fetch_maybe_at(self.db, &key, revision)),
+                None => self.storage.try_fetch_maybe(self.db, &key),
+            }
+        })
+    }
+
+    /// Remove all values for this query that have not been used in
+    /// the most recent revision.
+    pub fn sweep(&self, strategy: SweepStrategy)
+    where
+        Q::Storage: plumbing::QueryStorageMassOps<DB>,
+    {
+        self.storage.sweep(self.db, strategy, None);
+    }
+
+    /// Like `sweep`, but only considers the memos of the given keys,
+    /// leaving the rest of the table alone. This is cheap even for
+    /// large tables, as only the given keys are looked up. Keys that
+    /// have no memo are ignored.
+    pub fn sweep_keys(&self, keys: impl IntoIterator<Item = Q::Key>, strategy: SweepStrategy) {
+        self.storage
+            .sweep_keys(self.db, &mut keys.into_iter(), strategy);
+    }
+
+    /// Like `sweep`, but asks `policy` about each memo before the
+    /// strategy is applied to it; see `Database::sweep_all_with_policy`.
+    pub fn sweep_with_policy(&self, strategy: SweepStrategy, policy: &dyn SweepPolicy)
+    where
+        Q::Storage: plumbing::QueryStorageMassOps<DB>,
+    {
+        self.storage.sweep(self.db, strategy, Some(policy));
+    }
+
+    /// Estimates the memory used by the table of this query: how many
+    /// keys it holds, how many of them have a value in memory, and
+    /// roughly how many bytes that takes (see `MemoryReport::bytes`).
+    /// Meant to help choosing LRU capacities; to account for the heap
+    /// memory owned by values, give the query a size function with
+    /// `#[salsa::heap_size]`.
+    pub fn memory_usage(&self) -> MemoryReport
+    where
+        Q::Storage: plumbing::QueryStorageMassOps<DB>,
+    {
+        self.storage.memory_usage(self.db).1
+    }
+
+    /// Returns the execution statistics collected for this query
+    /// (summed over all keys). Statistics are only collected while
+    /// enabled with [`Runtime::set_statistics_mode`].
+    ///
+    /// [`Runtime::set_statistics_mode`]: struct.Runtime.html#method.set_statistics_mode
+    pub fn statistics(&self) -> QueryStatistics {
+        self.db.salsa_runtime().query_statistics::<Q>()
+    }
+
+    /// Returns the execution statistics collected for a single key
+    /// of this query. These are only collected in
+    /// `StatisticsMode::PerKey`.
+    pub fn key_statistics(&self, key: Q::Key) -> QueryStatistics {
+        let database_key = <DB as plumbing::GetQueryTable<Q>>::database_key(self.db, key);
+        self.db.salsa_runtime().key_statistics(&database_key)
+    }
+
+    /// Returns the key that identifies the query for `key` across the
+    /// whole database, as used by events, `ParallelDatabase::prefetch`
+    /// and `Runtime::report_dependency`.
+    pub fn database_key(&self, key: Q::Key) -> DB::DatabaseKey {
+        <DB as plumbing::GetQueryTable<Q>>::database_key(self.db, key)
+    }
+
+    /// Returns why the query last had to be executed for the given
+    /// key; see `Database::last_invalidation_reason`.
+    pub fn last_invalidation_reason(
+        &self,
+        key: Q::Key,
+    ) -> Option<InvalidationReason<DB::DatabaseKey>> {
+        let database_key = <DB as plumbing::GetQueryTable<Q>>::database_key(self.db, key);
+        self.db.last_invalidation_reason(&database_key)
+    }
+
+    /// Returns the entries of a derived query whose memoized value
+    /// changed since `revision` (as returned by
+    /// `Runtime::current_revision`), so that (e.g.) a user interface
+    /// can update only what changed. With `#[salsa::keep_previous]`,
+    /// the entries also hold the value that the key had in `revision`.
+    ///
+    /// Only values that were recomputed are reported: keys that were
+    /// not demanded since their inputs changed are not, and neither are
+    /// keys whose value was discarded. An entry may also be reported if
+    /// its value changed and then changed back.
+    pub fn changed_entries_since(&self, revision: Revision) -> Vec<ChangedEntry<Q::Key, Q::Value>>
+    where
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        self.storage.changed_entries_since(self.db, revision)
+    }
+
+    /// Specifies the value for `key`, as a side product of the query
+    /// that is currently executing (the "specifier"), which must not
+    /// read that value itself. This lets one query compute the values
+    /// of many keys at once -- say, the signatures of all the items of
+    /// a module -- rather than each key walking the module on its own.
+    ///
+    /// The value for `key` then depends on the specifier: when the key
+    /// is read in a later revision, the specifier is brought up to
+    /// date first, and if it specified a different value, that value
+    /// is used (and can be backdated like any other). Keys that were
+    /// never specified are computed by the query function as usual.
+    ///
+    /// # Panics
+    ///
+    /// If no query is executing, if another query already specified
+    /// `key` in the current revision, or if the value for `key` was
+    /// already read in the current revision and this changes it.
+    ///
+    /// # Limitations
+    ///
+    /// A key keeps its last specified value (and its specifier) even
+    /// if the specifier no longer specifies it after re-executing.
+    pub fn specify(&self, key: Q::Key, value: Q::Value)
+    where
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        self.storage.specify(self.db, &key, value)
+    }
+
+    /// Watches the value for `key`; see `Database::watch`. The value is
+    /// computed right away (if it is not up to date already), as the
+    /// baseline for changes. From then on, it is only checked when
+    /// `Database::poll_watches` is invoked in a new revision: it is then
+    /// validated (or re-executed) like by `get`, and `callback` is
+    /// invoked if it changed since the previous poll (or since the
+    /// watch was registered). Values that are backdated do not count
+    /// as changed.
+    ///
+    /// Returns an id that can be passed to `Runtime::unwatch`.
+    pub fn watch(
+        &self,
+        key: Q::Key,
+        mut callback: impl FnMut(&Q::Value) + MaybeSend + 'static,
+    ) -> SubscriptionId
+    where
+        Q::Key: MaybeSend + 'static,
+    {
+        self.get(key.clone());
+        let runtime = self.db.salsa_runtime();
+        let mut polled_at = runtime.current_revision();
+        runtime.register_watch(Box::new(move |db: &DB| {
+            let revision_now = db.salsa_runtime().current_revision();
+            if revision_now == polled_at {
+                return false;
+            }
+            let table = db.query(Q::default());
+            let value = table.get(key.clone());
+            let changed = table.maybe_changed_since(key.clone(), polled_at);
+            polled_at = revision_now;
+            if changed {
+                callback(&value);
+            }
+            changed
+        }))
+    }
+
+    /// Pins the value of `key`, so that it is exempt from LRU
+    /// eviction and from sweeping (see `Database::sweep_all`) until it
+    /// is unpinned. The value is still recomputed as usual when its
+    /// inputs change. Pinning a key that has not been computed yet
+    /// takes effect once it is.
+    pub fn pin(&self, key: Q::Key)
+    where
+        Q::Storage: plumbing::PinQueryStorageOps<DB, Q>,
+    {
+        self.storage.set_pinned(&key, true);
+    }
+
+    /// Undoes the effect of `pin`, making the value of `key` eligible
+    /// for sweeping again, and for LRU eviction once it is next used.
+    pub fn unpin(&self, key: Q::Key)
+    where
+        Q::Storage: plumbing::PinQueryStorageOps<DB, Q>,
+    {
+        self.storage.set_pinned(&key, false);
+    }
+}
+
+/// An entry of a query table whose value changed; see
+/// [`QueryTable::changed_entries_since`].
+///
+/// [`QueryTable::changed_entries_since`]: struct.QueryTable.html#method.changed_entries_since
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangedEntry<K, V> {
+    /// The key whose value changed.
+    pub key: K,
+
+    /// The value that the key had in the given revision, if it is
+    /// known: that is, if the query is marked `#[salsa::keep_previous]`,
+    /// the key had a value then, and the value only changed once since.
+    pub old_value: Option<V>,
+
+    /// The current value.
+    pub new_value: V,
+
+    /// The revision in which the value changed.
+    pub changed_at: Revision,
+}
+
+/// Return value from [the `query_mut` method] on `Database`.
 /// Gives access to the `set` method, notably, that is used to
 /// set the value of an input query.
 ///
@@ -544,6 +2231,133 @@ where
     {
         self.storage
             .set(self.db, &key, &self.database_key(&key), value, durability);
+        self.db.salsa_runtime().discard_forgotten_writes(self.db);
+    }
+
+    /// Like `set`, but if the input is already set to an equal value
+    /// (with the same durability), leaves it alone rather than start
+    /// a new revision, so that the queries that read it need not be
+    /// revalidated. Returns true if the value was set.
+    pub fn set_if_changed(&self, key: Q::Key, value: Q::Value) -> bool
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
+    {
+        self.set_with_durability_if_changed(key, value, Durability::LOW)
+    }
+
+    /// Like `set_with_durability`, but does nothing if the input is
+    /// already set to an equal value with the same durability; see
+    /// `set_if_changed`. Returns true if the value was set.
+    pub fn set_with_durability_if_changed(
+        &self,
+        key: Q::Key,
+        value: Q::Value,
+        durability: Durability,
+    ) -> bool
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+        Q::Value: Eq,
+    {
+        if self.storage.is_set_to(&key, &value, durability) {
+            return false;
+        }
+        self.set_with_durability(key, value, durability);
+        true
+    }
+
+    /// Changes the durability of the value that is currently assigned
+    /// to an "input query", without changing the value itself. When
+    /// the durability is raised, queries that read the input are not
+    /// invalidated; this is useful to promote an input once you know
+    /// it will not change anymore (e.g., a file that became
+    /// read-only). Lowering the durability, on the other hand,
+    /// invalidates those queries just like setting a new value would.
+    /// Must be used outside of an active query computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    ///
+    /// # Panics
+    ///
+    /// Panics if no value has been set for `key`.
+    pub fn set_durability(&self, key: Q::Key, durability: Durability)
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.storage
+            .set_durability(self.db, &key, &self.database_key(&key), durability);
+        self.db.salsa_runtime().discard_forgotten_writes(self.db);
+    }
+
+    /// Removes the value of an "input query", so that it reads as if
+    /// it had never been set: `get_maybe` returns `None`, and `get`
+    /// panics. Queries that read the old value are invalidated. Must
+    /// be used outside of an active query computation.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn remove(&self, key: Q::Key)
+    where
+        Q::Storage: plumbing::InputQueryStorageOps<DB, Q>,
+    {
+        self.storage.remove(self.db, &key, &self.database_key(&key));
+        self.db.salsa_runtime().discard_forgotten_writes(self.db);
+    }
+
+    /// Installs `value` as the memoized value of a derived query for
+    /// `key`, so that it is not executed until the memo is
+    /// invalidated. This is meant for warm starts, where the value was
+    /// computed earlier (say, by a previous run of the program) and
+    /// loaded from a cache. Must be used outside of an active query
+    /// computation; like setting an input, it starts a new revision,
+    /// in which the queries that read the old value (if any) are
+    /// revalidated.
+    ///
+    /// Salsa cannot tell which inputs the value was computed from, so
+    /// the memo is treated as having untracked inputs: it stays valid
+    /// until an input with a durability of (at most) `durability`
+    /// changes, and the query (and the queries that read it) is then
+    /// re-executed. It is up to you to
+    /// ensure that `value` is what the query would compute; if it is
+    /// not, queries may return stale or inconsistent results.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn set_memo(&self, key: Q::Key, value: Q::Value, durability: Durability)
+    where
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        self.storage
+            .set_memo(self.db, &key, &self.database_key(&key), value, durability);
+    }
+
+    /// Invalidates the memoized value of a derived query for `key`, so
+    /// that it is re-executed the next time it is needed. This is
+    /// meant for queries that read something salsa does not know about
+    /// (the network, the file system, ...), when you know which key
+    /// went stale. The old value is kept to compare the new one with,
+    /// so the queries that read it are only re-executed if it changed.
+    /// Must be used outside of an active query computation; like
+    /// setting an input, it starts a new revision. Does nothing if the
+    /// value was never computed.
+    ///
+    /// If you are using `snapshot`, see the notes on blocking
+    /// and cancellation on [the `query_mut` method].
+    ///
+    /// [the `query_mut` method]: trait.Database#method.query_mut
+    pub fn invalidate(&self, key: Q::Key)
+    where
+        Q::Storage: plumbing::DerivedQueryStorageOps<DB, Q>,
+    {
+        self.storage
+            .invalidate(self.db, &key, &self.database_key(&key));
     }
 
     /// Sets the size of LRU cache of values for this query table.
@@ -552,6 +2366,10 @@ where
     /// time. This helps with keeping maximum memory usage under control, at the
     /// cost of potential extra recalculations of evicted values.
     ///
+    /// For queries with a cost function (`#[salsa::lru_cost]`), `cap`
+    /// is instead a budget for the total cost of the values present in
+    /// the table.
+    ///
     /// If `cap` is zero, all values are preserved, this is the default.
     pub fn set_lru_capacity(&self, cap: usize)
     where
@@ -565,5 +2383,49 @@ where
 #[allow(unused_imports)]
 #[macro_use]
 extern crate salsa_macros;
+// Lets the procedural macros be used within this crate (see
+// `DynamicQueryDatabase`, `fs::FileSystemDatabase`,
+// `documents::DocumentDatabase` and `bench_support::GraphDatabase`), as
+// their output refers to `salsa::...`.
+#[cfg(any(
+    feature = "dynamic",
+    feature = "fs",
+    feature = "documents",
+    feature = "bench-support"
+))]
+extern crate self as salsa;
 #[doc(hidden)]
 pub use salsa_macros::*;
+
+/// Expands to its input if salsa was built with the `persist` feature
+/// and to nothing otherwise. The procedural macros wrap the code that
+/// supports persistence in this, since their output is compiled as
+/// part of the user's crate and cannot test salsa's features directly.
+#[cfg(feature = "persist")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_persist {
+    ($($tt:tt)*) => { $($tt)* };
+}
+
+#[cfg(not(feature = "persist"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_persist {
+    ($($tt:tt)*) => {};
+}
+
+/// Like `__if_persist`, but for the `dynamic` feature.
+#[cfg(feature = "dynamic")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_dynamic {
+    ($($tt:tt)*) => { $($tt)* };
+}
+
+#[cfg(not(feature = "dynamic"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_dynamic {
+    ($($tt:tt)*) => {};
+}
@@ -0,0 +1,27 @@
+//! The `log` macros that salsa uses. With the `strip-logging` feature,
+//! they expand to code that type-checks their arguments but is never
+//! run, so that salsa neither formats nor even evaluates them.
+
+#[cfg(not(feature = "strip-logging"))]
+pub(crate) use log::{debug, info};
+
+#[cfg(feature = "strip-logging")]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(feature = "strip-logging")]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(feature = "strip-logging")]
+pub(crate) use {debug, info};
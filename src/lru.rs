@@ -1,3 +1,4 @@
+use crate::logging::debug;
 use parking_lot::Mutex;
 use rand::rngs::SmallRng;
 use rand::Rng;
@@ -19,38 +20,72 @@ mod test;
 /// `LruNode`, which is a trait that gives access to a field that
 /// stores the index in the list. This index gives us a rough idea of
 /// how recently the node has been used.
+///
+/// Alternatively, the list can be limited by the total *cost* of its
+/// nodes rather than by their number (see `set_lru_cost_budget`). In
+/// that mode, there are no zones; instead, each node remembers when
+/// it was last used, and victims are picked by sampling a few random
+/// nodes and evicting the least recently used among them.
 #[derive(Debug)]
 pub(crate) struct Lru<Node>
 where
-    Node: LruNode,
+    Node: LruNode + ?Sized,
 {
     green_zone: AtomicUsize,
+    cost_budget: AtomicUsize,
+    clock: AtomicUsize,
     data: Mutex<LruData<Node>>,
 }
 
 #[derive(Debug)]
-struct LruData<Node> {
+struct LruData<Node: ?Sized> {
     end_red_zone: usize,
     end_yellow_zone: usize,
     end_green_zone: usize,
+    cost_budget: usize,
+    total_cost: usize,
     rng: SmallRng,
     entries: Vec<Arc<Node>>,
 }
 
-pub(crate) trait LruNode: Sized + Debug {
+pub(crate) trait LruNode: Debug {
     fn lru_index(&self) -> &LruIndex;
 }
 
+/// A slot (of any query table) that is a member of the database-wide
+/// LRU list; see `Runtime::set_global_lru_budget`. Slots have a
+/// separate index for that list, since they may also be a member of
+/// the LRU list of their own table.
+pub(crate) trait GlobalLruNode<DB>: Debug {
+    fn global_lru_index(&self) -> &LruIndex;
+
+    /// Discards the memoized value of the slot.
+    fn evict(&self, db: &DB);
+}
+
+impl<DB> LruNode for dyn GlobalLruNode<DB> + Send + Sync {
+    fn lru_index(&self) -> &LruIndex {
+        self.global_lru_index()
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct LruIndex {
     /// Index in the approprate LRU list, or std::usize::MAX if not a
     /// member.
     index: AtomicUsize,
+
+    /// Cost of the node, when the list is limited by cost.
+    cost: AtomicUsize,
+
+    /// Value of the list's clock when the node was last used, when
+    /// the list is limited by cost.
+    last_use: AtomicUsize,
 }
 
 impl<Node> Default for Lru<Node>
 where
-    Node: LruNode,
+    Node: LruNode + ?Sized,
 {
     fn default() -> Self {
         Lru::new()
@@ -61,9 +96,12 @@ where
 // predictable results.
 const LRU_SEED: &str = "Hello, Rustaceans";
 
+// Number of nodes sampled when picking a victim in a cost-limited list.
+const EVICTION_SAMPLES: usize = 5;
+
 impl<Node> Lru<Node>
 where
-    Node: LruNode,
+    Node: LruNode + ?Sized,
 {
     /// Creates a new LRU list where LRU caching is disabled.
     pub fn new() -> Self {
@@ -74,6 +112,8 @@ where
     fn with_seed(seed: &str) -> Self {
         Lru {
             green_zone: AtomicUsize::new(0),
+            cost_budget: AtomicUsize::new(0),
+            clock: AtomicUsize::new(0),
             data: Mutex::new(LruData::with_seed(seed)),
         }
     }
@@ -82,6 +122,8 @@ where
     /// once.  If `len` is zero, this disables LRU caching completely.
     pub fn set_lru_capacity(&self, len: usize) {
         let mut data = self.data.lock();
+        self.cost_budget.store(0, Ordering::Release);
+        data.cost_budget = 0;
 
         // We require each zone to have at least 1 slot. Therefore,
         // the length cannot be just 1 or 2.
@@ -108,13 +150,56 @@ where
         }
     }
 
+    /// Limits the total cost of the nodes in the list to `budget`,
+    /// instead of their number. If `budget` is zero, this disables
+    /// LRU caching completely.
+    pub fn set_lru_cost_budget(&self, budget: usize) {
+        let mut data = self.data.lock();
+        self.green_zone.store(0, Ordering::Release);
+        data.resize(0, 0, 0);
+        self.cost_budget.store(budget, Ordering::Release);
+        data.cost_budget = budget;
+    }
+
+    /// The budget set with `set_lru_cost_budget` (zero if the list is
+    /// not limited by cost).
+    pub fn cost_budget(&self) -> usize {
+        self.cost_budget.load(Ordering::Acquire)
+    }
+
+    /// Records that `node`, whose cost is now `cost`, was used. This
+    /// may displace old nodes until the total cost fits the budget
+    /// again; those are returned. A node that exceeds the budget on
+    /// its own is only displaced once another node is used.
+    pub fn record_use_with_cost(&self, node: &Arc<Node>, cost: usize) -> Vec<Arc<Node>> {
+        debug!("record_use_with_cost(node={:?}, cost={})", node, cost);
+
+        if self.cost_budget() == 0 {
+            return vec![];
+        }
+
+        let lru_index = node.lru_index();
+        lru_index.last_use.store(
+            self.clock.fetch_add(1, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+
+        // Already a member of the list, with an unchanged cost --
+        // nothing else to do!
+        if lru_index.is_in_lru() && lru_index.cost.load(Ordering::Acquire) == cost {
+            return vec![];
+        }
+
+        self.data.lock().record_use_with_cost(node, cost)
+    }
+
     /// Records that `node` was used. This may displace an old node (if the LRU limits are
     pub fn record_use(&self, node: &Arc<Node>) -> Option<Arc<Node>> {
-        log::debug!("record_use(node={:?})", node);
+        debug!("record_use(node={:?})", node);
 
         // Load green zone length and check if the LRU cache is even enabled.
         let green_zone = self.green_zone.load(Ordering::Acquire);
-        log::debug!("record_use: green_zone={}", green_zone);
+        debug!("record_use: green_zone={}", green_zone);
         if green_zone == 0 {
             return None;
         }
@@ -122,7 +207,7 @@ where
         // Find current index of list (if any) and the current length
         // of our green zone.
         let index = node.lru_index().load();
-        log::debug!("record_use: index={}", index);
+        debug!("record_use: index={}", index);
 
         // Already a member of the list, and in the green zone -- nothing to do!
         if index < green_zone {
@@ -135,7 +220,7 @@ where
 
 impl<Node> LruData<Node>
 where
-    Node: LruNode,
+    Node: LruNode + ?Sized,
 {
     fn with_seed(seed_str: &str) -> Self {
         Self::with_rng(rng_with_seed(seed_str))
@@ -146,6 +231,8 @@ where
             end_yellow_zone: 0,
             end_green_zone: 0,
             end_red_zone: 0,
+            cost_budget: 0,
+            total_cost: 0,
             entries: Vec::new(),
             rng,
         }
@@ -169,15 +256,72 @@ where
         self.end_red_zone = self.end_yellow_zone + len_red_zone;
         let entries = std::mem::replace(&mut self.entries, Vec::with_capacity(self.end_red_zone));
 
-        log::debug!("green_zone = {:?}", self.green_zone());
-        log::debug!("yellow_zone = {:?}", self.yellow_zone());
-        log::debug!("red_zone = {:?}", self.red_zone());
+        debug!("green_zone = {:?}", self.green_zone());
+        debug!("yellow_zone = {:?}", self.yellow_zone());
+        debug!("red_zone = {:?}", self.red_zone());
 
         // We expect to resize when the LRU cache is basically empty.
         // So just forget all the old LRU indices to start.
         for entry in entries {
             entry.lru_index().clear();
         }
+        self.total_cost = 0;
+    }
+
+    /// Records that a node was used in a cost-limited list, adding it
+    /// to the list if needed, and then evicts the least recently used
+    /// of a few randomly sampled nodes until the total cost fits the
+    /// budget. Returns the evicted nodes.
+    fn record_use_with_cost(&mut self, node: &Arc<Node>, cost: usize) -> Vec<Arc<Node>> {
+        // The budget may have been disabled since we checked.
+        if self.cost_budget == 0 {
+            return vec![];
+        }
+
+        let lru_index = node.lru_index();
+        let index = lru_index.load();
+        if index < self.entries.len() {
+            self.total_cost -= lru_index.cost.load(Ordering::Acquire);
+        } else {
+            lru_index.store(self.entries.len());
+            self.entries.push(node.clone());
+        }
+        lru_index.cost.store(cost, Ordering::Release);
+        self.total_cost += cost;
+
+        let mut evicted = vec![];
+        while self.total_cost > self.cost_budget && self.entries.len() > 1 {
+            let victim_index = self.pick_victim(lru_index.load());
+            let victim_node = self.entries.swap_remove(victim_index);
+            if let Some(moved_node) = self.entries.get(victim_index) {
+                moved_node.lru_index().store(victim_index);
+            }
+            debug!("evicting node {:?} from {}", victim_node, victim_index);
+            self.total_cost -= victim_node.lru_index().cost.load(Ordering::Acquire);
+            victim_node.lru_index().clear();
+            evicted.push(victim_node);
+        }
+        evicted
+    }
+
+    /// Picks the least recently used among a few random nodes, never
+    /// the one at `used_index` (the node that was just used). Requires
+    /// the list to contain at least two nodes.
+    fn pick_victim(&mut self, used_index: usize) -> usize {
+        let len = self.entries.len();
+        debug_assert!(len > 1);
+        let last_use = |node: &Arc<Node>| node.lru_index().last_use.load(Ordering::Relaxed);
+        let mut victim_index = if used_index == 0 { 1 } else { 0 };
+        let mut victim_last_use = last_use(&self.entries[victim_index]);
+        for _ in 0..EVICTION_SAMPLES {
+            let index = self.rng.gen_range(0, len);
+            let last_use = last_use(&self.entries[index]);
+            if index != used_index && last_use < victim_last_use {
+                victim_index = index;
+                victim_last_use = last_use;
+            }
+        }
+        victim_index
     }
 
     /// Records that a node was used. If it is already a member of the
@@ -187,7 +331,7 @@ where
     /// list may displace an old member of the red zone, in which case
     /// that is returned.
     fn record_use(&mut self, node: &Arc<Node>) -> Option<Arc<Node>> {
-        log::debug!("record_use(node={:?})", node);
+        debug!("record_use(node={:?})", node);
 
         // NB: When this is invoked, we have typically already loaded
         // the LRU index (to check if it is in green zone). But that
@@ -219,7 +363,7 @@ where
         if len < self.end_red_zone {
             self.entries.push(node.clone());
             node.lru_index().store(len);
-            log::debug!("inserted node {:?} at {}", node, len);
+            debug!("inserted node {:?} at {}", node, len);
             return self.record_use(node);
         }
 
@@ -227,7 +371,7 @@ where
         // zone and then promoting.
         let victim_index = self.pick_index(self.red_zone());
         let victim_node = std::mem::replace(&mut self.entries[victim_index], node.clone());
-        log::debug!("evicting red node {:?} from {}", victim_node, victim_index);
+        debug!("evicting red node {:?} from {}", victim_node, victim_index);
         victim_node.lru_index().clear();
         self.promote_red_to_green(node, victim_index);
         Some(victim_node)
@@ -248,11 +392,9 @@ where
         // going to invoke `self.promote_yellow` next, and it will get
         // updated then.
         let yellow_index = self.pick_index(self.yellow_zone());
-        log::debug!(
+        debug!(
             "demoting yellow node {:?} from {} to red at {}",
-            self.entries[yellow_index],
-            yellow_index,
-            red_index,
+            self.entries[yellow_index], yellow_index, red_index,
         );
         self.entries.swap(yellow_index, red_index);
         self.entries[red_index].lru_index().store(red_index);
@@ -272,17 +414,15 @@ where
 
         // Pick a yellow at random and switch places with it.
         let green_index = self.pick_index(self.green_zone());
-        log::debug!(
+        debug!(
             "demoting green node {:?} from {} to yellow at {}",
-            self.entries[green_index],
-            green_index,
-            yellow_index
+            self.entries[green_index], green_index, yellow_index
         );
         self.entries.swap(green_index, yellow_index);
         self.entries[yellow_index].lru_index().store(yellow_index);
         node.lru_index().store(green_index);
 
-        log::debug!("promoted {:?} to green index {}", node, green_index);
+        debug!("promoted {:?} to green index {}", node, green_index);
     }
 
     fn pick_index(&mut self, zone: std::ops::Range<usize>) -> usize {
@@ -295,6 +435,8 @@ impl Default for LruIndex {
     fn default() -> Self {
         Self {
             index: AtomicUsize::new(std::usize::MAX),
+            cost: AtomicUsize::new(0),
+            last_use: AtomicUsize::new(0),
         }
     }
 }
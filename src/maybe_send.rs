@@ -0,0 +1,41 @@
+//! `Send` and `Sync` bounds that the `single-threaded` feature lifts.
+//!
+//! Keys and values never have to be `Send` or `Sync` (only the
+//! database then is not), but callbacks and values that salsa keeps
+//! in the runtime do, so that snapshots can use them from other
+//! threads. With the `single-threaded` feature, there are no other
+//! threads: they can hold `Rc`s (or JavaScript values, on
+//! `wasm32-unknown-unknown`), but then neither the runtime nor the
+//! database is `Send`, so `ParallelDatabase` cannot be implemented.
+
+/// `Send`, unless salsa is built with the `single-threaded` feature,
+/// in which case every type is `MaybeSend`.
+#[cfg(not(feature = "single-threaded"))]
+pub trait MaybeSend: Send {}
+
+#[cfg(not(feature = "single-threaded"))]
+impl<T: ?Sized + Send> MaybeSend for T {}
+
+/// `Sync`, unless salsa is built with the `single-threaded` feature,
+/// in which case every type is `MaybeSync`.
+#[cfg(not(feature = "single-threaded"))]
+pub trait MaybeSync: Sync {}
+
+#[cfg(not(feature = "single-threaded"))]
+impl<T: ?Sized + Sync> MaybeSync for T {}
+
+/// `Send`, unless salsa is built with the `single-threaded` feature,
+/// in which case every type is `MaybeSend`.
+#[cfg(feature = "single-threaded")]
+pub trait MaybeSend {}
+
+#[cfg(feature = "single-threaded")]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// `Sync`, unless salsa is built with the `single-threaded` feature,
+/// in which case every type is `MaybeSync`.
+#[cfg(feature = "single-threaded")]
+pub trait MaybeSync {}
+
+#[cfg(feature = "single-threaded")]
+impl<T: ?Sized> MaybeSync for T {}
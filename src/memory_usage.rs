@@ -0,0 +1,65 @@
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+
+/// An estimate of the memory used by a query table; returned by
+/// [`QueryTable::memory_usage`] and [`Database::salsa_memory_usage`].
+///
+/// [`QueryTable::memory_usage`]: struct.QueryTable.html#method.memory_usage
+/// [`Database::salsa_memory_usage`]: trait.Database.html#method.salsa_memory_usage
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Number of keys in the table.
+    pub slots: usize,
+
+    /// Number of keys whose value is kept in memory.
+    pub values: usize,
+
+    /// Estimated number of bytes used by the table: the size of its
+    /// slots (which hold the keys, the memoized values and the
+    /// dependencies of each value), plus the heap memory owned by the
+    /// values as given by the `#[salsa::heap_size]` function of the
+    /// query, if it has one. Heap memory owned by the keys is not
+    /// counted.
+    pub bytes: usize,
+}
+
+impl MemoryReport {
+    /// The report of a single slot, `bytes` in size.
+    pub(crate) fn slot(bytes: usize) -> Self {
+        MemoryReport {
+            slots: 1,
+            values: 0,
+            bytes,
+        }
+    }
+
+    /// Adds a value, with the heap memory that `heap_size` (see
+    /// `Query::HEAP_SIZE`) says it owns, to this report.
+    pub(crate) fn add_value<V>(&mut self, value: &V, heap_size: Option<fn(&V) -> usize>) {
+        self.values += 1;
+        self.bytes += heap_size.map_or(0, |heap_size| heap_size(value));
+    }
+}
+
+impl Add for MemoryReport {
+    type Output = MemoryReport;
+
+    fn add(mut self, other: MemoryReport) -> MemoryReport {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for MemoryReport {
+    fn add_assign(&mut self, other: MemoryReport) {
+        self.slots += other.slots;
+        self.values += other.values;
+        self.bytes += other.bytes;
+    }
+}
+
+impl Sum for MemoryReport {
+    fn sum<I: Iterator<Item = MemoryReport>>(iter: I) -> MemoryReport {
+        iter.fold(MemoryReport::default(), Add::add)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{Database, Query};
+use std::fmt::{self, Debug};
+
+/// Debug-formats a key or value whose type may not implement `Debug`,
+/// writing `<opaque T>` for the types that don't.
+///
+/// This relies on "autoref specialization", and thus only works where
+/// the type of the value is known (as in the code that the query group
+/// macro generates):
+///
+/// ```ignore
+/// use salsa::plumbing::{DebugViaDebug as _, DebugViaOpaque as _};
+/// (&&DebugOrOpaque(&value)).fmt_debug(fmt)
+/// ```
+pub struct DebugOrOpaque<'a, T>(pub &'a T);
+
+/// Formats `DebugOrOpaque` with the `Debug` impl of its value.
+pub trait DebugViaDebug {
+    /// Formats the value with its `Debug` impl.
+    fn fmt_debug(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: Debug> DebugViaDebug for &DebugOrOpaque<'_, T> {
+    fn fmt_debug(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.0, fmt)
+    }
+}
+
+/// Formats `DebugOrOpaque` with the name of its type, if it doesn't
+/// implement `Debug`.
+pub trait DebugViaOpaque {
+    /// Writes `<opaque T>`.
+    fn fmt_debug(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl<T> DebugViaOpaque for DebugOrOpaque<'_, T> {
+    fn fmt_debug(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "<opaque {}>", std::any::type_name::<T>())
+    }
+}
+
+/// Debug-formats `value` with `fmt`; lets generic code format the keys
+/// and values of queries with `Query::debug_key` and
+/// `Query::debug_value`.
+pub(crate) struct DebugWith<'a, T> {
+    value: &'a T,
+    fmt: fn(&T, &mut fmt::Formatter<'_>) -> fmt::Result,
+}
+
+impl<T> Debug for DebugWith<'_, T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.fmt)(self.value, fmt)
+    }
+}
+
+pub(crate) fn debug_key<DB, Q>(key: &Q::Key) -> DebugWith<'_, Q::Key>
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    DebugWith {
+        value: key,
+        fmt: Q::debug_key,
+    }
+}
+
+pub(crate) fn debug_value<DB, Q>(value: &Q::Value) -> DebugWith<'_, Q::Value>
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    DebugWith {
+        value,
+        fmt: Q::debug_value,
+    }
+}
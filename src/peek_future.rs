@@ -0,0 +1,62 @@
+use crate::blocking_future::BlockingFuture;
+use crate::derived::slot_core::WaitResult;
+use crate::runtime::StampedValue;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The future returned by `QueryTable::peek_or_wait`: resolves to the
+/// value of a query if it is memoized already, or once the thread that
+/// is computing it completes; and to `None` if the value still has to
+/// be computed.
+///
+/// The future does not borrow the database, so it can be sent to
+/// another thread (if the value can), and dropped at any point.
+#[must_use = "futures do nothing unless polled"]
+pub struct PeekFuture<V> {
+    state: State<V>,
+}
+
+enum State<V> {
+    Ready(Option<V>),
+    Waiting(BlockingFuture<WaitResult<StampedValue<V>>>),
+    Done,
+}
+
+impl<V> PeekFuture<V> {
+    pub(crate) fn ready(value: Option<V>) -> Self {
+        PeekFuture {
+            state: State::Ready(value),
+        }
+    }
+
+    pub(crate) fn waiting(future: BlockingFuture<WaitResult<StampedValue<V>>>) -> Self {
+        PeekFuture {
+            state: State::Waiting(future),
+        }
+    }
+}
+
+// The value is never pinned: it is only ever moved out.
+impl<V> Unpin for PeekFuture<V> {}
+
+impl<V: Clone> Future for PeekFuture<V> {
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        let this = self.get_mut();
+        let value = match &mut this.state {
+            State::Ready(value) => value.take(),
+            State::Waiting(future) => match Pin::new(future).poll(cx) {
+                Poll::Ready(Some(WaitResult::Completed(value))) => Some(value.value),
+                // The other thread yielded the slot, or panicked: the
+                // value has to be computed again.
+                Poll::Ready(Some(WaitResult::Yielded)) | Poll::Ready(None) => None,
+                Poll::Pending => return Poll::Pending,
+            },
+            State::Done => panic!("`PeekFuture` polled after completion"),
+        };
+        this.state = State::Done;
+        Poll::Ready(value)
+    }
+}
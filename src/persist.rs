@@ -0,0 +1,449 @@
+//! Saving memoized query results to disk and loading them back into a
+//! fresh database, so that the work done in one process can be reused
+//! by the next. See `Database::serialize_memos` for the user-facing
+//! entry points.
+//!
+//! Only queries marked with `#[salsa::persist]` are saved. The file
+//! contains, for each such query, its keys and memoized values along
+//! with the revisions in which they were computed; dependencies are
+//! recorded as references to other persisted slots. Revisions are
+//! remapped on load so that the loaded database starts out "as if" it
+//! had executed the same history.
+
+use crate::artifact::HashedInput;
+use crate::dependency::Dependency;
+use crate::durability::Durability;
+use crate::revision::Revision;
+use crate::Database;
+use rustc_hash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Bumped whenever the layout of the persisted data changes.
+const FORMAT_VERSION: u32 = 3;
+
+/// Operations on the storage of a query marked `#[salsa::persist]`.
+/// Saving and loading each happen in two passes: first, every slot is
+/// assigned an identity (so that dependencies between tables can be
+/// expressed), then the tables themselves are written or read.
+pub trait PersistQueryStorageOps<DB: Database> {
+    /// Name under which the query's table is saved.
+    fn query_name(&self) -> &'static str;
+
+    /// Records the identity of every slot in the table.
+    fn save_slots(&self, table: u32, slots: &mut SavedSlots<DB>) -> io::Result<()>;
+
+    /// Serializes the contents of the table; dependencies on slots that
+    /// were not recorded in `slots` cause the memo to be skipped.
+    fn save_table(
+        &self,
+        slots: &SavedSlots<DB>,
+        revisions: &mut RevisionSet,
+    ) -> io::Result<Vec<u8>>;
+
+    /// Creates the slots described by `data`, recording their identity.
+    fn load_slots(
+        &self,
+        table: u32,
+        data: &[u8],
+        revisions: &RevisionMap,
+        slots: &mut LoadedSlots<DB>,
+    ) -> io::Result<()>;
+
+    /// Restores the memoized values described by `data`.
+    fn load_table(
+        &self,
+        data: &[u8],
+        revisions: &RevisionMap,
+        slots: &LoadedSlots<DB>,
+    ) -> io::Result<()>;
+
+    /// Returns the slot of the input with the given serialized key
+    /// along with a hash of its value, if this is an input query and the
+    /// input is set; see `QueryTable::export_results`.
+    fn hash_input(&self, _key: &[u8]) -> io::Result<Option<HashedInput<DB>>> {
+        Ok(None)
+    }
+}
+
+/// Identifies a slot in the persisted data: the index of the table
+/// that owns it plus its serialized key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct PersistedSlotId {
+    pub(crate) table: u32,
+    pub(crate) key: Vec<u8>,
+}
+
+/// Maps the slots of the database being saved to their identity.
+pub struct SavedSlots<DB: Database> {
+    map: FxHashMap<Dependency<DB>, PersistedSlotId>,
+}
+
+impl<DB: Database> Default for SavedSlots<DB> {
+    fn default() -> Self {
+        SavedSlots {
+            map: Default::default(),
+        }
+    }
+}
+
+impl<DB: Database> SavedSlots<DB> {
+    pub(crate) fn insert(&mut self, slot: Dependency<DB>, table: u32, key: Vec<u8>) {
+        self.map.insert(slot, PersistedSlotId { table, key });
+    }
+
+    pub(crate) fn get(&self, slot: &Dependency<DB>) -> Option<&PersistedSlotId> {
+        self.map.get(slot)
+    }
+}
+
+/// Maps the identities found in the persisted data to the slots
+/// created for them while loading.
+pub struct LoadedSlots<DB: Database> {
+    map: FxHashMap<PersistedSlotId, Dependency<DB>>,
+}
+
+impl<DB: Database> Default for LoadedSlots<DB> {
+    fn default() -> Self {
+        LoadedSlots {
+            map: Default::default(),
+        }
+    }
+}
+
+impl<DB: Database> LoadedSlots<DB> {
+    pub(crate) fn insert(&mut self, table: u32, key: Vec<u8>, slot: Dependency<DB>) {
+        self.map.insert(PersistedSlotId { table, key }, slot);
+    }
+
+    pub(crate) fn get(&self, id: &PersistedSlotId) -> Option<&Dependency<DB>> {
+        self.map.get(id)
+    }
+}
+
+/// The set of revisions referenced by the data being saved.
+#[derive(Default)]
+pub struct RevisionSet {
+    revisions: BTreeSet<u64>,
+}
+
+impl RevisionSet {
+    pub(crate) fn record(&mut self, revision: Revision) -> u64 {
+        let revision = revision.as_u64();
+        self.revisions.insert(revision);
+        revision
+    }
+}
+
+/// Maps the revisions found in the persisted data to revisions of
+/// the database being loaded. Relative order is preserved, but gaps
+/// are closed, so the loaded database starts with a compact history.
+pub struct RevisionMap {
+    map: FxHashMap<u64, Revision>,
+}
+
+impl RevisionMap {
+    fn new(revisions: &[u64]) -> Self {
+        let mut sorted = revisions.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        let start = Revision::start().as_u64();
+        let map = sorted
+            .into_iter()
+            .enumerate()
+            .map(|(index, revision)| (revision, Revision::from(start + index as u64)))
+            .collect();
+        RevisionMap { map }
+    }
+
+    pub(crate) fn get(&self, revision: u64) -> io::Result<Revision> {
+        self.map
+            .get(&revision)
+            .cloned()
+            .ok_or_else(|| invalid_data(format!("unknown revision {}", revision)))
+    }
+}
+
+/// A memoized value of a derived query, as saved on disk.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PersistedMemo<V> {
+    pub(crate) value: Option<V>,
+    pub(crate) verified_at: u64,
+    pub(crate) changed_at: u64,
+    pub(crate) durability: u8,
+    pub(crate) inputs: Option<Vec<PersistedSlotId>>,
+}
+
+/// The contents of a whole persisted database.
+#[derive(Serialize, Deserialize)]
+struct PersistedDatabase {
+    version: u32,
+
+    /// Every revision referenced anywhere below.
+    revisions: Vec<u64>,
+
+    /// The revision in which inputs of each durability last changed;
+    /// the first element is also the current revision.
+    runtime_revisions: Vec<u64>,
+
+    tables: Vec<PersistedTable>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedTable {
+    query_name: String,
+    data: Vec<u8>,
+}
+
+pub(crate) fn serialize_memos<DB: Database>(
+    db: &DB,
+    writer: &mut impl io::Write,
+) -> io::Result<()> {
+    let (_, slots) = save_all_slots(db)?;
+
+    let mut revisions = RevisionSet::default();
+    let mut tables = vec![];
+    let mut result = Ok(());
+    db.for_each_persistent_query(|storage| {
+        if result.is_ok() {
+            match storage.save_table(&slots, &mut revisions) {
+                Ok(data) => tables.push(PersistedTable {
+                    query_name: storage.query_name().to_string(),
+                    data,
+                }),
+                Err(err) => result = Err(err),
+            }
+        }
+    });
+    result?;
+
+    let runtime_revisions = db
+        .salsa_runtime()
+        .persisted_revisions()
+        .into_iter()
+        .map(|revision| revisions.record(revision))
+        .collect();
+
+    let persisted = PersistedDatabase {
+        version: FORMAT_VERSION,
+        revisions: revisions.revisions.into_iter().collect(),
+        runtime_revisions,
+        tables,
+    };
+    bincode::serialize_into(writer, &persisted).map_err(|error| bincode_error(*error))
+}
+
+/// Assigns an identity to the slots of all persisted queries; returns
+/// the names of the queries, indexed by table, along with the slots.
+pub(crate) fn save_all_slots<DB: Database>(
+    db: &DB,
+) -> io::Result<(Vec<&'static str>, SavedSlots<DB>)> {
+    let mut query_names = vec![];
+    let mut slots = SavedSlots::default();
+    let mut result = Ok(());
+    db.for_each_persistent_query(|storage| {
+        if result.is_ok() {
+            let table = query_names.len() as u32;
+            query_names.push(storage.query_name());
+            result = storage.save_slots(table, &mut slots);
+        }
+    });
+    result?;
+    check_unique_names(&query_names)?;
+    Ok((query_names, slots))
+}
+
+pub(crate) fn deserialize_memos<DB: Database>(
+    db: &DB,
+    reader: &mut impl io::Read,
+) -> io::Result<()> {
+    let runtime = db.salsa_runtime();
+    if !runtime.is_fresh() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "memos can only be loaded into a database that has no inputs set",
+        ));
+    }
+
+    let persisted: PersistedDatabase =
+        bincode::deserialize_from(reader).map_err(|error| bincode_error(*error))?;
+    if persisted.version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported format version {}",
+            persisted.version
+        )));
+    }
+    if persisted.runtime_revisions.len() != Durability::LEN {
+        return Err(invalid_data(
+            "unexpected number of durabilities".to_string(),
+        ));
+    }
+    let query_names: Vec<&str> = persisted
+        .tables
+        .iter()
+        .map(|table| &table.query_name[..])
+        .collect();
+    check_unique_names(&query_names)?;
+
+    let revisions = RevisionMap::new(&persisted.revisions);
+    let runtime_revisions = persisted
+        .runtime_revisions
+        .iter()
+        .map(|&revision| revisions.get(revision))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Tables for queries that are no longer persisted (or no longer
+    // exist) are ignored, and so are any memos depending on them.
+    let tables: FxHashMap<&str, (u32, &[u8])> = persisted
+        .tables
+        .iter()
+        .enumerate()
+        .map(|(index, table)| (&table.query_name[..], (index as u32, &table.data[..])))
+        .collect();
+
+    let mut slots = LoadedSlots::default();
+    let mut result = Ok(());
+    db.for_each_persistent_query(|storage| {
+        if let (Ok(()), Some(&(table, data))) = (&result, tables.get(storage.query_name())) {
+            result = storage.load_slots(table, data, &revisions, &mut slots);
+        }
+    });
+    result?;
+
+    let mut result = Ok(());
+    db.for_each_persistent_query(|storage| {
+        if let (Ok(()), Some(&(_, data))) = (&result, tables.get(storage.query_name())) {
+            result = storage.load_table(data, &revisions, &slots);
+        }
+    });
+    result?;
+
+    runtime.restore_persisted_revisions(&runtime_revisions);
+    Ok(())
+}
+
+/// A cache shared by several processes (say, a memory-mapped file or
+/// a cache server) through which they exchange the memos saved by
+/// `Database::serialize_memos`; see `Database::attach_memo_cache`.
+pub trait MemoCache {
+    /// Returns the memos published most recently, if any.
+    fn fetch(&self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Publishes `memos`, replacing the memos published before.
+    fn publish(&self, memos: &[u8]) -> io::Result<()>;
+}
+
+/// A `MemoCache` kept in a file. Publishing writes a temporary file
+/// that then replaces the cache file, so that processes fetching
+/// concurrently see either the old memos or the new ones.
+pub struct FileMemoCache {
+    path: PathBuf,
+}
+
+impl FileMemoCache {
+    /// Uses the file at `path`, which need not exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileMemoCache { path: path.into() }
+    }
+}
+
+impl MemoCache for FileMemoCache {
+    fn fetch(&self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(memos) => Ok(Some(memos)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn publish(&self, memos: &[u8]) -> io::Result<()> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&temp_path, memos)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+/// The values of an interned query along with their intern-index,
+/// as written by `QueryTable::export_interned`.
+#[derive(Serialize, Deserialize)]
+struct PersistedInternTable<V> {
+    version: u32,
+    query_name: String,
+    values: V,
+}
+
+pub(crate) fn write_intern_table<K: Serialize>(
+    out: &mut dyn io::Write,
+    query_name: &str,
+    values: &[(u32, &K)],
+) -> io::Result<()> {
+    let table = PersistedInternTable {
+        version: FORMAT_VERSION,
+        query_name: query_name.to_string(),
+        values,
+    };
+    bincode::serialize_into(out, &table).map_err(|error| bincode_error(*error))
+}
+
+pub(crate) fn read_intern_table<K: DeserializeOwned>(
+    reader: &mut dyn io::Read,
+    query_name: &str,
+) -> io::Result<Vec<(u32, K)>> {
+    let table: PersistedInternTable<Vec<(u32, K)>> =
+        bincode::deserialize_from(reader).map_err(|error| bincode_error(*error))?;
+    if table.version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported format version {}",
+            table.version
+        )));
+    }
+    if table.query_name != query_name {
+        return Err(invalid_data(format!(
+            "expected the table of `{}`, found `{}`",
+            query_name, table.query_name
+        )));
+    }
+    Ok(table.values)
+}
+
+fn check_unique_names(query_names: &[&str]) -> io::Result<()> {
+    let mut seen = BTreeSet::new();
+    for name in query_names {
+        if !seen.insert(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("multiple persistent queries named `{}`", name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn serialize<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|error| bincode_error(*error))
+}
+
+pub(crate) fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+    bincode::deserialize(bytes).map_err(|error| bincode_error(*error))
+}
+
+pub(crate) fn durability_from_u8(durability: u8) -> io::Result<Durability> {
+    Durability::from_index(durability as usize)
+        .ok_or_else(|| invalid_data(format!("invalid durability {}", durability)))
+}
+
+fn bincode_error(error: bincode::ErrorKind) -> io::Error {
+    match error {
+        bincode::ErrorKind::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -2,22 +2,56 @@
 
 use crate::debug::TableEntry;
 use crate::durability::Durability;
+use crate::opaque::debug_key;
+use crate::ChangedEntry;
+use crate::CycleError;
 use crate::Database;
+use crate::MemoryReport;
+use crate::PeekFuture;
 use crate::Query;
+use crate::QueryPanic;
+use crate::QueryPanicked;
+use crate::QueryStorageStats;
 use crate::QueryTable;
 use crate::QueryTableMut;
+use crate::RuntimeId;
+use crate::SweepPolicy;
 use crate::SweepStrategy;
-use std::fmt::Debug;
+use crate::ValueGuard;
+use crate::ValueStore;
+use std::any::Any;
+use std::fmt::{Debug, Display};
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
 
+#[cfg(feature = "persist")]
+pub use crate::artifact::{HashedInput, TransitiveInputs};
+pub use crate::derived::hash_fingerprint;
+pub use crate::derived::DedupStorage;
 pub use crate::derived::DependencyStorage;
+pub use crate::derived::EqWithStorage;
+pub use crate::derived::FingerprintStorage;
+pub use crate::derived::FirewallStorage;
 pub use crate::derived::MemoizedStorage;
+pub use crate::derived::WeakStorage;
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::{dynamic_arg, dynamic_args, dynamic_value};
 pub use crate::input::InputStorage;
 pub use crate::interned::InternedStorage;
 pub use crate::interned::LookupInternedStorage;
+pub use crate::journal::JournalWriteId;
+pub use crate::key_map::{KeyHasher, KeyHasherState, KeyOrderFn};
+pub use crate::opaque::{DebugOrOpaque, DebugViaDebug, DebugViaOpaque};
+#[cfg(feature = "persist")]
+pub use crate::persist::{
+    LoadedSlots, PersistQueryStorageOps, RevisionMap, RevisionSet, SavedSlots,
+};
 pub use crate::revision::Revision;
-
-pub struct CycleDetected;
+pub use crate::tracked::LookupTrackedStorage;
+pub use crate::tracked::TrackedStorage;
+#[cfg(feature = "dynamic")]
+pub use serde_json;
 
 /// Defines various associated types. An impl of this
 /// should be generated for your query-context type automatically by
@@ -44,25 +78,227 @@ pub trait DatabaseStorageTypes: Sized {
 }
 
 /// Internal operations that the runtime uses to operate on the database.
-pub trait DatabaseOps: Sized {
+pub trait DatabaseOps: DatabaseStorageTypes {
     /// Executes the callback for each kind of query.
     fn for_each_query(&self, op: impl FnMut(&dyn QueryStorageMassOps<Self>));
+
+    /// Executes the callback for each derived, input and interned
+    /// query, with its name and storage; see
+    /// `Database::for_each_query_table`.
+    fn for_each_query_stats(&self, op: impl FnMut(&'static str, &dyn QueryStorageStats));
+
+    /// Fetches the value of the query identified by `database_key`,
+    /// discarding the result; see `ParallelDatabase::prefetch` and
+    /// `Runtime::report_dependency`. Inputs that have no value and
+    /// values that are not interned yet are read without panicking.
+    fn fetch_by_key(&self, database_key: &Self::DatabaseKey);
+
+    /// Executes the callback for each query marked `#[salsa::persist]`.
+    #[cfg(feature = "persist")]
+    fn for_each_persistent_query(&self, op: impl FnMut(&dyn PersistQueryStorageOps<Self>));
+
+    /// Invokes the query marked `#[salsa::dynamic]` with the given
+    /// name, if any; see `Database::query_by_name`.
+    #[cfg(feature = "dynamic")]
+    fn invoke_by_name(
+        &self,
+        name: &str,
+        args: &serde_json::Value,
+    ) -> Option<Result<serde_json::Value, crate::QueryByNameError>>;
 }
 
 /// Internal operations performed on the query storage as a whole
 /// (note that these ops do not need to know the identity of the
 /// query, unlike `QueryStorageOps`).
 pub trait QueryStorageMassOps<DB: Database> {
-    /// Discards memoized values that are not up to date with the current revision.
-    fn sweep(&self, db: &DB, strategy: SweepStrategy);
+    /// Discards memoized values that are not up to date with the
+    /// current revision (consulting `policy`, if any).
+    fn sweep(&self, db: &DB, strategy: SweepStrategy, policy: Option<&dyn SweepPolicy>);
+
+    /// Sweeps at most `limit` slots, starting with the one at position
+    /// `start` in the table; see `Database::sweep_incremental`.
+    /// Returns the number of slots swept and the position to continue
+    /// from, or `None` if the end of the table was reached.
+    fn sweep_some(
+        &self,
+        db: &DB,
+        strategy: SweepStrategy,
+        start: usize,
+        limit: usize,
+    ) -> (usize, Option<usize>);
+
+    /// Writes the state of every slot; see `DebugQueryTable::debug_dump`.
+    fn debug_dump(&self, db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()>;
+
+    /// Returns the name of the query and an estimate of the memory
+    /// used by its table; see `QueryTable::memory_usage`.
+    fn memory_usage(&self, db: &DB) -> (&'static str, MemoryReport);
+
+    /// If this table made the journaled `write`, applies its old values
+    /// (if `undo`) or its new values; see `Database::undo`.
+    fn replay_journaled(&self, _db: &DB, _write: JournalWriteId, _undo: bool) {}
+
+    /// Discards the values of the given journaled writes, if this table
+    /// made them.
+    fn forget_journaled(&self, _writes: &[JournalWriteId]) {}
+
+    /// If this table made the journaled `write` (in `revision`), writes
+    /// a line describing it; see `Database::dump_journal`.
+    fn dump_journaled(
+        &self,
+        _revision: Revision,
+        _write: JournalWriteId,
+        _out: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Adds the (displayed) database-keys of the queries in
+    /// progress in runtimes that no longer exist to `dangling`, with
+    /// the ids of these runtimes; see
+    /// `Database::assert_no_dangling_in_progress`.
+    fn find_dangling_in_progress(&self, _db: &DB, _dangling: &mut Vec<(String, RuntimeId)>) {}
+
+    /// Moves the writes that this table recorded (see
+    /// `salsa::replay::start_recording`) to `writes`.
+    fn take_recorded_writes(&self, _writes: &mut Vec<RecordedWrite<DB>>)
+    where
+        DB: 'static,
+    {
+    }
+}
+
+/// The key of a query in the database. Its `Display` output
+/// describes the query to end users; see `#[salsa::display_key]`.
+pub trait DatabaseKey<DB>: Clone + Debug + Display + Eq + Hash {
+    /// Unwinds with a `QueryPanicked` payload carrying this key; see
+    /// `PropagatedPanicPolicy::Error`. The `database_storage` macro
+    /// implements this with `QueryPanickedKey`.
+    fn throw_query_panicked(&self) -> !;
+
+    /// Describes `payload`, if it is a `QueryPanicked` payload thrown by
+    /// `throw_query_panicked`.
+    fn query_panicked_message(payload: &(dyn Any + Send)) -> Option<String>;
+}
+
+/// Throws `QueryPanicked` payloads carrying database keys of type `K`,
+/// if `K` is `Send` and `'static`, or panics with the `Display` output
+/// of the key otherwise.
+///
+/// This relies on "autoref specialization", like `DebugOrOpaque`, and
+/// thus only works where the type of the key is known (as in the code
+/// that the `database_storage` macro generates):
+///
+/// ```ignore
+/// use salsa::plumbing::{QueryPanickedViaMessage as _, QueryPanickedViaPayload as _};
+/// (&&QueryPanickedKey::<K>(PhantomData)).throw(key)
+/// ```
+///
+/// The keys of a database that is shared with other threads (which is
+/// the only way for a query to panic while another thread is blocked
+/// on it) are `Send`, so the fallback mostly lets the keys that are not
+/// compile.
+pub struct QueryPanickedKey<K>(pub PhantomData<K>);
+
+/// Throws `QueryPanicked` payloads, for the keys that are `Send` and
+/// `'static`.
+pub trait QueryPanickedViaPayload<K> {
+    /// Throws a `QueryPanicked` payload carrying `key`.
+    fn throw(&self, key: &K) -> !;
+
+    /// Describes `payload`, if it is a `QueryPanicked<K>`.
+    fn message(&self, payload: &(dyn Any + Send)) -> Option<String>;
 }
 
-pub trait DatabaseKey<DB>: Clone + Debug + Eq + Hash {}
+impl<K> QueryPanickedViaPayload<K> for &QueryPanickedKey<K>
+where
+    K: Clone + Display + Send + 'static,
+{
+    fn throw(&self, key: &K) -> ! {
+        QueryPanicked::throw(key.clone())
+    }
+
+    fn message(&self, payload: &(dyn Any + Send)) -> Option<String> {
+        let panicked = payload.downcast_ref::<QueryPanicked<K>>()?;
+        Some(panicked.to_string())
+    }
+}
+
+/// Panics with a message naming the query, for the keys that are not
+/// `Send` or not `'static`.
+pub trait QueryPanickedViaMessage<K> {
+    /// Panics with a message naming the query of `key`.
+    fn throw(&self, key: &K) -> !;
+
+    /// Returns `None`: there are no payloads to describe.
+    fn message(&self, payload: &(dyn Any + Send)) -> Option<String>;
+}
+
+impl<K: Display> QueryPanickedViaMessage<K> for QueryPanickedKey<K> {
+    fn throw(&self, key: &K) -> ! {
+        panic!("concurrent salsa query {} panicked", key)
+    }
+
+    fn message(&self, _payload: &(dyn Any + Send)) -> Option<String> {
+        None
+    }
+}
 
 pub trait QueryFunction<DB: Database>: Query<DB> {
     fn execute(db: &DB, key: Self::Key) -> Self::Value;
+
+    /// Estimates the cost of keeping a value memoized; set with the
+    /// `#[salsa::lru_cost]` attribute. If present, the LRU capacity of
+    /// the query is a budget for the total cost of its values.
+    const LRU_COST: Option<fn(&Self::Value) -> usize> = None;
+
+    /// Computes a fingerprint of a value; set with the
+    /// `#[salsa::fingerprint]` attribute. Values with equal
+    /// fingerprints are considered equal when backdating.
+    const FINGERPRINT: Option<fn(&Self::Value) -> u128> = None;
+
+    /// Gives the store in which memoized values are kept instead of
+    /// memory; set with the `#[salsa::value_store]` attribute.
+    const VALUE_STORE: Option<ValueStoreFn<DB, Self::Key, Self::Value>> = None;
+
+    /// How long after it was computed a memoized value expires; set
+    /// with the `#[salsa::max_age]` attribute. Expired values are
+    /// recomputed when read, even within a revision.
+    const MAX_AGE: Option<Duration> = None;
+
+    /// If true, memoized values are only validated against the inputs
+    /// that were already verified in the current revision, and
+    /// re-executed otherwise; set with the
+    /// `#[salsa::shallow_revalidation]` attribute.
+    const SHALLOW_REVALIDATION: bool = false;
+
+    /// If true, memos keep the value that their value replaced; set
+    /// with the `#[salsa::keep_previous]` attribute.
+    const KEEP_PREVIOUS: bool = false;
+
+    /// If set, panics of `execute` are caught and turned into a value
+    /// with this function; set with the `#[salsa::catch_panics]`
+    /// attribute.
+    const CATCH_PANICS: Option<fn(QueryPanic) -> Self::Value> = None;
+
+    /// If set, values for which this returns true are not memoized,
+    /// only the inputs they were computed from; set to skip the errors
+    /// of `#[salsa::fallible(no_memoize)]` queries.
+    const FORGET_VALUE: Option<fn(&Self::Value) -> bool> = None;
+
+    /// Compares values when backdating the memos of `EqWithStorage`
+    /// queries, which are never backdated if this is `None`; set with
+    /// the `#[salsa::eq_with]` attribute (or left unset by
+    /// `#[salsa::no_backdate]`).
+    const VALUE_EQ: Option<ValueEqFn<Self::Value>> = None;
 }
 
+/// Gives the value store of a query; see `QueryFunction::VALUE_STORE`.
+pub type ValueStoreFn<DB, K, V> = fn(&DB) -> &dyn ValueStore<K, V>;
+
+/// Compares the values of a query; see `QueryFunction::VALUE_EQ`.
+pub type ValueEqFn<V> = fn(&V, &V) -> bool;
+
 /// The `GetQueryTable` trait makes the connection the *database type*
 /// `DB` and some specific *query type* `Q` that it supports. Note
 /// that the `Database` trait itself is not specific to any query, and
@@ -131,6 +367,9 @@ where
 
     /// "Upcast" a group key into a database key.
     fn database_key(group_key: G::GroupKey) -> Self::DatabaseKey;
+
+    /// Executes the callback for each query in the group.
+    fn for_each_query(db: &Self, op: &mut dyn FnMut(&dyn QueryStorageMassOps<Self>));
 }
 
 pub trait QueryStorageOps<DB, Q>: Default
@@ -146,11 +385,57 @@ where
     /// Returns `Err` in the event of a cycle, meaning that computing
     /// the value for this `key` is recursively attempting to fetch
     /// itself.
-    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleDetected>;
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>>;
+
+    /// Like `try_fetch`, but may borrow the value rather than clone
+    /// it; see `QueryTable::get_ref`. The default clones the value
+    /// returned by `try_fetch`.
+    fn try_fetch_ref<'a>(
+        &'a self,
+        db: &'a DB,
+        key: &Q::Key,
+    ) -> Result<ValueGuard<'a, Q::Value>, CycleError<DB::DatabaseKey>> {
+        self.try_fetch(db, key).map(ValueGuard::owned)
+    }
+
+    /// Like `try_fetch`, but returns the value as of `revision`, for a
+    /// snapshot pinned at that revision once the database has moved on
+    /// (see `ParallelDatabase::snapshot_at_current_revision`). The
+    /// default panics, as only `#[salsa::multi_version]` queries keep
+    /// the values of older revisions.
+    fn try_fetch_at(
+        &self,
+        _db: &DB,
+        key: &Q::Key,
+        revision: Revision,
+    ) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        not_multi_version::<DB, Q>(key, revision)
+    }
 
     /// Returns the durability associated with a given key.
     fn durability(&self, db: &DB, key: &Q::Key) -> Durability;
 
+    /// True if the value for `key` may have changed since `revision`;
+    /// see `QueryTable::maybe_changed_since`.
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool;
+
+    /// Returns the value for `key` if it is known in the current
+    /// revision, without computing it or recording a read; see
+    /// `QueryTable::peek`.
+    fn peek(&self, db: &DB, key: &Q::Key) -> Option<Q::Value>;
+
+    /// Like `peek`, but if another thread is computing the value,
+    /// returns a future of that value; see `QueryTable::peek_or_wait`.
+    /// The default invokes `peek`, which is appropriate for storage
+    /// whose values are never computed by another thread.
+    fn peek_or_wait(&self, db: &DB, key: &Q::Key) -> PeekFuture<Q::Value> {
+        PeekFuture::ready(self.peek(db, key))
+    }
+
+    /// Like `QueryStorageMassOps::sweep`, but only considers the
+    /// given keys; see `QueryTable::sweep_keys`.
+    fn sweep_keys(&self, db: &DB, keys: &mut dyn Iterator<Item = Q::Key>, strategy: SweepStrategy);
+
     /// Get the (current) set of the entries in the query storage
     fn entries<C>(&self, db: &DB) -> C
     where
@@ -173,6 +458,56 @@ where
         new_value: Q::Value,
         durability: Durability,
     );
+
+    fn set_durability(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        durability: Durability,
+    );
+
+    /// Removes the value of `key`, if any.
+    fn remove(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey);
+
+    /// True if `key` is set to `value`, with the given durability.
+    fn is_set_to(&self, key: &Q::Key, value: &Q::Value, durability: Durability) -> bool
+    where
+        Q::Value: Eq;
+
+    /// Reads the value of `key`, returning `None` (and recording the
+    /// read) if no value is set.
+    fn try_fetch_maybe(&self, db: &DB, key: &Q::Key) -> Option<Q::Value>;
+
+    /// Like `try_fetch_maybe`, but returns the value as of `revision`;
+    /// see `QueryStorageOps::try_fetch_at`.
+    fn try_fetch_maybe_at(&self, db: &DB, key: &Q::Key, revision: Revision) -> Option<Q::Value>;
+}
+
+/// An optional trait that is implemented for the storage of interned
+/// queries.
+pub trait InternedQueryStorageOps<DB, Q>: Default
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    /// Returns the id that `key` is interned as, if it is interned
+    /// already, without interning it; see `QueryTable::lookup_existing`.
+    fn lookup_existing(&self, db: &DB, key: &Q::Key) -> Option<Q::Value>;
+
+    /// Writes the interned values and their ids; see
+    /// `QueryTable::export_interned`.
+    #[cfg(feature = "persist")]
+    fn export_interned(&self, out: &mut dyn std::io::Write) -> std::io::Result<()>
+    where
+        Q::Key: serde::Serialize;
+
+    /// Interns the values written by `export_interned` under the same
+    /// ids; see `QueryTable::import_interned`.
+    #[cfg(feature = "persist")]
+    fn import_interned(&self, db: &DB, reader: &mut dyn std::io::Read) -> std::io::Result<()>
+    where
+        Q::Key: serde::de::DeserializeOwned;
 }
 
 /// An optional trait that is implemented for "user mutable" storage:
@@ -181,3 +516,110 @@ where
 pub trait LruQueryStorageOps: Default {
     fn set_lru_capacity(&self, new_capacity: usize);
 }
+
+/// An optional trait that is implemented for the storage of derived
+/// queries.
+pub trait DerivedQueryStorageOps<DB, Q>: Default
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    /// Installs `value` as the memoized value of `key`, in a new
+    /// revision; see `QueryTableMut::set_memo`.
+    fn set_memo(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        database_key: &DB::DatabaseKey,
+        value: Q::Value,
+        durability: Durability,
+    );
+
+    /// Marks the memoized value of `key` (if any) as invalidated, in a
+    /// new revision; see `QueryTableMut::invalidate`.
+    fn invalidate(&self, db: &DB, key: &Q::Key, database_key: &DB::DatabaseKey);
+
+    /// Records `value` as the value of `key`, specified by the query
+    /// that is currently executing; see `QueryTable::specify`.
+    fn specify(&self, db: &DB, key: &Q::Key, value: Q::Value);
+
+    /// Returns the entries whose memoized value changed since
+    /// `revision`; see `QueryTable::changed_entries_since`.
+    fn changed_entries_since(
+        &self,
+        db: &DB,
+        revision: Revision,
+    ) -> Vec<ChangedEntry<Q::Key, Q::Value>>;
+
+    /// Adds the slots without inputs of their own (inputs, mostly) that
+    /// the memoized value of `key` transitively depends on to `inputs`;
+    /// returns false if the inputs of the memo are not tracked (or there
+    /// is no memo). See `QueryTable::export_results`.
+    #[cfg(feature = "persist")]
+    fn transitive_inputs(&self, key: &Q::Key, inputs: &mut TransitiveInputs<DB>) -> bool;
+
+    /// Installs `value` as the memoized value of `key`, computed from
+    /// `inputs`, unless the key has a memo already; returns whether it
+    /// was installed. See `QueryTable::import_results`.
+    #[cfg(feature = "persist")]
+    fn import_result(
+        &self,
+        db: &DB,
+        key: &Q::Key,
+        value: Q::Value,
+        inputs: &[&HashedInput<DB>],
+    ) -> bool;
+}
+
+/// An optional trait that is implemented for storage whose values can
+/// be evicted or swept, allowing individual keys to be exempted.
+pub trait PinQueryStorageOps<DB, Q>: Default
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    /// Pins (or unpins) the value of `key`: the value of a pinned key
+    /// is never evicted by the LRU lists nor discarded by sweeps.
+    fn set_pinned(&self, key: &Q::Key, pinned: bool);
+}
+
+/// Panics because `key` was read from a snapshot pinned at `revision`
+/// after the database moved on, although `Q` is not multi-version.
+pub(crate) fn not_multi_version<DB, Q>(key: &Q::Key, revision: Revision) -> !
+where
+    DB: Database,
+    Q: Query<DB>,
+{
+    panic!(
+        "cannot read {:?}({:?}) from a snapshot pinned at {:?}: the database has moved on, \
+         and the query is not `#[salsa::multi_version]`",
+        Q::default(),
+        debug_key::<DB, Q>(key),
+        revision
+    )
+}
+
+/// A write to an input, as recorded by the table of the input; see
+/// `salsa::replay`.
+pub trait ReplayWrite<DB>: Debug {
+    /// Makes the write again, on `db`.
+    fn replay(&self, db: &mut DB);
+}
+
+/// A write to an input made while recording; see
+/// `QueryStorageMassOps::take_recorded_writes`.
+pub struct RecordedWrite<DB> {
+    pub(crate) seq: u64,
+    pub(crate) revision: Revision,
+    pub(crate) write: Box<dyn ReplayWrite<DB>>,
+}
+
+impl<DB> RecordedWrite<DB> {
+    pub(crate) fn new(seq: u64, revision: Revision, write: Box<dyn ReplayWrite<DB>>) -> Self {
+        RecordedWrite {
+            seq,
+            revision,
+            write,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{ParallelDatabase, Priority};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// The worker threads started by [`ParallelDatabase::prefetch`].
+/// Dropping the handle leaves them running in the background.
+///
+/// [`ParallelDatabase::prefetch`]: trait.ParallelDatabase.html#method.prefetch
+pub struct Prefetch {
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Prefetch {
+    /// Blocks until every worker is done, either because all the
+    /// queries were fetched or because a new revision cancelled them.
+    pub fn wait(self) {
+        for worker in self.workers {
+            // Workers catch the panics of the queries they fetch.
+            let _ = worker.join();
+        }
+    }
+}
+
+pub(crate) fn spawn<DB>(db: &DB, database_keys: Vec<DB::DatabaseKey>) -> Prefetch
+where
+    DB: ParallelDatabase + 'static,
+    DB::DatabaseKey: Send,
+{
+    let worker_count = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(database_keys.len());
+    let queue: Arc<Mutex<VecDeque<DB::DatabaseKey>>> =
+        Arc::new(Mutex::new(database_keys.into_iter().collect()));
+    let workers = (0..worker_count)
+        .map(|_| {
+            let snapshot = db.snapshot();
+            snapshot.salsa_runtime().set_priority(Priority::Background);
+            let queue = queue.clone();
+            std::thread::spawn(move || loop {
+                if snapshot.salsa_runtime().is_current_revision_canceled() {
+                    break;
+                }
+                let database_key = match queue.lock().pop_front() {
+                    Some(database_key) => database_key,
+                    None => break,
+                };
+                // A query that panics will panic again when it is read
+                // in the foreground, so we just move on to the next one.
+                // The same goes for queries that we yielded to a
+                // foreground runtime; if the revision was cancelled,
+                // we stop above.
+                let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    snapshot.fetch_by_key(&database_key)
+                }));
+            })
+        })
+        .collect();
+    Prefetch { workers }
+}
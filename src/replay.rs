@@ -0,0 +1,261 @@
+//! Recording a session and replaying it deterministically.
+//!
+//! While recording (see `start_recording`), the runtime captures every
+//! write to an input (`set`, `set_durability` and `remove`, with the
+//! values that were set) and every top-level read: a `get`, `get_ref`
+//! or `get_maybe` that is not made from inside a query, along with
+//! the time it took. `stop_recording` returns the resulting
+//! `Recording`, whose `replay` performs the same writes and reads on a
+//! fresh database, in the same order and grouped into the same
+//! revisions.
+//!
+//! This reproduces performance problems and incremental bugs that
+//! depend on the exact history of a database rather than on its final
+//! state: embed the harness in the application, record a session in
+//! which the problem shows up, and replay it in a test or a profiler.
+//! `Recording::replay` reports how long each read took then and now.
+//!
+//! Other changes, such as `set_memo`, invalidation tokens and
+//! synthetic writes, are not recorded. Reads of interned queries are
+//! replayed as lookups, which do not intern anything.
+
+use crate::plumbing::ReplayWrite;
+use crate::revision::Revision;
+use crate::time::Instant;
+use crate::Database;
+use std::fmt;
+use std::time::Duration;
+
+/// Starts recording the writes and top-level reads made on `db` and
+/// its snapshots; see the module documentation. A recording that was
+/// already in progress is discarded.
+pub fn start_recording<DB: Database + 'static>(db: &DB) {
+    drop(stop_recording(db));
+    db.salsa_runtime().start_recording();
+}
+
+/// Stops recording and returns what was recorded since
+/// `start_recording`; the recording is empty if none was in progress.
+pub fn stop_recording<DB: Database + 'static>(db: &DB) -> Recording<DB> {
+    let reads = db
+        .salsa_runtime()
+        .stop_recording()
+        .map(RecordingState::into_reads)
+        .unwrap_or_default();
+    let mut writes = Vec::new();
+    db.for_each_query(|query_storage| query_storage.take_recorded_writes(&mut writes));
+
+    let mut steps: Vec<(u64, Step<DB>)> = reads
+        .into_iter()
+        .map(|read| {
+            let step = Step::Read {
+                database_key: read.database_key,
+                elapsed: read.elapsed,
+            };
+            (read.seq, step)
+        })
+        .chain(writes.into_iter().map(|write| {
+            let step = Step::Write {
+                revision: write.revision,
+                write: write.write,
+            };
+            (write.seq, step)
+        }))
+        .collect();
+    steps.sort_by_key(|&(seq, _)| seq);
+    Recording {
+        steps: steps.into_iter().map(|(_, step)| step).collect(),
+    }
+}
+
+/// The writes and top-level reads made while recording, in order; see
+/// `stop_recording`.
+pub struct Recording<DB: Database> {
+    steps: Vec<Step<DB>>,
+}
+
+enum Step<DB: Database> {
+    Write {
+        revision: Revision,
+        write: Box<dyn ReplayWrite<DB>>,
+    },
+    Read {
+        database_key: DB::DatabaseKey,
+        elapsed: Duration,
+    },
+}
+
+impl<DB: Database> Recording<DB> {
+    /// Returns the number of writes and reads in the recording.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns true if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Performs the recorded writes and reads on `db`, which is meant
+    /// to be a fresh database: consecutive writes that were made in
+    /// the same revision are made in a single transaction again.
+    /// Returns how long each read took, both when it was recorded and
+    /// now.
+    pub fn replay(&self, db: &mut DB) -> ReplayReport<DB::DatabaseKey> {
+        let mut reads = Vec::new();
+        let mut transaction: Option<(Revision, bool)> = None;
+        for step in &self.steps {
+            if let Some((revision, nested)) = transaction {
+                let same_revision = match step {
+                    Step::Write { revision: r, .. } => *r == revision,
+                    Step::Read { .. } => false,
+                };
+                if !same_revision {
+                    db.salsa_runtime().end_transaction(nested);
+                    transaction = None;
+                }
+            }
+            match step {
+                Step::Write { revision, write } => {
+                    if transaction.is_none() {
+                        let nested = db.salsa_runtime().begin_transaction();
+                        transaction = Some((*revision, nested));
+                    }
+                    write.replay(db);
+                }
+                Step::Read {
+                    database_key,
+                    elapsed,
+                } => {
+                    let start = Instant::now();
+                    db.fetch_by_key(database_key);
+                    reads.push(ReadTiming {
+                        database_key: database_key.clone(),
+                        recorded: *elapsed,
+                        replayed: start.elapsed(),
+                    });
+                }
+            }
+        }
+        if let Some((_, nested)) = transaction {
+            db.salsa_runtime().end_transaction(nested);
+        }
+        ReplayReport { reads }
+    }
+}
+
+impl<DB: Database> fmt::Debug for Recording<DB> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = fmt.debug_list();
+        for step in &self.steps {
+            match step {
+                Step::Write { write, .. } => list.entry(write),
+                Step::Read { database_key, .. } => list.entry(database_key),
+            };
+        }
+        list.finish()
+    }
+}
+
+/// How long the reads of a `Recording` took; see `Recording::replay`.
+/// Its `Display` output lists the reads with both timings, followed by
+/// the totals.
+#[derive(Clone, Debug)]
+pub struct ReplayReport<K> {
+    reads: Vec<ReadTiming<K>>,
+}
+
+/// How long a recorded read took.
+#[derive(Clone, Debug)]
+pub struct ReadTiming<K> {
+    /// The database-key of the query that was read.
+    pub database_key: K,
+
+    /// How long the read took when it was recorded.
+    pub recorded: Duration,
+
+    /// How long it took when it was replayed.
+    pub replayed: Duration,
+}
+
+impl<K> ReplayReport<K> {
+    /// Returns the timings of the reads, in the order in which they
+    /// were made.
+    pub fn reads(&self) -> &[ReadTiming<K>] {
+        &self.reads
+    }
+
+    /// Returns the total time that the reads took when they were
+    /// recorded.
+    pub fn total_recorded(&self) -> Duration {
+        self.reads.iter().map(|read| read.recorded).sum()
+    }
+
+    /// Returns the total time that the reads took when they were
+    /// replayed.
+    pub fn total_replayed(&self) -> Duration {
+        self.reads.iter().map(|read| read.replayed).sum()
+    }
+}
+
+impl<K: fmt::Display> fmt::Display for ReplayReport<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for read in &self.reads {
+            writeln!(
+                fmt,
+                "{}: recorded {:?}, replayed {:?}",
+                read.database_key, read.recorded, read.replayed
+            )?;
+        }
+        write!(
+            fmt,
+            "total: recorded {:?}, replayed {:?}",
+            self.total_recorded(),
+            self.total_replayed()
+        )
+    }
+}
+
+/// A top-level read made while recording.
+pub(crate) struct RecordedRead<K> {
+    seq: u64,
+    database_key: K,
+    elapsed: Duration,
+}
+
+/// The reads recorded by the runtime so far, and the sequence number
+/// of the next read or write, which orders them.
+pub(crate) struct RecordingState<K> {
+    next_seq: u64,
+    reads: Vec<RecordedRead<K>>,
+}
+
+impl<K> Default for RecordingState<K> {
+    fn default() -> Self {
+        RecordingState {
+            next_seq: 0,
+            reads: Vec::new(),
+        }
+    }
+}
+
+impl<K> RecordingState<K> {
+    pub(crate) fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    pub(crate) fn record_read(&mut self, database_key: K, elapsed: Duration) {
+        let seq = self.next_seq();
+        self.reads.push(RecordedRead {
+            seq,
+            database_key,
+            elapsed,
+        });
+    }
+
+    fn into_reads(self) -> Vec<RecordedRead<K>> {
+        self.reads
+    }
+}
@@ -0,0 +1,141 @@
+use crate::dependency::Dependency;
+use crate::revision::Revision;
+use crate::runtime::FxIndexSet;
+use crate::{Database, ParallelDatabase};
+use parking_lot::Mutex;
+use std::any::Any;
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Checks the inputs of memos in parallel; see
+/// `ParallelDatabase::set_parallel_revalidation`.
+pub(crate) struct ParallelRevalidation<DB: Database> {
+    /// Memos with fewer inputs are checked on the current thread.
+    min_inputs: usize,
+
+    /// Returns the index of an input that may have changed, if any.
+    /// As `DB` is not known to be a `ParallelDatabase` where the inputs
+    /// are checked, this is set to `find_changed_input_in_parallel`
+    /// where it is.
+    find_changed_input: fn(&DB, &FxIndexSet<Dependency<DB>>, Revision) -> Option<usize>,
+}
+
+impl<DB: Database> ParallelRevalidation<DB> {
+    pub(crate) fn new(min_inputs: usize) -> Self
+    where
+        DB: ParallelDatabase,
+        DB::DatabaseData: Send + Sync,
+    {
+        ParallelRevalidation {
+            min_inputs,
+            find_changed_input: find_changed_input_in_parallel::<DB>,
+        }
+    }
+}
+
+impl<DB: Database> Clone for ParallelRevalidation<DB> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<DB: Database> Copy for ParallelRevalidation<DB> {}
+
+/// Returns an input that may have changed since `revision`, if any.
+/// Which one is unspecified if several did, as they may be checked
+/// in parallel.
+pub(crate) fn find_changed_input<'i, DB: Database>(
+    db: &DB,
+    inputs: &'i FxIndexSet<Dependency<DB>>,
+    revision: Revision,
+) -> Option<&'i Dependency<DB>> {
+    if let Some(parallel) = db.salsa_runtime().parallel_revalidation() {
+        if inputs.len() >= parallel.min_inputs {
+            return (parallel.find_changed_input)(db, inputs, revision)
+                .and_then(|index| inputs.get_index(index));
+        }
+    }
+
+    inputs
+        .iter()
+        .find(|input| input.maybe_changed_since(db, revision))
+}
+
+/// Checks `inputs` on worker threads, each with its own snapshot of
+/// the database, until one of them finds an input that may have
+/// changed.
+///
+/// The workers never block on a query that another runtime is
+/// computing, as that runtime may be waiting for us (see
+/// `Runtime::give_up_revalidation`). They give up on the input
+/// instead, and we check the inputs they gave up on ourselves.
+fn find_changed_input_in_parallel<DB>(
+    db: &DB,
+    inputs: &FxIndexSet<Dependency<DB>>,
+    revision: Revision,
+) -> Option<usize>
+where
+    DB: ParallelDatabase,
+    DB::DatabaseData: Send + Sync,
+{
+    let runtime = db.salsa_runtime();
+    let worker_count = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(inputs.len());
+    let snapshots: Vec<_> = (0..worker_count)
+        .map(|_| runtime.fork_revalidation_worker(|| db.snapshot()))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let changed = AtomicBool::new(false);
+    let changed_input = AtomicUsize::new(usize::MAX);
+    let given_up: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    let panic: Mutex<Option<Box<dyn Any + Send>>> = Mutex::new(None);
+
+    std::thread::scope(|scope| {
+        for snapshot in snapshots {
+            let (next, changed, changed_input) = (&next, &changed, &changed_input);
+            let (given_up, panic) = (&given_up, &panic);
+            scope.spawn(move || {
+                while !changed.load(Ordering::Acquire) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let input = match inputs.get_index(index) {
+                        Some(input) => input,
+                        None => break,
+                    };
+                    match std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        input.maybe_changed_since(&*snapshot, revision)
+                    })) {
+                        Ok(false) => (),
+                        Ok(true) => {
+                            changed_input.fetch_min(index, Ordering::Relaxed);
+                            changed.store(true, Ordering::Release);
+                        }
+                        Err(_) if snapshot.salsa_runtime().is_yielding() => {
+                            snapshot.salsa_runtime().end_yielding();
+                            given_up.lock().push(index);
+                        }
+                        Err(payload) => {
+                            panic.lock().get_or_insert(payload);
+                            changed.store(true, Ordering::Release);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(payload) = panic.into_inner() {
+        std::panic::resume_unwind(payload);
+    }
+    if changed.into_inner() {
+        return Some(changed_input.into_inner());
+    }
+
+    let mut given_up = given_up.into_inner();
+    given_up.sort_unstable();
+    given_up
+        .into_iter()
+        .find(|&index| inputs[index].maybe_changed_since(db, revision))
+}
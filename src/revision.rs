@@ -8,8 +8,9 @@ const START_U64: u64 = 1;
 /// A unique identifier for the current version of the database; each
 /// time an input is changed, the revision number is incremented.
 /// `Revision` is used internally to track which values may need to be
-/// recomputed, but is not something you should have to interact with
-/// directly as a user of salsa.
+/// recomputed. Users mostly don't need it, except to later ask whether
+/// a query may have changed since a given revision; see
+/// `Runtime::current_revision` and `QueryTable::maybe_changed_since`.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Revision {
     generation: NonZeroU64,
@@ -30,7 +31,7 @@ impl Revision {
         Self::from(self.generation.get() + 1)
     }
 
-    fn as_u64(self) -> u64 {
+    pub(crate) fn as_u64(self) -> u64 {
         self.generation.get()
     }
 }
@@ -0,0 +1,141 @@
+use crate::durability::Durability;
+use crate::invalidation_token::InvalidationToken;
+use crate::revision::Revision;
+use std::collections::VecDeque;
+
+/// The changes made to inputs in one revision, as recorded by the
+/// runtime while a revision log is kept; see
+/// [`Runtime::set_revision_log_capacity`].
+///
+/// [`Runtime::set_revision_log_capacity`]: struct.Runtime.html#method.set_revision_log_capacity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevisionLogEntry<K> {
+    /// The revision that the changes created.
+    pub revision: Revision,
+
+    /// The changes, in the order in which they were made. A revision
+    /// created within a transaction (see `Database::transaction`) may
+    /// have several.
+    pub changes: Vec<InputChange<K>>,
+}
+
+/// A change recorded in a [`RevisionLogEntry`].
+///
+/// [`RevisionLogEntry`]: struct.RevisionLogEntry.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputChange<K> {
+    /// The input was set.
+    Set {
+        /// The database-key of the input.
+        database_key: K,
+
+        /// The durability it was set with.
+        durability: Durability,
+    },
+
+    /// The durability of the input was changed (see
+    /// `QueryTableMut::set_durability`) without changing its value.
+    DurabilityChanged {
+        /// The database-key of the input.
+        database_key: K,
+
+        /// The new durability.
+        durability: Durability,
+    },
+
+    /// The value of the input was removed.
+    Removed {
+        /// The database-key of the input.
+        database_key: K,
+
+        /// The durability that the value had.
+        durability: Durability,
+    },
+
+    /// The memoized value of a derived query was set (see
+    /// `QueryTableMut::set_memo`).
+    MemoSet {
+        /// The database-key of the query.
+        database_key: K,
+
+        /// The durability it was set with.
+        durability: Durability,
+    },
+
+    /// The memoized value of a derived query was invalidated (see
+    /// `QueryTableMut::invalidate`).
+    Invalidated {
+        /// The database-key of the query.
+        database_key: K,
+
+        /// The durability that the value had.
+        durability: Durability,
+    },
+
+    /// An invalidation token was invalidated (see
+    /// `Database::invalidate_token`).
+    TokenInvalidated {
+        /// The token.
+        token: InvalidationToken,
+    },
+
+    /// A synthetic write (see `Runtime::synthetic_write`).
+    SyntheticWrite {
+        /// The durability of the write.
+        durability: Durability,
+    },
+}
+
+/// The last `capacity` entries of the revision log.
+pub(crate) struct RevisionLog<K> {
+    capacity: usize,
+    entries: VecDeque<RevisionLogEntry<K>>,
+}
+
+impl<K> Default for RevisionLog<K> {
+    fn default() -> Self {
+        RevisionLog {
+            capacity: 0,
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Clone> RevisionLog<K> {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Sets the number of revisions to keep, discarding the oldest
+    /// entries if there are too many.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.truncate();
+    }
+
+    pub(crate) fn record(&mut self, revision: Revision, change: InputChange<K>) {
+        if !self.is_enabled() {
+            return;
+        }
+        match self.entries.back_mut() {
+            Some(entry) if entry.revision == revision => entry.changes.push(change),
+            _ => {
+                self.entries.push_back(RevisionLogEntry {
+                    revision,
+                    changes: vec![change],
+                });
+                self.truncate();
+            }
+        }
+    }
+
+    pub(crate) fn entries(&self) -> Vec<RevisionLogEntry<K>> {
+        self.entries.iter().cloned().collect()
+    }
+
+    fn truncate(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+}
@@ -1,22 +1,43 @@
+use crate::dedup::ValueTables;
 use crate::dependency::DatabaseSlot;
 use crate::dependency::Dependency;
 use crate::durability::Durability;
+#[cfg(feature = "dynamic")]
+use crate::dynamic::DynamicQueries;
+use crate::invalidation_token::{InvalidationToken, TokenSlots};
+use crate::journal::{Journal, JournalWriteId};
+use crate::logging::debug;
+use crate::lru::{GlobalLruNode, Lru};
+use crate::replay::RecordingState;
+use crate::revalidate::ParallelRevalidation;
 use crate::revision::{AtomicRevision, Revision};
-use crate::{Database, Event, EventKind, SweepStrategy};
-use log::debug;
-use parking_lot::{Mutex, RwLock};
+use crate::revision_log::{InputChange, RevisionLog, RevisionLogEntry};
+use crate::statistics::{QueryStatistics, Statistics, StatisticsMode, WriteLockStatistics};
+use crate::stream::StreamShared;
+use crate::time::Instant;
+use crate::{
+    Cancelled, CycleError, CycleRuntime, DanglingInProgress, Database, Event, EventKind,
+    InvalidationReason, MaybeSend, MaybeSync, MemoryReport, Query, QueryTimedOut,
+    RecursionLimitExceeded, SweepBudget, SweepConfig, SweepPolicy, SweepStrategy,
+};
+use crossbeam::atomic::AtomicCell;
 use parking_lot::lock_api::{RawRwLock, RawRwLockRecursive};
-use rustc_hash::{FxHashMap, FxHasher};
+use parking_lot::{Mutex, RwLock};
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
 use smallvec::SmallVec;
-use std::fmt::Write;
-use std::hash::BuildHasherDefault;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
+use std::hash::{BuildHasherDefault, Hash};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub(crate) type FxIndexSet<K> = indexmap::IndexSet<K, BuildHasherDefault<FxHasher>>;
 
 mod local_state;
 use local_state::LocalState;
+pub(crate) use local_state::SlotBorrow;
 
 /// The salsa runtime stores the storage for all queries as well as
 /// tracking the query stack and dependencies between cycles.
@@ -34,11 +55,35 @@ pub struct Runtime<DB: Database> {
     /// lock.
     revision_guard: Option<RevisionGuard<DB>>,
 
+    /// If this runtime belongs to a snapshot created with
+    /// `ParallelDatabase::snapshot_at_current_revision`, the revision
+    /// it is pinned at; such runtimes hold no lock.
+    pinned_at: Option<PinnedRevision<DB>>,
+
     /// Local state that is specific to this runtime (thread).
     local_state: LocalState<DB>,
 
     /// Shared state that is accessible via all runtimes.
     shared_state: Arc<SharedState<DB>>,
+
+    /// Whether writes are currently being batched; see
+    /// `Database::transaction`.
+    transaction: AtomicCell<TransactionState>,
+}
+
+/// See `Database::transaction`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TransactionState {
+    /// Every write creates a new revision.
+    None,
+
+    /// Inside a transaction; the next write creates a new revision.
+    Open,
+
+    /// Inside a transaction; the given revision was created by the
+    /// previous write, and nothing was read since, so further writes
+    /// can go into it as well.
+    Writing(Revision),
 }
 
 impl<DB> Default for Runtime<DB>
@@ -49,8 +94,10 @@ where
         Runtime {
             id: RuntimeId { counter: 0 },
             revision_guard: None,
+            pinned_at: None,
             shared_state: Default::default(),
             local_state: Default::default(),
+            transaction: AtomicCell::new(TransactionState::None),
         }
     }
 }
@@ -63,11 +110,28 @@ where
         fmt.debug_struct("Runtime")
             .field("id", &self.id())
             .field("forked", &self.revision_guard.is_some())
+            .field("pinned_at", &self.pinned_revision())
             .field("shared_state", &self.shared_state)
             .finish()
     }
 }
 
+impl<DB> Drop for Runtime<DB>
+where
+    DB: Database,
+{
+    fn drop(&mut self) {
+        self.shared_state.live_runtimes.lock().remove(&self.id);
+        if self.local_state.priority() == Priority::Background {
+            self.shared_state
+                .background_runtimes
+                .lock()
+                .remove(&self.id);
+            self.shared_state.yield_requests.lock().remove(&self.id);
+        }
+    }
+}
+
 impl<DB> Runtime<DB>
 where
     DB: Database,
@@ -98,22 +162,162 @@ where
             "invoked `snapshot` with a non-matching database"
         );
 
-        if self.local_state.query_in_progress() {
+        if self.local_state.query_in_progress() && !self.local_state.is_forking() {
             panic!("it is not legal to `snapshot` during a query (see salsa-rs/salsa#80)");
         }
 
-        let revision_guard = RevisionGuard::new(&self.shared_state);
+        // The snapshot may memoize values in the current revision, so
+        // a transaction cannot add more writes to it.
+        self.end_transaction_batch();
+
+        // Snapshots of a pinned snapshot are pinned at the same
+        // revision.
+        let nested = self.holds_query_lock();
+        let pinned_at = if self.local_state.is_pinning() {
+            Some(PinnedRevision::new(&self.shared_state, None, nested))
+        } else {
+            self.pinned_at.as_ref().map(|pinned_at| {
+                PinnedRevision::new(&self.shared_state, Some(pinned_at.revision), nested)
+            })
+        };
+        let revision_guard = match pinned_at {
+            Some(_) => None,
+            None => Some(RevisionGuard::new(&self.shared_state)),
+        };
 
         let id = RuntimeId {
             counter: self.shared_state.next_id.fetch_add(1, Ordering::SeqCst),
         };
+        self.shared_state.live_runtimes.lock().insert(id);
 
-        Runtime {
+        let local_state = LocalState::default();
+        if self.local_state.is_forking() {
+            local_state.set_revalidation_worker(true);
+        }
+
+        let runtime = Runtime {
             id,
-            revision_guard: Some(revision_guard),
+            revision_guard,
+            pinned_at,
             shared_state: self.shared_state.clone(),
-            local_state: Default::default(),
+            local_state,
+            transaction: AtomicCell::new(TransactionState::None),
+        };
+        if self.local_state.is_forking() {
+            runtime.set_priority(self.priority());
+        }
+        runtime
+    }
+
+    /// Invokes `snapshot`, which snapshots the database of this
+    /// runtime, to create a worker for
+    /// `ParallelDatabase::set_parallel_revalidation`. Unlike other
+    /// snapshots, this one can be created while a query is executing.
+    pub(crate) fn fork_revalidation_worker<R>(&self, snapshot: impl FnOnce() -> R) -> R {
+        self.local_state.set_forking(true);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(snapshot));
+        self.local_state.set_forking(false);
+        result.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+    }
+
+    /// Invokes `snapshot`, which snapshots the database of this
+    /// runtime, to create a snapshot pinned at the current revision;
+    /// see `ParallelDatabase::snapshot_at_current_revision`.
+    pub(crate) fn pin_snapshot<R>(&self, snapshot: impl FnOnce() -> R) -> R {
+        self.local_state.set_pinning(true);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(snapshot));
+        self.local_state.set_pinning(false);
+        result.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+    }
+
+    /// If this runtime belongs to a snapshot created with
+    /// `ParallelDatabase::snapshot_at_current_revision`, returns the
+    /// revision that the snapshot observes. This stays the same when
+    /// the database moves on to newer revisions.
+    pub fn pinned_revision(&self) -> Option<Revision> {
+        self.pinned_at.as_ref().map(|pinned_at| pinned_at.revision)
+    }
+
+    /// True if this runtime may hold the query lock (in read mode),
+    /// now or while its snapshots run.
+    fn holds_query_lock(&self) -> bool {
+        self.revision_guard.is_some()
+            || self.local_state.query_in_progress()
+            || self
+                .pinned_at
+                .as_ref()
+                .is_some_and(|pinned_at| pinned_at.nested)
+    }
+
+    /// For a pinned runtime, performs the usual `read` if the database
+    /// is still at the pinned `revision`, holding the query lock so
+    /// that it cannot move on meanwhile. Returns `None` if it did
+    /// already: the value has to be read as of `revision` instead.
+    pub(crate) fn read_pinned<R>(&self, revision: Revision, read: impl FnOnce() -> R) -> Option<R> {
+        if self.current_revision() != revision {
+            return None;
+        }
+        // The lock is fair: if a writer is waiting for it, we wait for
+        // the writer, which moves the database on to a new revision
+        // (so we return `None` below). Unless we may hold the lock
+        // already: then we must not wait for a writer that waits for us.
+        let _lock = if self.holds_query_lock() {
+            self.shared_state.query_lock.read_recursive()
+        } else {
+            self.shared_state.query_lock.read()
+        };
+        if self.current_revision() != revision {
+            return None;
+        }
+        Some(read())
+    }
+
+    /// True if a snapshot is pinned at a revision in `from..until`; a
+    /// value that was current in those revisions must then be kept when
+    /// it is replaced.
+    pub(crate) fn is_pinned_within(&self, from: Revision, until: Revision) -> bool {
+        from < until
+            && self
+                .shared_state
+                .pinned_revisions
+                .lock()
+                .range(from..until)
+                .next()
+                .is_some()
+    }
+
+    /// Returns how to check the inputs of memos in parallel, if
+    /// enabled (and if this runtime is not itself a worker doing so).
+    pub(crate) fn parallel_revalidation(&self) -> Option<ParallelRevalidation<DB>> {
+        if self.local_state.is_revalidation_worker() {
+            return None;
         }
+        self.shared_state.parallel_revalidation.load()
+    }
+
+    pub(crate) fn set_parallel_revalidation(&self, parallel: Option<ParallelRevalidation<DB>>) {
+        self.shared_state.parallel_revalidation.store(parallel);
+    }
+
+    /// Invoked when about to block on a query that `other_id` is
+    /// computing. A worker checking inputs on behalf of another
+    /// runtime must not block, as that runtime, which waits for its
+    /// workers, may be what `other_id` is (transitively) blocked on.
+    /// The worker unwinds instead, like a runtime that yields, and
+    /// the input it was checking is checked again by the runtime it
+    /// works for.
+    pub(crate) fn give_up_revalidation_if_worker(&self) {
+        if self.local_state.is_revalidation_worker() {
+            debug!("giving up revalidation instead of blocking");
+            self.local_state.set_yielding(true);
+            Cancelled::throw();
+        }
+    }
+
+    /// Resets the state set by `give_up_revalidation_if_worker`, once
+    /// unwound.
+    pub(crate) fn end_yielding(&self) {
+        self.local_state.set_yielding(false);
     }
 
     /// A "synthetic write" causes the system to act *as though* some
@@ -146,9 +350,515 @@ where
     pub fn synthetic_write(&self, durability: Durability) {
         self.with_incremented_revision(|guard| {
             guard.mark_durability_as_changed(durability);
+            guard.record_change(|| InputChange::SyntheticWrite { durability });
+        });
+    }
+
+    /// Invalidates `token` in a new revision; see
+    /// `Database::invalidate_token`.
+    pub(crate) fn invalidate_token(&self, token: &InvalidationToken) {
+        self.with_incremented_revision(|guard| {
+            self.shared_state
+                .invalidation_tokens
+                .invalidate(token, guard.new_revision());
+            guard.mark_durability_as_changed(Durability::LOW);
+            guard.record_change(|| InputChange::TokenInvalidated {
+                token: token.clone(),
+            });
         });
     }
 
+    /// Enables (or disables) the collection of execution statistics
+    /// for derived queries. The statistics are shared with all
+    /// snapshots of this runtime and can be read with
+    /// `Database::salsa_statistics` or `QueryTable::statistics`.
+    pub fn set_statistics_mode(&self, mode: StatisticsMode) {
+        self.shared_state.statistics_mode.store(mode);
+    }
+
+    /// Returns the current statistics mode.
+    pub fn statistics_mode(&self) -> StatisticsMode {
+        self.shared_state.statistics_mode.load()
+    }
+
+    /// Discards all statistics collected so far, including the
+    /// statistics of the query lock.
+    pub fn reset_statistics(&self) {
+        *self.shared_state.statistics.lock() = Statistics::default();
+        *self.shared_state.write_lock_statistics.lock() = WriteLockStatistics::default();
+    }
+
+    /// Returns how long writes to this database waited for the query
+    /// lock so far. Unlike the statistics of queries, these are always
+    /// collected.
+    pub fn write_lock_statistics(&self) -> WriteLockStatistics {
+        *self.shared_state.write_lock_statistics.lock()
+    }
+
+    /// Default implementation for `Database::salsa_statistics`.
+    pub fn statistics(&self) -> Vec<(&'static str, QueryStatistics)> {
+        self.shared_state.statistics.lock().summary()
+    }
+
+    pub(crate) fn query_statistics<Q: Query<DB>>(&self) -> QueryStatistics {
+        self.shared_state.statistics.lock().query(TypeId::of::<Q>())
+    }
+
+    pub(crate) fn key_statistics(&self, database_key: &DB::DatabaseKey) -> QueryStatistics {
+        self.shared_state.statistics.lock().key(database_key)
+    }
+
+    /// Returns the time at which an execution starts, if statistics
+    /// are being collected.
+    pub(crate) fn statistics_start(&self) -> Option<Instant> {
+        match self.statistics_mode() {
+            StatisticsMode::Off => None,
+            StatisticsMode::PerQuery | StatisticsMode::PerKey => Some(Instant::now()),
+        }
+    }
+
+    /// Applies `update` to the statistics for `Q` (and, depending on
+    /// the mode, for the key returned by `database_key`), if
+    /// statistics are being collected.
+    pub(crate) fn record_statistics<Q: Query<DB>>(
+        &self,
+        database_key: impl FnOnce() -> DB::DatabaseKey,
+        update: impl Fn(&mut QueryStatistics),
+    ) {
+        let key = match self.statistics_mode() {
+            StatisticsMode::Off => return,
+            StatisticsMode::PerQuery => None,
+            StatisticsMode::PerKey => Some(database_key()),
+        };
+        self.shared_state.statistics.lock().record(
+            TypeId::of::<Q>(),
+            Q::QUERY_NAME,
+            key.as_ref(),
+            update,
+        );
+    }
+
+    /// Replaces a value computed by a `#[salsa::dedup]` query by an
+    /// equal value that is already shared, if any; returns true if it
+    /// did.
+    pub(crate) fn dedup_value<T>(&self, value: &mut Arc<T>) -> bool
+    where
+        T: Eq + Hash + MaybeSend + MaybeSync + 'static,
+    {
+        self.shared_state.value_tables.lock().dedup(value)
+    }
+
+    /// Default implementation for `Database::salsa_memory_usage`.
+    pub fn memory_usage(&self, db: &DB) -> Vec<(&'static str, MemoryReport)> {
+        let mut reports = vec![];
+        db.for_each_query(|query_storage| reports.push(query_storage.memory_usage(db)));
+        reports.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        reports
+    }
+
+    /// Default implementation for `Database::set_global_lru_budget`.
+    pub fn set_global_lru_budget(&self, budget: usize) {
+        self.shared_state.global_lru.set_lru_cost_budget(budget);
+    }
+
+    /// Registers a listener that is invoked for each `Event` that
+    /// occurs in this runtime or in any of its snapshots, in addition
+    /// to `Database::salsa_event`. Several listeners can be registered
+    /// at once; they are invoked in the order in which they were
+    /// registered, on the thread where the event occurs. Listeners
+    /// must not (un)subscribe themselves, as that would deadlock.
+    /// With the `strip-logging` feature of salsa, no events are
+    /// reported, so listeners are never invoked.
+    ///
+    /// Returns an id that can be passed to `unsubscribe_events`.
+    pub fn subscribe_events(&self, listener: EventListener<DB>) -> SubscriptionId {
+        let id = SubscriptionId {
+            counter: self
+                .shared_state
+                .next_subscription_id
+                .fetch_add(1, Ordering::SeqCst),
+        };
+        self.shared_state
+            .event_listeners
+            .write()
+            .push((id, listener));
+        id
+    }
+
+    /// Removes a listener registered with `subscribe_events`. Returns
+    /// false if there was no such listener.
+    pub fn unsubscribe_events(&self, id: SubscriptionId) -> bool {
+        let mut listeners = self.shared_state.event_listeners.write();
+        let len = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != len
+    }
+
+    /// Records that `creator` creates tracked entities, so that the
+    /// revision in which it last executed is kept from now on; see
+    /// `TrackedStorage`.
+    pub(crate) fn register_creator(&self, creator: &DB::DatabaseKey) {
+        if self.shared_state.creators.read().contains_key(creator) {
+            return;
+        }
+        self.shared_state
+            .creators
+            .write()
+            .entry(creator.clone())
+            .or_insert_with(Revision::start);
+    }
+
+    /// Returns the last revision in which `creator`, which was
+    /// registered with `register_creator`, finished executing (or
+    /// `None` if it is not registered).
+    pub(crate) fn last_execution_of(&self, creator: &DB::DatabaseKey) -> Option<Revision> {
+        self.shared_state.creators.read().get(creator).copied()
+    }
+
+    /// Registers `poll`, which checks a watched query for changes; see
+    /// `QueryTable::watch`.
+    pub(crate) fn register_watch(&self, poll: WatchPoll<DB>) -> SubscriptionId {
+        let id = SubscriptionId {
+            counter: self
+                .shared_state
+                .next_subscription_id
+                .fetch_add(1, Ordering::SeqCst),
+        };
+        self.shared_state
+            .watches
+            .lock()
+            .push((id, Arc::new(Mutex::new(poll))));
+        id
+    }
+
+    /// Removes a watch registered with `QueryTable::watch`. Returns
+    /// false if there was no such watch.
+    pub fn unwatch(&self, id: SubscriptionId) -> bool {
+        let mut watches = self.shared_state.watches.lock();
+        let len = watches.len();
+        watches.retain(|(watch_id, _)| *watch_id != id);
+        watches.len() != len
+    }
+
+    /// Default implementation for `Database::poll_watches`.
+    pub fn poll_watches(&self, db: &DB) -> usize {
+        let watches: Vec<_> = self
+            .shared_state
+            .watches
+            .lock()
+            .iter()
+            .map(|(_, poll)| poll.clone())
+            .collect();
+        watches
+            .iter()
+            .filter(|poll| {
+                // A callback that polls again skips its own watch.
+                match poll.try_lock() {
+                    Some(mut poll) => (*poll)(db),
+                    None => false,
+                }
+            })
+            .count()
+    }
+
+    /// Reports an event to `Database::salsa_event` and to the
+    /// listeners registered with `subscribe_events`.
+    pub(crate) fn report_event(&self, db: &DB, event_fn: impl Fn() -> Event<DB>) {
+        if cfg!(feature = "strip-logging") {
+            return;
+        }
+        db.salsa_event(&event_fn);
+        let listeners = self.shared_state.event_listeners.read();
+        if !listeners.is_empty() {
+            let event = event_fn();
+            for (_, listener) in listeners.iter() {
+                listener(&event);
+            }
+        }
+    }
+
+    /// Delivers `item` to the streams reading the active query (see
+    /// `QueryTable::stream`) right away, before the query completes.
+    /// Does nothing if there is no active query or no such stream, or
+    /// if the stream has a different item type.
+    ///
+    /// The items emitted by a query must be a prefix, in order, of the
+    /// items of the value it eventually returns: once the value is
+    /// complete, a stream only receives the items that were not
+    /// emitted yet.
+    pub fn emit_partial<T: Clone + Send + 'static>(&self, item: T) {
+        if self.shared_state.stream_count.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let database_key = match self.local_state.active_query() {
+            Some(database_key) => database_key,
+            None => return,
+        };
+        if let Some(subscriptions) = self.shared_state.streams.lock().get(&database_key) {
+            for subscription in subscriptions.iter().filter(|s| s.active) {
+                if let Some(sink) = subscription.sink.downcast_ref::<StreamShared<T>>() {
+                    sink.push(item.clone());
+                }
+            }
+        }
+    }
+
+    /// Registers `sink` to receive the items emitted (with
+    /// `emit_partial`) by executions of `database_key` that start from
+    /// now on.
+    pub(crate) fn register_stream(
+        &self,
+        database_key: DB::DatabaseKey,
+        sink: Arc<dyn Any + Send + Sync>,
+    ) -> SubscriptionId {
+        let id = SubscriptionId {
+            counter: self
+                .shared_state
+                .next_subscription_id
+                .fetch_add(1, Ordering::SeqCst),
+        };
+        self.shared_state
+            .streams
+            .lock()
+            .entry(database_key)
+            .or_default()
+            .push(StreamSubscription {
+                id,
+                active: false,
+                sink,
+            });
+        self.shared_state
+            .stream_count
+            .fetch_add(1, Ordering::SeqCst);
+        id
+    }
+
+    /// Removes a stream registered with `register_stream`.
+    pub(crate) fn unregister_stream(&self, database_key: &DB::DatabaseKey, id: SubscriptionId) {
+        let mut streams = self.shared_state.streams.lock();
+        if let Some(subscriptions) = streams.get_mut(database_key) {
+            let len = subscriptions.len();
+            subscriptions.retain(|subscription| subscription.id != id);
+            let removed = len - subscriptions.len();
+            if subscriptions.is_empty() {
+                streams.remove(database_key);
+            }
+            self.shared_state
+                .stream_count
+                .fetch_sub(removed, Ordering::SeqCst);
+        }
+    }
+
+    /// Enables (or disables) invalidation tracing: while enabled,
+    /// each time a derived query has to be executed, the runtime
+    /// records why its memoized value could not be reused. See
+    /// `Database::last_invalidation_reason`. Disabling tracing
+    /// discards the reasons recorded so far.
+    pub fn set_invalidation_tracing(&self, enabled: bool) {
+        self.shared_state
+            .trace_invalidations
+            .store(enabled, Ordering::SeqCst);
+        if !enabled {
+            self.shared_state.invalidation_reasons.lock().clear();
+        }
+    }
+
+    /// Default implementation for `Database::last_invalidation_reason`.
+    pub fn last_invalidation_reason(
+        &self,
+        database_key: &DB::DatabaseKey,
+    ) -> Option<InvalidationReason<DB::DatabaseKey>> {
+        self.shared_state
+            .invalidation_reasons
+            .lock()
+            .get(database_key)
+            .cloned()
+    }
+
+    /// Records why `database_key` is about to be executed, if
+    /// invalidation tracing is enabled.
+    pub(crate) fn record_invalidation(
+        &self,
+        database_key: &DB::DatabaseKey,
+        reason: InvalidationReason<DB::DatabaseKey>,
+    ) {
+        if self.shared_state.trace_invalidations.load(Ordering::SeqCst) {
+            self.shared_state
+                .invalidation_reasons
+                .lock()
+                .insert(database_key.clone(), reason);
+        }
+    }
+
+    /// Sets the number of revisions for which the runtime records the
+    /// inputs that were changed (see `Runtime::revision_log`); only
+    /// the most recent `capacity` revisions are kept. A capacity of 0
+    /// (the default) disables the log and discards its entries.
+    pub fn set_revision_log_capacity(&self, capacity: usize) {
+        self.shared_state.revision_log.lock().set_capacity(capacity);
+    }
+
+    /// Returns the changes made to inputs in each of the most recent
+    /// revisions, oldest first, as recorded since the revision log was
+    /// enabled with `Runtime::set_revision_log_capacity`. This helps
+    /// to explain why queries were re-executed. Only revisions in
+    /// which inputs were changed (or synthetic writes were made) are
+    /// listed.
+    pub fn revision_log(&self) -> Vec<RevisionLogEntry<DB::DatabaseKey>> {
+        self.shared_state.revision_log.lock().entries()
+    }
+
+    /// Sets the number of steps that the journal of input writes keeps
+    /// for `Database::undo`; only the most recent `capacity` steps can
+    /// be undone. A step holds the writes made in one revision, so
+    /// batch related writes with `Database::transaction` to undo them
+    /// together. A capacity of 0 (the default) disables the journal and
+    /// discards its steps.
+    ///
+    /// The journal keeps the old and new values of each write, so
+    /// they are cloned when it is enabled. The values of discarded
+    /// steps are freed the next time an input is written.
+    pub fn set_journal_capacity(&self, capacity: usize) {
+        self.shared_state.journal.lock().set_capacity(capacity);
+    }
+
+    /// Frees the values of the writes that were dropped from the
+    /// journal; see `Runtime::set_journal_capacity`.
+    pub(crate) fn discard_forgotten_writes(&self, db: &DB) {
+        let forgotten = self.shared_state.journal.lock().take_forgotten();
+        if !forgotten.is_empty() {
+            db.for_each_query(|query_storage| query_storage.forget_journaled(&forgotten));
+        }
+    }
+
+    /// Default implementation for `Database::undo` (if `undo`) and
+    /// `Database::redo`: applies the writes of a step, all in one new
+    /// revision. Returns false if there was no step to apply.
+    pub(crate) fn replay_journal(&self, db: &DB, undo: bool) -> bool {
+        let writes = {
+            let mut journal = self.shared_state.journal.lock();
+            if undo {
+                journal.begin_undo()
+            } else {
+                journal.begin_redo()
+            }
+        };
+        let writes = match writes {
+            Some(writes) => writes,
+            None => return false,
+        };
+        debug!("replay_journal(undo={}): {} writes", undo, writes.len());
+
+        let nested = self.begin_transaction();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            for &write in &writes {
+                db.for_each_query(|query_storage| query_storage.replay_journaled(db, write, undo));
+            }
+        }));
+        self.end_transaction(nested);
+        self.shared_state.journal.lock().end_replay();
+        result.unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        self.discard_forgotten_writes(db);
+        true
+    }
+
+    /// Default implementation for `Database::dump_journal`.
+    pub(crate) fn dump_journal(
+        &self,
+        db: &DB,
+        out: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        self.discard_forgotten_writes(db);
+        let steps = self.shared_state.journal.lock().steps();
+        for (revision, writes) in steps {
+            for write in writes {
+                let mut result = Ok(());
+                db.for_each_query(|query_storage| {
+                    if result.is_ok() {
+                        result = query_storage.dump_journaled(revision, write, &mut *out);
+                    }
+                });
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts recording writes and top-level reads; see
+    /// `salsa::replay::start_recording`.
+    pub(crate) fn start_recording(&self) {
+        *self.shared_state.recording.lock() = Some(Default::default());
+        self.shared_state.is_recording.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops recording; returns the reads recorded so far, if a
+    /// recording was in progress.
+    pub(crate) fn stop_recording(&self) -> Option<RecordingState<DB::DatabaseKey>> {
+        self.shared_state
+            .is_recording
+            .store(false, Ordering::SeqCst);
+        self.shared_state.recording.lock().take()
+    }
+
+    /// True if a read made now would be recorded: a recording is in
+    /// progress, and no query is executing on this thread.
+    pub(crate) fn is_recording_top_level_reads(&self) -> bool {
+        self.shared_state.is_recording.load(Ordering::Relaxed)
+            && !self.local_state.query_in_progress()
+    }
+
+    pub(crate) fn record_top_level_read(&self, database_key: DB::DatabaseKey, elapsed: Duration) {
+        if let Some(recording) = &mut *self.shared_state.recording.lock() {
+            recording.record_read(database_key, elapsed);
+        }
+    }
+
+    /// Enables (or disables) determinism checking: while enabled, the
+    /// first time in each revision that a memoized value is reused,
+    /// the query is re-executed to check that it produces the same
+    /// value. See `salsa::testing`.
+    pub(crate) fn set_determinism_checking(&self, enabled: bool) {
+        self.shared_state
+            .check_determinism
+            .store(enabled, Ordering::SeqCst);
+        self.shared_state.determinism_checked.lock().1.clear();
+    }
+
+    /// True if determinism checking is enabled.
+    pub(crate) fn is_checking_determinism(&self) -> bool {
+        self.shared_state.check_determinism.load(Ordering::SeqCst)
+    }
+
+    /// True if the memoized value of `database_key`, which is about to
+    /// be reused, should be re-executed to check it.
+    pub(crate) fn should_check_determinism(
+        &self,
+        database_key: impl FnOnce() -> DB::DatabaseKey,
+    ) -> bool {
+        if !self.shared_state.check_determinism.load(Ordering::SeqCst) {
+            return false;
+        }
+        let revision_now = self.current_revision();
+        let mut checked = self.shared_state.determinism_checked.lock();
+        if checked.0 != revision_now {
+            *checked = (revision_now, FxHashSet::default());
+        }
+        checked.1.insert(database_key())
+    }
+
+    /// Re-executes a query to check its memoized value, without
+    /// recording the execution (or its dependencies) anywhere.
+    pub(crate) fn execute_query_again<V>(
+        &self,
+        database_key: &DB::DatabaseKey,
+        execute: impl FnOnce() -> V,
+    ) -> V {
+        let active_query = self.local_state.push_query(database_key, Durability::MAX);
+        let value = execute();
+        active_query.complete();
+        value
+    }
+
     /// Default implementation for `Database::sweep_all`.
     pub fn sweep_all(&self, db: &DB, strategy: SweepStrategy) {
         // Note that we do not acquire the query lock (or any locks)
@@ -156,7 +866,106 @@ where
         // and there is no need to bring things to a halt. That said,
         // users may wish to guarantee atomicity.
 
-        db.for_each_query(|query_storage| query_storage.sweep(db, strategy));
+        db.for_each_query(|query_storage| query_storage.sweep(db, strategy, None));
+    }
+
+    /// Enables (with `Some`) or disables automatic sweeping; see
+    /// `Database::enable_auto_sweep`.
+    pub fn enable_auto_sweep(&self, config: Option<SweepConfig>) {
+        *self.shared_state.auto_sweep.lock() = config.map(|config| AutoSweep {
+            config,
+            last_sweep: Instant::now(),
+            swept_revision: self.current_revision(),
+        });
+    }
+
+    /// Default implementation for `Database::auto_sweep_tick`.
+    pub fn auto_sweep_tick(&self, db: &DB) -> bool {
+        if !self.permits_increment() || self.live_snapshots() > 0 {
+            return false;
+        }
+
+        let strategy = {
+            let mut auto_sweep = self.shared_state.auto_sweep.lock();
+            let auto_sweep = match &mut *auto_sweep {
+                Some(auto_sweep) => auto_sweep,
+                None => return false,
+            };
+            let revision_now = self.current_revision();
+            if auto_sweep.swept_revision == revision_now
+                || auto_sweep.last_sweep.elapsed() < auto_sweep.config.interval
+            {
+                return false;
+            }
+            auto_sweep.last_sweep = Instant::now();
+            auto_sweep.swept_revision = revision_now;
+            auto_sweep.config.strategy
+        };
+
+        debug!("auto_sweep_tick: sweeping with {:?}", strategy);
+        self.sweep_all(db, strategy);
+        true
+    }
+
+    /// Default implementation for `Database::sweep_incremental`.
+    pub fn sweep_incremental(&self, db: &DB, strategy: SweepStrategy, budget: SweepBudget) -> bool {
+        /// With a time budget, the number of slots swept between
+        /// checks of the time.
+        const BATCH: usize = 64;
+
+        let start_time = Instant::now();
+        let mut remaining = match budget {
+            SweepBudget::Slots(slots) => slots,
+            SweepBudget::Time(_) => usize::MAX,
+        };
+        let mut cursor = self.shared_state.sweep_cursor.lock();
+        let mut exhausted = false;
+        let mut index = 0;
+        db.for_each_query(|query_storage| {
+            if index == cursor.query && !exhausted {
+                loop {
+                    let limit = match budget {
+                        SweepBudget::Slots(_) => remaining,
+                        SweepBudget::Time(time) if start_time.elapsed() < time => BATCH,
+                        SweepBudget::Time(_) => 0,
+                    };
+                    if limit == 0 {
+                        exhausted = true;
+                        break;
+                    }
+                    let (swept, next) = query_storage.sweep_some(db, strategy, cursor.slot, limit);
+                    if let SweepBudget::Slots(_) = budget {
+                        remaining -= swept;
+                    }
+                    match next {
+                        Some(next) => cursor.slot = next,
+                        None => {
+                            cursor.query += 1;
+                            cursor.slot = 0;
+                            break;
+                        }
+                    }
+                }
+            }
+            index += 1;
+        });
+
+        if exhausted {
+            false
+        } else {
+            *cursor = SweepCursor::default();
+            true
+        }
+    }
+
+    /// Default implementation for `Database::sweep_all_with_policy`.
+    pub fn sweep_all_with_policy(
+        &self,
+        db: &DB,
+        strategy: SweepStrategy,
+        policy: &dyn SweepPolicy,
+    ) {
+        db.for_each_query(|query_storage| query_storage.sweep(db, strategy, Some(policy)));
     }
 
     /// The unique identifier attached to this `SalsaRuntime`. Each
@@ -169,12 +978,41 @@ where
     /// Returns the database-key for the query that this thread is
     /// actively executing (if any).
     pub fn active_query(&self) -> Option<DB::DatabaseKey> {
-        self.local_state.active_query()
+        match self.local_state.active_query() {
+            Some(database_key) => Some(database_key),
+            None => self.lent_query_stack()?.pop(),
+        }
     }
 
-    /// Read current value of the revision counter.
+    /// Returns the database-keys of all the queries that this thread
+    /// is actively executing, outermost first (so the last one is the
+    /// `active_query`). Queries that are being revalidated, rather than
+    /// executed, do not appear on the stack.
+    pub fn active_query_stack(&self) -> Vec<DB::DatabaseKey> {
+        let query_stack = self.local_state.borrow_query_stack();
+        if query_stack.is_empty() {
+            return self.lent_query_stack().unwrap_or_default();
+        }
+        query_stack
+            .iter()
+            .map(|active_query| active_query.database_key.clone())
+            .collect()
+    }
+
+    /// While the runtime is blocked on another one (e.g., in a
+    /// `WillBlockOn` event), its query stack is lent to the dependency
+    /// graph (see `try_block_on`): returns its keys.
+    fn lent_query_stack(&self) -> Option<Vec<DB::DatabaseKey>> {
+        self.shared_state
+            .dependency_graph
+            .lock()
+            .lent_query_stack(self.id())
+    }
+
+    /// Read current value of the revision counter. The result can be
+    /// passed to `QueryTable::maybe_changed_since` later on.
     #[inline]
-    pub(crate) fn current_revision(&self) -> Revision {
+    pub fn current_revision(&self) -> Revision {
         self.shared_state.revisions[0].load()
     }
 
@@ -236,6 +1074,12 @@ where
     #[inline]
     pub fn is_current_revision_canceled(&self) -> bool {
         let current_revision = self.current_revision();
+        if let Some(pinned_revision) = self.pinned_revision() {
+            // New revisions do not affect pinned snapshots.
+            if pinned_revision != current_revision {
+                return false;
+            }
+        }
         let pending_revision = self.pending_revision();
         debug!(
             "is_current_revision_canceled: current_revision={:?}, pending_revision={:?}",
@@ -281,6 +1125,317 @@ where
         }
     }
 
+    /// Checks whether the current revision is canceled (see
+    /// [`is_current_revision_canceled`]) and, if so, unwinds with a
+    /// [`Cancelled`] payload. Long-running queries can call this
+    /// periodically so that a pending `set` does not have to wait for
+    /// them to complete.
+    ///
+    /// Unlike `is_current_revision_canceled`, this method does not
+    /// record any read on the active query: if the revision is
+    /// canceled, the query never completes, and so there is no
+    /// memoized result for the read to affect.
+    ///
+    /// Note that salsa already performs this check automatically
+    /// before it (re-)executes a derived query, so you only need to
+    /// call it from queries that do a lot of work on their own.
+    ///
+    /// [`is_current_revision_canceled`]: struct.Runtime.html#method.is_current_revision_canceled
+    /// [`Cancelled`]: struct.Cancelled.html
+    pub fn unwind_if_cancelled(&self) {
+        let current_revision = self.current_revision();
+        let pending_revision = self.pending_revision();
+        if pending_revision > current_revision {
+            debug!(
+                "unwind_if_cancelled: current_revision={:?}, pending_revision={:?}",
+                current_revision, pending_revision
+            );
+            Cancelled::throw();
+        }
+        if self.local_state.is_timed_out() {
+            debug!("unwind_if_cancelled: query timed out");
+            QueryTimedOut::throw();
+        }
+        if self.local_state.priority() == Priority::Background
+            && self.shared_state.yield_requests.lock().remove(&self.id)
+        {
+            debug!("unwind_if_cancelled: yielding to a foreground runtime");
+            self.local_state.set_yielding(true);
+            Cancelled::throw();
+        }
+    }
+
+    /// Unwinds with a [`Cancelled`] payload if a writer is waiting to
+    /// create a new revision, which cannot happen until the queries
+    /// running in the current one complete. Query functions that do a
+    /// lot of work between reads of other queries can call this
+    /// periodically to keep the latency of writes low; see also
+    /// [`set_yield_at_dependency_reads`].
+    ///
+    /// Unlike [`unwind_if_cancelled`], this only checks for writers:
+    /// time limits and yield requests of foreground runtimes are
+    /// ignored. Snapshots pinned with
+    /// `ParallelDatabase::snapshot_at_current_revision` never yield, as
+    /// writers do not wait for them.
+    ///
+    /// [`Cancelled`]: struct.Cancelled.html
+    /// [`set_yield_at_dependency_reads`]: struct.Runtime.html#method.set_yield_at_dependency_reads
+    /// [`unwind_if_cancelled`]: struct.Runtime.html#method.unwind_if_cancelled
+    pub fn yield_if_writer_waiting(&self) {
+        if self.pinned_revision().is_some() {
+            return;
+        }
+        let current_revision = self.current_revision();
+        let pending_revision = self.pending_revision();
+        if pending_revision > current_revision {
+            debug!(
+                "yield_if_writer_waiting: current_revision={:?}, pending_revision={:?}",
+                current_revision, pending_revision
+            );
+            Cancelled::throw();
+        }
+    }
+
+    /// If `enabled`, queries call [`yield_if_writer_waiting`] whenever
+    /// they read another query, so that a write never waits for more
+    /// than one step of the queries running in the current revision
+    /// (plus whatever work the query functions do on their own between
+    /// reads). Disabled by default; applies to this runtime and its
+    /// snapshots.
+    ///
+    /// [`yield_if_writer_waiting`]: struct.Runtime.html#method.yield_if_writer_waiting
+    pub fn set_yield_at_dependency_reads(&self, enabled: bool) {
+        self.shared_state
+            .yield_at_dependency_reads
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Returns the priority of this runtime; see `set_priority`.
+    pub fn priority(&self) -> Priority {
+        self.local_state.priority()
+    }
+
+    /// Sets the priority of this runtime (typically, of a snapshot
+    /// used for work that nobody is waiting for yet, such as
+    /// `ParallelDatabase::prefetch`). Runtimes start out as
+    /// `Priority::Foreground`.
+    ///
+    /// When several runtimes are blocked on a query that another one
+    /// is computing, foreground runtimes are woken first. And when a
+    /// foreground runtime blocks on a query that a background runtime
+    /// is computing, the background runtime gives up the query (and
+    /// any query it is computing it for) at its next cancellation
+    /// check (see [`unwind_if_cancelled`]), unwinding with a
+    /// [`Cancelled`] payload. The foreground runtime then computes the
+    /// query itself, reusing whatever the background runtime already
+    /// memoized on the way.
+    ///
+    /// [`unwind_if_cancelled`]: struct.Runtime.html#method.unwind_if_cancelled
+    /// [`Cancelled`]: struct.Cancelled.html
+    pub fn set_priority(&self, priority: Priority) {
+        self.local_state.set_priority(priority);
+        let mut background_runtimes = self.shared_state.background_runtimes.lock();
+        match priority {
+            Priority::Foreground => {
+                background_runtimes.remove(&self.id);
+                self.shared_state.yield_requests.lock().remove(&self.id);
+            }
+            Priority::Background => {
+                background_runtimes.insert(self.id);
+            }
+        }
+    }
+
+    /// True if we are unwinding in order to yield our queries to a
+    /// foreground runtime, rather than because of a panic.
+    pub(crate) fn is_yielding(&self) -> bool {
+        self.local_state.is_yielding()
+    }
+
+    /// Sets a time limit for executing queries, or removes it if
+    /// `timeout` is `None`. When a query invoked from outside of salsa
+    /// (together with the queries it invokes in turn) takes longer
+    /// than `timeout`, it unwinds with a [`QueryTimedOut`] payload
+    /// instead of running on. Like cancellation, this is cooperative:
+    /// the time is checked whenever salsa is about to (re-)execute a
+    /// derived query, and whenever a query calls
+    /// [`unwind_if_cancelled`]. The limit applies to the queries that
+    /// start executing afterwards, in this runtime and its snapshots.
+    ///
+    /// [`QueryTimedOut`]: struct.QueryTimedOut.html
+    /// [`unwind_if_cancelled`]: struct.Runtime.html#method.unwind_if_cancelled
+    pub fn set_query_timeout(&self, timeout: Option<Duration>) {
+        self.shared_state.query_timeout.store(timeout);
+    }
+
+    /// Sets how often a thread that is blocked on a query executing in
+    /// another runtime checks that this other runtime still exists, or
+    /// disables the check if `interval` is `None`; by default, it
+    /// checks once per second. If the runtime was dropped without
+    /// releasing the query (say, because its thread died without
+    /// unwinding), the blocked thread unwinds with a
+    /// [`DanglingInProgress`] payload naming the query, rather than
+    /// waiting forever. See also
+    /// [`Database::assert_no_dangling_in_progress`].
+    ///
+    /// [`DanglingInProgress`]: struct.DanglingInProgress.html
+    /// [`Database::assert_no_dangling_in_progress`]: trait.Database.html#method.assert_no_dangling_in_progress
+    pub fn set_liveness_check_interval(&self, interval: Option<Duration>) {
+        self.shared_state.liveness_check_interval.store(interval);
+    }
+
+    pub(crate) fn liveness_check_interval(&self) -> Option<Duration> {
+        self.shared_state.liveness_check_interval.load()
+    }
+
+    /// Installs `hook`, which is then invoked whenever this runtime or
+    /// one of its snapshots reaches a [`SchedulePoint`], on the thread
+    /// that reaches it; or removes the hook, if `hook` is `None`. The
+    /// hook can block, so that a test decides the order in which
+    /// threads go through the points where they race on a query (see
+    /// [`testing::Scheduler`]). It must neither read queries nor
+    /// panic, as it may be invoked while a thread unwinds.
+    ///
+    /// [`SchedulePoint`]: enum.SchedulePoint.html
+    /// [`testing::Scheduler`]: testing/struct.Scheduler.html
+    pub fn set_schedule_hook(&self, hook: Option<ScheduleHook>) {
+        let mut schedule_hook = self.shared_state.schedule_hook.write();
+        self.shared_state
+            .has_schedule_hook
+            .store(hook.is_some(), Ordering::SeqCst);
+        *schedule_hook = hook;
+    }
+
+    /// Invokes the hook installed with `set_schedule_hook`, if any.
+    /// Must not be invoked while holding the lock of a slot.
+    pub(crate) fn schedule(&self, point: SchedulePoint) {
+        if !self.shared_state.has_schedule_hook.load(Ordering::Acquire) {
+            return;
+        }
+        // Not holding the lock while the hook blocks.
+        let hook = self.shared_state.schedule_hook.read().clone();
+        if let Some(hook) = hook {
+            hook(self.id(), &point);
+        }
+    }
+
+    /// True if the runtime `id` (this one or one of its snapshots) has
+    /// not been dropped yet.
+    pub(crate) fn is_runtime_live(&self, id: RuntimeId) -> bool {
+        self.shared_state.live_runtimes.lock().contains(&id)
+    }
+
+    /// Returns the queries that are in progress in runtimes that no
+    /// longer exist; see `Database::assert_no_dangling_in_progress`.
+    pub(crate) fn find_dangling_in_progress(&self, db: &DB) -> Option<DanglingInProgress> {
+        let mut queries = Vec::new();
+        db.for_each_query(|query_storage| {
+            query_storage.find_dangling_in_progress(db, &mut queries)
+        });
+        if queries.is_empty() {
+            None
+        } else {
+            Some(DanglingInProgress::new(queries))
+        }
+    }
+
+    /// Enables (or disables) dependency pruning. When enabled, a
+    /// derived query that depends on a query whose values are never
+    /// memoized (see `#[salsa::dependencies]`) does not record the
+    /// inputs of that query as its own inputs, even if it read them
+    /// directly: such a query is re-executed whenever one of its inputs
+    /// changes, so it can never be backdated, and depending on it is
+    /// enough to notice that they changed. This reduces the memory used
+    /// by memos and the number of inputs checked to validate them.
+    ///
+    /// The inputs that are left out still count for the durability and
+    /// the `changed_at` revision of the value. But they no longer show
+    /// up as its inputs (in `DebugQueryTable::debug_dump`, for
+    /// instance), and the query is reported as invalidated by the
+    /// dominating query instead of by them.
+    pub fn set_dependency_pruning(&self, enabled: bool) {
+        self.shared_state
+            .prune_dependencies
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    pub(crate) fn prunes_dependencies(&self) -> bool {
+        self.shared_state.prune_dependencies.load(Ordering::SeqCst)
+    }
+
+    /// The functions of dynamic queries, shared with all snapshots.
+    #[cfg(feature = "dynamic")]
+    pub(crate) fn dynamic_queries(&self) -> &DynamicQueries {
+        &self.shared_state.dynamic_queries
+    }
+
+    /// Sets the maximum number of queries that may be executing at
+    /// once on a single thread (one invoking the next), or removes the
+    /// limit if `limit` is `None` (the default). Executing a query
+    /// beyond the limit unwinds with a [`RecursionLimitExceeded`]
+    /// payload that names the innermost queries, rather than
+    /// (eventually) overflowing the stack. Queries whose memoized
+    /// values are reused do not count towards the limit. The limit
+    /// applies to this runtime and its snapshots.
+    ///
+    /// Alternatively, with the `grow-stack` feature enabled, salsa
+    /// grows the stack as needed whenever it executes a query, so that
+    /// deep chains of queries do not need a thread with a huge stack.
+    ///
+    /// [`RecursionLimitExceeded`]: struct.RecursionLimitExceeded.html
+    pub fn set_recursion_limit(&self, limit: Option<usize>) {
+        self.shared_state.recursion_limit.store(limit);
+    }
+
+    /// Returns the context value of type `T`, if one was set with
+    /// [`set_context`].
+    ///
+    /// Reading a context value does **not** record a dependency: it is
+    /// meant for things that don't affect the results of queries, such
+    /// as handles to external services or the configuration of
+    /// logging, not for inputs.
+    ///
+    /// [`set_context`]: struct.Runtime.html#method.set_context
+    pub fn context<T: Any + MaybeSend + MaybeSync>(&self) -> Option<Arc<T>> {
+        let contexts = self.shared_state.contexts.read();
+        let context = contexts.get(&TypeId::of::<T>())?;
+        if self.local_state.query_in_progress() {
+            context.read_by_query.store(true, Ordering::Relaxed);
+        }
+        Some(context.value.downcast_ref::<Arc<T>>().unwrap().clone())
+    }
+
+    /// Sets the context value of type `T`, which queries can access
+    /// (untracked) with [`context`]; applies to this runtime and its
+    /// snapshots.
+    ///
+    /// Since queries do not depend on context values, replacing one
+    /// does not invalidate anything. In debug builds, this panics if
+    /// called while a query is executing, or if it replaces a value
+    /// that a query has already read: values that change over time
+    /// should be inputs (or use interior mutability, when their
+    /// changes do not affect the results of queries).
+    ///
+    /// [`context`]: struct.Runtime.html#method.context
+    pub fn set_context<T: Any + MaybeSend + MaybeSync>(&self, value: T) {
+        debug_assert!(
+            !self.local_state.query_in_progress(),
+            "set_context invoked while a query is executing"
+        );
+        let previous = self.shared_state.contexts.write().insert(
+            TypeId::of::<T>(),
+            ContextValue {
+                value: Box::new(Arc::new(value)),
+                read_by_query: AtomicBool::new(false),
+            },
+        );
+        debug_assert!(
+            !previous.is_some_and(|previous| previous.read_by_query.load(Ordering::Relaxed)),
+            "set_context replaced a `{}` that queries have read; use an input instead",
+            std::any::type_name::<T>()
+        );
+    }
+
     /// Acquires the **global query write lock** (ensuring that no
     /// queries are executing) and then increments the current
     /// revision counter; invokes `op` with the global query write
@@ -298,18 +1453,51 @@ where
         &self,
         op: impl FnOnce(&DatabaseWriteLockGuard<'_, DB>) -> R,
     ) -> R {
-        log::debug!("increment_revision()");
+        debug!("increment_revision()");
 
         if !self.permits_increment() {
-            panic!("increment_revision invoked during a query computation");
+            match self.local_state.active_query() {
+                Some(database_key) => panic!(
+                    "illegal write: inputs cannot be changed while executing {:?}",
+                    database_key
+                ),
+                None => panic!("increment_revision invoked on a snapshot"),
+            }
+        }
+
+        if let TransactionState::Writing(revision) = self.transaction.load() {
+            if revision == self.current_revision() {
+                debug!("increment_revision: reusing {:?}", revision);
+                let _lock = self.shared_state.query_lock.write();
+                return op(&DatabaseWriteLockGuard {
+                    runtime: self,
+                    new_revision: revision,
+                });
+            }
         }
 
         // Set the `pending_revision` field so that people
         // know current revision is canceled.
         let current_revision = self.shared_state.pending_revision.fetch_then_increment();
 
-        // To modify the revision, we need the lock.
-        let _lock = self.shared_state.query_lock.write();
+        // To modify the revision, we need the lock. If snapshots are
+        // alive, this blocks until they have all been dropped.
+        let (_lock, waited) = match self.shared_state.query_lock.try_write() {
+            Some(lock) => (lock, None),
+            None => {
+                debug!(
+                    "increment_revision: waiting for {} snapshot(s) to be dropped",
+                    self.live_snapshots()
+                );
+                let start = Instant::now();
+                let lock = self.shared_state.query_lock.write();
+                (lock, Some(start.elapsed()))
+            }
+        };
+        self.shared_state
+            .write_lock_statistics
+            .lock()
+            .record(waited);
 
         let old_revision = self.shared_state.revisions[0].fetch_then_increment();
         assert_eq!(current_revision, old_revision);
@@ -318,14 +1506,89 @@ where
 
         debug!("increment_revision: incremented to {:?}", new_revision);
 
+        if self.transaction.load() != TransactionState::None {
+            self.transaction
+                .store(TransactionState::Writing(new_revision));
+        }
+
         op(&DatabaseWriteLockGuard {
             runtime: self,
             new_revision,
         })
     }
 
+    /// Returns the number of snapshots (see
+    /// `ParallelDatabase::snapshot`) of this database that are
+    /// currently alive. While this is non-zero, setting an input
+    /// blocks until they are all dropped; a thread that owns a
+    /// snapshot can check this to avoid deadlocking itself.
+    pub fn live_snapshots(&self) -> usize {
+        self.shared_state.live_snapshots.load(Ordering::SeqCst)
+    }
+
+    /// Starts batching writes into a single revision; returns whether
+    /// a transaction was already in progress, to be passed to
+    /// `end_transaction`.
+    pub(crate) fn begin_transaction(&self) -> bool {
+        let nested = self.transaction.load() != TransactionState::None;
+        if !nested {
+            self.transaction.store(TransactionState::Open);
+        }
+        nested
+    }
+
+    pub(crate) fn end_transaction(&self, nested: bool) {
+        if !nested {
+            self.transaction.store(TransactionState::None);
+        }
+    }
+
+    /// Invoked before reading a derived query. Reading may memoize
+    /// values that are verified in the current revision, so if we are
+    /// in a transaction, the writes that follow must go into a new
+    /// revision.
+    pub(crate) fn end_transaction_batch(&self) {
+        if let TransactionState::Writing(_) = self.transaction.load() {
+            self.transaction.store(TransactionState::Open);
+        }
+    }
+
     pub(crate) fn permits_increment(&self) -> bool {
-        self.revision_guard.is_none() && !self.local_state.query_in_progress()
+        self.revision_guard.is_none()
+            && self.pinned_at.is_none()
+            && !self.local_state.query_in_progress()
+    }
+
+    /// True if no new revision was ever created (i.e., no input has
+    /// been set yet).
+    #[cfg(feature = "persist")]
+    pub(crate) fn is_fresh(&self) -> bool {
+        self.pending_revision() == Revision::start()
+    }
+
+    /// The "last changed" revision of each durability; the first
+    /// element is the current revision.
+    #[cfg(feature = "persist")]
+    pub(crate) fn persisted_revisions(&self) -> Vec<Revision> {
+        self.shared_state
+            .revisions
+            .iter()
+            .map(|revision| revision.load())
+            .collect()
+    }
+
+    /// Overwrites the revisions of a fresh runtime with the ones that
+    /// were persisted (after remapping), so that loaded memos are
+    /// interpreted relative to the history they were computed in.
+    #[cfg(feature = "persist")]
+    pub(crate) fn restore_persisted_revisions(&self, revisions: &[Revision]) {
+        let _lock = self.shared_state.query_lock.write();
+        assert!(self.is_fresh());
+        assert_eq!(revisions.len(), self.shared_state.revisions.len());
+        for (slot, &revision) in self.shared_state.revisions.iter().zip(revisions) {
+            slot.store(revision);
+        }
+        self.shared_state.pending_revision.store(revisions[0]);
     }
 
     pub(crate) fn execute_query_implementation<V>(
@@ -336,13 +1599,45 @@ where
     ) -> ComputedQueryResult<DB, V> {
         debug!("{:?}: execute_query_implementation invoked", database_key);
 
-        db.salsa_event(|| Event {
-            runtime_id: db.salsa_runtime().id(),
+        if let Some(limit) = self.shared_state.recursion_limit.load() {
+            let query_stack = self.local_state.borrow_query_stack();
+            if query_stack.len() >= limit {
+                let skip = (query_stack.len() + 1)
+                    .saturating_sub(RecursionLimitExceeded::REPORTED_QUERIES);
+                let queries = query_stack
+                    .iter()
+                    .map(|active_query| &active_query.database_key)
+                    .chain(Some(database_key))
+                    .skip(skip)
+                    .map(|database_key| database_key.to_string())
+                    .collect();
+                std::mem::drop(query_stack);
+                RecursionLimitExceeded::throw(limit, queries);
+            }
+        }
+
+        self.report_event(db, || Event {
+            runtime_id: self.id(),
             kind: EventKind::WillExecute {
                 database_key: database_key.clone(),
             },
         });
 
+        if self.shared_state.stream_count.load(Ordering::SeqCst) > 0 {
+            if let Some(subscriptions) = self.shared_state.streams.lock().get_mut(database_key) {
+                for subscription in subscriptions {
+                    subscription.active = true;
+                }
+            }
+        }
+
+        if !self.local_state.query_in_progress() {
+            let timeout = self.shared_state.query_timeout.load();
+            self.local_state
+                .set_deadline(timeout.map(|timeout| Instant::now() + timeout));
+            self.local_state.set_yielding(false);
+        }
+
         // Push the active query onto the stack.
         let max_durability = Durability::MAX;
         let active_query = self.local_state.push_query(database_key, max_durability);
@@ -358,6 +1653,15 @@ where
             ..
         } = active_query.complete();
 
+        // Tracked entities that the query did not create again are
+        // dead now; see `register_creator`.
+        if self.shared_state.creators.read().contains_key(database_key) {
+            self.shared_state
+                .creators
+                .write()
+                .insert(database_key.clone(), self.current_revision());
+        }
+
         ComputedQueryResult {
             value,
             durability,
@@ -380,11 +1684,44 @@ where
         durability: Durability,
         changed_at: Revision,
     ) {
+        if self
+            .shared_state
+            .yield_at_dependency_reads
+            .load(Ordering::Relaxed)
+            && self.local_state.query_in_progress()
+        {
+            self.yield_if_writer_waiting();
+        }
         let dependency = Dependency::new(database_slot);
         self.local_state
             .report_query_read(dependency, durability, changed_at);
     }
 
+    /// Records that the memoized value of `slot`, whose cost is now
+    /// `cost`, was used; if a global LRU budget is set, this may evict
+    /// the values of other slots.
+    pub(crate) fn record_global_lru_use<'hack>(
+        &self,
+        db: &DB,
+        slot: &Arc<impl GlobalLruNode<DB> + 'hack>,
+        cost: usize,
+    ) {
+        if self.shared_state.global_lru.cost_budget() == 0 {
+            return;
+        }
+
+        let slot: Arc<dyn GlobalLruNode<DB> + 'hack> = slot.clone();
+        // Unsafety note: It is safe to 'pretend' the trait object is
+        // Send+Sync+'static because the slot is owned by the database
+        // storage, which the shared state contains as well (the same
+        // reasoning as in `Dependency::new`).
+        let slot: Arc<dyn GlobalLruNode<DB> + Send + Sync> = unsafe { std::mem::transmute(slot) };
+        let global_lru = &self.shared_state.global_lru;
+        for evicted in global_lru.record_use_with_cost(&slot, cost) {
+            evicted.evict(db);
+        }
+    }
+
     /// Reports that the query depends on some state unknown to salsa.
     ///
     /// Queries which report untracked reads will be re-executed in the next
@@ -394,6 +1731,42 @@ where
             .report_untracked_read(self.current_revision());
     }
 
+    /// Reports that the query depends on some state unknown to salsa,
+    /// which only changes when an input of durability `durability` (or
+    /// lower) changes. To tell salsa about such a change (say, when a
+    /// file that is watched separately was modified), invoke
+    /// `synthetic_write` with the same durability.
+    ///
+    /// Like `report_untracked_read`, this means that the query cannot
+    /// be validated by checking its inputs; but unlike it, the query
+    /// is not re-executed in every new revision, only in those where
+    /// some input of durability `durability` or lower changed.
+    pub fn report_synthetic_read(&self, durability: Durability) {
+        self.local_state
+            .report_synthetic_read(durability, self.current_revision());
+    }
+
+    /// Reports that the query depends on the state that `token` stands
+    /// for, so that it is re-executed once the token is invalidated
+    /// (see `Database::invalidate_token`). Unlike with
+    /// `report_untracked_read`, the query can still be reused in later
+    /// revisions, as long as the token was not invalidated and its
+    /// other inputs did not change.
+    pub fn report_read_of(&self, token: &InvalidationToken) {
+        let slot = self.shared_state.invalidation_tokens.get(token);
+        let changed_at = slot.changed_at();
+        self.report_query_read(slot, Durability::LOW, changed_at);
+    }
+
+    /// Reports that the query depends on the query identified by
+    /// `database_key` (see `QueryTable::database_key`), as if it had
+    /// read its value, which this fetches. This lets a query declare
+    /// dependencies that it does not otherwise read, such as the
+    /// queries that some external state it consulted was derived from.
+    pub fn report_dependency(&self, db: &DB, database_key: &DB::DatabaseKey) {
+        db.fetch_by_key(database_key);
+    }
+
     /// An "anonymous" read is a read that doesn't come from executing
     /// a query, but from some other internal operation. It just
     /// modifies the "changed at" to be at least the given revision.
@@ -407,33 +1780,90 @@ where
     }
 
     /// Obviously, this should be user configurable at some point.
-    pub(crate) fn report_unexpected_cycle(&self, database_key: DB::DatabaseKey) -> ! {
-        debug!("report_unexpected_cycle(database_key={:?})", database_key);
+    pub(crate) fn report_unexpected_cycle(&self, error: CycleError<DB::DatabaseKey>) -> ! {
+        debug!("report_unexpected_cycle(cycle={:?})", error.cycle());
+        panic!("{}", error)
+    }
 
+    /// Constructs the error for a cycle that lies entirely within
+    /// this runtime: `database_key` is already being computed further
+    /// up on our own query stack.
+    pub(crate) fn cycle_error(
+        &self,
+        database_key: &DB::DatabaseKey,
+    ) -> CycleError<DB::DatabaseKey> {
         let query_stack = self.local_state.borrow_query_stack();
-        let start_index = (0..query_stack.len())
-            .rev()
-            .filter(|&i| query_stack[i].database_key == database_key)
-            .next()
-            .unwrap();
+        let mut cycle = vec![];
+        push_cycle_path(
+            &mut cycle,
+            database_key,
+            query_stack.iter().map(|q| &q.database_key),
+        );
+        CycleError::new(cycle)
+    }
+
+    /// Records that a `ValueGuard` returned by `QueryTable::get_ref`
+    /// holds a read lock on the slot at `slot` for as long as the
+    /// returned `SlotBorrow` lives; see `is_slot_borrowed`.
+    pub(crate) fn borrow_slot(&self, slot: *const ()) -> SlotBorrow<'_> {
+        self.local_state.borrow_slot(slot as usize)
+    }
+
+    /// True if a `ValueGuard` of this runtime holds a read lock on the
+    /// slot at `slot`, so that locking it for writing on this thread
+    /// would deadlock.
+    pub(crate) fn is_slot_borrowed(&self, slot: *const ()) -> bool {
+        self.local_state.is_slot_borrowed(slot as usize)
+    }
 
-        let mut message = format!("Internal error, cycle detected:\n");
-        for active_query in &query_stack[start_index..] {
-            writeln!(message, "- {:?}\n", active_query.database_key).unwrap();
+    /// Try to make this runtime blocked on `other_id`. Returns an
+    /// error listing the queries involved if `other_id` is already
+    /// (transitively) blocked on us. Otherwise, our query stack is
+    /// lent to the dependency graph (so that a runtime that closes a
+    /// cycle through us can report it) until the returned `BlockedOn`
+    /// is dropped.
+    pub(crate) fn try_block_on(
+        &self,
+        database_key: &DB::DatabaseKey,
+        other_id: RuntimeId,
+    ) -> Result<BlockedOn<'_, DB>, CycleError<DB::DatabaseKey>> {
+        self.shared_state.dependency_graph.lock().add_edge(
+            self.id(),
+            database_key,
+            other_id,
+            &self.local_state,
+        )?;
+
+        if self.local_state.priority() == Priority::Foreground
+            && self
+                .shared_state
+                .background_runtimes
+                .lock()
+                .contains(&other_id)
+        {
+            self.shared_state.yield_requests.lock().insert(other_id);
         }
-        panic!(message)
+        Ok(BlockedOn { runtime: self })
     }
 
-    /// Try to make this runtime blocked on `other_id`. Returns true
-    /// upon success or false if `other_id` is already blocked on us.
-    pub(crate) fn try_block_on(&self, database_key: &DB::DatabaseKey, other_id: RuntimeId) -> bool {
-        self.shared_state
+    /// Stops waiting for the query we blocked on with `try_block_on`
+    /// (if it did not complete yet) and takes back our query stack.
+    fn end_blocking(&self) {
+        let query_stack = self
+            .shared_state
             .dependency_graph
             .lock()
-            .add_edge(self.id(), database_key, other_id)
+            .remove_blocked(self.id());
+        if let Some(query_stack) = query_stack {
+            self.local_state.restore_query_stack(query_stack);
+        }
     }
 
-    pub(crate) fn unblock_queries_blocked_on_self(&self, database_key: &DB::DatabaseKey) {
+    /// Returns the runtimes that were blocked on `database_key`.
+    pub(crate) fn unblock_queries_blocked_on_self(
+        &self,
+        database_key: &DB::DatabaseKey,
+    ) -> Vec<RuntimeId> {
         self.shared_state
             .dependency_graph
             .lock()
@@ -471,6 +1901,38 @@ where
             rev.store(self.new_revision);
         }
     }
+
+    /// Records a write to an input made in the new revision, if the
+    /// journal is enabled; the caller then keeps the old and new values
+    /// under the returned id.
+    pub(crate) fn journal_write(&self) -> Option<JournalWriteId> {
+        self.runtime
+            .shared_state
+            .journal
+            .lock()
+            .record(self.new_revision)
+    }
+
+    /// If a recording is in progress, returns the sequence number
+    /// under which the caller records a write made in the new revision;
+    /// see `salsa::replay`.
+    pub(crate) fn record_write(&self) -> Option<u64> {
+        let shared_state = &self.runtime.shared_state;
+        if !shared_state.is_recording.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut recording = shared_state.recording.lock();
+        recording.as_mut().map(|recording| recording.next_seq())
+    }
+
+    /// Records a change made in the new revision, if the revision log
+    /// is enabled.
+    pub(crate) fn record_change(&self, change: impl FnOnce() -> InputChange<DB::DatabaseKey>) {
+        let mut revision_log = self.runtime.shared_state.revision_log.lock();
+        if revision_log.is_enabled() {
+            revision_log.record(self.new_revision, change());
+        }
+    }
 }
 
 /// State that will be common to all threads (when we support multiple threads)
@@ -480,6 +1942,15 @@ struct SharedState<DB: Database> {
     /// Stores the next id to use for a snapshotted runtime (starts at 1).
     next_id: AtomicU64,
 
+    /// Number of snapshots that are currently alive; while there are
+    /// any, new revisions cannot be created.
+    live_snapshots: AtomicUsize,
+
+    /// The revisions that snapshots created with
+    /// `ParallelDatabase::snapshot_at_current_revision` are pinned at,
+    /// with the number of such snapshots for each.
+    pinned_revisions: Mutex<BTreeMap<Revision, usize>>,
+
     /// Whenever derived queries are executing, they acquire this lock
     /// in read mode. Mutating inputs (and thus creating a new
     /// revision) requires a write lock (thus guaranteeing that no
@@ -488,8 +1959,18 @@ struct SharedState<DB: Database> {
     /// is stored in an `AtomicU64` so it can be cheaply read
     /// without acquiring the lock.  Rather, the `query_lock` is used
     /// to ensure a higher-level consistency property.
+    ///
+    /// The lock prefers writers: once a writer waits for it, readers
+    /// that do not hold it yet wait for the writer. Readers that may
+    /// hold it already (a snapshot taking a snapshot of its own, or a
+    /// query reading another one in a pinned snapshot) acquire it
+    /// recursively instead, so as not to deadlock; they belong to the
+    /// canceled revision, and are expected to wind down promptly.
     query_lock: RwLock<()>,
 
+    /// See `Runtime::write_lock_statistics`.
+    write_lock_statistics: Mutex<WriteLockStatistics>,
+
     /// This is typically equal to `revision` -- set to `revision+1`
     /// when a new revision is pending (which implies that the current
     /// revision is canceled).
@@ -509,17 +1990,204 @@ struct SharedState<DB: Database> {
     /// The dependency graph tracks which runtimes are blocked on one
     /// another, waiting for queries to terminate.
     dependency_graph: Mutex<DependencyGraph<DB>>,
+
+    /// Which execution statistics to collect (if any).
+    statistics_mode: AtomicCell<StatisticsMode>,
+
+    /// Execution statistics collected so far.
+    statistics: Mutex<Statistics<DB::DatabaseKey>>,
+
+    /// The database-wide LRU list, which holds the slots of all
+    /// derived queries once a global budget is set.
+    global_lru: Lru<dyn GlobalLruNode<DB> + Send + Sync>,
+
+    /// Whether to record why derived queries had to be executed.
+    trace_invalidations: AtomicBool,
+
+    /// The reason each query was last executed, while tracing.
+    invalidation_reasons: Mutex<FxHashMap<DB::DatabaseKey, InvalidationReason<DB::DatabaseKey>>>,
+
+    /// Listeners registered with `Runtime::subscribe_events`.
+    event_listeners: RwLock<Vec<(SubscriptionId, EventListener<DB>)>>,
+
+    /// Watches registered with `QueryTable::watch`.
+    watches: Mutex<Vec<(SubscriptionId, Watch<DB>)>>,
+
+    /// Stores the next id to use for an event listener (or stream).
+    next_subscription_id: AtomicU64,
+
+    /// Streams registered with `QueryTable::stream`, indexed by the
+    /// query whose items they receive.
+    streams: Mutex<FxHashMap<DB::DatabaseKey, Vec<StreamSubscription>>>,
+
+    /// Number of registered streams, so that executing a query need
+    /// not lock `streams` when there are none.
+    stream_count: AtomicUsize,
+
+    /// The time limit for executing queries; see
+    /// `Runtime::set_query_timeout`.
+    query_timeout: AtomicCell<Option<Duration>>,
+
+    /// The maximum depth of the query stack; see
+    /// `Runtime::set_recursion_limit`.
+    recursion_limit: AtomicCell<Option<usize>>,
+
+    /// Whether reused memoized values are re-executed to check that
+    /// they are deterministic; see `salsa::testing`.
+    check_determinism: AtomicBool,
+
+    /// The queries checked so far in the given revision, while
+    /// checking determinism.
+    determinism_checked: Mutex<(Revision, FxHashSet<DB::DatabaseKey>)>,
+
+    /// The runtimes that were not dropped yet; see
+    /// `Runtime::set_liveness_check_interval`.
+    live_runtimes: Mutex<FxHashSet<RuntimeId>>,
+
+    /// See `Runtime::set_liveness_check_interval`.
+    liveness_check_interval: AtomicCell<Option<Duration>>,
+
+    /// See `Runtime::set_yield_at_dependency_reads`.
+    yield_at_dependency_reads: AtomicBool,
+
+    /// See `Runtime::set_schedule_hook`.
+    schedule_hook: RwLock<Option<ScheduleHook>>,
+
+    /// True while a schedule hook is installed.
+    has_schedule_hook: AtomicBool,
+
+    /// Runtimes whose priority is `Priority::Background`.
+    background_runtimes: Mutex<FxHashSet<RuntimeId>>,
+
+    /// Background runtimes that a foreground runtime is blocked on;
+    /// they yield at their next cancellation check.
+    yield_requests: Mutex<FxHashSet<RuntimeId>>,
+
+    /// The inputs changed in recent revisions; see
+    /// `Runtime::revision_log`.
+    revision_log: Mutex<RevisionLog<DB::DatabaseKey>>,
+
+    /// The journal of input writes; see
+    /// `Runtime::set_journal_capacity`.
+    journal: Mutex<Journal>,
+
+    /// The reads recorded so far, if a recording is in progress; see
+    /// `salsa::replay`.
+    recording: Mutex<Option<RecordingState<DB::DatabaseKey>>>,
+
+    /// True while a recording is in progress.
+    is_recording: AtomicBool,
+
+    /// Where the next call to `Runtime::sweep_incremental` continues.
+    sweep_cursor: Mutex<SweepCursor>,
+
+    /// The state of automatic sweeping, if enabled; see
+    /// `Runtime::auto_sweep_tick`.
+    auto_sweep: Mutex<Option<AutoSweep>>,
+
+    /// The shared values of `#[salsa::dedup]` queries.
+    value_tables: Mutex<ValueTables>,
+
+    /// How to check the inputs of memos in parallel, if enabled; see
+    /// `ParallelDatabase::set_parallel_revalidation`.
+    parallel_revalidation: AtomicCell<Option<ParallelRevalidation<DB>>>,
+
+    /// Whether dominated dependencies are left out of memos; see
+    /// `Runtime::set_dependency_pruning`.
+    prune_dependencies: AtomicBool,
+
+    /// The functions of dynamic queries; see
+    /// `Database::register_dynamic_query`.
+    #[cfg(feature = "dynamic")]
+    dynamic_queries: DynamicQueries,
+
+    /// The slots of the invalidation tokens that were read or
+    /// invalidated; see `Runtime::report_read_of`.
+    invalidation_tokens: TokenSlots,
+
+    /// For each query that creates tracked entities, the last revision
+    /// in which it finished executing; see `Runtime::register_creator`.
+    creators: RwLock<FxHashMap<DB::DatabaseKey, Revision>>,
+
+    /// The context values, by type; see `Runtime::set_context`.
+    contexts: RwLock<FxHashMap<TypeId, ContextValue>>,
+}
+
+struct ContextValue {
+    /// An `Arc<T>`, where `T` is the type of the value.
+    value: Box<AnyValue>,
+
+    /// Whether a query read the value, which then must not change.
+    read_by_query: AtomicBool,
+}
+
+struct AutoSweep {
+    config: SweepConfig,
+
+    /// When the last automatic sweep happened (or when automatic
+    /// sweeping was enabled).
+    last_sweep: Instant,
+
+    /// The current revision at that time.
+    swept_revision: Revision,
+}
+
+/// A position in the query tables of a database: the index of the
+/// query (in the order of `DatabaseOps::for_each_query`) and of the
+/// slot in its table.
+#[derive(Default)]
+struct SweepCursor {
+    query: usize,
+    slot: usize,
 }
 
 impl<DB: Database> SharedState<DB> {
     fn with_durabilities(durabilities: usize) -> Self {
         SharedState {
             next_id: AtomicU64::new(1),
+            live_snapshots: AtomicUsize::new(0),
+            pinned_revisions: Default::default(),
             storage: Default::default(),
             query_lock: Default::default(),
+            write_lock_statistics: Default::default(),
             revisions: (0..durabilities).map(|_| AtomicRevision::start()).collect(),
             pending_revision: AtomicRevision::start(),
             dependency_graph: Default::default(),
+            statistics_mode: AtomicCell::new(StatisticsMode::Off),
+            statistics: Default::default(),
+            global_lru: Default::default(),
+            trace_invalidations: AtomicBool::new(false),
+            invalidation_reasons: Default::default(),
+            event_listeners: Default::default(),
+            watches: Default::default(),
+            next_subscription_id: AtomicU64::new(0),
+            streams: Default::default(),
+            stream_count: AtomicUsize::new(0),
+            query_timeout: AtomicCell::new(None),
+            recursion_limit: AtomicCell::new(None),
+            check_determinism: AtomicBool::new(false),
+            determinism_checked: Mutex::new((Revision::start(), FxHashSet::default())),
+            live_runtimes: Mutex::new(std::iter::once(RuntimeId { counter: 0 }).collect()),
+            liveness_check_interval: AtomicCell::new(Some(Duration::from_secs(1))),
+            yield_at_dependency_reads: AtomicBool::new(false),
+            schedule_hook: Default::default(),
+            has_schedule_hook: AtomicBool::new(false),
+            background_runtimes: Default::default(),
+            yield_requests: Default::default(),
+            revision_log: Default::default(),
+            journal: Default::default(),
+            recording: Default::default(),
+            is_recording: AtomicBool::new(false),
+            sweep_cursor: Default::default(),
+            auto_sweep: Default::default(),
+            value_tables: Default::default(),
+            parallel_revalidation: AtomicCell::new(None),
+            prune_dependencies: AtomicBool::new(false),
+            #[cfg(feature = "dynamic")]
+            dynamic_queries: Default::default(),
+            invalidation_tokens: Default::default(),
+            creators: Default::default(),
+            contexts: Default::default(),
         }
     }
 }
@@ -614,11 +2282,28 @@ impl<DB: Database> ActiveQuery<DB> {
         self.changed_at = changed_at;
     }
 
+    fn add_synthetic_read(&mut self, durability: Durability, revision: Revision) {
+        self.dependencies = None;
+        self.durability = self.durability.min(durability);
+        self.changed_at = self.changed_at.max(revision);
+    }
+
     fn add_anon_read(&mut self, changed_at: Revision) {
         self.changed_at = self.changed_at.max(changed_at);
     }
 }
 
+/// The priority of a runtime; see `Runtime::set_priority`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Work that somebody is waiting for, such as answering a request.
+    #[default]
+    Foreground,
+
+    /// Work done ahead of time, which gives way to foreground work.
+    Background,
+}
+
 /// A unique identifier for a particular runtime. Each time you create
 /// a snapshot, a fresh `RuntimeId` is generated. Once a snapshot is
 /// complete, its `RuntimeId` may potentially be re-used.
@@ -627,6 +2312,102 @@ pub struct RuntimeId {
     counter: u64,
 }
 
+/// Identifies an event listener registered with
+/// `Runtime::subscribe_events` (or a watch registered with
+/// `QueryTable::watch`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId {
+    counter: u64,
+}
+
+/// A stream registered with `Runtime::register_stream`.
+struct StreamSubscription {
+    id: SubscriptionId,
+
+    /// Set once an execution of the query starts after the stream was
+    /// registered; items emitted by executions that were already
+    /// underway are not delivered, as they may have been missed.
+    active: bool,
+
+    /// The `StreamShared<T>` that receives the items.
+    sink: Arc<dyn Any + Send + Sync>,
+}
+
+/// An event listener; see `Runtime::subscribe_events`.
+#[cfg(not(feature = "single-threaded"))]
+pub type EventListener<DB> = Box<dyn Fn(&Event<DB>) + Send + Sync>;
+
+/// An event listener; see `Runtime::subscribe_events`.
+#[cfg(feature = "single-threaded")]
+pub type EventListener<DB> = Box<dyn Fn(&Event<DB>)>;
+
+/// A schedule hook; see `Runtime::set_schedule_hook`.
+pub type ScheduleHook = Arc<dyn Fn(RuntimeId, &SchedulePoint) + Send + Sync>;
+
+/// The points where threads that read the same derived query race
+/// with one another, at which the hook installed with
+/// `Runtime::set_schedule_hook` is invoked. Reading a value that is
+/// up to date reaches none of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchedulePoint {
+    /// The runtime is about to claim a query that has no up-to-date
+    /// value, so as to validate or execute it (another runtime may
+    /// claim it first).
+    Claim,
+
+    /// The runtime claimed the query, and is about to validate or
+    /// execute it.
+    Execute,
+
+    /// The runtime is about to block until the runtime executing a
+    /// query releases it.
+    Block,
+
+    /// The runtime is done blocking.
+    Unblock,
+
+    /// The runtime released a query it had claimed, with its new value
+    /// or because it panicked. Each runtime in `unblocked` was blocked
+    /// on the query; they are woken up once the hook returns, and reach
+    /// `Unblock` next.
+    Release {
+        /// The runtimes that were blocked on the query.
+        unblocked: Vec<RuntimeId>,
+    },
+}
+
+/// Checks a watched query for changes, invoking its callback if it
+/// changed; returns true if it did. See `QueryTable::watch`.
+#[cfg(not(feature = "single-threaded"))]
+pub(crate) type WatchPoll<DB> = Box<dyn FnMut(&DB) -> bool + Send>;
+
+#[cfg(feature = "single-threaded")]
+pub(crate) type WatchPoll<DB> = Box<dyn FnMut(&DB) -> bool>;
+
+/// A value of any type that is `MaybeSend` and `MaybeSync`.
+#[cfg(not(feature = "single-threaded"))]
+pub(crate) type AnyValue = dyn Any + Send + Sync;
+
+#[cfg(feature = "single-threaded")]
+pub(crate) type AnyValue = dyn Any;
+
+/// A registered watch; locked while it is polled.
+type Watch<DB> = Arc<Mutex<WatchPoll<DB>>>;
+
+/// Returned by `Runtime::try_block_on`: the runtime is blocked on
+/// another one for as long as this lives. Dropping it (once the value
+/// arrived, or when unwinding) stops waiting and takes back the query
+/// stack that the runtime lent to the dependency graph.
+pub(crate) struct BlockedOn<'me, DB: Database> {
+    runtime: &'me Runtime<DB>,
+}
+
+impl<DB: Database> Drop for BlockedOn<'_, DB> {
+    fn drop(&mut self) {
+        self.runtime.end_blocking();
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct StampedValue<V> {
     pub(crate) value: V,
@@ -639,8 +2420,24 @@ struct DependencyGraph<DB: Database> {
     /// `K` is blocked on some query executing in the runtime `V`.
     /// This encodes a graph that must be acyclic (or else deadlock
     /// will result).
-    edges: FxHashMap<RuntimeId, RuntimeId>,
+    edges: FxHashMap<RuntimeId, Edge<DB>>,
     labels: FxHashMap<DB::DatabaseKey, SmallVec<[RuntimeId; 4]>>,
+
+    /// The query stacks of the runtimes that were unblocked, until
+    /// they wake up and take them back.
+    unblocked: FxHashMap<RuntimeId, Vec<ActiveQuery<DB>>>,
+}
+
+struct Edge<DB: Database> {
+    /// The runtime we are blocked on.
+    id: RuntimeId,
+
+    /// The query we are waiting for the other runtime to complete.
+    database_key: DB::DatabaseKey,
+
+    /// The query stack of the blocked runtime, which it lends to us
+    /// while it is blocked; used to reconstruct cross-thread cycles.
+    query_stack: Vec<ActiveQuery<DB>>,
 }
 
 impl<DB: Database> Default for DependencyGraph<DB> {
@@ -648,50 +2445,154 @@ impl<DB: Database> Default for DependencyGraph<DB> {
         DependencyGraph {
             edges: Default::default(),
             labels: Default::default(),
+            unblocked: Default::default(),
         }
     }
 }
 
 impl<DB: Database> DependencyGraph<DB> {
-    /// Attempt to add an edge `from_id -> to_id` into the result graph.
+    /// Attempt to add an edge `from_id -> to_id` into the result
+    /// graph, where `local_state` belongs to `from_id`. If adding the
+    /// edge would create a cycle, returns an error listing all the
+    /// queries (across all runtimes) that participate in it; otherwise,
+    /// the query stack of `from_id` moves into the edge.
     fn add_edge(
         &mut self,
         from_id: RuntimeId,
         database_key: &DB::DatabaseKey,
         to_id: RuntimeId,
-    ) -> bool {
+        local_state: &LocalState<DB>,
+    ) -> Result<(), CycleError<DB::DatabaseKey>> {
         assert_ne!(from_id, to_id);
         debug_assert!(!self.edges.contains_key(&from_id));
 
         // First: walk the chain of things that `to_id` depends on,
         // looking for us.
         let mut p = to_id;
-        while let Some(&q) = self.edges.get(&p) {
-            if q == from_id {
-                return false;
+        while let Some(q) = self.edges.get(&p) {
+            if q.id == from_id {
+                let query_stack = local_state.borrow_query_stack();
+                return Err(self.cycle_error(from_id, &query_stack, database_key, to_id));
             }
 
-            p = q;
+            p = q.id;
         }
 
-        self.edges.insert(from_id, to_id);
+        self.edges.insert(
+            from_id,
+            Edge {
+                id: to_id,
+                database_key: database_key.clone(),
+                query_stack: local_state.take_query_stack(),
+            },
+        );
         self.labels
             .entry(database_key.clone())
             .or_default()
             .push(from_id);
-        true
+        Ok(())
+    }
+
+    /// Reconstructs the cycle that would be created if the runtime
+    /// `from_id`, with query stack `from_stack`, were to block on
+    /// `to_id` (which is computing `database_key`). The cycle starts
+    /// with the portion of `from_stack` that the chain of blocked
+    /// runtimes is waiting on and then follows that chain back around
+    /// to us. The error also records the full query stack of each
+    /// runtime in the chain.
+    fn cycle_error(
+        &self,
+        from_id: RuntimeId,
+        from_stack: &[ActiveQuery<DB>],
+        database_key: &DB::DatabaseKey,
+        to_id: RuntimeId,
+    ) -> CycleError<DB::DatabaseKey> {
+        let keys = |stack: &[ActiveQuery<DB>]| -> Vec<DB::DatabaseKey> {
+            stack.iter().map(|q| q.database_key.clone()).collect()
+        };
+        let mut runtimes = vec![CycleRuntime {
+            runtime_id: from_id,
+            query_stack: keys(from_stack),
+            blocked_on: database_key.clone(),
+        }];
+        let mut rest = vec![];
+        let mut key = database_key;
+        let mut id = to_id;
+        while let Some(edge) = self.edges.get(&id) {
+            push_cycle_path(
+                &mut rest,
+                key,
+                edge.query_stack.iter().map(|q| &q.database_key),
+            );
+            runtimes.push(CycleRuntime {
+                runtime_id: id,
+                query_stack: keys(&edge.query_stack),
+                blocked_on: edge.database_key.clone(),
+            });
+            key = &edge.database_key;
+            id = edge.id;
+        }
+
+        let mut cycle = vec![];
+        push_cycle_path(&mut cycle, key, from_stack.iter().map(|q| &q.database_key));
+        cycle.extend(rest);
+        debug!("cycle across runtimes: {:?}", runtimes);
+        CycleError::new(cycle).with_runtimes(runtimes)
     }
 
-    fn remove_edge(&mut self, database_key: &DB::DatabaseKey, to_id: RuntimeId) {
-        let vec = self
-            .labels
-            .remove(database_key)
-            .unwrap_or_default();
+    fn remove_edge(&mut self, database_key: &DB::DatabaseKey, to_id: RuntimeId) -> Vec<RuntimeId> {
+        let vec = self.labels.remove(database_key).unwrap_or_default();
 
         for from_id in &vec {
-            let to_id1 = self.edges.remove(from_id);
-            assert_eq!(Some(to_id), to_id1);
+            let edge = self.edges.remove(from_id).unwrap();
+            assert_eq!(to_id, edge.id);
+            self.unblocked.insert(*from_id, edge.query_stack);
+        }
+        vec.into_vec()
+    }
+
+    /// The keys of the query stack that `from_id` lent us, if it is
+    /// blocked (or was unblocked, but did not take its stack back yet).
+    fn lent_query_stack(&self, from_id: RuntimeId) -> Option<Vec<DB::DatabaseKey>> {
+        let query_stack = match self.unblocked.get(&from_id) {
+            Some(query_stack) => query_stack,
+            None => &self.edges.get(&from_id)?.query_stack,
+        };
+        Some(query_stack.iter().map(|q| q.database_key.clone()).collect())
+    }
+
+    /// Removes the edge of `from_id` (if it is still blocked) and
+    /// returns the query stack that it lent us, if any.
+    fn remove_blocked(&mut self, from_id: RuntimeId) -> Option<Vec<ActiveQuery<DB>>> {
+        if let Some(query_stack) = self.unblocked.remove(&from_id) {
+            return Some(query_stack);
         }
+        let edge = self.edges.remove(&from_id)?;
+        if let Some(from_ids) = self.labels.get_mut(&edge.database_key) {
+            from_ids.retain(|id| *id != from_id);
+            if from_ids.is_empty() {
+                self.labels.remove(&edge.database_key);
+            }
+        }
+        Some(edge.query_stack)
+    }
+}
+
+/// Appends to `cycle` the suffix of the query stack `path` that
+/// begins with `database_key`. If `database_key` is not on the stack
+/// (e.g., because its memoized value is being revalidated rather than
+/// executed), only `database_key` itself is appended.
+fn push_cycle_path<'a, K>(
+    cycle: &mut Vec<K>,
+    database_key: &K,
+    path: impl IntoIterator<Item = &'a K>,
+) where
+    K: Clone + Eq + 'a,
+{
+    let path: Vec<&K> = path.into_iter().collect();
+    match path.iter().position(|&k| k == database_key) {
+        Some(index) => cycle.extend(path[index..].iter().map(|&k| k.clone())),
+        None => cycle.push(database_key.clone()),
     }
 }
 
@@ -723,6 +2624,7 @@ where
         unsafe {
             shared_state.query_lock.raw().lock_shared_recursive();
         }
+        shared_state.live_snapshots.fetch_add(1, Ordering::SeqCst);
 
         Self {
             shared_state: shared_state.clone(),
@@ -737,8 +2639,61 @@ where
     fn drop(&mut self) {
         // Release our read-lock without using RAII. As documented in
         // `Snapshot::new` above, this requires the unsafe keyword.
+        self.shared_state
+            .live_snapshots
+            .fetch_sub(1, Ordering::SeqCst);
         unsafe {
             self.shared_state.query_lock.raw().unlock_shared();
         }
     }
 }
+
+/// Registers a snapshot as pinned at `revision` (see
+/// `ParallelDatabase::snapshot_at_current_revision`), for as long as
+/// it is alive.
+struct PinnedRevision<DB: Database> {
+    shared_state: Arc<SharedState<DB>>,
+    revision: Revision,
+
+    /// True if the snapshot was taken by a runtime that may hold the
+    /// query lock, and so may wait for the snapshot while holding it.
+    nested: bool,
+}
+
+impl<DB> PinnedRevision<DB>
+where
+    DB: Database,
+{
+    /// Pins `revision`, or the current revision if `None`.
+    fn new(shared_state: &Arc<SharedState<DB>>, revision: Option<Revision>, nested: bool) -> Self {
+        // Holding the query lock, so that no new revision can be
+        // created until the writer knows to keep the values of this
+        // one.
+        let _lock = shared_state.query_lock.read_recursive();
+        let revision = revision.unwrap_or_else(|| shared_state.revisions[0].load());
+        *shared_state
+            .pinned_revisions
+            .lock()
+            .entry(revision)
+            .or_insert(0) += 1;
+        PinnedRevision {
+            shared_state: shared_state.clone(),
+            revision,
+            nested,
+        }
+    }
+}
+
+impl<DB> Drop for PinnedRevision<DB>
+where
+    DB: Database,
+{
+    fn drop(&mut self) {
+        let mut pinned_revisions = self.shared_state.pinned_revisions.lock();
+        let count = pinned_revisions.get_mut(&self.revision).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            pinned_revisions.remove(&self.revision);
+        }
+    }
+}
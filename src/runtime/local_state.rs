@@ -1,8 +1,11 @@
 use crate::dependency::Dependency;
 use crate::durability::Durability;
 use crate::runtime::ActiveQuery;
+use crate::runtime::Priority;
 use crate::runtime::Revision;
+use crate::time::Instant;
 use crate::Database;
+use std::cell::Cell;
 use std::cell::Ref;
 use std::cell::RefCell;
 
@@ -18,12 +21,43 @@ pub(super) struct LocalState<DB: Database> {
     /// Unwinding note: pushes onto this vector must be popped -- even
     /// during unwinding.
     query_stack: RefCell<Vec<ActiveQuery<DB>>>,
+
+    /// When the outermost active query times out, if a query timeout
+    /// was set when it started executing.
+    deadline: Cell<Option<Instant>>,
+
+    /// The priority of this runtime; see `Runtime::set_priority`.
+    priority: Cell<Priority>,
+
+    /// Set while we unwind to yield our slots to a foreground runtime.
+    yielding: Cell<bool>,
+
+    /// Set while `Runtime::fork_revalidation_worker` snapshots us.
+    forking: Cell<bool>,
+
+    /// Set while `Runtime::pin_snapshot` snapshots us.
+    pinning: Cell<bool>,
+
+    /// True if this runtime checks inputs on behalf of another one;
+    /// see `ParallelDatabase::set_parallel_revalidation`.
+    revalidation_worker: Cell<bool>,
+
+    /// The addresses of the slots whose values are borrowed by a live
+    /// `ValueGuard`; see `SlotBorrow`.
+    borrowed_slots: RefCell<Vec<usize>>,
 }
 
 impl<DB: Database> Default for LocalState<DB> {
     fn default() -> Self {
         LocalState {
             query_stack: Default::default(),
+            deadline: Cell::new(None),
+            priority: Cell::new(Priority::Foreground),
+            yielding: Cell::new(false),
+            forking: Cell::new(false),
+            pinning: Cell::new(false),
+            revalidation_worker: Cell::new(false),
+            borrowed_slots: Default::default(),
         }
     }
 }
@@ -51,10 +85,90 @@ impl<DB: Database> LocalState<DB> {
         self.query_stack.borrow()
     }
 
+    /// Takes the query stack, leaving an empty one behind, while the
+    /// runtime is blocked; see `Runtime::try_block_on`.
+    pub(super) fn take_query_stack(&self) -> Vec<ActiveQuery<DB>> {
+        std::mem::take(&mut *self.query_stack.borrow_mut())
+    }
+
+    /// Puts back the query stack taken by `take_query_stack`.
+    pub(super) fn restore_query_stack(&self, query_stack: Vec<ActiveQuery<DB>>) {
+        let mut old_stack = self.query_stack.borrow_mut();
+        debug_assert!(old_stack.is_empty());
+        *old_stack = query_stack;
+    }
+
     pub(super) fn query_in_progress(&self) -> bool {
         !self.query_stack.borrow().is_empty()
     }
 
+    pub(super) fn set_deadline(&self, deadline: Option<Instant>) {
+        self.deadline.set(deadline);
+    }
+
+    /// True if a query is executing and has run past its deadline.
+    pub(super) fn is_timed_out(&self) -> bool {
+        match self.deadline.get() {
+            Some(deadline) => self.query_in_progress() && Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    pub(super) fn priority(&self) -> Priority {
+        self.priority.get()
+    }
+
+    pub(super) fn set_priority(&self, priority: Priority) {
+        self.priority.set(priority);
+    }
+
+    pub(super) fn is_yielding(&self) -> bool {
+        self.yielding.get()
+    }
+
+    pub(super) fn set_yielding(&self, yielding: bool) {
+        self.yielding.set(yielding);
+    }
+
+    pub(super) fn is_forking(&self) -> bool {
+        self.forking.get()
+    }
+
+    pub(super) fn set_forking(&self, forking: bool) {
+        self.forking.set(forking);
+    }
+
+    pub(super) fn is_pinning(&self) -> bool {
+        self.pinning.get()
+    }
+
+    pub(super) fn set_pinning(&self, pinning: bool) {
+        self.pinning.set(pinning);
+    }
+
+    pub(super) fn is_revalidation_worker(&self) -> bool {
+        self.revalidation_worker.get()
+    }
+
+    pub(super) fn set_revalidation_worker(&self, revalidation_worker: bool) {
+        self.revalidation_worker.set(revalidation_worker);
+    }
+
+    /// Records that a `ValueGuard` borrows the value of the slot at
+    /// `slot` until the returned `SlotBorrow` is dropped.
+    pub(super) fn borrow_slot(&self, slot: usize) -> SlotBorrow<'_> {
+        self.borrowed_slots.borrow_mut().push(slot);
+        SlotBorrow {
+            borrowed_slots: &self.borrowed_slots,
+            slot,
+        }
+    }
+
+    /// True if a `ValueGuard` borrows the value of the slot at `slot`.
+    pub(super) fn is_slot_borrowed(&self, slot: usize) -> bool {
+        self.borrowed_slots.borrow().contains(&slot)
+    }
+
     pub(super) fn active_query(&self) -> Option<DB::DatabaseKey> {
         self.query_stack
             .borrow()
@@ -79,6 +193,12 @@ impl<DB: Database> LocalState<DB> {
         }
     }
 
+    pub(super) fn report_synthetic_read(&self, durability: Durability, current_revision: Revision) {
+        if let Some(top_query) = self.query_stack.borrow_mut().last_mut() {
+            top_query.add_synthetic_read(durability, current_revision);
+        }
+    }
+
     pub(super) fn report_anon_read(&self, revision: Revision) {
         if let Some(top_query) = self.query_stack.borrow_mut().last_mut() {
             top_query.add_anon_read(revision);
@@ -126,3 +246,20 @@ where
         self.pop_helper();
     }
 }
+
+/// Returned by `LocalState::borrow_slot`; kept by a `ValueGuard` that
+/// holds a read lock on a slot, so that this runtime can detect that
+/// locking the slot for writing would deadlock.
+pub(crate) struct SlotBorrow<'me> {
+    borrowed_slots: &'me RefCell<Vec<usize>>,
+    slot: usize,
+}
+
+impl Drop for SlotBorrow<'_> {
+    fn drop(&mut self) {
+        let mut borrowed_slots = self.borrowed_slots.borrow_mut();
+        if let Some(index) = borrowed_slots.iter().rposition(|&s| s == self.slot) {
+            borrowed_slots.swap_remove(index);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use parking_lot::Mutex;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// The stored value of a `#[salsa::send_only]` query, whose value type
+/// is `Send` but need not be `Sync` (say, because it contains a
+/// `Cell`).
+///
+/// The database shares references to its values across threads, so a
+/// value is only ever accessed under its lock: it is cloned out with
+/// `get` or moved out with `into_inner`, but never borrowed. That
+/// makes `SendOnly<T>` both `Send` and `Sync` whenever `T: Send`.
+pub struct SendOnly<T> {
+    value: Mutex<T>,
+}
+
+impl<T> SendOnly<T> {
+    /// Wraps `value`.
+    pub fn new(value: T) -> Self {
+        SendOnly {
+            value: Mutex::new(value),
+        }
+    }
+
+    /// Returns a clone of the value.
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.value.lock().clone()
+    }
+
+    /// Returns the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Clone> Clone for SendOnly<T> {
+    fn clone(&self) -> Self {
+        SendOnly::new(self.get())
+    }
+}
+
+impl<T: PartialEq> PartialEq for SendOnly<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+
+        // Lock in address order, so that two threads comparing the
+        // same values the other way around cannot deadlock.
+        let (first, second) = if (self as *const Self) < (other as *const Self) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let first = first.value.lock();
+        let second = second.value.lock();
+        *first == *second
+    }
+}
+
+impl<T: Eq> Eq for SendOnly<T> {}
+
+impl<T: Hash> Hash for SendOnly<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.lock().hash(state)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SendOnly<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.value.lock(), fmt)
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<T: serde::Serialize> serde::Serialize for SendOnly<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.lock().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for SendOnly<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(SendOnly::new)
+    }
+}
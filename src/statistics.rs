@@ -0,0 +1,147 @@
+use rustc_hash::FxHashMap;
+use std::any::TypeId;
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Controls which execution statistics the runtime collects; see
+/// [`Runtime::set_statistics_mode`].
+///
+/// [`Runtime::set_statistics_mode`]: struct.Runtime.html#method.set_statistics_mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StatisticsMode {
+    /// Collect nothing (the default).
+    Off,
+
+    /// Collect statistics for each query.
+    PerQuery,
+
+    /// Collect statistics for each query and also for each of its
+    /// keys. This is more expensive, both in time and memory.
+    PerKey,
+}
+
+/// Execution statistics of a derived query (or of one of its keys),
+/// as collected by the runtime while statistics are enabled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryStatistics {
+    /// Number of times the query function was executed.
+    pub executions: u64,
+
+    /// Number of times a memoized value was found to still be valid
+    /// in a new revision, so that re-executing the query was avoided.
+    pub validations: u64,
+
+    /// Number of executions that produced a value equal to the old
+    /// one, so that the value was "backdated" and queries depending
+    /// on it did not have to re-execute.
+    pub backdates: u64,
+
+    /// Number of executions whose value was equal to one that was
+    /// already shared, and was replaced by it (see
+    /// `#[salsa::dedup]`). Divided by `executions`, this is the hit
+    /// rate of the deduplication.
+    pub deduplications: u64,
+
+    /// Number of times a runtime blocked, waiting for another runtime
+    /// that was computing the query.
+    pub blocks: u64,
+
+    /// Number of dependencies that were left out of the memoized
+    /// values because other dependencies dominated them (see
+    /// `Runtime::set_dependency_pruning`).
+    pub pruned_dependencies: u64,
+
+    /// Total wall-clock time spent executing the query function. This
+    /// includes time spent in the queries it invoked.
+    pub execution_time: Duration,
+}
+
+/// How long writes waited for the query lock, that is, for the
+/// queries running in the current revision to complete (or to notice
+/// that they are canceled); see `Runtime::write_lock_statistics`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteLockStatistics {
+    /// Number of times a new revision was created.
+    pub writes: u64,
+
+    /// Number of those times the lock was held by queries, so that the
+    /// write had to wait.
+    pub contended_writes: u64,
+
+    /// Total time that writes spent waiting for the lock.
+    pub time_waiting_for_write: Duration,
+
+    /// The longest time that a single write waited for the lock.
+    pub longest_wait_for_write: Duration,
+}
+
+impl WriteLockStatistics {
+    pub(crate) fn record(&mut self, waited: Option<Duration>) {
+        self.writes += 1;
+        if let Some(waited) = waited {
+            self.contended_writes += 1;
+            self.time_waiting_for_write += waited;
+            self.longest_wait_for_write = self.longest_wait_for_write.max(waited);
+        }
+    }
+}
+
+pub(crate) struct Statistics<K> {
+    per_query: FxHashMap<TypeId, (&'static str, QueryStatistics)>,
+    per_key: FxHashMap<K, QueryStatistics>,
+}
+
+impl<K> Default for Statistics<K> {
+    fn default() -> Self {
+        Statistics {
+            per_query: Default::default(),
+            per_key: Default::default(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Statistics<K> {
+    /// Applies `update` to the statistics of the given query and,
+    /// if `key` is given, to those of the key as well.
+    pub(crate) fn record(
+        &mut self,
+        query: TypeId,
+        query_name: &'static str,
+        key: Option<&K>,
+        update: impl Fn(&mut QueryStatistics),
+    ) {
+        update(
+            &mut self
+                .per_query
+                .entry(query)
+                .or_insert_with(|| (query_name, QueryStatistics::default()))
+                .1,
+        );
+        if let Some(key) = key {
+            update(self.per_key.entry(key.clone()).or_default());
+        }
+    }
+
+    pub(crate) fn query(&self, query: TypeId) -> QueryStatistics {
+        self.per_query
+            .get(&query)
+            .map(|(_, statistics)| *statistics)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn key(&self, key: &K) -> QueryStatistics {
+        self.per_key.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Statistics of all queries, the ones with the largest
+    /// execution time first.
+    pub(crate) fn summary(&self) -> Vec<(&'static str, QueryStatistics)> {
+        let mut summary: Vec<_> = self.per_query.values().cloned().collect();
+        summary.sort_by(|a, b| {
+            b.1.execution_time
+                .cmp(&a.1.execution_time)
+                .then(a.0.cmp(b.0))
+        });
+        summary
+    }
+}
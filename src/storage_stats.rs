@@ -0,0 +1,19 @@
+/// Counts of the slots in the table of a query, for diagnostics (say,
+/// an "about this database" page); implemented by the storage of
+/// derived, input and interned queries. See
+/// [`Database::for_each_query_table`].
+///
+/// [`Database::for_each_query_table`]: trait.Database.html#method.for_each_query_table
+pub trait QueryStorageStats {
+    /// Returns the number of keys in the table.
+    fn slot_count(&self) -> usize;
+
+    /// Returns the number of keys whose value some runtime is computing
+    /// right now; always 0 for input and interned queries.
+    fn in_progress_count(&self) -> usize;
+
+    /// Returns the number of keys that have a value: for derived
+    /// queries, a memoized value (in memory or in the value store of
+    /// the query); for inputs, a value that was set and not removed.
+    fn memoized_count(&self) -> usize;
+}
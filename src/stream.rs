@@ -0,0 +1,88 @@
+use parking_lot::{Condvar, Mutex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// The items of a query value, delivered while the query is still
+/// being computed; returned by [`QueryTable::stream`].
+///
+/// Iterating blocks until the next item is available, and ends once
+/// the query has completed and all of its items have been delivered.
+/// If the computation panics (or is cancelled), the stream ends
+/// early instead.
+///
+/// [`QueryTable::stream`]: struct.QueryTable.html#method.stream
+pub struct Stream<T> {
+    shared: Arc<StreamShared<T>>,
+}
+
+/// The state shared by a `Stream` and the thread that feeds it.
+pub(crate) struct StreamShared<T> {
+    state: Mutex<StreamState<T>>,
+    available: Condvar,
+}
+
+struct StreamState<T> {
+    /// Items that were pushed but not consumed yet.
+    items: VecDeque<T>,
+
+    /// Number of items pushed so far.
+    pushed: usize,
+
+    /// Set once no more items will be pushed.
+    closed: bool,
+}
+
+impl<T> Stream<T> {
+    pub(crate) fn new(shared: Arc<StreamShared<T>>) -> Self {
+        Stream { shared }
+    }
+}
+
+impl<T> Iterator for Stream<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let mut state = self.shared.state.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            self.shared.available.wait(&mut state);
+        }
+    }
+}
+
+impl<T> StreamShared<T> {
+    pub(crate) fn new() -> Self {
+        StreamShared {
+            state: Mutex::new(StreamState {
+                items: VecDeque::new(),
+                pushed: 0,
+                closed: false,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn push(&self, item: T) {
+        let mut state = self.state.lock();
+        if !state.closed {
+            state.items.push_back(item);
+            state.pushed += 1;
+            self.available.notify_one();
+        }
+    }
+
+    /// Number of items pushed so far.
+    pub(crate) fn pushed(&self) -> usize {
+        self.state.lock().pushed
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.lock().closed = true;
+        self.available.notify_one();
+    }
+}
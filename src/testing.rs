@@ -0,0 +1,421 @@
+//! Helpers for testing query functions.
+//!
+//! Query functions must be pure: given the same inputs, they have to
+//! produce the same value. A query that is not (say, because it reads
+//! some state that salsa does not track) usually only shows up as a
+//! stale result much later. Wrapping the database in a `TestDatabase`
+//! catches such bugs early, by re-executing queries whose memoized
+//! values are reused and checking that they produce the same value.
+//! `assert_deterministic` (with the `test-support` feature) checks that
+//! a sequence of operations always has the same observable outcome, and
+//! `Scheduler` runs threads in an order that a test controls, so as to
+//! explore the ways they can interleave.
+
+#[cfg(feature = "test-support")]
+use crate::key_map;
+use crate::{Database, ParallelDatabase, RuntimeId, SchedulePoint, SubscriptionId};
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use rustc_hash::FxHashMap;
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+/// Wraps a database for testing. Queries are invoked through the
+/// wrapper as usual (it dereferences to the database), but:
+///
+/// - the first time in each revision that a memoized value is reused,
+///   the query is re-executed, and the test panics if that produces a
+///   different value (values that read untracked inputs are exempt);
+/// - every event (see `Event`) is recorded in a trace, one line per
+///   event, which can be compared with a golden file. The trace does
+///   not mention runtime ids, so it only depends on the order in which
+///   queries are executed. With the `strip-logging` feature, the trace
+///   is always empty.
+///
+/// This is meant for single-threaded tests; both apply to all the
+/// snapshots of the database, too.
+pub struct TestDatabase<DB: Database> {
+    db: DB,
+    trace: Arc<Mutex<Vec<String>>>,
+    subscription: SubscriptionId,
+}
+
+impl<DB: Database> TestDatabase<DB> {
+    /// Starts testing `db`.
+    pub fn new(db: DB) -> Self {
+        let trace: Arc<Mutex<Vec<String>>> = Default::default();
+        let runtime = db.salsa_runtime();
+        runtime.set_determinism_checking(true);
+        let subscription = runtime.subscribe_events({
+            let trace = trace.clone();
+            Box::new(move |event| trace.lock().push(format!("{:?}", event.kind)))
+        });
+        TestDatabase {
+            db,
+            trace,
+            subscription,
+        }
+    }
+
+    /// Returns the events recorded since the last call.
+    pub fn take_trace(&self) -> Vec<String> {
+        std::mem::take(&mut *self.trace.lock())
+    }
+}
+
+impl<DB: Database> Deref for TestDatabase<DB> {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        &self.db
+    }
+}
+
+impl<DB: Database> DerefMut for TestDatabase<DB> {
+    fn deref_mut(&mut self) -> &mut DB {
+        &mut self.db
+    }
+}
+
+impl<DB: Database> Drop for TestDatabase<DB> {
+    fn drop(&mut self) {
+        let runtime = self.db.salsa_runtime();
+        runtime.unsubscribe_events(self.subscription);
+        runtime.set_determinism_checking(false);
+    }
+}
+
+/// Runs `ops` on two new databases, and panics unless both runs return
+/// the same result and record the same events (see
+/// `TestDatabase::take_trace`).
+///
+/// Given the same operations, salsa behaves the same way whatever the
+/// hashes of the keys are: storage is iterated in insertion order (or
+/// in the order of the keys, for `#[salsa::ordered]` queries), and the
+/// dependencies of a query are kept in the order it read them. To check
+/// that, the second run perturbs the hashes of the keys of the maps of
+/// the database. Any difference between the runs thus points to a
+/// query (or operation) that depends on something else than its inputs,
+/// or on an order that salsa does not define, such as the order in
+/// which threads run. `ops` should return what the test observes, say,
+/// the values of some queries and the `entries` of some tables.
+///
+/// Perturbing the hashes has a cost for every map of every database,
+/// so this requires the `test-support` feature of salsa, which is meant
+/// to be enabled in the `dev-dependencies` only.
+#[cfg(feature = "test-support")]
+pub fn assert_deterministic<DB, R>(ops: impl Fn(&mut TestDatabase<DB>) -> R)
+where
+    DB: Database + Default,
+    R: PartialEq + Debug,
+{
+    let run = || {
+        let mut db = TestDatabase::new(DB::default());
+        let result = ops(&mut db);
+        (result, db.take_trace())
+    };
+    let (expected, expected_trace) = run();
+    let (actual, actual_trace) = key_map::with_key_hash_seed(0x9e37_79b9_7f4a_7c15, run);
+    assert_eq!(expected, actual, "the runs returned different results");
+    if let Some(index) = (0..expected_trace.len().max(actual_trace.len()))
+        .find(|&index| expected_trace.get(index) != actual_trace.get(index))
+    {
+        panic!(
+            "the runs recorded different events: event {} was {:?} in the first run, and {:?} \
+             in the second one",
+            index,
+            expected_trace.get(index),
+            actual_trace.get(index),
+        );
+    }
+}
+
+/// Runs operations on snapshots of a database, each on a thread of its
+/// own, but one thread at a time: a thread runs until it reaches a
+/// `SchedulePoint` (see `Runtime::set_schedule_hook`), where the
+/// scheduler decides which thread runs next. Threads only race on a
+/// query at those points, so a run is determined by the decisions the
+/// scheduler takes, and a test can explore the ways the threads
+/// interleave (say, which one executes a query that they all read,
+/// and which ones block on it) by running the same operations with
+/// several schedulers.
+///
+/// At each decision, the scheduler picks one of the threads that are
+/// ready to run (ordered by index): it follows a list of choices, and
+/// once that is exhausted, picks at random from a seed. Any list of
+/// choices is valid, so it can be generated (and shrunk) by a property
+/// testing library; the choices taken by a run (see `ScheduledRun`)
+/// replay it exactly.
+///
+/// The operations must only read queries, synchronously: writes and
+/// `peek_or_wait` are not scheduled, nor are threads spawned by salsa
+/// itself (for `ParallelDatabase::set_parallel_revalidation`).
+#[derive(Clone, Debug, Default)]
+pub struct Scheduler {
+    choices: Vec<usize>,
+    seed: Option<u64>,
+}
+
+/// The outcome of `Scheduler::run`.
+#[derive(Debug)]
+pub struct ScheduledRun<R> {
+    /// What each operation returned, or the payload it panicked with.
+    pub results: Vec<std::thread::Result<R>>,
+
+    /// The choices taken at each decision of the run; passing them to
+    /// `Scheduler::replay` replays the run.
+    pub choices: Vec<usize>,
+
+    /// The schedule points that were reached, in order, one line per
+    /// point, such as `1: Block` (the thread of the second operation
+    /// is about to block). Threads are named after their operation.
+    pub trace: Vec<String>,
+}
+
+/// An operation run by `Scheduler::run`.
+pub type ScheduledOp<'a, DB, R> = Box<dyn FnOnce(&DB) -> R + Send + 'a>;
+
+impl Scheduler {
+    /// A scheduler that picks the threads at random, from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Scheduler {
+            choices: vec![],
+            seed: Some(seed),
+        }
+    }
+
+    /// A scheduler that follows `choices`: at each decision, it picks
+    /// the ready thread whose rank (modulo the number of ready threads)
+    /// is the next choice, and once the choices are exhausted, always
+    /// picks the first one.
+    pub fn replay(choices: Vec<usize>) -> Self {
+        Scheduler {
+            choices,
+            seed: None,
+        }
+    }
+
+    /// Runs each of `ops` on a snapshot of `db`, on a thread of its
+    /// own, and waits for all of them. Installs a schedule hook on
+    /// `db` for the duration of the run, replacing any other.
+    pub fn run<'a, DB, R>(&self, db: &DB, ops: Vec<ScheduledOp<'a, DB, R>>) -> ScheduledRun<R>
+    where
+        DB: ParallelDatabase,
+        R: Send,
+    {
+        let snapshots: Vec<_> = ops.iter().map(|_| db.snapshot()).collect();
+        let baton = Arc::new(Baton {
+            state: Mutex::new(BatonState {
+                threads: snapshots
+                    .iter()
+                    .enumerate()
+                    .map(|(index, snapshot)| (snapshot.salsa_runtime().id(), index))
+                    .collect(),
+                running: None,
+                passing: false,
+                ready: BTreeSet::new(),
+                waking: BTreeSet::new(),
+                choices: self.choices.clone().into_iter(),
+                rng: self.seed,
+                taken: vec![],
+                trace: vec![],
+            }),
+            changed: Condvar::new(),
+        });
+
+        let runtime = db.salsa_runtime();
+        runtime.set_schedule_hook(Some({
+            let baton = baton.clone();
+            Arc::new(move |id, point| baton.reach(id, point))
+        }));
+        let threads = ops.len();
+        let results = std::thread::scope(|scope| {
+            let handles: Vec<_> = ops
+                .into_iter()
+                .zip(snapshots)
+                .enumerate()
+                .map(|(index, (op, snapshot))| {
+                    let baton = &baton;
+                    scope.spawn(move || {
+                        baton.arrive(index);
+                        let result = std::panic::catch_unwind(AssertUnwindSafe(|| op(&*snapshot)));
+                        std::mem::drop(snapshot);
+                        baton.finish();
+                        result
+                    })
+                })
+                .collect();
+
+            let mut state = baton.state.lock();
+            while state.ready.len() < threads {
+                baton.changed.wait(&mut state);
+            }
+            baton.pass(&mut state);
+            std::mem::drop(state);
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        runtime.set_schedule_hook(None);
+
+        let state = &mut *baton.state.lock();
+        ScheduledRun {
+            results,
+            choices: std::mem::take(&mut state.taken),
+            trace: std::mem::take(&mut state.trace),
+        }
+    }
+}
+
+/// Decides which of the threads of a `Scheduler` runs: the one that
+/// holds the baton.
+struct Baton {
+    state: Mutex<BatonState>,
+    changed: Condvar,
+}
+
+struct BatonState {
+    /// The index of the operation of each runtime.
+    threads: FxHashMap<RuntimeId, usize>,
+
+    /// The thread that holds the baton, if any.
+    running: Option<usize>,
+
+    /// True while a thread is passing the baton.
+    passing: bool,
+
+    /// The threads waiting for the baton.
+    ready: BTreeSet<usize>,
+
+    /// The threads that were unblocked, and will wait for the baton
+    /// once they wake up.
+    waking: BTreeSet<usize>,
+
+    /// The choices left to follow.
+    choices: std::vec::IntoIter<usize>,
+
+    /// The state of the random number generator (splitmix64), if the
+    /// choices left over are random.
+    rng: Option<u64>,
+
+    /// The choices taken so far.
+    taken: Vec<usize>,
+
+    /// See `ScheduledRun::trace`.
+    trace: Vec<String>,
+}
+
+impl Baton {
+    /// Invoked by the schedule hook.
+    fn reach(&self, id: RuntimeId, point: &SchedulePoint) {
+        let mut state = self.state.lock();
+        // The runtime of the database itself is not scheduled.
+        let index = match state.threads.get(&id) {
+            Some(&index) => index,
+            None => return,
+        };
+        let line = match point {
+            SchedulePoint::Release { unblocked } => {
+                let unblocked: Vec<_> = unblocked
+                    .iter()
+                    .filter_map(|id| state.threads.get(id).copied())
+                    .collect();
+                state.waking.extend(unblocked.iter().copied());
+                format!("{}: Release {{ unblocked: {:?} }}", index, unblocked)
+            }
+            point => format!("{}: {:?}", index, point),
+        };
+        // Unblocked threads wake up while another thread runs, so they
+        // only record that they did once they run.
+        if *point != SchedulePoint::Unblock {
+            state.trace.push(line.clone());
+        }
+
+        match point {
+            SchedulePoint::Block => {
+                // Runs again once unblocked.
+                self.pass(&mut state);
+            }
+            SchedulePoint::Unblock => {
+                state.waking.remove(&index);
+                self.wait_for_baton(&mut state, index);
+                state.trace.push(line);
+            }
+            SchedulePoint::Claim | SchedulePoint::Execute => {
+                state.running = None;
+                self.wait_for_baton(&mut state, index);
+            }
+            // The threads it unblocked run once the baton is passed.
+            SchedulePoint::Release { .. } => {}
+        }
+    }
+
+    /// Waits until thread `index` is picked to run.
+    fn wait_for_baton(&self, state: &mut MutexGuard<'_, BatonState>, index: usize) {
+        state.ready.insert(index);
+        self.changed.notify_all();
+        if state.running.is_none() && !state.passing {
+            self.pass(state);
+        }
+        while state.running != Some(index) {
+            self.changed.wait(state);
+        }
+    }
+
+    /// Waits until thread `index` is picked to run for the first time.
+    fn arrive(&self, index: usize) {
+        let mut state = self.state.lock();
+        state.ready.insert(index);
+        self.changed.notify_all();
+        while state.running != Some(index) {
+            self.changed.wait(&mut state);
+        }
+    }
+
+    /// Invoked by a thread once its operation is done.
+    fn finish(&self) {
+        let mut state = self.state.lock();
+        self.pass(&mut state);
+    }
+
+    /// Gives the baton to one of the threads that are ready, once the
+    /// threads that were unblocked are ready too (so that when they
+    /// wake up does not matter).
+    fn pass(&self, state: &mut MutexGuard<'_, BatonState>) {
+        state.running = None;
+        state.passing = true;
+        while !state.waking.is_empty() {
+            self.changed.wait(state);
+        }
+        state.passing = false;
+        if !state.ready.is_empty() {
+            let choice = match state.choices.next() {
+                Some(choice) => choice,
+                None => state.next_random(),
+            } % state.ready.len();
+            let index = *state.ready.iter().nth(choice).unwrap();
+            state.ready.remove(&index);
+            state.taken.push(choice);
+            state.running = Some(index);
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl BatonState {
+    fn next_random(&mut self) -> usize {
+        let rng = match &mut self.rng {
+            Some(rng) => rng,
+            None => return 0,
+        };
+        *rng = rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as usize
+    }
+}
@@ -0,0 +1,54 @@
+//! The clock that salsa reads, to expire values (see `MAX_AGE`), time
+//! queries out and collect statistics. `wasm32-unknown-unknown` has
+//! no clock (`std::time::Instant::now` panics there), so on that
+//! target, time stands still: durations measured by salsa are always
+//! zero, values never expire, and queries never time out.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use self::frozen::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod frozen {
+    use std::ops::{Add, Sub};
+    use std::time::Duration;
+
+    /// A point in time, as an offset from the one instant that `now`
+    /// ever returns.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub(crate) struct Instant {
+        offset: Duration,
+    }
+
+    impl Instant {
+        pub(crate) fn now() -> Self {
+            Instant {
+                offset: Duration::from_secs(0),
+            }
+        }
+
+        pub(crate) fn elapsed(&self) -> Duration {
+            Instant::now() - *self
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, duration: Duration) -> Instant {
+            Instant {
+                offset: self.offset + duration,
+            }
+        }
+    }
+
+    impl Sub for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Instant) -> Duration {
+            self.offset.checked_sub(earlier.offset).unwrap_or_default()
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use crate::debug::{self, SlotDump, SlotState, TableEntry};
+use crate::dependency::DatabaseSlot;
+use crate::durability::Durability;
+use crate::intern_id::InternId;
+use crate::interned::InternKey;
+use crate::key_map::KeyMap;
+use crate::logging::debug;
+use crate::opaque::debug_key;
+use crate::plumbing::GetQueryTable;
+use crate::plumbing::HasQueryGroup;
+use crate::plumbing::QueryStorageMassOps;
+use crate::plumbing::QueryStorageOps;
+use crate::revision::{AtomicRevision, Revision};
+use crate::runtime::Runtime;
+use crate::Query;
+use crate::{CycleError, Database, DiscardIf, MemoryReport, SweepPolicy, SweepStrategy};
+use parking_lot::RwLock;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const TRACKED_DURABILITY: Durability = Durability::HIGH;
+
+/// Storage for "tracked" entities: ids that are created by a query
+/// (the creator) while it executes, identified by the creator together
+/// with the key. As long as the creator keeps creating the key each
+/// time it is executed, the id stays the same; once it is executed
+/// without creating the key, the entity is dead, and the next sweep
+/// frees it. Ids are never reused.
+pub struct TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    tables: RwLock<TrackedTables<DB, Q>>,
+}
+
+/// Storage for looking up the key of tracked entities.
+pub struct LookupTrackedStorage<DB, Q, TQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    TQ: Query<DB, Key = Q::Value, Value = Q::Key>,
+    DB: Database,
+{
+    phantom: std::marker::PhantomData<(Q::Key, TQ)>,
+}
+
+struct TrackedTables<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    /// Map from the creator and the key to the id of the entity.
+    map: KeyMap<(DB::DatabaseKey, Q::Key), InternId>,
+
+    /// For each id, the entity, or `None` if it was freed.
+    values: Vec<Option<Arc<Slot<DB, Q>>>>,
+}
+
+struct Slot<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    index: InternId,
+
+    /// The query that created the entity.
+    creator: DB::DatabaseKey,
+
+    key: Q::Key,
+
+    /// When the entity was first created (this informs the
+    /// "changed-at" result).
+    created_at: Revision,
+
+    /// The last revision in which the creator created the entity.
+    produced_at: AtomicRevision,
+
+    /// Set once the entity is freed.
+    freed: AtomicBool,
+}
+
+impl<DB, Q> Slot<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    /// True if the creator created the entity in its latest execution
+    /// (or is executing for the first time).
+    fn is_alive(&self, runtime: &Runtime<DB>) -> bool {
+        match runtime.last_execution_of(&self.creator) {
+            Some(executed_at) => self.produced_at.load() >= executed_at,
+            None => true,
+        }
+    }
+}
+
+impl<DB, Q> Debug for Slot<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database,
+{
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{:?}({:?})",
+            Q::default(),
+            debug_key::<DB, Q>(&self.key)
+        )
+    }
+}
+
+impl<DB, Q> std::panic::RefUnwindSafe for TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+    DB::DatabaseKey: std::panic::RefUnwindSafe,
+    Q::Key: std::panic::RefUnwindSafe,
+{
+}
+
+impl<DB, Q> Default for TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    fn default() -> Self {
+        TrackedStorage {
+            tables: RwLock::new(TrackedTables {
+                map: Default::default(),
+                values: Default::default(),
+            }),
+        }
+    }
+}
+
+impl<DB, Q, TQ> Default for LookupTrackedStorage<DB, Q, TQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    TQ: Query<DB, Key = Q::Value, Value = Q::Key>,
+    DB: Database,
+{
+    fn default() -> Self {
+        LookupTrackedStorage {
+            phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<DB, Q> TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Key: Eq + Hash + Clone,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    /// Returns the entity that the active query created for `key`, if
+    /// any.
+    fn slot_of_active_query(&self, db: &DB, key: &Q::Key) -> Option<Arc<Slot<DB, Q>>> {
+        let creator = db.salsa_runtime().active_query()?;
+        let tables = self.tables.read();
+        let index = tables.map.get(&(creator, key.clone()))?;
+        tables.values[index.as_usize()].clone()
+    }
+
+    /// Returns the entity with the given id.
+    fn lookup_slot(&self, index: InternId) -> Arc<Slot<DB, Q>> {
+        match self.tables.read().values.get(index.as_usize()) {
+            Some(Some(slot)) => slot.clone(),
+            _ => panic!(
+                "{:?} has no entity {:?}, or it was freed",
+                Q::default(),
+                index
+            ),
+        }
+    }
+
+    /// Frees the dead entities for which `filter` returns true;
+    /// returns how many were freed.
+    fn free_dead(&self, db: &DB, mut filter: impl FnMut(&Q::Key) -> bool) -> usize {
+        let runtime = db.salsa_runtime();
+        let mut tables = self.tables.write();
+        let TrackedTables { map, values } = &mut *tables;
+        let mut freed = 0;
+        map.retain(|(_, key), index| {
+            let slot = match &values[index.as_usize()] {
+                Some(slot) => slot,
+                None => panic!(
+                    "key {:?} maps to id {:?} which is free",
+                    debug_key::<DB, Q>(key),
+                    index
+                ),
+            };
+            if !filter(key) || slot.is_alive(runtime) {
+                return true;
+            }
+            debug!("{:?}: freed", slot);
+            slot.freed.store(true, Ordering::SeqCst);
+            values[index.as_usize()] = None;
+            freed += 1;
+            false
+        });
+        freed
+    }
+}
+
+impl<DB, Q> QueryStorageOps<DB, Q> for TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        let runtime = db.salsa_runtime();
+        let creator = match runtime.active_query() {
+            Some(creator) => creator,
+            None => panic!(
+                "{:?}({:?}) created outside of a query",
+                Q::default(),
+                debug_key::<DB, Q>(key)
+            ),
+        };
+        runtime.register_creator(&creator);
+        let revision_now = runtime.current_revision();
+
+        // Creating an entity is not a read: the id only depends on
+        // the creator and the key.
+        let map_key = (creator, key.clone());
+        {
+            let tables = self.tables.read();
+            if let Some(&index) = tables.map.get(&map_key) {
+                if let Some(slot) = &tables.values[index.as_usize()] {
+                    slot.produced_at.store(revision_now);
+                }
+                return Ok(<Q::Value>::from_intern_id(index));
+            }
+        }
+
+        let mut tables = self.tables.write();
+        let tables = &mut *tables;
+        // Somebody may have created the entity while we were waiting
+        // for the write lock.
+        let index = match tables.map.get(&map_key) {
+            Some(&index) => index,
+            None => {
+                let index = InternId::from(tables.values.len());
+                let produced_at = AtomicRevision::start();
+                produced_at.store(revision_now);
+                tables.values.push(Some(Arc::new(Slot {
+                    index,
+                    creator: map_key.0.clone(),
+                    key: key.clone(),
+                    created_at: revision_now,
+                    produced_at,
+                    freed: AtomicBool::new(false),
+                })));
+                tables.map.insert(map_key, index);
+                index
+            }
+        };
+        Ok(<Q::Value>::from_intern_id(index))
+    }
+
+    fn durability(&self, _db: &DB, _key: &Q::Key) -> Durability {
+        TRACKED_DURABILITY
+    }
+
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        match self.slot_of_active_query(db, key) {
+            Some(slot) => slot.maybe_changed_since(db, revision),
+            None => true,
+        }
+    }
+
+    fn peek(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        let slot = self.slot_of_active_query(db, key)?;
+        Some(<Q::Value>::from_intern_id(slot.index))
+    }
+
+    fn sweep_keys(&self, db: &DB, keys: &mut dyn Iterator<Item = Q::Key>, strategy: SweepStrategy) {
+        if strategy.discard_if == DiscardIf::Never {
+            return;
+        }
+        let keys: Vec<Q::Key> = keys.collect();
+        self.free_dead(db, |key| keys.contains(key));
+    }
+
+    fn entries<C>(&self, _db: &DB) -> C
+    where
+        C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
+    {
+        let tables = self.tables.read();
+        tables
+            .values
+            .iter()
+            .flatten()
+            .map(|slot| {
+                TableEntry::new(
+                    slot.key.clone(),
+                    Some(<Q::Value>::from_intern_id(slot.index)),
+                )
+                .with_stamp(slot.created_at, TRACKED_DURABILITY)
+            })
+            .collect()
+    }
+}
+
+impl<DB, Q> QueryStorageMassOps<DB> for TrackedStorage<DB, Q>
+where
+    Q: Query<DB>,
+    Q::Value: InternKey,
+    DB: Database,
+{
+    /// Frees the entities that their creator did not create again in
+    /// its latest execution, regardless of the strategy (unless it
+    /// never discards anything): unlike memoized values, such entities
+    /// can never be needed again.
+    fn sweep(&self, db: &DB, strategy: SweepStrategy, _policy: Option<&dyn SweepPolicy>) {
+        if strategy.discard_if == DiscardIf::Never {
+            return;
+        }
+        self.free_dead(db, |_| true);
+    }
+
+    fn sweep_some(
+        &self,
+        db: &DB,
+        strategy: SweepStrategy,
+        start: usize,
+        limit: usize,
+    ) -> (usize, Option<usize>) {
+        let keys: Vec<Q::Key> = self
+            .tables
+            .read()
+            .map
+            .keys()
+            .skip(start)
+            .take(limit)
+            .map(|(_, key)| key.clone())
+            .collect();
+        let swept = keys.len();
+        let next = Some(start + swept).filter(|_| swept == limit);
+        if strategy.discard_if == DiscardIf::Never {
+            return (swept, next);
+        }
+
+        // Removing entities does not reorder the remaining ones, but
+        // the ones after them move up.
+        let freed = self.free_dead(db, |key| keys.contains(key));
+        (swept, next.map(|next| next.saturating_sub(freed)))
+    }
+
+    fn debug_dump(&self, _db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let tables = self.tables.read();
+        let slots = tables
+            .values
+            .iter()
+            .flatten()
+            .map(|slot| {
+                SlotDump::new(&debug_key::<DB, Q>(&slot.key), SlotState::Memoized)
+                    .with_stamp(slot.created_at, TRACKED_DURABILITY)
+            })
+            .collect();
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        let tables = self.tables.read();
+        let mut report = MemoryReport {
+            slots: tables.map.len(),
+            values: tables.map.len(),
+            bytes: tables.values.len() * std::mem::size_of::<Option<Arc<Slot<DB, Q>>>>(),
+        };
+        report.bytes += tables.map.len()
+            * (std::mem::size_of::<((DB::DatabaseKey, Q::Key), InternId)>()
+                + std::mem::size_of::<Slot<DB, Q>>());
+        (Q::QUERY_NAME, report)
+    }
+}
+
+impl<DB, Q, TQ> QueryStorageOps<DB, Q> for LookupTrackedStorage<DB, Q, TQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    Q::Value: Eq + Hash,
+    TQ: Query<
+        DB,
+        Key = Q::Value,
+        Value = Q::Key,
+        Storage = TrackedStorage<DB, TQ>,
+        Group = Q::Group,
+        GroupStorage = Q::GroupStorage,
+        GroupKey = Q::GroupKey,
+    >,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn try_fetch(&self, db: &DB, key: &Q::Key) -> Result<Q::Value, CycleError<DB::DatabaseKey>> {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let slot = TQ::query_storage(group_storage).lookup_slot(key.as_intern_id());
+        let value = slot.key.clone();
+        let created_at = slot.created_at;
+        db.salsa_runtime()
+            .report_query_read(slot, TRACKED_DURABILITY, created_at);
+        Ok(value)
+    }
+
+    fn durability(&self, _db: &DB, _key: &Q::Key) -> Durability {
+        TRACKED_DURABILITY
+    }
+
+    fn maybe_changed_since(&self, db: &DB, key: &Q::Key, revision: Revision) -> bool {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let tables = TQ::query_storage(group_storage).tables.read();
+        match tables.values.get(key.as_intern_id().as_usize()) {
+            Some(Some(slot)) => slot.created_at > revision,
+            _ => true,
+        }
+    }
+
+    fn peek(&self, db: &DB, key: &Q::Key) -> Option<Q::Value> {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let tables = TQ::query_storage(group_storage).tables.read();
+        let slot = tables.values.get(key.as_intern_id().as_usize())?.as_ref()?;
+        Some(slot.key.clone())
+    }
+
+    fn sweep_keys(
+        &self,
+        _db: &DB,
+        _keys: &mut dyn Iterator<Item = Q::Key>,
+        _strategy: SweepStrategy,
+    ) {
+    }
+
+    fn entries<C>(&self, db: &DB) -> C
+    where
+        C: std::iter::FromIterator<TableEntry<Q::Key, Q::Value>>,
+    {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let tables = TQ::query_storage(group_storage).tables.read();
+        tables
+            .values
+            .iter()
+            .flatten()
+            .map(|slot| {
+                TableEntry::new(<Q::Key>::from_intern_id(slot.index), Some(slot.key.clone()))
+                    .with_stamp(slot.created_at, TRACKED_DURABILITY)
+            })
+            .collect()
+    }
+}
+
+impl<DB, Q, TQ> QueryStorageMassOps<DB> for LookupTrackedStorage<DB, Q, TQ>
+where
+    Q: Query<DB>,
+    Q::Key: InternKey,
+    Q::Value: Eq + Hash,
+    TQ: Query<
+        DB,
+        Key = Q::Value,
+        Value = Q::Key,
+        Storage = TrackedStorage<DB, TQ>,
+        Group = Q::Group,
+        GroupStorage = Q::GroupStorage,
+        GroupKey = Q::GroupKey,
+    >,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn sweep(&self, _db: &DB, _strategy: SweepStrategy, _policy: Option<&dyn SweepPolicy>) {}
+
+    fn sweep_some(
+        &self,
+        _db: &DB,
+        _strategy: SweepStrategy,
+        _start: usize,
+        _limit: usize,
+    ) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    fn debug_dump(&self, db: &DB, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let group_storage = <DB as HasQueryGroup<Q::Group>>::group_storage(db);
+        let tables = TQ::query_storage(group_storage).tables.read();
+        let slots = tables
+            .values
+            .iter()
+            .flatten()
+            .map(|slot| {
+                SlotDump::new(
+                    &debug_key::<DB, Q>(&<Q::Key>::from_intern_id(slot.index)),
+                    SlotState::Memoized,
+                )
+                .with_stamp(slot.created_at, TRACKED_DURABILITY)
+            })
+            .collect();
+        debug::write_query_dump(out, Q::QUERY_NAME, slots)
+    }
+
+    fn memory_usage(&self, _db: &DB) -> (&'static str, MemoryReport) {
+        // The keys are kept (and counted) by the tracked query.
+        (Q::QUERY_NAME, MemoryReport::default())
+    }
+}
+
+// Unsafe proof obligation: `Slot<DB, Q>` is Send + Sync if the query
+// key and the database key are Send + Sync (also, that we introduce
+// no references).
+unsafe impl<DB, Q> DatabaseSlot<DB> for Slot<DB, Q>
+where
+    Q: Query<DB>,
+    DB: Database + HasQueryGroup<Q::Group>,
+{
+    fn database_key(&self, db: &DB) -> Option<DB::DatabaseKey> {
+        Some(<DB as GetQueryTable<Q>>::database_key(db, self.key.clone()))
+    }
+
+    fn maybe_changed_since(&self, _db: &DB, revision: Revision) -> bool {
+        self.freed.load(Ordering::SeqCst) || self.created_at > revision
+    }
+}
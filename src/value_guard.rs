@@ -0,0 +1,104 @@
+use crate::runtime::SlotBorrow;
+use parking_lot::MappedRwLockReadGuard;
+use std::fmt::{self, Debug};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A query value returned by [`QueryTable::get_ref`], which
+/// dereferences to the value.
+///
+/// If the value was already memoized, the guard borrows it from the
+/// memo rather than cloning it, holding a read lock on the memo until
+/// the guard is dropped. Otherwise (say, because the value was just
+/// computed, or because the query does not keep its values in memory)
+/// the guard owns a copy of the value.
+///
+/// While the guard holds a read lock, the memo cannot be locked for
+/// writing: evicting it (say, because other queries took its place in
+/// the LRU list) or sweeping it skips it instead, and re-executing the
+/// query on the same thread (once a `#[salsa::max_age]` value expired)
+/// is reported as a cycle, as it would deadlock.
+/// Setting inputs requires `&mut` access to the database, which the
+/// guard prevents by borrowing it.
+///
+/// [`QueryTable::get_ref`]: struct.QueryTable.html#method.get_ref
+pub struct ValueGuard<'a, V> {
+    inner: Inner<'a, V>,
+}
+
+enum Inner<'a, V> {
+    Owned(V),
+    Borrowed(Box<dyn Deref<Target = V> + 'a>),
+}
+
+/// A lock on a value kept inside `T`, along with the `Arc` that keeps
+/// `T` alive while it is locked.
+struct Locked<'a, T, V> {
+    // Declared before `_owner` and `_borrow`, so that the lock is
+    // released first.
+    guard: MappedRwLockReadGuard<'a, V>,
+    _owner: Arc<T>,
+    _borrow: Option<SlotBorrow<'a>>,
+}
+
+impl<'a, V> ValueGuard<'a, V> {
+    pub(crate) fn owned(value: V) -> Self {
+        ValueGuard {
+            inner: Inner::Owned(value),
+        }
+    }
+
+    /// Creates a guard holding the lock that `lock` acquires on some
+    /// value in `owner`, if any. `borrow` records the lock with the
+    /// runtime of the current thread, if it could deadlock that
+    /// runtime; see `Runtime::borrow_slot`.
+    ///
+    /// # Safety
+    ///
+    /// `lock` is given a reference to `owner` that claims to live for
+    /// `'a`, while it really lives as long as the guard; it must not
+    /// keep that reference anywhere but in the lock it returns.
+    pub(crate) unsafe fn borrowed<T: 'a>(
+        owner: Arc<T>,
+        borrow: Option<SlotBorrow<'a>>,
+        lock: impl FnOnce(&'a T) -> Option<MappedRwLockReadGuard<'a, V>>,
+    ) -> Option<Self>
+    where
+        V: 'a,
+    {
+        let owner_ref: &'a T = &*Arc::as_ptr(&owner);
+        let guard = lock(owner_ref)?;
+        Some(ValueGuard {
+            inner: Inner::Borrowed(Box::new(Locked {
+                guard,
+                _owner: owner,
+                _borrow: borrow,
+            })),
+        })
+    }
+}
+
+impl<V> Deref for ValueGuard<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match &self.inner {
+            Inner::Owned(value) => value,
+            Inner::Borrowed(guard) => guard,
+        }
+    }
+}
+
+impl<V: Debug> Debug for ValueGuard<'_, V> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, fmt)
+    }
+}
+
+impl<T, V> Deref for Locked<'_, T, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.guard
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{MaybeSend, MaybeSync};
+
+/// Somewhere other than memory to keep the memoized values of a
+/// derived query, typically an on-disk key-value store (such as sled
+/// or rocksdb) that serializes the keys and values. See the
+/// `#[salsa::value_store]` attribute.
+///
+/// Only the values are kept in the store; the revision stamps and
+/// dependencies of each memo stay in memory, so that validating a
+/// value does not require loading it.
+pub trait ValueStore<K, V>: MaybeSend + MaybeSync {
+    /// Saves `value` as the value for `key`, replacing the previous
+    /// one (if any).
+    fn store(&self, key: &K, value: &V);
+
+    /// Loads the value saved for `key`. Returning `None` (say, if the
+    /// value could not be read back) causes the query to re-execute.
+    fn load(&self, key: &K) -> Option<V>;
+
+    /// Discards the value saved for `key`, if any.
+    fn remove(&self, key: &K);
+}
@@ -0,0 +1,60 @@
+//! Test `Runtime::active_query_stack`.
+
+use salsa::Database;
+
+#[salsa::query_group(StackStorage)]
+trait StackDatabase: salsa::Database {
+    fn outer(&self) -> Vec<String>;
+
+    fn inner(&self) -> Vec<String>;
+
+    fn depth(&self, n: u32) -> usize;
+}
+
+fn outer(db: &impl StackDatabase) -> Vec<String> {
+    db.inner()
+}
+
+fn inner(db: &impl StackDatabase) -> Vec<String> {
+    db.salsa_runtime()
+        .active_query_stack()
+        .iter()
+        .map(|key| format!("{:?}", key))
+        .collect()
+}
+
+fn depth(db: &impl StackDatabase, n: u32) -> usize {
+    if n == 0 {
+        db.salsa_runtime().active_query_stack().len()
+    } else {
+        db.depth(n - 1)
+    }
+}
+
+#[salsa::database(StackStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn stack_outermost_first() {
+    let db = DatabaseImpl::default();
+    let stack = db.outer();
+    assert_eq!(stack.len(), 2, "unexpected stack {:?}", stack);
+    assert!(stack[0].contains("outer"), "unexpected stack {:?}", stack);
+    assert!(stack[1].contains("inner"), "unexpected stack {:?}", stack);
+}
+
+#[test]
+fn stack_depth() {
+    let db = DatabaseImpl::default();
+    assert_eq!(db.depth(9), 10);
+    assert!(db.salsa_runtime().active_query_stack().is_empty());
+}
@@ -0,0 +1,75 @@
+//! Test `#[salsa::arc]` queries, whose values are wrapped in an `Arc`.
+
+use std::sync::Arc;
+
+#[salsa::query_group(ArcStorage)]
+trait ArcDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::arc]
+    fn text(&self, key: u32) -> String;
+
+    #[salsa::arc]
+    fn words(&self, key: u32) -> Vec<String>;
+
+    #[salsa::weak]
+    #[salsa::arc]
+    fn upper(&self, key: u32) -> String;
+}
+
+fn words(db: &impl ArcDatabase, key: u32) -> Vec<String> {
+    db.text(key)
+        .split(' ')
+        .map(|word| word.to_string())
+        .collect()
+}
+
+fn upper(db: &impl ArcDatabase, key: u32) -> String {
+    db.text(key).to_uppercase()
+}
+
+#[salsa::database(ArcStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn values_are_shared() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello world".to_string());
+
+    let text: Arc<String> = db.text(1);
+    assert_eq!(*text, "hello world");
+    assert!(Arc::ptr_eq(&text, &db.text(1)));
+
+    let words: Arc<Vec<String>> = db.words(1);
+    assert_eq!(*words, vec!["hello", "world"]);
+    assert!(Arc::ptr_eq(&words, &db.words(1)));
+}
+
+#[test]
+fn values_are_updated() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello world".to_string());
+    assert_eq!(db.words(1).len(), 2);
+
+    db.set_text(1, "a b c".to_string());
+    assert_eq!(db.words(1).len(), 3);
+    assert_eq!(db.maybe_text(2), None);
+}
+
+#[test]
+fn combine_with_weak() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello".to_string());
+
+    let upper = db.upper(1);
+    assert_eq!(*upper, "HELLO");
+    assert!(Arc::ptr_eq(&upper, &db.upper(1)));
+}
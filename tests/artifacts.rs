@@ -0,0 +1,160 @@
+//! Test exporting query results as artifact bundles and importing them
+//! into another database.
+#![cfg(feature = "persist")]
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{ArtifactBundle, Database};
+use std::sync::Arc;
+
+#[salsa::query_group(ArtifactsStorage)]
+trait ArtifactsDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    #[salsa::persist]
+    fn source(&self, name: String) -> String;
+
+    #[salsa::input]
+    fn not_persisted(&self, name: String) -> u32;
+
+    fn words(&self, name: String) -> Arc<Vec<String>>;
+
+    /// The number of distinct words in all the given sources.
+    fn vocabulary(&self, names: Vec<String>) -> usize;
+
+    fn uses_not_persisted(&self, name: String) -> u32;
+}
+
+fn words(db: &impl ArtifactsDatabase, name: String) -> Arc<Vec<String>> {
+    db.log().add(format!("words({})", name));
+    Arc::new(db.source(name).split(' ').map(String::from).collect())
+}
+
+fn vocabulary(db: &impl ArtifactsDatabase, names: Vec<String>) -> usize {
+    db.log().add(format!("vocabulary({:?})", names));
+    let mut words: Vec<String> = names
+        .into_iter()
+        .flat_map(|name| db.words(name).to_vec())
+        .collect();
+    words.sort();
+    words.dedup();
+    words.len()
+}
+
+fn uses_not_persisted(db: &impl ArtifactsDatabase, name: String) -> u32 {
+    db.not_persisted(name)
+}
+
+#[salsa::database(ArtifactsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn set_sources(db: &mut DatabaseImpl, a: &str, b: &str) {
+    db.set_source("a".to_string(), a.to_string());
+    db.set_source("b".to_string(), b.to_string());
+}
+
+fn export(a: &str, b: &str) -> ArtifactBundle {
+    let mut db = DatabaseImpl::default();
+    set_sources(&mut db, a, b);
+    let bundle = db
+        .export_results(VocabularyQuery, vec![names(&["a"]), names(&["a", "b"])])
+        .unwrap();
+    assert_eq!(bundle.len(), 2);
+
+    // Round-trip the bundle through its serialized form.
+    let mut bytes = Vec::new();
+    bundle.write(&mut bytes).unwrap();
+    ArtifactBundle::read(&mut &bytes[..]).unwrap()
+}
+
+#[test]
+fn imported_results_are_used() {
+    let bundle = export("x y", "y z");
+
+    let mut db = DatabaseImpl::default();
+    set_sources(&mut db, "x y", "y z");
+    assert_eq!(db.import_results(VocabularyQuery, &bundle).unwrap(), 2);
+    assert_eq!(db.vocabulary(names(&["a"])), 2);
+    assert_eq!(db.vocabulary(names(&["a", "b"])), 3);
+    assert!(db.log().take().is_empty());
+
+    // Once an input changes, the values that depend on it are
+    // re-executed as usual.
+    db.set_source("b".to_string(), "y".to_string());
+    assert_eq!(db.vocabulary(names(&["a"])), 2);
+    assert!(db.log().take().is_empty());
+    assert_eq!(db.vocabulary(names(&["a", "b"])), 2);
+    assert_eq!(
+        db.log().take(),
+        vec!["vocabulary([\"a\", \"b\"])", "words(a)", "words(b)"]
+    );
+}
+
+#[test]
+fn results_with_changed_inputs_are_not_imported() {
+    let bundle = export("x y", "y z");
+
+    let mut db = DatabaseImpl::default();
+    set_sources(&mut db, "x y", "z");
+    assert_eq!(db.import_results(VocabularyQuery, &bundle).unwrap(), 1);
+    assert_eq!(db.vocabulary(names(&["a"])), 2);
+    assert!(db.log().take().is_empty());
+    assert_eq!(db.vocabulary(names(&["a", "b"])), 3);
+    assert_eq!(
+        db.log().take(),
+        vec!["vocabulary([\"a\", \"b\"])", "words(a)", "words(b)"]
+    );
+}
+
+#[test]
+fn results_are_not_imported_over_memos() {
+    let bundle = export("x y", "y z");
+
+    let mut db = DatabaseImpl::default();
+    set_sources(&mut db, "x y", "y z");
+    db.vocabulary(names(&["a"]));
+    assert_eq!(db.import_results(VocabularyQuery, &bundle).unwrap(), 1);
+}
+
+#[test]
+fn results_of_other_queries_are_ignored() {
+    let bundle = export("x y", "y z");
+
+    let mut db = DatabaseImpl::default();
+    set_sources(&mut db, "x y", "y z");
+    assert_eq!(
+        db.import_results(UsesNotPersistedQuery, &bundle).unwrap(),
+        0
+    );
+}
+
+#[test]
+fn export_requires_persisted_inputs() {
+    let mut db = DatabaseImpl::default();
+    db.set_not_persisted("a".to_string(), 1);
+    let error = db
+        .export_results(UsesNotPersistedQuery, vec!["a".to_string()])
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("not a persisted input"));
+}
@@ -0,0 +1,34 @@
+//! Test the synthetic graphs of `salsa::bench_support`.
+#![cfg(feature = "bench-support")]
+
+use salsa::bench_support::{Graph, GraphDatabase};
+
+#[test]
+fn shapes() {
+    let chain = Graph::chain(5);
+    assert_eq!(chain.node_count(), 5);
+    assert_eq!(chain.edges(0), &[1]);
+    assert_eq!(chain.leaves().collect::<Vec<_>>(), vec![4]);
+    assert_eq!(chain.database().node_value(0), 5);
+
+    let fan_out = Graph::fan_out(3);
+    assert_eq!(fan_out.edges(0), &[1, 2, 3]);
+    assert_eq!(fan_out.leaves().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(fan_out.database().node_value(0), 4);
+
+    let layered = Graph::layered(2, 3);
+    assert_eq!(layered.node_count(), 7);
+    assert_eq!(layered.edges(0), &[1, 2, 3]);
+    assert_eq!(layered.edges(2), &[4, 5, 6]);
+    assert_eq!(layered.leaves().collect::<Vec<_>>(), vec![4, 5, 6]);
+    assert_eq!(layered.database().node_value(0), 13);
+}
+
+#[test]
+fn changes() {
+    let graph = Graph::layered(2, 3);
+    let mut db = graph.database();
+    assert_eq!(db.node_value(0), 13);
+    db.set_weight(6, 2);
+    assert_eq!(db.node_value(0), 16);
+}
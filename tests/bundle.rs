@@ -0,0 +1,72 @@
+//! Test `salsa::query_group_bundle!`, which lets a database include
+//! several query groups by naming a single bundle.
+
+#[macro_use]
+mod plugin {
+    #[salsa::query_group(SourceStorage)]
+    pub trait Source: salsa::Database {
+        #[salsa::input]
+        fn source(&self, key: u32) -> String;
+    }
+
+    #[salsa::query_group(LengthStorage)]
+    pub trait Length: Source {
+        fn length(&self, key: u32) -> usize;
+    }
+
+    fn length(db: &impl Length, key: u32) -> usize {
+        db.source(key).len()
+    }
+
+    #[salsa::query_group(WordsStorage)]
+    pub trait Words: Source {
+        fn words(&self, key: u32) -> usize;
+    }
+
+    fn words(db: &impl Words, key: u32) -> usize {
+        db.source(key).split_whitespace().count()
+    }
+
+    salsa::query_group_bundle! {
+        /// The query groups that count things.
+        counting_groups = [crate::plugin::LengthStorage, crate::plugin::WordsStorage];
+    }
+
+    salsa::query_group_bundle! {
+        /// All the query groups of the plugin.
+        pub plugin_groups = [crate::plugin::SourceStorage, counting_groups!];
+    }
+}
+
+#[salsa::query_group(HostStorage)]
+trait Host: plugin::Length + plugin::Words {
+    fn summary(&self, key: u32) -> String;
+}
+
+fn summary(db: &impl Host, key: u32) -> String {
+    format!("{} words, {} bytes", db.words(key), db.length(key))
+}
+
+#[salsa::database(HostStorage, plugin_groups!)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn bundled_groups() {
+    use plugin::Source;
+
+    let mut db = DatabaseImpl::default();
+    db.set_source(1, "hello world".to_string());
+    assert_eq!(db.summary(1), "2 words, 11 bytes");
+
+    db.set_source(1, "hi".to_string());
+    assert_eq!(db.summary(1), "1 words, 2 bytes");
+}
@@ -0,0 +1,89 @@
+//! Test `#[salsa::catch_panics]` queries, which return (and memoize)
+//! a `QueryPanic` error when their function panics.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::QueryPanic;
+
+#[salsa::query_group(CatchPanicsStorage)]
+trait CatchPanicsDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn text(&self, name: &'static str) -> String;
+
+    #[salsa::input]
+    fn other(&self) -> u32;
+
+    #[salsa::catch_panics]
+    fn parse(&self, name: &'static str) -> Result<u32, QueryPanic>;
+
+    fn parse_or_zero(&self, name: &'static str) -> u32;
+}
+
+fn parse(db: &impl CatchPanicsDatabase, name: &'static str) -> u32 {
+    db.log().add(format!("parse({})", name));
+    db.text(name).parse().expect("not a number")
+}
+
+fn parse_or_zero(db: &impl CatchPanicsDatabase, name: &'static str) -> u32 {
+    db.log().add(format!("parse_or_zero({})", name));
+    db.parse(name).unwrap_or(0)
+}
+
+#[salsa::database(CatchPanicsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn value_is_returned_unless_function_panics() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "22".to_string());
+    assert_eq!(db.parse("a"), Ok(22));
+}
+
+#[test]
+fn panic_is_memoized_as_error() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+
+    let error = db.parse("a").unwrap_err();
+    assert!(error.message().contains("not a number"), "{}", error);
+    assert_eq!(db.log().take(), vec!["parse(a)"]);
+
+    assert_eq!(db.parse("a"), Err(error));
+    assert_eq!(db.parse_or_zero("a"), 0);
+    assert_eq!(db.log().take(), vec!["parse_or_zero(a)"]);
+}
+
+#[test]
+fn error_is_only_memoized_for_current_revision() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    db.set_other(0);
+    assert!(db.parse("a").is_err());
+    db.log().take();
+
+    // The input of `parse` did not change, but it is executed again.
+    db.set_other(1);
+    assert!(db.parse("a").is_err());
+    assert_eq!(db.log().take(), vec!["parse(a)"]);
+
+    db.set_text("a", "1".to_string());
+    assert_eq!(db.parse("a"), Ok(1));
+    assert_eq!(db.parse_or_zero("a"), 1);
+}
@@ -0,0 +1,116 @@
+//! Test `QueryTable::changed_entries_since` and `#[salsa::keep_previous]`.
+
+use salsa::{ChangedEntry, Database};
+
+#[salsa::query_group(ChangedEntriesStorage)]
+trait ChangedEntriesDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    #[salsa::keep_previous]
+    fn label(&self, key: u32) -> String;
+
+    fn is_large(&self, key: u32) -> bool;
+}
+
+fn label(db: &impl ChangedEntriesDatabase, key: u32) -> String {
+    format!("{}: {}", key, db.input(key))
+}
+
+fn is_large(db: &impl ChangedEntriesDatabase, key: u32) -> bool {
+    db.input(key) >= 100
+}
+
+#[salsa::database(ChangedEntriesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn changed_labels(
+    db: &DatabaseImpl,
+    revision: salsa::Revision,
+) -> Vec<(u32, Option<String>, String)> {
+    let mut entries: Vec<_> = db
+        .query(LabelQuery)
+        .changed_entries_since(revision)
+        .into_iter()
+        .map(|entry| (entry.key, entry.old_value, entry.new_value))
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn reports_old_and_new_values() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    db.set_input(2, 20);
+    db.set_input(3, 30);
+    for key in 1..=3 {
+        db.label(key);
+    }
+
+    let rendered = db.salsa_runtime().current_revision();
+    assert_eq!(changed_labels(&db, rendered), vec![]);
+
+    db.set_input(1, 11);
+    db.set_input(2, 21);
+    db.set_input(3, 30);
+    for key in 1..=3 {
+        db.label(key);
+    }
+    assert_eq!(
+        changed_labels(&db, rendered),
+        vec![
+            (1, Some("1: 10".to_string()), "1: 11".to_string()),
+            (2, Some("2: 20".to_string()), "2: 21".to_string()),
+        ]
+    );
+
+    // A value that changed twice since has no known old value.
+    db.set_input(1, 12);
+    db.label(1);
+    assert_eq!(
+        changed_labels(&db, rendered),
+        vec![
+            (1, None, "1: 12".to_string()),
+            (2, Some("2: 20".to_string()), "2: 21".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn backdated_values_are_not_reported() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert!(!db.is_large(1));
+
+    let rendered = db.salsa_runtime().current_revision();
+    db.set_input(1, 12);
+    assert!(!db.is_large(1));
+    assert_eq!(
+        db.query(IsLargeQuery).changed_entries_since(rendered),
+        vec![]
+    );
+
+    db.set_input(1, 130);
+    assert!(db.is_large(1));
+    let entries = db.query(IsLargeQuery).changed_entries_since(rendered);
+    assert_eq!(
+        entries,
+        vec![ChangedEntry {
+            key: 1,
+            // Without `#[salsa::keep_previous]`, old values are unknown.
+            old_value: None,
+            new_value: true,
+            changed_at: db.salsa_runtime().current_revision(),
+        }]
+    );
+}
@@ -0,0 +1,4 @@
+//! Fixtures shared by the test files (each of which is its own crate
+//! and includes this module with `mod common;`).
+
+pub(crate) mod log;
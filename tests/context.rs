@@ -0,0 +1,106 @@
+//! Test `Runtime::context` and `Runtime::set_context`.
+
+use salsa::Database;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the calls to `length`, through interior mutability.
+#[derive(Default)]
+struct CallCounter(AtomicUsize);
+
+struct Prefix(&'static str);
+
+#[salsa::query_group(ContextStorage)]
+trait ContextDatabase: salsa::Database {
+    #[salsa::input]
+    fn text(&self) -> String;
+
+    fn length(&self) -> usize;
+
+    fn prefixed(&self) -> String;
+
+    fn set_prefix_in_query(&self) -> ();
+}
+
+fn length(db: &impl ContextDatabase) -> usize {
+    if let Some(counter) = db.salsa_runtime().context::<CallCounter>() {
+        counter.0.fetch_add(1, Ordering::SeqCst);
+    }
+    db.text().len()
+}
+
+fn prefixed(db: &impl ContextDatabase) -> String {
+    let prefix = db.salsa_runtime().context::<Prefix>().unwrap();
+    format!("{}{}", prefix.0, db.text())
+}
+
+fn set_prefix_in_query(db: &impl ContextDatabase) {
+    db.salsa_runtime().set_context(Prefix("query"));
+}
+
+#[salsa::database(ContextStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[cfg(not(feature = "single-threaded"))]
+impl salsa::ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+#[test]
+#[cfg(not(feature = "single-threaded"))]
+fn context_is_shared_and_untracked() {
+    use salsa::ParallelDatabase;
+
+    let mut db = DatabaseImpl::default();
+    assert!(db.salsa_runtime().context::<CallCounter>().is_none());
+    db.salsa_runtime().set_context(CallCounter::default());
+    let counter = db.salsa_runtime().context::<CallCounter>().unwrap();
+
+    db.set_text("abc".to_string());
+    assert_eq!(db.length(), 3);
+    assert_eq!(db.snapshot().length(), 3);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+    // Replacing a context value that no query read is fine, and does
+    // not invalidate anything.
+    db.salsa_runtime().set_context(Prefix("a"));
+    db.salsa_runtime().set_context(Prefix(">"));
+    assert_eq!(db.length(), 3);
+    assert_eq!(db.snapshot().prefixed(), ">abc");
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+    db.set_text("abcd".to_string());
+    assert_eq!(db.length(), 4);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "set_context replaced a `context::Prefix` that queries have read")]
+fn replacing_context_read_by_query() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_context(Prefix(">"));
+    db.set_text("abc".to_string());
+    db.prefixed();
+    db.salsa_runtime().set_context(Prefix("<"));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "set_context invoked while a query is executing")]
+fn setting_context_in_query() {
+    let db = DatabaseImpl::default();
+    db.set_prefix_in_query();
+}
@@ -1,3 +1,5 @@
+use salsa::plumbing::HasQueryGroup;
+
 #[salsa::database(GroupStruct)]
 #[derive(Default)]
 struct DatabaseImpl {
@@ -17,6 +19,11 @@ trait Database: salsa::Database {
     fn memoized_b(&self) -> ();
     fn volatile_a(&self) -> ();
     fn volatile_b(&self) -> ();
+
+    // `cycle_a` and `cycle_b` also form a cycle, but `cycle_b`
+    // recovers from it and returns the participants as its result
+    fn cycle_a(&self) -> Vec<String>;
+    fn cycle_b(&self) -> Vec<String>;
 }
 
 fn memoized_a(db: &impl Database) -> () {
@@ -37,6 +44,17 @@ fn volatile_b(db: &impl Database) -> () {
     db.volatile_a()
 }
 
+fn cycle_a(db: &impl Database) -> Vec<String> {
+    db.cycle_b()
+}
+
+fn cycle_b(db: &(impl Database + HasQueryGroup<GroupStruct>)) -> Vec<String> {
+    match db.query(CycleAQuery).try_get(()) {
+        Ok(v) => v,
+        Err(err) => err.cycle().iter().map(|k| format!("{:?}", k)).collect(),
+    }
+}
+
 #[test]
 #[should_panic(expected = "cycle detected")]
 fn cycle_memoized() {
@@ -50,3 +68,24 @@ fn cycle_volatile() {
     let query = DatabaseImpl::default();
     query.volatile_a();
 }
+
+#[test]
+fn cycle_participants() {
+    let query = DatabaseImpl::default();
+    let cycle = query.cycle_a();
+    assert_eq!(cycle.len(), 2, "unexpected cycle {:?}", cycle);
+    assert!(cycle[0].contains("cycle_a"), "unexpected cycle {:?}", cycle);
+    assert!(cycle[1].contains("cycle_b"), "unexpected cycle {:?}", cycle);
+}
+
+#[test]
+fn cycle_panic_lists_participants() {
+    let result = std::panic::catch_unwind(|| {
+        let query = DatabaseImpl::default();
+        query.memoized_a();
+    });
+    let payload = result.unwrap_err();
+    let message = payload.downcast_ref::<String>().unwrap();
+    assert!(message.contains("memoized_a"), "{}", message);
+    assert!(message.contains("memoized_b"), "{}", message);
+}
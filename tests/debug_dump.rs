@@ -0,0 +1,104 @@
+//! Test `DebugQueryTable::debug_dump` and `salsa::debug::dump_group`.
+
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, InternId};
+
+#[salsa::query_group(DumpStorage)]
+trait DumpDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: char) -> u32;
+
+    fn double(&self, key: char) -> u32;
+
+    fn total(&self) -> u32;
+
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> InternId;
+}
+
+fn double(db: &impl DumpDatabase, key: char) -> u32 {
+    db.input(key) * 2
+}
+
+fn total(db: &impl DumpDatabase) -> u32 {
+    db.double('a') + db.double('b')
+}
+
+#[salsa::database(DumpStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn dump_query(table: impl DebugQueryTable) -> String {
+    let mut out = Vec::new();
+    table.debug_dump(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn dump_input_and_derived() {
+    let mut db = DatabaseImpl::default();
+    db.set_input('b', 2);
+    db.set_input('a', 1);
+    assert_eq!(db.total(), 6);
+
+    assert_eq!(
+        dump_query(db.query(InputQuery)),
+        concat!(
+            r#"{"query":"input","slots":["#,
+            r#"{"key":"'a'","state":"Memoized","changed_at":3,"verified_at":null,"durability":0,"untracked":false,"inputs":[]},"#,
+            r#"{"key":"'b'","state":"Memoized","changed_at":2,"verified_at":null,"durability":0,"untracked":false,"inputs":[]}"#,
+            r#"]}"#,
+        )
+    );
+
+    assert_eq!(
+        dump_query(db.query(TotalQuery)),
+        concat!(
+            r#"{"query":"total","slots":["#,
+            r#"{"key":"()","state":"Memoized","changed_at":3,"verified_at":3,"durability":0,"untracked":false,"inputs":["#,
+            r#""__SalsaDatabaseKey { kind: DumpStorage(double('a')) }","#,
+            r#""__SalsaDatabaseKey { kind: DumpStorage(double('b')) }""#,
+            r#"]}]}"#,
+        )
+    );
+}
+
+#[test]
+fn dump_not_computed() {
+    let mut db = DatabaseImpl::default();
+    db.set_input('a', 1);
+    assert_eq!(db.double('a'), 2);
+    db.salsa_runtime().synthetic_write(salsa::Durability::LOW);
+    db.query(DoubleQuery)
+        .sweep(salsa::SweepStrategy::discard_outdated());
+
+    let dump = dump_query(db.query(DoubleQuery));
+    assert!(dump.contains(r#""state":"NotComputed","changed_at":null"#));
+}
+
+#[test]
+fn dump_group() {
+    let mut db = DatabaseImpl::default();
+    db.set_input('a', 1);
+    db.intern_name("x\"y".to_string());
+
+    let mut out = Vec::new();
+    salsa::debug::dump_group::<DumpStorage, _>(&db, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with(r#"[{"query":"input","slots":[{"key":"'a'""#));
+    assert!(out.contains(r#"{"query":"double","slots":[]}"#));
+    assert!(out.contains(
+        r#"{"query":"intern_name","slots":[{"key":"\"x\\\"y\"","state":"Memoized","changed_at":2,"verified_at":null,"durability":15,"#
+    ));
+    assert!(out.contains(r#"{"query":"lookup_intern_name","slots":[{"key":"0","state":"Memoized""#));
+    assert!(out.ends_with("]}]"));
+}
@@ -0,0 +1,117 @@
+//! Test `#[salsa::dedup]` queries, whose equal values are shared.
+
+use salsa::{Database, StatisticsMode};
+use std::sync::Arc;
+
+#[salsa::query_group(DedupStorage)]
+trait DedupDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    /// One message per multiple of 10 below the input.
+    #[salsa::dedup]
+    fn messages(&self, key: u32) -> Arc<Vec<String>>;
+
+    #[salsa::dedup]
+    fn other_messages(&self, key: u32) -> Arc<Vec<String>>;
+
+    fn count(&self, key: u32) -> usize;
+}
+
+fn messages(db: &impl DedupDatabase, key: u32) -> Arc<Vec<String>> {
+    let messages = (0..db.input(key) / 10)
+        .map(|i| format!("message {}", i))
+        .collect();
+    Arc::new(messages)
+}
+
+fn other_messages(db: &impl DedupDatabase, key: u32) -> Arc<Vec<String>> {
+    Arc::new((*db.messages(key)).clone())
+}
+
+fn count(db: &impl DedupDatabase, key: u32) -> usize {
+    db.messages(key).len()
+}
+
+#[salsa::database(DedupStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn equal_values_shared() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 5);
+    db.set_input(2, 7);
+    db.set_input(3, 25);
+
+    let empty1 = db.messages(1);
+    let empty2 = db.messages(2);
+    let full = db.messages(3);
+    assert!(empty1.is_empty());
+    assert!(Arc::ptr_eq(&empty1, &empty2));
+    assert!(!Arc::ptr_eq(&empty1, &full));
+    assert_eq!(*full, vec!["message 0", "message 1"]);
+}
+
+#[test]
+fn shared_across_queries() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 25);
+
+    assert!(Arc::ptr_eq(&db.messages(1), &db.other_messages(1)));
+}
+
+#[test]
+fn shared_after_change() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 5);
+    db.set_input(2, 25);
+    let full = db.messages(2);
+
+    db.set_input(1, 22);
+    assert!(Arc::ptr_eq(&db.messages(1), &full));
+}
+
+#[test]
+fn backdated_values_still_shared() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+    db.set_input(1, 5);
+    db.set_input(2, 7);
+    assert_eq!(db.count(1), 0);
+
+    db.set_input(1, 6);
+    assert_eq!(db.count(1), 0);
+    assert!(Arc::ptr_eq(&db.messages(1), &db.messages(2)));
+
+    assert_eq!(db.query(CountQuery).statistics().executions, 1);
+    assert_eq!(db.query(MessagesQuery).statistics().backdates, 1);
+}
+
+#[test]
+fn statistics() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+    for key in 0..10 {
+        db.set_input(key, key * 3);
+    }
+    for key in 0..10 {
+        db.messages(key);
+    }
+
+    // The values are empty for keys 0 to 3, and have one message for
+    // keys 4 to 6 and two for keys 7 to 9.
+    let statistics = db.query(MessagesQuery).statistics();
+    assert_eq!(statistics.executions, 10);
+    assert_eq!(statistics.deduplications, 7);
+}
@@ -0,0 +1,93 @@
+//! Test `Runtime::set_dependency_pruning`.
+
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, StatisticsMode};
+
+#[salsa::query_group(PruningStorage)]
+trait PruningDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: char) -> u32;
+
+    /// Checks the input, without memoizing the result.
+    #[salsa::dependencies]
+    fn checked(&self, key: char) -> u32;
+
+    fn total(&self) -> u32;
+}
+
+fn checked(db: &impl PruningDatabase, key: char) -> u32 {
+    assert!(db.input(key) < 100);
+    db.input(key)
+}
+
+fn total(db: &impl PruningDatabase) -> u32 {
+    db.checked('a') + db.input('a') + db.input('b')
+}
+
+#[salsa::database(PruningStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn total_inputs(db: &DatabaseImpl) -> String {
+    let mut out = Vec::new();
+    db.query(TotalQuery).debug_dump(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let start = out.find(r#""inputs":"#).unwrap();
+    out[start..].to_string()
+}
+
+#[test]
+fn dominated_inputs_pruned() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+    db.salsa_runtime().set_dependency_pruning(true);
+    db.set_input('a', 1);
+    db.set_input('b', 2);
+
+    assert_eq!(db.total(), 4);
+    assert_eq!(
+        total_inputs(&db),
+        concat!(
+            r#""inputs":["#,
+            r#""__SalsaDatabaseKey { kind: PruningStorage(checked('a')) }","#,
+            r#""__SalsaDatabaseKey { kind: PruningStorage(input('b')) }""#,
+            r#"]}]}"#,
+        )
+    );
+    assert_eq!(db.query(TotalQuery).statistics().pruned_dependencies, 1);
+
+    // Changing the pruned input is noticed through `checked('a')`.
+    db.set_input('a', 10);
+    assert_eq!(db.total(), 22);
+    db.set_input('b', 3);
+    assert_eq!(db.total(), 23);
+    assert_eq!(db.query(TotalQuery).statistics().executions, 3);
+}
+
+#[test]
+fn no_pruning_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input('a', 1);
+    db.set_input('b', 2);
+
+    assert_eq!(db.total(), 4);
+    assert_eq!(
+        total_inputs(&db),
+        concat!(
+            r#""inputs":["#,
+            r#""__SalsaDatabaseKey { kind: PruningStorage(checked('a')) }","#,
+            r#""__SalsaDatabaseKey { kind: PruningStorage(input('a')) }","#,
+            r#""__SalsaDatabaseKey { kind: PruningStorage(input('b')) }""#,
+            r#"]}]}"#,
+        )
+    );
+}
@@ -0,0 +1,100 @@
+//! Test that storage is iterated in a deterministic order, with
+//! `salsa::testing::assert_deterministic`.
+#![cfg(feature = "test-support")]
+
+use salsa::debug::DebugQueryTable;
+use salsa::testing::assert_deterministic;
+use salsa::{Database, SweepStrategy};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[salsa::query_group(DeterministicStorage)]
+trait DeterministicDatabase: salsa::Database {
+    #[salsa::input]
+    fn text(&self, name: String) -> String;
+
+    fn length(&self, name: String) -> usize;
+
+    #[salsa::interned]
+    fn intern_word(&self, word: String) -> salsa::InternId;
+
+    fn words(&self, name: String) -> Vec<salsa::InternId>;
+}
+
+fn length(db: &impl DeterministicDatabase, name: String) -> usize {
+    db.text(name).len()
+}
+
+fn words(db: &impl DeterministicDatabase, name: String) -> Vec<salsa::InternId> {
+    db.text(name)
+        .split_whitespace()
+        .map(|word| db.intern_word(word.to_string()))
+        .collect()
+}
+
+#[salsa::database(DeterministicStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+const NAMES: &[&str] = &[
+    "main", "lib", "util", "parse", "lex", "emit", "check", "infer", "mod", "test",
+];
+
+#[test]
+fn entries_are_deterministic() {
+    assert_deterministic(|db: &mut salsa::testing::TestDatabase<DatabaseImpl>| {
+        for (i, name) in NAMES.iter().enumerate() {
+            db.set_text(name.to_string(), format!("{} word{} shared", name, i));
+        }
+        for name in NAMES {
+            db.length(name.to_string());
+            db.words(name.to_string());
+        }
+
+        db.set_text("lib".to_string(), "changed".to_string());
+        db.words("lib".to_string());
+        db.sweep_all(SweepStrategy::discard_outdated());
+        let ids: Vec<salsa::InternId> = NAMES
+            .iter()
+            .flat_map(|name| db.words(name.to_string()))
+            .collect();
+
+        let texts: Vec<String> = db
+            .query(TextQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        let lengths: Vec<String> = db
+            .query(LengthQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        let words: Vec<String> = db
+            .query(InternWordQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        (ids, texts, lengths, words)
+    });
+}
+
+#[test]
+#[should_panic(expected = "the runs returned different results")]
+fn nondeterministic_ops() {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    assert_deterministic(|db: &mut salsa::testing::TestDatabase<DatabaseImpl>| {
+        let run = RUNS.fetch_add(1, Ordering::SeqCst);
+        db.set_text("main".to_string(), "x".repeat(run + 1));
+        db.length("main".to_string()) + db.query(LengthQuery).entries::<Vec<_>>().len()
+    });
+}
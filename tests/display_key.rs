@@ -0,0 +1,110 @@
+//! Test `#[salsa::display_key]` and the `Display` impl of database-keys.
+
+use salsa::Database;
+use std::fmt;
+
+#[salsa::query_group(DisplayStorage)]
+trait DisplayDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::display_key(display_file)]
+    fn file_text(&self, file: u32) -> String;
+
+    fn line_count(&self, file: u32) -> usize;
+
+    fn line(&self, file: u32, line: usize) -> String;
+
+    fn cycle_a(&self) -> ();
+
+    fn cycle_b(&self) -> ();
+}
+
+fn display_file(file: &u32, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(fmt, "text of file #{}", file)
+}
+
+fn line_count(db: &impl DisplayDatabase, file: u32) -> usize {
+    db.file_text(file).lines().count()
+}
+
+fn line(db: &impl DisplayDatabase, file: u32, line: usize) -> String {
+    db.file_text(file)
+        .lines()
+        .nth(line)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn cycle_a(db: &impl DisplayDatabase) {
+    db.cycle_b()
+}
+
+fn cycle_b(db: &impl DisplayDatabase) {
+    db.cycle_a()
+}
+
+#[salsa::database(DisplayStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn custom_display() {
+    let db = DatabaseImpl::default();
+    assert_eq!(
+        db.query(FileTextQuery).database_key(3).to_string(),
+        "text of file #3"
+    );
+}
+
+#[test]
+fn default_display() {
+    let db = DatabaseImpl::default();
+    assert_eq!(
+        db.query(LineCountQuery).database_key(3).to_string(),
+        "line_count(3)"
+    );
+    assert_eq!(
+        db.query(LineQuery).database_key((3, 1)).to_string(),
+        "line(3, 1)"
+    );
+    assert_eq!(
+        db.query(CycleAQuery).database_key(()).to_string(),
+        "cycle_a()"
+    );
+}
+
+#[test]
+#[should_panic(expected = "cycle detected:\n- cycle_a()\n- cycle_b()\n")]
+fn cycle_error_displays_keys() {
+    let db = DatabaseImpl::default();
+    db.cycle_a();
+}
+
+#[test]
+#[cfg(not(feature = "strip-logging"))]
+fn events_display_keys() {
+    use salsa::EventListener;
+    use std::sync::{Arc, Mutex};
+
+    let mut db = DatabaseImpl::default();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let listener: EventListener<DatabaseImpl> = Box::new({
+        let log = log.clone();
+        move |event| log.lock().unwrap().push(event.kind.to_string())
+    });
+    db.salsa_runtime().subscribe_events(listener);
+
+    db.set_file_text(1, "a\nb".to_string());
+    assert_eq!(db.line(1, 1), "b");
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["changing text of file #1", "computing line(1, 1)"]
+    );
+}
@@ -0,0 +1,116 @@
+//! Test the `salsa::documents` query group.
+#![cfg(feature = "documents")]
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::documents::{self, ContentChange, DocumentDatabase, Position, Range};
+
+#[salsa::query_group(WordsStorage)]
+trait WordsDatabase: DocumentDatabase + HasLog {
+    fn word_count(&self, uri: String) -> usize;
+}
+
+fn word_count(db: &impl WordsDatabase, uri: String) -> usize {
+    db.log().add(format!("word_count({})", uri));
+    db.document_text(uri).split_whitespace().count()
+}
+
+#[salsa::database(salsa::documents::DocumentStorage, WordsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> ContentChange {
+    ContentChange {
+        range: Some(Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        }),
+        text: text.to_string(),
+    }
+}
+
+#[test]
+fn apply_change() {
+    let mut text = "fn main() {\n    let x = 1;\n}\n".to_string();
+    documents::apply_change(&mut text, &edit((1, 8), (1, 9), "answer"));
+    assert_eq!(text, "fn main() {\n    let answer = 1;\n}\n");
+
+    // Positions past the end of a line or of the text are clamped.
+    documents::apply_change(&mut text, &edit((1, 19), (1, 99), " // ok"));
+    assert_eq!(text, "fn main() {\n    let answer = 1; // ok\n}\n");
+    documents::apply_change(&mut text, &edit((9, 0), (9, 0), "// end"));
+    assert_eq!(text, "fn main() {\n    let answer = 1; // ok\n}\n// end");
+
+    let change = ContentChange {
+        range: None,
+        text: "new".to_string(),
+    };
+    documents::apply_change(&mut text, &change);
+    assert_eq!(text, "new");
+}
+
+#[test]
+fn utf16_positions() {
+    // `𝕏` is two UTF-16 code units (and four bytes).
+    let text = "a𝕏b\nc";
+    let offset = |line, character| documents::offset_of(text, Position { line, character });
+    assert_eq!(offset(0, 1), 1);
+    assert_eq!(offset(0, 3), 5);
+    assert_eq!(offset(0, 4), 6);
+    assert_eq!(offset(1, 0), 7);
+}
+
+#[test]
+fn open_change_close() {
+    let mut db = DatabaseImpl::default();
+    let uri = "file:///a.txt".to_string();
+    documents::open_document(&mut db, uri.clone(), 1, "one two".to_string());
+    assert!(db.document_is_open(uri.clone()));
+    assert_eq!(db.word_count(uri.clone()), 2);
+
+    documents::change_document(
+        &mut db,
+        uri.clone(),
+        2,
+        &[edit((0, 7), (0, 7), " three"), edit((0, 0), (0, 4), "")],
+    );
+    assert_eq!(*db.document_text(uri.clone()), "two three");
+    assert_eq!(db.document_version(uri.clone()), 2);
+    assert_eq!(db.word_count(uri.clone()), 2);
+    assert_eq!(db.log().take(), vec!["word_count(file:///a.txt)"; 2]);
+
+    // Closing keeps the text.
+    documents::close_document(&mut db, uri.clone());
+    assert!(!db.document_is_open(uri.clone()));
+    assert_eq!(db.word_count(uri.clone()), 2);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+}
+
+#[test]
+#[should_panic(expected = "is not open")]
+fn change_closed_document() {
+    let mut db = DatabaseImpl::default();
+    documents::change_document(&mut db, "file:///a.txt".to_string(), 1, &[]);
+}
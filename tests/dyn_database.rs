@@ -0,0 +1,97 @@
+//! Test query groups that extend `salsa::DynDatabase`, whose query
+//! functions take the database as a trait object.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[salsa::query_group(SourceStorage)]
+trait Source: salsa::DynDatabase {
+    #[salsa::input]
+    fn text(&self, name: String) -> String;
+
+    fn line_count(&self, name: String) -> usize;
+}
+
+fn line_count(db: &dyn Source, name: String) -> usize {
+    db.text(name).lines().count()
+}
+
+#[salsa::query_group(SummaryStorage)]
+trait Summary: Source {
+    fn summary(&self, names: Vec<String>) -> String;
+
+    fn volatile_lines(&self, name: String) -> usize;
+}
+
+static EXECUTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A helper that holds on to the database, which a generic query
+/// function could not store without being generic itself.
+struct Summarizer<'db> {
+    db: &'db dyn Source,
+}
+
+impl Summarizer<'_> {
+    fn describe(&self, name: &str) -> String {
+        format!("{}: {}", name, self.db.line_count(name.to_string()))
+    }
+}
+
+fn summary(db: &dyn Summary, names: Vec<String>) -> String {
+    EXECUTIONS.fetch_add(1, Ordering::SeqCst);
+    let summarizer = Summarizer { db };
+    names
+        .iter()
+        .map(|name| summarizer.describe(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn volatile_lines(db: &dyn Summary, name: String) -> usize {
+    db.report_untracked_read();
+    db.line_count(name)
+}
+
+#[salsa::database(SourceStorage, SummaryStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn dyn_query_functions() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a".to_string(), "1\n2".to_string());
+    db.set_text("b".to_string(), "1".to_string());
+    let names = vec!["a".to_string(), "b".to_string()];
+    assert_eq!(db.summary(names.clone()), "a: 2, b: 1");
+    assert_eq!(EXECUTIONS.load(Ordering::SeqCst), 1);
+
+    // Reads through the trait object are tracked.
+    db.set_text("b".to_string(), "1\n2\n3".to_string());
+    assert_eq!(db.summary(names.clone()), "a: 2, b: 3");
+    assert_eq!(EXECUTIONS.load(Ordering::SeqCst), 2);
+
+    // `line_count("a")` did not change, so the summary is reused.
+    db.set_text("a".to_string(), "3\n4".to_string());
+    assert_eq!(db.summary(names), "a: 2, b: 3");
+    assert_eq!(EXECUTIONS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn dyn_database_methods() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a".to_string(), "1".to_string());
+    let dyn_db: &dyn Summary = &db;
+    assert_eq!(dyn_db.volatile_lines("a".to_string()), 1);
+    assert!(!dyn_db.is_current_revision_canceled());
+
+    let revision = salsa::DynDatabase::current_revision(&db);
+    db.set_text("b".to_string(), "1".to_string());
+    assert!(salsa::DynDatabase::current_revision(&db) > revision);
+}
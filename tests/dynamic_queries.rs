@@ -0,0 +1,97 @@
+//! Test queries registered at runtime with `register_dynamic_query`.
+#![cfg(feature = "dynamic")]
+
+use salsa::{Database, DynamicContext, QueryByNameError};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[salsa::query_group(HostStorage)]
+trait HostDatabase: salsa::DynamicQueryDatabase {
+    #[salsa::input]
+    #[salsa::dynamic]
+    fn text(&self, key: u32) -> String;
+
+    /// Reports what the `word_count` plugin query says about a text.
+    fn report(&self, key: u32) -> Result<String, QueryByNameError>;
+}
+
+fn report(db: &impl HostDatabase, key: u32) -> Result<String, QueryByNameError> {
+    let count = db.invoke("word_count", json!(key))?;
+    Ok(format!("{} words", count))
+}
+
+#[salsa::database(HostStorage, salsa::DynamicQueryStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// Registers a `word_count` query, which counts the words of `text`
+/// (ignoring those in `stop_words`), and returns the number of times
+/// it was executed.
+fn register_word_count(db: &mut DatabaseImpl) -> Arc<AtomicUsize> {
+    let executions = Arc::new(AtomicUsize::new(0));
+    let counter = executions.clone();
+    db.register_dynamic_query("word_count", move |db: &dyn DynamicContext, key: &Value| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        let text = db.invoke("text", json!([key])).unwrap();
+        json!(text.as_str().unwrap().split_whitespace().count())
+    });
+    executions
+}
+
+#[test]
+fn invoke_dynamic_query() {
+    let mut db = DatabaseImpl::default();
+    let executions = register_word_count(&mut db);
+    db.set_text(1, "hello world".to_string());
+
+    assert_eq!(db.report(1), Ok("2 words".to_string()));
+    assert_eq!(db.invoke("word_count", json!(1)), Ok(json!(2)));
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+
+    db.set_text(1, "hello big world".to_string());
+    assert_eq!(db.report(1), Ok("3 words".to_string()));
+    assert_eq!(executions.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn dynamic_query_backdated() {
+    let mut db = DatabaseImpl::default();
+    let executions = register_word_count(&mut db);
+    db.set_text(1, "hello world".to_string());
+    assert_eq!(db.report(1), Ok("2 words".to_string()));
+    let revision = db.salsa_runtime().current_revision();
+
+    // The word count is the same, so `report` did not change.
+    db.set_text(1, "goodbye world".to_string());
+    assert!(!db.query(ReportQuery).maybe_changed_since(1, revision));
+    assert_eq!(executions.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn register_later() {
+    let mut db = DatabaseImpl::default();
+    db.set_text(1, "hello world".to_string());
+    assert_eq!(
+        db.report(1),
+        Err(QueryByNameError::UnknownQuery("word_count".to_string()))
+    );
+
+    // Registering the query re-executes the queries that looked for it.
+    let executions = register_word_count(&mut db);
+    assert_eq!(db.report(1), Ok("2 words".to_string()));
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+
+    // So does registering it anew.
+    db.register_dynamic_query("word_count", |_: &dyn DynamicContext, _: &Value| json!(0));
+    assert_eq!(db.report(1), Ok("0 words".to_string()));
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,131 @@
+//! Test `#[salsa::eq_with]` and `#[salsa::no_backdate]` queries, whose
+//! values do not implement `Eq`.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::testing::TestDatabase;
+use salsa::{Database, Durability};
+use std::sync::Arc;
+
+/// A value that only implements `PartialEq`.
+#[derive(Clone, Debug, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+/// A value that implements neither.
+#[derive(Clone)]
+struct Scale(Arc<dyn Fn(f64) -> f64 + Send + Sync>);
+
+#[salsa::query_group(EqWithStorage)]
+trait EqWithDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn coordinates(&self) -> (i32, i32);
+
+    #[salsa::eq_with(approx_eq)]
+    fn point(&self) -> Point;
+
+    fn distance(&self) -> u32;
+
+    #[salsa::no_backdate]
+    fn scale(&self) -> Scale;
+
+    fn scaled_distance(&self) -> u32;
+}
+
+fn approx_eq(old: &Point, new: &Point) -> bool {
+    (old.x - new.x).abs() < 1.0 && (old.y - new.y).abs() < 1.0
+}
+
+fn point(db: &impl EqWithDatabase) -> Point {
+    db.log().add("point");
+    let (x, y) = db.coordinates();
+    Point {
+        x: f64::from(x) / 10.0,
+        y: f64::from(y) / 10.0,
+    }
+}
+
+fn distance(db: &impl EqWithDatabase) -> u32 {
+    db.log().add("distance");
+    let point = db.point();
+    (point.x * point.x + point.y * point.y).sqrt() as u32
+}
+
+fn scale(db: &impl EqWithDatabase) -> Scale {
+    db.log().add("scale");
+    let factor = f64::from(db.coordinates().0.signum());
+    Scale(Arc::new(move |x| x * factor))
+}
+
+fn scaled_distance(db: &impl EqWithDatabase) -> u32 {
+    db.log().add("scaled_distance");
+    (db.scale().0)(f64::from(db.distance())) as u32
+}
+
+#[salsa::database(EqWithStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn eq_with_backdates() {
+    let mut db = DatabaseImpl::default();
+    db.set_coordinates((30, 40));
+    assert_eq!(db.distance(), 5);
+    assert_eq!(db.log().take(), vec!["distance", "point"]);
+
+    // Close enough: `distance` is not re-executed.
+    db.set_coordinates((31, 40));
+    assert_eq!(db.distance(), 5);
+    assert_eq!(db.log().take(), vec!["point"]);
+
+    db.set_coordinates((60, 80));
+    assert_eq!(db.distance(), 10);
+    assert_eq!(db.log().take(), vec!["point", "distance"]);
+}
+
+#[test]
+fn no_backdate_is_memoized() {
+    let mut db = DatabaseImpl::default();
+    db.set_coordinates((30, 40));
+    assert_eq!(db.scaled_distance(), 5);
+    assert_eq!(db.scaled_distance(), 5);
+    db.log().take();
+
+    // `scale` is recomputed to an equivalent value, which is
+    // considered changed anyway.
+    db.set_coordinates((31, 40));
+    assert_eq!(db.scaled_distance(), 5);
+    assert_eq!(db.log().take(), vec!["scale", "scaled_distance", "point"]);
+}
+
+#[test]
+fn no_backdate_skips_determinism_check() {
+    let mut db = TestDatabase::new(DatabaseImpl::default());
+    db.set_coordinates((30, 40));
+    db.scale();
+    db.log().take();
+
+    // Reusing the value of `scale` does not re-execute it to compare
+    // the values.
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+    db.scale();
+    assert!(db.log().take().is_empty());
+}
@@ -0,0 +1,147 @@
+//! Test subscribing to events from several listeners, and the events
+//! reported when values are evicted or swept.
+#![cfg(not(feature = "strip-logging"))]
+
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, DiscardWhat, EventKind, EventListener, SweepStrategy};
+use std::sync::{Arc, Mutex};
+
+#[salsa::query_group(EventsStorage)]
+trait EventsDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    fn double(&self) -> u32;
+
+    fn triple(&self, key: u32) -> u32;
+}
+
+fn double(db: &impl EventsDatabase) -> u32 {
+    db.input() * 2
+}
+
+fn triple(db: &impl EventsDatabase, key: u32) -> u32 {
+    db.input() * 3 + key
+}
+
+#[salsa::database(EventsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[cfg(not(feature = "single-threaded"))]
+impl salsa::ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+fn listener(log: &Log) -> EventListener<DatabaseImpl> {
+    let log = log.clone();
+    Box::new(move |event| {
+        let name = match event.kind {
+            EventKind::DidValidateMemoizedValue { .. } => "validate",
+            EventKind::WillBlockOn { .. } => "block",
+            EventKind::WillChangeInputValue { .. } => "change",
+            EventKind::WillExecute { .. } => "execute",
+            EventKind::DidEvictValue { .. } => "evict",
+            EventKind::DidSweep {
+                discarded: DiscardWhat::Values,
+                ..
+            } => "sweep values",
+            EventKind::DidSweep { .. } => "sweep everything",
+        };
+        log.lock().unwrap().push(name.to_string());
+    })
+}
+
+#[test]
+fn multiple_listeners() {
+    let mut db = DatabaseImpl::default();
+    let first = Log::default();
+    let second = Log::default();
+    let first_id = db.salsa_runtime().subscribe_events(listener(&first));
+    db.salsa_runtime().subscribe_events(listener(&second));
+
+    db.set_input(1);
+    assert_eq!(db.double(), 2);
+    assert_eq!(*first.lock().unwrap(), ["change", "execute"]);
+    assert_eq!(*second.lock().unwrap(), ["change", "execute"]);
+
+    assert!(db.salsa_runtime().unsubscribe_events(first_id));
+    assert!(!db.salsa_runtime().unsubscribe_events(first_id));
+
+    db.set_input(2);
+    assert_eq!(db.double(), 4);
+    assert_eq!(first.lock().unwrap().len(), 2);
+    assert_eq!(
+        *second.lock().unwrap(),
+        ["change", "execute", "change", "execute"]
+    );
+}
+
+#[test]
+#[cfg(not(feature = "single-threaded"))]
+fn listeners_observe_snapshots() {
+    use salsa::ParallelDatabase;
+
+    let mut db = DatabaseImpl::default();
+    let log = Log::default();
+    db.salsa_runtime().subscribe_events(listener(&log));
+    db.set_input(1);
+    log.lock().unwrap().clear();
+
+    let snapshot = db.snapshot();
+    std::thread::spawn(move || snapshot.double())
+        .join()
+        .unwrap();
+    assert_eq!(*log.lock().unwrap(), ["execute"]);
+}
+
+#[test]
+fn evict_and_sweep() {
+    let mut db = DatabaseImpl::default();
+    let log = Log::default();
+    db.salsa_runtime().subscribe_events(listener(&log));
+    db.query_mut(TripleQuery).set_lru_capacity(4);
+    db.set_input(1);
+
+    for key in 0..32 {
+        db.triple(key);
+    }
+    let count = |name: &str| log.lock().unwrap().iter().filter(|n| *n == name).count();
+    let present = |db: &DatabaseImpl| {
+        db.query(TripleQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .filter(|entry| entry.value.is_some())
+            .count()
+    };
+    assert_eq!(count("execute"), 32);
+    assert!(count("evict") > 0);
+    let live = present(&db);
+    assert_eq!(count("evict"), 32 - live);
+
+    // Only values that are present are reported as swept...
+    db.set_input(2);
+    db.query(TripleQuery)
+        .sweep(SweepStrategy::default().discard_values().sweep_outdated());
+    assert_eq!(count("sweep values"), live);
+    assert_eq!(present(&db), 0);
+
+    // ...but all memos are when discarding everything.
+    db.query(TripleQuery)
+        .sweep(SweepStrategy::discard_outdated());
+    assert_eq!(count("sweep everything"), 32);
+}
@@ -0,0 +1,86 @@
+//! Test `#[salsa::extends]`, with which a query group reuses the
+//! queries (and storage) of the groups it extends.
+
+mod library {
+    #[salsa::query_group(SourceStorage)]
+    pub trait Source: salsa::Database {
+        #[salsa::input]
+        fn source(&self, key: u32) -> String;
+    }
+
+    #[salsa::query_group(LengthStorage)]
+    #[salsa::extends(crate::library::SourceStorage)]
+    pub trait Length: Source {
+        fn length(&self, key: u32) -> usize;
+    }
+
+    fn length(db: &impl Length, key: u32) -> usize {
+        db.source(key).len()
+    }
+
+    #[salsa::query_group(WordsStorage)]
+    #[salsa::extends(crate::library::SourceStorage)]
+    pub(crate) trait Words: Source {
+        fn words(&self, key: u32) -> usize;
+    }
+
+    fn words(db: &impl Words, key: u32) -> usize {
+        db.source(key).split_whitespace().count()
+    }
+}
+
+#[salsa::query_group(SummaryStorage)]
+#[salsa::extends(library::LengthStorage!)]
+#[salsa::extends(library::WordsStorage!)]
+trait Summary: library::Length + library::Words {
+    fn summary(&self, key: u32) -> String;
+}
+
+fn summary(db: &impl Summary, key: u32) -> String {
+    format!("{} words, {} bytes", db.words(key), db.length(key))
+}
+
+// `SourceStorage` is listed twice (by `LengthStorage` and
+// `WordsStorage`), but only stored once.
+#[salsa::database(SummaryStorage!)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+mod length {
+    // A database can list a parent group explicitly, too.
+    #[salsa::database(crate::library::SourceStorage, crate::library::LengthStorage!)]
+    #[derive(Default)]
+    pub(crate) struct LengthDatabase {
+        runtime: salsa::Runtime<LengthDatabase>,
+    }
+
+    impl salsa::Database for LengthDatabase {
+        fn salsa_runtime(&self) -> &salsa::Runtime<LengthDatabase> {
+            &self.runtime
+        }
+    }
+}
+
+#[test]
+fn extended_groups() {
+    use library::{Length, Source};
+
+    let mut db = DatabaseImpl::default();
+    db.set_source(1, "hello world".to_string());
+    assert_eq!(db.summary(1), "2 words, 11 bytes");
+
+    db.set_source(1, "hi".to_string());
+    assert_eq!(db.summary(1), "1 words, 2 bytes");
+
+    let mut db = length::LengthDatabase::default();
+    db.set_source(1, "hello".to_string());
+    assert_eq!(db.length(1), 5);
+}
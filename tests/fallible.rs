@@ -0,0 +1,157 @@
+//! Test `#[salsa::fallible]` queries and `QueryTable::failed`.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::Database;
+
+/// An error that implements neither `Clone` nor `Eq`.
+#[derive(Debug)]
+struct Unreadable(String);
+
+#[derive(Debug, PartialEq, Eq)]
+struct NotANumber(String);
+
+#[salsa::query_group(FallibleStorage)]
+trait FallibleDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn text(&self, name: &'static str) -> String;
+
+    #[salsa::fallible]
+    fn number(&self, name: &'static str) -> Result<u32, NotANumber>;
+
+    #[salsa::fallible(no_backdate)]
+    fn unbackdated_number(&self, name: &'static str) -> Result<u32, Unreadable>;
+
+    #[salsa::fallible(no_backdate, no_memoize)]
+    fn unmemoized_number(&self, name: &'static str) -> Result<u32, Unreadable>;
+
+    fn double(&self, name: &'static str) -> Option<u32>;
+
+    fn unbackdated_double(&self, name: &'static str) -> Option<u32>;
+}
+
+fn number(db: &impl FallibleDatabase, name: &'static str) -> Result<u32, NotANumber> {
+    db.log().add(format!("number({})", name));
+    let text = db.text(name);
+    let text = text.trim();
+    text.parse().map_err(|_| NotANumber(text.to_string()))
+}
+
+fn unbackdated_number(db: &impl FallibleDatabase, name: &'static str) -> Result<u32, Unreadable> {
+    db.log().add(format!("unbackdated_number({})", name));
+    let text = db.text(name);
+    text.trim().parse().map_err(|_| Unreadable(text))
+}
+
+fn unmemoized_number(db: &impl FallibleDatabase, name: &'static str) -> Result<u32, Unreadable> {
+    db.log().add(format!("unmemoized_number({})", name));
+    let text = db.text(name);
+    text.trim().parse().map_err(|_| Unreadable(text))
+}
+
+fn double(db: &impl FallibleDatabase, name: &'static str) -> Option<u32> {
+    db.log().add(format!("double({})", name));
+    db.number(name).ok().map(|n| n * 2)
+}
+
+fn unbackdated_double(db: &impl FallibleDatabase, name: &'static str) -> Option<u32> {
+    db.log().add(format!("unbackdated_double({})", name));
+    db.unbackdated_number(name).ok().map(|n| n * 2)
+}
+
+#[salsa::database(FallibleStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn error_is_shared() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    let error = db.unbackdated_number("a").unwrap_err();
+    assert_eq!(error.0, "x");
+    assert_eq!(format!("{:?}", error), "Unreadable(\"x\")");
+    assert_eq!(db.unbackdated_number("a").unwrap_err(), error);
+
+    db.set_text("a", "1".to_string());
+    assert_eq!(db.unbackdated_number("a"), Ok(1));
+}
+
+#[test]
+fn equal_error_is_backdated() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    assert_eq!(db.double("a"), None);
+    db.log().take();
+
+    db.set_text("a", " x".to_string());
+    assert_eq!(db.double("a"), None);
+    assert_eq!(db.log().take(), vec!["number(a)"]);
+}
+
+#[test]
+fn error_is_not_backdated() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    assert_eq!(db.unbackdated_double("a"), None);
+    db.log().take();
+
+    db.set_text("a", "x".to_string());
+    assert_eq!(db.unbackdated_double("a"), None);
+    assert_eq!(
+        db.log().take(),
+        vec!["unbackdated_number(a)", "unbackdated_double(a)"]
+    );
+
+    // Values are still backdated.
+    db.set_text("a", "1".to_string());
+    db.unbackdated_double("a");
+    db.log().take();
+    db.set_text("a", " 1".to_string());
+    assert_eq!(db.unbackdated_double("a"), Some(2));
+    assert_eq!(db.log().take(), vec!["unbackdated_number(a)"]);
+}
+
+#[test]
+fn error_is_not_memoized() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    db.unmemoized_number("a").unwrap_err();
+    db.unmemoized_number("a").unwrap_err();
+    assert_eq!(
+        db.log().take(),
+        vec!["unmemoized_number(a)", "unmemoized_number(a)"]
+    );
+
+    db.set_text("a", "1".to_string());
+    assert_eq!(db.unmemoized_number("a"), Ok(1));
+    assert_eq!(db.unmemoized_number("a"), Ok(1));
+    assert_eq!(db.log().take(), vec!["unmemoized_number(a)"]);
+}
+
+#[test]
+fn failed() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    db.set_text("b", "1".to_string());
+    assert!(db.query(NumberQuery).failed("a"));
+    assert!(!db.query(NumberQuery).failed("b"));
+    assert_eq!(db.log().take(), vec!["number(a)", "number(b)"]);
+    assert!(!db.query(NumberQuery).failed("b"));
+    assert!(db.log().take().is_empty());
+}
@@ -0,0 +1,105 @@
+//! Test `#[salsa::fields]`, which tracks reads of individual fields
+//! of an input.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct File {
+    text: String,
+    version: u32,
+}
+
+#[salsa::query_group(FieldsStorage)]
+trait FieldsDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    #[salsa::fields(text: String, version: u32)]
+    fn file(&self, key: u32) -> File;
+
+    fn text_len(&self, key: u32) -> usize;
+
+    fn next_version(&self, key: u32) -> u32;
+}
+
+fn text_len(db: &impl FieldsDatabase, key: u32) -> usize {
+    db.log().add(format!("text_len({})", key));
+    db.file_text(key).len()
+}
+
+fn next_version(db: &impl FieldsDatabase, key: u32) -> u32 {
+    db.log().add(format!("next_version({})", key));
+    db.file_version(key) + 1
+}
+
+#[salsa::database(FieldsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn field_queries() {
+    let mut db = DatabaseImpl::default();
+    db.set_file(
+        1,
+        File {
+            text: "hello".to_string(),
+            version: 1,
+        },
+    );
+    assert_eq!(db.file_text(1), "hello");
+    assert_eq!(db.file_version(1), 1);
+}
+
+#[test]
+fn only_changed_fields_invalidate() {
+    let mut db = DatabaseImpl::default();
+    db.set_file(
+        1,
+        File {
+            text: "hello".to_string(),
+            version: 1,
+        },
+    );
+    assert_eq!(db.text_len(1), 5);
+    assert_eq!(db.next_version(1), 2);
+    assert_eq!(db.log().take(), vec!["text_len(1)", "next_version(1)"]);
+
+    // Only the version changes.
+    db.set_file(
+        1,
+        File {
+            text: "hello".to_string(),
+            version: 2,
+        },
+    );
+    assert_eq!(db.text_len(1), 5);
+    assert_eq!(db.next_version(1), 3);
+    assert_eq!(db.log().take(), vec!["next_version(1)"]);
+
+    // Only the text changes.
+    db.set_file(
+        1,
+        File {
+            text: "hello world".to_string(),
+            version: 2,
+        },
+    );
+    assert_eq!(db.text_len(1), 11);
+    assert_eq!(db.next_version(1), 3);
+    assert_eq!(db.log().take(), vec!["text_len(1)"]);
+}
@@ -0,0 +1,132 @@
+//! Test `#[salsa::fingerprint]` queries, which backdate by comparing
+//! fingerprints rather than values.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use std::sync::Arc;
+
+/// A value that can be hashed but not compared.
+#[derive(Clone, Debug, Hash)]
+struct Tree {
+    children: Vec<Tree>,
+}
+
+#[salsa::query_group(FingerprintStorage)]
+trait FingerprintDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn depth(&self) -> usize;
+
+    #[salsa::input]
+    fn label(&self) -> String;
+
+    #[salsa::fingerprint]
+    fn tree(&self) -> Arc<Tree>;
+
+    fn tree_size(&self) -> usize;
+
+    #[salsa::fingerprint(label_length)]
+    fn padded_label(&self) -> String;
+
+    fn label_line(&self) -> String;
+}
+
+fn tree(db: &impl FingerprintDatabase) -> Arc<Tree> {
+    db.log().add("tree");
+    let mut tree = Tree { children: vec![] };
+    for _ in 0..db.depth() {
+        tree = Tree {
+            children: vec![tree],
+        };
+    }
+    Arc::new(tree)
+}
+
+fn size(tree: &Tree) -> usize {
+    1 + tree.children.iter().map(size).sum::<usize>()
+}
+
+fn tree_size(db: &impl FingerprintDatabase) -> usize {
+    db.log().add("tree_size");
+    size(&db.tree())
+}
+
+fn padded_label(db: &impl FingerprintDatabase) -> String {
+    db.log().add("padded_label");
+    format!("{:<8}", db.label())
+}
+
+/// A deliberately coarse fingerprint: labels of the same length are
+/// considered equal.
+#[allow(clippy::ptr_arg)]
+fn label_length(label: &String) -> u128 {
+    label.len() as u128
+}
+
+fn label_line(db: &impl FingerprintDatabase) -> String {
+    db.log().add("label_line");
+    format!("[{}]", db.padded_label())
+}
+
+#[salsa::database(FingerprintStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn backdate_on_equal_hash() {
+    let mut db = DatabaseImpl::default();
+    db.set_depth(3);
+    assert_eq!(db.tree_size(), 4);
+    assert_eq!(db.log().take(), vec!["tree_size", "tree"]);
+
+    // Same value: `tree` re-executes but is backdated.
+    db.set_depth(3);
+    assert_eq!(db.tree_size(), 4);
+    assert_eq!(db.log().take(), vec!["tree"]);
+
+    db.set_depth(4);
+    assert_eq!(db.tree_size(), 5);
+    assert_eq!(db.log().take(), vec!["tree", "tree_size"]);
+}
+
+#[test]
+fn custom_fingerprint() {
+    let mut db = DatabaseImpl::default();
+    db.set_label("abc".to_string());
+    assert_eq!(db.label_line(), "[abc     ]");
+    assert_eq!(db.log().take(), vec!["label_line", "padded_label"]);
+
+    // Padded to the same length, so `label_line` is not re-executed
+    // (and keeps its now stale value).
+    db.set_label("xyz".to_string());
+    assert_eq!(db.padded_label(), "xyz     ");
+    assert_eq!(db.label_line(), "[abc     ]");
+    assert_eq!(db.log().take(), vec!["padded_label"]);
+
+    db.set_label("much longer".to_string());
+    assert_eq!(db.label_line(), "[much longer]");
+    assert_eq!(db.log().take(), vec!["padded_label", "label_line"]);
+}
+
+#[test]
+fn hash_fingerprint_is_stable() {
+    let a = salsa::plumbing::hash_fingerprint(&"hello");
+    assert_eq!(a, salsa::plumbing::hash_fingerprint(&"hello"));
+    assert_ne!(a, salsa::plumbing::hash_fingerprint(&"world"));
+    assert_ne!(a >> 64, a & u128::from(u64::MAX));
+}
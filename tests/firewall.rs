@@ -0,0 +1,82 @@
+//! Test `#[salsa::firewall]` queries, which compare a hash of their
+//! value when their inputs change.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+
+#[salsa::query_group(FirewallStorage)]
+trait FirewallDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn text(&self) -> String;
+
+    /// The words of `text`, ignoring whitespace.
+    #[salsa::firewall]
+    fn words(&self) -> Vec<String>;
+
+    fn word_count(&self) -> usize;
+}
+
+fn words(db: &impl FirewallDatabase) -> Vec<String> {
+    db.log().add("words");
+    db.text().split_whitespace().map(str::to_string).collect()
+}
+
+fn word_count(db: &impl FirewallDatabase) -> usize {
+    db.log().add("word_count");
+    db.words().len()
+}
+
+#[salsa::database(FirewallStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn value_cached() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a b".to_string());
+
+    assert_eq!(db.words(), vec!["a", "b"]);
+    assert_eq!(db.words(), vec!["a", "b"]);
+    assert_eq!(db.log().take(), vec!["words"]);
+
+    // Reading it again in a new revision re-executes it, as the input
+    // changed, but reading its dependents afterwards does not.
+    db.set_text("a  b".to_string());
+    assert_eq!(db.words(), vec!["a", "b"]);
+    assert_eq!(db.word_count(), 2);
+    assert_eq!(db.log().take(), vec!["words", "word_count"]);
+}
+
+#[test]
+fn unchanged_projection_stops_invalidation() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a b".to_string());
+    assert_eq!(db.word_count(), 2);
+    assert_eq!(db.log().take(), vec!["word_count", "words"]);
+
+    // Only the whitespace changed: `words` is re-executed to compare
+    // its hash, but `word_count` is not.
+    db.set_text("a  b ".to_string());
+    assert_eq!(db.word_count(), 2);
+    assert_eq!(db.log().take(), vec!["words"]);
+
+    db.set_text("a b c".to_string());
+    assert_eq!(db.word_count(), 3);
+    assert_eq!(db.log().take(), vec!["words", "word_count"]);
+}
@@ -0,0 +1,113 @@
+//! Test the `salsa::fs` query group and its `FileWatcher`.
+#![cfg(feature = "fs")]
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::fs::{self, FileSystemDatabase, FileWatcher};
+use salsa::{Database, Durability};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[salsa::query_group(LinesStorage)]
+trait LinesDatabase: FileSystemDatabase + HasLog {
+    /// The number of lines of all the files directly in `dir`.
+    fn line_count(&self, dir: PathBuf) -> usize;
+}
+
+fn line_count(db: &impl LinesDatabase, dir: PathBuf) -> usize {
+    db.log().add("line_count");
+    db.dir_entries(dir)
+        .iter()
+        .filter_map(|path| db.file_text(path.clone()))
+        .map(|text| text.lines().count())
+        .sum()
+}
+
+#[salsa::database(salsa::fs::FileSystemStorage, LinesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+/// Creates an empty directory for a test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("salsa-fs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write(path: &Path, text: &str) {
+    std::fs::write(path, text).unwrap();
+}
+
+#[test]
+fn load_and_reload() {
+    let dir = test_dir("load");
+    write(&dir.join("a.txt"), "1\n2\n");
+    write(&dir.join("b.txt"), "3\n");
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    write(&dir.join("sub/c.txt"), "4\n");
+
+    let mut db = DatabaseImpl::default();
+    fs::load_path(&mut db, &dir, Durability::LOW);
+    assert!(db.file_exists(dir.join("sub/c.txt")));
+    assert_eq!(db.file_text(dir.join("sub")), None);
+    assert_eq!(db.line_count(dir.clone()), 3);
+    assert_eq!(db.log().take(), vec!["line_count"]);
+
+    // Reloading without changes keeps the revision.
+    let revision = db.salsa_runtime().current_revision();
+    fs::load_path(&mut db, &dir, Durability::LOW);
+    assert_eq!(db.salsa_runtime().current_revision(), revision);
+
+    write(&dir.join("sub/c.txt"), "4\n5\n");
+    fs::load_path(&mut db, &dir, Durability::LOW);
+    assert_eq!(db.line_count(dir.clone()), 3);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    std::fs::remove_dir_all(dir.join("sub")).unwrap();
+    write(&dir.join("b.txt"), "3\n4\n");
+    fs::load_path(&mut db, &dir, Durability::LOW);
+    assert!(!db.file_exists(dir.join("sub/c.txt")));
+    assert_eq!(db.line_count(dir.clone()), 4);
+    assert_eq!(db.log().take(), vec!["line_count"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn watcher_applies_changes() {
+    let dir = test_dir("watch");
+    write(&dir.join("a.txt"), "1\n");
+
+    let mut db = DatabaseImpl::default();
+    let mut watcher = FileWatcher::new().unwrap();
+    watcher.watch(&mut db, &dir, Durability::LOW).unwrap();
+    assert_eq!(db.line_count(dir.clone()), 1);
+
+    write(&dir.join("b.txt"), "2\n3\n");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while db.line_count(dir.clone()) != 3 {
+        assert!(Instant::now() < deadline, "change was not applied");
+        std::thread::sleep(Duration::from_millis(10));
+        watcher.apply_changes(&mut db);
+    }
+    assert!(db.file_exists(dir.join("b.txt")));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use crate::db;
+use crate::group::{FibonacciQuery, GcDatabase};
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, Durability, SweepConfig, SweepStrategy};
+use std::time::Duration;
+
+fn config(interval: Duration) -> SweepConfig {
+    SweepConfig {
+        interval,
+        strategy: SweepStrategy::discard_outdated(),
+    }
+}
+
+#[test]
+fn disabled_by_default() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert!(!db.auto_sweep_tick());
+}
+
+#[test]
+fn sweeps_after_new_revision() {
+    let db = db::DatabaseImpl::default();
+    db.enable_auto_sweep(config(Duration::from_secs(0)));
+    db.fibonacci(3);
+
+    // Nothing changed since sweeping was enabled.
+    assert!(!db.auto_sweep_tick());
+
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    db.fibonacci(1);
+    assert!(db.auto_sweep_tick());
+    assert_keys! {
+        db,
+        FibonacciQuery => (1),
+    }
+
+    // Only once per revision.
+    assert!(!db.auto_sweep_tick());
+
+    db.disable_auto_sweep();
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert!(!db.auto_sweep_tick());
+}
+
+#[test]
+fn waits_for_interval() {
+    let db = db::DatabaseImpl::default();
+    db.enable_auto_sweep(config(Duration::from_secs(3600)));
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert!(!db.auto_sweep_tick());
+}
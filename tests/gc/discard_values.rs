@@ -2,6 +2,7 @@ use crate::db;
 use crate::group::{FibonacciQuery, GcDatabase};
 use salsa::debug::DebugQueryTable;
 use salsa::{Database, Durability, SweepStrategy};
+use std::time::Duration;
 
 #[test]
 fn sweep_default() {
@@ -54,3 +55,65 @@ fn sweep_default() {
         "fibonacci(0)",
     ]);
 }
+
+#[test]
+fn sweep_skips_pinned() {
+    let db = db::DatabaseImpl::default();
+
+    db.query(FibonacciQuery).pin(3);
+    db.fibonacci(5);
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+    db.fibonacci(5);
+
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_keys! {
+        db,
+        FibonacciQuery => (3, 5),
+    }
+
+    // Once unpinned, 3 is swept like the rest.
+    db.query(FibonacciQuery).unpin(3);
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_keys! {
+        db,
+        FibonacciQuery => (5),
+    }
+}
+
+#[test]
+fn sweep_idle_values() {
+    let db = db::DatabaseImpl::default();
+
+    db.fibonacci(5);
+    std::thread::sleep(Duration::from_millis(200));
+    db.fibonacci(3);
+
+    // Only the value of 3 was read recently; the others are discarded
+    // but their dependencies are kept.
+    let ttl = Duration::from_millis(100);
+    db.sweep_all(SweepStrategy::discard_older_than(ttl));
+    assert_keys! {
+        db,
+        FibonacciQuery => (0, 1, 2, 3, 4, 5),
+    }
+    let mut with_values: Vec<_> = db
+        .query(FibonacciQuery)
+        .entries::<Vec<_>>()
+        .into_iter()
+        .filter(|e| e.value.is_some())
+        .map(|e| e.key)
+        .collect();
+    with_values.sort();
+    assert_eq!(with_values, vec![3]);
+
+    // Discarded values are recomputed when read again.
+    db.clear_log();
+    db.fibonacci(5);
+    db.assert_log(&[
+        "fibonacci(5)",
+        "fibonacci(4)",
+        "fibonacci(2)",
+        "fibonacci(1)",
+        "fibonacci(0)",
+    ]);
+}
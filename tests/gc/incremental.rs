@@ -0,0 +1,66 @@
+use crate::db;
+use crate::group::{FibonacciQuery, GcDatabase};
+use crate::interned::{InternDatabase, InternStrQuery};
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, Durability, SweepBudget, SweepStrategy};
+use std::time::Duration;
+
+#[test]
+fn sweep_in_steps() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(9);
+    db.intern_str("foo");
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+
+    // There are 11 slots in all (10 for `fibonacci`, one interned
+    // value), so it takes three calls to sweep them 4 at a time.
+    let strategy = SweepStrategy::discard_outdated();
+    assert!(!db.sweep_incremental(strategy, SweepBudget::Slots(4)));
+    let entries: Vec<_> = db.query(FibonacciQuery).entries();
+    assert_eq!(entries.len(), 6);
+
+    assert!(!db.sweep_incremental(strategy, SweepBudget::Slots(4)));
+    let entries: Vec<_> = db.query(FibonacciQuery).entries();
+    assert_eq!(entries.len(), 2);
+
+    assert!(db.sweep_incremental(strategy, SweepBudget::Slots(4)));
+    assert_keys! {
+        db,
+        FibonacciQuery => (),
+        InternStrQuery => (),
+    }
+}
+
+#[test]
+fn start_new_pass() {
+    let db = db::DatabaseImpl::default();
+    let strategy = SweepStrategy::discard_outdated();
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert!(db.sweep_incremental(strategy, SweepBudget::Slots(100)));
+
+    // The cursor was reset, so the next pass sweeps everything again.
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert!(!db.sweep_incremental(strategy, SweepBudget::Slots(3)));
+    assert!(db.sweep_incremental(strategy, SweepBudget::Slots(3)));
+    assert_keys! {
+        db,
+        FibonacciQuery => (),
+    }
+}
+
+#[test]
+fn sweep_with_time_budget() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(9);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+
+    let strategy = SweepStrategy::discard_outdated();
+    assert!(!db.sweep_incremental(strategy, SweepBudget::Time(Duration::from_secs(0))));
+    assert!(db.sweep_incremental(strategy, SweepBudget::Time(Duration::from_secs(60))));
+    assert_keys! {
+        db,
+        FibonacciQuery => (),
+    }
+}
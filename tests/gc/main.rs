@@ -2,18 +2,33 @@ macro_rules! assert_keys {
     ($db:expr, $($query:expr => ($($key:expr),*),)*) => {
         $(
             let entries = $db.query($query).entries::<Vec<_>>();
-            let mut keys = entries.into_iter().map(|e| e.key).collect::<Vec<_>>();
-            keys.sort();
-            assert_eq!(keys, vec![$($key),*], "query {:?} had wrong keys", $query);
+            let keys = entries.into_iter().map(|e| e.key).collect::<Vec<_>>();
+            crate::check_keys($query, keys, vec![$($key),*]);
         )*
     };
 }
 
+/// Compares the keys of `query`, sorted, to `expected`. (A function
+/// rather than part of `assert_keys`, so that the type of the keys is
+/// inferred even when `expected` is empty.)
+fn check_keys<K: std::fmt::Debug + Ord>(
+    query: impl std::fmt::Debug,
+    mut keys: Vec<K>,
+    expected: Vec<K>,
+) {
+    keys.sort();
+    assert_eq!(keys, expected, "query {:?} had wrong keys", query);
+}
+
+mod auto_sweep;
 mod db;
 mod derived_tests;
 mod discard_values;
 mod group;
+mod incremental;
 mod interned;
 mod log;
 mod shallow_constant_tests;
+mod sweep_keys;
+mod sweep_policy;
 mod volatile_tests;
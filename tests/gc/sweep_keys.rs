@@ -0,0 +1,54 @@
+use crate::db;
+use crate::group::{FibonacciQuery, GcDatabase};
+use crate::interned::{InternDatabase, InternStrQuery};
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, Durability, SweepStrategy};
+
+#[test]
+fn sweep_only_given_keys() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(5);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+
+    // All keys are outdated, but only 1, 3 and 4 are swept (6 has no
+    // memo and is ignored).
+    db.query(FibonacciQuery)
+        .sweep_keys(vec![1, 3, 4, 6], SweepStrategy::discard_outdated());
+    assert_keys! {
+        db,
+        FibonacciQuery => (0, 2, 5),
+    }
+}
+
+#[test]
+fn sweep_keys_keeps_current() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    db.fibonacci(1);
+
+    db.query(FibonacciQuery)
+        .sweep_keys(0..4, SweepStrategy::discard_outdated());
+    assert_keys! {
+        db,
+        FibonacciQuery => (1),
+    }
+}
+
+#[test]
+fn sweep_interned_keys() {
+    let db = db::DatabaseImpl::default();
+    db.intern_str("foo");
+    db.intern_str("bar");
+    db.intern_str("baz");
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    db.intern_str("baz");
+
+    // "baz" was used in the current revision, so it is kept.
+    db.query(InternStrQuery)
+        .sweep_keys(vec!["foo", "baz"], SweepStrategy::discard_outdated());
+    assert_keys! {
+        db,
+        InternStrQuery => ("bar", "baz"),
+    }
+}
@@ -0,0 +1,92 @@
+use crate::db;
+use crate::group::{FibonacciQuery, GcDatabase};
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, Discard, Durability, SweepInfo, SweepPolicy, SweepStrategy};
+use std::cell::RefCell;
+use std::fmt::Debug;
+
+/// Keeps the memos of the given `fibonacci` keys and discards all
+/// others, recording the queries it was asked about.
+struct KeepKeys {
+    keys: Vec<&'static str>,
+    asked: RefCell<Vec<String>>,
+}
+
+impl KeepKeys {
+    fn new(keys: Vec<&'static str>) -> Self {
+        KeepKeys {
+            keys,
+            asked: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl SweepPolicy for KeepKeys {
+    fn should_discard(&self, key: &dyn Debug, info: &SweepInfo) -> Discard {
+        let key = format!("{:?}", key);
+        self.asked
+            .borrow_mut()
+            .push(format!("{}({})", info.query_name, key));
+        if self.keys.contains(&&key[..]) {
+            Discard::Keep
+        } else {
+            Discard::Anyway
+        }
+    }
+}
+
+#[test]
+fn policy_keeps_and_discards() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+    db.fibonacci(3);
+
+    // Everything was verified in the current revision, so the
+    // strategy alone would keep it all; the policy keeps only 0 and 3.
+    let policy = KeepKeys::new(vec!["0", "3"]);
+    db.sweep_all_with_policy(SweepStrategy::discard_outdated(), &policy);
+    assert_keys! {
+        db,
+        FibonacciQuery => (0, 3),
+    }
+
+    let mut asked = policy.asked.take();
+    asked.sort();
+    assert_eq!(
+        asked,
+        vec![
+            "fibonacci(0)",
+            "fibonacci(1)",
+            "fibonacci(2)",
+            "fibonacci(3)",
+        ]
+    );
+}
+
+#[test]
+fn policy_vetoes_strategy() {
+    let db = db::DatabaseImpl::default();
+    db.fibonacci(3);
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    db.fibonacci(1);
+
+    // The strategy discards all outdated memos, except those the
+    // policy keeps.
+    struct KeepTwo;
+    impl SweepPolicy for KeepTwo {
+        fn should_discard(&self, key: &dyn Debug, _info: &SweepInfo) -> Discard {
+            if format!("{:?}", key) == "2" {
+                Discard::Keep
+            } else {
+                Discard::FollowStrategy
+            }
+        }
+    }
+    db.query(FibonacciQuery)
+        .sweep_with_policy(SweepStrategy::discard_outdated(), &KeepTwo);
+    assert_keys! {
+        db,
+        FibonacciQuery => (1, 2),
+    }
+}
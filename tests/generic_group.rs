@@ -0,0 +1,102 @@
+//! Test query groups with type parameters, of which a database can
+//! include several instances.
+
+use salsa::Database;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+trait Arch: 'static {
+    const NAME: &'static str;
+    const POINTER_SIZE: usize;
+    type Register: Clone + Eq + Hash + Debug + Send + Sync;
+}
+
+/// Implements none of the traits that keys and values need.
+struct X86;
+
+impl Arch for X86 {
+    const NAME: &'static str = "x86";
+    const POINTER_SIZE: usize = 4;
+    type Register = &'static str;
+}
+
+struct Arm64;
+
+impl Arch for Arm64 {
+    const NAME: &'static str = "arm64";
+    const POINTER_SIZE: usize = 8;
+    type Register = u8;
+}
+
+#[salsa::query_group(LayoutStorage)]
+trait Layout<A: Arch>: salsa::Database {
+    #[salsa::input]
+    fn pointer_fields(&self, name: String) -> usize;
+
+    fn struct_size(&self, name: String) -> usize;
+
+    #[salsa::input]
+    fn register_alias(&self, register: A::Register) -> A::Register;
+
+    fn describe_register(&self, register: A::Register) -> String;
+}
+
+fn struct_size<A: Arch>(db: &impl Layout<A>, name: String) -> usize {
+    db.pointer_fields(name) * A::POINTER_SIZE
+}
+
+fn describe_register<A: Arch>(db: &impl Layout<A>, register: A::Register) -> String {
+    format!("{}:{:?}", A::NAME, db.register_alias(register))
+}
+
+#[salsa::database(LayoutStorage<X86>, LayoutStorage<Arm64>)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn instances_are_separate() {
+    let mut db = DatabaseImpl::default();
+    Layout::<X86>::set_pointer_fields(&mut db, "Vec".to_string(), 3);
+    Layout::<Arm64>::set_pointer_fields(&mut db, "Vec".to_string(), 3);
+    assert_eq!(Layout::<X86>::struct_size(&db, "Vec".to_string()), 12);
+    assert_eq!(Layout::<Arm64>::struct_size(&db, "Vec".to_string()), 24);
+
+    Layout::<X86>::set_register_alias(&mut db, "sp", "esp");
+    Layout::<Arm64>::set_register_alias(&mut db, 31, 29);
+    assert_eq!(Layout::<X86>::describe_register(&db, "sp"), "x86:\"esp\"");
+    assert_eq!(Layout::<Arm64>::describe_register(&db, 31), "arm64:29");
+
+    // Changing the input of one instance does not affect the other.
+    Layout::<X86>::set_pointer_fields(&mut db, "Vec".to_string(), 2);
+    assert_eq!(Layout::<X86>::struct_size(&db, "Vec".to_string()), 8);
+    assert_eq!(Layout::<Arm64>::struct_size(&db, "Vec".to_string()), 24);
+}
+
+#[test]
+fn query_tables_and_keys() {
+    let mut db = DatabaseImpl::default();
+    Layout::<Arm64>::set_pointer_fields(&mut db, "Box".to_string(), 1);
+    assert_eq!(
+        db.query(StructSizeQuery::<Arm64>::default())
+            .get("Box".to_string()),
+        8
+    );
+    assert_eq!(
+        db.query(StructSizeQuery::<Arm64>::default())
+            .database_key("Box".to_string())
+            .to_string(),
+        "struct_size(\"Box\")"
+    );
+    assert_eq!(
+        format!("{:?}", StructSizeQuery::<Arm64>::default()),
+        "StructSizeQuery"
+    );
+}
@@ -0,0 +1,210 @@
+//! Test `QueryTable::get_ref`, which borrows memoized values instead
+//! of cloning them.
+
+use salsa::{Database, SweepStrategy};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts how often it gets cloned.
+#[derive(Debug, PartialEq, Eq)]
+struct Counted(u32);
+
+static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+impl Clone for Counted {
+    fn clone(&self) -> Self {
+        CLONES.fetch_add(1, Ordering::SeqCst);
+        Counted(self.0)
+    }
+}
+
+fn clones() -> usize {
+    CLONES.load(Ordering::SeqCst)
+}
+
+#[salsa::query_group(GetRefStorage)]
+trait GetRefDatabase: salsa::Database + Helpers {
+    #[salsa::input]
+    fn input(&self) -> Counted;
+
+    fn double(&self) -> Counted;
+
+    /// Reads `input` through `get_ref`.
+    fn input_plus_one(&self) -> u32;
+
+    /// Does not involve `Counted`, so that its tests can run alongside
+    /// the others.
+    fn constant(&self) -> u32;
+
+    /// Likewise.
+    fn keyed(&self, key: u32) -> u32;
+}
+
+/// Query functions only see `impl GetRefDatabase`, which cannot name
+/// the query tables, so `get_ref` is called through the database type.
+trait Helpers {
+    fn count(&self);
+
+    fn input_ref_value(&self) -> u32;
+}
+
+fn double(db: &impl GetRefDatabase) -> Counted {
+    db.count();
+    Counted(db.input().0 * 2)
+}
+
+fn input_plus_one(db: &impl GetRefDatabase) -> u32 {
+    db.count();
+    db.input_ref_value() + 1
+}
+
+fn constant(db: &impl GetRefDatabase) -> u32 {
+    db.count();
+    7
+}
+
+fn keyed(db: &impl GetRefDatabase, key: u32) -> u32 {
+    db.count();
+    key
+}
+
+#[salsa::database(GetRefStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Cell<u32>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl Helpers for DatabaseImpl {
+    fn count(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+
+    fn input_ref_value(&self) -> u32 {
+        self.query(InputQuery).get_ref(()).0
+    }
+}
+
+// The tests share the clone counter, so they run as one test.
+#[test]
+fn get_ref() {
+    borrow_memoized_value();
+    borrow_input();
+    track_reads_through_get_ref();
+    set_after_dropping_guard();
+}
+
+fn borrow_memoized_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(Counted(2));
+
+    let value = db.query(DoubleQuery).get_ref(());
+    assert_eq!(*value, Counted(4));
+    drop(value);
+
+    // Once memoized, the value is borrowed from the memo.
+    let before = clones();
+    let value = db.query(DoubleQuery).get_ref(());
+    assert_eq!(*value, Counted(4));
+    assert_eq!(format!("{:?}", value), "Counted(4)");
+    drop(value);
+    assert_eq!(clones(), before);
+    assert_eq!(db.executions.get(), 1);
+
+    // `get` still clones.
+    assert_eq!(db.double(), Counted(4));
+    assert_eq!(clones(), before + 1);
+}
+
+fn borrow_input() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(Counted(2));
+
+    let before = clones();
+    assert_eq!(*db.query(InputQuery).get_ref(()), Counted(2));
+    assert_eq!(clones(), before);
+}
+
+fn track_reads_through_get_ref() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(Counted(2));
+    assert_eq!(db.input_plus_one(), 3);
+    assert_eq!(db.input_plus_one(), 3);
+    assert_eq!(db.executions.get(), 1);
+
+    db.set_input(Counted(5));
+    assert_eq!(db.input_plus_one(), 6);
+    assert_eq!(db.executions.get(), 2);
+}
+
+fn set_after_dropping_guard() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(Counted(2));
+
+    // The guard borrows `db`, so it must be dropped before `set` (the
+    // compiler enforces this).
+    let value = db.query(DoubleQuery).get_ref(());
+    assert_eq!(*value, Counted(4));
+    drop(value);
+
+    db.set_input(Counted(3));
+    assert_eq!(*db.query(DoubleQuery).get_ref(()), Counted(6));
+    assert_eq!(db.executions.get(), 2);
+}
+
+#[test]
+fn sweep_after_dropping_guard() {
+    let db = DatabaseImpl::default();
+    let value = db.query(ConstantQuery).get_ref(());
+    assert_eq!(*value, 7);
+    drop(value);
+
+    db.query(ConstantQuery).sweep(
+        SweepStrategy::default()
+            .discard_values()
+            .sweep_all_revisions(),
+    );
+    assert_eq!(*db.query(ConstantQuery).get_ref(()), 7);
+    assert_eq!(db.executions.get(), 2);
+}
+
+/// Sweeping the value would deadlock while the guard holds a lock on
+/// it; the value is skipped instead.
+#[test]
+fn sweep_while_borrowed() {
+    let db = DatabaseImpl::default();
+    db.constant();
+    let value = db.query(ConstantQuery).get_ref(());
+    db.query(ConstantQuery).sweep(
+        SweepStrategy::default()
+            .discard_values()
+            .sweep_all_revisions(),
+    );
+    assert_eq!(*value, 7);
+    drop(value);
+
+    assert_eq!(db.query(ConstantQuery).peek(()), Some(7));
+    assert_eq!(db.executions.get(), 1);
+}
+
+/// Likewise, evicting the value (here, to make room for another key in
+/// the LRU list) skips it.
+#[test]
+fn evict_while_borrowed() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(KeyedQuery).set_lru_capacity(1);
+    db.keyed(1);
+    let value = db.query(KeyedQuery).get_ref(1);
+    assert_eq!(db.keyed(2), 2);
+    assert_eq!(*value, 1);
+    drop(value);
+
+    assert_eq!(db.query(KeyedQuery).peek(1), Some(1));
+    assert_eq!(db.executions.get(), 2);
+}
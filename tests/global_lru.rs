@@ -0,0 +1,101 @@
+//! Test that a global LRU budget limits the values of all query
+//! tables together.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use salsa::Database as _;
+
+/// A value that counts (in `LIVE`) how many of its instances are
+/// alive.
+#[derive(Debug, PartialEq, Eq)]
+struct Counted(u32);
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+impl Counted {
+    fn new(id: u32) -> Counted {
+        LIVE.fetch_add(1, Ordering::SeqCst);
+        Counted(id)
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        LIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[salsa::query_group(QueryGroupStorage)]
+trait QueryGroup: salsa::Database {
+    fn a(&self, x: u32) -> Arc<Counted>;
+    fn b(&self, x: u32) -> Arc<Counted>;
+
+    #[salsa::lru_cost(expensive_cost)]
+    fn expensive(&self, x: u32) -> Arc<Counted>;
+}
+
+fn a(_db: &impl QueryGroup, x: u32) -> Arc<Counted> {
+    Arc::new(Counted::new(x))
+}
+
+fn b(_db: &impl QueryGroup, x: u32) -> Arc<Counted> {
+    Arc::new(Counted::new(x))
+}
+
+fn expensive(_db: &impl QueryGroup, x: u32) -> Arc<Counted> {
+    Arc::new(Counted::new(x))
+}
+
+fn expensive_cost(_value: &Arc<Counted>) -> usize {
+    10
+}
+
+#[salsa::database(QueryGroupStorage)]
+#[derive(Default)]
+struct Database {
+    runtime: salsa::Runtime<Database>,
+}
+
+impl salsa::Database for Database {
+    fn salsa_runtime(&self) -> &salsa::Runtime<Database> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn global_budget() {
+    let db = Database::default();
+    db.set_global_lru_budget(50);
+
+    for i in 0..100u32 {
+        assert_eq!(db.a(i).0, i);
+        assert_eq!(db.b(i).0, i);
+    }
+    assert_eq!(LIVE.load(Ordering::SeqCst), 50);
+
+    // Values with a cost function count with their cost.
+    for i in 0..100u32 {
+        assert_eq!(db.expensive(i).0, i);
+    }
+    assert!(LIVE.load(Ordering::SeqCst) <= 5);
+
+    // Cheap values displace expensive ones as needed.
+    for i in 0..25u32 {
+        assert_eq!(db.a(i).0, i);
+        assert_eq!(db.b(i).0, i);
+    }
+    let live = LIVE.load(Ordering::SeqCst);
+    assert!(live > 5 && live <= 50);
+
+    // Special case: setting the budget to zero disables the limit.
+    db.set_global_lru_budget(0);
+    for i in 0..100u32 {
+        assert_eq!(db.a(i).0, i);
+    }
+    assert!(LIVE.load(Ordering::SeqCst) >= 100);
+
+    drop(db);
+    assert_eq!(LIVE.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,40 @@
+//! Test that the `grow-stack` feature lets deeply nested queries run
+//! on a thread with a small stack.
+#![cfg(feature = "grow-stack")]
+
+#[salsa::query_group(GrowStackStorage)]
+trait GrowStackDatabase: salsa::Database {
+    fn depth(&self, n: u32) -> u32;
+}
+
+fn depth(db: &impl GrowStackDatabase, n: u32) -> u32 {
+    if n == 0 {
+        0
+    } else {
+        db.depth(n - 1) + 1
+    }
+}
+
+#[salsa::database(GrowStackStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn deep_chain_on_small_stack() {
+    let thread = std::thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(|| {
+            let db = DatabaseImpl::default();
+            db.depth(10_000)
+        })
+        .unwrap();
+    assert_eq!(thread.join().unwrap(), 10_000);
+}
@@ -0,0 +1,53 @@
+//! Test that changing inputs from inside a query is reported right
+//! away, naming the query.
+
+use salsa::Durability;
+
+#[salsa::query_group(WriteStorage)]
+trait WriteDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    fn writes(&self) -> u32;
+
+    fn reads_writes(&self) -> u32;
+}
+
+fn writes(db: &impl WriteDatabase) -> u32 {
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+    db.input()
+}
+
+fn reads_writes(db: &impl WriteDatabase) -> u32 {
+    db.writes()
+}
+
+#[salsa::database(WriteStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+#[should_panic(expected = "illegal write: inputs cannot be changed while executing")]
+fn write_in_query() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.writes();
+}
+
+#[test]
+fn write_in_query_names_innermost_query() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| db.reads_writes()));
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("writes(())"), "{}", message);
+    assert!(!message.contains("reads_writes"), "{}", message);
+}
@@ -146,3 +146,90 @@ fn constant_to_non_constant() {
     db.set_input('a', 22);
     assert_eq!(db.add3('a', 'b', 'c'), 77);
 }
+
+#[test]
+fn promote_durability() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_input('a', 22);
+    db.set_input('b', 44);
+    assert_eq!(db.add('a', 'b'), 66);
+    db.assert_log(&["add(a, b)"]);
+
+    // Promoting does not invalidate anything...
+    db.query_mut(InputQuery)
+        .set_durability('a', Durability::HIGH);
+    db.query_mut(InputQuery)
+        .set_durability('b', Durability::HIGH);
+    assert_eq!(db.add('a', 'b'), 66);
+    db.assert_log(&[]);
+    assert_eq!(Durability::HIGH, db.query(InputQuery).durability('a'));
+
+    // ...but values computed afterwards get the higher durability.
+    assert_eq!(db.add('b', 'a'), 66);
+    db.assert_log(&["add(b, a)"]);
+    assert_eq!(Durability::HIGH, db.query(AddQuery).durability(('b', 'a')));
+
+    // Changing the promoted input still invalidates.
+    db.set_input_with_durability('a', 33, Durability::HIGH);
+    assert_eq!(db.add('b', 'a'), 77);
+    db.assert_log(&["add(b, a)"]);
+}
+
+#[test]
+fn demote_durability() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_input_with_durability('a', 22, Durability::HIGH);
+    db.set_input_with_durability('b', 44, Durability::HIGH);
+    assert_eq!(db.add('a', 'b'), 66);
+    db.assert_log(&["add(a, b)"]);
+
+    // Demoting invalidates, so that `add(a, b)` is no longer durable.
+    db.query_mut(InputQuery)
+        .set_durability('a', Durability::LOW);
+    assert_eq!(db.add('a', 'b'), 66);
+    db.assert_log(&["add(a, b)"]);
+    assert_eq!(Durability::LOW, db.query(AddQuery).durability(('a', 'b')));
+
+    db.set_input('a', 33);
+    assert_eq!(db.add('a', 'b'), 77);
+    db.assert_log(&["add(a, b)"]);
+}
+
+#[test]
+fn user_durability_levels() {
+    let db = &mut TestContextImpl::default();
+
+    assert_eq!(Durability::user(0), Durability::LOW);
+    assert_eq!(Durability::user(1), Durability::MEDIUM);
+    assert_eq!(
+        Durability::user(Durability::LEVELS as u8 - 1),
+        Durability::HIGH
+    );
+
+    db.set_input_with_durability('a', 22, Durability::user(3));
+    db.set_input_with_durability('b', 44, Durability::user(5));
+    assert_eq!(db.add('a', 'b'), 66);
+    assert_eq!(
+        Durability::user(3),
+        db.query(AddQuery).durability(('a', 'b'))
+    );
+    db.assert_log(&["add(a, b)"]);
+
+    // Changes to less durable inputs do not require re-execution...
+    db.set_input_with_durability('c', 11, Durability::user(2));
+    assert_eq!(db.add('a', 'b'), 66);
+    db.assert_log(&[]);
+
+    // ...but changes to the inputs themselves do.
+    db.set_input_with_durability('b', 55, Durability::user(5));
+    assert_eq!(db.add('a', 'b'), 77);
+    db.assert_log(&["add(a, b)"]);
+}
+
+#[test]
+#[should_panic(expected = "durability level out of range")]
+fn user_durability_out_of_range() {
+    Durability::user(Durability::LEVELS as u8);
+}
@@ -5,5 +5,6 @@ mod log;
 mod memoized_dep_inputs;
 mod memoized_inputs;
 mod memoized_volatile;
+mod transaction;
 
 fn main() {}
@@ -0,0 +1,63 @@
+use crate::implementation::TestContextImpl;
+use crate::memoized_inputs::MemoizedInputsContext;
+use salsa::Database;
+
+#[test]
+fn batch_writes() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_input1(0);
+    db.set_input2(0);
+    assert_eq!(db.max(), 0);
+    db.assert_log(&["Max invoked"]);
+
+    db.transaction(|db| {
+        db.set_input1(22);
+        db.set_input2(44);
+    });
+    assert_eq!(db.max(), 44);
+    db.assert_log(&["Max invoked"]);
+
+    // Writes after the transaction create new revisions as usual.
+    db.set_input2(11);
+    assert_eq!(db.max(), 22);
+    db.assert_log(&["Max invoked"]);
+}
+
+#[test]
+fn read_within_transaction() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_input1(0);
+    db.set_input2(0);
+
+    let max = db.transaction(|db| {
+        db.set_input1(22);
+        assert_eq!(db.max(), 22);
+        db.assert_log(&["Max invoked"]);
+
+        // The value memoized above must not be reused after this
+        // write, even though it happens in the same transaction.
+        db.set_input2(44);
+        db.max()
+    });
+    assert_eq!(max, 44);
+    db.assert_log(&["Max invoked"]);
+}
+
+#[test]
+fn nested_transaction() {
+    let db = &mut TestContextImpl::default();
+
+    db.set_input1(0);
+    db.set_input2(0);
+    assert_eq!(db.max(), 0);
+    db.assert_log(&["Max invoked"]);
+
+    db.transaction(|db| {
+        db.transaction(|db| db.set_input1(22));
+        db.set_input2(44);
+    });
+    assert_eq!(db.max(), 44);
+    db.assert_log(&["Max invoked"]);
+}
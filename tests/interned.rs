@@ -14,6 +14,7 @@ impl salsa::Database for Database {
     }
 }
 
+#[cfg(not(feature = "single-threaded"))]
 impl salsa::ParallelDatabase for Database {
     fn snapshot(&self) -> salsa::Snapshot<Self> {
         salsa::Snapshot::new(Database {
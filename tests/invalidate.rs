@@ -0,0 +1,117 @@
+//! Test `QueryTableMut::invalidate`, which invalidates the memoized
+//! values of derived queries one key at a time.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{Database, InvalidationReason};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[salsa::query_group(InvalidateStorage)]
+trait InvalidateDatabase: salsa::Database + HasRemote + HasLog {
+    /// Reads a value that salsa does not know about.
+    fn fetch(&self, key: u32) -> String;
+
+    fn shout(&self, key: u32) -> String;
+}
+
+trait HasRemote {
+    fn remote(&self, key: u32) -> String;
+}
+
+fn fetch(db: &impl InvalidateDatabase, key: u32) -> String {
+    db.log().add(format!("fetch({})", key));
+    db.remote(key)
+}
+
+fn shout(db: &impl InvalidateDatabase, key: u32) -> String {
+    db.log().add(format!("shout({})", key));
+    db.fetch(key).to_uppercase()
+}
+
+#[salsa::database(InvalidateStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    remote: RefCell<HashMap<u32, String>>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasRemote for DatabaseImpl {
+    fn remote(&self, key: u32) -> String {
+        self.remote.borrow()[&key].clone()
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+impl DatabaseImpl {
+    fn set_remote(&self, key: u32, value: &str) {
+        self.remote.borrow_mut().insert(key, value.to_string());
+    }
+}
+
+#[test]
+fn invalidate_stale_key() {
+    let mut db = DatabaseImpl::default();
+    db.set_remote(1, "one");
+    db.set_remote(2, "two");
+    assert_eq!(db.shout(1), "ONE");
+    assert_eq!(db.shout(2), "TWO");
+    db.log().take();
+
+    db.set_remote(1, "uno");
+    db.set_remote(2, "dos");
+    assert_eq!(db.shout(1), "ONE");
+
+    db.query_mut(FetchQuery).invalidate(1);
+    assert_eq!(db.shout(1), "UNO");
+    assert_eq!(db.shout(2), "TWO");
+    assert_eq!(db.log().take(), vec!["fetch(1)", "shout(1)"]);
+}
+
+#[test]
+fn unchanged_value_backdated() {
+    let mut db = DatabaseImpl::default();
+    db.set_remote(1, "one");
+    assert_eq!(db.shout(1), "ONE");
+    db.log().take();
+
+    db.query_mut(FetchQuery).invalidate(1);
+    assert_eq!(db.shout(1), "ONE");
+    assert_eq!(db.log().take(), vec!["fetch(1)"]);
+}
+
+#[test]
+fn invalidation_reason() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+    db.set_remote(1, "one");
+    assert_eq!(db.fetch(1), "one");
+
+    db.query_mut(FetchQuery).invalidate(1);
+    assert_eq!(db.fetch(1), "one");
+    assert_eq!(
+        db.query(FetchQuery).last_invalidation_reason(1),
+        Some(InvalidationReason::Invalidated)
+    );
+}
+
+#[test]
+fn invalidate_uncomputed_key() {
+    let mut db = DatabaseImpl::default();
+    let revision = db.salsa_runtime().current_revision();
+    db.query_mut(FetchQuery).invalidate(1);
+    assert_eq!(db.salsa_runtime().current_revision(), revision);
+}
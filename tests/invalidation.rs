@@ -0,0 +1,87 @@
+//! Test the invalidation trace, which records why queries had to be
+//! re-executed.
+
+use salsa::{Database, InvalidationReason};
+
+#[salsa::query_group(InvalidationStorage)]
+trait InvalidationDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn sum(&self, key: u32) -> u32;
+
+    fn untracked(&self) -> u32;
+}
+
+fn sum(db: &impl InvalidationDatabase, key: u32) -> u32 {
+    db.input(key) + db.input(key + 1)
+}
+
+fn untracked(db: &impl InvalidationDatabase) -> u32 {
+    db.salsa_runtime().report_untracked_read();
+    22
+}
+
+#[salsa::database(InvalidationStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    db.sum(1);
+    db.set_input(2, 3);
+    db.sum(1);
+
+    assert_eq!(db.query(SumQuery).last_invalidation_reason(1), None);
+}
+
+#[test]
+fn input_changed() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    assert_eq!(db.sum(1), 3);
+    assert_eq!(
+        db.query(SumQuery).last_invalidation_reason(1),
+        Some(InvalidationReason::NotComputed)
+    );
+
+    db.set_input(2, 3);
+    assert_eq!(db.sum(1), 4);
+    match db.query(SumQuery).last_invalidation_reason(1) {
+        Some(InvalidationReason::InputChanged(input)) => {
+            assert!(format!("{:?}", input).contains("input(2)"))
+        }
+        reason => panic!("unexpected reason: {:?}", reason),
+    }
+
+    db.salsa_runtime().set_invalidation_tracing(false);
+    assert_eq!(db.query(SumQuery).last_invalidation_reason(1), None);
+}
+
+#[test]
+fn untracked_read() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+
+    db.untracked();
+    db.set_input(1, 1);
+    db.untracked();
+    assert_eq!(
+        db.query(UntrackedQuery).last_invalidation_reason(()),
+        Some(InvalidationReason::UntrackedRead)
+    );
+}
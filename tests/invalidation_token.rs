@@ -0,0 +1,142 @@
+//! Test `Runtime::report_read_of` and `Database::invalidate_token`.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{Database, InvalidationReason, InvalidationToken};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[salsa::query_group(TokenStorage)]
+trait TokenDatabase: salsa::Database + HasFiles + HasLog {
+    #[salsa::input]
+    fn suffix(&self) -> String;
+
+    /// Reads a file, which salsa does not know about.
+    fn file(&self, name: String) -> String;
+
+    fn shout(&self, name: String) -> String;
+
+    fn decorated(&self, name: String) -> String;
+}
+
+trait HasFiles {
+    fn read_file(&self, name: &str) -> String;
+}
+
+fn file_system() -> InvalidationToken {
+    InvalidationToken::new("file system")
+}
+
+fn file(db: &impl TokenDatabase, name: String) -> String {
+    db.log().add(format!("file({})", name));
+    db.salsa_runtime().report_read_of(&file_system());
+    db.read_file(&name)
+}
+
+fn shout(db: &impl TokenDatabase, name: String) -> String {
+    db.log().add(format!("shout({})", name));
+    db.file(name).to_uppercase()
+}
+
+fn decorated(db: &impl TokenDatabase, name: String) -> String {
+    db.log().add(format!("decorated({})", name));
+    format!("{}{}", db.shout(name), db.suffix())
+}
+
+#[salsa::database(TokenStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    files: RefCell<HashMap<String, String>>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasFiles for DatabaseImpl {
+    fn read_file(&self, name: &str) -> String {
+        self.files.borrow()[name].clone()
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+impl DatabaseImpl {
+    fn write_file(&self, name: &str, contents: &str) {
+        self.files
+            .borrow_mut()
+            .insert(name.to_string(), contents.to_string());
+    }
+}
+
+#[test]
+fn invalidated_token_reexecutes_readers() {
+    let mut db = DatabaseImpl::default();
+    db.set_suffix("!".to_string());
+    db.write_file("a", "hello");
+    assert_eq!(db.decorated("a".to_string()), "HELLO!");
+    assert_eq!(db.log().take(), vec!["decorated(a)", "shout(a)", "file(a)"]);
+
+    db.write_file("a", "bye");
+    db.invalidate_token(&file_system());
+    assert_eq!(db.decorated("a".to_string()), "BYE!");
+    assert_eq!(db.log().take(), vec!["file(a)", "shout(a)", "decorated(a)"]);
+}
+
+#[test]
+fn other_changes_keep_readers() {
+    let mut db = DatabaseImpl::default();
+    db.set_suffix("!".to_string());
+    db.write_file("a", "hello");
+    assert_eq!(db.decorated("a".to_string()), "HELLO!");
+    db.log().take();
+
+    // Unlike an untracked read, reading the token does not make the
+    // query re-execute in every new revision.
+    db.set_suffix("?".to_string());
+    assert_eq!(db.decorated("a".to_string()), "HELLO?");
+    assert_eq!(db.log().take(), vec!["decorated(a)"]);
+
+    // Other tokens are unrelated.
+    db.invalidate_token(&InvalidationToken::new("network"));
+    assert_eq!(db.decorated("a".to_string()), "HELLO?");
+    assert_eq!(db.log().take(), Vec::<String>::new());
+}
+
+#[test]
+fn unchanged_contents_are_backdated() {
+    let mut db = DatabaseImpl::default();
+    db.set_suffix("!".to_string());
+    db.write_file("a", "hello");
+    assert_eq!(db.decorated("a".to_string()), "HELLO!");
+    db.log().take();
+
+    db.invalidate_token(&file_system());
+    assert_eq!(db.decorated("a".to_string()), "HELLO!");
+    assert_eq!(db.log().take(), vec!["file(a)"]);
+}
+
+#[test]
+fn invalidation_reason() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+    db.write_file("a", "hello");
+    assert_eq!(db.shout("a".to_string()), "HELLO");
+
+    db.invalidate_token(&file_system());
+    assert_eq!(db.shout("a".to_string()), "HELLO");
+    let database_key = db.query(FileQuery).database_key("a".to_string());
+    assert_eq!(
+        db.last_invalidation_reason(&database_key),
+        Some(InvalidationReason::TokenInvalidated(file_system()))
+    );
+}
@@ -0,0 +1,155 @@
+//! Test the journal of input writes: `Database::undo`, `Database::redo`
+//! and `Database::dump_journal`.
+
+use salsa::{Database, Durability};
+
+#[salsa::query_group(JournalStorage)]
+trait JournalDatabase: salsa::Database {
+    #[salsa::input]
+    fn text(&self, name: &'static str) -> String;
+
+    fn length(&self, name: &'static str) -> usize;
+}
+
+fn length(db: &impl JournalDatabase, name: &'static str) -> usize {
+    db.text(name).len()
+}
+
+#[salsa::database(JournalStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn journaled_db(capacity: usize) -> DatabaseImpl {
+    let db = DatabaseImpl::default();
+    db.salsa_runtime().set_journal_capacity(capacity);
+    db
+}
+
+fn text(db: &DatabaseImpl, name: &'static str) -> Option<String> {
+    db.query(TextQuery).get_maybe(name)
+}
+
+#[test]
+fn undo_and_redo() {
+    let mut db = journaled_db(10);
+    db.set_text("a", "x".to_string());
+    db.set_text("a", "xyz".to_string());
+    assert_eq!(db.length("a"), 3);
+
+    assert!(db.undo());
+    assert_eq!(db.length("a"), 1);
+    assert!(db.undo());
+    assert_eq!(text(&db, "a"), None);
+    assert!(!db.undo());
+
+    assert!(db.redo());
+    assert_eq!(db.length("a"), 1);
+    assert!(db.redo());
+    assert_eq!(db.length("a"), 3);
+    assert!(!db.redo());
+}
+
+#[test]
+fn transaction_is_one_step() {
+    let mut db = journaled_db(10);
+    db.set_text("a", "x".to_string());
+    db.transaction(|db| {
+        db.set_text("a", "y".to_string());
+        db.set_text("b", "z".to_string());
+        db.set_text("a", "yy".to_string());
+    });
+
+    db.salsa_runtime().set_revision_log_capacity(10);
+    assert!(db.undo());
+    assert_eq!(text(&db, "a"), Some("x".to_string()));
+    assert_eq!(text(&db, "b"), None);
+    let log = db.salsa_runtime().revision_log();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].changes.len(), 3);
+}
+
+#[test]
+fn undo_restores_durability() {
+    let mut db = journaled_db(10);
+    db.query_mut(TextQuery)
+        .set_with_durability("a", "x".to_string(), Durability::HIGH);
+    db.query_mut(TextQuery).set_durability("a", Durability::LOW);
+    db.query_mut(TextQuery).remove("a");
+
+    db.salsa_runtime().set_revision_log_capacity(10);
+    assert!(db.undo());
+    assert!(db.undo());
+    assert_eq!(text(&db, "a"), Some("x".to_string()));
+    let log = db.salsa_runtime().revision_log();
+    assert_eq!(
+        log[1].changes,
+        vec![salsa::InputChange::Set {
+            database_key: db.query(TextQuery).database_key("a"),
+            durability: Durability::HIGH,
+        }]
+    );
+}
+
+#[test]
+fn new_write_discards_redo() {
+    let mut db = journaled_db(10);
+    db.set_text("a", "x".to_string());
+    db.set_text("a", "y".to_string());
+    assert!(db.undo());
+    db.set_text("a", "z".to_string());
+    assert!(!db.redo());
+    assert!(db.undo());
+    assert_eq!(text(&db, "a"), Some("x".to_string()));
+}
+
+#[test]
+fn capacity_limits_undo() {
+    let mut db = journaled_db(2);
+    for text in &["a", "b", "c", "d"] {
+        db.set_text("a", text.to_string());
+    }
+    assert!(db.undo());
+    assert!(db.undo());
+    assert!(!db.undo());
+    assert_eq!(text(&db, "a"), Some("b".to_string()));
+}
+
+#[test]
+fn journal_is_disabled_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("a", "x".to_string());
+    assert!(!db.undo());
+
+    let mut out = Vec::new();
+    db.dump_journal(&mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn dump_journal() {
+    let mut db = journaled_db(10);
+    db.set_text("a", "x".to_string());
+    db.set_text("a", "y".to_string());
+    db.query_mut(TextQuery).remove("a");
+
+    let mut out = Vec::new();
+    db.dump_journal(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with("-> \"x\" (Durability(0))"), "{}", out);
+    assert!(
+        lines[1].ends_with(": \"x\" (Durability(0)) -> \"y\" (Durability(0))"),
+        "{}",
+        out
+    );
+    assert!(lines[2].ends_with("-> -"), "{}", out);
+}
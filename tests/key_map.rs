@@ -0,0 +1,78 @@
+//! Test `#[salsa::hasher]` and `#[salsa::ordered]` queries.
+
+use salsa::debug::{DebugQueryTable, TableEntry};
+use salsa::Database;
+use std::collections::hash_map::RandomState;
+
+#[salsa::query_group(KeyMapStorage)]
+trait KeyMapDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::hasher(RandomState)]
+    fn hashed_input(&self, key: String) -> u32;
+
+    #[salsa::hasher(RandomState)]
+    fn hashed_length(&self, key: String) -> usize;
+
+    #[salsa::input]
+    #[salsa::ordered]
+    fn ordered_input(&self, key: u32) -> u32;
+
+    #[salsa::ordered]
+    #[salsa::hasher(RandomState)]
+    fn ordered_double(&self, key: u32) -> u32;
+}
+
+fn hashed_length(db: &impl KeyMapDatabase, key: String) -> usize {
+    db.hashed_input(key.clone()) as usize + key.len()
+}
+
+fn ordered_double(db: &impl KeyMapDatabase, key: u32) -> u32 {
+    db.ordered_input(key) * 2
+}
+
+#[salsa::database(KeyMapStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn keys(entries: Vec<TableEntry<u32, u32>>) -> Vec<u32> {
+    entries.into_iter().map(|entry| entry.key).collect()
+}
+
+#[test]
+fn custom_hasher() {
+    let mut db = DatabaseImpl::default();
+    for (i, key) in ["a", "bb", "ccc"].iter().enumerate() {
+        db.set_hashed_input(key.to_string(), i as u32);
+    }
+    assert_eq!(db.hashed_length("bb".to_string()), 3);
+    assert_eq!(db.hashed_length("ccc".to_string()), 5);
+
+    db.set_hashed_input("bb".to_string(), 10);
+    assert_eq!(db.hashed_length("bb".to_string()), 12);
+    assert_eq!(db.hashed_length("ccc".to_string()), 5);
+}
+
+#[test]
+fn ordered_iteration() {
+    let mut db = DatabaseImpl::default();
+    let keys_in = [17, 3, 250, 42, 0, 99, 8, 1000, 5, 64];
+    for &key in &keys_in {
+        db.set_ordered_input(key, key + 1);
+    }
+    for &key in &keys_in {
+        assert_eq!(db.ordered_double(key), (key + 1) * 2);
+    }
+
+    let mut sorted = keys_in.to_vec();
+    sorted.sort();
+    assert_eq!(keys(db.query(OrderedInputQuery).entries()), sorted);
+    assert_eq!(keys(db.query(OrderedDoubleQuery).entries()), sorted);
+}
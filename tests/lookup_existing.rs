@@ -0,0 +1,94 @@
+//! Test `QueryTable::lookup_existing` on interned queries.
+
+use salsa::{Database, InternId};
+use std::cell::Cell;
+
+#[salsa::query_group(LookupStorage)]
+trait LookupDatabase: salsa::Database + Helpers {
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> InternId;
+
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    /// Returns whether "foo" has been interned yet.
+    fn foo_is_interned(&self) -> bool;
+}
+
+/// Query functions only see `impl LookupDatabase`, which cannot name
+/// the query tables, so the lookup goes through the database type.
+trait Helpers {
+    fn count(&self);
+
+    fn lookup_foo(&self) -> Option<InternId>;
+}
+
+fn foo_is_interned(db: &impl LookupDatabase) -> bool {
+    db.count();
+    db.lookup_foo().is_some()
+}
+
+#[salsa::database(LookupStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Cell<u32>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl Helpers for DatabaseImpl {
+    fn count(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+
+    fn lookup_foo(&self) -> Option<InternId> {
+        self.query(InternNameQuery)
+            .lookup_existing("foo".to_string())
+    }
+}
+
+#[test]
+fn lookup_present_and_absent() {
+    let db = DatabaseImpl::default();
+    let foo = db.intern_name("foo".to_string());
+
+    let table = db.query(InternNameQuery);
+    assert_eq!(table.lookup_existing("foo".to_string()), Some(foo));
+    assert_eq!(table.lookup_existing("bar".to_string()), None);
+}
+
+#[test]
+fn lookup_does_not_allocate() {
+    let db = DatabaseImpl::default();
+    let table = db.query(InternNameQuery);
+    assert_eq!(table.lookup_existing("foo".to_string()), None);
+    assert_eq!(table.lookup_existing("foo".to_string()), None);
+
+    // "foo" still gets the first id, as nothing was interned before.
+    let foo = db.intern_name("foo".to_string());
+    assert_eq!(foo, InternId::from(0u32));
+    assert_eq!(table.lookup_existing("foo".to_string()), Some(foo));
+}
+
+#[test]
+fn missing_value_is_untracked() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+    assert!(!db.foo_is_interned());
+    assert_eq!(db.executions.get(), 1);
+
+    db.intern_name("foo".to_string());
+    db.set_input(1);
+    assert!(db.foo_is_interned());
+    assert_eq!(db.executions.get(), 2);
+
+    // Once found, the value only depends on the interned slot.
+    db.set_input(2);
+    assert!(db.foo_is_interned());
+    assert_eq!(db.executions.get(), 2);
+}
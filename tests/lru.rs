@@ -4,6 +4,7 @@ use std::sync::{
     Arc,
 };
 
+use salsa::debug::DebugQueryTable;
 use salsa::Database as _;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -24,10 +25,34 @@ impl Drop for HotPotato {
     }
 }
 
+/// A value of the given size; the sizes of all live values are
+/// tracked in `BALLAST_SIZE`.
+#[derive(Debug, PartialEq, Eq)]
+struct Ballast(usize);
+
+static BALLAST_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+impl Ballast {
+    fn new(size: usize) -> Ballast {
+        BALLAST_SIZE.fetch_add(size, Ordering::SeqCst);
+        Ballast(size)
+    }
+}
+
+impl Drop for Ballast {
+    fn drop(&mut self) {
+        BALLAST_SIZE.fetch_sub(self.0, Ordering::SeqCst);
+    }
+}
+
 #[salsa::query_group(QueryGroupStorage)]
 trait QueryGroup: salsa::Database {
     fn get(&self, x: u32) -> Arc<HotPotato>;
     fn get_volatile(&self, x: u32) -> usize;
+    fn get_pinned(&self, x: u32) -> u32;
+
+    #[salsa::lru_cost(ballast_cost)]
+    fn get_ballast(&self, size: usize) -> Arc<Ballast>;
 }
 
 fn get(_db: &impl QueryGroup, x: u32) -> Arc<HotPotato> {
@@ -40,6 +65,18 @@ fn get_volatile(db: &impl QueryGroup, _x: u32) -> usize {
     COUNTER.fetch_add(1, Ordering::SeqCst)
 }
 
+fn get_pinned(_db: &impl QueryGroup, x: u32) -> u32 {
+    x
+}
+
+fn get_ballast(_db: &impl QueryGroup, size: usize) -> Arc<Ballast> {
+    Arc::new(Ballast::new(size))
+}
+
+fn ballast_cost(ballast: &Arc<Ballast>) -> usize {
+    ballast.0
+}
+
 #[salsa::database(QueryGroupStorage)]
 #[derive(Default)]
 struct Database {
@@ -106,3 +143,63 @@ fn lru_doesnt_break_volatile_queries() {
         assert_eq!(x, i)
     }
 }
+
+#[test]
+fn lru_cost_budget() {
+    let mut db = Database::default();
+    db.query_mut(GetBallastQuery).set_lru_capacity(1000);
+
+    for size in 1..=100 {
+        assert_eq!(db.get_ballast(size).0, size);
+        assert!(BALLAST_SIZE.load(Ordering::SeqCst) <= 1000);
+    }
+    assert!(BALLAST_SIZE.load(Ordering::SeqCst) > 900);
+
+    // A value that exceeds the budget on its own is kept until
+    // something else is used.
+    assert_eq!(db.get_ballast(5000).0, 5000);
+    assert!(BALLAST_SIZE.load(Ordering::SeqCst) >= 5000);
+    assert_eq!(db.get_ballast(1).0, 1);
+    assert!(BALLAST_SIZE.load(Ordering::SeqCst) <= 1000);
+
+    // Special case: setting capacity to zero disables LRU
+    db.query_mut(GetBallastQuery).set_lru_capacity(0);
+    for size in 1..=100 {
+        assert_eq!(db.get_ballast(size).0, size);
+    }
+    assert_eq!(BALLAST_SIZE.load(Ordering::SeqCst), 5050);
+
+    drop(db);
+    assert_eq!(BALLAST_SIZE.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn lru_skips_pinned() {
+    let mut db = Database::default();
+    db.query_mut(GetPinnedQuery).set_lru_capacity(8);
+    db.query(GetPinnedQuery).pin(0);
+
+    for i in 0..64u32 {
+        assert_eq!(db.get_pinned(i), i);
+    }
+    let present = |db: &Database| -> Vec<u32> {
+        let mut keys: Vec<u32> = db
+            .query(GetPinnedQuery)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .filter(|e| e.value.is_some())
+            .map(|e| e.key)
+            .collect();
+        keys.sort();
+        keys
+    };
+    assert!(present(&db).contains(&0));
+    assert!(present(&db).len() <= 9);
+
+    db.query(GetPinnedQuery).unpin(0);
+    assert_eq!(db.get_pinned(0), 0);
+    for i in 64..128u32 {
+        assert_eq!(db.get_pinned(i), i);
+    }
+    assert!(!present(&db).contains(&0));
+}
@@ -0,0 +1,176 @@
+//! Test `#[salsa::max_age]` queries, whose memoized values expire
+//! after a wall-clock duration.
+
+use salsa::{Database, InvalidationReason};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+const MAX_AGE: Duration = Duration::from_millis(50);
+
+#[salsa::query_group(MaxAgeStorage)]
+trait MaxAgeDatabase: salsa::Database + HasClock {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    #[salsa::input]
+    fn other(&self) -> u32;
+
+    /// Reads the clock, which salsa does not know about.
+    #[salsa::max_age(MAX_AGE)]
+    fn now(&self) -> u32;
+
+    fn later(&self) -> u32;
+}
+
+trait HasClock {
+    fn clock(&self) -> u32;
+
+    fn log(&self, message: &'static str);
+}
+
+fn now(db: &impl MaxAgeDatabase) -> u32 {
+    db.log("now");
+    db.clock()
+}
+
+fn later(db: &impl MaxAgeDatabase) -> u32 {
+    db.log("later");
+    db.now() + db.input()
+}
+
+#[salsa::database(MaxAgeStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    clock: Cell<u32>,
+    log: RefCell<Vec<&'static str>>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasClock for DatabaseImpl {
+    fn clock(&self) -> u32 {
+        self.clock.get()
+    }
+
+    fn log(&self, message: &'static str) {
+        self.log.borrow_mut().push(message);
+    }
+}
+
+impl DatabaseImpl {
+    fn take_log(&self) -> Vec<&'static str> {
+        std::mem::take(&mut *self.log.borrow_mut())
+    }
+}
+
+fn expire() {
+    std::thread::sleep(MAX_AGE + Duration::from_millis(10));
+}
+
+#[test]
+fn reused_before_expiry() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.clock.set(10);
+    assert_eq!(db.now(), 10);
+
+    db.clock.set(20);
+    assert_eq!(db.now(), 10);
+    assert_eq!(db.take_log(), vec!["now"]);
+}
+
+#[test]
+fn recomputed_after_expiry() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+    db.set_input(1);
+    db.clock.set(10);
+    assert_eq!(db.now(), 10);
+
+    // No new revision is needed for the value to expire.
+    db.clock.set(20);
+    expire();
+    assert_eq!(db.now(), 20);
+    assert_eq!(db.take_log(), vec!["now", "now"]);
+    assert_eq!(
+        db.query(NowQuery).last_invalidation_reason(()),
+        Some(InvalidationReason::Expired)
+    );
+}
+
+#[test]
+fn dependents_keep_value_within_revision() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.clock.set(10);
+    assert_eq!(db.later(), 11);
+
+    db.clock.set(20);
+    expire();
+    assert_eq!(db.later(), 11);
+    assert_eq!(db.now(), 20);
+
+    db.set_input(2);
+    assert_eq!(db.later(), 22);
+}
+
+#[test]
+fn expired_value_backdated() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.clock.set(10);
+    assert_eq!(db.later(), 11);
+    assert_eq!(db.take_log(), vec!["later", "now"]);
+
+    // In a new revision, `now` expired but produces the same value,
+    // so `later` need not be re-executed.
+    expire();
+    db.set_other(1);
+    assert_eq!(db.later(), 11);
+    assert_eq!(db.take_log(), vec!["now"]);
+
+    db.clock.set(20);
+    expire();
+    db.set_other(2);
+    assert_eq!(db.later(), 21);
+    assert_eq!(db.take_log(), vec!["now", "later"]);
+}
+
+#[test]
+fn not_peeked_after_expiry() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.clock.set(10);
+    assert_eq!(db.now(), 10);
+    assert_eq!(db.query(NowQuery).peek(()), Some(10));
+
+    expire();
+    assert_eq!(db.query(NowQuery).peek(()), None);
+    assert_eq!(db.take_log(), vec!["now"]);
+}
+
+/// Re-executing the query would deadlock while a `ValueGuard` on the
+/// same thread holds a lock on its memo; it is reported as a cycle
+/// instead.
+#[test]
+fn expired_while_borrowed() {
+    let db = DatabaseImpl::default();
+    db.clock.set(10);
+    assert_eq!(db.now(), 10);
+    let value = db.query(NowQuery).get_ref(());
+
+    expire();
+    let err = db.query(NowQuery).try_get(()).unwrap_err();
+    assert_eq!(err.cycle(), [db.query(NowQuery).database_key(())]);
+    assert_eq!(*value, 10);
+    drop(value);
+
+    db.clock.set(20);
+    assert_eq!(db.now(), 20);
+    assert_eq!(db.take_log(), vec!["now", "now"]);
+}
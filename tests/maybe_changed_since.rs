@@ -0,0 +1,73 @@
+//! Test asking whether queries may have changed since a revision.
+
+use salsa::Database;
+
+#[salsa::query_group(ChangedStorage)]
+trait ChangedDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn is_even(&self, key: u32) -> bool;
+
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> salsa::InternId;
+}
+
+fn is_even(db: &impl ChangedDatabase, key: u32) -> bool {
+    db.input(key) & 1 == 0
+}
+
+#[salsa::database(ChangedStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn inputs_and_derived_queries() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    assert!(!db.is_even(1));
+    let revision = db.salsa_runtime().current_revision();
+
+    assert!(!db.query(InputQuery).maybe_changed_since(1, revision));
+    assert!(!db.query(IsEvenQuery).maybe_changed_since(1, revision));
+
+    // `is_even(1)` is re-executed, but produces the same value.
+    db.set_input(1, 3);
+    assert!(db.query(InputQuery).maybe_changed_since(1, revision));
+    assert!(!db.query(IsEvenQuery).maybe_changed_since(1, revision));
+    assert!(!db.query(InputQuery).maybe_changed_since(2, revision));
+
+    db.set_input(1, 4);
+    assert!(db.query(IsEvenQuery).maybe_changed_since(1, revision));
+
+    // Values that were never computed are considered changed.
+    assert!(db.query(IsEvenQuery).maybe_changed_since(2, revision));
+    assert!(db.query(InputQuery).maybe_changed_since(3, revision));
+}
+
+#[test]
+fn interned() {
+    let mut db = DatabaseImpl::default();
+    let name = db.intern_name("a".to_string());
+    let revision = db.salsa_runtime().current_revision();
+    db.set_input(1, 1);
+
+    assert!(!db
+        .query(InternNameQuery)
+        .maybe_changed_since("a".to_string(), revision));
+    assert!(!db
+        .query(InternNameLookupQuery)
+        .maybe_changed_since(name, revision));
+    assert!(db
+        .query(InternNameQuery)
+        .maybe_changed_since("b".to_string(), revision));
+}
@@ -0,0 +1,139 @@
+//! Test `QueryTable::memory_usage` and `Database::salsa_memory_usage`.
+
+use salsa::{Database, MemoryReport, SweepStrategy};
+
+#[salsa::query_group(MemoryStorage)]
+trait MemoryDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::heap_size(string_heap_size)]
+    fn input(&self, key: u32) -> String;
+
+    #[salsa::heap_size(vec_heap_size)]
+    fn words(&self, key: u32) -> Vec<String>;
+
+    fn len(&self, key: u32) -> usize;
+
+    #[salsa::dependencies]
+    fn uncached(&self, key: u32) -> usize;
+
+    #[salsa::interned]
+    fn intern_word(&self, word: String) -> salsa::InternId;
+}
+
+fn string_heap_size(value: &String) -> usize {
+    value.capacity()
+}
+
+fn vec_heap_size(value: &Vec<String>) -> usize {
+    value.capacity() * std::mem::size_of::<String>()
+        + value.iter().map(string_heap_size).sum::<usize>()
+}
+
+fn words(db: &impl MemoryDatabase, key: u32) -> Vec<String> {
+    db.input(key).split(' ').map(String::from).collect()
+}
+
+fn len(db: &impl MemoryDatabase, key: u32) -> usize {
+    db.words(key).len()
+}
+
+fn uncached(db: &impl MemoryDatabase, key: u32) -> usize {
+    db.len(key)
+}
+
+#[salsa::database(MemoryStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn empty_tables() {
+    let db = DatabaseImpl::default();
+    assert_eq!(db.query(WordsQuery).memory_usage(), MemoryReport::default());
+    assert_eq!(db.query(InputQuery).memory_usage(), MemoryReport::default());
+}
+
+#[test]
+fn counts_slots_and_values() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "a b c".to_string());
+    db.set_input(2, "d e".to_string());
+    db.uncached(1);
+    db.uncached(2);
+    db.intern_word("a".to_string());
+
+    let words = db.query(WordsQuery).memory_usage();
+    assert_eq!((words.slots, words.values), (2, 2));
+
+    let input = db.query(InputQuery).memory_usage();
+    assert_eq!((input.slots, input.values), (2, 2));
+
+    let uncached = db.query(UncachedQuery).memory_usage();
+    assert_eq!((uncached.slots, uncached.values), (2, 0));
+
+    let interned = db.query(InternWordQuery).memory_usage();
+    assert_eq!((interned.slots, interned.values), (1, 1));
+}
+
+#[test]
+fn heap_size_counted() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "a".to_string());
+    db.len(1);
+    let small = db.query(WordsQuery).memory_usage();
+    let len_small = db.query(LenQuery).memory_usage();
+
+    db.set_input(1, "a ".repeat(1000));
+    db.len(1);
+    let large = db.query(WordsQuery).memory_usage();
+    assert_eq!(large.slots, 1);
+    assert!(large.bytes >= small.bytes + 1000 * std::mem::size_of::<String>());
+
+    // Without a `heap_size` function, only the size of the slot counts.
+    assert_eq!(db.query(LenQuery).memory_usage(), len_small);
+}
+
+#[test]
+fn sweep_frees_values() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "a b".to_string());
+    db.set_input(2, "c".to_string());
+    db.len(1);
+    db.len(2);
+
+    db.set_input(3, String::new());
+    db.len(1);
+    db.sweep_all(SweepStrategy::discard_outdated());
+    let words = db.query(WordsQuery).memory_usage();
+    assert_eq!((words.slots, words.values), (2, 1));
+}
+
+#[test]
+fn database_roll_up() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "a b c".to_string());
+    db.len(1);
+
+    let reports = db.salsa_memory_usage();
+    let names: Vec<_> = reports.iter().map(|(name, _)| *name).collect();
+    assert_eq!(names.len(), 6);
+    assert!(names.contains(&"words"));
+    assert!(reports.windows(2).all(|w| w[0].1.bytes >= w[1].1.bytes));
+
+    let total: MemoryReport = reports.iter().map(|(_, report)| *report).sum();
+    assert_eq!(total.slots, 3);
+    assert_eq!(total.values, 3);
+    assert_eq!(
+        total.bytes,
+        db.query(InputQuery).memory_usage().bytes
+            + db.query(WordsQuery).memory_usage().bytes
+            + db.query(LenQuery).memory_usage().bytes
+    );
+}
@@ -0,0 +1,91 @@
+//! Test queries whose keys and values do not implement `Debug`.
+
+use salsa::debug::DebugQueryTable;
+use salsa::Database;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// A key that does not implement `Debug`.
+#[derive(Clone)]
+struct Callback(Arc<dyn Fn(u32) -> u32 + Send + Sync>);
+
+impl PartialEq for Callback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Callback {}
+
+impl Hash for Callback {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state)
+    }
+}
+
+/// A value that does not implement `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+struct Output(u32);
+
+#[salsa::query_group(NoDebugStorage)]
+trait NoDebugDatabase: salsa::Database {
+    #[salsa::input]
+    fn offset(&self, callback: Callback) -> Output;
+
+    fn apply(&self, callback: Callback, value: u32) -> Output;
+}
+
+fn apply(db: &impl NoDebugDatabase, callback: Callback, value: u32) -> Output {
+    Output((callback.0)(value) + db.offset(callback).0)
+}
+
+#[salsa::database(NoDebugStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn execute_and_format() {
+    let mut db = DatabaseImpl::default();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    db.salsa_runtime().subscribe_events(Box::new({
+        let log = log.clone();
+        move |event| log.lock().unwrap().push(format!("{:?}", event.kind))
+    }));
+
+    let double = Callback(Arc::new(|x| x * 2));
+    db.set_offset(double.clone(), Output(1));
+    assert!(db.apply(double.clone(), 3) == Output(7));
+
+    // No events are reported with the `strip-logging` feature.
+    if cfg!(not(feature = "strip-logging")) {
+        let log = log.lock().unwrap();
+        assert!(
+            log[1].contains("apply((<opaque no_debug::Callback>, 3))"),
+            "{:?}",
+            log
+        );
+    }
+    assert_eq!(
+        db.query(ApplyQuery)
+            .database_key((double.clone(), 3))
+            .to_string(),
+        "apply(<opaque no_debug::Callback>, 3)"
+    );
+
+    let mut dump = Vec::new();
+    db.query(OffsetQuery).debug_dump(&mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    assert!(
+        dump.contains(r#""key":"<opaque no_debug::Callback>""#),
+        "{}",
+        dump
+    );
+}
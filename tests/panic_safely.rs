@@ -1,4 +1,4 @@
-use salsa::{Database, ParallelDatabase, Snapshot};
+use salsa::Database;
 use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
 
@@ -35,16 +35,20 @@ impl salsa::Database for DatabaseStruct {
     }
 }
 
+#[cfg(not(feature = "single-threaded"))]
 impl salsa::ParallelDatabase for DatabaseStruct {
-    fn snapshot(&self) -> Snapshot<Self> {
-        Snapshot::new(DatabaseStruct {
+    fn snapshot(&self) -> salsa::Snapshot<Self> {
+        salsa::Snapshot::new(DatabaseStruct {
             runtime: self.runtime.snapshot(self),
         })
     }
 }
 
 #[test]
+#[cfg(not(feature = "single-threaded"))]
 fn should_panic_safely() {
+    use salsa::ParallelDatabase;
+
     let mut db = DatabaseStruct::default();
     db.set_one(0);
 
@@ -0,0 +1,117 @@
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, SumQuery, WithValue};
+use salsa::{Database, ParallelDatabase};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Wakes up a thread that is polling a future by hand.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn thread_waker() -> Waker {
+    Waker::from(Arc::new(ThreadWaker(thread::current())))
+}
+
+/// Polls `future` on the current thread until it completes.
+fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match Pin::new(&mut future).poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Starts a thread executing `sum("abc")`, and returns once it has
+/// entered the query; it leaves once stage 2 is signaled.
+fn start_sum(db: &ParDatabaseImpl) -> thread::JoinHandle<usize> {
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_exit
+                    .with_value(2, || db.sum("abc"))
+            })
+        }
+    });
+    db.knobs().signal.wait_for(1);
+    thread1
+}
+
+fn database() -> ParDatabaseImpl {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+    db
+}
+
+/// Test where one thread is executing `sum("abc")` and another peeks
+/// at the same query: the future is pending until the first thread is
+/// done, and can be awaited on yet another thread.
+#[test]
+fn peek_or_wait_waits_for_other_thread() {
+    let db = database();
+    let thread1 = start_sum(&db);
+
+    let mut future = db.query(SumQuery).peek_or_wait("abc");
+    let waker = thread_waker();
+    assert!(Pin::new(&mut future)
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+
+    // The future does not borrow the database.
+    let thread2 = std::thread::spawn(move || block_on(future));
+
+    // Let thread 1 finish; it wakes up thread 2.
+    db.knobs().signal.signal(2);
+    assert_eq!(thread2.join().unwrap(), Some(111));
+    assert_eq!(thread1.join().unwrap(), 111);
+
+    // Now the value is memoized.
+    assert_eq!(block_on(db.query(SumQuery).peek_or_wait("abc")), Some(111));
+}
+
+/// Test that dropping a pending future leaves the slot alone: other
+/// readers still wait for the thread computing it.
+#[test]
+fn peek_or_wait_dropped_while_pending() {
+    let db = database();
+    let thread1 = start_sum(&db);
+
+    let mut future = db.query(SumQuery).peek_or_wait("abc");
+    let waker = thread_waker();
+    assert!(Pin::new(&mut future)
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+    std::mem::drop(future);
+
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || db.sum("abc")
+    });
+
+    db.knobs().signal.signal(2);
+    assert_eq!(thread2.join().unwrap(), 111);
+    assert_eq!(thread1.join().unwrap(), 111);
+    assert_eq!(db.sum("abc"), 111);
+}
+
+/// Test that `peek_or_wait` does not execute the query when nobody is
+/// computing it.
+#[test]
+fn peek_or_wait_not_computed() {
+    let db = database();
+    assert_eq!(block_on(db.query(SumQuery).peek_or_wait("abc")), None);
+    assert_eq!(db.query(SumQuery).peek("abc"), None);
+}
@@ -1,7 +1,8 @@
 use crate::setup::{
     CancelationFlag, Canceled, Knobs, ParDatabase, ParDatabaseImpl, WithValue,
 };
-use salsa::ParallelDatabase;
+use salsa::{Database, ParallelDatabase};
+use std::panic::AssertUnwindSafe;
 
 macro_rules! assert_canceled {
     ($flag:expr, $thread:expr) => {
@@ -187,3 +188,32 @@ fn transitive_cancellation() {
 
     assert_eq!(thread1.join().unwrap(), 22);
 }
+
+/// Check that once a write is pending, a snapshot that would have to
+/// execute a query unwinds with `salsa::Cancelled` instead.
+#[test]
+fn cancelled_before_execute() {
+    let mut db = ParDatabaseImpl::default();
+
+    db.set_input('a', 1);
+
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.signal(1);
+
+            // Wait for the main thread to signal cancellation.
+            while !db.salsa_runtime().is_current_revision_canceled() {
+                std::thread::yield_now();
+            }
+
+            salsa::Cancelled::catch(AssertUnwindSafe(|| db.sum("a")))
+        }
+    });
+
+    db.wait_for(1);
+    db.set_input('a', 2);
+
+    assert_eq!(thread1.join().unwrap(), Err(salsa::Cancelled));
+    assert_eq!(db.sum("a"), 2);
+}
@@ -0,0 +1,168 @@
+use crate::signal::Signal;
+use parking_lot::Mutex;
+use salsa::plumbing::HasQueryGroup;
+use salsa::{Database, ParallelDatabase, Snapshot};
+use std::sync::Arc;
+
+#[salsa::query_group(Cycles)]
+trait CycleDatabase: salsa::Database + HasSignal {
+    fn cycle_a(&self) -> Vec<String>;
+    fn cycle_b(&self) -> Vec<String>;
+}
+
+/// The query stack of a runtime in a cycle, and the query it is blocked on.
+type RuntimeReport = (Vec<String>, String);
+
+trait HasSignal {
+    fn signal(&self) -> &Signal;
+
+    /// Records the runtimes reported by a cycle error.
+    fn report_runtimes(&self, runtimes: Vec<RuntimeReport>);
+}
+
+/// Runs on the first thread: waits for `cycle_b` to start executing
+/// on the other thread before invoking it (and hence blocking).
+fn cycle_a(db: &impl CycleDatabase) -> Vec<String> {
+    db.signal().signal(1);
+    db.signal().wait_for(2);
+    db.cycle_b()
+}
+
+/// Runs on the second thread: once the first thread is blocked on us,
+/// invokes `cycle_a`, closing the cycle, and reports the cycle as its
+/// result.
+fn cycle_b(db: &(impl CycleDatabase + HasQueryGroup<Cycles>)) -> Vec<String> {
+    db.signal().wait_for(1);
+    db.signal().signal(2);
+    db.signal().wait_for(3);
+    match db.query(CycleAQuery).try_get(()) {
+        Ok(v) => v,
+        Err(err) => {
+            db.report_runtimes(
+                err.runtimes()
+                    .iter()
+                    .map(|runtime| {
+                        let stack = runtime.query_stack.iter().map(|k| format!("{:?}", k));
+                        (stack.collect(), format!("{:?}", runtime.blocked_on))
+                    })
+                    .collect(),
+            );
+            err.cycle().iter().map(|k| format!("{:?}", k)).collect()
+        }
+    }
+}
+
+#[salsa::database(Cycles)]
+#[derive(Default)]
+struct CycleDatabaseImpl {
+    runtime: salsa::Runtime<CycleDatabaseImpl>,
+    signal: Arc<Signal>,
+    runtimes: Arc<Mutex<Vec<RuntimeReport>>>,
+    blocked_stack: Arc<Mutex<Vec<String>>>,
+}
+
+impl Database for CycleDatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<CycleDatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        if let salsa::EventKind::WillBlockOn { .. } = event_fn().kind {
+            let stack = self.runtime.active_query_stack();
+            *self.blocked_stack.lock() = stack.iter().map(|k| format!("{:?}", k)).collect();
+            self.signal.signal(3);
+        }
+    }
+}
+
+impl ParallelDatabase for CycleDatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(CycleDatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            signal: self.signal.clone(),
+            runtimes: self.runtimes.clone(),
+            blocked_stack: self.blocked_stack.clone(),
+        })
+    }
+}
+
+impl HasSignal for CycleDatabaseImpl {
+    fn signal(&self) -> &Signal {
+        &self.signal
+    }
+
+    fn report_runtimes(&self, runtimes: Vec<RuntimeReport>) {
+        *self.runtimes.lock() = runtimes;
+    }
+}
+
+/// Test a cycle that spans two threads: the first thread computes
+/// `cycle_a` and blocks on `cycle_b`, which is being computed by the
+/// second thread and then requests `cycle_a`. The error should list
+/// both queries.
+#[test]
+fn in_par_cross_thread_cycle() {
+    let db = CycleDatabaseImpl::default();
+
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || db.cycle_a()
+    });
+
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || db.cycle_b()
+    });
+
+    let cycle = thread2.join().unwrap();
+    assert_eq!(cycle.len(), 2, "unexpected cycle {:?}", cycle);
+    assert!(cycle[0].contains("cycle_b"), "unexpected cycle {:?}", cycle);
+    assert!(cycle[1].contains("cycle_a"), "unexpected cycle {:?}", cycle);
+
+    assert_eq!(thread1.join().unwrap(), cycle);
+
+    // The second thread detected the cycle while executing `cycle_b`
+    // (and blocking on `cycle_a`); the first one is executing
+    // `cycle_a` (and blocked on `cycle_b`).
+    let runtimes = db.runtimes.lock().clone();
+    assert_eq!(runtimes.len(), 2, "unexpected runtimes {:?}", runtimes);
+    let (stack, blocked_on) = &runtimes[0];
+    assert_eq!(stack.len(), 1, "unexpected runtimes {:?}", runtimes);
+    assert!(
+        stack[0].contains("cycle_b"),
+        "unexpected runtimes {:?}",
+        runtimes
+    );
+    assert!(
+        blocked_on.contains("cycle_a"),
+        "unexpected runtimes {:?}",
+        runtimes
+    );
+    let (stack, blocked_on) = &runtimes[1];
+    assert_eq!(stack.len(), 1, "unexpected runtimes {:?}", runtimes);
+    assert!(
+        stack[0].contains("cycle_a"),
+        "unexpected runtimes {:?}",
+        runtimes
+    );
+    assert!(
+        blocked_on.contains("cycle_b"),
+        "unexpected runtimes {:?}",
+        runtimes
+    );
+
+    // While blocked on `cycle_b`, the first thread still reports the
+    // query it is executing.
+    let blocked_stack = db.blocked_stack.lock().clone();
+    assert_eq!(
+        blocked_stack.len(),
+        1,
+        "unexpected stack {:?}",
+        blocked_stack
+    );
+    assert!(
+        blocked_stack[0].contains("cycle_a"),
+        "unexpected stack {:?}",
+        blocked_stack
+    );
+}
@@ -63,3 +63,21 @@ fn in_par_get_set_cancellation() {
     let c = thread2.join().unwrap();
     assert_eq!(c, 2);
 }
+
+/// Test that the runtime keeps track of the snapshots that would
+/// block a `set`.
+#[test]
+fn live_snapshots() {
+    let mut db = ParDatabaseImpl::default();
+    assert_eq!(db.salsa_runtime().live_snapshots(), 0);
+
+    let snapshot1 = db.snapshot();
+    let snapshot2 = snapshot1.snapshot();
+    assert_eq!(db.salsa_runtime().live_snapshots(), 2);
+    assert_eq!(snapshot2.salsa_runtime().live_snapshots(), 2);
+
+    drop(snapshot1);
+    drop(snapshot2);
+    assert_eq!(db.salsa_runtime().live_snapshots(), 0);
+    db.set_input('a', 1);
+}
@@ -0,0 +1,45 @@
+use crate::setup::{ParDatabase, ParDatabaseImpl};
+use salsa::{Database, ParallelDatabase, SweepStrategy};
+
+/// Test threads repeatedly reading values verified in the current
+/// revision, while another thread keeps discarding those values, so
+/// that reads race with values being unpublished and recomputed.
+#[test]
+fn hot_reads_race_with_sweeps() {
+    let mut db = ParDatabaseImpl::default();
+
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.snapshot();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    assert_eq!(db.sum("abc"), 111);
+                    assert_eq!(db.sum2("abc"), 111);
+                }
+            })
+        })
+        .collect();
+
+    let sweeper = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            for i in 0..1000 {
+                let strategy = SweepStrategy::default().sweep_all_revisions();
+                if i % 2 == 0 {
+                    db.sweep_all(strategy.discard_values());
+                } else {
+                    db.sweep_all(strategy.discard_everything());
+                }
+            }
+        }
+    });
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    sweeper.join().unwrap();
+}
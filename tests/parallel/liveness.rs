@@ -0,0 +1,63 @@
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, WithValue};
+use salsa::{Database, ParallelDatabase};
+use std::time::Duration;
+
+/// Test where a thread blocks on `sum("abc")` while another one takes
+/// a while to compute it: the blocked thread checks many times that the
+/// other runtime still exists, and then receives the value. Meanwhile,
+/// the query is in progress, but not dangling.
+#[test]
+fn blocked_thread_waits_for_live_runtime() {
+    let mut db = ParDatabaseImpl::default();
+    db.salsa_runtime()
+        .set_liveness_check_interval(Some(Duration::from_millis(1)));
+
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_exit
+                    .with_value(3, || db.sum("abc"))
+            })
+        }
+    });
+
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().signal.wait_for(1);
+            db.knobs().signal_on_will_block.set(2);
+            db.sum("abc")
+        }
+    });
+
+    db.knobs().signal.wait_for(2);
+    std::thread::sleep(Duration::from_millis(20));
+    db.assert_no_dangling_in_progress();
+    db.knobs().signal.signal(3);
+
+    assert_eq!(thread1.join().unwrap(), 111);
+    assert_eq!(thread2.join().unwrap(), 111);
+}
+
+/// Test that a query whose execution panicked is released, rather
+/// than left in progress.
+#[test]
+fn no_dangling_query_after_panic() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 1);
+
+    let thread = std::thread::spawn({
+        let db = db.snapshot();
+        move || db.knobs().sum_should_panic.with_value(true, || db.sum("a"))
+    });
+    assert!(thread.join().is_err());
+
+    db.assert_no_dangling_in_progress();
+    assert_eq!(db.sum("a"), 1);
+}
@@ -1,10 +1,25 @@
+// The tests wait for the events that tell them that a thread is
+// blocked, which are not reported with the `strip-logging` feature;
+// with the `single-threaded` feature, there are no other threads.
+#![cfg(not(any(feature = "strip-logging", feature = "single-threaded")))]
+
 mod setup;
 
+mod async_read;
 mod cancellation;
+mod cycles;
 mod fork_from_query;
 mod frozen;
+mod hot_reads;
 mod independent;
+mod liveness;
+mod par_iter;
+mod prefetch;
+mod priority;
 mod race;
+mod revalidation;
 mod signal;
 mod stress;
 mod true_parallel;
+mod write_lock;
+mod yield_points;
@@ -0,0 +1,25 @@
+use crate::setup::{ParDatabase, ParDatabaseImpl};
+use rayon::prelude::*;
+use salsa::ParallelDatabase;
+
+/// Test that cloned snapshots can be used as per-worker state of a
+/// rayon iterator.
+#[test]
+fn par_iter_with_snapshots() {
+    let mut db = ParDatabaseImpl::default();
+
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    let keys = vec!["a", "b", "c", "ab", "bc", "abc"];
+    let sums: Vec<usize> = keys
+        .par_iter()
+        .map_with(db.snapshot(), |db, &key| db.sum(key))
+        .collect();
+    assert_eq!(sums, vec![100, 10, 1, 110, 11, 111]);
+
+    // All snapshots are dropped, so we can set inputs again.
+    db.set_input('a', 200);
+    assert_eq!(db.sum("abc"), 211);
+}
@@ -0,0 +1,54 @@
+use crate::setup::{
+    CancelationFlag, InputQuery, Knobs, ParDatabase, ParDatabaseImpl, Sum2Query, SumQuery,
+};
+use salsa::{Database, ParallelDatabase};
+
+/// Prefetched queries are up to date when they are read afterwards.
+#[test]
+fn prefetch_then_read() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    db.prefetch(vec![
+        db.query(SumQuery).database_key("abc"),
+        db.query(Sum2Query).database_key("ab"),
+        db.query(InputQuery).database_key('a'),
+    ])
+    .wait();
+
+    assert_eq!(db.query(SumQuery).peek("abc"), Some(111));
+    assert_eq!(db.query(Sum2Query).peek("ab"), Some(110));
+    assert_eq!(db.query(SumQuery).peek("ab"), Some(110));
+    assert_eq!(db.query(SumQuery).peek("c"), None);
+}
+
+/// Setting an input cancels the prefetch rather than waiting for it.
+#[test]
+fn prefetch_cancelled_by_new_revision() {
+    let mut db = ParDatabaseImpl::default();
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+    db.set_input('d', 0);
+
+    // The workers copy these knobs from `db`: `sum` signals stage 1
+    // and then spins until it is cancelled.
+    db.knobs().sum_signal_on_entry.set(1);
+    db.knobs()
+        .sum_wait_for_cancellation
+        .set(CancelationFlag::Panic);
+    let prefetch = db.prefetch(vec![db.query(SumQuery).database_key("abc")]);
+    db.wait_for(1);
+
+    db.knobs().sum_signal_on_entry.set(0);
+    db.knobs()
+        .sum_wait_for_cancellation
+        .set(CancelationFlag::Down);
+    db.set_input('d', 1000);
+    prefetch.wait();
+
+    assert_eq!(db.query(SumQuery).peek("abc"), None);
+    assert_eq!(db.sum("abc"), 111);
+}
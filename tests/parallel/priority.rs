@@ -0,0 +1,126 @@
+use crate::signal::Signal;
+use salsa::{Cancelled, Database, ParallelDatabase, Priority, Snapshot};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[salsa::query_group(PriorityStorage)]
+trait PriorityDatabase: salsa::ParallelDatabase + HasSignal {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    /// Signals stage 1, waits for stage 2, then invokes `inner`.
+    fn outer(&self) -> u32;
+
+    fn inner(&self) -> u32;
+}
+
+trait HasSignal {
+    fn signal(&self) -> &Signal;
+
+    fn count_outer(&self);
+}
+
+fn outer(db: &impl PriorityDatabase) -> u32 {
+    db.count_outer();
+    db.signal().signal(1);
+    db.signal().wait_for(2);
+    db.inner() + 1
+}
+
+fn inner(db: &impl PriorityDatabase) -> u32 {
+    db.input() * 2
+}
+
+#[salsa::database(PriorityStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    signal: Arc<Signal>,
+    outer_executions: Arc<AtomicUsize>,
+
+    /// If set, signals stage 2 when this runtime blocks.
+    signal_on_will_block: Cell<bool>,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        if let salsa::EventKind::WillBlockOn { .. } = event_fn().kind {
+            if self.signal_on_will_block.get() {
+                self.signal.signal(2);
+            }
+        }
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            signal: self.signal.clone(),
+            outer_executions: self.outer_executions.clone(),
+            signal_on_will_block: Cell::new(false),
+        })
+    }
+}
+
+impl HasSignal for DatabaseImpl {
+    fn signal(&self) -> &Signal {
+        &self.signal
+    }
+
+    fn count_outer(&self) {
+        self.outer_executions.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A background runtime gives up a query that a foreground runtime
+/// blocks on, and the foreground runtime computes it instead.
+#[test]
+fn background_yields_to_foreground() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(10);
+    assert_eq!(db.salsa_runtime().priority(), Priority::Foreground);
+
+    let background = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.salsa_runtime().set_priority(Priority::Background);
+            Cancelled::catch(std::panic::AssertUnwindSafe(|| db.outer()))
+        }
+    });
+
+    // Wait until the background runtime is computing `outer`, then
+    // block on it: that lets it continue, up to its next check.
+    db.signal.wait_for(1);
+    let foreground = db.snapshot();
+    foreground.signal_on_will_block.set(true);
+    assert_eq!(foreground.outer(), 21);
+
+    assert_eq!(background.join().unwrap(), Err(Cancelled));
+    assert_eq!(db.outer_executions.load(Ordering::SeqCst), 2);
+}
+
+/// Foreground runtimes do not yield to one another.
+#[test]
+fn foreground_does_not_yield() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(10);
+
+    let first = std::thread::spawn({
+        let db = db.snapshot();
+        move || db.outer()
+    });
+
+    db.signal.wait_for(1);
+    let second = db.snapshot();
+    second.signal_on_will_block.set(true);
+    assert_eq!(second.outer(), 21);
+
+    assert_eq!(first.join().unwrap(), 21);
+    assert_eq!(db.outer_executions.load(Ordering::SeqCst), 1);
+}
@@ -1,5 +1,6 @@
 use crate::setup::{ParDatabase, ParDatabaseImpl};
 use salsa::ParallelDatabase;
+use std::panic::AssertUnwindSafe;
 
 /// Test where a read and a set are racing with one another.
 /// Should be atomic.
@@ -13,10 +14,7 @@ fn in_par_get_set_race() {
 
     let thread1 = std::thread::spawn({
         let db = db.snapshot();
-        move || {
-            let v = db.sum("abc");
-            v
-        }
+        move || salsa::Cancelled::catch(AssertUnwindSafe(|| db.sum("abc")))
     });
 
     let thread2 = std::thread::spawn(move || {
@@ -26,13 +24,15 @@ fn in_par_get_set_race() {
 
     // If the 1st thread runs first, you get 111, otherwise you get
     // 1011; if they run concurrently and the 1st thread observes the
-    // cancelation, you get back usize::max.
-    let value1 = thread1.join().unwrap();
-    assert!(
-        value1 == 111 || value1 == 1011 || value1 == std::usize::MAX,
-        "illegal result {}",
-        value1
-    );
+    // cancelation, you get back usize::max, or it unwinds with
+    // `Cancelled` before executing a query.
+    if let Ok(value1) = thread1.join().unwrap() {
+        assert!(
+            value1 == 111 || value1 == 1011 || value1 == std::usize::MAX,
+            "illegal result {}",
+            value1
+        );
+    }
 
     assert_eq!(thread2.join().unwrap(), 1000);
 }
@@ -0,0 +1,103 @@
+use parking_lot::Mutex;
+use salsa::{Database, ParallelDatabase, RuntimeId, Snapshot, StatisticsMode};
+use std::sync::Arc;
+
+#[salsa::query_group(RevalidationStorage)]
+trait RevalidationDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn is_odd(&self, key: u32) -> bool;
+
+    /// Counts the odd inputs among the first `n`.
+    fn count_odd(&self, n: u32) -> usize;
+}
+
+fn is_odd(db: &impl RevalidationDatabase, key: u32) -> bool {
+    db.input(key) % 2 == 1
+}
+
+fn count_odd(db: &impl RevalidationDatabase, n: u32) -> usize {
+    (0..n).filter(|&key| db.is_odd(key)).count()
+}
+
+#[salsa::database(RevalidationStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+
+    /// The runtimes that executed `is_odd`.
+    executed_by: Arc<Mutex<Vec<RuntimeId>>>,
+}
+
+impl Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, event_fn: impl Fn() -> salsa::Event<Self>) {
+        let event = event_fn();
+        if let salsa::EventKind::WillExecute { database_key } = &event.kind {
+            if format!("{:?}", database_key).contains("is_odd") {
+                self.executed_by.lock().push(event.runtime_id);
+            }
+        }
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            executed_by: self.executed_by.clone(),
+        })
+    }
+}
+
+/// Test that the inputs of `count_odd` are checked by workers, which
+/// re-execute (and backdate) the `is_odd` queries that depend on
+/// changed inputs.
+#[test]
+fn revalidate_in_parallel() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+    db.set_parallel_revalidation(Some(10));
+
+    for key in 0..100 {
+        db.set_input(key, key);
+    }
+    assert_eq!(db.count_odd(100), 50);
+    assert_eq!(db.query(CountOddQuery).statistics().executions, 1);
+    db.executed_by.lock().clear();
+
+    // `is_odd(3)` is backdated, so `count_odd` is validated.
+    db.set_input(3, 5);
+    assert_eq!(db.count_odd(100), 50);
+    assert_eq!(db.query(CountOddQuery).statistics().executions, 1);
+    assert_eq!(db.query(CountOddQuery).statistics().validations, 1);
+    let executed_by = std::mem::take(&mut *db.executed_by.lock());
+    assert_eq!(executed_by.len(), 1);
+    assert_ne!(executed_by[0], db.salsa_runtime().id());
+
+    db.set_input(3, 4);
+    assert_eq!(db.count_odd(100), 49);
+    assert_eq!(db.query(CountOddQuery).statistics().executions, 2);
+
+    // Memos with fewer inputs are checked on the current thread.
+    db.executed_by.lock().clear();
+    db.set_input(1, 3);
+    assert_eq!(db.count_odd(5), 1);
+    assert_eq!(db.count_odd(5), 1);
+    db.set_input(1, 5);
+    assert_eq!(db.count_odd(5), 1);
+    let executed_by = std::mem::take(&mut *db.executed_by.lock());
+    assert!(executed_by.iter().all(|&id| id == db.salsa_runtime().id()));
+
+    // Disabled again.
+    db.set_parallel_revalidation(None);
+    db.set_input(3, 7);
+    assert_eq!(db.count_odd(100), 50);
+    let executed_by = std::mem::take(&mut *db.executed_by.lock());
+    assert!(executed_by.iter().all(|&id| id == db.salsa_runtime().id()));
+}
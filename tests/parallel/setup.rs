@@ -1,6 +1,7 @@
 use crate::signal::Signal;
 use salsa::Database;
 use salsa::ParallelDatabase;
+use salsa::PropagatedPanicPolicy;
 use salsa::Snapshot;
 use std::cell::Cell;
 use std::sync::Arc;
@@ -109,6 +110,9 @@ pub(crate) struct KnobsStruct {
 
     /// Invocations of `sum3_drop_sum` will panic unconditionally
     pub(crate) sum3_drop_sum_should_panic: Cell<bool>,
+
+    /// The database's `propagated_panic_policy`.
+    pub(crate) propagated_panic_policy: Cell<PropagatedPanicPolicy>,
 }
 
 fn sum(db: &impl ParDatabase, key: &'static str) -> usize {
@@ -210,6 +214,10 @@ impl Database for ParDatabaseImpl {
     fn on_propagated_panic(&self) -> ! {
         Canceled::throw()
     }
+
+    fn propagated_panic_policy(&self) -> PropagatedPanicPolicy {
+        self.knobs().propagated_panic_policy.get()
+    }
 }
 
 impl ParallelDatabase for ParDatabaseImpl {
@@ -203,7 +203,11 @@ fn stress_test() {
                 check_cancellation,
             } => all_threads.push(std::thread::spawn({
                 let db = db.snapshot();
-                move || db_reader_thread(&db, ops, check_cancellation)
+                move || {
+                    // Readers may be cancelled by a concurrent write; that's fine.
+                    let _ =
+                        salsa::Cancelled::catch(|| db_reader_thread(&db, ops, check_cancellation));
+                }
             })),
         }
     }
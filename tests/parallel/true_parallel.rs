@@ -1,5 +1,6 @@
-use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, WithValue};
-use salsa::ParallelDatabase;
+use crate::setup::{Knobs, ParDatabase, ParDatabaseImpl, SumQuery, WithValue};
+use salsa::plumbing::DatabaseStorageTypes;
+use salsa::{Database, ParallelDatabase, PropagatedPanicPolicy, QueryPanicked, StatisticsMode};
 use std::panic::{self, AssertUnwindSafe};
 
 /// Test where two threads are executing sum. We show that they can
@@ -124,3 +125,97 @@ fn true_parallel_propagate_panic() {
     assert!(result1.is_err());
     assert!(result2.is_err());
 }
+
+type DatabaseKey = <ParDatabaseImpl as DatabaseStorageTypes>::DatabaseKey;
+
+/// Like `true_parallel_propagate_panic`, but `thread2` chose to see
+/// the panic as a `QueryPanicked` error carrying the key of the query.
+#[test]
+fn true_parallel_propagate_panic_as_error() {
+    let mut db = ParDatabaseImpl::default();
+
+    db.set_input('a', 1);
+
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs().sum_wait_for_on_entry.with_value(2, || {
+                    db.knobs().sum_should_panic.with_value(true, || db.sum("a"))
+                })
+            })
+        }
+    });
+
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().signal.wait_for(1);
+            db.knobs().signal_on_will_block.set(2);
+            db.knobs()
+                .propagated_panic_policy
+                .set(PropagatedPanicPolicy::Error);
+            QueryPanicked::<DatabaseKey>::catch(AssertUnwindSafe(|| db.sum("a")))
+        }
+    });
+
+    assert!(thread1.join().is_err());
+    let error = thread2.join().unwrap().unwrap_err();
+    assert_eq!(*error.database_key(), db.query(SumQuery).database_key("a"));
+
+    // The panic was not memoized.
+    assert_eq!(db.sum("a"), 1);
+}
+
+/// Test where two threads block on `sum("abc")` while a third one
+/// computes it: both receive the value, and both blocking events are
+/// counted.
+#[test]
+fn true_parallel_several_waiters() {
+    let mut db = ParDatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+
+    db.set_input('a', 100);
+    db.set_input('b', 10);
+    db.set_input('c', 1);
+
+    // Thread 1 will wait_for both other threads to block.
+    let thread1 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().sum_signal_on_entry.with_value(1, || {
+                db.knobs()
+                    .sum_wait_for_on_entry
+                    .with_value(3, || db.sum("abc"))
+            })
+        }
+    });
+
+    // Thread 2 blocks once Thread 1 has entered `sum`, and Thread 3
+    // once Thread 2 has blocked.
+    let thread2 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().signal.wait_for(1);
+            db.knobs().signal_on_will_block.set(2);
+            db.sum("abc")
+        }
+    });
+    let thread3 = std::thread::spawn({
+        let db = db.snapshot();
+        move || {
+            db.knobs().signal.wait_for(2);
+            db.knobs().signal_on_will_block.set(3);
+            db.sum("abc")
+        }
+    });
+
+    assert_eq!(thread1.join().unwrap(), 111);
+    assert_eq!(thread2.join().unwrap(), 111);
+    assert_eq!(thread3.join().unwrap(), 111);
+
+    let statistics = db.query(SumQuery).statistics();
+    assert_eq!(statistics.executions, 1);
+    assert_eq!(statistics.blocks, 2);
+}
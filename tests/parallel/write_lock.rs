@@ -0,0 +1,119 @@
+//! Test that writes are not starved by readers of pinned snapshots,
+//! and `Runtime::write_lock_statistics`.
+
+use salsa::{Database, ParallelDatabase};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[salsa::query_group(WriteLockStorage)]
+trait WriteLockDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::multi_version]
+    fn input(&self) -> u32;
+
+    /// Takes 2ms.
+    #[salsa::multi_version]
+    fn slow(&self, key: u32) -> u32;
+}
+
+fn slow(db: &impl WriteLockDatabase, key: u32) -> u32 {
+    std::thread::sleep(Duration::from_millis(2));
+    db.input() + key
+}
+
+#[salsa::database(WriteLockStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+#[test]
+fn write_is_not_starved_by_pinned_readers() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+
+    for round in 1..4 {
+        // Readers that keep executing queries, so that at any time
+        // some of them hold the query lock.
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|thread| {
+                let snapshot = db.snapshot_at_current_revision();
+                let stop = stop.clone();
+                std::thread::spawn(move || {
+                    let mut key = thread * 1_000_000;
+                    while !stop.load(Ordering::SeqCst) {
+                        snapshot.slow(key);
+                        key += 1;
+                    }
+                })
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(10));
+
+        db.set_input(round);
+        stop.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(db.slow(0), round);
+    }
+
+    let statistics = db.salsa_runtime().write_lock_statistics();
+    assert_eq!(statistics.writes, 4);
+    assert!(statistics.contended_writes <= 3);
+    assert!(
+        statistics.longest_wait_for_write < Duration::from_secs(1),
+        "{:?}",
+        statistics
+    );
+}
+
+#[test]
+fn write_lock_statistics() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+    assert_eq!(db.salsa_runtime().write_lock_statistics().writes, 1);
+    assert_eq!(
+        db.salsa_runtime().write_lock_statistics().contended_writes,
+        0
+    );
+
+    let snapshot = db.snapshot();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        drop(snapshot);
+    });
+    db.set_input(1);
+    thread.join().unwrap();
+
+    let statistics = db.salsa_runtime().write_lock_statistics();
+    assert_eq!(statistics.writes, 2);
+    assert_eq!(statistics.contended_writes, 1);
+    assert!(statistics.time_waiting_for_write >= Duration::from_millis(40));
+    assert_eq!(
+        statistics.longest_wait_for_write,
+        statistics.time_waiting_for_write
+    );
+
+    db.salsa_runtime().reset_statistics();
+    assert_eq!(
+        db.salsa_runtime().write_lock_statistics(),
+        salsa::WriteLockStatistics::default()
+    );
+}
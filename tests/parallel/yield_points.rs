@@ -0,0 +1,121 @@
+//! Test `Runtime::yield_if_writer_waiting` and
+//! `Runtime::set_yield_at_dependency_reads`: a long-running query
+//! yields to a pending write instead of making it wait.
+
+use salsa::{Cancelled, Database, ParallelDatabase};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The number of steps of the slow queries, which take 2ms each.
+const STEPS: usize = 500;
+
+#[salsa::query_group(YieldStorage)]
+trait YieldDatabase: salsa::Database + HasSteps {
+    #[salsa::input]
+    fn input(&self) -> usize;
+
+    /// Reads `input` at each step.
+    fn slow_reads(&self) -> usize;
+
+    /// Reads `input` once, and calls `yield_if_writer_waiting` at each
+    /// step.
+    fn slow_work(&self) -> usize;
+}
+
+trait HasSteps {
+    fn steps(&self) -> &AtomicUsize;
+}
+
+fn slow_reads(db: &impl YieldDatabase) -> usize {
+    let mut sum = 0;
+    for _ in 0..STEPS {
+        sum += db.input();
+        db.steps().fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    sum
+}
+
+fn slow_work(db: &impl YieldDatabase) -> usize {
+    let input = db.input();
+    for _ in 0..STEPS {
+        db.salsa_runtime().yield_if_writer_waiting();
+        db.steps().fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    input * STEPS
+}
+
+#[salsa::database(YieldStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    steps: Arc<AtomicUsize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            steps: self.steps.clone(),
+        })
+    }
+}
+
+impl HasSteps for DatabaseImpl {
+    fn steps(&self) -> &AtomicUsize {
+        &self.steps
+    }
+}
+
+/// Runs `query` in another thread and, once it made a few steps, sets
+/// the input; returns whether the query was cancelled, and the number
+/// of steps it made.
+fn set_while_running(db: &mut DatabaseImpl, query: fn(&DatabaseImpl) -> usize) -> (bool, usize) {
+    db.set_input(1);
+    let thread = std::thread::spawn({
+        let db = db.snapshot();
+        move || Cancelled::catch(AssertUnwindSafe(|| query(&db))).is_err()
+    });
+    while db.steps.load(Ordering::SeqCst) < 3 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    db.set_input(2);
+    let cancelled = thread.join().unwrap();
+    (cancelled, db.steps.swap(0, Ordering::SeqCst))
+}
+
+#[test]
+fn query_yields_at_dependency_reads() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_yield_at_dependency_reads(true);
+    let (cancelled, steps) = set_while_running(&mut db, |db| db.slow_reads());
+    assert!(cancelled);
+    assert!(steps < STEPS, "{} steps", steps);
+
+    assert_eq!(db.slow_reads(), 2 * STEPS);
+}
+
+#[test]
+fn query_yields_explicitly() {
+    let mut db = DatabaseImpl::default();
+    let (cancelled, steps) = set_while_running(&mut db, |db| db.slow_work());
+    assert!(cancelled);
+    assert!(steps < STEPS, "{} steps", steps);
+}
+
+#[test]
+fn query_does_not_yield_by_default() {
+    let mut db = DatabaseImpl::default();
+    let (cancelled, steps) = set_while_running(&mut db, |db| db.slow_reads());
+    assert!(!cancelled);
+    assert_eq!(steps, STEPS);
+}
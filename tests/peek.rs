@@ -0,0 +1,97 @@
+//! Test `QueryTable::peek`, which returns memoized values without
+//! computing them.
+
+use salsa::{Database, InternId};
+use std::cell::Cell;
+
+#[salsa::query_group(PeekStorage)]
+trait PeekDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> Name;
+}
+
+trait HasCounter {
+    fn count(&self);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Name(InternId);
+
+impl salsa::InternKey for Name {
+    fn from_intern_id(v: InternId) -> Self {
+        Name(v)
+    }
+
+    fn as_intern_id(&self) -> InternId {
+        self.0
+    }
+}
+
+fn double(db: &impl PeekDatabase, key: u32) -> u32 {
+    db.count();
+    db.input(key) * 2
+}
+
+#[salsa::database(PeekStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Cell<usize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn count(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+}
+
+#[test]
+fn peek_derived() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.query(DoubleQuery).peek(1), None);
+    assert_eq!(db.executions.get(), 0);
+
+    assert_eq!(db.double(1), 20);
+    assert_eq!(db.query(DoubleQuery).peek(1), Some(20));
+    assert_eq!(db.executions.get(), 1);
+
+    // After a new revision, the memo has to be revalidated first.
+    db.set_input(2, 0);
+    assert_eq!(db.query(DoubleQuery).peek(1), None);
+    assert_eq!(db.double(1), 20);
+    assert_eq!(db.query(DoubleQuery).peek(1), Some(20));
+    assert_eq!(db.executions.get(), 1);
+}
+
+#[test]
+fn peek_input() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(db.query(InputQuery).peek(1), None);
+    db.set_input(1, 10);
+    assert_eq!(db.query(InputQuery).peek(1), Some(10));
+}
+
+#[test]
+fn peek_interned() {
+    let db = DatabaseImpl::default();
+    assert_eq!(db.query(InternNameQuery).peek("a".to_string()), None);
+
+    let a = db.intern_name("a".to_string());
+    assert_eq!(db.query(InternNameQuery).peek("a".to_string()), Some(a));
+    assert_eq!(
+        db.query(InternNameLookupQuery).peek(a),
+        Some("a".to_string())
+    );
+}
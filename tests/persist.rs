@@ -0,0 +1,217 @@
+//! Test saving memoized results and loading them into a new database.
+#![cfg(feature = "persist")]
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{Database, FileMemoCache, InternId};
+
+#[salsa::query_group(PersistStorage)]
+trait PersistDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    #[salsa::persist]
+    fn input(&self, key: u32) -> u32;
+
+    #[salsa::persist]
+    fn double(&self, key: u32) -> u32;
+
+    #[salsa::interned]
+    #[salsa::persist]
+    fn intern_name(&self, name: String) -> InternId;
+
+    #[salsa::persist]
+    fn name_len(&self, name: InternId) -> usize;
+
+    fn not_persisted(&self, key: u32) -> u32;
+
+    #[salsa::persist]
+    fn uses_not_persisted(&self, key: u32) -> u32;
+
+    #[salsa::persist]
+    fn untracked(&self) -> u32;
+}
+
+fn double(db: &impl PersistDatabase, key: u32) -> u32 {
+    db.log().add(format!("double({})", key));
+    db.input(key) * 2
+}
+
+fn name_len(db: &impl PersistDatabase, name: InternId) -> usize {
+    db.log()
+        .add(format!("name_len({})", db.lookup_intern_name(name)));
+    db.lookup_intern_name(name).len()
+}
+
+fn not_persisted(db: &impl PersistDatabase, key: u32) -> u32 {
+    db.log().add(format!("not_persisted({})", key));
+    db.input(key) + 1
+}
+
+fn uses_not_persisted(db: &impl PersistDatabase, key: u32) -> u32 {
+    db.log().add(format!("uses_not_persisted({})", key));
+    db.not_persisted(key)
+}
+
+fn untracked(db: &impl PersistDatabase) -> u32 {
+    db.log().add("untracked");
+    db.salsa_runtime().report_untracked_read();
+    22
+}
+
+#[salsa::database(PersistStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+fn saved_database() -> Vec<u8> {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    db.set_input(2, 20);
+    assert_eq!(db.double(1), 20);
+    assert_eq!(db.double(2), 40);
+    let name = db.intern_name("hello".to_string());
+    assert_eq!(db.name_len(name), 5);
+    assert_eq!(db.uses_not_persisted(1), 11);
+    assert_eq!(db.untracked(), 22);
+
+    let mut bytes = vec![];
+    db.serialize_memos(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn reuse_loaded_memos() {
+    let bytes = saved_database();
+
+    let mut db = DatabaseImpl::default();
+    db.deserialize_memos(&mut &bytes[..]).unwrap();
+
+    // Loaded values are reused without re-executing anything...
+    assert_eq!(db.input(1), 10);
+    assert_eq!(db.double(1), 20);
+    assert_eq!(db.double(2), 40);
+    let name = db.intern_name("hello".to_string());
+    assert_eq!(db.name_len(name), 5);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    // ...except those that depend on queries that were not saved, or
+    // that read untracked state.
+    assert_eq!(db.uses_not_persisted(1), 11);
+    assert_eq!(db.untracked(), 22);
+    assert_eq!(
+        db.log().take(),
+        vec!["uses_not_persisted(1)", "not_persisted(1)", "untracked"]
+    );
+}
+
+#[test]
+fn revalidate_loaded_memos() {
+    let bytes = saved_database();
+
+    let mut db = DatabaseImpl::default();
+    db.deserialize_memos(&mut &bytes[..]).unwrap();
+
+    db.set_input(1, 11);
+    assert_eq!(db.double(1), 22);
+    assert_eq!(db.double(2), 40);
+    assert_eq!(db.log().take(), vec!["double(1)"]);
+
+    // Newly interned values do not clash with loaded ones.
+    let name = db.intern_name("hello".to_string());
+    let other = db.intern_name("world!".to_string());
+    assert_ne!(name, other);
+    assert_eq!(db.name_len(other), 6);
+}
+
+#[test]
+fn load_requires_fresh_database() {
+    let bytes = saved_database();
+
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    let err = db.deserialize_memos(&mut &bytes[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn load_garbage() {
+    let mut db = DatabaseImpl::default();
+    assert!(db.deserialize_memos(&mut &[1, 2, 3][..]).is_err());
+}
+
+#[test]
+fn share_memos_through_cache() {
+    let path = std::env::temp_dir().join(format!("salsa-memo-cache-{}", std::process::id()));
+    let cache = FileMemoCache::new(&path);
+
+    let mut db = DatabaseImpl::default();
+    assert!(!db.attach_memo_cache(&cache).unwrap());
+    db.set_input(1, 10);
+    assert_eq!(db.double(1), 20);
+    db.publish_memos(&cache).unwrap();
+
+    let mut db = DatabaseImpl::default();
+    assert!(db.attach_memo_cache(&cache).unwrap());
+    assert_eq!(db.input(1), 10);
+    assert_eq!(db.double(1), 20);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn export_and_import_interned() {
+    let db = DatabaseImpl::default();
+    let a = db.intern_name("a".to_string());
+    let b = db.intern_name("b".to_string());
+    let c = db.intern_name("c".to_string());
+    let mut bytes = vec![];
+    db.query(InternNameQuery)
+        .export_interned(&mut bytes)
+        .unwrap();
+
+    // The other database interns the values in a different order, but
+    // still assigns them the same ids.
+    let other = DatabaseImpl::default();
+    other
+        .query(InternNameQuery)
+        .import_interned(&mut &bytes[..])
+        .unwrap();
+    assert_eq!(other.intern_name("c".to_string()), c);
+    assert_eq!(other.intern_name("a".to_string()), a);
+    assert_eq!(other.lookup_intern_name(b), "b");
+
+    // New values get ids that were not taken yet.
+    let d = other.intern_name("d".to_string());
+    assert!(![a, b, c].contains(&d));
+}
+
+#[test]
+fn import_interned_requires_empty_table() {
+    let db = DatabaseImpl::default();
+    db.intern_name("a".to_string());
+    let mut bytes = vec![];
+    db.query(InternNameQuery)
+        .export_interned(&mut bytes)
+        .unwrap();
+
+    let err = db
+        .query(InternNameQuery)
+        .import_interned(&mut &bytes[..])
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,163 @@
+//! Test `ParallelDatabase::snapshot_at_current_revision`, whose
+//! snapshots keep reading `#[salsa::multi_version]` queries as of the
+//! revision they were created in.
+
+#![cfg(not(feature = "single-threaded"))]
+
+use salsa::{Database, ParallelDatabase};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[salsa::query_group(PinnedStorage)]
+trait PinnedDatabase: salsa::Database + HasExecutions {
+    #[salsa::input]
+    #[salsa::multi_version]
+    fn base(&self) -> u32;
+
+    #[salsa::input]
+    fn unversioned(&self) -> u32;
+
+    #[salsa::multi_version]
+    fn double(&self) -> u32;
+
+    #[salsa::multi_version]
+    fn plus_unversioned(&self) -> u32;
+}
+
+trait HasExecutions {
+    fn executions(&self) -> &AtomicUsize;
+}
+
+fn double(db: &impl PinnedDatabase) -> u32 {
+    db.executions().fetch_add(1, Ordering::SeqCst);
+    db.base() * 2
+}
+
+fn plus_unversioned(db: &impl PinnedDatabase) -> u32 {
+    db.base() + db.unversioned()
+}
+
+#[salsa::database(PinnedStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Arc<AtomicUsize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            executions: self.executions.clone(),
+        })
+    }
+}
+
+impl HasExecutions for DatabaseImpl {
+    fn executions(&self) -> &AtomicUsize {
+        &self.executions
+    }
+}
+
+impl DatabaseImpl {
+    fn take_executions(&self) -> usize {
+        self.executions.swap(0, Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn set_does_not_wait_for_pinned_snapshot() {
+    let mut db = DatabaseImpl::default();
+    db.set_base(1);
+    let snapshot = db.snapshot_at_current_revision();
+    let pinned = snapshot.salsa_runtime().pinned_revision().unwrap();
+
+    db.set_base(2);
+    assert_eq!(snapshot.salsa_runtime().pinned_revision(), Some(pinned));
+    assert!(db.salsa_runtime().current_revision() > pinned);
+    assert!(!snapshot.salsa_runtime().is_current_revision_canceled());
+
+    assert_eq!(snapshot.base(), 1);
+    assert_eq!(db.base(), 2);
+}
+
+#[test]
+fn derived_value_is_kept_for_pinned_snapshot() {
+    let mut db = DatabaseImpl::default();
+    db.set_base(1);
+    assert_eq!(db.double(), 2);
+    let snapshot = db.snapshot_at_current_revision();
+
+    db.set_base(2);
+    assert_eq!(db.double(), 4);
+    assert_eq!(db.take_executions(), 2);
+
+    assert_eq!(snapshot.double(), 2);
+    assert_eq!(db.take_executions(), 0);
+}
+
+#[test]
+fn derived_value_is_computed_at_pinned_revision() {
+    let mut db = DatabaseImpl::default();
+    db.set_base(1);
+    let snapshot = db.snapshot_at_current_revision();
+
+    db.set_base(2);
+    assert_eq!(snapshot.double(), 2);
+    assert_eq!(db.take_executions(), 1);
+
+    // The value is kept for further reads from the snapshot, without
+    // affecting the current one.
+    assert_eq!(snapshot.double(), 2);
+    assert_eq!(db.take_executions(), 0);
+    assert_eq!(db.double(), 4);
+    assert_eq!(db.take_executions(), 1);
+}
+
+#[test]
+fn unversioned_query_is_readable_until_database_moves_on() {
+    let mut db = DatabaseImpl::default();
+    db.set_base(1);
+    db.set_unversioned(10);
+    let snapshot = db.snapshot_at_current_revision();
+    assert_eq!(snapshot.unversioned(), 10);
+
+    db.set_base(2);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| snapshot.plus_unversioned()));
+    let message = result.unwrap_err();
+    assert!(message
+        .downcast_ref::<String>()
+        .unwrap()
+        .contains("is not `#[salsa::multi_version]`"));
+}
+
+#[test]
+fn snapshot_of_pinned_snapshot_is_pinned() {
+    let mut db = DatabaseImpl::default();
+    db.set_base(1);
+    let snapshot = db.snapshot_at_current_revision();
+    let nested = snapshot.snapshot();
+    assert_eq!(
+        nested.salsa_runtime().pinned_revision(),
+        snapshot.salsa_runtime().pinned_revision()
+    );
+
+    drop(snapshot);
+    db.set_base(2);
+    assert_eq!(nested.base(), 1);
+    assert_eq!(nested.double(), 2);
+}
+
+#[test]
+fn ordinary_snapshot_is_not_pinned() {
+    let db = DatabaseImpl::default();
+    let snapshot = db.snapshot();
+    assert_eq!(snapshot.salsa_runtime().pinned_revision(), None);
+}
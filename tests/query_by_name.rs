@@ -0,0 +1,89 @@
+//! Test invoking queries by name.
+#![cfg(feature = "dynamic")]
+
+use salsa::{Database, QueryByNameError};
+use serde_json::json;
+
+#[salsa::query_group(DynamicStorage)]
+trait DynamicDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::dynamic]
+    fn input(&self, key: u32) -> String;
+
+    #[salsa::dynamic]
+    fn length(&self, key: u32) -> usize;
+
+    #[salsa::dynamic]
+    fn concat(&self, key1: u32, key2: u32) -> String;
+
+    #[salsa::dynamic]
+    fn nothing(&self) -> Option<u32>;
+
+    fn not_dynamic(&self, key: u32) -> usize;
+}
+
+fn length(db: &impl DynamicDatabase, key: u32) -> usize {
+    db.input(key).len()
+}
+
+fn concat(db: &impl DynamicDatabase, key1: u32, key2: u32) -> String {
+    format!("{}{}", db.input(key1), db.input(key2))
+}
+
+fn nothing(_db: &impl DynamicDatabase) -> Option<u32> {
+    None
+}
+
+fn not_dynamic(db: &impl DynamicDatabase, key: u32) -> usize {
+    db.length(key)
+}
+
+#[salsa::database(DynamicStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn invoke() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "hello".to_string());
+    db.set_input(2, "world".to_string());
+
+    assert_eq!(db.query_by_name("input", json!([1])), Ok(json!("hello")));
+    assert_eq!(db.query_by_name("length", json!([2])), Ok(json!(5)));
+    assert_eq!(
+        db.query_by_name("concat", json!([1, 2])),
+        Ok(json!("helloworld"))
+    );
+    assert_eq!(db.query_by_name("nothing", json!([])), Ok(json!(null)));
+}
+
+#[test]
+fn errors() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "hello".to_string());
+
+    assert_eq!(
+        db.query_by_name("not_dynamic", json!([1])),
+        Err(QueryByNameError::UnknownQuery("not_dynamic".to_string()))
+    );
+    match db.query_by_name("length", json!([1, 2])) {
+        Err(QueryByNameError::InvalidArguments {
+            query: "length", ..
+        }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match db.query_by_name("length", json!(["one"])) {
+        Err(QueryByNameError::InvalidArguments {
+            query: "length", ..
+        }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
@@ -0,0 +1,53 @@
+//! Test `Runtime::set_recursion_limit`.
+
+use salsa::{Database, RecursionLimitExceeded};
+
+#[salsa::query_group(RecursionStorage)]
+trait RecursionDatabase: salsa::Database {
+    fn depth(&self, n: u32) -> u32;
+}
+
+fn depth(db: &impl RecursionDatabase, n: u32) -> u32 {
+    if n == 0 {
+        0
+    } else {
+        db.depth(n - 1) + 1
+    }
+}
+
+#[salsa::database(RecursionStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn within_limit() {
+    let db = DatabaseImpl::default();
+    db.salsa_runtime().set_recursion_limit(Some(10));
+    assert_eq!(db.depth(9), 9);
+}
+
+#[test]
+fn limit_exceeded() {
+    let db = DatabaseImpl::default();
+    db.salsa_runtime().set_recursion_limit(Some(20));
+
+    let err = RecursionLimitExceeded::catch(|| db.depth(100)).unwrap_err();
+    assert_eq!(err.limit(), 20);
+    let queries = err.queries();
+    assert_eq!(queries.len(), 10);
+    assert!(queries[0].contains("depth(89)"), "{:?}", queries);
+    assert!(queries[9].contains("depth(80)"), "{:?}", queries);
+    assert!(err.to_string().contains("depth(80)"));
+
+    // Values memoized below the limit make deeper chains possible.
+    assert_eq!(db.depth(15), 15);
+    assert_eq!(db.depth(30), 30);
+}
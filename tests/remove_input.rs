@@ -0,0 +1,98 @@
+//! Test removing the values of input queries.
+
+use salsa::debug::DebugQueryTable;
+use salsa::Database;
+use std::cell::Cell;
+
+#[salsa::query_group(RemoveStorage)]
+trait RemoveDatabase: salsa::Database + Counter {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn maybe_double(&self, key: u32) -> Option<u32>;
+
+    fn double(&self, key: u32) -> u32;
+}
+
+trait Counter {
+    fn increment(&self);
+}
+
+fn maybe_double(db: &impl RemoveDatabase, key: u32) -> Option<u32> {
+    db.increment();
+    db.maybe_input(key).map(|value| value * 2)
+}
+
+fn double(db: &impl RemoveDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+#[salsa::database(RemoveStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Cell<usize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl Counter for DatabaseImpl {
+    fn increment(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+}
+
+#[test]
+fn remove_invalidates_dependents() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.maybe_double(1), Some(20));
+
+    db.remove_input(1);
+    assert_eq!(db.maybe_input(1), None);
+    assert_eq!(db.maybe_double(1), None);
+    assert_eq!(db.executions.get(), 2);
+    assert!(db.query(InputQuery).entries::<Vec<_>>().is_empty());
+
+    db.set_input(1, 11);
+    assert_eq!(db.maybe_double(1), Some(22));
+    assert_eq!(db.executions.get(), 3);
+}
+
+#[test]
+fn set_after_absent_read() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(db.maybe_double(1), None);
+
+    // Unrelated changes do not invalidate the absent read...
+    db.set_input(2, 20);
+    assert_eq!(db.maybe_double(1), None);
+    assert_eq!(db.executions.get(), 1);
+
+    // ...but setting the key does.
+    db.set_input(1, 10);
+    assert_eq!(db.maybe_double(1), Some(20));
+    assert_eq!(db.executions.get(), 2);
+}
+
+#[test]
+fn remove_missing_key() {
+    let mut db = DatabaseImpl::default();
+    db.query_mut(InputQuery).remove(1);
+    assert_eq!(db.query(InputQuery).get_maybe(1), None);
+}
+
+#[test]
+#[should_panic(expected = "was removed")]
+fn get_removed() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.double(1), 20);
+
+    db.query_mut(InputQuery).remove(1);
+    db.double(1);
+}
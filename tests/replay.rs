@@ -0,0 +1,126 @@
+//! Test `salsa::replay`: recording writes and top-level reads, and
+//! replaying them on a fresh database.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::replay;
+use salsa::{Database, Durability};
+
+#[salsa::query_group(ReplayStorage)]
+trait ReplayDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn text(&self, name: &'static str) -> String;
+
+    fn length(&self, name: &'static str) -> usize;
+
+    fn total_length(&self) -> usize;
+}
+
+fn length(db: &impl ReplayDatabase, name: &'static str) -> usize {
+    db.log().add(format!("length({})", name));
+    db.text(name).len()
+}
+
+fn total_length(db: &impl ReplayDatabase) -> usize {
+    db.log().add("total_length");
+    db.length("a") + db.length("b")
+}
+
+#[salsa::database(ReplayStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+/// Makes some writes and reads, returning the log of executed queries.
+fn session(db: &mut DatabaseImpl) -> Vec<String> {
+    db.transaction(|db| {
+        db.set_text("a", "xx".to_string());
+        db.set_text("b", "yyy".to_string());
+    });
+    assert_eq!(db.total_length(), 5);
+    db.query_mut(TextQuery)
+        .set_with_durability("b", "y".to_string(), Durability::HIGH);
+    assert_eq!(db.total_length(), 3);
+    db.query_mut(TextQuery).remove("a");
+    assert_eq!(db.query(TextQuery).get_maybe("a"), None);
+    db.log().take()
+}
+
+#[test]
+fn replay_reproduces_session() {
+    let mut db = DatabaseImpl::default();
+    replay::start_recording(&db);
+    let log = session(&mut db);
+    let recording = replay::stop_recording(&db);
+    assert_eq!(recording.len(), 7);
+
+    let mut fresh = DatabaseImpl::default();
+    let report = recording.replay(&mut fresh);
+    assert_eq!(fresh.log().take(), log);
+    assert_eq!(fresh.text("b"), "y");
+    assert_eq!(fresh.query(TextQuery).get_maybe("a"), None);
+
+    // Only the top-level reads are timed.
+    let reads: Vec<String> = report
+        .reads()
+        .iter()
+        .map(|read| format!("{:?}", read.database_key))
+        .collect();
+    assert_eq!(reads.len(), 3);
+    assert!(reads[0].contains("total_length"), "{:?}", reads);
+    assert!(report
+        .to_string()
+        .lines()
+        .last()
+        .unwrap()
+        .starts_with("total: recorded"));
+}
+
+#[test]
+fn replay_groups_writes_into_revisions() {
+    let mut db = DatabaseImpl::default();
+    replay::start_recording(&db);
+    session(&mut db);
+    let recording = replay::stop_recording(&db);
+
+    let mut fresh = DatabaseImpl::default();
+    fresh.salsa_runtime().set_revision_log_capacity(10);
+    recording.replay(&mut fresh);
+    let log = fresh.salsa_runtime().revision_log();
+    let changes: Vec<usize> = log.iter().map(|entry| entry.changes.len()).collect();
+    assert_eq!(changes, vec![2, 1, 1]);
+}
+
+#[test]
+fn nothing_is_recorded_after_stop() {
+    let mut db = DatabaseImpl::default();
+    assert!(replay::stop_recording(&db).is_empty());
+
+    replay::start_recording(&db);
+    db.set_text("a", "x".to_string());
+    let recording = replay::stop_recording(&db);
+    assert_eq!(recording.len(), 1);
+    assert_eq!(
+        format!("{:?}", recording),
+        "[TextQuery(\"a\") = \"x\" (Durability(0))]"
+    );
+
+    db.set_text("a", "y".to_string());
+    db.length("a");
+    assert!(replay::stop_recording(&db).is_empty());
+}
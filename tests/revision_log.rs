@@ -0,0 +1,130 @@
+//! Test `Runtime::revision_log`.
+
+use salsa::{Database, Durability, InputChange, RevisionLogEntry};
+
+#[salsa::query_group(LogStorage)]
+trait LogDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+}
+
+#[salsa::database(LogStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// Describes each entry of the log as `revision: change, ..`.
+fn log(db: &DatabaseImpl) -> Vec<String> {
+    db.salsa_runtime()
+        .revision_log()
+        .into_iter()
+        .map(|RevisionLogEntry { revision, changes }| {
+            let changes: Vec<String> = changes
+                .iter()
+                .map(|change| match change {
+                    InputChange::Set {
+                        database_key,
+                        durability,
+                    } => format!("set {:?} {:?}", database_key, durability),
+                    InputChange::DurabilityChanged {
+                        database_key,
+                        durability,
+                    } => format!("durability {:?} {:?}", database_key, durability),
+                    InputChange::Removed { database_key, .. } => {
+                        format!("removed {:?}", database_key)
+                    }
+                    InputChange::MemoSet {
+                        database_key,
+                        durability,
+                    } => format!("memo {:?} {:?}", database_key, durability),
+                    InputChange::Invalidated { database_key, .. } => {
+                        format!("invalidated {:?}", database_key)
+                    }
+                    InputChange::TokenInvalidated { token } => {
+                        format!("token {}", token.name())
+                    }
+                    InputChange::SyntheticWrite { durability } => {
+                        format!("synthetic {:?}", durability)
+                    }
+                })
+                .collect();
+            format!("{:?}: {}", revision, changes.join(", "))
+        })
+        .collect()
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    assert!(db.salsa_runtime().revision_log().is_empty());
+}
+
+#[test]
+fn records_changes() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_revision_log_capacity(10);
+
+    db.set_input(1, 1);
+    db.set_input_with_durability(2, 2, Durability::HIGH);
+    db.query_mut(InputQuery)
+        .set_durability(2, Durability::MEDIUM);
+    db.query_mut(InputQuery).remove(1);
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+
+    assert_eq!(
+        log(&db),
+        vec![
+            "R2: set __SalsaDatabaseKey { kind: LogStorage(input(1)) } Durability(0)",
+            "R3: set __SalsaDatabaseKey { kind: LogStorage(input(2)) } Durability(15)",
+            "R4: durability __SalsaDatabaseKey { kind: LogStorage(input(2)) } Durability(1)",
+            "R5: removed __SalsaDatabaseKey { kind: LogStorage(input(1)) }",
+            "R6: synthetic Durability(0)",
+        ]
+    );
+}
+
+#[test]
+fn transaction_is_one_entry() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_revision_log_capacity(10);
+
+    db.transaction(|db| {
+        db.set_input(1, 1);
+        db.set_input(2, 2);
+    });
+
+    assert_eq!(
+        log(&db),
+        vec![
+            "R2: set __SalsaDatabaseKey { kind: LogStorage(input(1)) } Durability(0), \
+             set __SalsaDatabaseKey { kind: LogStorage(input(2)) } Durability(0)",
+        ]
+    );
+}
+
+#[test]
+fn keeps_most_recent_revisions() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_revision_log_capacity(3);
+
+    for i in 0..5 {
+        db.set_input(i, i);
+    }
+    let revisions: Vec<String> = log(&db).iter().map(|l| l[..2].to_string()).collect();
+    assert_eq!(revisions, vec!["R4", "R5", "R6"]);
+
+    db.salsa_runtime().set_revision_log_capacity(1);
+    assert_eq!(log(&db).len(), 1);
+
+    db.salsa_runtime().set_revision_log_capacity(0);
+    db.set_input(0, 1);
+    assert!(db.salsa_runtime().revision_log().is_empty());
+}
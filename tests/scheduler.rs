@@ -0,0 +1,156 @@
+//! Test `salsa::testing::Scheduler`, which runs threads one at a time
+//! and decides where they switch.
+
+#![cfg(not(feature = "single-threaded"))]
+
+use salsa::testing::{ScheduledOp, ScheduledRun, Scheduler};
+use salsa::{ParallelDatabase, Snapshot};
+use std::collections::BTreeSet;
+
+#[salsa::query_group(SchedulerStorage)]
+trait SchedulerDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: char) -> usize;
+
+    fn double(&self, key: char) -> usize;
+
+    fn sum(&self) -> usize;
+
+    fn panics(&self) -> usize;
+}
+
+fn double(db: &impl SchedulerDatabase, key: char) -> usize {
+    db.input(key) * 2
+}
+
+fn sum(db: &impl SchedulerDatabase) -> usize {
+    db.double('a') + db.double('b')
+}
+
+fn panics(db: &impl SchedulerDatabase) -> usize {
+    db.salsa_runtime().report_untracked_read();
+    panic!("query panicked")
+}
+
+#[salsa::database(SchedulerStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+fn database() -> DatabaseImpl {
+    let mut db = DatabaseImpl::default();
+    db.set_input('a', 1);
+    db.set_input('b', 2);
+    db
+}
+
+/// Three threads that read the same queries, in different orders.
+fn readers(scheduler: &Scheduler, db: &DatabaseImpl) -> ScheduledRun<usize> {
+    let ops: Vec<ScheduledOp<'_, DatabaseImpl, usize>> = vec![
+        Box::new(|db| db.sum()),
+        Box::new(|db| db.double('b') + db.sum()),
+        Box::new(|db| db.double('a')),
+    ];
+    scheduler.run(db, ops)
+}
+
+fn values(run: &ScheduledRun<usize>) -> Vec<usize> {
+    run.results
+        .iter()
+        .map(|result| *result.as_ref().unwrap())
+        .collect()
+}
+
+#[test]
+fn same_seed_same_run() {
+    let first = readers(&Scheduler::new(7), &database());
+    let second = readers(&Scheduler::new(7), &database());
+    assert_eq!(values(&first), vec![6, 10, 2]);
+    assert_eq!(values(&second), vec![6, 10, 2]);
+    assert_eq!(first.choices, second.choices);
+    assert_eq!(first.trace, second.trace);
+}
+
+#[test]
+fn replay_choices() {
+    for seed in 0..20 {
+        let run = readers(&Scheduler::new(seed), &database());
+        let replayed = readers(&Scheduler::replay(run.choices.clone()), &database());
+        assert_eq!(values(&replayed), vec![6, 10, 2]);
+        assert_eq!(run.choices, replayed.choices);
+        assert_eq!(run.trace, replayed.trace);
+    }
+}
+
+#[test]
+fn any_choices_are_valid() {
+    // Whatever the choices (as a property test might generate and then
+    // shrink), the run completes.
+    for choices in [vec![], vec![5, 0, 17, 3], vec![1; 40], vec![usize::MAX]] {
+        let run = readers(&Scheduler::replay(choices), &database());
+        assert_eq!(values(&run), vec![6, 10, 2]);
+    }
+}
+
+#[test]
+fn explores_interleavings() {
+    let mut traces = BTreeSet::new();
+    let mut blocked = false;
+    for seed in 0..50 {
+        let run = readers(&Scheduler::new(seed), &database());
+        assert_eq!(values(&run), vec![6, 10, 2]);
+        blocked |= run.trace.iter().any(|line| line.ends_with(": Block"));
+        traces.insert(run.trace);
+    }
+    assert!(traces.len() > 1);
+    assert!(blocked, "no thread ever blocked on another");
+}
+
+#[test]
+fn blocked_thread_gets_released() {
+    // Thread 0 claims `double('a')`, then thread 1 runs, and blocks on
+    // it (without claiming it) until thread 0 has executed it.
+    let db = database();
+    let ops: Vec<ScheduledOp<'_, DatabaseImpl, usize>> =
+        vec![Box::new(|db| db.double('a')), Box::new(|db| db.double('a'))];
+    let run = Scheduler::replay(vec![0, 0, 1]).run(&db, ops);
+    assert_eq!(values(&run), vec![2, 2]);
+    assert_eq!(
+        run.trace,
+        vec![
+            "0: Claim",
+            "0: Execute",
+            "1: Block",
+            "0: Release { unblocked: [1] }",
+            "1: Unblock",
+        ]
+    );
+}
+
+#[test]
+fn panic_propagates_to_blocked_thread() {
+    let db = database();
+    let ops: Vec<ScheduledOp<'_, DatabaseImpl, usize>> =
+        vec![Box::new(|db| db.panics()), Box::new(|db| db.panics())];
+    let run = Scheduler::replay(vec![0, 0, 1]).run(&db, ops);
+    assert!(run.results.iter().all(|result| result.is_err()));
+    assert!(run.trace.contains(&"1: Block".to_string()));
+    assert!(run
+        .trace
+        .contains(&"0: Release { unblocked: [1] }".to_string()));
+}
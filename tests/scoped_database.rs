@@ -0,0 +1,107 @@
+//! Test a database that borrows from its environment (here, the text
+//! of a file) for as long as it lives.
+
+#![cfg(not(feature = "single-threaded"))]
+
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, ParallelDatabase, Snapshot};
+
+/// Gives the queries access to the environment.
+trait HasSource {
+    fn source(&self) -> &str;
+}
+
+#[salsa::query_group(ScopedStorage)]
+trait ScopedDatabase: salsa::Database + HasSource {
+    #[salsa::input]
+    fn offset(&self) -> usize;
+
+    fn line(&self, index: usize) -> String;
+
+    fn line_count(&self) -> usize;
+}
+
+// The source cannot change while the database borrows it, so reading it
+// needs no tracking.
+fn line(db: &impl ScopedDatabase, index: usize) -> String {
+    let index = index + db.offset();
+    db.source().lines().nth(index).unwrap_or("").to_string()
+}
+
+fn line_count(db: &impl ScopedDatabase) -> usize {
+    db.source().lines().count() - db.offset()
+}
+
+#[salsa::database(ScopedStorage)]
+struct DatabaseImpl<'env> {
+    runtime: salsa::Runtime<DatabaseImpl<'env>>,
+    source: &'env str,
+}
+
+impl<'env> DatabaseImpl<'env> {
+    fn new(source: &'env str) -> Self {
+        let mut db = DatabaseImpl {
+            runtime: Default::default(),
+            source,
+        };
+        db.set_offset(0);
+        db
+    }
+}
+
+impl<'env> salsa::Database for DatabaseImpl<'env> {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl<'env>> {
+        &self.runtime
+    }
+}
+
+impl<'env> ParallelDatabase for DatabaseImpl<'env> {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            source: self.source,
+        })
+    }
+}
+
+impl HasSource for DatabaseImpl<'_> {
+    fn source(&self) -> &str {
+        self.source
+    }
+}
+
+#[test]
+fn borrows_environment() {
+    let source = String::from("first\nsecond\nthird");
+    let mut db = DatabaseImpl::new(&source);
+    assert_eq!(db.line(1), "second");
+    assert_eq!(db.line_count(), 3);
+
+    db.set_offset(1);
+    assert_eq!(db.line(1), "third");
+    assert_eq!(db.line_count(), 2);
+    assert_eq!(
+        db.query(LineQuery).entries::<Vec<_>>().len(),
+        1,
+        "only `line(1)` was read"
+    );
+}
+
+#[test]
+fn scoped_threads() {
+    let source = String::from("first\nsecond\nthird");
+    let db = DatabaseImpl::new(&source);
+    let lines: Vec<String> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..3)
+            .map(|index| {
+                let db = db.snapshot();
+                scope.spawn(move || db.line(index))
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    });
+    assert_eq!(lines, vec!["first", "second", "third"]);
+}
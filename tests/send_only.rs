@@ -0,0 +1,113 @@
+//! Test `#[salsa::send_only]` queries, whose values are `Send` but not
+//! `Sync`.
+
+#![cfg(not(feature = "single-threaded"))]
+
+use salsa::{Database, ParallelDatabase, Snapshot};
+use std::cell::Cell;
+
+/// A value that is `Send` but not `Sync`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Counter {
+    count: Cell<u32>,
+}
+
+impl Counter {
+    fn new(count: u32) -> Self {
+        Counter {
+            count: Cell::new(count),
+        }
+    }
+}
+
+#[salsa::query_group(SendOnlyStorage)]
+trait SendOnlyDatabase: salsa::Database {
+    #[salsa::input]
+    #[salsa::send_only]
+    fn start(&self, key: u32) -> Counter;
+
+    #[salsa::send_only]
+    fn doubled(&self, key: u32) -> Counter;
+
+    fn total(&self, key: u32) -> u32;
+}
+
+fn doubled(db: &impl SendOnlyDatabase, key: u32) -> Counter {
+    Counter::new(db.start(key).count.get() * 2)
+}
+
+fn total(db: &impl SendOnlyDatabase, key: u32) -> u32 {
+    db.start(key).count.get() + db.doubled(key).count.get()
+}
+
+#[salsa::database(SendOnlyStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> Snapshot<Self> {
+        Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+        })
+    }
+}
+
+/// The database is `Send` (as are its snapshots) only if the storage
+/// of its values is `Sync`.
+fn is_send<T: Send>(_: &T) {}
+
+#[test]
+fn accessors_return_clones() {
+    let mut db = DatabaseImpl::default();
+    db.set_start(1, Counter::new(3));
+
+    // Changing the clone does not change the stored value.
+    let start = db.start(1);
+    start.count.set(100);
+    assert_eq!(db.start(1), Counter::new(3));
+    assert_eq!(db.doubled(1), Counter::new(6));
+    assert_eq!(db.maybe_start(1), Some(Counter::new(3)));
+    assert_eq!(db.maybe_start(2), None);
+
+    let stored: salsa::SendOnly<Counter> = db.query(StartQuery).get(1);
+    assert_eq!(stored.get(), Counter::new(3));
+}
+
+#[test]
+fn values_are_updated() {
+    let mut db = DatabaseImpl::default();
+    db.set_start(1, Counter::new(3));
+    assert_eq!(db.total(1), 9);
+
+    db.set_start(1, Counter::new(4));
+    assert_eq!(db.total(1), 12);
+}
+
+#[test]
+fn snapshots_read_on_other_threads() {
+    let mut db = DatabaseImpl::default();
+    is_send(&db);
+    db.set_start(1, Counter::new(3));
+    db.set_start(2, Counter::new(5));
+    assert_eq!(db.doubled(1), Counter::new(6));
+
+    let threads: Vec<_> = (1..=2)
+        .map(|key| {
+            let db = db.snapshot();
+            std::thread::spawn(move || db.total(key))
+        })
+        .collect();
+    let totals: Vec<u32> = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect();
+    assert_eq!(totals, vec![9, 15]);
+}
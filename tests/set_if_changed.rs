@@ -0,0 +1,60 @@
+//! Test `QueryTableMut::set_if_changed`.
+
+use salsa::{Database, Durability};
+
+#[salsa::query_group(SetIfChangedStorage)]
+trait SetIfChangedDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> String;
+}
+
+#[salsa::database(SetIfChangedStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn equal_value_keeps_revision() {
+    let mut db = DatabaseImpl::default();
+    assert!(db.query_mut(InputQuery).set_if_changed(1, "a".to_string()));
+    let revision = db.salsa_runtime().current_revision();
+
+    assert!(!db.query_mut(InputQuery).set_if_changed(1, "a".to_string()));
+    assert_eq!(db.salsa_runtime().current_revision(), revision);
+
+    assert!(db.query_mut(InputQuery).set_if_changed(1, "b".to_string()));
+    assert!(db.salsa_runtime().current_revision() > revision);
+    assert_eq!(db.input(1), "b");
+}
+
+#[test]
+fn different_durability_is_a_change() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, "a".to_string(), Durability::HIGH);
+    let revision = db.salsa_runtime().current_revision();
+
+    assert!(db.query_mut(InputQuery).set_if_changed(1, "a".to_string()));
+    assert!(db.salsa_runtime().current_revision() > revision);
+
+    assert!(!db.query_mut(InputQuery).set_with_durability_if_changed(
+        1,
+        "a".to_string(),
+        Durability::LOW
+    ));
+}
+
+#[test]
+fn removed_value_is_a_change() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, "a".to_string());
+    db.remove_input(1);
+    assert!(db.query_mut(InputQuery).set_if_changed(1, "a".to_string()));
+    assert_eq!(db.input(1), "a");
+}
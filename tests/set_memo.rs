@@ -0,0 +1,108 @@
+//! Test `QueryTableMut::set_memo`, which seeds the memoized values of
+//! derived queries.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{Database, Durability};
+
+#[salsa::query_group(SetMemoStorage)]
+trait SetMemoDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    fn quadruple(&self, key: u32) -> u32;
+}
+
+fn double(db: &impl SetMemoDatabase, key: u32) -> u32 {
+    db.log().add(format!("double({})", key));
+    db.input(key) * 2
+}
+
+fn quadruple(db: &impl SetMemoDatabase, key: u32) -> u32 {
+    db.log().add(format!("quadruple({})", key));
+    db.double(key) * 2
+}
+
+#[salsa::database(SetMemoStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn seeded_value_is_used() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    db.query_mut(DoubleQuery).set_memo(1, 20, Durability::LOW);
+
+    assert_eq!(db.quadruple(1), 40);
+    assert_eq!(db.log().take(), vec!["quadruple(1)"]);
+
+    // Once a low-durability input changes, the value is recomputed, as
+    // are the queries that read it.
+    db.set_input(2, 0);
+    assert_eq!(db.quadruple(1), 40);
+    assert_eq!(db.log().take(), vec!["quadruple(1)", "double(1)"]);
+
+    // From then on, its inputs are tracked.
+    db.set_input(2, 1);
+    assert_eq!(db.quadruple(1), 40);
+    assert!(db.log().take().is_empty());
+}
+
+#[test]
+fn durable_seeded_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::HIGH);
+    db.query_mut(DoubleQuery).set_memo(1, 20, Durability::HIGH);
+
+    db.set_input(2, 0);
+    assert_eq!(db.double(1), 20);
+    assert!(db.log().take().is_empty());
+
+    db.set_input_with_durability(1, 5, Durability::HIGH);
+    assert_eq!(db.double(1), 10);
+    assert_eq!(db.log().take(), vec!["double(1)"]);
+}
+
+#[test]
+fn replaced_value_invalidates_readers() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.quadruple(1), 40);
+    db.log().take();
+
+    db.query_mut(DoubleQuery).set_memo(1, 100, Durability::LOW);
+    assert_eq!(db.quadruple(1), 200);
+    assert_eq!(db.log().take(), vec!["quadruple(1)"]);
+}
+
+#[test]
+fn durable_readers_invalidated() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::HIGH);
+    assert_eq!(db.quadruple(1), 40);
+    db.log().take();
+
+    // `quadruple(1)` only depends on high-durability values, but must
+    // still see the new value of `double(1)`.
+    db.query_mut(DoubleQuery).set_memo(1, 100, Durability::HIGH);
+    assert_eq!(db.quadruple(1), 200);
+    assert_eq!(db.log().take(), vec!["quadruple(1)"]);
+}
@@ -0,0 +1,145 @@
+//! Test `#[salsa::shallow_revalidation]` queries, which re-execute
+//! rather than validate the queries they depend on.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::{Database, InvalidationReason};
+
+#[salsa::query_group(ShallowStorage)]
+trait ShallowDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn length(&self, key: u32) -> usize;
+
+    fn is_long(&self, key: u32) -> bool;
+
+    #[salsa::shallow_revalidation]
+    fn shallow(&self, key: u32) -> bool;
+
+    fn deep(&self, key: u32) -> bool;
+
+    fn describe(&self, key: u32) -> &'static str;
+}
+
+fn length(db: &impl ShallowDatabase, key: u32) -> usize {
+    db.log().add(format!("length({})", key));
+    db.input(key).to_string().len()
+}
+
+fn is_long(db: &impl ShallowDatabase, key: u32) -> bool {
+    db.log().add(format!("is_long({})", key));
+    db.length(key) > 2
+}
+
+fn shallow(db: &impl ShallowDatabase, key: u32) -> bool {
+    db.log().add(format!("shallow({})", key));
+    db.is_long(key)
+}
+
+fn deep(db: &impl ShallowDatabase, key: u32) -> bool {
+    db.log().add(format!("deep({})", key));
+    db.is_long(key)
+}
+
+fn describe(db: &impl ShallowDatabase, key: u32) -> &'static str {
+    db.log().add(format!("describe({})", key));
+    if db.shallow(key) {
+        "long"
+    } else {
+        "short"
+    }
+}
+
+#[salsa::database(ShallowStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn shallow_query_reexecutes() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime().set_invalidation_tracing(true);
+    db.set_input(1, 10);
+    assert!(!db.shallow(1));
+    assert_eq!(
+        db.log().take(),
+        vec!["shallow(1)", "is_long(1)", "length(1)"]
+    );
+
+    // `length(1)` is backdated, so `is_long(1)` is not re-executed; but
+    // `shallow(1)` is, as `is_long(1)` was not validated yet.
+    db.set_input(1, 20);
+    assert!(!db.shallow(1));
+    assert_eq!(db.log().take(), vec!["shallow(1)", "length(1)"]);
+    match db.query(ShallowQuery).last_invalidation_reason(1) {
+        Some(InvalidationReason::InputUnverified(input)) => {
+            assert!(format!("{:?}", input).contains("is_long(1)"))
+        }
+        reason => panic!("unexpected reason: {:?}", reason),
+    }
+}
+
+#[test]
+fn deep_query_validates() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert!(!db.deep(1));
+    db.log().take();
+
+    db.set_input(1, 20);
+    assert!(!db.deep(1));
+    assert_eq!(db.log().take(), vec!["length(1)"]);
+}
+
+#[test]
+fn verified_inputs_are_trusted() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert!(!db.shallow(1));
+    db.log().take();
+
+    // Once `is_long(1)` is validated in this revision, `shallow(1)` can
+    // be validated against it without re-executing.
+    db.set_input(1, 20);
+    assert!(!db.is_long(1));
+    assert!(!db.shallow(1));
+    assert_eq!(db.log().take(), vec!["length(1)"]);
+
+    db.set_input(1, 100);
+    assert!(db.is_long(1));
+    assert!(db.shallow(1));
+    assert_eq!(
+        db.log().take(),
+        vec!["length(1)", "is_long(1)", "shallow(1)"]
+    );
+}
+
+#[test]
+fn shallow_query_backdates() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    assert_eq!(db.describe(1), "short");
+    db.log().take();
+
+    // `shallow(1)` is re-executed, but its value is unchanged, so the
+    // queries depending on it are not.
+    db.set_input(1, 20);
+    assert_eq!(db.describe(1), "short");
+    assert_eq!(db.log().take(), vec!["shallow(1)", "length(1)"]);
+}
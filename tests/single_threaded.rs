@@ -0,0 +1,111 @@
+//! Test the `single-threaded` feature, with which callbacks and
+//! contexts need not be `Send` or `Sync`, and reading a query that is
+//! in progress in another runtime reports a cycle instead of blocking.
+
+#![cfg(feature = "single-threaded")]
+
+use salsa::Database;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[salsa::query_group(SingleThreadedStorage)]
+trait SingleThreadedDatabase: salsa::Database {
+    #[salsa::input]
+    fn text(&self) -> String;
+
+    fn length(&self) -> usize;
+
+    fn outer(&self) -> String;
+}
+
+fn length(db: &impl SingleThreadedDatabase) -> usize {
+    if let Some(counter) = db.salsa_runtime().context::<Counter>() {
+        *counter.0.borrow_mut() += 1;
+    }
+    db.text().len()
+}
+
+fn outer(db: &impl SingleThreadedDatabase) -> String {
+    OTHER_HANDLE.with(|other| match &*other.borrow() {
+        Some(other) => match other.query(OuterQuery).try_get(()) {
+            Ok(value) => value,
+            Err(cycle) => format!("cycle through {}", cycle.cycle()[0]),
+        },
+        None => db.text(),
+    })
+}
+
+thread_local! {
+    /// A second handle on the database, used from within `outer`.
+    static OTHER_HANDLE: RefCell<Option<DatabaseImpl>> = const { RefCell::new(None) };
+}
+
+/// A context value that is neither `Send` nor `Sync`.
+struct Counter(Rc<RefCell<usize>>);
+
+#[salsa::database(SingleThreadedStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn rc_event_listener_and_context() {
+    let mut db = DatabaseImpl::default();
+    let events = Rc::new(RefCell::new(vec![]));
+    db.salsa_runtime().subscribe_events({
+        let events = events.clone();
+        Box::new(move |event| events.borrow_mut().push(format!("{:?}", event.kind)))
+    });
+    let executions = Rc::new(RefCell::new(0));
+    db.salsa_runtime().set_context(Counter(executions.clone()));
+
+    db.set_text("abc".to_string());
+    assert_eq!(db.length(), 3);
+    assert_eq!(db.length(), 3);
+    assert_eq!(*executions.borrow(), 1);
+    // No events are reported with the `strip-logging` feature.
+    assert_eq!(
+        events
+            .borrow()
+            .iter()
+            .any(|event| event.starts_with("WillExecute")),
+        cfg!(not(feature = "strip-logging"))
+    );
+}
+
+#[test]
+fn rc_watch_callback() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("abc".to_string());
+    let seen = Rc::new(RefCell::new(vec![]));
+    db.watch(LengthQuery, (), {
+        let seen = seen.clone();
+        move |length: &usize| seen.borrow_mut().push(*length)
+    });
+
+    db.set_text("abcd".to_string());
+    db.poll_watches();
+    assert_eq!(*seen.borrow(), vec![4]);
+}
+
+#[test]
+fn in_progress_in_other_handle_is_a_cycle() {
+    let mut db = DatabaseImpl::default();
+    db.set_text("abc".to_string());
+    let other = DatabaseImpl {
+        runtime: db.salsa_runtime().snapshot(&db),
+    };
+    OTHER_HANDLE.with(|handle| *handle.borrow_mut() = Some(other));
+
+    // Blocking until `db` completes `outer` would never return.
+    let value = db.outer();
+    OTHER_HANDLE.with(|handle| *handle.borrow_mut() = None);
+    assert_eq!(value, "cycle through outer()");
+}
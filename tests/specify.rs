@@ -0,0 +1,142 @@
+//! Test `QueryTable::specify`, with which one query computes the values
+//! of other queries as a side product.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::Database;
+use std::sync::Arc;
+
+#[salsa::query_group(SpecifyStorage)]
+trait SpecifyDatabase: salsa::Database + HasLog {
+    /// The items of a module, with their sizes.
+    #[salsa::input]
+    fn module_items(&self, module: u32) -> Arc<Vec<(String, u32)>>;
+
+    /// Lowers a module: specifies `item_size` for all its items, and
+    /// returns the number of items.
+    fn lower_module(&self, module: u32) -> usize;
+
+    /// Only ever specified by `lower_module`.
+    #[salsa::specifiable]
+    fn item_size(&self, item: String) -> u32;
+
+    fn describe_item(&self, item: String) -> String;
+}
+
+fn lower_module(db: &impl SpecifyDatabase, module: u32) -> usize {
+    db.log().add(format!("lower_module({})", module));
+    let items = db.module_items(module);
+    for (item, size) in items.iter() {
+        db.specify_item_size(item.clone(), *size);
+    }
+    items.len()
+}
+
+fn item_size(db: &impl SpecifyDatabase, item: String) -> u32 {
+    db.log().add(format!("item_size({})", item));
+    0
+}
+
+fn describe_item(db: &impl SpecifyDatabase, item: String) -> String {
+    db.log().add(format!("describe_item({})", item));
+    format!("{} has size {}", item, db.item_size(item.clone()))
+}
+
+#[salsa::database(SpecifyStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+fn items(items: &[(&str, u32)]) -> Arc<Vec<(String, u32)>> {
+    Arc::new(
+        items
+            .iter()
+            .map(|&(item, size)| (item.to_string(), size))
+            .collect(),
+    )
+}
+
+#[test]
+fn specified_values_are_used() {
+    let mut db = DatabaseImpl::default();
+    db.set_module_items(0, items(&[("a", 1), ("b", 2)]));
+
+    assert_eq!(db.lower_module(0), 2);
+    assert_eq!(db.describe_item("a".to_string()), "a has size 1");
+    assert_eq!(db.item_size("b".to_string()), 2);
+    assert_eq!(db.log().take(), vec!["lower_module(0)", "describe_item(a)"],);
+
+    // Keys that were not specified are computed by the query function.
+    assert_eq!(db.item_size("c".to_string()), 0);
+    assert_eq!(db.log().take(), vec!["item_size(c)"]);
+}
+
+#[test]
+fn specified_values_are_updated() {
+    let mut db = DatabaseImpl::default();
+    db.set_module_items(0, items(&[("a", 1), ("b", 2)]));
+    db.lower_module(0);
+    db.describe_item("a".to_string());
+    db.describe_item("b".to_string());
+    db.log().take();
+
+    // Reading a specified value brings the specifier up to date; the
+    // value of `a` is backdated, so `describe_item(a)` is reused.
+    db.set_module_items(0, items(&[("a", 1), ("b", 3)]));
+    assert_eq!(db.describe_item("a".to_string()), "a has size 1");
+    assert_eq!(db.log().take(), vec!["lower_module(0)"]);
+    assert_eq!(db.describe_item("b".to_string()), "b has size 3");
+    assert_eq!(db.log().take(), vec!["describe_item(b)"]);
+
+    // The same, if the specifier is re-executed first.
+    db.set_module_items(0, items(&[("a", 4), ("b", 3)]));
+    assert_eq!(db.lower_module(0), 2);
+    assert_eq!(db.describe_item("a".to_string()), "a has size 4");
+    assert_eq!(db.describe_item("b".to_string()), "b has size 3");
+    assert_eq!(db.log().take(), vec!["lower_module(0)", "describe_item(a)"]);
+}
+
+#[test]
+fn unchanged_specifier() {
+    let mut db = DatabaseImpl::default();
+    db.set_module_items(0, items(&[("a", 1)]));
+    db.set_module_items(1, items(&[]));
+    db.lower_module(0);
+    db.describe_item("a".to_string());
+    db.log().take();
+
+    db.set_module_items(1, items(&[("b", 2)]));
+    assert_eq!(db.describe_item("a".to_string()), "a has size 1");
+    assert!(db.log().take().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "after it was read in the same revision")]
+fn specify_after_read() {
+    let mut db = DatabaseImpl::default();
+    db.set_module_items(0, items(&[("a", 1)]));
+    db.item_size("a".to_string());
+    db.lower_module(0);
+}
+
+#[test]
+#[should_panic(expected = "specified outside of a query")]
+fn specify_outside_of_query() {
+    let db = DatabaseImpl::default();
+    db.specify(ItemSizeQuery, "a".to_string(), 1);
+}
@@ -0,0 +1,113 @@
+//! Test the collection of per-query execution statistics.
+
+use salsa::{Database, QueryStatistics, StatisticsMode};
+
+#[salsa::query_group(StatisticsStorage)]
+trait StatisticsDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn is_even(&self, key: u32) -> bool;
+
+    fn describe(&self, key: u32) -> String;
+}
+
+fn is_even(db: &impl StatisticsDatabase, key: u32) -> bool {
+    db.input(key) & 1 == 0
+}
+
+fn describe(db: &impl StatisticsDatabase, key: u32) -> String {
+    if db.is_even(key) {
+        "even".to_string()
+    } else {
+        "odd".to_string()
+    }
+}
+
+#[salsa::database(StatisticsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn counts(statistics: QueryStatistics) -> (u64, u64, u64) {
+    (
+        statistics.executions,
+        statistics.validations,
+        statistics.backdates,
+    )
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    db.describe(1);
+
+    assert_eq!(
+        db.query(IsEvenQuery).statistics(),
+        QueryStatistics::default()
+    );
+    assert!(db.salsa_statistics().is_empty());
+}
+
+#[test]
+fn per_query() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerQuery);
+
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    db.describe(1);
+    db.describe(2);
+    assert_eq!(counts(db.query(IsEvenQuery).statistics()), (2, 0, 0));
+    assert_eq!(counts(db.query(DescribeQuery).statistics()), (2, 0, 0));
+
+    // `is_even(1)` re-executes but produces the same value, so it is
+    // backdated and `describe(1)` is validated without executing.
+    db.set_input(1, 3);
+    db.describe(1);
+    assert_eq!(counts(db.query(IsEvenQuery).statistics()), (3, 0, 1));
+    assert_eq!(counts(db.query(DescribeQuery).statistics()), (2, 1, 0));
+
+    // Per-key statistics are not collected in this mode.
+    assert_eq!(
+        db.query(IsEvenQuery).key_statistics(1),
+        QueryStatistics::default()
+    );
+
+    let names: Vec<_> = db.salsa_statistics().iter().map(|s| s.0).collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&"is_even"));
+    assert!(names.contains(&"describe"));
+
+    db.salsa_runtime().reset_statistics();
+    assert!(db.salsa_statistics().is_empty());
+}
+
+#[test]
+fn per_key() {
+    let mut db = DatabaseImpl::default();
+    db.salsa_runtime()
+        .set_statistics_mode(StatisticsMode::PerKey);
+
+    db.set_input(1, 1);
+    db.set_input(2, 2);
+    db.describe(1);
+    db.describe(2);
+    db.set_input(2, 3);
+    db.describe(1);
+    db.describe(2);
+
+    assert_eq!(counts(db.query(IsEvenQuery).key_statistics(1)), (1, 1, 0));
+    assert_eq!(counts(db.query(IsEvenQuery).key_statistics(2)), (2, 0, 0));
+    assert_eq!(counts(db.query(DescribeQuery).key_statistics(2)), (2, 0, 0));
+    assert_eq!(counts(db.query(IsEvenQuery).statistics()), (3, 1, 0));
+}
@@ -0,0 +1,108 @@
+//! Test `Database::for_each_query_table` and `QueryStorageStats`.
+
+use salsa::Database;
+
+#[salsa::query_group(StatsStorage)]
+trait StatsDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    #[salsa::dependencies]
+    fn triple(&self, key: u32) -> u32;
+
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> salsa::InternId;
+
+    #[salsa::transparent]
+    fn quadruple(&self, key: u32) -> u32;
+
+    /// Returns the number of queries in progress in each table.
+    fn in_progress(&self) -> Vec<(&'static str, usize)>;
+}
+
+fn double(db: &impl StatsDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+fn triple(db: &impl StatsDatabase, key: u32) -> u32 {
+    db.input(key) * 3
+}
+
+fn quadruple(db: &impl StatsDatabase, key: u32) -> u32 {
+    db.double(key) * 2
+}
+
+fn in_progress(db: &impl StatsDatabase) -> Vec<(&'static str, usize)> {
+    let mut counts = Vec::new();
+    db.for_each_query_table(|name, stats| counts.push((name, stats.in_progress_count())));
+    counts
+}
+
+#[salsa::database(StatsStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn counts(db: &DatabaseImpl) -> Vec<(&'static str, usize, usize)> {
+    let mut counts = Vec::new();
+    db.for_each_query_table(|name, stats| {
+        counts.push((name, stats.slot_count(), stats.memoized_count()))
+    });
+    counts
+}
+
+#[test]
+fn counts_slots_and_memoized_values() {
+    let mut db = DatabaseImpl::default();
+    assert_eq!(
+        counts(&db),
+        vec![
+            ("input", 0, 0),
+            ("double", 0, 0),
+            ("triple", 0, 0),
+            ("intern_name", 0, 0),
+            ("in_progress", 0, 0),
+        ]
+    );
+
+    db.set_input(1, 10);
+    db.set_input(2, 20);
+    db.quadruple(1);
+    db.triple(1);
+    db.intern_name("a".to_string());
+    db.query_mut(InputQuery).remove(2);
+    assert_eq!(
+        counts(&db),
+        vec![
+            ("input", 2, 1),
+            ("double", 1, 1),
+            ("triple", 1, 0),
+            ("intern_name", 1, 1),
+            ("in_progress", 0, 0),
+        ]
+    );
+}
+
+#[test]
+fn counts_queries_in_progress() {
+    let db = DatabaseImpl::default();
+    assert_eq!(
+        db.in_progress(),
+        vec![
+            ("input", 0),
+            ("double", 0),
+            ("triple", 0),
+            ("intern_name", 0),
+            ("in_progress", 1),
+        ]
+    );
+}
@@ -0,0 +1,139 @@
+//! Test `QueryTable::stream`, which delivers the items of a query
+//! value while it is being computed.
+
+#![cfg(not(feature = "single-threaded"))]
+
+use salsa::{Database, ParallelDatabase};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+
+#[salsa::query_group(StreamStorage)]
+trait StreamDatabase: salsa::Database + HasKnobs {
+    #[salsa::input]
+    fn count(&self) -> u32;
+
+    /// Emits its first item, then waits on the barrier (if any) before
+    /// emitting the others.
+    fn diagnostics(&self) -> Vec<u32>;
+
+    /// Emits nothing; all items are delivered once it completes.
+    fn silent(&self) -> Vec<u32>;
+
+    fn panicky(&self) -> Vec<u32>;
+}
+
+trait HasKnobs {
+    fn barrier(&self) -> Option<Arc<Barrier>>;
+
+    fn executions(&self) -> &AtomicUsize;
+}
+
+fn diagnostics(db: &impl StreamDatabase) -> Vec<u32> {
+    db.executions().fetch_add(1, Ordering::SeqCst);
+    let mut items = vec![];
+    for i in 0..db.count() {
+        if i == 1 {
+            if let Some(barrier) = db.barrier() {
+                barrier.wait();
+            }
+        }
+        db.salsa_runtime().emit_partial(i);
+        items.push(i);
+    }
+    items
+}
+
+fn silent(db: &impl StreamDatabase) -> Vec<u32> {
+    (0..db.count()).collect()
+}
+
+fn panicky(db: &impl StreamDatabase) -> Vec<u32> {
+    db.salsa_runtime().emit_partial(0_u32);
+    panic!("panicky")
+}
+
+#[salsa::database(StreamStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    barrier: Option<Arc<Barrier>>,
+    executions: Arc<AtomicUsize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl ParallelDatabase for DatabaseImpl {
+    fn snapshot(&self) -> salsa::Snapshot<DatabaseImpl> {
+        salsa::Snapshot::new(DatabaseImpl {
+            runtime: self.runtime.snapshot(self),
+            barrier: self.barrier.clone(),
+            executions: self.executions.clone(),
+        })
+    }
+}
+
+impl HasKnobs for DatabaseImpl {
+    fn barrier(&self) -> Option<Arc<Barrier>> {
+        self.barrier.clone()
+    }
+
+    fn executions(&self) -> &AtomicUsize {
+        &self.executions
+    }
+}
+
+#[test]
+fn items_delivered_before_completion() {
+    let barrier = Arc::new(Barrier::new(2));
+    let mut db = DatabaseImpl {
+        barrier: Some(barrier.clone()),
+        ..Default::default()
+    };
+    db.set_count(4);
+
+    let mut stream = db.query(DiagnosticsQuery).stream(());
+    // The query is still blocked on the barrier at this point.
+    assert_eq!(stream.next(), Some(0));
+    barrier.wait();
+    assert_eq!(stream.collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn memoized_value_reused() {
+    let mut db = DatabaseImpl::default();
+    db.set_count(3);
+
+    let items: Vec<u32> = db.query(DiagnosticsQuery).stream(()).collect();
+    assert_eq!(items, vec![0, 1, 2]);
+    assert_eq!(db.diagnostics(), vec![0, 1, 2]);
+
+    let items: Vec<u32> = db.query(DiagnosticsQuery).stream(()).collect();
+    assert_eq!(items, vec![0, 1, 2]);
+    assert_eq!(db.executions.load(Ordering::SeqCst), 1);
+
+    db.set_count(2);
+    let items: Vec<u32> = db.query(DiagnosticsQuery).stream(()).collect();
+    assert_eq!(items, vec![0, 1]);
+    assert_eq!(db.executions.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn items_delivered_on_completion() {
+    let mut db = DatabaseImpl::default();
+    db.set_count(3);
+
+    let items: Vec<u32> = db.query(SilentQuery).stream(()).collect();
+    assert_eq!(items, vec![0, 1, 2]);
+}
+
+#[test]
+fn stream_ends_on_panic() {
+    let db = DatabaseImpl::default();
+
+    let items: Vec<u32> = db.query(PanickyQuery).stream(()).collect();
+    assert_eq!(items, vec![0]);
+}
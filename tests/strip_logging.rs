@@ -0,0 +1,55 @@
+//! Test that no events are reported with the `strip-logging` feature.
+#![cfg(feature = "strip-logging")]
+
+use salsa::Database;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[salsa::query_group(StripLoggingStorage)]
+trait StripLoggingDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+}
+
+fn double(db: &impl StripLoggingDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+#[salsa::database(StripLoggingStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    salsa_events: AtomicUsize,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+
+    fn salsa_event(&self, _event_fn: impl Fn() -> salsa::Event<Self>) {
+        self.salsa_events.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn no_events() {
+    let mut db = DatabaseImpl::default();
+    let listened = Arc::new(AtomicUsize::new(0));
+    db.salsa_runtime().subscribe_events({
+        let listened = listened.clone();
+        Box::new(move |_| {
+            listened.fetch_add(1, Ordering::SeqCst);
+        })
+    });
+
+    db.set_input(1, 21);
+    assert_eq!(db.double(1), 42);
+    db.set_input(1, 22);
+    assert_eq!(db.double(1), 44);
+
+    assert_eq!(db.salsa_events.load(Ordering::SeqCst), 0);
+    assert_eq!(listened.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,128 @@
+//! Test `Runtime::report_synthetic_read` and
+//! `Runtime::report_dependency`.
+
+use salsa::{Database, Durability};
+use std::cell::Cell;
+
+#[salsa::query_group(SyntheticStorage)]
+trait SyntheticDatabase: salsa::Database + External {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    /// Reads the external value, which only changes along with
+    /// high-durability inputs.
+    fn external_high(&self) -> u32;
+
+    /// Reads the external value as an untracked input.
+    fn external_untracked(&self) -> u32;
+
+    /// Declares a dependency on `input` without reading it.
+    fn depends_on_input(&self) -> u32;
+}
+
+/// State kept outside of salsa; the counter records how often the
+/// queries above are executed.
+trait External {
+    fn external(&self) -> u32;
+
+    fn count(&self);
+
+    /// Query functions only see `impl SyntheticDatabase`, which cannot
+    /// name the query tables, so this goes through the database type.
+    fn report_input_dependency(&self);
+}
+
+fn external_high(db: &impl SyntheticDatabase) -> u32 {
+    db.count();
+    db.salsa_runtime().report_synthetic_read(Durability::HIGH);
+    db.external()
+}
+
+fn external_untracked(db: &impl SyntheticDatabase) -> u32 {
+    db.count();
+    db.salsa_runtime().report_untracked_read();
+    db.external()
+}
+
+fn depends_on_input(db: &impl SyntheticDatabase) -> u32 {
+    db.count();
+    db.report_input_dependency();
+    db.external()
+}
+
+#[salsa::database(SyntheticStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    external: Cell<u32>,
+    executions: Cell<u32>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl External for DatabaseImpl {
+    fn external(&self) -> u32 {
+        self.external.get()
+    }
+
+    fn count(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+
+    fn report_input_dependency(&self) {
+        let database_key = self.query(InputQuery).database_key(());
+        self.salsa_runtime().report_dependency(self, &database_key);
+    }
+}
+
+#[test]
+fn synthetic_read_survives_low_durability_changes() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+    db.external.set(1);
+    assert_eq!(db.external_high(), 1);
+    assert_eq!(db.executions.get(), 1);
+
+    db.external.set(2);
+    db.set_input(1);
+    assert_eq!(db.external_high(), 1);
+    assert_eq!(db.executions.get(), 1);
+
+    db.salsa_runtime().synthetic_write(Durability::HIGH);
+    assert_eq!(db.external_high(), 2);
+    assert_eq!(db.executions.get(), 2);
+}
+
+#[test]
+fn untracked_read_reexecutes() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+    assert_eq!(db.external_untracked(), 0);
+
+    db.set_input(1);
+    assert_eq!(db.external_untracked(), 0);
+    assert_eq!(db.executions.get(), 2);
+}
+
+#[test]
+fn reported_dependency() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(0);
+    db.external.set(1);
+    assert_eq!(db.depends_on_input(), 1);
+
+    // Unrelated changes do not invalidate the query...
+    db.salsa_runtime().synthetic_write(Durability::LOW);
+    db.external.set(2);
+    assert_eq!(db.depends_on_input(), 1);
+    assert_eq!(db.executions.get(), 1);
+
+    // ...but changing the input it declared a dependency on does.
+    db.set_input(1);
+    assert_eq!(db.depends_on_input(), 2);
+    assert_eq!(db.executions.get(), 2);
+}
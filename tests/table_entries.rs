@@ -0,0 +1,102 @@
+//! Test the revision stamps reported by `DebugQueryTable::entries`.
+
+use salsa::debug::{DebugQueryTable, TableEntry};
+use salsa::{Database, Durability};
+
+#[salsa::query_group(EntriesStorage)]
+trait EntriesDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    fn untracked(&self) -> u32;
+
+    #[salsa::interned]
+    fn intern_name(&self, name: String) -> salsa::InternId;
+}
+
+fn double(db: &impl EntriesDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+fn untracked(db: &impl EntriesDatabase) -> u32 {
+    db.salsa_runtime().report_untracked_read();
+    22
+}
+
+#[salsa::database(EntriesStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn input_and_derived_stamps() {
+    let mut db = DatabaseImpl::default();
+    db.set_input_with_durability(1, 10, Durability::HIGH);
+    let r1 = db.salsa_runtime().current_revision();
+    assert_eq!(db.double(1), 20);
+
+    db.set_input(2, 20);
+    let r2 = db.salsa_runtime().current_revision();
+    assert_eq!(db.double(1), 20);
+
+    let mut inputs: Vec<TableEntry<u32, u32>> = db.query(InputQuery).entries();
+    inputs.sort_by_key(|e| e.key);
+    assert_eq!(inputs.len(), 2);
+    assert_eq!(inputs[1].changed_at, Some(r2));
+    assert_eq!(inputs[0].changed_at, Some(r1));
+    assert_eq!(inputs[0].verified_at, None);
+    assert_eq!(inputs[0].durability, Some(Durability::HIGH));
+    assert!(!inputs[0].has_untracked_input);
+
+    let derived: Vec<TableEntry<u32, u32>> = db.query(DoubleQuery).entries();
+    assert_eq!(derived.len(), 1);
+    assert_eq!(derived[0].value, Some(20));
+    assert_eq!(derived[0].changed_at, Some(r1));
+    assert_eq!(derived[0].verified_at, Some(r2));
+    assert_eq!(derived[0].durability, Some(Durability::HIGH));
+    assert!(!derived[0].has_untracked_input);
+}
+
+#[test]
+fn untracked_stamps() {
+    let db = DatabaseImpl::default();
+    assert_eq!(db.untracked(), 22);
+
+    let entries: Vec<TableEntry<(), u32>> = db.query(UntrackedQuery).entries();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].has_untracked_input);
+    assert_eq!(entries[0].durability, Some(Durability::LOW));
+}
+
+#[test]
+fn interned_stamps() {
+    let mut db = DatabaseImpl::default();
+    db.intern_name("a".to_string());
+    let r1 = db.salsa_runtime().current_revision();
+    db.set_input(1, 1);
+    db.intern_name("b".to_string());
+    let r2 = db.salsa_runtime().current_revision();
+
+    let mut entries: Vec<TableEntry<String, salsa::InternId>> = db.query(InternNameQuery).entries();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    let stamps: Vec<_> = entries
+        .iter()
+        .map(|e| (e.key.as_str(), e.changed_at, e.durability))
+        .collect();
+    assert_eq!(
+        stamps,
+        vec![
+            ("a", Some(r1), Some(Durability::HIGH)),
+            ("b", Some(r2), Some(Durability::HIGH)),
+        ]
+    );
+}
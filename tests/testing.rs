@@ -0,0 +1,100 @@
+//! Test `salsa::testing::TestDatabase`.
+
+use salsa::testing::TestDatabase;
+use std::cell::Cell;
+
+#[salsa::query_group(TestingStorage)]
+trait TestingDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn double(&self, key: u32) -> u32;
+
+    fn quadruple(&self, key: u32) -> u32;
+
+    /// Reads a counter that salsa does not know about.
+    fn counter_plus(&self, key: u32) -> u32;
+}
+
+trait HasCounter {
+    fn next_count(&self) -> u32;
+}
+
+fn double(db: &impl TestingDatabase, key: u32) -> u32 {
+    db.input(key) * 2
+}
+
+fn quadruple(db: &impl TestingDatabase, key: u32) -> u32 {
+    db.double(key) * 2
+}
+
+fn counter_plus(db: &impl TestingDatabase, key: u32) -> u32 {
+    db.next_count() + db.input(key)
+}
+
+#[salsa::database(TestingStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    counter: Cell<u32>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn next_count(&self) -> u32 {
+        self.counter.set(self.counter.get() + 1);
+        self.counter.get()
+    }
+}
+
+#[test]
+fn deterministic_queries_pass() {
+    let mut db = TestDatabase::new(DatabaseImpl::default());
+    db.set_input(1, 10);
+    assert_eq!(db.quadruple(1), 40);
+    assert_eq!(db.quadruple(1), 40);
+
+    db.set_input(2, 0);
+    assert_eq!(db.quadruple(1), 40);
+}
+
+#[test]
+#[should_panic(expected = "is not deterministic")]
+fn nondeterministic_query_detected() {
+    let mut db = TestDatabase::new(DatabaseImpl::default());
+    db.set_input(1, 10);
+    assert_eq!(db.counter_plus(1), 11);
+    db.counter_plus(1);
+}
+
+#[test]
+#[cfg(not(feature = "strip-logging"))]
+fn trace() {
+    let mut db = TestDatabase::new(DatabaseImpl::default());
+    db.set_input(1, 10);
+    assert_eq!(db.quadruple(1), 40);
+    assert_eq!(
+        db.take_trace(),
+        vec![
+            "WillChangeInputValue { database_key: __SalsaDatabaseKey { kind: TestingStorage(input(1)) } }",
+            "WillExecute { database_key: __SalsaDatabaseKey { kind: TestingStorage(quadruple(1)) } }",
+            "WillExecute { database_key: __SalsaDatabaseKey { kind: TestingStorage(double(1)) } }",
+        ]
+    );
+
+    db.set_input(2, 0);
+    assert_eq!(db.quadruple(1), 40);
+    assert_eq!(
+        db.take_trace(),
+        vec![
+            "WillChangeInputValue { database_key: __SalsaDatabaseKey { kind: TestingStorage(input(2)) } }",
+            "DidValidateMemoizedValue { database_key: __SalsaDatabaseKey { kind: TestingStorage(double(1)) } }",
+            "DidValidateMemoizedValue { database_key: __SalsaDatabaseKey { kind: TestingStorage(quadruple(1)) } }",
+        ]
+    );
+}
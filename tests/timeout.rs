@@ -0,0 +1,86 @@
+//! Test `Runtime::set_query_timeout`.
+
+use salsa::{Database, QueryTimedOut};
+use std::time::Duration;
+
+#[salsa::query_group(TimeoutStorage)]
+trait TimeoutDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    /// Loops until the input is zero, which it never becomes.
+    fn runaway(&self) -> u32;
+
+    /// Recomputes `step(n)` for decreasing `n`, never returning.
+    fn step(&self, n: u64) -> u32;
+
+    fn quick(&self) -> u32;
+}
+
+fn runaway(db: &impl TimeoutDatabase) -> u32 {
+    while db.input() != 0 {
+        db.salsa_runtime().unwind_if_cancelled();
+    }
+    0
+}
+
+fn step(db: &impl TimeoutDatabase, n: u64) -> u32 {
+    if n == 0 {
+        return db.input();
+    }
+    // Sleep so that the deadline passes even without explicit checks.
+    std::thread::sleep(Duration::from_millis(1));
+    db.step(n - 1)
+}
+
+fn quick(db: &impl TimeoutDatabase) -> u32 {
+    db.input() + 1
+}
+
+#[salsa::database(TimeoutStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+#[test]
+fn runaway_query_times_out() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.salsa_runtime()
+        .set_query_timeout(Some(Duration::from_millis(20)));
+
+    let result = QueryTimedOut::catch(|| db.runaway());
+    assert_eq!(result, Err(QueryTimedOut));
+
+    // The deadline is per outermost query: other queries still run.
+    assert_eq!(db.quick(), 2);
+}
+
+#[test]
+fn deep_query_times_out() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    db.salsa_runtime()
+        .set_query_timeout(Some(Duration::from_millis(20)));
+
+    let result = QueryTimedOut::catch(|| db.step(u64::MAX));
+    assert_eq!(result, Err(QueryTimedOut));
+
+    db.salsa_runtime().set_query_timeout(None);
+    assert_eq!(db.step(30), 1);
+}
+
+#[test]
+fn no_timeout_by_default() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1);
+    assert_eq!(db.step(30), 1);
+    assert_eq!(db.query(StepQuery).get(5), 1);
+}
@@ -0,0 +1,161 @@
+//! Test the `tracing` spans and events emitted with the `trace` feature.
+#![cfg(feature = "trace")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+#[salsa::query_group(TraceStorage)]
+trait TraceDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn parity(&self, key: u32) -> bool;
+
+    fn describe(&self, key: u32) -> String;
+}
+
+fn parity(db: &impl TraceDatabase, key: u32) -> bool {
+    db.input(key).is_multiple_of(2)
+}
+
+fn describe(db: &impl TraceDatabase, key: u32) -> String {
+    if db.parity(key) {
+        "even".to_string()
+    } else {
+        "odd".to_string()
+    }
+}
+
+#[salsa::database(TraceStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// Records spans as `name(field=value, ..)` and events as their
+/// `message` (followed by the other fields), one line each.
+#[derive(Clone, Default)]
+struct Recorder {
+    next_id: Arc<AtomicU64>,
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    others: Vec<String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.others.push(format!("{}={}", field.name(), value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.others.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.lines.lock().unwrap())
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with("salsa")
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        self.lines.lock().unwrap().push(format!(
+            "{}({})",
+            span.metadata().name(),
+            fields.others.join(", ")
+        ));
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut line = fields.message;
+        for other in fields.others {
+            line.push(' ');
+            line.push_str(&other);
+        }
+        self.lines.lock().unwrap().push(line);
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[test]
+fn spans_and_events() {
+    let recorder = Recorder::default();
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 2);
+
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert_eq!(db.describe(1), "even");
+    });
+    assert_eq!(
+        recorder.take(),
+        vec![
+            "salsa_query(query=describe, key=1)",
+            "executing query reason=NotComputed",
+            "salsa_query(query=parity, key=1)",
+            "executing query reason=NotComputed",
+        ]
+    );
+
+    db.set_input(1, 4);
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert_eq!(db.describe(1), "even");
+    });
+    let lines = recorder.take();
+    assert_eq!(lines[0], "salsa_query(query=describe, key=1)");
+    assert_eq!(lines[1], "salsa_query(query=parity, key=1)");
+    assert!(lines[2].starts_with("executing query reason="));
+    assert!(lines[3].starts_with("backdated value changed_at="));
+    assert_eq!(lines[4], "validated memoized value");
+    assert_eq!(lines.len(), 5);
+}
+
+#[test]
+fn memoized_reads_are_quiet() {
+    let recorder = Recorder::default();
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 1);
+    assert_eq!(db.describe(1), "odd");
+
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert_eq!(db.describe(1), "odd");
+    });
+    assert_eq!(recorder.take(), Vec::<String>::new());
+}
@@ -0,0 +1,133 @@
+//! Test `#[salsa::tracked]` queries, which create entities whose ids
+//! are tied to the query that created them.
+
+use salsa::debug::DebugQueryTable;
+use salsa::{Database, InternId, SweepStrategy};
+use std::sync::Arc;
+
+#[salsa::query_group(TrackedStorage)]
+trait TrackedDatabase: salsa::Database {
+    /// Lines of the form `name size`.
+    #[salsa::input]
+    fn file_text(&self, file: u32) -> Arc<String>;
+
+    /// Creates an item for each line of the file.
+    fn lower_file(&self, file: u32) -> Arc<Vec<ItemId>>;
+
+    #[salsa::tracked]
+    fn item(&self, name: String) -> ItemId;
+
+    /// Specified by `lower_file`.
+    #[salsa::specifiable]
+    fn item_size(&self, item: ItemId) -> u32;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct ItemId(InternId);
+
+impl salsa::InternKey for ItemId {
+    fn from_intern_id(v: InternId) -> Self {
+        ItemId(v)
+    }
+
+    fn as_intern_id(&self) -> InternId {
+        self.0
+    }
+}
+
+fn lower_file(db: &impl TrackedDatabase, file: u32) -> Arc<Vec<ItemId>> {
+    let text = db.file_text(file);
+    let items = text
+        .lines()
+        .map(|line| {
+            let mut words = line.split(' ');
+            let item = db.item(words.next().unwrap().to_string());
+            db.specify_item_size(item, words.next().unwrap().parse().unwrap());
+            item
+        })
+        .collect();
+    Arc::new(items)
+}
+
+fn item_size(_db: &impl TrackedDatabase, item: ItemId) -> u32 {
+    panic!("size of {:?} was not specified", item)
+}
+
+#[salsa::database(TrackedStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+fn entity_count(db: &DatabaseImpl) -> usize {
+    db.query(ItemQuery).entries::<Vec<_>>().len()
+}
+
+#[test]
+fn ids_are_stable() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text(0, Arc::new("a 1\nb 2".to_string()));
+    let items = db.lower_file(0);
+    assert_eq!(db.lookup_item(items[0]), "a");
+    assert_eq!(db.item_size(items[1]), 2);
+
+    // The items that are created again keep their ids.
+    db.set_file_text(0, Arc::new("c 3\nb 4\na 1".to_string()));
+    let new_items = db.lower_file(0);
+    assert_eq!(new_items[2], items[0]);
+    assert_eq!(new_items[1], items[1]);
+    assert!(!items.contains(&new_items[0]));
+    assert_eq!(db.lookup_item(new_items[0]), "c");
+    assert_eq!(db.item_size(new_items[1]), 4);
+}
+
+#[test]
+fn ids_depend_on_creator() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text(0, Arc::new("a 1".to_string()));
+    db.set_file_text(1, Arc::new("a 2".to_string()));
+    let items0 = db.lower_file(0);
+    let items1 = db.lower_file(1);
+    assert_ne!(items0[0], items1[0]);
+    assert_eq!(db.lookup_item(items1[0]), "a");
+    assert_eq!(db.item_size(items0[0]), 1);
+    assert_eq!(db.item_size(items1[0]), 2);
+}
+
+#[test]
+fn dead_entities_are_swept() {
+    let mut db = DatabaseImpl::default();
+    db.set_file_text(0, Arc::new("a 1\nb 2".to_string()));
+    let items = db.lower_file(0);
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_eq!(entity_count(&db), 2);
+
+    // Until the creator is executed again, `b` is still alive.
+    db.set_file_text(0, Arc::new("a 1".to_string()));
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_eq!(entity_count(&db), 2);
+
+    assert_eq!(*db.lower_file(0), vec![items[0]]);
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_eq!(entity_count(&db), 1);
+
+    // A key that is created again gets a new id.
+    db.set_file_text(0, Arc::new("a 1\nb 2".to_string()));
+    let new_items = db.lower_file(0);
+    assert_eq!(new_items[0], items[0]);
+    assert_ne!(new_items[1], items[1]);
+    assert_eq!(entity_count(&db), 2);
+}
+
+#[test]
+#[should_panic(expected = "created outside of a query")]
+fn create_outside_of_query() {
+    let db = DatabaseImpl::default();
+    db.item("a".to_string());
+}
@@ -0,0 +1,62 @@
+//! Test queries without arguments (whose key is `()`), which keep
+//! their single slot inline.
+
+use salsa::debug::DebugQueryTable;
+use salsa::Database;
+use std::cell::Cell;
+
+#[salsa::query_group(UnitKeyStorage)]
+trait UnitKeyDatabase: salsa::Database + HasCounter {
+    #[salsa::input]
+    fn input(&self) -> u32;
+
+    fn double(&self) -> u32;
+}
+
+trait HasCounter {
+    fn increment(&self);
+}
+
+fn double(db: &impl UnitKeyDatabase) -> u32 {
+    db.increment();
+    db.input() * 2
+}
+
+#[salsa::database(UnitKeyStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    executions: Cell<usize>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasCounter for DatabaseImpl {
+    fn increment(&self) {
+        self.executions.set(self.executions.get() + 1);
+    }
+}
+
+#[test]
+fn single_slot() {
+    let mut db = DatabaseImpl::default();
+    assert!(db.query(DoubleQuery).entries::<Vec<_>>().is_empty());
+
+    db.set_input(1);
+    assert_eq!(db.double(), 2);
+    assert_eq!(db.double(), 2);
+    assert_eq!(db.query(DoubleQuery).get(()), 2);
+    assert_eq!(db.executions.get(), 1);
+
+    db.set_input(2);
+    assert_eq!(db.double(), 4);
+    assert_eq!(db.executions.get(), 2);
+
+    let entries = db.query(DoubleQuery).entries::<Vec<_>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].value, Some(4));
+}
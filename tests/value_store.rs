@@ -0,0 +1,159 @@
+//! Test `#[salsa::value_store]` queries, whose values are kept in a
+//! `ValueStore` rather than in memory.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use salsa::debug::{DebugQueryTable, TableEntry};
+use salsa::{Database, SweepStrategy, ValueStore};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[salsa::query_group(ValueStoreStorage)]
+trait ValueStoreDatabase: salsa::Database + HasStore + HasLog {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    #[salsa::value_store(squares_store)]
+    fn squares(&self, key: u32) -> Vec<u32>;
+
+    fn total(&self, key: u32) -> u32;
+}
+
+trait HasStore {
+    fn store(&self) -> &JsonStore;
+}
+
+/// Keeps values serialized as JSON, as an on-disk store would.
+#[derive(Default)]
+struct JsonStore {
+    values: Mutex<HashMap<u32, String>>,
+}
+
+impl JsonStore {
+    fn keys(&self) -> Vec<u32> {
+        let mut keys: Vec<u32> = self.values.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn clear(&self) {
+        self.values.lock().unwrap().clear();
+    }
+}
+
+impl ValueStore<u32, Vec<u32>> for JsonStore {
+    fn store(&self, key: &u32, value: &Vec<u32>) {
+        let json = serde_json::to_string(value).unwrap();
+        self.values.lock().unwrap().insert(*key, json);
+    }
+
+    fn load(&self, key: &u32) -> Option<Vec<u32>> {
+        let values = self.values.lock().unwrap();
+        values
+            .get(key)
+            .map(|json| serde_json::from_str(json).unwrap())
+    }
+
+    fn remove(&self, key: &u32) {
+        self.values.lock().unwrap().remove(key);
+    }
+}
+
+fn squares_store(db: &impl ValueStoreDatabase) -> &dyn ValueStore<u32, Vec<u32>> {
+    db.store()
+}
+
+fn squares(db: &impl ValueStoreDatabase, key: u32) -> Vec<u32> {
+    db.log().add(format!("squares({})", key));
+    (0..db.input(key)).map(|i| i * i).collect()
+}
+
+fn total(db: &impl ValueStoreDatabase, key: u32) -> u32 {
+    db.log().add(format!("total({})", key));
+    db.squares(key).iter().sum()
+}
+
+#[salsa::database(ValueStoreStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    store: JsonStore,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasStore for DatabaseImpl {
+    fn store(&self) -> &JsonStore {
+        &self.store
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn values_loaded_from_store() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 3);
+
+    assert_eq!(db.squares(1), vec![0, 1, 4]);
+    assert_eq!(db.store.keys(), vec![1]);
+    assert_eq!(db.squares(1), vec![0, 1, 4]);
+    assert_eq!(db.log().take(), vec!["squares(1)"]);
+
+    // The entry in the query table has no value of its own.
+    let entries: Vec<TableEntry<u32, Vec<u32>>> = db.query(SquaresQuery).entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].value, None);
+}
+
+#[test]
+fn backdate_against_stored_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 3);
+    assert_eq!(db.total(1), 5);
+    assert_eq!(db.log().take(), vec!["total(1)", "squares(1)"]);
+
+    db.set_input(1, 3);
+    assert_eq!(db.total(1), 5);
+    assert_eq!(db.log().take(), vec!["squares(1)"]);
+
+    db.set_input(1, 4);
+    assert_eq!(db.total(1), 14);
+    assert_eq!(db.log().take(), vec!["squares(1)", "total(1)"]);
+}
+
+#[test]
+fn missing_value_recomputed() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 2);
+    assert_eq!(db.squares(1), vec![0, 1]);
+
+    db.store.clear();
+    assert_eq!(db.squares(1), vec![0, 1]);
+    assert_eq!(db.log().take(), vec!["squares(1)", "squares(1)"]);
+    assert_eq!(db.store.keys(), vec![1]);
+}
+
+#[test]
+fn sweep_removes_stored_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 2);
+    db.set_input(2, 2);
+    assert_eq!(db.squares(1), vec![0, 1]);
+    assert_eq!(db.squares(2), vec![0, 1]);
+
+    db.set_input(3, 0);
+    assert_eq!(db.squares(1), vec![0, 1]);
+    db.sweep_all(SweepStrategy::discard_outdated());
+    assert_eq!(db.store.keys(), vec![1]);
+}
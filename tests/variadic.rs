@@ -53,3 +53,18 @@ fn execute() {
     assert_eq!(db.two(11, 2), 22);
     assert_eq!(db.trailing(24, 2), 22);
 }
+
+#[test]
+fn tuple_keys() {
+    use salsa::Database;
+
+    let mut db = DatabaseStruct::default();
+
+    // The arguments of multi-argument queries form a tuple key when
+    // going through the query table.
+    db.query_mut(InputQuery).set((1, 2), 3);
+    assert_eq!(db.input(1, 2), 3);
+    assert_eq!(db.query(InputQuery).get((1, 2)), 3);
+    assert_eq!(db.query(TwoQuery).get((11, 2)), db.two(11, 2));
+    assert_eq!(db.query(NoneQuery).get(()), db.none());
+}
@@ -0,0 +1,98 @@
+//! Test `Database::watch` and `Database::poll_watches`.
+
+use salsa::Database;
+use std::sync::{Arc, Mutex};
+
+#[salsa::query_group(WatchStorage)]
+trait WatchDatabase: salsa::Database {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    fn is_large(&self, key: u32) -> bool;
+}
+
+fn is_large(db: &impl WatchDatabase, key: u32) -> bool {
+    db.input(key) >= 100
+}
+
+#[salsa::database(WatchStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+/// Returns a callback that records the values it is invoked with.
+fn recorder<T: Clone + Send + 'static>() -> (Arc<Mutex<Vec<T>>>, impl FnMut(&T) + Send) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    (seen, move |value: &T| {
+        sink.lock().unwrap().push(value.clone())
+    })
+}
+
+fn take<T>(seen: &Mutex<Vec<T>>) -> Vec<T> {
+    std::mem::take(&mut *seen.lock().unwrap())
+}
+
+#[test]
+fn callback_on_change() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    let (seen, callback) = recorder();
+    db.watch(IsLargeQuery, 1, callback);
+
+    assert_eq!(db.poll_watches(), 0);
+
+    // Backdated values do not count as changes.
+    db.set_input(1, 20);
+    assert_eq!(db.poll_watches(), 0);
+    assert_eq!(take(&seen), Vec::<bool>::new());
+
+    db.set_input(1, 200);
+    assert_eq!(db.poll_watches(), 1);
+    assert_eq!(take(&seen), vec![true]);
+
+    // Polling again in the same revision does nothing.
+    assert_eq!(db.poll_watches(), 0);
+
+    // Changes to unrelated inputs are not reported.
+    db.set_input(2, 0);
+    assert_eq!(db.poll_watches(), 0);
+
+    // Changes in revisions that were not polled are still reported.
+    db.set_input(1, 5);
+    db.set_input(2, 1);
+    assert_eq!(db.poll_watches(), 1);
+    assert_eq!(take(&seen), vec![false]);
+}
+
+#[test]
+fn unwatch() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 10);
+    db.set_input(2, 20);
+    let (seen_1, callback_1) = recorder();
+    let (seen_2, callback_2) = recorder();
+    let id = db.watch(InputQuery, 1, callback_1);
+    db.watch(InputQuery, 2, callback_2);
+
+    db.set_input(1, 11);
+    db.set_input(2, 21);
+    assert_eq!(db.poll_watches(), 2);
+    assert_eq!(take(&seen_1), vec![11]);
+    assert_eq!(take(&seen_2), vec![21]);
+
+    assert!(db.salsa_runtime().unwatch(id));
+    assert!(!db.salsa_runtime().unwatch(id));
+    db.set_input(1, 12);
+    db.set_input(2, 22);
+    assert_eq!(db.poll_watches(), 1);
+    assert_eq!(take(&seen_1), Vec::<u32>::new());
+    assert_eq!(take(&seen_2), vec![22]);
+}
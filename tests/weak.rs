@@ -0,0 +1,102 @@
+//! Test `#[salsa::weak]` queries, whose memos only hold a weak
+//! reference to their value.
+
+mod common;
+
+use crate::common::log::{HasLog, Log};
+use std::sync::Arc;
+
+#[salsa::query_group(WeakStorage)]
+trait WeakDatabase: salsa::Database + HasLog {
+    #[salsa::input]
+    fn input(&self, key: u32) -> u32;
+
+    #[salsa::weak]
+    fn table(&self, key: u32) -> Arc<Vec<u32>>;
+
+    fn table_len(&self, key: u32) -> usize;
+}
+
+fn table(db: &impl WeakDatabase, key: u32) -> Arc<Vec<u32>> {
+    db.log().add(format!("table({})", key));
+    Arc::new((0..db.input(key)).collect())
+}
+
+fn table_len(db: &impl WeakDatabase, key: u32) -> usize {
+    db.log().add(format!("table_len({})", key));
+    db.table(key).len()
+}
+
+#[salsa::database(WeakStorage)]
+#[derive(Default)]
+struct DatabaseImpl {
+    runtime: salsa::Runtime<DatabaseImpl>,
+    log: Log,
+}
+
+impl salsa::Database for DatabaseImpl {
+    fn salsa_runtime(&self) -> &salsa::Runtime<DatabaseImpl> {
+        &self.runtime
+    }
+}
+
+impl HasLog for DatabaseImpl {
+    fn log(&self) -> &Log {
+        &self.log
+    }
+}
+
+#[test]
+fn reused_while_held() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 3);
+
+    let held = db.table(1);
+    assert_eq!(db.table(1), held);
+    assert!(Arc::ptr_eq(&db.table(1), &held));
+    assert_eq!(db.log().take(), vec!["table(1)"]);
+
+    // Still held, so it is validated rather than recomputed in a
+    // new revision.
+    db.set_input(2, 0);
+    assert!(Arc::ptr_eq(&db.table(1), &held));
+    assert_eq!(db.log().take(), Vec::<String>::new());
+}
+
+#[test]
+fn recomputed_once_dropped() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 3);
+
+    assert_eq!(db.table(1).len(), 3);
+    assert_eq!(db.table(1).len(), 3);
+    assert_eq!(db.log().take(), vec!["table(1)", "table(1)"]);
+
+    db.set_input(2, 0);
+    assert_eq!(db.table(1).len(), 3);
+    assert_eq!(db.log().take(), vec!["table(1)"]);
+}
+
+#[test]
+fn dependents_validated_without_value() {
+    let mut db = DatabaseImpl::default();
+    db.set_input(1, 3);
+
+    assert_eq!(db.table_len(1), 3);
+    assert_eq!(db.log().take(), vec!["table_len(1)", "table(1)"]);
+
+    // The value of `table(1)` is gone by now, but as its inputs did
+    // not change, neither query has to re-execute.
+    db.set_input(2, 0);
+    assert_eq!(db.table_len(1), 3);
+    assert_eq!(db.log().take(), Vec::<String>::new());
+
+    // When the input does change, the value computed while validating
+    // `table_len(1)` is gone again by the time `table_len(1)` reads it.
+    db.set_input(1, 4);
+    assert_eq!(db.table_len(1), 4);
+    assert_eq!(
+        db.log().take(),
+        vec!["table(1)", "table_len(1)", "table(1)"]
+    );
+}